@@ -2,7 +2,8 @@ use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 
 use proto::app_server::messages::AppRequest;
 use proto::funder::messages::{
-    AckClosePayment, CreatePayment, CreateTransaction, Currency, FriendsRoute,
+    AckClosePayment, Commit, CreatePayment, CreateTransaction, Currency, DeliverCommit,
+    FriendsRoute,
 };
 
 pub fn create_payment(
@@ -53,3 +54,11 @@ pub fn ack_close_payment(payment_id: PaymentId, ack_uid: Uid) -> AppRequest {
 
     AppRequest::AckClosePayment(ack_close_payment)
 }
+
+/// Send a commit to the seller, along the route of the transaction that completed the payment.
+/// The seller's node will commit the invoice automatically.
+pub fn deliver_commit(request_id: Uid, commit: Commit) -> AppRequest {
+    let deliver_commit = DeliverCommit { request_id, commit };
+
+    AppRequest::DeliverCommit(deliver_commit)
+}
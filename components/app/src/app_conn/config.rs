@@ -4,8 +4,9 @@ use proto::app_server::messages::{
     AppRequest, CloseFriendCurrency, NamedRelayAddress, OpenFriendCurrency, RelayAddress,
 };
 use proto::funder::messages::{
-    AddFriend, Currency, Rate, RemoveFriendCurrency, ResetFriendChannel, SetFriendCurrencyMaxDebt,
-    SetFriendCurrencyRate, SetFriendName, SetFriendRelays,
+    AddFriend, Currency, CurrencyPair, ExchangeRate, FeePolicy, Rate, RebalancePolicy,
+    RemoveFriendCurrency, ResetFriendChannel, SetFriendCurrencyFeePolicy, SetFriendCurrencyMaxDebt,
+    SetFriendCurrencyRate, SetFriendCurrencyRebalancePolicy, SetFriendName, SetFriendRelays,
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
@@ -105,6 +106,32 @@ pub fn set_friend_currency_rate(
     AppRequest::SetFriendCurrencyRate(set_friend_currency_rate)
 }
 
+pub fn set_friend_currency_fee_policy(
+    friend_public_key: PublicKey,
+    currency: Currency,
+    opt_fee_policy: Option<FeePolicy>,
+) -> AppRequest {
+    let set_friend_currency_fee_policy = SetFriendCurrencyFeePolicy {
+        friend_public_key,
+        currency,
+        opt_fee_policy,
+    };
+    AppRequest::SetFriendCurrencyFeePolicy(set_friend_currency_fee_policy)
+}
+
+pub fn set_friend_currency_rebalance_policy(
+    friend_public_key: PublicKey,
+    currency: Currency,
+    opt_rebalance_policy: Option<RebalancePolicy>,
+) -> AppRequest {
+    let set_friend_currency_rebalance_policy = SetFriendCurrencyRebalancePolicy {
+        friend_public_key,
+        currency,
+        opt_rebalance_policy,
+    };
+    AppRequest::SetFriendCurrencyRebalancePolicy(set_friend_currency_rebalance_policy)
+}
+
 pub fn reset_friend_channel(friend_public_key: PublicKey, reset_token: Signature) -> AppRequest {
     // TODO: Check if a reset confusion attack is possible here.
     // Maybe we (locally) should be the ones generating the reset token.
@@ -117,6 +144,10 @@ pub fn reset_friend_channel(friend_public_key: PublicKey, reset_token: Signature
     AppRequest::ResetFriendChannel(reset_friend_channel)
 }
 
+pub fn settle_friend(friend_public_key: PublicKey) -> AppRequest {
+    AppRequest::SettleFriend(friend_public_key)
+}
+
 pub fn add_index_server(named_index_server: NamedIndexServerAddress) -> AppRequest {
     AppRequest::AddIndexServer(named_index_server)
 }
@@ -124,3 +155,30 @@ pub fn add_index_server(named_index_server: NamedIndexServerAddress) -> AppReque
 pub fn remove_index_server(index_public_key: PublicKey) -> AppRequest {
     AppRequest::RemoveIndexServer(index_public_key)
 }
+
+pub fn set_exchange_rate(
+    src_currency: Currency,
+    dest_currency: Currency,
+    mul: u64,
+    valid_from: u64,
+    valid_until: u64,
+) -> AppRequest {
+    let exchange_rate = ExchangeRate {
+        currency_pair: CurrencyPair {
+            src_currency,
+            dest_currency,
+        },
+        mul,
+        valid_from,
+        valid_until,
+    };
+    AppRequest::SetExchangeRate(exchange_rate)
+}
+
+pub fn remove_exchange_rate(src_currency: Currency, dest_currency: Currency) -> AppRequest {
+    let currency_pair = CurrencyPair {
+        src_currency,
+        dest_currency,
+    };
+    AppRequest::RemoveExchangeRate(currency_pair)
+}
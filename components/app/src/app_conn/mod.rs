@@ -2,3 +2,4 @@ pub mod buyer;
 pub mod config;
 pub mod routes;
 pub mod seller;
+pub mod verify;
@@ -1,6 +1,6 @@
 use proto::crypto::InvoiceId;
 
-use proto::app_server::messages::AppRequest;
+use proto::app_server::messages::{AppRequest, RelayAddress, UnsignedInvoice};
 use proto::funder::messages::{AddInvoice, Commit, Currency};
 
 pub fn add_invoice(
@@ -23,3 +23,22 @@ pub fn cancel_invoice(invoice_id: InvoiceId) -> AppRequest {
 pub fn commit_invoice(commit: Commit) -> AppRequest {
     AppRequest::CommitInvoice(commit)
 }
+
+pub fn sign_invoice(
+    invoice_id: InvoiceId,
+    currency: Currency,
+    total_dest_payment: u128,
+    relays: Vec<RelayAddress>,
+    valid_until: u64,
+    description: String,
+) -> AppRequest {
+    let unsigned_invoice = UnsignedInvoice {
+        invoice_id,
+        currency,
+        total_dest_payment,
+        relays,
+        valid_until,
+        description,
+    };
+    AppRequest::SignInvoice(unsigned_invoice)
+}
@@ -0,0 +1,35 @@
+use proto::crypto::{InvoiceId, PublicKey, Uid};
+
+use proto::app_server::messages::{AppRequest, VerifyFriendToken, VerifyReceipt};
+use proto::funder::messages::Receipt;
+use proto::report::messages::MoveTokenHashedReport;
+
+pub fn verify_receipt(
+    request_id: Uid,
+    receipt: Receipt,
+    dest_public_key: PublicKey,
+    invoice_id: InvoiceId,
+    total_dest_payment: u128,
+) -> AppRequest {
+    let verify_receipt = VerifyReceipt {
+        request_id,
+        receipt,
+        dest_public_key,
+        invoice_id,
+        total_dest_payment,
+    };
+    AppRequest::VerifyReceipt(verify_receipt)
+}
+
+pub fn verify_friend_token(
+    request_id: Uid,
+    move_token_hashed_report: MoveTokenHashedReport,
+    friend_public_key: PublicKey,
+) -> AppRequest {
+    let verify_friend_token = VerifyFriendToken {
+        request_id,
+        move_token_hashed_report,
+        friend_public_key,
+    };
+    AppRequest::VerifyFriendToken(verify_friend_token)
+}
@@ -20,19 +20,24 @@ pub mod gen;
 /// Utils for serializing and deserializing
 pub mod ser_utils {
     pub use common::ser_utils::*;
+    pub use proto::invoice_uri::*;
     pub use proto::ser_string::*;
 }
 
 /// Common types
 pub mod common {
     pub use crypto::identity::derive_public_key;
-    pub use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
+    pub use proto::app_server::messages::{
+        NamedRelayAddress, RelayAddress, SignedInvoice, UnsignedInvoice,
+    };
     pub use proto::crypto::{
         HashResult, HashedLock, InvoiceId, PaymentId, PlainLock, PrivateKey, PublicKey, RandValue,
         Signature, Uid,
     };
     pub use proto::funder::messages::{
-        Commit, Currency, FriendsRoute, PaymentStatus, PaymentStatusSuccess, Rate, Receipt,
+        Commit, CreditLimit, Currency, CurrencyPair, ExchangeRate, FeeCurve, FeePolicy,
+        FriendsRoute, PaymentStatus, PaymentStatusSuccess, Rate, RebalancePolicy, Receipt,
+        SettlementStatement,
     };
     pub use proto::index_server::messages::{
         MultiRoute, NamedIndexServerAddress, RouteCapacityRate,
@@ -45,13 +50,15 @@ pub use proto::file;
 
 /// Offset connection
 pub mod conn {
-    pub use super::app_conn::{buyer, config, routes, seller};
+    pub use super::app_conn::{buyer, config, routes, seller, verify};
     pub use super::connect::{connect, AppConnTuple, ConnPairApp, ConnectError};
     pub use super::identity::{identity_from_file, IdentityFromFileError};
     pub use proto::app_server::messages::{
-        AppPermissions, AppRequest, AppServerToApp, AppToAppServer,
+        now_secs, AllowedConfig, AllowedDestinations, AppPermissions, AppRequest, AppRestrictions,
+        AppServerToApp, AppToAppServer, ConfigPermission, ResponseVerifyFriendToken,
+        ResponseVerifyReceipt, SpendingLimit, VerifyFriendTokenStatus, VerifyReceiptStatus,
     };
-    pub use proto::funder::messages::{RequestResult, ResponseClosePayment};
+    pub use proto::funder::messages::{RequestResult, ResponseClosePayment, TransactionResult};
     pub use proto::index_client::messages::{ClientResponseRoutes, ResponseRoutesResult};
 }
 
@@ -62,7 +69,7 @@ pub mod report {
         AddFriendReport, ChannelConsistentReport, ChannelInconsistentReport, ChannelStatusReport,
         CurrencyConfigReport, CurrencyReport, FriendLivenessReport, FriendReport,
         FriendStatusReport, FunderReport, McBalanceReport, MoveTokenHashedReport,
-        RequestsStatusReport, ResetTermsReport,
+        RequestsStatusReport, ResetTermsReport, SettlementReport,
     };
 
     pub use proto::funder::messages::{
@@ -75,5 +82,8 @@ pub mod report {
 
 /// Verification functions
 pub mod verify {
-    pub use signature::verify::{verify_commit, verify_move_token_hashed_report, verify_receipt};
+    pub use signature::verify::{
+        verify_commit, verify_friend_token, verify_move_token_hashed_report, verify_receipt,
+        verify_receipt_for_invoice, verify_settlement_statement, verify_signed_invoice,
+    };
 }
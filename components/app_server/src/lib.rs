@@ -15,8 +15,12 @@ extern crate log;
 extern crate common;
 
 mod server;
+mod spending;
 
 #[cfg(test)]
 mod tests;
 
-pub use self::server::{app_server_loop, AppServerError, ConnPairServer, IncomingAppConnection};
+pub use self::server::{
+    app_server_loop, AppPermissionsUpdate, AppServerError, ConnPairServer, IncomingAppConnection,
+};
+pub use self::spending::{SpendingCharge, SpendingTracker};
@@ -3,28 +3,40 @@ use std::fmt::Debug;
 use std::marker::Unpin;
 
 use futures::channel::{mpsc, oneshot};
+use futures::future::{self, AbortHandle};
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+use futures::{stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
 
 use common::conn::{sink_to_sender, BoxStream, ConnPair};
 use common::select_streams::select_streams;
 // use common::mutable_state::MutableState;
-use proto::crypto::{PaymentId, Uid};
+
+use identity::IdentityClient;
+
+use proto::crypto::{PaymentId, PublicKey, Uid};
 
 use proto::funder::messages::{
-    FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl, RequestsStatus,
-    SetFriendCurrencyRequestsStatus, SetFriendStatus,
+    CreateTransaction, Currency, FriendStatus, FriendsRoute, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, RequestResult, RequestsStatus, SetFriendCurrencyRequestsStatus,
+    SetFriendStatus, TransactionResult,
 };
 use proto::report::convert::funder_report_mutation_to_index_mutation;
 
 use proto::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport, NodeReportMutation,
-    ReportMutations,
+    now_secs, AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer,
+    NodeReport, NodeReportMutation, ReportMutations, ResponseVerifyFriendToken,
+    ResponseVerifyReceipt, SignedInvoice,
 };
 use proto::index_client::messages::{
-    AppServerToIndexClient, IndexClientRequest, IndexClientToAppServer,
+    AppServerToIndexClient, HopOutcome, IndexClientRequest, IndexClientToAppServer, RouteOutcome,
 };
 
+use signature::canonical::CanonicalSerialize;
+use signature::signature_buff::create_invoice_signature_buff;
+use signature::verify::{verify_friend_token, verify_receipt_for_invoice};
+
+use crate::spending::{SpendingCharge, SpendingTracker};
+
 const APP_SENDER_BUFFER: usize = 0x20;
 
 pub type ConnPairServer<B> = ConnPair<AppServerToApp<B>, AppToAppServer<B>>;
@@ -38,12 +50,20 @@ pub type IncomingAppConnection<B> = (
 
 #[derive(Debug)]
 pub struct IncomingAppConnection<B> {
+    pub app_public_key: PublicKey,
     pub app_permissions: AppPermissions,
     // The server has to send the `NodeReport` first. Only then communication with the App becomes
     // possible.
     pub report_sender: oneshot::Sender<(NodeReport<B>, oneshot::Sender<ConnPairServer<B>>)>,
 }
 
+/// The current set of trusted apps, applied to apps that are already connected.
+#[derive(Debug, Clone)]
+pub struct AppPermissionsUpdate {
+    /// Permissions of all trusted apps. Connected apps missing from this set are revoked.
+    pub trusted_apps: HashMap<PublicKey, AppPermissions>,
+}
+
 #[derive(Debug)]
 pub enum AppServerError {
     FunderClosed,
@@ -54,6 +74,7 @@ pub enum AppServerError {
     AllAppsClosed,
     ObtainConnPairError,
     SendNodeReportError,
+    RequestSignatureError,
 }
 
 // TODO: Possibly remove Clone annotation here?
@@ -62,6 +83,7 @@ pub enum AppServerError {
 pub enum AppServerEvent<B: Clone> {
     IncomingConnection(IncomingAppConnection<B>),
     IncomingConnectionsClosed,
+    PermissionsUpdate(AppPermissionsUpdate),
     FromFunder(FunderOutgoingControl<B>),
     FunderClosed,
     FromIndexClient(IndexClientToAppServer<B>),
@@ -71,18 +93,28 @@ pub enum AppServerEvent<B: Clone> {
 
 // TODO: Possibly remove Clone annotation here?
 pub struct App<B: Clone> {
+    public_key: PublicKey,
     permissions: AppPermissions,
     opt_sender: Option<mpsc::Sender<AppServerToApp<B>>>,
+    /// Aborts the task forwarding messages from the app
+    receiver_abort_handle: AbortHandle,
 }
 
 impl<B> App<B>
 where
     B: Clone,
 {
-    pub fn new(permissions: AppPermissions, sender: mpsc::Sender<AppServerToApp<B>>) -> Self {
+    pub fn new(
+        public_key: PublicKey,
+        permissions: AppPermissions,
+        sender: mpsc::Sender<AppServerToApp<B>>,
+        receiver_abort_handle: AbortHandle,
+    ) -> Self {
         App {
+            public_key,
             permissions,
             opt_sender: Some(sender),
+            receiver_abort_handle,
         }
     }
 
@@ -95,6 +127,17 @@ where
     }
 }
 
+impl<B> Drop for App<B>
+where
+    B: Clone,
+{
+    /// Stop reading messages from the app once the app is removed.
+    /// Otherwise a removed app could keep its connection open.
+    fn drop(&mut self) {
+        self.receiver_abort_handle.abort();
+    }
+}
+
 pub struct AppServer<B: Clone, TF, TIC, S> {
     to_funder: TF,
     to_index_client: TIC,
@@ -111,6 +154,16 @@ pub struct AppServer<B: Clone, TF, TIC, S> {
     route_requests: HashMap<Uid, u128>,
     close_payment_requests: HashMap<PaymentId, u128>,
     transactions: HashMap<Uid, u128>,
+    /// Currencies of open payments, used to report route outcomes to the index client
+    payment_currencies: HashMap<PaymentId, Currency>,
+    /// Routes of ongoing transactions, used to report route outcomes to the index client
+    transaction_routes: HashMap<Uid, (Currency, FriendsRoute)>,
+    /// Credits spent by apps with spending limits
+    spending_tracker: SpendingTracker,
+    /// Charges of ongoing transactions, refunded if the transaction fails
+    transaction_charges: HashMap<Uid, SpendingCharge>,
+    /// Used to sign invoices on behalf of the node
+    identity_client: IdentityClient,
     spawner: S,
 }
 
@@ -126,10 +179,16 @@ fn check_request_permissions<B>(
         AppRequest::CreateTransaction(_) => app_permissions.buyer,
         AppRequest::RequestClosePayment(_) => app_permissions.buyer,
         AppRequest::AckClosePayment(_) => app_permissions.buyer,
+        AppRequest::DeliverCommit(_) => app_permissions.buyer,
 
         AppRequest::AddInvoice(_) => app_permissions.seller,
         AppRequest::CancelInvoice(_) => app_permissions.seller,
         AppRequest::CommitInvoice(_) => app_permissions.seller,
+        AppRequest::SignInvoice(_) => app_permissions.seller,
+
+        // Verification does not change any state:
+        AppRequest::VerifyReceipt(_) => true,
+        AppRequest::VerifyFriendToken(_) => true,
 
         AppRequest::AddFriend(_) => app_permissions.config,
         AppRequest::SetFriendRelays(_) => app_permissions.config,
@@ -141,17 +200,48 @@ fn check_request_permissions<B>(
         AppRequest::CloseFriendCurrency(_) => app_permissions.config,
         AppRequest::SetFriendCurrencyMaxDebt(_) => app_permissions.config,
         AppRequest::SetFriendCurrencyRate(_) => app_permissions.config,
+        AppRequest::SetFriendCurrencyFeePolicy(_) => app_permissions.config,
+        AppRequest::SetFriendCurrencyRebalancePolicy(_) => app_permissions.config,
         AppRequest::RemoveFriendCurrency(_) => app_permissions.config,
         AppRequest::ResetFriendChannel(_) => app_permissions.config,
+        AppRequest::SettleFriend(_) => app_permissions.config,
+        AppRequest::SetExchangeRate(_) => app_permissions.config,
+        AppRequest::RemoveExchangeRate(_) => app_permissions.config,
         AppRequest::RequestRoutes(_) => app_permissions.routes,
         AppRequest::AddIndexServer(_) => app_permissions.config,
         AppRequest::RemoveIndexServer(_) => app_permissions.config,
     }
 }
 
+/// Check if an app_request is allowed by the finer grained restrictions of an app.
+/// Spending limits are checked separately, as they depend on previous requests.
+fn check_request_restrictions<B>(
+    app_restrictions: &AppRestrictions,
+    app_request: &AppRequest<B>,
+    now: u64,
+) -> bool {
+    if app_restrictions.is_expired(now) {
+        return false;
+    }
+
+    if let Some(config_permission) = app_request.config_permission() {
+        if !app_restrictions.is_config_allowed(&config_permission) {
+            return false;
+        }
+    }
+
+    if let AppRequest::CreatePayment(create_payment) = app_request {
+        if !app_restrictions.is_destination_allowed(&create_payment.dest_public_key) {
+            return false;
+        }
+    }
+
+    true
+}
+
 impl<B, TF, TIC, S> AppServer<B, TF, TIC, S>
 where
-    B: Clone + PartialEq + Eq + Debug + Send + Sync + 'static,
+    B: Clone + PartialEq + Eq + Debug + CanonicalSerialize + Send + Sync + 'static,
     TF: Sink<FunderIncomingControl<B>> + Unpin + Sync + Send,
     TIC: Sink<AppServerToIndexClient<B>> + Unpin,
     S: Spawn,
@@ -161,6 +251,7 @@ where
         to_index_client: TIC,
         from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
         node_report: NodeReport<B>,
+        identity_client: IdentityClient,
         spawner: S,
     ) -> Self {
         AppServer {
@@ -174,6 +265,11 @@ where
             route_requests: HashMap::new(),
             close_payment_requests: HashMap::new(),
             transactions: HashMap::new(),
+            payment_currencies: HashMap::new(),
+            transaction_routes: HashMap::new(),
+            spending_tracker: SpendingTracker::new(),
+            transaction_charges: HashMap::new(),
+            identity_client,
             spawner,
         }
     }
@@ -184,6 +280,7 @@ where
         incoming_app_connection: IncomingAppConnection<B>,
     ) -> Result<(), AppServerError> {
         let IncomingAppConnection {
+            app_public_key,
             app_permissions,
             report_sender,
         } = incoming_app_connection;
@@ -210,13 +307,20 @@ where
             // Notify that the connection to the app was closed:
             let _ = from_app_sender.send((app_counter, None)).await;
         };
+        // The forwarding is aborted when the app is removed:
+        let (send_all_fut, receiver_abort_handle) = future::abortable(send_all_fut);
 
         self.spawner
-            .spawn(send_all_fut)
+            .spawn(send_all_fut.map(|_| ()))
             .map_err(|_| AppServerError::SpawnError)?;
 
         let sender = sink_to_sender(sender, APP_SENDER_BUFFER, &self.spawner);
-        let app = App::new(app_permissions, sender);
+        let app = App::new(
+            app_public_key,
+            app_permissions,
+            sender,
+            receiver_abort_handle,
+        );
 
         self.apps.insert(self.app_counter, app);
         self.app_counter = self.app_counter.wrapping_add(1);
@@ -228,12 +332,55 @@ where
     /// This means we will not receive any new connections
     pub async fn handle_incoming_connections_closed(&mut self) -> Result<(), AppServerError> {
         self.incoming_connections_closed = true;
-        if self.apps.is_empty() {
+        self.check_all_apps_closed()
+    }
+
+    /// Fail if there are no connected apps, and no new apps can connect
+    fn check_all_apps_closed(&self) -> Result<(), AppServerError> {
+        if self.apps.is_empty() && self.incoming_connections_closed {
             return Err(AppServerError::AllAppsClosed);
         }
         Ok(())
     }
 
+    /// Apply the current set of trusted apps to all connected apps.
+    /// Connections of apps that are no longer trusted are closed.
+    /// Internal apps (Connected using the node's own public key) are never revoked.
+    pub fn handle_permissions_update(
+        &mut self,
+        permissions_update: AppPermissionsUpdate,
+    ) -> Result<(), AppServerError> {
+        let AppPermissionsUpdate { trusted_apps } = permissions_update;
+        let local_public_key = &self.node_report.funder_report.local_public_key;
+
+        self.apps.retain(|app_id, app| {
+            if &app.public_key == local_public_key {
+                return true;
+            }
+            match trusted_apps.get(&app.public_key) {
+                Some(app_permissions) => {
+                    if &app.permissions != app_permissions {
+                        info!(
+                            "Updating permissions of app {:?} (public key: {:?})",
+                            app_id, app.public_key
+                        );
+                        app.permissions = app_permissions.clone();
+                    }
+                    true
+                }
+                None => {
+                    info!(
+                        "Disconnecting revoked app {:?} (public key: {:?})",
+                        app_id, app.public_key
+                    );
+                    // Dropping the app closes its connection:
+                    false
+                }
+            }
+        });
+        self.check_all_apps_closed()
+    }
+
     /// Send node report mutations to all connected apps
     pub async fn broadcast_node_report_mutations(&mut self, report_mutations: ReportMutations<B>) {
         // Send node report mutations to all connected apps
@@ -248,7 +395,49 @@ where
         funder_message: FunderOutgoingControl<B>,
     ) -> Result<(), AppServerError> {
         match funder_message {
-            FunderOutgoingControl::TransactionResult(transaction_result) => {
+            FunderOutgoingControl::TransactionResult(transaction_result, is_first_hop_failure) => {
+                // Report the outcome of the used route to the index client:
+                if let Some((currency, route)) = self
+                    .transaction_routes
+                    .remove(&transaction_result.request_id)
+                {
+                    let num_hops = route.public_keys.len().saturating_sub(1);
+                    let hop_outcomes = match transaction_result.result {
+                        RequestResult::Complete(_) | RequestResult::Success => {
+                            vec![HopOutcome::Success; num_hops]
+                        }
+                        // We only know where the transaction failed if it never left our node.
+                        // Otherwise, the first hop is known to be fine, and any of the remaining
+                        // hops might have failed.
+                        RequestResult::Failure => (0..num_hops)
+                            .map(|hop_index| match (hop_index, is_first_hop_failure) {
+                                (0, true) => HopOutcome::Failure,
+                                (0, false) => HopOutcome::Success,
+                                _ => HopOutcome::Unknown,
+                            })
+                            .collect(),
+                    };
+                    let route_outcome = RouteOutcome {
+                        currency,
+                        route,
+                        hop_outcomes,
+                    };
+                    self.to_index_client
+                        .send(AppServerToIndexClient::ReportRouteOutcome(route_outcome))
+                        .await
+                        .map_err(|_| AppServerError::SendToIndexClientError)?;
+                }
+
+                // Failed transactions do not count towards the spending limits of the app:
+                if let Some(charge) = self
+                    .transaction_charges
+                    .remove(&transaction_result.request_id)
+                {
+                    if let RequestResult::Failure = transaction_result.result {
+                        self.spending_tracker.refund(charge);
+                    }
+                }
+
                 // Find the app that issued the request, and forward the response to this app:
                 let app_id = if let Some(app_id) =
                     self.transactions.remove(&transaction_result.request_id)
@@ -317,6 +506,15 @@ where
 
                 self.broadcast_node_report_mutations(report_mutations).await;
             }
+            FunderOutgoingControl::InvoiceCommitted(commit) => {
+                // The invoice could have been opened by any seller app:
+                for app in &mut self.apps.values_mut() {
+                    if app.permissions.seller {
+                        app.send(AppServerToApp::InvoiceCommitted(commit.clone()))
+                            .await;
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -372,13 +570,10 @@ where
     ) -> Result<(), AppServerError> {
         match opt_app_message {
             None => {
-                // Remove the application. We assert that this application exists
-                // in our apps map:
-                self.apps.remove(&app_id).unwrap();
-                if self.apps.is_empty() && self.incoming_connections_closed {
-                    return Err(AppServerError::AllAppsClosed);
-                }
-                Ok(())
+                // Remove the application. Note that the application might have been removed
+                // already (For example, if it was revoked):
+                self.apps.remove(&app_id);
+                self.check_all_apps_closed()
             }
             Some(app_message) => self.handle_app_message(app_id, app_message).await,
         }
@@ -395,7 +590,13 @@ where
         };
 
         // Make sure this message is allowed for this application:
-        if !check_request_permissions(&app.permissions, &app_message.app_request) {
+        if !check_request_permissions(&app.permissions, &app_message.app_request)
+            || !check_request_restrictions(
+                &app.permissions.restrictions,
+                &app_message.app_request,
+                now_secs(),
+            )
+        {
             warn!(
                 "App {:?} does not have permissions for {:?}",
                 app_id, app_message
@@ -406,6 +607,47 @@ where
         true
     }
 
+    /// Charge a transaction against the spending limit of the issuing app, if there is any.
+    /// Returns false if the transaction exceeds the limit.
+    fn charge_transaction(&mut self, app_id: u128, create_transaction: &CreateTransaction) -> bool {
+        let app = match self.apps.get(&app_id) {
+            Some(app) => app,
+            None => return false,
+        };
+        let app_restrictions = &app.permissions.restrictions;
+
+        let currency = match self.payment_currencies.get(&create_transaction.payment_id) {
+            Some(currency) => currency,
+            // We can not tell the currency of this payment:
+            None => return app_restrictions.spending_limits.is_empty(),
+        };
+
+        let spending_limit = match app_restrictions.spending_limit(currency) {
+            Some(spending_limit) => spending_limit,
+            None => return true,
+        };
+
+        let amount = match create_transaction
+            .dest_payment
+            .checked_add(create_transaction.fees)
+        {
+            Some(amount) => amount,
+            None => return false,
+        };
+
+        match self
+            .spending_tracker
+            .charge(&app.public_key, spending_limit, amount, now_secs())
+        {
+            Some(charge) => {
+                self.transaction_charges
+                    .insert(create_transaction.request_id.clone(), charge);
+                true
+            }
+            None => false,
+        }
+    }
+
     // Clippy doesn't like `match {}` blocks with that many arms
     #[allow(clippy::cognitive_complexity)]
     async fn handle_app_message(
@@ -416,7 +658,7 @@ where
         if !self.check_app_permissions(app_id, &app_message) {
             // Eliminate application's connection:
             self.apps.remove(&app_id);
-            return Ok(());
+            return self.check_all_apps_closed();
         }
 
         let AppToAppServer {
@@ -449,7 +691,14 @@ where
             // Requests that go to funder:
             AddRelay(x) => to_funder!(AddRelay(x)),
             RemoveRelay(x) => to_funder!(RemoveRelay(x)),
-            CreatePayment(x) => to_funder!(CreatePayment(x)),
+            CreatePayment(create_payment) => {
+                // Remember the currency of the payment, to be able to report route outcomes:
+                self.payment_currencies.insert(
+                    create_payment.payment_id.clone(),
+                    create_payment.currency.clone(),
+                );
+                to_funder!(CreatePayment(create_payment))
+            }
             RequestClosePayment(payment_id) => {
                 if self
                     .close_payment_requests
@@ -460,27 +709,128 @@ where
                 }
                 to_funder!(RequestClosePayment(payment_id))
             }
-            AckClosePayment(x) => to_funder!(AckClosePayment(x)),
+            AckClosePayment(ack_close_payment) => {
+                let _ = self
+                    .payment_currencies
+                    .remove(&ack_close_payment.payment_id);
+                to_funder!(AckClosePayment(ack_close_payment))
+            }
+            DeliverCommit(x) => to_funder!(DeliverCommit(x)),
             AddInvoice(x) => to_funder!(AddInvoice(x)),
             CancelInvoice(x) => to_funder!(CancelInvoice(x)),
             CommitInvoice(x) => to_funder!(CommitInvoice(x)),
+            SignInvoice(unsigned_invoice) => {
+                // Invoices are signed by the node, and are sent back only to the requesting app:
+                let dest_public_key = self.node_report.funder_report.local_public_key.clone();
+                let signature_buff =
+                    create_invoice_signature_buff(&dest_public_key, unsigned_invoice.clone());
+                let signature = self
+                    .identity_client
+                    .request_signature(signature_buff)
+                    .await
+                    .map_err(|_| AppServerError::RequestSignatureError)?;
+
+                let signed_invoice = SignedInvoice {
+                    invoice_id: unsigned_invoice.invoice_id,
+                    currency: unsigned_invoice.currency,
+                    total_dest_payment: unsigned_invoice.total_dest_payment,
+                    relays: unsigned_invoice.relays,
+                    valid_until: unsigned_invoice.valid_until,
+                    description: unsigned_invoice.description,
+                    dest_public_key,
+                    signature,
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseSignInvoice(signed_invoice))
+                        .await;
+                }
+                Ok(())
+            }
+            VerifyReceipt(verify_receipt) => {
+                let status = verify_receipt_for_invoice(
+                    &verify_receipt.receipt,
+                    &verify_receipt.dest_public_key,
+                    &verify_receipt.invoice_id,
+                    verify_receipt.total_dest_payment,
+                );
+                let response_verify_receipt = ResponseVerifyReceipt {
+                    request_id: verify_receipt.request_id,
+                    status,
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseVerifyReceipt(
+                        response_verify_receipt,
+                    ))
+                    .await;
+                }
+                Ok(())
+            }
+            VerifyFriendToken(verify_token) => {
+                let status = verify_friend_token(
+                    &verify_token.move_token_hashed_report,
+                    &verify_token.friend_public_key,
+                );
+                let response_verify_friend_token = ResponseVerifyFriendToken {
+                    request_id: verify_token.request_id,
+                    status,
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseVerifyFriendToken(
+                        response_verify_friend_token,
+                    ))
+                    .await;
+                }
+                Ok(())
+            }
             AddFriend(x) => to_funder!(AddFriend(x)),
             SetFriendRelays(x) => to_funder!(SetFriendRelays(x)),
             SetFriendName(x) => to_funder!(SetFriendName(x)),
             SetFriendCurrencyMaxDebt(x) => to_funder!(SetFriendCurrencyMaxDebt(x)),
             SetFriendCurrencyRate(x) => to_funder!(SetFriendCurrencyRate(x)),
+            SetFriendCurrencyFeePolicy(x) => to_funder!(SetFriendCurrencyFeePolicy(x)),
+            SetFriendCurrencyRebalancePolicy(x) => to_funder!(SetFriendCurrencyRebalancePolicy(x)),
             RemoveFriendCurrency(x) => to_funder!(RemoveFriendCurrency(x)),
             ResetFriendChannel(x) => to_funder!(ResetFriendChannel(x)),
+            SetExchangeRate(x) => to_funder!(SetExchangeRate(x)),
+            RemoveExchangeRate(x) => to_funder!(RemoveExchangeRate(x)),
             CreateTransaction(create_transaction) => {
+                if !self.charge_transaction(app_id, &create_transaction) {
+                    // Reject the transaction, without disconnecting the app:
+                    warn!(
+                        "App {:?} exceeded its spending limit. Rejecting transaction {:?}",
+                        app_id, create_transaction.request_id
+                    );
+                    let transaction_result = TransactionResult {
+                        request_id: create_transaction.request_id,
+                        result: RequestResult::Failure,
+                    };
+                    if let Some(app) = self.apps.get_mut(&app_id) {
+                        app.send(AppServerToApp::TransactionResult(transaction_result))
+                            .await;
+                    }
+                    return Ok(());
+                }
+
                 // Keep track of which application issued this request:
                 self.transactions
                     .insert(create_transaction.request_id.clone(), app_id);
+                if let Some(currency) = self.payment_currencies.get(&create_transaction.payment_id)
+                {
+                    self.transaction_routes.insert(
+                        create_transaction.request_id.clone(),
+                        (currency.clone(), create_transaction.route.clone()),
+                    );
+                }
                 to_funder!(CreateTransaction(create_transaction))
             }
             RemoveFriend(friend_public_key) => {
                 let remove_friend = proto::funder::messages::RemoveFriend { friend_public_key };
                 to_funder!(RemoveFriend(remove_friend))
             }
+            SettleFriend(friend_public_key) => {
+                let settle_friend = proto::funder::messages::SettleFriend { friend_public_key };
+                to_funder!(SettleFriend(settle_friend))
+            }
             EnableFriend(friend_public_key) => {
                 let set_friend_status = SetFriendStatus {
                     friend_public_key,
@@ -530,22 +880,25 @@ where
     }
 }
 
-pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, S>(
+pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, PU, S>(
     from_funder: FF,
     to_funder: TF,
     from_index_client: FIC,
     to_index_client: TIC,
     incoming_connections: IC,
+    permissions_updates: PU,
     initial_node_report: NodeReport<B>,
+    identity_client: IdentityClient,
     spawner: S,
 ) -> Result<(), AppServerError>
 where
-    B: Clone + PartialEq + Eq + Debug + Send + Sync + 'static,
+    B: Clone + PartialEq + Eq + Debug + CanonicalSerialize + Send + Sync + 'static,
     FF: Stream<Item = FunderOutgoingControl<B>> + Unpin + Send,
     TF: Sink<FunderIncomingControl<B>> + Unpin + Sync + Send,
     FIC: Stream<Item = IndexClientToAppServer<B>> + Unpin + Send,
     TIC: Sink<AppServerToIndexClient<B>> + Unpin,
     IC: Stream<Item = IncomingAppConnection<B>> + Unpin + Send,
+    PU: Stream<Item = AppPermissionsUpdate> + Unpin + Send,
     S: Spawn,
 {
     let (from_app_sender, from_app_receiver) = mpsc::channel(0);
@@ -554,6 +907,7 @@ where
         to_index_client,
         from_app_sender,
         initial_node_report,
+        identity_client,
         spawner,
     );
 
@@ -575,11 +929,14 @@ where
             AppServerEvent::IncomingConnectionsClosed,
         )));
 
+    let permissions_updates = permissions_updates.map(AppServerEvent::PermissionsUpdate);
+
     let mut events = select_streams![
         from_funder,
         from_index_client,
         from_app_receiver,
-        incoming_connections
+        incoming_connections,
+        permissions_updates
     ];
 
     while let Some(event) = events.next().await {
@@ -592,6 +949,9 @@ where
             AppServerEvent::IncomingConnectionsClosed => {
                 app_server.handle_incoming_connections_closed().await?
             }
+            AppServerEvent::PermissionsUpdate(permissions_update) => {
+                app_server.handle_permissions_update(permissions_update)?
+            }
             AppServerEvent::FromFunder(funder_outgoing_control) => {
                 app_server
                     .handle_from_funder(funder_outgoing_control)
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use proto::app_server::messages::SpendingLimit;
+use proto::crypto::PublicKey;
+use proto::funder::messages::Currency;
+
+#[derive(Debug, Clone)]
+struct SpendingWindow {
+    /// Start of the window (Seconds since the Unix epoch)
+    start: u64,
+    spent: u128,
+}
+
+/// Credits charged against the spending limit of an app.
+/// Kept until the result of the transaction is known, so that failed transactions can be
+/// refunded.
+#[derive(Debug, Clone)]
+pub struct SpendingCharge {
+    app_public_key: PublicKey,
+    currency: Currency,
+    amount: u128,
+    /// Start of the charged window
+    window_start: u64,
+}
+
+/// Keeps track of the credits spent by apps, per currency.
+/// Spending is tracked by the public key of the app (And not by connection), so that an app can
+/// not reset its limits by reconnecting.
+#[derive(Debug)]
+pub struct SpendingTracker {
+    windows: HashMap<(PublicKey, Currency), SpendingWindow>,
+}
+
+impl SpendingTracker {
+    pub fn new() -> Self {
+        SpendingTracker {
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Attempt to charge `amount` credits against a spending limit of an app.
+    /// Returns None if the charge exceeds the limit.
+    pub fn charge(
+        &mut self,
+        app_public_key: &PublicKey,
+        spending_limit: &SpendingLimit,
+        amount: u128,
+        now: u64,
+    ) -> Option<SpendingCharge> {
+        let window = self
+            .windows
+            .entry((app_public_key.clone(), spending_limit.currency.clone()))
+            .or_insert(SpendingWindow {
+                start: now,
+                spent: 0,
+            });
+
+        // Start a new window if the current window is over:
+        if now >= window.start.saturating_add(spending_limit.window_secs) {
+            window.start = now;
+            window.spent = 0;
+        }
+
+        let new_spent = window.spent.checked_add(amount)?;
+        if new_spent > spending_limit.max_amount {
+            return None;
+        }
+        window.spent = new_spent;
+
+        Some(SpendingCharge {
+            app_public_key: app_public_key.clone(),
+            currency: spending_limit.currency.clone(),
+            amount,
+            window_start: window.start,
+        })
+    }
+
+    /// Refund the charge of a transaction that failed.
+    pub fn refund(&mut self, charge: SpendingCharge) {
+        if let Some(window) = self
+            .windows
+            .get_mut(&(charge.app_public_key, charge.currency))
+        {
+            // Charges from previous windows are already forgotten:
+            if window.start == charge.window_start {
+                window.spent = window.spent.saturating_sub(charge.amount);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    fn spending_limit() -> SpendingLimit {
+        SpendingLimit {
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            max_amount: 100,
+            window_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_spending_tracker_limit() {
+        let mut spending_tracker = SpendingTracker::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let pk_b = PublicKey::from(&[0xbb; PublicKey::len()]);
+        let spending_limit = spending_limit();
+
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 60, 1000)
+            .is_some());
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 40, 1010)
+            .is_some());
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 1, 1020)
+            .is_none());
+
+        // Other apps have their own limits:
+        assert!(spending_tracker
+            .charge(&pk_b, &spending_limit, 100, 1020)
+            .is_some());
+
+        // A new window starts:
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 100, 1060)
+            .is_some());
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 1, 1061)
+            .is_none());
+    }
+
+    #[test]
+    fn test_spending_tracker_refund() {
+        let mut spending_tracker = SpendingTracker::new();
+        let pk_a = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let spending_limit = spending_limit();
+
+        let charge = spending_tracker
+            .charge(&pk_a, &spending_limit, 80, 1000)
+            .unwrap();
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 30, 1010)
+            .is_none());
+
+        spending_tracker.refund(charge);
+        let charge = spending_tracker
+            .charge(&pk_a, &spending_limit, 30, 1020)
+            .unwrap();
+
+        // A refund from a previous window does not affect the current window:
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 90, 1070)
+            .is_some());
+        spending_tracker.refund(charge);
+        assert!(spending_tracker
+            .charge(&pk_a, &spending_limit, 20, 1080)
+            .is_none());
+    }
+}
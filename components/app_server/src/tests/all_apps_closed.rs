@@ -7,7 +7,7 @@ use common::conn::ConnPair;
 
 use proto::crypto::PublicKey;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use proto::index_client::messages::{
     IndexClientReportMutation, IndexClientReportMutations, IndexClientToAppServer,
 };
@@ -39,10 +39,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
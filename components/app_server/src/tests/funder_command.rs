@@ -5,10 +5,10 @@ use futures::{SinkExt, StreamExt};
 
 use common::conn::ConnPair;
 
-use proto::crypto::Uid;
+use proto::crypto::{PublicKey, Uid};
 
 use proto::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReportMutation,
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer, NodeReportMutation,
 };
 use proto::funder::messages::{FunderControl, FunderOutgoingControl};
 use proto::report::messages::{FunderReportMutation, FunderReportMutations};
@@ -38,10 +38,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -6,7 +6,7 @@ use futures::{SinkExt, StreamExt};
 use common::conn::ConnPair;
 
 use proto::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReportMutation,
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer, NodeReportMutation,
 };
 use proto::crypto::{PublicKey, Uid};
 use proto::index_client::messages::{
@@ -40,10 +40,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
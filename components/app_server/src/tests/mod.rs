@@ -1,7 +1,11 @@
 mod all_apps_closed;
 mod funder_command;
 mod index_client_command;
+mod permissions_update;
 mod request_routes;
 mod request_send_funds;
+mod sign_invoice;
+mod spending_limit;
 mod two_apps;
 mod utils;
+mod verify_receipt;
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer, RelayAddress,
+    UnsignedInvoice,
+};
+use proto::funder::messages::{CreatePayment, Currency};
+
+use super::utils::spawn_dummy_app_server_with_updates;
+use crate::server::{AppPermissionsUpdate, IncomingAppConnection};
+
+/// Connect an app to the app server
+async fn connect_app(
+    connections_sender: &mut mpsc::Sender<IncomingAppConnection<u32>>,
+    app_public_key: PublicKey,
+    app_permissions: AppPermissions,
+) -> (
+    mpsc::Sender<AppToAppServer>,
+    mpsc::Receiver<AppServerToApp<u32>>,
+) {
+    let (app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key,
+        app_permissions,
+        report_sender,
+    };
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    (app_sender, app_receiver)
+}
+
+async fn task_app_server_loop_permissions_update<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (mut permissions_updates_sender, permissions_updates) = mpsc::channel(0);
+    let (
+        _funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        initial_node_report,
+    ) = spawn_dummy_app_server_with_updates(spawner.clone(), permissions_updates);
+
+    let pk_a = PublicKey::from(&[0xa0; PublicKey::len()]);
+    let pk_b = PublicKey::from(&[0xa1; PublicKey::len()]);
+    // Internal apps connect using the node's own public key:
+    let pk_internal = initial_node_report.funder_report.local_public_key.clone();
+
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        restrictions: AppRestrictions::default(),
+    };
+
+    // Connect two apps and an internal app:
+    let (mut app_sender_a, mut app_receiver_a) = connect_app(
+        &mut connections_sender,
+        pk_a.clone(),
+        app_permissions.clone(),
+    )
+    .await;
+    let (mut app_sender_b, mut app_receiver_b) = connect_app(
+        &mut connections_sender,
+        pk_b.clone(),
+        app_permissions.clone(),
+    )
+    .await;
+    let (mut app_sender_internal, mut app_receiver_internal) = connect_app(
+        &mut connections_sender,
+        pk_internal.clone(),
+        app_permissions.clone(),
+    )
+    .await;
+
+    // App A may no longer buy. App B is missing from the trusted apps (Although it was never
+    // revoked explicitly), and should be disconnected:
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        pk_a.clone(),
+        AppPermissions {
+            buyer: false,
+            ..app_permissions
+        },
+    );
+    permissions_updates_sender
+        .send(AppPermissionsUpdate { trusted_apps })
+        .await
+        .unwrap();
+    assert!(app_receiver_b.next().await.is_none());
+
+    // The app server stops reading messages from app B.
+    // Sending eventually fails, once the receiving side of the connection is dropped:
+    loop {
+        let res = app_sender_b
+            .send(AppToAppServer::new(
+                Uid::from(&[21; Uid::len()]),
+                AppRequest::RemoveRelay(PublicKey::from(&[2; PublicKey::len()])),
+            ))
+            .await;
+        if res.is_err() {
+            break;
+        }
+    }
+
+    // App A is still connected, and may still sell:
+    let unsigned_invoice = UnsignedInvoice {
+        invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+        currency: Currency::try_from("FST".to_owned()).unwrap(),
+        total_dest_payment: 100,
+        relays: vec![RelayAddress {
+            public_key: PublicKey::from(&[2; PublicKey::len()]),
+            address: 2u32,
+        }],
+        valid_until: 1_600_000_000,
+        description: "Two apples".to_owned(),
+    };
+    app_sender_a
+        .send(AppToAppServer::new(
+            Uid::from(&[22; Uid::len()]),
+            AppRequest::SignInvoice(unsigned_invoice.clone()),
+        ))
+        .await
+        .unwrap();
+    match app_receiver_a.next().await.unwrap() {
+        AppServerToApp::ResponseSignInvoice(_) => {}
+        _ => unreachable!(),
+    };
+
+    // The internal app is still connected, although it is not a trusted app:
+    app_sender_internal
+        .send(AppToAppServer::new(
+            Uid::from(&[24; Uid::len()]),
+            AppRequest::SignInvoice(unsigned_invoice),
+        ))
+        .await
+        .unwrap();
+    match app_receiver_internal.next().await.unwrap() {
+        AppServerToApp::ResponseSignInvoice(_) => {}
+        _ => unreachable!(),
+    };
+
+    // Buying is not allowed anymore, app A should be disconnected:
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[3; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[4; InvoiceId::len()]),
+        currency: Currency::try_from("FST".to_owned()).unwrap(),
+        total_dest_payment: 20,
+        dest_public_key: PublicKey::from(&[0xff; PublicKey::len()]),
+    };
+    app_sender_a
+        .send(AppToAppServer::new(
+            Uid::from(&[23; Uid::len()]),
+            AppRequest::CreatePayment(create_payment),
+        ))
+        .await
+        .unwrap();
+    assert!(app_receiver_a.next().await.is_none());
+}
+
+#[test]
+fn test_app_server_loop_permissions_update() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_permissions_update(thread_pool.clone()));
+}
@@ -9,7 +9,9 @@ use common::conn::ConnPair;
 
 use proto::crypto::{PublicKey, Uid};
 
-use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer,
+};
 use proto::funder::messages::Currency;
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientRequest, IndexClientToAppServer,
@@ -41,10 +43,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -68,9 +72,11 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa1; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -9,11 +9,14 @@ use common::conn::ConnPair;
 
 use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 
-use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer,
+};
 use proto::funder::messages::{
     CreatePayment, CreateTransaction, Currency, FriendsRoute, FunderControl, FunderOutgoingControl,
     RequestResult, TransactionResult,
 };
+use proto::index_client::messages::{AppServerToIndexClient, HopOutcome, RouteOutcome};
 
 use super::utils::spawn_dummy_app_server;
 use crate::server::IncomingAppConnection;
@@ -26,7 +29,7 @@ where
         mut funder_sender,
         mut funder_receiver,
         _index_client_sender,
-        _index_client_receiver,
+        mut index_client_receiver,
         mut connections_sender,
         initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
@@ -40,10 +43,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -67,10 +72,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa1; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -151,7 +158,10 @@ where
         result: RequestResult::Failure,
     };
     funder_sender
-        .send(FunderOutgoingControl::TransactionResult(transaction_result))
+        .send(FunderOutgoingControl::TransactionResult(
+            transaction_result,
+            true,
+        ))
         .await
         .unwrap();
 
@@ -167,10 +177,27 @@ where
     funder_sender
         .send(FunderOutgoingControl::TransactionResult(
             transaction_result.clone(),
+            true,
         ))
         .await
         .unwrap();
 
+    // The outcome of the used route is reported to the index client:
+    match index_client_receiver.next().await.unwrap() {
+        AppServerToIndexClient::ReportRouteOutcome(route_outcome) => {
+            assert_eq!(
+                route_outcome,
+                RouteOutcome {
+                    currency: currency1.clone(),
+                    route: create_transaction.route.clone(),
+                    // The transaction failed before leaving our node:
+                    hop_outcomes: vec![HopOutcome::Failure],
+                }
+            );
+        }
+        _ => unreachable!(),
+    }
+
     let to_app_message = app_receiver0.next().await.unwrap();
     match to_app_message {
         AppServerToApp::TransactionResult(received_transaction_result) => {
@@ -189,7 +216,10 @@ where
         result: RequestResult::Failure,
     };
     funder_sender
-        .send(FunderOutgoingControl::TransactionResult(transaction_result))
+        .send(FunderOutgoingControl::TransactionResult(
+            transaction_result,
+            true,
+        ))
         .await
         .unwrap();
 
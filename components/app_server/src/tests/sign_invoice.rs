@@ -0,0 +1,108 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{InvoiceId, PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer, RelayAddress,
+    UnsignedInvoice,
+};
+use proto::funder::messages::Currency;
+
+use signature::verify::verify_signed_invoice;
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_sign_invoice<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: true,
+        config: false,
+        restrictions: AppRestrictions::default(),
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let unsigned_invoice = UnsignedInvoice {
+        invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+        currency: Currency::try_from("FST".to_owned()).unwrap(),
+        total_dest_payment: 100,
+        relays: vec![RelayAddress {
+            public_key: PublicKey::from(&[2; PublicKey::len()]),
+            address: 2u32,
+        }],
+        valid_until: 1_600_000_000,
+        description: "Two apples".to_owned(),
+    };
+
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[22; Uid::len()]),
+            AppRequest::SignInvoice(unsigned_invoice.clone()),
+        ))
+        .await
+        .unwrap();
+
+    // The node should send back the signed invoice:
+    let signed_invoice = match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseSignInvoice(signed_invoice) => signed_invoice,
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        signed_invoice.dest_public_key,
+        initial_node_report.funder_report.local_public_key
+    );
+    assert!(verify_signed_invoice(&signed_invoice));
+    assert_eq!(
+        UnsignedInvoice::from(signed_invoice.clone()),
+        unsigned_invoice
+    );
+
+    // A tampered invoice should not pass verification:
+    let mut tampered_invoice = signed_invoice;
+    tampered_invoice.total_dest_payment = 101;
+    assert!(!verify_signed_invoice(&tampered_invoice));
+}
+
+#[test]
+fn test_app_server_loop_sign_invoice() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_sign_invoice(thread_pool.clone()));
+}
@@ -0,0 +1,225 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+
+use proto::app_server::messages::{
+    AllowedDestinations, AppPermissions, AppRequest, AppRestrictions, AppServerToApp,
+    AppToAppServer, SpendingLimit,
+};
+use proto::funder::messages::{
+    CreatePayment, CreateTransaction, Currency, FriendsRoute, FunderControl, FunderOutgoingControl,
+    RequestResult, TransactionResult,
+};
+use proto::index_client::messages::AppServerToIndexClient;
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_spending_limit<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let pk_e = PublicKey::from(&[0xee; PublicKey::len()]);
+    let pk_f = PublicKey::from(&[0xff; PublicKey::len()]);
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+
+    // The app may only pay pk_f, and may spend at most 30 credits every hour:
+    let (mut app_sender, app_server_receiver) = mpsc::channel(1);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(1);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: false,
+        config: false,
+        restrictions: AppRestrictions {
+            spending_limits: vec![SpendingLimit {
+                currency: currency1.clone(),
+                max_amount: 30,
+                window_secs: 3600,
+            }],
+            allowed_destinations: AllowedDestinations::Only(vec![pk_f.clone()]),
+            ..AppRestrictions::default()
+        },
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[1; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+        currency: currency1.clone(),
+        total_dest_payment: 20,
+        dest_public_key: pk_f.clone(),
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[22; Uid::len()]),
+            AppRequest::CreatePayment(create_payment),
+        ))
+        .await
+        .unwrap();
+
+    match funder_receiver.next().await.unwrap().funder_control {
+        FunderControl::CreatePayment(_) => {}
+        _ => unreachable!(),
+    };
+
+    let route = FriendsRoute {
+        public_keys: vec![pk_e.clone(), pk_f.clone()],
+    };
+
+    // A transaction within the limit (20 + 4 <= 30) is forwarded to the funder:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[1; PaymentId::len()]),
+        request_id: Uid::from(&[3; Uid::len()]),
+        route: route.clone(),
+        dest_payment: 20,
+        fees: 4,
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[23; Uid::len()]),
+            AppRequest::CreateTransaction(create_transaction.clone()),
+        ))
+        .await
+        .unwrap();
+
+    match funder_receiver.next().await.unwrap().funder_control {
+        FunderControl::CreateTransaction(received_create_transaction) => {
+            assert_eq!(received_create_transaction, create_transaction)
+        }
+        _ => unreachable!(),
+    };
+
+    // Another transaction would exceed the limit (24 + 10 > 30):
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[1; PaymentId::len()]),
+        request_id: Uid::from(&[4; Uid::len()]),
+        route: route.clone(),
+        dest_payment: 10,
+        fees: 0,
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[24; Uid::len()]),
+            AppRequest::CreateTransaction(create_transaction),
+        ))
+        .await
+        .unwrap();
+
+    // The transaction is rejected, and the app remains connected:
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::TransactionResult(transaction_result) => {
+            assert_eq!(
+                transaction_result,
+                TransactionResult {
+                    request_id: Uid::from(&[4; Uid::len()]),
+                    result: RequestResult::Failure,
+                }
+            );
+        }
+        _ => unreachable!(),
+    }
+    assert!(funder_receiver.try_next().is_err());
+
+    // The first transaction fails, and its charge is refunded:
+    let transaction_result = TransactionResult {
+        request_id: Uid::from(&[3; Uid::len()]),
+        result: RequestResult::Failure,
+    };
+    funder_sender
+        .send(FunderOutgoingControl::TransactionResult(
+            transaction_result.clone(),
+            false,
+        ))
+        .await
+        .unwrap();
+
+    match index_client_receiver.next().await.unwrap() {
+        AppServerToIndexClient::ReportRouteOutcome(_) => {}
+        _ => unreachable!(),
+    }
+    match app_receiver.next().await.unwrap() {
+        AppServerToApp::TransactionResult(received_transaction_result) => {
+            assert_eq!(received_transaction_result, transaction_result);
+        }
+        _ => unreachable!(),
+    }
+
+    // Now the app can spend again:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[1; PaymentId::len()]),
+        request_id: Uid::from(&[5; Uid::len()]),
+        route,
+        dest_payment: 20,
+        fees: 10,
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[25; Uid::len()]),
+            AppRequest::CreateTransaction(create_transaction.clone()),
+        ))
+        .await
+        .unwrap();
+
+    match funder_receiver.next().await.unwrap().funder_control {
+        FunderControl::CreateTransaction(received_create_transaction) => {
+            assert_eq!(received_create_transaction, create_transaction)
+        }
+        _ => unreachable!(),
+    };
+
+    // Paying a destination that is not allowed disconnects the app:
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[6; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[7; InvoiceId::len()]),
+        currency: currency1.clone(),
+        total_dest_payment: 1,
+        dest_public_key: pk_e.clone(),
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[26; Uid::len()]),
+            AppRequest::CreatePayment(create_payment),
+        ))
+        .await
+        .unwrap();
+
+    assert!(app_receiver.next().await.is_none());
+}
+
+#[test]
+fn test_app_server_loop_spending_limit() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_spending_limit(thread_pool.clone()));
+}
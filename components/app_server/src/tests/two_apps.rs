@@ -7,7 +7,9 @@ use common::conn::ConnPair;
 
 use proto::crypto::PublicKey;
 
-use proto::app_server::messages::{AppPermissions, AppServerToApp, NodeReportMutation};
+use proto::app_server::messages::{
+    AppPermissions, AppRestrictions, AppServerToApp, NodeReportMutation,
+};
 use proto::index_client::messages::{
     IndexClientReportMutation, IndexClientReportMutations, IndexClientToAppServer,
 };
@@ -37,10 +39,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -64,10 +68,12 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
 
     let (report_sender, report_receiver) = oneshot::channel();
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa1; PublicKey::len()]),
         app_permissions,
         report_sender,
     };
@@ -2,9 +2,15 @@ use std::collections::HashMap;
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{FutureExt, TryFutureExt};
+use futures::{stream, FutureExt, Stream, TryFutureExt};
 
-use proto::crypto::PublicKey;
+use crypto::identity::{Identity, SoftwareEd25519Identity};
+use crypto::rand::RandGen;
+use crypto::test_utils::DummyRandom;
+
+use identity::{create_identity, IdentityClient};
+
+use proto::crypto::{PrivateKey, PublicKey};
 
 use proto::app_server::messages::{NamedRelayAddress, NodeReport};
 use proto::funder::messages::{FunderIncomingControl, FunderOutgoingControl};
@@ -14,7 +20,7 @@ use proto::index_client::messages::{
 use proto::index_server::messages::NamedIndexServerAddress;
 use proto::report::messages::FunderReport;
 
-use crate::server::{app_server_loop, IncomingAppConnection};
+use crate::server::{app_server_loop, AppPermissionsUpdate, IncomingAppConnection};
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
@@ -47,6 +53,26 @@ pub fn spawn_dummy_app_server<S>(
 )
 where
     S: Spawn + Clone + Send + 'static,
+{
+    spawn_dummy_app_server_with_updates(spawner, stream::empty())
+}
+
+/// Spawns an app server loop that receives updates to the permissions of apps from
+/// `permissions_updates`.
+pub fn spawn_dummy_app_server_with_updates<S, PU>(
+    spawner: S,
+    permissions_updates: PU,
+) -> (
+    mpsc::Sender<FunderOutgoingControl<u32>>,
+    mpsc::Receiver<FunderIncomingControl<u32>>,
+    mpsc::Sender<IndexClientToAppServer<u32>>,
+    mpsc::Receiver<AppServerToIndexClient<u32>>,
+    mpsc::Sender<IncomingAppConnection<u32>>,
+    NodeReport<u32>,
+)
+where
+    S: Spawn + Clone + Send + 'static,
+    PU: Stream<Item = AppPermissionsUpdate> + Unpin + Send + 'static,
 {
     let (funder_sender, from_funder) = mpsc::channel(0);
     let (to_funder, funder_receiver) = mpsc::channel(0);
@@ -56,13 +82,23 @@ where
 
     let (connections_sender, incoming_connections) = mpsc::channel(0);
 
+    // Spawn an identity service for the node:
+    let mut rng = DummyRandom::new(&[1u8]);
+    let private_key = PrivateKey::rand_gen(&mut rng);
+    let identity = SoftwareEd25519Identity::from_private_key(&private_key).unwrap();
+    let local_public_key = identity.get_public_key();
+    let (requests_sender, identity_server) = create_identity(identity);
+    spawner.spawn(identity_server).unwrap();
+    let identity_client = IdentityClient::new(requests_sender);
+
     // Create a dummy initial_node_report:
     let funder_report = FunderReport {
-        local_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+        local_public_key,
         relays: vec![dummy_named_relay_address(0), dummy_named_relay_address(1)]
             .into_iter()
             .collect(),
         friends: HashMap::new(),
+        exchange_rates: Vec::new(),
     };
 
     let server100 = NamedIndexServerAddress {
@@ -80,6 +116,7 @@ where
     let index_client_report = IndexClientReport {
         index_servers: vec![server100, server101],
         opt_connected_server: Some(PublicKey::from(&[0xaa; PublicKey::len()])),
+        topology_snapshots: Vec::new(),
     };
 
     let initial_node_report = NodeReport {
@@ -93,7 +130,9 @@ where
         from_index_client,
         to_index_client,
         incoming_connections,
+        permissions_updates,
         initial_node_report.clone(),
+        identity_client,
         spawner.clone(),
     )
     .map_err(|e| error!("app_server_loop() error: {:?}", e))
@@ -0,0 +1,125 @@
+use std::convert::TryFrom;
+
+use futures::channel::{mpsc, oneshot};
+use futures::executor::{block_on, ThreadPool};
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use common::conn::ConnPair;
+
+use proto::crypto::{HashResult, InvoiceId, PlainLock, PublicKey, Signature, Uid};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer, VerifyReceipt,
+    VerifyReceiptStatus,
+};
+use proto::funder::messages::{Currency, Receipt};
+
+use super::utils::spawn_dummy_app_server;
+use crate::server::IncomingAppConnection;
+
+async fn task_app_server_loop_verify_receipt<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let server_conn_pair = ConnPair::from_raw(app_server_sender, app_server_receiver);
+
+    // Verification does not require any permissions:
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: false,
+        restrictions: AppRestrictions::default(),
+    };
+
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: PublicKey::from(&[0xa0; PublicKey::len()]),
+        app_permissions,
+        report_sender,
+    };
+
+    connections_sender
+        .send(incoming_app_connection)
+        .await
+        .unwrap();
+
+    let (_report, conn_sender) = report_receiver.await.unwrap();
+    conn_sender.send(server_conn_pair).unwrap();
+
+    let receipt = Receipt {
+        response_hash: HashResult::from(&[0; HashResult::len()]),
+        invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+        currency: Currency::try_from("FST".to_owned()).unwrap(),
+        src_plain_lock: PlainLock::from(&[2; PlainLock::len()]),
+        dest_plain_lock: PlainLock::from(&[3; PlainLock::len()]),
+        is_complete: true,
+        dest_payment: 100,
+        total_dest_payment: 100,
+        signature: Signature::from(&[4; Signature::len()]),
+    };
+
+    // Receipt does not match the invoice:
+    let verify_receipt = VerifyReceipt {
+        request_id: Uid::from(&[5; Uid::len()]),
+        receipt: receipt.clone(),
+        dest_public_key: PublicKey::from(&[6; PublicKey::len()]),
+        invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+        total_dest_payment: 101,
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[22; Uid::len()]),
+            AppRequest::VerifyReceipt(verify_receipt),
+        ))
+        .await
+        .unwrap();
+
+    let response = match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseVerifyReceipt(response) => response,
+        _ => unreachable!(),
+    };
+    assert_eq!(response.request_id, Uid::from(&[5; Uid::len()]));
+    assert_eq!(response.status, VerifyReceiptStatus::InvoiceMismatch);
+
+    // Receipt matches the invoice, but was not signed by dest_public_key:
+    let verify_receipt = VerifyReceipt {
+        request_id: Uid::from(&[7; Uid::len()]),
+        receipt,
+        dest_public_key: PublicKey::from(&[6; PublicKey::len()]),
+        invoice_id: InvoiceId::from(&[1; InvoiceId::len()]),
+        total_dest_payment: 100,
+    };
+    app_sender
+        .send(AppToAppServer::new(
+            Uid::from(&[23; Uid::len()]),
+            AppRequest::VerifyReceipt(verify_receipt),
+        ))
+        .await
+        .unwrap();
+
+    let response = match app_receiver.next().await.unwrap() {
+        AppServerToApp::ResponseVerifyReceipt(response) => response,
+        _ => unreachable!(),
+    };
+    assert_eq!(response.request_id, Uid::from(&[7; Uid::len()]));
+    assert_eq!(response.status, VerifyReceiptStatus::WrongSigner);
+}
+
+#[test]
+fn test_app_server_loop_verify_receipt() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_app_server_loop_verify_receipt(thread_pool.clone()));
+}
@@ -0,0 +1,30 @@
+#![deny(trivial_numeric_casts, warnings)]
+#![allow(intra_doc_link_resolution_failure)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::implicit_hasher,
+    clippy::module_inception,
+    clippy::new_without_default
+)]
+
+#[macro_use]
+extern crate log;
+
+use std::io;
+
+use structopt::StructOpt;
+
+use bin::stindexctl::{stindexctl, IndexCtlError, StIndexCtlCmd};
+
+fn run() -> Result<(), IndexCtlError> {
+    env_logger::init();
+
+    let st_index_ctl_cmd = StIndexCtlCmd::from_args();
+    stindexctl(st_index_ctl_cmd, &mut io::stdout())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        error!("run() error: {:?}", e);
+    }
+}
@@ -11,6 +11,7 @@
 extern crate log;
 
 pub mod stindex;
+pub mod stindexctl;
 pub mod stmgrlib;
 pub mod stnode;
 pub mod strelay;
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use std::fs;
 use std::net::SocketAddr;
@@ -7,6 +7,7 @@ use std::time::Duration;
 
 use futures::executor::{block_on, ThreadPool};
 use futures::task::SpawnExt;
+use futures::{future, stream, Stream, StreamExt};
 
 use structopt::StructOpt;
 
@@ -18,15 +19,19 @@ use crypto::rand::system_random;
 
 use identity::{create_identity, IdentityClient};
 
+use index_server::{ClientLimits, FileGraphStore, GraphStoreConfig, GraphStoreError};
+
 use derive_more::From;
 
 use crate::stindex::net_index::{net_index_server, NetIndexServerError};
 use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
-use timer::create_timer;
+use timer::{create_timer, TimerClient};
 
 use net::{TcpConnector, TcpListener};
 
-use proto::file::{IdentityFile, IndexServerFile};
+use proto::crypto::PublicKey;
+use proto::file::{IdentityFile, IndexAdminFile, IndexClientFile, IndexServerFile};
+use proto::net::messages::NetAddress;
 use proto::ser_string::{deserialize_from_string, StringSerdeError};
 
 // TODO: Maybe take as a command line argument in the future?
@@ -35,6 +40,8 @@ use proto::ser_string::{deserialize_from_string, StringSerdeError};
 pub const MAX_CONCURRENT_ENCRYPT: usize = 0x200;
 /// Amount of ticks we wait before attempting to reconnect to a remote index server.
 pub const BACKOFF_TICKS: usize = 0x8;
+/// Amount of ticks between reloads of the trusted index servers directory.
+pub const TRUSTED_RELOAD_TICKS: usize = 0x40;
 
 /// stindex: Offset Index Server
 /// A server used to index the Offset network. Collects topology information from nodes, and serves
@@ -51,9 +58,46 @@ pub struct StIndexCmd {
     /// Listening address for servers
     #[structopt(short = "s", long = "lserver")]
     pub lserver: SocketAddr,
-    /// Directory path of trusted index servers
+    /// Directory path of trusted index servers.
+    /// The directory is reloaded periodically, so servers can be added or removed at runtime.
     #[structopt(parse(from_os_str), short = "t", long = "trusted")]
     pub trusted: PathBuf,
+    /// Directory path for persisting the network graph (One file per currency).
+    /// If not provided, the graph is kept in memory only.
+    #[structopt(parse(from_os_str), short = "g", long = "graphdir")]
+    pub opt_graph_dir: Option<PathBuf>,
+    /// Maximum amount of currency graphs kept in memory when a graph directory is used
+    #[structopt(long = "maxgraphs", default_value = "64")]
+    pub max_loaded_graphs: usize,
+    /// Listening address for admins.
+    /// If not provided, the index server can not be inspected by admins.
+    #[structopt(long = "ladmin")]
+    pub opt_ladmin: Option<SocketAddr>,
+    /// Directory path of authorized admins (One admin ticket per file)
+    #[structopt(parse(from_os_str), long = "admins")]
+    pub opt_admins: Option<PathBuf>,
+    /// Directory path of allowed clients (One index client ticket per file).
+    /// If not provided, all clients are allowed.
+    #[structopt(parse(from_os_str), long = "allowed")]
+    pub opt_allowed: Option<PathBuf>,
+    /// Maximum amount of requests a client may send in a burst
+    #[structopt(long = "reqburst", default_value = "64")]
+    pub max_request_burst: usize,
+    /// Amount of requests a client is allowed to send every tick
+    #[structopt(long = "reqpertick", default_value = "16")]
+    pub requests_per_tick: usize,
+    /// Maximum amount of mutations updates a client may send in a burst
+    #[structopt(long = "mutburst", default_value = "32")]
+    pub max_mutations_burst: usize,
+    /// Amount of mutations updates a client is allowed to send every tick
+    #[structopt(long = "mutpertick", default_value = "8")]
+    pub mutations_per_tick: usize,
+    /// Maximum amount of mutations in a single mutations update
+    #[structopt(long = "maxmutations", default_value = "256")]
+    pub max_mutations_per_update: usize,
+    /// Maximum amount of route searches waiting to be processed
+    #[structopt(long = "maxsearches", default_value = "256")]
+    pub max_pending_route_searches: usize,
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -61,6 +105,7 @@ pub struct StIndexCmd {
 pub enum IndexServerBinError {
     CreateThreadPoolError,
     CreateTimerError,
+    RequestTimerStreamError,
     NetIndexServerError(NetIndexServerError),
     LoadIdentityError,
     CreateIdentityError,
@@ -68,6 +113,7 @@ pub enum IndexServerBinError {
     IoError(std::io::Error),
     ListenError,
     StringSerdeError(StringSerdeError),
+    GraphStoreError(GraphStoreError),
 }
 
 /// Load a directory of index server address files, and return a map representing
@@ -85,22 +131,138 @@ pub fn load_trusted_servers(dir_path: &Path) -> Result<Vec<IndexServerFile>, Ind
     Ok(res_trusted)
 }
 
+/// Load a directory of index server address files, and return a map from the public key of
+/// every trusted server to its address
+fn load_trusted_servers_map(
+    dir_path: &Path,
+) -> Result<HashMap<PublicKey, NetAddress>, IndexServerBinError> {
+    Ok(load_trusted_servers(dir_path)?
+        .into_iter()
+        .map(|index_server_file| (index_server_file.public_key, index_server_file.address))
+        .collect())
+}
+
+/// Reload the trusted servers directory every `reload_ticks` ticks.
+/// Returns a stream of the full set of trusted servers, yielding a new item whenever the contents
+/// of the directory changes.
+async fn watch_trusted_servers(
+    dir_path: PathBuf,
+    mut trusted_servers: HashMap<PublicKey, NetAddress>,
+    mut timer_client: TimerClient,
+    reload_ticks: usize,
+) -> Result<impl Stream<Item = HashMap<PublicKey, NetAddress>> + Unpin, IndexServerBinError> {
+    let timer_stream = timer_client
+        .request_timer_stream("watch_trusted_servers".to_owned())
+        .await
+        .map_err(|_| IndexServerBinError::RequestTimerStreamError)?;
+
+    Ok(timer_stream
+        .enumerate()
+        .filter(move |(tick_index, _)| future::ready((tick_index + 1) % reload_ticks == 0))
+        .filter_map(move |_| {
+            let opt_update = match load_trusted_servers_map(&dir_path) {
+                Ok(new_trusted_servers) => {
+                    if new_trusted_servers != trusted_servers {
+                        info!("Trusted servers directory has changed");
+                        trusted_servers = new_trusted_servers.clone();
+                        Some(new_trusted_servers)
+                    } else {
+                        None
+                    }
+                }
+                Err(e) => {
+                    // We keep the last known trusted servers:
+                    warn!("Failed to reload trusted servers: {:?}", e);
+                    None
+                }
+            };
+            future::ready(opt_update)
+        }))
+}
+
+/// Load a directory of index admin files, and return the public keys of all the admins
+pub fn load_admins(dir_path: &Path) -> Result<HashSet<PublicKey>, IndexServerBinError> {
+    let mut res_admins = HashSet::new();
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        let index_admin_file: IndexAdminFile =
+            deserialize_from_string(&fs::read_to_string(&path)?)?;
+        res_admins.insert(index_admin_file.public_key);
+    }
+    Ok(res_admins)
+}
+
+/// Load a directory of index client files, and return the public keys of all the clients
+pub fn load_allowed_clients(dir_path: &Path) -> Result<HashSet<PublicKey>, IndexServerBinError> {
+    let mut res_allowed = HashSet::new();
+    for entry in fs::read_dir(dir_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            continue;
+        }
+        let index_client_file: IndexClientFile =
+            deserialize_from_string(&fs::read_to_string(&path)?)?;
+        res_allowed.insert(index_client_file.public_key);
+    }
+    Ok(res_allowed)
+}
+
 pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let StIndexCmd {
         idfile,
         lclient,
         lserver,
         trusted,
+        opt_graph_dir,
+        max_loaded_graphs,
+        opt_ladmin,
+        opt_admins,
+        opt_allowed,
+        max_request_burst,
+        requests_per_tick,
+        max_mutations_burst,
+        mutations_per_tick,
+        max_mutations_per_update,
+        max_pending_route_searches,
     } = st_index_cmd;
 
     let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile)?)?;
     let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
         .map_err(|_| IndexServerBinError::LoadIdentityError)?;
 
-    let trusted_servers = load_trusted_servers(Path::new(&trusted))?
-        .into_iter()
-        .map(|index_server_file| (index_server_file.public_key, index_server_file.address))
-        .collect::<HashMap<_, _>>();
+    let trusted_servers = load_trusted_servers_map(Path::new(&trusted))?;
+
+    let admins = match opt_admins {
+        Some(admins) => load_admins(Path::new(&admins))?,
+        None => HashSet::new(),
+    };
+
+    let opt_allowed_clients = match opt_allowed {
+        Some(allowed) => Some(load_allowed_clients(Path::new(&allowed))?),
+        None => None,
+    };
+
+    let client_limits = ClientLimits {
+        max_request_burst,
+        requests_per_tick,
+        max_mutations_burst,
+        mutations_per_tick,
+        max_mutations_per_update,
+        max_pending_route_searches,
+    };
+
+    let opt_graph_store_config = match opt_graph_dir {
+        Some(graph_dir) => Some(GraphStoreConfig {
+            graph_store: FileGraphStore::new(graph_dir)?,
+            max_loaded_shards: max_loaded_graphs,
+        }),
+        None => None,
+    };
 
     // Create a ThreadPool:
     let thread_pool = ThreadPool::new().map_err(|_| IndexServerBinError::CreateThreadPoolError)?;
@@ -121,6 +283,14 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let timer_client = create_timer(dur, thread_pool.clone())
         .map_err(|_| IndexServerBinError::CreateTimerError)?;
 
+    // Watch the trusted servers directory for changes:
+    let trusted_servers_updates = block_on(watch_trusted_servers(
+        trusted,
+        trusted_servers.clone(),
+        timer_client.clone(),
+        TRUSTED_RELOAD_TICKS,
+    ))?;
+
     // Start listening to clients:
     let client_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
 
@@ -139,6 +309,20 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     } = block_on(server_tcp_listener.listen(lserver))
         .map_err(|_| IndexServerBinError::ListenError)?;
 
+    // Start listening to admins:
+    let incoming_admin_raw_conns = match opt_ladmin {
+        Some(ladmin) => {
+            let admin_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+            let ListenerClient {
+                config_sender: _,
+                conn_receiver: incoming_admin_raw_conns,
+            } = block_on(admin_tcp_listener.listen(ladmin))
+                .map_err(|_| IndexServerBinError::ListenError)?;
+            incoming_admin_raw_conns.boxed()
+        }
+        None => stream::empty().boxed(),
+    };
+
     // A tcp connector, Used to connect to remote servers:
     let raw_server_net_connector = TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone());
 
@@ -147,13 +331,19 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let index_server_fut = net_index_server(
         incoming_client_raw_conns,
         incoming_server_raw_conns,
+        incoming_admin_raw_conns,
+        admins,
+        opt_allowed_clients,
         raw_server_net_connector,
         identity_client,
         timer_client,
         rng,
         trusted_servers,
+        trusted_servers_updates,
         MAX_CONCURRENT_ENCRYPT,
         BACKOFF_TICKS,
+        client_limits,
+        opt_graph_store_config,
         graph_service_thread_pool,
         thread_pool,
     );
@@ -0,0 +1,3 @@
+mod stindexctllib;
+
+pub use self::stindexctllib::{stindexctl, IndexCtlError, StIndexCtlCmd};
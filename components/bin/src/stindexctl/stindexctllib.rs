@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures::executor::{block_on, ThreadPool};
+use futures::task::SpawnExt;
+use futures::{SinkExt, StreamExt};
+
+use structopt::StructOpt;
+
+use derive_more::From;
+
+use common::conn::{ConnPairVec, FutTransform};
+use common::int_convert::usize_to_u64;
+
+use crypto::identity::SoftwareEd25519Identity;
+use crypto::rand::{system_random, RandGen};
+
+use identity::{create_identity, IdentityClient};
+
+use connection::create_secure_connector;
+use net::TcpConnector;
+use timer::create_timer;
+
+use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
+use proto::crypto::{PublicKey, Uid};
+use proto::file::{IdentityFile, IndexServerFile};
+use proto::index_server::messages::{
+    IndexAdminToServer, IndexServerStatus, IndexServerToAdmin, ServerConnState,
+};
+use proto::net::messages::NetAddress;
+use proto::proto_ser::{ProtoDeserialize, ProtoSerialize};
+use proto::ser_string::{deserialize_from_string, StringSerdeError};
+
+#[derive(Debug, StructOpt)]
+pub struct StatusCmd {
+    /// Index server admin ticket files (public key and admin listening address).
+    /// Such a ticket can be created using `stmgr index-ticket` with the admin listening address.
+    #[structopt(parse(from_os_str), short = "s", long = "server")]
+    pub servers: Vec<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum StIndexCtlSubcommand {
+    /// Show the status of index servers, and detect federation partitions
+    #[structopt(name = "status")]
+    Status(StatusCmd),
+}
+
+/// stindex-ctl: Offset Index Server administration
+/// Inspect the state of running index servers
+#[derive(Debug, StructOpt)]
+#[structopt(name = "stindex-ctl")]
+pub struct StIndexCtlCmd {
+    /// Index admin identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile: PathBuf,
+    #[structopt(subcommand)]
+    pub subcommand: StIndexCtlSubcommand,
+}
+
+#[derive(Debug, From)]
+pub enum IndexCtlError {
+    CreateThreadPoolError,
+    CreateTimerError,
+    LoadIdentityError,
+    CreateIdentityError,
+    IoError(io::Error),
+    StringSerdeError(StringSerdeError),
+    NoServers,
+    ConnectError(PublicKey),
+    SendRequestError(PublicKey),
+    ReceiveResponseError(PublicKey),
+    InvalidResponse(PublicKey),
+}
+
+/// Encode a public key for display
+fn public_key_to_string(public_key: &PublicKey) -> String {
+    base64::encode_config(&public_key, base64::URL_SAFE_NO_PAD)
+}
+
+/// Divide the servers into groups that can exchange mutations with each other.
+/// Two servers are linked if at least one of them reports the other as reachable.
+/// Every queried server shows up in exactly one of the returned groups.
+fn federation_components(statuses: &[(PublicKey, IndexServerStatus)]) -> Vec<Vec<PublicKey>> {
+    let mut links: HashMap<PublicKey, HashSet<PublicKey>> = HashMap::new();
+    for (public_key, index_server_status) in statuses {
+        let _ = links.entry(public_key.clone()).or_insert_with(HashSet::new);
+        for server_status in &index_server_status.servers {
+            if !server_status.is_reachable() {
+                continue;
+            }
+            links
+                .entry(public_key.clone())
+                .or_insert_with(HashSet::new)
+                .insert(server_status.public_key.clone());
+            links
+                .entry(server_status.public_key.clone())
+                .or_insert_with(HashSet::new)
+                .insert(public_key.clone());
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut components = Vec::new();
+    for (public_key, _) in statuses {
+        if visited.contains(public_key) {
+            continue;
+        }
+        let mut component = Vec::new();
+        let mut pending = vec![public_key.clone()];
+        visited.insert(public_key.clone());
+        while let Some(cur_public_key) = pending.pop() {
+            for neighbor in &links[&cur_public_key] {
+                if visited.insert(neighbor.clone()) {
+                    pending.push(neighbor.clone());
+                }
+            }
+            component.push(cur_public_key);
+        }
+        components.push(component);
+    }
+    components
+}
+
+/// Connect to an index server as an admin, and request its status
+async fn request_status<C>(
+    mut secure_connector: C,
+    index_server_file: IndexServerFile,
+    request_id: Uid,
+) -> Result<IndexServerStatus, IndexCtlError>
+where
+    C: FutTransform<Input = (PublicKey, NetAddress), Output = Option<ConnPairVec>>,
+{
+    let IndexServerFile {
+        public_key,
+        address,
+    } = index_server_file;
+
+    let (mut sender, mut receiver) = secure_connector
+        .transform((public_key.clone(), address))
+        .await
+        .ok_or_else(|| IndexCtlError::ConnectError(public_key.clone()))?
+        .split();
+
+    sender
+        .send(IndexAdminToServer::RequestStatus(request_id.clone()).proto_serialize())
+        .await
+        .map_err(|_| IndexCtlError::SendRequestError(public_key.clone()))?;
+
+    let data = receiver
+        .next()
+        .await
+        .ok_or_else(|| IndexCtlError::ReceiveResponseError(public_key.clone()))?;
+
+    let IndexServerToAdmin::ResponseStatus(index_server_status) =
+        IndexServerToAdmin::proto_deserialize(&data)
+            .map_err(|_| IndexCtlError::InvalidResponse(public_key.clone()))?;
+
+    if index_server_status.request_id != request_id {
+        return Err(IndexCtlError::InvalidResponse(public_key));
+    }
+    Ok(index_server_status)
+}
+
+/// Print the status of one index server
+fn print_status(
+    public_key: &PublicKey,
+    index_server_status: &IndexServerStatus,
+    writer: &mut impl io::Write,
+) -> Result<(), IndexCtlError> {
+    writeln!(writer, "Index server {}:", public_key_to_string(public_key))?;
+    writeln!(writer, "  Clients: {}", index_server_status.num_clients)?;
+    writeln!(writer, "  Nodes: {}", index_server_status.num_nodes)?;
+    writeln!(
+        writer,
+        "  Exchange rates: {}",
+        index_server_status.num_exchange_rates
+    )?;
+
+    writeln!(writer, "  Trusted servers:")?;
+    for server_status in &index_server_status.servers {
+        let conn_state_str = match server_status.conn_state {
+            ServerConnState::Connected => "connected",
+            ServerConnState::Initiating => "initiating",
+            ServerConnState::Listening => "listening",
+        };
+        writeln!(
+            writer,
+            "    {} {} (ticks since time hash: {}){}",
+            public_key_to_string(&server_status.public_key),
+            conn_state_str,
+            server_status.ticks_since_time_hash,
+            if server_status.is_lagging {
+                " LAGGING"
+            } else {
+                ""
+            }
+        )?;
+    }
+
+    writeln!(writer, "  Currencies:")?;
+    for currency_graph_status in &index_server_status.currency_graphs {
+        if currency_graph_status.is_loaded {
+            writeln!(
+                writer,
+                "    {}: {} nodes, {} edges",
+                currency_graph_status.currency.as_str(),
+                currency_graph_status.num_nodes,
+                currency_graph_status.num_edges
+            )?;
+        } else {
+            writeln!(
+                writer,
+                "    {}: not loaded",
+                currency_graph_status.currency.as_str()
+            )?;
+        }
+    }
+
+    let unreachable_servers = index_server_status.unreachable_servers();
+    if !unreachable_servers.is_empty() {
+        writeln!(writer, "  Unreachable servers:")?;
+        for server_public_key in &unreachable_servers {
+            writeln!(writer, "    {}", public_key_to_string(server_public_key))?;
+        }
+    }
+    Ok(())
+}
+
+fn status(
+    status_cmd: StatusCmd,
+    identity_client: IdentityClient,
+    thread_pool: ThreadPool,
+    writer: &mut impl io::Write,
+) -> Result<(), IndexCtlError> {
+    if status_cmd.servers.is_empty() {
+        return Err(IndexCtlError::NoServers);
+    }
+
+    let mut index_server_files = Vec::new();
+    for server_path in &status_cmd.servers {
+        let index_server_file: IndexServerFile =
+            deserialize_from_string(&fs::read_to_string(server_path)?)?;
+        index_server_files.push(index_server_file);
+    }
+
+    let mut rng = system_random();
+
+    // Get a timer client:
+    let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
+    let timer_client =
+        create_timer(dur, thread_pool.clone()).map_err(|_| IndexCtlError::CreateTimerError)?;
+
+    // A tcp connector, Used to connect to remote servers:
+    let tcp_connector = TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone());
+
+    let secure_connector = create_secure_connector(
+        tcp_connector,
+        timer_client,
+        identity_client,
+        rng.clone(),
+        thread_pool,
+    );
+
+    let mut statuses = Vec::new();
+    for index_server_file in index_server_files {
+        let public_key = index_server_file.public_key.clone();
+        let request_id = Uid::rand_gen(&mut rng);
+        match block_on(request_status(
+            secure_connector.clone(),
+            index_server_file,
+            request_id,
+        )) {
+            Ok(index_server_status) => {
+                print_status(&public_key, &index_server_status, writer)?;
+                statuses.push((public_key, index_server_status));
+            }
+            Err(e) => {
+                writeln!(
+                    writer,
+                    "Index server {}: failed to obtain status: {:?}",
+                    public_key_to_string(&public_key),
+                    e
+                )?;
+            }
+        }
+        writeln!(writer)?;
+    }
+
+    let components = federation_components(&statuses);
+    if components.len() > 1 {
+        writeln!(
+            writer,
+            "Federation is partitioned into {} groups:",
+            components.len()
+        )?;
+        for (i, component) in components.iter().enumerate() {
+            writeln!(writer, "  Group {}:", i)?;
+            for public_key in component {
+                writeln!(writer, "    {}", public_key_to_string(public_key))?;
+            }
+        }
+    } else if !statuses.is_empty() {
+        writeln!(writer, "No federation partitions detected.")?;
+    }
+
+    Ok(())
+}
+
+pub fn stindexctl(
+    st_index_ctl_cmd: StIndexCtlCmd,
+    writer: &mut impl io::Write,
+) -> Result<(), IndexCtlError> {
+    let StIndexCtlCmd { idfile, subcommand } = st_index_ctl_cmd;
+
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| IndexCtlError::LoadIdentityError)?;
+
+    // Create a ThreadPool:
+    let thread_pool = ThreadPool::new().map_err(|_| IndexCtlError::CreateThreadPoolError)?;
+
+    // Spawn identity service:
+    let (sender, identity_loop) = create_identity(identity);
+    thread_pool
+        .spawn(identity_loop)
+        .map_err(|_| IndexCtlError::CreateIdentityError)?;
+    let identity_client = IdentityClient::new(sender);
+
+    match subcommand {
+        StIndexCtlSubcommand::Status(status_cmd) => {
+            status(status_cmd, identity_client, thread_pool, writer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proto::index_server::messages::ServerStatus;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    fn index_server_status(servers: &[(u8, bool)]) -> IndexServerStatus {
+        IndexServerStatus {
+            request_id: Uid::from(&[0; Uid::len()]),
+            servers: servers
+                .iter()
+                .map(|(i, is_reachable)| ServerStatus {
+                    public_key: pk(*i),
+                    conn_state: if *is_reachable {
+                        ServerConnState::Connected
+                    } else {
+                        ServerConnState::Initiating
+                    },
+                    ticks_since_time_hash: 0,
+                    is_lagging: false,
+                })
+                .collect(),
+            num_clients: 0,
+            num_nodes: 0,
+            currency_graphs: Vec::new(),
+            num_exchange_rates: 0,
+        }
+    }
+
+    #[test]
+    fn test_federation_components() {
+        // 0 -- 1, 2 -- 3 (3 was not queried), 4 can not reach anyone:
+        let statuses = vec![
+            (pk(0), index_server_status(&[(1, true), (2, false)])),
+            (pk(1), index_server_status(&[(0, true), (4, false)])),
+            (pk(2), index_server_status(&[(0, false), (3, true)])),
+            (pk(4), index_server_status(&[(1, false)])),
+        ];
+        let mut components: Vec<Vec<PublicKey>> = federation_components(&statuses)
+            .into_iter()
+            .map(|mut component| {
+                component.sort();
+                component
+            })
+            .collect();
+        components.sort();
+        assert_eq!(
+            components,
+            vec![vec![pk(0), pk(1)], vec![pk(2), pk(3)], vec![pk(4)]]
+        );
+
+        // Fully connected federation:
+        let statuses = vec![
+            (pk(0), index_server_status(&[(1, true), (2, true)])),
+            (pk(1), index_server_status(&[(0, true), (2, true)])),
+        ];
+        assert_eq!(federation_components(&statuses).len(), 1);
+    }
+}
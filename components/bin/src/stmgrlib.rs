@@ -10,7 +10,7 @@ use structopt::StructOpt;
 use crypto::identity::{Identity, SoftwareEd25519Identity};
 use crypto::rand::{system_random, RandGen};
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use proto::crypto::PrivateKey;
 use proto::net::messages::{NetAddress, NetAddressError};
 
@@ -18,7 +18,8 @@ use database::file_db::FileDb;
 use node::NodeState;
 
 use proto::file::{
-    IdentityFile, IndexServerFile, NodeAddressFile, NodeEntryFile, RelayAddressFile, TrustedAppFile,
+    IdentityFile, IndexAdminFile, IndexClientFile, IndexServerFile, NodeAddressFile, NodeEntryFile,
+    RelayAddressFile, TrustedAppFile,
 };
 use proto::ser_string::{deserialize_from_string, serialize_to_string, StringSerdeError};
 
@@ -96,6 +97,26 @@ pub struct IndexTicketCmd {
     pub output_path: PathBuf,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AdminTicketCmd {
+    /// StCtrl index admin identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Index admin ticket output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct IndexClientTicketCmd {
+    /// StCtrl node identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile_path: PathBuf,
+    /// Index client ticket output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output_path: PathBuf,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct NodeTicketCmd {
     /// StCtrl node identity file path
@@ -145,6 +166,13 @@ pub enum StMgrCmd {
     /// Create an index server ticket
     #[structopt(name = "index-ticket")]
     IndexTicket(IndexTicketCmd),
+    /// Create an index server administrator ticket
+    #[structopt(name = "admin-ticket")]
+    AdminTicket(AdminTicketCmd),
+    /// Create an index server client ticket.
+    /// Used by index servers that only serve an allowlist of clients.
+    #[structopt(name = "index-client-ticket")]
+    IndexClientTicket(IndexClientTicketCmd),
     /// Create a node server ticket
     #[structopt(name = "node-ticket")]
     NodeTicket(NodeTicketCmd),
@@ -250,6 +278,7 @@ fn app_ticket(
         buyer: pbuyer,
         seller: pseller,
         config: pconfig,
+        restrictions: AppRestrictions::default(),
     };
 
     // Store app ticket to file:
@@ -343,6 +372,76 @@ fn index_ticket(
     Ok(())
 }
 
+#[derive(Debug, From)]
+pub enum AdminTicketError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    IoError(std::io::Error),
+    StringSerdeError(StringSerdeError),
+}
+
+/// Create an index server administrator ticket
+/// The ticket can be put in the admins directory of an index server, allowing the
+/// administrator to inspect the index server's state.
+fn admin_ticket(
+    AdminTicketCmd {
+        idfile_path,
+        output_path,
+    }: AdminTicketCmd,
+) -> Result<(), AdminTicketError> {
+    // Make sure that output does not exist.
+    if output_path.exists() {
+        return Err(AdminTicketError::OutputAlreadyExists);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| AdminTicketError::LoadIdentityError)?;
+    let public_key = identity.get_public_key();
+
+    let index_admin_file = IndexAdminFile { public_key };
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&index_admin_file)?.as_bytes())?;
+    Ok(())
+}
+
+#[derive(Debug, From)]
+pub enum IndexClientTicketError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    IoError(std::io::Error),
+    StringSerdeError(StringSerdeError),
+}
+
+/// Create an index server client ticket
+/// The ticket can be put in the allowed clients directory of an index server, allowing the
+/// node to use the index server.
+fn index_client_ticket(
+    IndexClientTicketCmd {
+        idfile_path,
+        output_path,
+    }: IndexClientTicketCmd,
+) -> Result<(), IndexClientTicketError> {
+    // Make sure that output does not exist.
+    if output_path.exists() {
+        return Err(IndexClientTicketError::OutputAlreadyExists);
+    }
+
+    // Parse identity file:
+    let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile_path)?)?;
+    let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
+        .map_err(|_| IndexClientTicketError::LoadIdentityError)?;
+    let public_key = identity.get_public_key();
+
+    let index_client_file = IndexClientFile { public_key };
+
+    let mut file = File::create(output_path)?;
+    file.write_all(&serialize_to_string(&index_client_file)?.as_bytes())?;
+    Ok(())
+}
+
 #[derive(Debug, From)]
 pub enum NodeTicketError {
     OutputAlreadyExists,
@@ -426,6 +525,8 @@ pub enum StmError {
     AppTicketError(AppTicketError),
     RelayTicketError(RelayTicketError),
     IndexTicketError(IndexTicketError),
+    AdminTicketError(AdminTicketError),
+    IndexClientTicketError(IndexClientTicketError),
     NodeTicketError(NodeTicketError),
 }
 
@@ -436,6 +537,8 @@ pub fn stmgr(st_mgr_cmd: StMgrCmd) -> Result<(), StmError> {
         StMgrCmd::AppTicket(i) => app_ticket(i)?,
         StMgrCmd::RelayTicket(i) => relay_ticket(i)?,
         StMgrCmd::IndexTicket(i) => index_ticket(i)?,
+        StMgrCmd::AdminTicket(i) => admin_ticket(i)?,
+        StMgrCmd::IndexClientTicket(i) => index_client_ticket(i)?,
         StMgrCmd::NodeTicket(i) => node_ticket(i)?,
         StMgrCmd::NodeEntry(i) => node_entry(i)?,
     }
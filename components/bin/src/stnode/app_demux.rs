@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{Stream, StreamExt};
+
+use common::conn::ConnPairVec;
+use common::transform_pool::transform_pool_loop;
+
+use connection::create_app_target_reader;
+
+use proto::crypto::PublicKey;
+
+use timer::TimerClient;
+
+/// Maximum amount of incoming app connections we read the target node of at the same time.
+const MAX_CONCURRENT_TARGET_READS: usize = 0x20;
+
+#[derive(Debug)]
+pub enum AppDemuxError {
+    SpawnError,
+}
+
+/// Forward every incoming app connection to the hosted node it targets.
+/// The target node is the destination public key declared by the app when setting up the secure
+/// channel. If only one node is hosted, all connections are forwarded to it.
+///
+/// Connections that target an unknown node are dropped. Connections are also dropped if the target
+/// node has too many pending incoming connections (The capacity of its channel).
+pub async fn demux_incoming_apps<IAC, S>(
+    incoming_app_raw_conns: IAC,
+    mut nodes: HashMap<PublicKey, mpsc::Sender<ConnPairVec>>,
+    timer_client: TimerClient,
+    spawner: S,
+) -> Result<(), AppDemuxError>
+where
+    IAC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    // A single hosted node can handle every incoming connection:
+    if nodes.len() == 1 {
+        if let Some((_public_key, node_sender)) = nodes.into_iter().next() {
+            let _ = incoming_app_raw_conns.map(Ok).forward(node_sender).await;
+        }
+        return Ok(());
+    }
+
+    let (targets_sender, mut targets_receiver) = mpsc::channel(0);
+
+    // Read the target node of every incoming connection:
+    let pool_fut = transform_pool_loop(
+        incoming_app_raw_conns,
+        targets_sender,
+        create_app_target_reader(timer_client),
+        MAX_CONCURRENT_TARGET_READS,
+    );
+    let _pool_handle = spawner
+        .spawn_with_handle(pool_fut)
+        .map_err(|_| AppDemuxError::SpawnError)?;
+
+    while let Some((opt_public_key, conn_pair)) = targets_receiver.next().await {
+        let node_sender = match opt_public_key
+            .as_ref()
+            .and_then(|public_key| nodes.get_mut(public_key))
+        {
+            Some(node_sender) => node_sender,
+            None => {
+                warn!(
+                    "App connection targets an unknown node {:?}",
+                    opt_public_key
+                );
+                continue;
+            }
+        };
+        // Forward the connection without waiting, so that a busy node will not delay
+        // connections to other nodes:
+        if node_sender.try_send(conn_pair).is_err() {
+            warn!("Node {:?} is busy. Dropping app connection", opt_public_key);
+        }
+    }
+    Ok(())
+}
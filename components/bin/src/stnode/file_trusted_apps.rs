@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
-use futures::StreamExt;
+use futures::channel::mpsc;
+use futures::{future, SinkExt, Stream, StreamExt};
 
 use async_std::fs;
 use async_std::path::{Path, PathBuf};
@@ -14,6 +15,8 @@ use proto::crypto::PublicKey;
 use proto::file::TrustedAppFile;
 use proto::ser_string::{deserialize_from_string, StringSerdeError};
 
+use node::AppPermissionsUpdate;
+
 use crate::stnode::net_node::TrustedApps;
 
 #[derive(Debug, From)]
@@ -81,3 +84,39 @@ impl TrustedApps for FileTrustedApps {
         })
     }
 }
+
+/// Reload the trusted apps directory every `reload_ticks` ticks.
+/// The full set of trusted apps is sent on every reload, and not only when it changes: An app
+/// could be added and removed between two reloads, connecting in between.
+pub async fn watch_trusted_apps<TS>(
+    trusted_apps_path: PathBuf,
+    timer_stream: TS,
+    reload_ticks: usize,
+    mut updates_sender: mpsc::Sender<AppPermissionsUpdate>,
+) where
+    TS: Stream + Unpin,
+{
+    let mut reload_stream = timer_stream
+        .enumerate()
+        .filter(move |(tick_index, _)| future::ready((tick_index + 1) % reload_ticks == 0));
+
+    while reload_stream.next().await.is_some() {
+        let trusted_apps = match load_trusted_apps(&trusted_apps_path).await {
+            Ok(trusted_apps) => trusted_apps,
+            Err(e) => {
+                // Connected apps keep their last known permissions:
+                warn!("Failed to reload trusted apps: {:?}", e);
+                continue;
+            }
+        };
+
+        if updates_sender
+            .send(AppPermissionsUpdate { trusted_apps })
+            .await
+            .is_err()
+        {
+            // The node is no longer listening to updates:
+            return;
+        }
+    }
+}
@@ -1,6 +1,8 @@
+mod app_demux;
 mod file_trusted_apps;
 mod net_node;
 mod stnodelib;
 
+pub use self::app_demux::{demux_incoming_apps, AppDemuxError};
 pub use self::net_node::{net_node, NetNodeError, TrustedApps};
 pub use self::stnodelib::{stnode, NodeBinError, StNodeCmd};
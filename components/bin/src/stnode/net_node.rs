@@ -30,7 +30,8 @@ use version::VersionPrefix;
 */
 
 use node::{
-    node, ConnPairServer, IncomingAppConnection, NodeConfig, NodeError, NodeMutation, NodeState,
+    node, AppPermissionsUpdate, ConnPairServer, IncomingAppConnection, NodeConfig, NodeError,
+    NodeMutation, NodeState,
 };
 
 #[derive(Debug)]
@@ -80,7 +81,17 @@ where
 
             // Obtain permissions for app (Or reject it if not trusted):
             let app_permissions: AppPermissions =
-                self.trusted_apps.app_permissions(&public_key).await?;
+                match self.trusted_apps.app_permissions(&public_key).await {
+                    Some(app_permissions) => app_permissions,
+                    None => {
+                        warn!("Rejected untrusted app {:?}", public_key);
+                        return None;
+                    }
+                };
+            info!(
+                "Admitted app {:?} with permissions {:?}",
+                public_key, app_permissions
+            );
 
             // Tell app about its permissions:
             sender.send(app_permissions.proto_serialize()).await.ok()?;
@@ -136,6 +147,7 @@ where
                 .ok()?;
 
             Some(IncomingAppConnection {
+                app_public_key: public_key,
                 app_permissions: app_permissions.clone(),
                 report_sender,
             })
@@ -198,7 +210,7 @@ pub trait TrustedApps {
     ) -> BoxFuture<'a, Option<AppPermissions>>;
 }
 
-pub async fn net_node<IAC, C, R, TA, S>(
+pub async fn net_node<IAC, C, R, TA, PU, S>(
     incoming_app_raw_conns: IAC,
     connector: C,
     timer_client: TimerClient,
@@ -206,6 +218,7 @@ pub async fn net_node<IAC, C, R, TA, S>(
     rng: R,
     node_config: NodeConfig,
     trusted_apps: TA,
+    app_permissions_updates: PU,
     node_state: NodeState<NetAddress>,
     database_client: DatabaseClient<NodeMutation<NetAddress>>,
     spawner: S,
@@ -215,6 +228,7 @@ where
     C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>> + Clone + Send + 'static,
     R: CryptoRandom + Clone + Send + Sync + 'static,
     TA: TrustedApps + Send + Clone + 'static,
+    PU: Stream<Item = AppPermissionsUpdate> + Unpin + Send + 'static,
     S: Spawn + Clone + Send + 'static,
 {
     // TODO: Move this number somewhere else?
@@ -264,6 +278,7 @@ where
         secure_connector,
         encrypt_keepalive,
         incoming_apps,
+        app_permissions_updates,
         rng,
         spawner.clone(),
     )
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs;
 use std::net::SocketAddr;
@@ -9,35 +10,38 @@ use derive_more::From;
 use futures::channel::mpsc;
 use futures::executor::{block_on, ThreadPool};
 use futures::task::SpawnExt;
-use futures::{FutureExt, TryFutureExt};
+use futures::{future, FutureExt, TryFutureExt};
 
 use structopt::StructOpt;
 
-use common::conn::{Listener, ListenerClient};
+use common::conn::{BoxFuture, ConnPairVec, Listener, ListenerClient};
 use common::int_convert::usize_to_u64;
 
-use crypto::identity::SoftwareEd25519Identity;
+use crypto::identity::{Identity, SoftwareEd25519Identity};
 use crypto::rand::system_random;
 
 use identity::{create_identity, IdentityClient};
-use timer::create_timer;
+use timer::{create_timer, TimerClient};
 
 use database::file_db::FileDb;
 use database::{database_loop, AtomicDb, DatabaseClient};
 
 use net::{TcpConnector, TcpListener};
 use proto::consts::{
-    KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY,
-    TICK_MS,
+    ANNOUNCE_CREDIT_LIMITS, KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_NODE_RELAYS,
+    MAX_OPERATIONS_IN_BATCH, REBALANCE_BACKOFF_TICKS, REBALANCE_STAGE_TIMEOUT_TICKS,
+    TICKS_TO_REKEY, TICK_MS, TOPOLOGY_SYNC_TICKS,
 };
+use proto::crypto::PublicKey;
 use proto::net::messages::NetAddress;
 use proto::ser_string::{deserialize_from_string, StringSerdeError};
 
 use proto::file::IdentityFile;
 
-use node::{NodeConfig, NodeState};
+use node::{NodeConfig, NodeState, RoutesPrivacy};
 
-use crate::stnode::file_trusted_apps::FileTrustedApps;
+use crate::stnode::app_demux::{demux_incoming_apps, AppDemuxError};
+use crate::stnode::file_trusted_apps::{watch_trusted_apps, FileTrustedApps};
 use crate::stnode::net_node::{net_node, NetNodeError};
 
 /// Memory allocated to a channel in memory (Used to connect two components)
@@ -54,6 +58,8 @@ const MAX_OPEN_INDEX_CLIENT_REQUESTS: usize = 0x8;
 /// The amount of ticks we are willing to wait until a connection is established (Through
 /// the relay)
 const CONN_TIMEOUT_TICKS: usize = 0x8;
+/// Amount of ticks between reloads of the trusted apps directory.
+const TRUSTED_RELOAD_TICKS: usize = 0x4;
 /*
 /// Maximum amount of concurrent applications
 /// going through the incoming connection transform at the same time
@@ -66,10 +72,16 @@ pub enum NodeBinError {
     LoadIdentityError,
     CreateThreadPoolError,
     CreateTimerError,
+    RequestTimerStreamError,
     LoadDbError,
     SpawnError,
     ListenError,
+    /// The amounts of identity files, databases and trusted directories do not match
+    ProfilesMismatch,
+    /// The same identity was given for more than one node
+    DuplicateIdentity,
     NetNodeError(NetNodeError),
+    AppDemuxError(AppDemuxError),
     // SerializeError(SerializeError),
     StringSerdeError(StringSerdeError),
     IoError(std::io::Error),
@@ -83,39 +95,99 @@ pub enum NodeBinError {
 #[derive(Debug, StructOpt)]
 #[structopt(name = "stnode")]
 pub struct StNodeCmd {
-    /// StCtrl app identity file path
-    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
-    pub idfile: PathBuf,
+    /// Node identity file path.
+    /// Can be specified multiple times to host multiple nodes in one process. The n-th identity
+    /// file, database and trusted applications directory make up the profile of the n-th node.
+    /// Hosted nodes share the timer, the TCP connector and the apps listener, but every node
+    /// keeps its own connections to relays.
+    #[structopt(
+        parse(from_os_str),
+        short = "i",
+        long = "idfile",
+        required = true,
+        number_of_values = 1
+    )]
+    pub idfile: Vec<PathBuf>,
     /// Listening address (Used for communication with apps)
     #[structopt(short = "l", long = "laddr")]
     pub laddr: SocketAddr,
-    /// Database file path
-    #[structopt(parse(from_os_str), short = "d", long = "database")]
-    pub database: PathBuf,
-    /// Directory path of trusted applications
-    #[structopt(parse(from_os_str), short = "t", long = "trusted")]
-    pub trusted: PathBuf,
+    /// Database file path (One for every node)
+    #[structopt(
+        parse(from_os_str),
+        short = "d",
+        long = "database",
+        required = true,
+        number_of_values = 1
+    )]
+    pub database: Vec<PathBuf>,
+    /// Directory path of trusted applications (One for every node)
+    #[structopt(
+        parse(from_os_str),
+        short = "t",
+        long = "trusted",
+        required = true,
+        number_of_values = 1
+    )]
+    pub trusted: Vec<PathBuf>,
+    /// Amount of decoy routes requests sent to index servers together with every real request.
+    /// Hides payment destinations from index servers, at the cost of more requests.
+    #[structopt(long = "decoys", default_value = "0")]
+    pub decoys: usize,
+    /// Round requested route capacities up to a power of two, to hide exact payment amounts
+    /// from index servers.
+    #[structopt(long = "roundcap")]
+    pub roundcap: bool,
+    /// Keep a local snapshot of the routes topology, synced from the index server.
+    /// Routes are computed locally from the snapshot, and are still available while the index
+    /// server is unreachable.
+    #[structopt(long = "synctopology")]
+    pub sync_topology: bool,
+    /// Report to the index server which routes succeeded or failed when sending credits.
+    /// Amounts are not reported.
+    #[structopt(long = "reportroutes")]
+    pub report_routes: bool,
 }
 
-pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
-    let StNodeCmd {
+/// The files of a single node hosted by stnode.
+struct NodeProfile {
+    idfile: PathBuf,
+    database: PathBuf,
+    trusted: PathBuf,
+}
+
+/// A loaded node, ready to run.
+struct LoadedNode {
+    public_key: PublicKey,
+    /// Used to forward incoming app connections to the node
+    incoming_apps_sender: mpsc::Sender<ConnPairVec>,
+    node_fut: BoxFuture<'static, Result<(), NetNodeError>>,
+}
+
+/// Load the identity, database and trusted applications of a node.
+/// Services used only by this node (Identity, database, trusted apps watcher) are spawned. The
+/// node itself starts running only when `node_fut` is polled.
+///
+/// Relay connections are not shared between nodes: A relay identifies its clients by the public
+/// key of the secure channel, so every node connects to its relays with its own identity.
+fn load_node(
+    node_profile: NodeProfile,
+    node_config: NodeConfig,
+    tcp_connector: TcpConnector<ThreadPool>,
+    mut timer_client: TimerClient,
+    thread_pool: ThreadPool,
+    file_system_thread_pool: ThreadPool,
+) -> Result<LoadedNode, NodeBinError> {
+    let NodeProfile {
         idfile,
-        laddr,
         database,
         trusted,
-    } = st_node_cmd;
+    } = node_profile;
 
     // Parse identity file:
     let identity_file: IdentityFile = deserialize_from_string(&fs::read_to_string(&idfile)?)?;
     let identity = SoftwareEd25519Identity::from_private_key(&identity_file.private_key)
         .map_err(|_| NodeBinError::LoadIdentityError)?;
-
-    // Create a ThreadPool:
-    let thread_pool = ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
-
-    // Create thread pool for file system operations:
-    let file_system_thread_pool =
-        ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
+    let public_key = identity.get_public_key();
 
     // Spawn identity service:
     let (sender, identity_loop) = create_identity(identity);
@@ -124,6 +196,102 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         .map_err(|_| NodeBinError::SpawnError)?;
     let identity_client = IdentityClient::new(sender);
 
+    // Obtain secure cryptographic random:
+    let rng = system_random();
+
+    // Load database:
+    let atomic_db =
+        FileDb::<NodeState<NetAddress>>::load(database).map_err(|_| NodeBinError::LoadDbError)?;
+
+    let trusted_apps = FileTrustedApps::new(trusted.clone().into());
+
+    // Watch the trusted apps directory, to notify the node about revoked apps and changed
+    // permissions:
+    let timer_stream = block_on(timer_client.request_timer_stream("watch_trusted_apps".to_owned()))
+        .map_err(|_| NodeBinError::RequestTimerStreamError)?;
+    let (app_permissions_sender, app_permissions_updates) = mpsc::channel(0);
+    thread_pool
+        .spawn(watch_trusted_apps(
+            trusted.into(),
+            timer_stream,
+            TRUSTED_RELOAD_TICKS,
+            app_permissions_sender,
+        ))
+        .map_err(|_| NodeBinError::SpawnError)?;
+
+    // Get initial node_state:
+    let node_state = atomic_db.get_state().clone();
+
+    // Spawn database service:
+    let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
+    let loop_fut = database_loop(atomic_db, incoming_db_requests, file_system_thread_pool)
+        .map_err(|e| error!("database_loop() error: {:?}", e))
+        .map(|_| ());
+
+    thread_pool
+        .spawn(loop_fut)
+        .map_err(|_| NetNodeError::SpawnError)?;
+
+    // Obtain a client to the database service:
+    let database_client = DatabaseClient::new(db_request_sender);
+
+    // Incoming app connections that target this node:
+    let (incoming_apps_sender, incoming_app_raw_conns) = mpsc::channel(CHANNEL_LEN);
+
+    let node_fut = net_node(
+        incoming_app_raw_conns,
+        tcp_connector,
+        timer_client,
+        identity_client,
+        rng,
+        node_config,
+        trusted_apps,
+        app_permissions_updates,
+        node_state,
+        database_client,
+        thread_pool,
+    )
+    .boxed();
+
+    Ok(LoadedNode {
+        public_key,
+        incoming_apps_sender,
+        node_fut,
+    })
+}
+
+pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
+    let StNodeCmd {
+        idfile,
+        laddr,
+        database,
+        trusted,
+        decoys,
+        roundcap,
+        sync_topology,
+        report_routes,
+    } = st_node_cmd;
+
+    // Every node is described by an identity file, a database and a trusted apps directory:
+    if idfile.len() != database.len() || idfile.len() != trusted.len() {
+        return Err(NodeBinError::ProfilesMismatch);
+    }
+    let node_profiles = idfile
+        .into_iter()
+        .zip(database.into_iter().zip(trusted.into_iter()))
+        .map(|(idfile, (database, trusted))| NodeProfile {
+            idfile,
+            database,
+            trusted,
+        });
+
+    // Create a ThreadPool:
+    let thread_pool = ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
+
+    // Create thread pool for file system operations:
+    let file_system_thread_pool =
+        ThreadPool::new().map_err(|_| NodeBinError::CreateThreadPoolError)?;
+
     // Get a timer client:
     let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
     let timer_client =
@@ -153,59 +321,70 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        /// The amount of ticks we wait before attempting to rebalance a mutual credit again.
+        rebalance_backoff_ticks: REBALANCE_BACKOFF_TICKS,
+        /// The amount of ticks we wait for a response during a rebalance before giving up.
+        rebalance_stage_timeout_ticks: REBALANCE_STAGE_TIMEOUT_TICKS,
+        /// Announce to friends the credit we are willing to extend to them.
+        announce_credit_limits: ANNOUNCE_CREDIT_LIMITS,
+        /// Privacy settings for routes requests sent to index servers.
+        routes_privacy: RoutesPrivacy {
+            num_decoys: decoys,
+            round_capacity: roundcap,
+        },
+        /// Amount of ticks between syncs of route topology snapshots from the index server.
+        opt_topology_sync_ticks: if sync_topology {
+            Some(TOPOLOGY_SYNC_TICKS)
+        } else {
+            None
+        },
+        /// Report outcomes of routes used for sending credits to the index server.
+        report_route_outcomes: report_routes,
         /*
         /// Maximum amount of incoming app connections we set up at the same time
         // max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
          */
     };
 
-    // A tcp connector, Used to connect to remote servers:
+    // A tcp connector, Used to connect to remote servers (Shared by all nodes):
     let tcp_connector = TcpConnector::new(MAX_FRAME_LENGTH, thread_pool.clone());
 
-    // Obtain secure cryptographic random:
-    let rng = system_random();
-
-    // Load database:
-    let atomic_db =
-        FileDb::<NodeState<NetAddress>>::load(database).map_err(|_| NodeBinError::LoadDbError)?;
-
-    // Start listening to apps:
+    let mut nodes = HashMap::new();
+    let mut node_futs = Vec::new();
+    for node_profile in node_profiles {
+        let loaded_node = load_node(
+            node_profile,
+            node_config.clone(),
+            tcp_connector.clone(),
+            timer_client.clone(),
+            thread_pool.clone(),
+            file_system_thread_pool.clone(),
+        )?;
+        if nodes.contains_key(&loaded_node.public_key) {
+            return Err(NodeBinError::DuplicateIdentity);
+        }
+        info!("Hosting node {:?}", loaded_node.public_key);
+        nodes.insert(loaded_node.public_key, loaded_node.incoming_apps_sender);
+        node_futs.push(loaded_node.node_fut);
+    }
+
+    // Start listening to apps (One listener for all nodes):
     let app_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
     let ListenerClient {
         config_sender: _config_sender,
         conn_receiver: incoming_app_raw_conns,
     } = block_on(app_tcp_listener.listen(laddr)).map_err(|_| NodeBinError::ListenError)?;
 
-    let trusted_apps = FileTrustedApps::new(trusted.into());
-
-    // Get initial node_state:
-    let node_state = atomic_db.get_state().clone();
-
-    // Spawn database service:
-    let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
-    let loop_fut = database_loop(atomic_db, incoming_db_requests, file_system_thread_pool)
-        .map_err(|e| error!("database_loop() error: {:?}", e))
-        .map(|_| ());
-
-    thread_pool
-        .spawn(loop_fut)
-        .map_err(|_| NetNodeError::SpawnError)?;
-
-    // Obtain a client to the database service:
-    let database_client = DatabaseClient::new(db_request_sender);
-
-    let node_fut = net_node(
+    // Forward every incoming app connection to the node it targets:
+    let demux_fut = demux_incoming_apps(
         incoming_app_raw_conns,
-        tcp_connector,
+        nodes,
         timer_client,
-        identity_client,
-        rng,
-        node_config,
-        trusted_apps,
-        node_state,
-        database_client,
-        thread_pool,
-    );
+        thread_pool.clone(),
+    )
+    .map_err(NodeBinError::AppDemuxError);
 
-    block_on(node_fut).map_err(NodeBinError::NetNodeError)
+    // Run the nodes, until any of them fails:
+    let nodes_fut = future::try_join_all(node_futs).map_err(NodeBinError::NetNodeError);
+    block_on(future::try_join(nodes_fut, demux_fut)).map(|_| ())
 }
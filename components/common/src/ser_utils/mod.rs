@@ -3,6 +3,7 @@ pub mod ser_map_b64_any;
 pub mod ser_map_str_any;
 pub mod ser_map_str_str;
 pub mod ser_option_b64;
+pub mod ser_option_string;
 pub mod ser_seq_b64;
 pub mod ser_seq_str;
 pub mod ser_string;
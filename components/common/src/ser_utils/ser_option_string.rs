@@ -0,0 +1,82 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::string::ToString;
+
+use serde::de::{Error, Visitor};
+use serde::ser::Serializer;
+use serde::Deserializer;
+
+pub fn serialize<T, S>(opt_item: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: ToString,
+{
+    match opt_item {
+        Some(item) => serializer.serialize_some(&item.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+{
+    struct ItemVisitor<T> {
+        item: PhantomData<T>,
+    }
+
+    impl<'de, T> Visitor<'de> for ItemVisitor<T>
+    where
+        T: FromStr,
+    {
+        type Value = Option<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("An option")
+        }
+
+        fn visit_none<E>(self) -> Result<Self::Value, E>
+        where
+            E: Error,
+        {
+            Ok(None)
+        }
+
+        fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            struct StrVisitor<T> {
+                item: PhantomData<T>,
+            }
+
+            impl<'de, T> Visitor<'de> for StrVisitor<T>
+            where
+                T: FromStr,
+            {
+                type Value = T;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("A string like item")
+                }
+
+                fn visit_str<E>(self, str_item: &str) -> Result<Self::Value, E>
+                where
+                    E: Error,
+                {
+                    str_item
+                        .parse()
+                        .map_err(|_| Error::custom("Failed to parse as string"))
+                }
+            }
+
+            let str_visitor = StrVisitor { item: PhantomData };
+            Ok(Some(deserializer.deserialize_str(str_visitor)?))
+        }
+    }
+
+    let visitor = ItemVisitor { item: PhantomData };
+    deserializer.deserialize_option(visitor)
+}
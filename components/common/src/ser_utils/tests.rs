@@ -24,6 +24,13 @@ struct MyOptionB64Struct {
     my_opt: Option<[u8; 16]>,
 }
 
+#[allow(unused)]
+#[derive(Serialize, Deserialize)]
+struct MyOptionStringStruct {
+    #[serde(with = "ser_option_string")]
+    my_opt: Option<u128>,
+}
+
 #[allow(unused)]
 #[derive(Serialize, Deserialize)]
 struct MyMapB64AnyStruct {
@@ -14,5 +14,6 @@ mod timeout;
 mod transforms;
 
 pub use self::transforms::{
-    create_encrypt_keepalive, create_secure_connector, create_version_encrypt_keepalive,
+    create_app_target_reader, create_encrypt_keepalive, create_secure_connector,
+    create_version_encrypt_keepalive,
 };
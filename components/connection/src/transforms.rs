@@ -1,10 +1,13 @@
 use futures::task::Spawn;
+use futures::{stream, StreamExt};
 
-use common::conn::{ConnPairVec, FuncFutTransform, FutTransform};
+use common::conn::{ConnPair, ConnPairVec, FuncFutTransform, FutTransform};
 
 use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
 use proto::crypto::PublicKey;
 use proto::net::messages::NetAddress;
+use proto::proto_ser::ProtoDeserialize;
+use proto::secure_channel::messages::ExchangeRandNonce;
 
 use crypto::rand::CryptoRandom;
 
@@ -136,3 +139,39 @@ where
     });
     TimeoutFutTransform::new(fut_transform, timer_client, CONN_TIMEOUT_TICKS)
 }
+
+/// Find the node an incoming app connection targets, without consuming any of its data.
+/// A single process might host multiple nodes behind one address. The target node is the
+/// destination public key the app declares in the first message of the secure channel handshake,
+/// right after the version prefix.
+///
+/// The returned connection still begins with the version prefix and the handshake message, so that
+/// the target node can set up the connection as usual.
+pub fn create_app_target_reader(
+    timer_client: TimerClient,
+) -> impl FutTransform<Input = ConnPairVec, Output = Option<(Option<PublicKey>, ConnPairVec)>>
+       + Clone
+       + Send {
+    let fut_transform = FuncFutTransform::new(move |conn_pair: ConnPairVec| {
+        Box::pin(async move {
+            let (sender, mut receiver) = conn_pair.split();
+
+            // We can only parse the handshake of apps that use our protocol version:
+            let version_data = receiver.next().await?;
+            if version_data[..] != PROTOCOL_VERSION.to_be_bytes()[..] {
+                warn!("App connection with an invalid version prefix");
+                return None;
+            }
+
+            let first_message = receiver.next().await?;
+            let exchange_rand_nonce = ExchangeRandNonce::proto_deserialize(&first_message).ok()?;
+
+            let receiver = stream::iter(vec![version_data, first_message]).chain(receiver);
+            Some((
+                exchange_rand_nonce.opt_dest_public_key,
+                ConnPair::from_raw(sender, receiver),
+            ))
+        })
+    });
+    TimeoutFutTransform::new(fut_transform, timer_client, CONN_TIMEOUT_TICKS)
+}
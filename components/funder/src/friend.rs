@@ -2,6 +2,7 @@ use im::vector::Vector as ImVec;
 use std::collections::HashMap as ImHashMap;
 use std::fmt::Debug;
 
+use common::safe_arithmetic::SafeUnsignedArithmetic;
 use common::ser_utils::{ser_b64, ser_map_str_any, ser_string};
 
 use signature::canonical::CanonicalSerialize;
@@ -9,8 +10,9 @@ use signature::canonical::CanonicalSerialize;
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::crypto::PublicKey;
 use proto::funder::messages::{
-    CancelSendFundsOp, CollectSendFundsOp, Currency, FriendStatus, Rate, RequestSendFundsOp,
-    ResetTerms, ResponseSendFundsOp,
+    CancelSendFundsOp, CollectSendFundsOp, CreditLimit, Currency, CurrencyBalance, FeePolicy,
+    FriendStatus, Rate, RebalancePolicy, RequestSendFundsOp, ResetTerms, ResponseSendFundsOp,
+    SettlementSignature, SettlementStatement,
 };
 
 use crate::token_channel::{TcMutation, TokenChannel};
@@ -116,11 +118,43 @@ pub struct CurrencyConfig {
     /// for a certain currency.
     pub rate: Rate,
     /// Credit frame for the remote side (Set by the user of this node)
-    /// The remote side does not know this value.
+    /// The remote side only knows this value if credit limits are announced.
     #[serde(with = "ser_string")]
     pub remote_max_debt: u128,
     /// Can new requests be sent through the mutual credit with this friend?
     pub is_open: bool,
+    /// Dynamic fee policy. If set, the forwarding rate is recomputed from the current balance,
+    /// and `rate` is ignored.
+    #[serde(default)]
+    pub opt_fee_policy: Option<FeePolicy>,
+    /// Automatic rebalancing policy. Not used by the funder itself, but reported to the node's
+    /// rebalancer.
+    #[serde(default)]
+    pub opt_rebalance_policy: Option<RebalancePolicy>,
+}
+
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SettlementInProgress {
+    /// Our last signature over the drained channel
+    pub opt_local_signature: Option<SettlementSignature>,
+    /// Last signature received from the remote friend
+    pub opt_remote_signature: Option<SettlementSignature>,
+}
+
+/// Cooperative close of the relationship with a friend.
+/// While in progress, no new requests are sent or accepted through this friend.
+#[allow(clippy::large_enum_variant)]
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum Settlement {
+    NotStarted,
+    InProgress(SettlementInProgress),
+    Complete(SettlementStatement),
+}
+
+impl Default for Settlement {
+    fn default() -> Self {
+        Settlement::NotStarted
+    }
 }
 
 #[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -147,6 +181,16 @@ pub struct FriendState<B: Clone> {
     pub status: FriendStatus,
     /// Mutual credit channel information
     pub channel_status: ChannelStatus<B>,
+    /// Cooperative close state
+    #[serde(default)]
+    pub settlement: Settlement,
+    /// The last credit limits announced by the remote friend (Credit it is willing to extend to
+    /// us), sorted by currency.
+    #[serde(default)]
+    pub remote_credit_limits: Vec<CreditLimit>,
+    /// The last credit limits we have announced to the remote friend.
+    #[serde(default)]
+    pub sent_credit_limits: Vec<CreditLimit>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -170,6 +214,9 @@ pub enum FriendMutation<B: Clone> {
     SetRemoteRelays(Vec<RelayAddress<B>>),
     SetName(String),
     SetSentLocalRelays(SentLocalRelays<B>),
+    SetSettlement(Settlement),
+    SetRemoteCreditLimits(Vec<CreditLimit>),
+    SetSentCreditLimits(Vec<CreditLimit>),
 }
 
 impl CurrencyConfig {
@@ -178,6 +225,8 @@ impl CurrencyConfig {
             rate: Rate::new(),
             remote_max_debt: 0,
             is_open: false,
+            opt_fee_policy: None,
+            opt_rebalance_policy: None,
         }
     }
 }
@@ -208,9 +257,100 @@ where
             currency_configs: ImHashMap::new(),
             status: FriendStatus::Disabled,
             channel_status: ChannelStatus::Consistent(channel_consistent),
+            settlement: Settlement::NotStarted,
+            remote_credit_limits: Vec::new(),
+            sent_credit_limits: Vec::new(),
+        }
+    }
+
+    /// Credit limits we currently extend to the remote friend, sorted by currency.
+    pub fn local_credit_limits(&self) -> Vec<CreditLimit> {
+        let mut credit_limits: Vec<_> = self
+            .currency_configs
+            .iter()
+            .map(|(currency, currency_config)| CreditLimit {
+                currency: currency.clone(),
+                max_debt: currency_config.remote_max_debt,
+            })
+            .collect();
+        credit_limits.sort_by(|a, b| a.currency.cmp(&b.currency));
+        credit_limits
+    }
+
+    /// Amount of credits we may still freeze when sending a request to the remote friend in a
+    /// certain currency, according to the credit limit announced by the remote friend.
+    /// Returns None if the remote friend has not announced a limit for this currency.
+    pub fn calc_announced_send_capacity(&self, currency: &Currency) -> Option<u128> {
+        let credit_limit = self
+            .remote_credit_limits
+            .iter()
+            .find(|credit_limit| &credit_limit.currency == currency)?;
+
+        let channel_consistent = match &self.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => return Some(0),
+        };
+
+        let (balance, local_pending_debt) = match channel_consistent
+            .token_channel
+            .get_mutual_credits()
+            .get(currency)
+        {
+            Some(mutual_credit) => {
+                let mc_balance = &mutual_credit.state().balance;
+                (mc_balance.balance, mc_balance.local_pending_debt)
+            }
+            None => (0, 0),
+        };
+
+        Some(
+            credit_limit
+                .max_debt
+                .saturating_add_signed(balance)
+                .saturating_sub(local_pending_debt),
+        )
+    }
+
+    pub fn is_settling(&self) -> bool {
+        match &self.settlement {
+            Settlement::NotStarted => false,
+            Settlement::InProgress(_) | Settlement::Complete(_) => true,
         }
     }
 
+    /// Get the move token counter and the final balances (sorted by currency) of the channel
+    /// with this friend, if the channel is drained: consistent, with no pending transactions
+    /// and nothing left to send.
+    pub fn drained_balances(&self) -> Option<(u128, Vec<CurrencyBalance>)> {
+        let channel_consistent = match &self.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent,
+            ChannelStatus::Inconsistent(_) => return None,
+        };
+
+        if !channel_consistent.pending_requests.is_empty()
+            || !channel_consistent.pending_backwards_ops.is_empty()
+            || !channel_consistent.pending_user_requests.is_empty()
+        {
+            return None;
+        }
+
+        let token_channel = &channel_consistent.token_channel;
+        let mut balances = Vec::new();
+        for (currency, mutual_credit) in token_channel.get_mutual_credits() {
+            let pending_transactions = &mutual_credit.state().pending_transactions;
+            if !pending_transactions.local.is_empty() || !pending_transactions.remote.is_empty() {
+                return None;
+            }
+            balances.push(CurrencyBalance {
+                currency: currency.clone(),
+                balance: mutual_credit.state().balance.balance,
+            });
+        }
+        balances.sort_by(|a, b| a.currency.cmp(&b.currency));
+
+        Some((token_channel.get_move_token_counter(), balances))
+    }
+
     /*
     // TODO: Do we use this function somewhere?
     /// Find the shared credits we have with this friend.
@@ -240,6 +380,41 @@ where
     }
     */
 
+    /// Calculate the rate currently charged for forwarding transactions that arrived from this
+    /// friend, for a certain currency.
+    /// If a fee policy is configured, the rate is derived from the current balance.
+    /// Otherwise, the static configured rate is returned.
+    pub fn calc_effective_rate(&self, currency: &Currency) -> Option<Rate> {
+        let currency_config = self.currency_configs.get(currency)?;
+        let fee_policy = match &currency_config.opt_fee_policy {
+            Some(fee_policy) => fee_policy,
+            None => return Some(currency_config.rate.clone()),
+        };
+
+        let (balance, remote_pending_debt) = match &self.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => {
+                match channel_consistent
+                    .token_channel
+                    .get_mutual_credits()
+                    .get(currency)
+                {
+                    Some(mutual_credit) => {
+                        let mc_balance = &mutual_credit.state().balance;
+                        (mc_balance.balance, mc_balance.remote_pending_debt)
+                    }
+                    None => (0, 0),
+                }
+            }
+            ChannelStatus::Inconsistent(_) => (0, 0),
+        };
+
+        Some(fee_policy.calc_rate(
+            currency_config.remote_max_debt,
+            balance,
+            remote_pending_debt,
+        ))
+    }
+
     pub fn mutate(&mut self, friend_mutation: &FriendMutation<B>) {
         match friend_mutation {
             FriendMutation::TcMutation(tc_mutation) => match &mut self.channel_status {
@@ -369,6 +544,15 @@ where
             FriendMutation::SetSentLocalRelays(sent_local_relays) => {
                 self.sent_local_relays = sent_local_relays.clone();
             }
+            FriendMutation::SetSettlement(settlement) => {
+                self.settlement = settlement.clone();
+            }
+            FriendMutation::SetRemoteCreditLimits(remote_credit_limits) => {
+                self.remote_credit_limits = remote_credit_limits.clone();
+            }
+            FriendMutation::SetSentCreditLimits(sent_credit_limits) => {
+                self.sent_credit_limits = sent_credit_limits.clone();
+            }
         }
     }
 }
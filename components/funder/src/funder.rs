@@ -48,6 +48,7 @@ pub async fn inner_funder_loop<B, R>(
     max_operations_in_batch: usize,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    announce_credit_limits: bool,
     mut opt_event_sender: Option<mpsc::Sender<FunderEvent<B>>>,
 ) -> Result<(), FunderError>
 where
@@ -96,6 +97,7 @@ where
             max_node_relays,
             max_operations_in_batch,
             max_pending_user_requests,
+            announce_credit_limits,
             funder_incoming,
         )
         .await;
@@ -158,6 +160,7 @@ pub async fn funder_loop<B, R>(
     max_operations_in_batch: usize,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    announce_credit_limits: bool,
     funder_state: FunderState<B>,
     db_client: DatabaseClient<FunderMutation<B>>,
 ) -> Result<(), FunderError>
@@ -177,6 +180,7 @@ where
         max_operations_in_batch,
         max_node_relays,
         max_pending_user_requests,
+        announce_credit_limits,
         None,
     )
     .await
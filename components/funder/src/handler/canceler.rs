@@ -216,8 +216,10 @@ pub fn cancel_local_pending_transactions<B, R>(
                         request_id: pending_local_transaction.request_id.clone(),
                         result: RequestResult::Failure,
                     };
-                    outgoing_control
-                        .push(FunderOutgoingControl::TransactionResult(transaction_result));
+                    outgoing_control.push(FunderOutgoingControl::TransactionResult(
+                        transaction_result,
+                        true,
+                    ));
                     remove_transaction(
                         m_state,
                         outgoing_control,
@@ -263,7 +265,10 @@ pub fn cancel_request<B, R>(
                 request_id: pending_request.request_id.clone(),
                 result: RequestResult::Failure,
             };
-            outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+            outgoing_control.push(FunderOutgoingControl::TransactionResult(
+                transaction_result,
+                true,
+            ));
             remove_transaction(m_state, outgoing_control, rng, &pending_request.request_id);
         }
     };
@@ -13,11 +13,12 @@ use crate::state::{FunderMutation, NewTransactions, Payment, PaymentStage};
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
     AckClosePayment, AddFriend, AddInvoice, ChannelerUpdateFriend, CollectSendFundsOp, Commit,
-    CreatePayment, CreateTransaction, FriendStatus, FunderControl, FunderOutgoingControl,
-    PaymentStatus, PaymentStatusSuccess, RemoveFriend, RemoveFriendCurrency, RequestResult,
-    RequestSendFundsOp, ResetFriendChannel, ResponseClosePayment, SetFriendCurrencyMaxDebt,
-    SetFriendCurrencyRate, SetFriendCurrencyRequestsStatus, SetFriendName, SetFriendRelays,
-    SetFriendStatus, TransactionResult,
+    CreatePayment, CreateTransaction, CurrencyPair, DeliverCommit, ExchangeRate, FriendStatus,
+    FunderControl, FunderOutgoingControl, PaymentStatus, PaymentStatusSuccess, RemoveFriend,
+    RemoveFriendCurrency, RequestResult, RequestSendFundsOp, ResetFriendChannel,
+    ResponseClosePayment, SetFriendCurrencyFeePolicy, SetFriendCurrencyMaxDebt,
+    SetFriendCurrencyRate, SetFriendCurrencyRebalancePolicy, SetFriendCurrencyRequestsStatus,
+    SetFriendName, SetFriendRelays, SetFriendStatus, SettleFriend, TransactionResult,
 };
 use signature::verify::verify_commit;
 
@@ -27,9 +28,12 @@ use crate::handler::canceler::{
     reply_with_cancel, CurrencyChoice,
 };
 use crate::handler::prepare::prepare_commit;
+use crate::handler::settlement::start_settlement;
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
 use crate::handler::types::SendCommands;
-use crate::handler::utils::{find_local_pending_transaction, find_request_origin, is_friend_ready};
+use crate::handler::utils::{
+    find_local_pending_transaction, find_request_destination, find_request_origin, is_friend_ready,
+};
 
 use crate::types::ChannelerConfig;
 
@@ -57,6 +61,12 @@ pub enum HandleControlError {
     FriendCurrencyDoesNotExist,
     CanNotRemoveActiveCurrency,
     CurrencyNotConfigured,
+    InvalidCurrencyPair,
+    InvalidValidityWindow,
+    ExchangeRateDoesNotExist,
+    SettlementAlreadyStarted,
+    CreditLimitExceeded,
+    TransactionDoesNotExist,
 }
 
 fn control_set_friend_currency_max_debt<B>(
@@ -140,6 +150,39 @@ where
     Ok(())
 }
 
+fn control_settle_friend<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &mut R,
+    settle_friend: SettleFriend,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let friend = m_state
+        .state()
+        .friends
+        .get(&settle_friend.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    if friend.is_settling() {
+        return Err(HandleControlError::SettlementAlreadyStarted);
+    }
+
+    // Our signature over the final balances is created by the sender,
+    // once all pending transactions are drained.
+    start_settlement(
+        m_state,
+        send_commands,
+        outgoing_control,
+        rng,
+        &settle_friend.friend_public_key,
+    );
+    Ok(())
+}
+
 fn enable_friend<B>(
     m_state: &mut MutableFunderState<B>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
@@ -557,6 +600,101 @@ where
     Ok(())
 }
 
+fn control_set_friend_currency_fee_policy<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    set_friend_currency_fee_policy: SetFriendCurrencyFeePolicy,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&set_friend_currency_fee_policy.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    // If the newly proposed fee policy is the same as the old one, we do nothing:
+    let mut new_currency_config = if let Some(currency_config) = friend
+        .currency_configs
+        .get(&set_friend_currency_fee_policy.currency)
+    {
+        if currency_config.opt_fee_policy == set_friend_currency_fee_policy.opt_fee_policy {
+            return Ok(());
+        }
+        currency_config.clone()
+    } else {
+        // A new local currency was added, we need to report to remote side:
+        send_commands.set_try_send(&set_friend_currency_fee_policy.friend_public_key);
+        CurrencyConfig::new()
+    };
+
+    new_currency_config.opt_fee_policy = set_friend_currency_fee_policy.opt_fee_policy;
+
+    let friend_mutation = FriendMutation::UpdateCurrencyConfig((
+        set_friend_currency_fee_policy.currency,
+        new_currency_config,
+    ));
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_currency_fee_policy.friend_public_key.clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
+fn control_set_friend_currency_rebalance_policy<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    set_friend_currency_rebalance_policy: SetFriendCurrencyRebalancePolicy,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&set_friend_currency_rebalance_policy.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    // If the newly proposed rebalance policy is the same as the old one, we do nothing:
+    let mut new_currency_config = if let Some(currency_config) = friend
+        .currency_configs
+        .get(&set_friend_currency_rebalance_policy.currency)
+    {
+        if currency_config.opt_rebalance_policy
+            == set_friend_currency_rebalance_policy.opt_rebalance_policy
+        {
+            return Ok(());
+        }
+        currency_config.clone()
+    } else {
+        // A new local currency was added, we need to report to remote side:
+        send_commands.set_try_send(&set_friend_currency_rebalance_policy.friend_public_key);
+        CurrencyConfig::new()
+    };
+
+    new_currency_config.opt_rebalance_policy =
+        set_friend_currency_rebalance_policy.opt_rebalance_policy;
+
+    let friend_mutation = FriendMutation::UpdateCurrencyConfig((
+        set_friend_currency_rebalance_policy.currency,
+        new_currency_config,
+    ));
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_currency_rebalance_policy
+            .friend_public_key
+            .clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
 fn control_remove_friend_currency<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
@@ -602,6 +740,56 @@ where
     Ok(())
 }
 
+fn control_set_exchange_rate<B>(
+    m_state: &mut MutableFunderState<B>,
+    exchange_rate: ExchangeRate,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let currency_pair = &exchange_rate.currency_pair;
+    if currency_pair.src_currency == currency_pair.dest_currency {
+        return Err(HandleControlError::InvalidCurrencyPair);
+    }
+
+    // The validity window must not be empty:
+    if exchange_rate.valid_from >= exchange_rate.valid_until {
+        return Err(HandleControlError::InvalidValidityWindow);
+    }
+
+    // If the newly proposed exchange rate is the same as the old one, we do nothing:
+    if m_state.state().exchange_rates.contains(&exchange_rate) {
+        return Ok(());
+    }
+
+    let funder_mutation = FunderMutation::SetExchangeRate(exchange_rate);
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
+fn control_remove_exchange_rate<B>(
+    m_state: &mut MutableFunderState<B>,
+    currency_pair: CurrencyPair,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if !m_state
+        .state()
+        .exchange_rates
+        .iter()
+        .any(|exchange_rate| exchange_rate.currency_pair == currency_pair)
+    {
+        return Err(HandleControlError::ExchangeRateDoesNotExist);
+    }
+
+    let funder_mutation = FunderMutation::RemoveExchangeRate(currency_pair);
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
 fn control_create_payment<B, R>(
     m_state: &mut MutableFunderState<B>,
     rng: &mut R,
@@ -739,6 +927,18 @@ where
         return Err(HandleControlError::PendingUserRequestsFull);
     }
 
+    // If the friend has announced its credit limit, we don't send requests that it is going to
+    // reject:
+    if let Some(send_capacity) = friend.calc_announced_send_capacity(&currency) {
+        let freeze_credits = create_transaction
+            .dest_payment
+            .checked_add(create_transaction.fees)
+            .ok_or(HandleControlError::CreditLimitExceeded)?;
+        if freeze_credits > send_capacity {
+            return Err(HandleControlError::CreditLimitExceeded);
+        }
+    }
+
     // Keep PlainLock:
     let funder_mutation = FunderMutation::AddTransaction((
         create_transaction.request_id.clone(),
@@ -848,7 +1048,10 @@ where
                     result: RequestResult::Success,
                 }
             };
-            outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+            outgoing_control.push(FunderOutgoingControl::TransactionResult(
+                transaction_result,
+                false,
+            ));
         }
         return Ok(());
     }
@@ -868,7 +1071,11 @@ where
             result: RequestResult::Failure,
         };
 
-        outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+        // The request never left our node:
+        outgoing_control.push(FunderOutgoingControl::TransactionResult(
+            transaction_result,
+            true,
+        ));
     }
 
     // Every CreateTransaction must have a matching response. Therefore we don't return an error
@@ -1018,6 +1225,43 @@ where
     Ok(())
 }
 
+/// Send a commit to the next node on the route of a transaction we have originated.
+/// The commit is forwarded along the route until it reaches the seller.
+fn control_deliver_commit<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    deliver_commit: DeliverCommit,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // We must be the origin of this transaction:
+    if !m_state
+        .state()
+        .open_transactions
+        .contains_key(&deliver_commit.request_id)
+    {
+        return Err(HandleControlError::TransactionDoesNotExist);
+    }
+
+    let currency = &deliver_commit.commit.currency;
+    let pending_transaction =
+        find_local_pending_transaction(m_state.state(), currency, &deliver_commit.request_id)
+            .ok_or(HandleControlError::TransactionDoesNotExist)?;
+
+    if pending_transaction.invoice_id != deliver_commit.commit.invoice_id {
+        return Err(HandleControlError::InvalidCommit);
+    }
+
+    let friend_public_key =
+        find_request_destination(m_state.state(), currency, &deliver_commit.request_id)
+            .ok_or(HandleControlError::TransactionDoesNotExist)?
+            .clone();
+
+    send_commands.push_deliver_commit(&friend_public_key, deliver_commit);
+    Ok(())
+}
+
 fn control_add_invoice<B, R>(
     m_state: &mut MutableFunderState<B>,
     rng: &mut R,
@@ -1090,7 +1334,7 @@ where
     Ok(())
 }
 
-fn control_commit_invoice<B>(
+pub fn control_commit_invoice<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     commit: &Commit,
@@ -1179,6 +1423,10 @@ where
             control_reset_friend_channel(m_state, send_commands, reset_friend_channel)
         }
 
+        FunderControl::SettleFriend(settle_friend) => {
+            control_settle_friend(m_state, send_commands, outgoing_control, rng, settle_friend)
+        }
+
         FunderControl::AddRelay(named_relay_address) => control_add_relay(
             m_state,
             send_commands,
@@ -1240,11 +1488,33 @@ where
         FunderControl::SetFriendCurrencyRate(set_friend_currency_rate) => {
             control_set_friend_currency_rate(m_state, send_commands, set_friend_currency_rate)
         }
+        FunderControl::SetFriendCurrencyFeePolicy(set_friend_currency_fee_policy) => {
+            control_set_friend_currency_fee_policy(
+                m_state,
+                send_commands,
+                set_friend_currency_fee_policy,
+            )
+        }
+        FunderControl::SetFriendCurrencyRebalancePolicy(set_friend_currency_rebalance_policy) => {
+            control_set_friend_currency_rebalance_policy(
+                m_state,
+                send_commands,
+                set_friend_currency_rebalance_policy,
+            )
+        }
 
         FunderControl::RemoveFriendCurrency(remove_friend_currency) => {
             control_remove_friend_currency(m_state, send_commands, remove_friend_currency)
         }
 
+        FunderControl::SetExchangeRate(exchange_rate) => {
+            control_set_exchange_rate(m_state, exchange_rate)
+        }
+
+        FunderControl::RemoveExchangeRate(currency_pair) => {
+            control_remove_exchange_rate(m_state, currency_pair)
+        }
+
         // Buyer API:
         FunderControl::CreatePayment(create_payment) => {
             control_create_payment(m_state, rng, create_payment)
@@ -1263,6 +1533,9 @@ where
         FunderControl::AckClosePayment(ack_close_payment) => {
             control_ack_close_payment(m_state, ack_close_payment)
         }
+        FunderControl::DeliverCommit(deliver_commit) => {
+            control_deliver_commit(m_state, send_commands, deliver_commit)
+        }
 
         // Seller API:
         FunderControl::AddInvoice(add_invoice) => control_add_invoice(m_state, rng, add_invoice),
@@ -10,10 +10,10 @@ use proto::crypto::{PublicKey, Signature, Uid};
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     BalanceInfo, CancelSendFundsOp, ChannelerUpdateFriend, CollectSendFundsOp, CountersInfo,
-    Currency, CurrencyBalance, CurrencyBalanceInfo, FriendMessage, FunderOutgoingControl, McInfo,
-    MoveTokenRequest, PaymentStatus, PaymentStatusSuccess, PendingTransaction, RequestResult,
-    RequestSendFundsOp, ResetTerms, ResponseClosePayment, ResponseSendFundsOp, TokenInfo,
-    TransactionResult,
+    CreditLimit, Currency, CurrencyBalance, CurrencyBalanceInfo, DeliverCommit, FriendMessage,
+    FunderOutgoingControl, McInfo, MoveTokenRequest, PaymentStatus, PaymentStatusSuccess,
+    PendingTransaction, RequestResult, RequestSendFundsOp, ResetTerms, ResponseClosePayment,
+    ResponseSendFundsOp, SettlementSignature, TokenInfo, TransactionResult,
 };
 use signature::signature_buff::hash_token_info;
 use signature::verify::verify_move_token;
@@ -28,7 +28,7 @@ use crate::types::{create_pending_transaction, ChannelerConfig};
 
 use crate::friend::{
     BackwardsOp, ChannelInconsistent, ChannelStatus, CurrencyConfig, FriendMutation,
-    SentLocalRelays,
+    SentLocalRelays, Settlement,
 };
 use crate::state::{FunderMutation, FunderState, Payment, PaymentStage};
 
@@ -38,11 +38,13 @@ use crate::handler::canceler::{
     cancel_local_pending_transactions, cancel_pending_requests, remove_transaction,
     reply_with_cancel, CurrencyChoice,
 };
+use crate::handler::handle_control::control_commit_invoice;
 use crate::handler::prepare::{prepare_commit, prepare_receipt};
+use crate::handler::settlement::{start_settlement, try_complete_settlement};
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
 use crate::handler::types::SendCommands;
 use crate::handler::utils::{
-    find_remote_pending_transaction, find_request_origin, is_friend_ready,
+    find_remote_pending_transaction, find_request_destination, find_request_origin, is_friend_ready,
 };
 
 #[derive(Debug)]
@@ -302,6 +304,19 @@ fn handle_request_send_funds<B>(
 ) where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
+    // We do not accept new requests from a friend we are settling with:
+    let friend = m_state.state().friends.get(&remote_public_key).unwrap();
+    if friend.is_settling() {
+        reply_with_cancel(
+            m_state,
+            send_commands,
+            remote_public_key,
+            currency,
+            &request_send_funds.request_id,
+        );
+        return;
+    }
+
     if request_send_funds.route.is_empty() {
         // We are the destination of this request.
 
@@ -402,17 +417,15 @@ fn handle_request_send_funds<B>(
     // Attempt to take our fee for forwarding the request.
     // Note that the rate is determined by the rate we set with the node that sent us the request
     // (And **not** with the node that we forward the request to).
-    let currency_configs = &m_state
+    // If a fee policy is configured, the rate depends on the current balance with the remote
+    // friend.
+    let rate = m_state
         .state()
         .friends
         .get(remote_public_key)
         .unwrap()
-        .currency_configs;
-
-    // Get the rate for this currency.
-    // // If not set up, the rate is 0:
-    // // let default_rate = Rate::new();
-    let rate = currency_configs.get(currency).unwrap().rate.clone();
+        .calc_effective_rate(currency)
+        .unwrap();
 
     let opt_local_fee = rate.calc_fee(request_send_funds.dest_payment);
 
@@ -522,7 +535,10 @@ fn handle_response_send_funds<B>(
                     result: RequestResult::Success,
                 }
             };
-            outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+            outgoing_control.push(FunderOutgoingControl::TransactionResult(
+                transaction_result,
+                false,
+            ));
         }
         Some(friend_public_key) => {
             // Queue this response message to another token channel:
@@ -562,12 +578,14 @@ fn handle_cancel_send_funds<B, R>(
                 &cancel_send_funds.request_id,
             );
 
-            // Inform user about the transaction failure:
+            // Inform user about the transaction failure.
+            // The cancellation was sent by a remote node, so the first hop was successful:
             outgoing_control.push(FunderOutgoingControl::TransactionResult(
                 TransactionResult {
                     request_id: pending_transaction.request_id,
                     result: RequestResult::Failure,
                 },
+                false,
             ));
         }
         Some(friend_public_key) => {
@@ -1120,6 +1138,154 @@ where
     Ok(())
 }
 
+fn handle_settlement_signature<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &mut R,
+    remote_public_key: &PublicKey,
+    settlement_signature: SettlementSignature,
+) -> Result<(), HandleFriendError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let friend = match m_state.state().friends.get(remote_public_key) {
+        Some(friend) => Ok(friend),
+        None => Err(HandleFriendError::FriendDoesNotExist),
+    }?;
+
+    let is_started = match &friend.settlement {
+        Settlement::NotStarted => false,
+        Settlement::InProgress(_) => true,
+        // We already have a final statement. The remote side will receive our signature again
+        // when it reconnects.
+        Settlement::Complete(_) => return Ok(()),
+    };
+
+    if !is_started {
+        // The remote side has initiated the settlement:
+        start_settlement(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            remote_public_key,
+        );
+    }
+
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    let mut settlement_in_progress = match &friend.settlement {
+        Settlement::InProgress(settlement_in_progress) => settlement_in_progress.clone(),
+        Settlement::NotStarted | Settlement::Complete(_) => unreachable!(),
+    };
+    settlement_in_progress.opt_remote_signature = Some(settlement_signature);
+
+    let friend_mutation =
+        FriendMutation::SetSettlement(Settlement::InProgress(settlement_in_progress));
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    try_complete_settlement(m_state, remote_public_key);
+
+    // We might need to sign over the current state of the channel:
+    send_commands.set_try_send(remote_public_key);
+    Ok(())
+}
+
+/// Keep the credit limits announced by the remote friend.
+fn handle_credit_limits<B>(
+    m_state: &mut MutableFunderState<B>,
+    remote_public_key: &PublicKey,
+    mut credit_limits: Vec<CreditLimit>,
+) -> Result<(), HandleFriendError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    credit_limits.sort_by(|a, b| a.currency.cmp(&b.currency));
+    credit_limits.dedup_by(|a, b| a.currency == b.currency);
+
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if friend.remote_credit_limits == credit_limits {
+        return Ok(());
+    }
+
+    let friend_mutation = FriendMutation::SetRemoteCreditLimits(credit_limits);
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+    Ok(())
+}
+
+/// Forward a commit to the next node on the route of a transaction.
+/// If we are the seller, the commit is used to commit the matching open invoice.
+fn handle_deliver_commit<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    remote_public_key: &PublicKey,
+    deliver_commit: DeliverCommit,
+) -> Result<(), HandleFriendError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let currency = deliver_commit.commit.currency.clone();
+
+    // The remote friend must have sent us the request of this transaction:
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    let opt_pending_transaction = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) => None,
+        ChannelStatus::Consistent(channel_consistent) => channel_consistent
+            .token_channel
+            .get_mutual_credits()
+            .get(&currency)
+            .and_then(|mutual_credit| {
+                mutual_credit
+                    .state()
+                    .pending_transactions
+                    .remote
+                    .get(&deliver_commit.request_id)
+            }),
+    };
+
+    let pending_transaction = if let Some(pending_transaction) = opt_pending_transaction {
+        pending_transaction
+    } else {
+        warn!("handle_deliver_commit(): Transaction does not exist");
+        return Ok(());
+    };
+
+    if pending_transaction.invoice_id != deliver_commit.commit.invoice_id {
+        warn!("handle_deliver_commit(): Invoice mismatch");
+        return Ok(());
+    }
+
+    if pending_transaction.route.is_empty() {
+        // We are the destination of this transaction. Commit the invoice:
+        match control_commit_invoice(m_state, send_commands, &deliver_commit.commit) {
+            Ok(()) => outgoing_control.push(FunderOutgoingControl::InvoiceCommitted(
+                deliver_commit.commit,
+            )),
+            Err(e) => warn!("handle_deliver_commit(): Failed to commit invoice: {:?}", e),
+        }
+        return Ok(());
+    }
+
+    // Forward the commit to the friend we have forwarded the request to:
+    let next_public_key = if let Some(next_public_key) =
+        find_request_destination(m_state.state(), &currency, &deliver_commit.request_id)
+    {
+        next_public_key.clone()
+    } else {
+        warn!("handle_deliver_commit(): Failed to find request destination");
+        return Ok(());
+    };
+
+    send_commands.push_deliver_commit(&next_public_key, deliver_commit);
+    Ok(())
+}
+
 pub fn handle_friend_message<B, R>(
     m_state: &mut MutableFunderState<B>,
     m_ephemeral: &mut MutableEphemeral,
@@ -1160,5 +1326,203 @@ where
             remote_public_key,
             remote_reset_terms,
         ),
+
+        FriendMessage::SettlementSignature(settlement_signature) => handle_settlement_signature(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            remote_public_key,
+            settlement_signature,
+        ),
+
+        FriendMessage::CreditLimits(credit_limits) => {
+            handle_credit_limits(m_state, remote_public_key, credit_limits)
+        }
+
+        FriendMessage::DeliverCommit(deliver_commit) => handle_deliver_commit(
+            m_state,
+            send_commands,
+            outgoing_control,
+            remote_public_key,
+            deliver_commit,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use proto::crypto::{HashedLock, InvoiceId};
+    use proto::funder::messages::{AddFriend, FeeCurve, FeePolicy, FriendsRoute, Rate};
+
+    use crate::friend::FriendState;
+    use crate::liveness::LivenessMutation;
+    use crate::mutual_credit::types::McMutation;
+    use crate::token_channel::TcMutation;
+
+    use crate::handler::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+    fn add_friend_currency(
+        state: &mut FunderState<u32>,
+        friend_public_key: &PublicKey,
+        currency: &Currency,
+        currency_config: CurrencyConfig,
+        balance: i128,
+    ) {
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: "friend".into(),
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        let friend_mutations = vec![
+            FriendMutation::UpdateCurrencyConfig((currency.clone(), currency_config)),
+            FriendMutation::TcMutation(TcMutation::AddMutualCredit(currency.clone())),
+            FriendMutation::TcMutation(TcMutation::McMutation((
+                currency.clone(),
+                McMutation::SetBalance(balance),
+            ))),
+        ];
+        for friend_mutation in friend_mutations {
+            let funder_mutation =
+                FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+            state.mutate(&funder_mutation);
+        }
+    }
+
+    fn create_request(request_id: u8, next_pk: &PublicKey, left_fees: u128) -> RequestSendFundsOp {
+        RequestSendFundsOp {
+            request_id: Uid::from(&[request_id; Uid::len()]),
+            src_hashed_lock: HashedLock::from(&[1; HashedLock::len()]),
+            route: FriendsRoute {
+                public_keys: vec![next_pk.clone(), PublicKey::from(&[0x44; PublicKey::len()])],
+            },
+            dest_payment: 20,
+            total_dest_payment: 20,
+            invoice_id: InvoiceId::from(&[2; InvoiceId::len()]),
+            left_fees,
+        }
+    }
+
+    fn pending_requests(friend: &FriendState<u32>) -> Vec<(Currency, RequestSendFundsOp)> {
+        match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent
+                .pending_requests
+                .iter()
+                .cloned()
+                .collect(),
+            ChannelStatus::Inconsistent(_) => unreachable!(),
+        }
+    }
+
+    fn pending_backwards_ops(friend: &FriendState<u32>) -> Vec<(Currency, BackwardsOp)> {
+        match &friend.channel_status {
+            ChannelStatus::Consistent(channel_consistent) => channel_consistent
+                .pending_backwards_ops
+                .iter()
+                .cloned()
+                .collect(),
+            ChannelStatus::Inconsistent(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_handle_request_send_funds_fee_policy() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+        let local_pk = PublicKey::from(&[0x11; PublicKey::len()]);
+        // The friend that sends us the request:
+        let prev_pk = PublicKey::from(&[0x22; PublicKey::len()]);
+        // The friend we forward the request to:
+        let next_pk = PublicKey::from(&[0x33; PublicKey::len()]);
+
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+
+        // The rate with prev_pk grows from add=1 (No debt) to add=11 (Full debt):
+        let prev_currency_config = CurrencyConfig {
+            rate: Rate::new(),
+            remote_max_debt: 100,
+            is_open: true,
+            opt_fee_policy: Some(FeePolicy {
+                min_rate: Rate { mul: 0, add: 1 },
+                max_rate: Rate { mul: 0, add: 11 },
+                curve: FeeCurve::Linear,
+            }),
+            opt_rebalance_policy: None,
+        };
+        // prev_pk owes us half of its max debt:
+        add_friend_currency(&mut state, &prev_pk, &currency, prev_currency_config, 50);
+
+        let next_currency_config = CurrencyConfig {
+            is_open: true,
+            ..CurrencyConfig::new()
+        };
+        add_friend_currency(&mut state, &next_pk, &currency, next_currency_config, 0);
+
+        // The effective rate is derived from the policy, and not from the static rate:
+        let effective_rate = state
+            .friends
+            .get(&prev_pk)
+            .unwrap()
+            .calc_effective_rate(&currency)
+            .unwrap();
+        assert_eq!(effective_rate, Rate { mul: 0, add: 6 });
+
+        let mut ephemeral = Ephemeral::new();
+        ephemeral
+            .liveness
+            .mutate(&LivenessMutation::SetOnline(next_pk.clone()));
+
+        let mut m_state = MutableFunderState::new(state);
+        let mut send_commands = SendCommands::new();
+
+        // Enough fees are left for us, the request is forwarded:
+        handle_request_send_funds(
+            &mut m_state,
+            &ephemeral,
+            &mut send_commands,
+            &prev_pk,
+            &currency,
+            create_request(1, &next_pk, 8),
+        );
+
+        // Not enough fees are left for us, the request is canceled:
+        handle_request_send_funds(
+            &mut m_state,
+            &ephemeral,
+            &mut send_commands,
+            &prev_pk,
+            &currency,
+            create_request(2, &next_pk, 5),
+        );
+
+        let (_initial_state, _funder_mutations, final_state) = m_state.done();
+
+        // Only the first request was forwarded, and we took 6 credits of fees:
+        let next_friend = final_state.friends.get(&next_pk).unwrap();
+        let pending_requests = pending_requests(next_friend);
+        assert_eq!(pending_requests.len(), 1);
+        let (pending_currency, pending_request) = &pending_requests[0];
+        assert_eq!(pending_currency, &currency);
+        assert_eq!(pending_request.request_id, Uid::from(&[1; Uid::len()]));
+        assert_eq!(pending_request.left_fees, 2);
+        assert_eq!(pending_request.route.public_keys.len(), 1);
+
+        // The second request was canceled back to prev_pk:
+        let prev_friend = final_state.friends.get(&prev_pk).unwrap();
+        let pending_backwards_ops = pending_backwards_ops(prev_friend);
+        assert_eq!(pending_backwards_ops.len(), 1);
+        match &pending_backwards_ops[0] {
+            (pending_currency, BackwardsOp::Cancel(cancel_send_funds)) => {
+                assert_eq!(pending_currency, &currency);
+                assert_eq!(cancel_send_funds.request_id, Uid::from(&[2; Uid::len()]));
+            }
+            _ => unreachable!(),
+        }
     }
 }
@@ -167,6 +167,7 @@ pub async fn funder_handle_message<'a, B, R>(
     max_node_relays: usize,
     max_operations_in_batch: usize,
     max_pending_user_requests: usize,
+    announce_credit_limits: bool,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandlerOutput<B>, FunderHandlerError>
 where
@@ -202,6 +203,7 @@ where
         m_ephemeral.ephemeral(),
         &send_commands,
         max_operations_in_batch,
+        announce_credit_limits,
         identity_client,
         rng,
     )
@@ -6,6 +6,7 @@ mod handle_liveness;
 mod handler;
 mod prepare;
 mod sender;
+mod settlement;
 mod state_wrap;
 mod types;
 mod utils;
@@ -12,21 +12,23 @@ use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::crypto::{PublicKey, RandValue};
 use proto::funder::messages::{
     BalanceInfo, ChannelerUpdateFriend, CountersInfo, Currency, CurrencyBalanceInfo,
-    CurrencyOperations, FriendMessage, FriendTcOp, McInfo, MoveTokenRequest, TokenInfo,
+    CurrencyOperations, FriendMessage, FriendTcOp, McInfo, MoveTokenRequest, SettlementSignature,
+    TokenInfo,
 };
 
 use identity::IdentityClient;
 
 use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
-use crate::types::{create_unsigned_move_token, sign_move_token, ChannelerConfig};
+use crate::types::{create_unsigned_move_token, sign_move_token, sign_settlement, ChannelerConfig};
 
 use crate::friend::{
     BackwardsOp, ChannelInconsistent, ChannelStatus, CurrencyConfig, FriendMutation,
-    SentLocalRelays,
+    SentLocalRelays, Settlement,
 };
 use crate::token_channel::{SendMoveTokenOutput, SetDirection, TcMutation, TokenChannel};
 
 use crate::ephemeral::Ephemeral;
+use crate::handler::settlement::try_complete_settlement;
 use crate::handler::state_wrap::MutableFunderState;
 use crate::handler::types::{FriendSendCommands, SendCommands};
 use crate::state::{FunderMutation, FunderState};
@@ -326,6 +328,118 @@ pub async fn apply_local_reset<'a, B, R>(
     }
 }
 
+/// Sign over the final balances with a friend we are settling with, once the channel is drained.
+/// Our signature is sent again if the remote side might have missed it.
+async fn send_settlement_signature<'a, B>(
+    m_state: &'a mut MutableFunderState<B>,
+    friend_public_key: &'a PublicKey,
+    friend_send_commands: &'a FriendSendCommands,
+    identity_client: &'a mut IdentityClient,
+    outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+{
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+
+    let mut settlement_in_progress = match &friend.settlement {
+        Settlement::NotStarted => return,
+        Settlement::InProgress(settlement_in_progress) => settlement_in_progress.clone(),
+        Settlement::Complete(settlement_statement) => {
+            if friend_send_commands.resend_outgoing {
+                let settlement_signature = SettlementSignature {
+                    move_token_counter: settlement_statement.move_token_counter,
+                    signature: settlement_statement.local_signature.clone(),
+                };
+                outgoing_messages.push((
+                    friend_public_key.clone(),
+                    FriendMessage::SettlementSignature(settlement_signature),
+                ));
+            }
+            return;
+        }
+    };
+
+    let (move_token_counter, balances) = match friend.drained_balances() {
+        Some(drained_balances) => drained_balances,
+        None => return,
+    };
+
+    if let Some(local_signature) = &settlement_in_progress.opt_local_signature {
+        if local_signature.move_token_counter == move_token_counter {
+            // We have already signed over the current state of the channel:
+            if friend_send_commands.resend_outgoing {
+                outgoing_messages.push((
+                    friend_public_key.clone(),
+                    FriendMessage::SettlementSignature(local_signature.clone()),
+                ));
+            }
+            return;
+        }
+    }
+
+    let local_public_key = friend.local_public_key.clone();
+    let settlement_signature = match sign_settlement(
+        &local_public_key,
+        friend_public_key,
+        &balances,
+        move_token_counter,
+        identity_client,
+    )
+    .await
+    {
+        Some(settlement_signature) => settlement_signature,
+        None => {
+            // The remote side can drive a balance to a value we are unable to sign over.
+            // The settlement can not complete, but it may still be canceled.
+            warn!("send_settlement_signature(): Unable to sign over drained balances");
+            return;
+        }
+    };
+
+    settlement_in_progress.opt_local_signature = Some(settlement_signature.clone());
+    let friend_mutation =
+        FriendMutation::SetSettlement(Settlement::InProgress(settlement_in_progress));
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    outgoing_messages.push((
+        friend_public_key.clone(),
+        FriendMessage::SettlementSignature(settlement_signature),
+    ));
+
+    // The remote side might have already signed over the same state:
+    try_complete_settlement(m_state, friend_public_key);
+}
+
+/// Announce to the remote friend the credit we are willing to extend to it, if it has changed
+/// since our last announcement, or if the remote side might have missed it.
+fn send_credit_limits<B>(
+    m_state: &mut MutableFunderState<B>,
+    friend_public_key: &PublicKey,
+    friend_send_commands: &FriendSendCommands,
+    outgoing_messages: &mut Vec<OutgoingMessage<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + Hash,
+{
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+    let local_credit_limits = friend.local_credit_limits();
+
+    if local_credit_limits == friend.sent_credit_limits && !friend_send_commands.resend_outgoing {
+        return;
+    }
+
+    let friend_mutation = FriendMutation::SetSentCreditLimits(local_credit_limits.clone());
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    outgoing_messages.push((
+        friend_public_key.clone(),
+        FriendMessage::CreditLimits(local_credit_limits),
+    ));
+}
+
 async fn send_friend_iter1<'a, B, R>(
     m_state: &'a mut MutableFunderState<B>,
     friend_public_key: &'a PublicKey,
@@ -334,6 +448,7 @@ async fn send_friend_iter1<'a, B, R>(
     identity_client: &'a mut IdentityClient,
     rng: &'a mut R,
     max_operations_in_batch: usize,
+    announce_credit_limits: bool,
     mut outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
     outgoing_channeler_config: &'a mut Vec<ChannelerConfig<RelayAddress<B>>>,
 ) where
@@ -378,6 +493,24 @@ async fn send_friend_iter1<'a, B, R>(
         }
     }
 
+    send_settlement_signature(
+        m_state,
+        friend_public_key,
+        friend_send_commands,
+        identity_client,
+        &mut outgoing_messages,
+    )
+    .await;
+
+    if announce_credit_limits {
+        send_credit_limits(
+            m_state,
+            friend_public_key,
+            friend_send_commands,
+            &mut outgoing_messages,
+        );
+    }
+
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
 
     let channel_consistent = match &friend.channel_status {
@@ -860,6 +993,7 @@ pub async fn create_friend_messages<'a, B, R>(
     ephemeral: &'a Ephemeral,
     send_commands: &'a SendCommands,
     max_operations_in_batch: usize,
+    announce_credit_limits: bool,
     identity_client: &'a mut IdentityClient,
     rng: &'a mut R,
 ) -> (
@@ -888,6 +1022,7 @@ where
             identity_client,
             rng,
             max_operations_in_batch,
+            announce_credit_limits,
             &mut outgoing_messages,
             &mut outgoing_channeler_config,
         )
@@ -923,5 +1058,17 @@ where
         .await;
     }
 
+    // Forward commits to the next node on the route.
+    // Delivery is best effort: If the friend is offline, the commit is dropped.
+    for (friend_public_key, deliver_commit) in &send_commands.deliver_commits {
+        if !ephemeral.liveness.is_online(friend_public_key) {
+            continue;
+        }
+        outgoing_messages.push((
+            friend_public_key.clone(),
+            FriendMessage::DeliverCommit(deliver_commit.clone()),
+        ));
+    }
+
     (outgoing_messages, outgoing_channeler_config)
 }
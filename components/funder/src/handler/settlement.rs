@@ -0,0 +1,108 @@
+use std::fmt::Debug;
+
+use signature::canonical::CanonicalSerialize;
+use signature::verify::verify_settlement_statement;
+
+use crypto::rand::CryptoRandom;
+
+use proto::crypto::PublicKey;
+use proto::funder::messages::{FunderOutgoingControl, SettlementStatement};
+
+use crate::friend::{FriendMutation, Settlement, SettlementInProgress};
+use crate::state::FunderMutation;
+
+use crate::handler::canceler::{cancel_pending_requests, CurrencyChoice};
+use crate::handler::state_wrap::MutableFunderState;
+use crate::handler::types::SendCommands;
+
+/// Start a cooperative close of the relationship with a friend.
+/// Pending requests to the friend are canceled, and no new requests will be sent or accepted.
+/// A final settlement is signed by both sides once all pending transactions are drained.
+pub fn start_settlement<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &mut R,
+    friend_public_key: &PublicKey,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+    R: CryptoRandom,
+{
+    // Cancel all pending requests to this friend:
+    cancel_pending_requests(
+        m_state,
+        send_commands,
+        outgoing_control,
+        rng,
+        friend_public_key,
+        &CurrencyChoice::All,
+    );
+
+    let settlement = Settlement::InProgress(SettlementInProgress {
+        opt_local_signature: None,
+        opt_remote_signature: None,
+    });
+    let friend_mutation = FriendMutation::SetSettlement(settlement);
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    send_commands.set_try_send(friend_public_key);
+}
+
+/// Complete the settlement with a friend if both sides have signed over the current state of the
+/// drained channel.
+pub fn try_complete_settlement<B>(
+    m_state: &mut MutableFunderState<B>,
+    friend_public_key: &PublicKey,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+    let settlement_in_progress = match &friend.settlement {
+        Settlement::InProgress(settlement_in_progress) => settlement_in_progress,
+        Settlement::NotStarted | Settlement::Complete(_) => return,
+    };
+
+    let (local_signature, remote_signature) = match (
+        &settlement_in_progress.opt_local_signature,
+        &settlement_in_progress.opt_remote_signature,
+    ) {
+        (Some(local_signature), Some(remote_signature)) => (local_signature, remote_signature),
+        _ => return,
+    };
+
+    let (move_token_counter, balances) = match friend.drained_balances() {
+        Some(drained_balances) => drained_balances,
+        None => return,
+    };
+
+    // Both signatures must refer to the current state of the channel:
+    if local_signature.move_token_counter != move_token_counter
+        || remote_signature.move_token_counter != move_token_counter
+    {
+        return;
+    }
+
+    let settlement_statement = SettlementStatement {
+        local_public_key: friend.local_public_key.clone(),
+        remote_public_key: friend.remote_public_key.clone(),
+        balances,
+        move_token_counter,
+        local_signature: local_signature.signature.clone(),
+        remote_signature: remote_signature.signature.clone(),
+    };
+
+    if !verify_settlement_statement(&settlement_statement) {
+        warn!(
+            "try_complete_settlement(): Invalid settlement signature from {:?}",
+            friend_public_key
+        );
+        return;
+    }
+
+    let friend_mutation = FriendMutation::SetSettlement(Settlement::Complete(settlement_statement));
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+}
@@ -680,7 +680,11 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(outgoing_control.len(), 2);
     let outgoing = &outgoing_control[1];
     let transaction_result = match outgoing {
-        FunderOutgoingControl::TransactionResult(transaction_result) => transaction_result,
+        FunderOutgoingControl::TransactionResult(transaction_result, is_first_hop_failure) => {
+            // The request was canceled by the remote side:
+            assert!(!is_first_hop_failure);
+            transaction_result
+        }
         _ => unreachable!(),
     };
 
@@ -939,7 +943,7 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(outgoing_control.len(), 2);
     let outgoing = &outgoing_control[1];
     let transaction_result = match outgoing {
-        FunderOutgoingControl::TransactionResult(transaction_result) => transaction_result,
+        FunderOutgoingControl::TransactionResult(transaction_result, _) => transaction_result,
         _ => unreachable!(),
     };
 
@@ -18,6 +18,7 @@ use crate::types::{FunderIncoming, FunderOutgoingComm};
 const TEST_MAX_NODE_RELAYS: usize = 16;
 const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+const TEST_ANNOUNCE_CREDIT_LIMITS: bool = false;
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
@@ -54,6 +55,7 @@ where
         TEST_MAX_NODE_RELAYS,
         TEST_MAX_OPERATIONS_IN_BATCH,
         TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_ANNOUNCE_CREDIT_LIMITS,
         funder_incoming,
     )
     .await?;
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use proto::crypto::PublicKey;
+use proto::funder::messages::DeliverCommit;
 
 #[derive(Debug, Clone)]
 pub struct FriendSendCommands {
@@ -31,12 +32,15 @@ impl FriendSendCommands {
 #[derive(Clone)]
 pub struct SendCommands {
     pub send_commands: HashMap<PublicKey, FriendSendCommands>,
+    /// Commits to be forwarded to friends along the route of a transaction
+    pub deliver_commits: Vec<(PublicKey, DeliverCommit)>,
 }
 
 impl SendCommands {
     pub fn new() -> Self {
         SendCommands {
             send_commands: HashMap::new(),
+            deliver_commits: Vec::new(),
         }
     }
 
@@ -79,4 +83,13 @@ impl SendCommands {
             .or_insert_with(FriendSendCommands::new);
         friend_send_commands.local_reset = true;
     }
+
+    pub fn push_deliver_commit(
+        &mut self,
+        friend_public_key: &PublicKey,
+        deliver_commit: DeliverCommit,
+    ) {
+        self.deliver_commits
+            .push((friend_public_key.clone(), deliver_commit));
+    }
 }
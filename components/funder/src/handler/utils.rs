@@ -53,6 +53,46 @@ where
     None
 }
 
+/// Find the friend we forwarded a request to.
+/// This should be a pending local request at some friend.
+/// Returns the public key of a friend. If we are the destination of this request, the function
+/// returns None.
+pub fn find_request_destination<'a, B>(
+    state: &'a FunderState<B>,
+    currency: &Currency,
+    request_id: &Uid,
+) -> Option<&'a PublicKey>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    for (friend_public_key, friend) in &state.friends {
+        match &friend.channel_status {
+            ChannelStatus::Inconsistent(_) => continue,
+            ChannelStatus::Consistent(channel_consistent) => {
+                let mutual_credit = if let Some(mutual_credit) = channel_consistent
+                    .token_channel
+                    .get_mutual_credits()
+                    .get(currency)
+                {
+                    mutual_credit
+                } else {
+                    continue;
+                };
+
+                if mutual_credit
+                    .state()
+                    .pending_transactions
+                    .local
+                    .contains_key(request_id)
+                {
+                    return Some(friend_public_key);
+                }
+            }
+        }
+    }
+    None
+}
+
 /// Find an outgoing pending transaction
 pub fn find_local_pending_transaction<'a, B>(
     state: &'a FunderState<B>,
@@ -141,6 +181,11 @@ where
         return false;
     }
 
+    // No new requests are sent to a friend we are settling with:
+    if friend.is_settling() {
+        return false;
+    }
+
     // Make sure that the channel is consistent:
     let token_channel = match &friend.channel_status {
         ChannelStatus::Inconsistent(_) => return false,
@@ -2,17 +2,18 @@ use std::collections::HashMap as ImHashMap;
 
 use signature::canonical::CanonicalSerialize;
 
+use proto::funder::messages::Currency;
 use proto::report::messages::{
     AddFriendReport, ChannelConsistentReport, ChannelInconsistentReport, ChannelStatusReport,
     CurrencyConfigReport, CurrencyReport, FriendLivenessReport, FriendReport, FriendReportMutation,
     FriendStatusReport, FunderReport, FunderReportMutation, McBalanceReport, MoveTokenHashedReport,
-    ResetTermsReport,
+    ResetTermsReport, SettlementReport,
 };
 
 use crate::types::MoveTokenHashed;
 
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
-use crate::friend::{ChannelStatus, FriendMutation, FriendState};
+use crate::friend::{ChannelStatus, CurrencyConfig, FriendMutation, FriendState, Settlement};
 use crate::liveness::LivenessMutation;
 use crate::mutual_credit::types::McBalance;
 use crate::state::{FunderMutation, FunderState};
@@ -27,6 +28,18 @@ impl From<&McBalance> for McBalanceReport {
     }
 }
 
+impl From<&Settlement> for SettlementReport {
+    fn from(settlement: &Settlement) -> SettlementReport {
+        match settlement {
+            Settlement::NotStarted => SettlementReport::NotStarted,
+            Settlement::InProgress(_) => SettlementReport::InProgress,
+            Settlement::Complete(settlement_statement) => {
+                SettlementReport::Complete(settlement_statement.clone())
+            }
+        }
+    }
+}
+
 impl From<&MoveTokenHashed> for MoveTokenHashedReport {
     fn from(move_token_hashed: &MoveTokenHashed) -> MoveTokenHashedReport {
         MoveTokenHashedReport {
@@ -82,6 +95,53 @@ where
     }
 }
 
+fn create_currency_config_report<B>(
+    friend_state: &FriendState<B>,
+    currency: &Currency,
+    currency_config: &CurrencyConfig,
+) -> CurrencyConfigReport
+where
+    B: Clone + CanonicalSerialize,
+{
+    CurrencyConfigReport {
+        currency: currency.clone(),
+        rate: currency_config.rate.clone(),
+        remote_max_debt: currency_config.remote_max_debt,
+        is_open: currency_config.is_open,
+        opt_fee_policy: currency_config.opt_fee_policy.clone(),
+        effective_rate: friend_state
+            .calc_effective_rate(currency)
+            .unwrap_or_else(|| currency_config.rate.clone()),
+        opt_rebalance_policy: currency_config.opt_rebalance_policy.clone(),
+    }
+}
+
+/// Create report mutations for all currencies whose effective rate was changed.
+/// This happens when a fee policy is configured and the balance with the friend changes.
+fn effective_rate_report_mutations<B>(
+    friend_before: &FriendState<B>,
+    friend_after: &FriendState<B>,
+) -> Vec<FriendReportMutation<B>>
+where
+    B: Clone + CanonicalSerialize,
+{
+    friend_after
+        .currency_configs
+        .iter()
+        .filter(|(currency, _)| {
+            friend_before.calc_effective_rate(currency)
+                != friend_after.calc_effective_rate(currency)
+        })
+        .map(|(currency, currency_config)| {
+            FriendReportMutation::UpdateCurrencyConfig(create_currency_config_report(
+                friend_after,
+                currency,
+                currency_config,
+            ))
+        })
+        .collect()
+}
+
 fn create_friend_report<B>(
     friend_state: &FriendState<B>,
     friend_liveness: &FriendLivenessReport,
@@ -98,11 +158,8 @@ where
             .clone()
             .into_iter()
             // .cloned()
-            .map(|(currency, currency_config)| CurrencyConfigReport {
-                currency,
-                rate: currency_config.rate,
-                remote_max_debt: currency_config.remote_max_debt,
-                is_open: currency_config.is_open,
+            .map(|(currency, currency_config)| {
+                create_currency_config_report(friend_state, &currency, &currency_config)
             })
             .collect(),
         remote_relays: friend_state.remote_relays.clone(),
@@ -113,6 +170,8 @@ where
         liveness: friend_liveness.clone(),
         channel_status,
         status: FriendStatusReport::from(&friend_state.status),
+        settlement: SettlementReport::from(&friend_state.settlement),
+        remote_credit_limits: friend_state.remote_credit_limits.clone(),
     }
 }
 
@@ -135,6 +194,7 @@ where
         local_public_key: funder_state.local_public_key.clone(),
         relays: funder_state.relays.clone().into_iter().collect(),
         friends: friends.into_iter().collect(),
+        exchange_rates: funder_state.exchange_rates.clone().into_iter().collect(),
     }
 }
 
@@ -165,7 +225,9 @@ where
                     .get_last_incoming_move_token_hashed()
                     .map(|move_token_hashed| MoveTokenHashedReport::from(&move_token_hashed)),
             );
-            vec![set_channel_status, set_last_incoming_move_token]
+            let mut report_mutations = vec![set_channel_status, set_last_incoming_move_token];
+            report_mutations.extend(effective_rate_report_mutations(friend, &friend_after));
+            report_mutations
         }
         FriendMutation::PushBackPendingRequest(_)
         | FriendMutation::PopFrontPendingRequest
@@ -185,18 +247,22 @@ where
         FriendMutation::SetName(name) => vec![FriendReportMutation::SetName(name.clone())],
         FriendMutation::UpdateCurrencyConfig((currency, currency_config)) => {
             vec![FriendReportMutation::UpdateCurrencyConfig(
-                CurrencyConfigReport {
-                    currency: currency.clone(),
-                    rate: currency_config.rate.clone(),
-                    remote_max_debt: currency_config.remote_max_debt,
-                    is_open: currency_config.is_open,
-                },
+                create_currency_config_report(&friend_after, currency, currency_config),
             )]
         }
         FriendMutation::RemoveCurrencyConfig(currency) => {
             vec![FriendReportMutation::RemoveCurrencyConfig(currency.clone())]
         }
         FriendMutation::SetSentLocalRelays(_) => vec![],
+        FriendMutation::SetRemoteCreditLimits(remote_credit_limits) => {
+            vec![FriendReportMutation::SetRemoteCreditLimits(
+                remote_credit_limits.clone(),
+            )]
+        }
+        FriendMutation::SetSentCreditLimits(_) => vec![],
+        FriendMutation::SetSettlement(settlement) => vec![FriendReportMutation::SetSettlement(
+            SettlementReport::from(settlement),
+        )],
         FriendMutation::SetInconsistent(_) | FriendMutation::SetConsistent(_) => {
             let channel_status_report = ChannelStatusReport::from(&friend_after.channel_status);
             let set_channel_status = FriendReportMutation::SetChannelStatus(channel_status_report);
@@ -206,7 +272,9 @@ where
                 .map(|move_token_hashed| MoveTokenHashedReport::from(&move_token_hashed));
             let set_last_incoming_move_token =
                 FriendReportMutation::SetOptLastIncomingMoveToken(opt_move_token_hashed_report);
-            vec![set_channel_status, set_last_incoming_move_token]
+            let mut report_mutations = vec![set_channel_status, set_last_incoming_move_token];
+            report_mutations.extend(effective_rate_report_mutations(friend, &friend_after));
+            report_mutations
         }
     }
 }
@@ -268,6 +336,14 @@ where
                 friend_public_key.clone(),
             )]
         }
+        FunderMutation::SetExchangeRate(exchange_rate) => {
+            vec![FunderReportMutation::SetExchangeRate(exchange_rate.clone())]
+        }
+        FunderMutation::RemoveExchangeRate(currency_pair) => {
+            vec![FunderReportMutation::RemoveExchangeRate(
+                currency_pair.clone(),
+            )]
+        }
         FunderMutation::AddInvoice(_)
         | FunderMutation::AddIncomingTransaction(_)
         | FunderMutation::SetInvoiceSrcHashedLock(_)
@@ -11,7 +11,9 @@ use signature::canonical::CanonicalSerialize;
 use proto::crypto::{HashedLock, InvoiceId, PaymentId, PlainLock, PublicKey, Uid};
 
 use proto::app_server::messages::NamedRelayAddress;
-use proto::funder::messages::{AddFriend, Currency, Receipt, ResponseSendFundsOp};
+use proto::funder::messages::{
+    AddFriend, Currency, CurrencyPair, ExchangeRate, Receipt, ResponseSendFundsOp,
+};
 
 use crate::friend::{FriendMutation, FriendState};
 
@@ -38,6 +40,9 @@ pub struct FunderState<B: Clone> {
     /// Ongoing payments (For which this node is the buyer):
     #[serde(with = "ser_map_b64_any")]
     pub payments: ImHashMap<PaymentId, Payment>,
+    /// Local exchange rates between currencies (At most one for every currency pair)
+    #[serde(default)]
+    pub exchange_rates: ImVec<ExchangeRate>,
 }
 
 /// A state of a Payment where new transactions may still be added.
@@ -146,6 +151,8 @@ pub enum FunderMutation<B: Clone> {
     RemoveTransaction(Uid),           // request_id
     UpdatePayment((PaymentId, Payment)),
     RemovePayment(PaymentId),
+    SetExchangeRate(ExchangeRate),
+    RemoveExchangeRate(CurrencyPair),
 }
 
 impl<B> FunderState<B>
@@ -163,6 +170,7 @@ where
             open_invoices: ImHashMap::new(),
             open_transactions: ImHashMap::new(),
             payments: ImHashMap::new(),
+            exchange_rates: ImVec::new(),
         }
     }
 
@@ -259,6 +267,39 @@ where
             FunderMutation::RemovePayment(payment_id) => {
                 let _ = self.payments.remove(payment_id);
             }
+            FunderMutation::SetExchangeRate(exchange_rate) => {
+                // Remove previous exchange rate for this currency pair (If exists):
+                self.exchange_rates.retain(|cur_exchange_rate| {
+                    cur_exchange_rate.currency_pair != exchange_rate.currency_pair
+                });
+                self.exchange_rates.push_back(exchange_rate.clone());
+            }
+            FunderMutation::RemoveExchangeRate(currency_pair) => {
+                self.exchange_rates
+                    .retain(|cur_exchange_rate| &cur_exchange_rate.currency_pair != currency_pair);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_funder_state_deserialize_without_exchange_rates() {
+        let local_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let funder_state = FunderState::<u32>::new(local_public_key, vec![]);
+
+        // A state that was persisted before exchange rates were introduced:
+        let mut value = serde_json::to_value(&funder_state).unwrap();
+        assert!(value
+            .as_object_mut()
+            .unwrap()
+            .remove("exchange_rates")
+            .is_some());
+
+        let loaded_state: FunderState<u32> = serde_json::from_value(value).unwrap();
+        assert_eq!(loaded_state, funder_state);
+    }
+}
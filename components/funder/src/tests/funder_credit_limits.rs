@@ -0,0 +1,135 @@
+use std::convert::TryFrom;
+
+use common::test_executor::TestExecutor;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    CreatePayment, CreateTransaction, CreditLimit, Currency, FriendStatus, FriendsRoute,
+    FunderControl, RequestResult, RequestsStatus,
+};
+use proto::report::messages::FunderReport;
+
+use super::utils::{create_node_controls, dummy_relay_address};
+
+/// Test the announcement of credit limits between two adjacent nodes
+async fn task_funder_credit_limits_basic(test_executor: TestExecutor) {
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+
+    let num_nodes = 2;
+    let mut node_controls = create_node_controls(num_nodes, test_executor.clone()).await;
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    node_controls[0]
+        .add_friend(&public_keys[1], relays1, "node1")
+        .await;
+    node_controls[1]
+        .add_friend(&public_keys[0], relays0, "node0")
+        .await;
+
+    node_controls[0]
+        .set_friend_status(&public_keys[1], FriendStatus::Enabled)
+        .await;
+    node_controls[1]
+        .set_friend_status(&public_keys[0], FriendStatus::Enabled)
+        .await;
+
+    node_controls[0]
+        .set_friend_currencies(&public_keys[1], vec![currency1.clone()])
+        .await;
+    node_controls[1]
+        .set_friend_currencies(&public_keys[0], vec![currency1.clone()])
+        .await;
+
+    node_controls[0]
+        .wait_until_currency_active(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_currency_active(&public_keys[0], &currency1)
+        .await;
+
+    // Set remote max debt for both sides:
+    node_controls[0]
+        .set_remote_max_debt(&public_keys[1], &currency1, 200)
+        .await;
+    node_controls[1]
+        .set_remote_max_debt(&public_keys[0], &currency1, 100)
+        .await;
+
+    // Each side should eventually learn the limit announced by the other side:
+    for (i, j, max_debt) in &[(0, 1, 100), (1, 0, 200)] {
+        let remote_public_key = public_keys[*j].clone();
+        let expected_credit_limits = vec![CreditLimit {
+            currency: currency1.clone(),
+            max_debt: *max_debt,
+        }];
+        let pred = |report: &FunderReport<_>| {
+            let friend = report.friends.get(&remote_public_key).unwrap();
+            friend.remote_credit_limits == expected_credit_limits
+        };
+        node_controls[*i].recv_until(pred).await;
+    }
+
+    // Open requests:
+    node_controls[0]
+        .set_requests_status(&public_keys[1], &currency1, RequestsStatus::Open)
+        .await;
+    node_controls[1]
+        .set_requests_status(&public_keys[0], &currency1, RequestsStatus::Open)
+        .await;
+
+    node_controls[0]
+        .wait_until_ready(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_ready(&public_keys[0], &currency1)
+        .await;
+
+    // Create payment 0 --> 1
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        currency: currency1.clone(),
+        total_dest_payment: 150,
+        dest_public_key: node_controls[1].public_key.clone(),
+    };
+    node_controls[0]
+        .send(FunderControl::CreatePayment(create_payment))
+        .await;
+
+    // Create transaction 0 --> 1, exceeding the credit announced by node 1:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        request_id: Uid::from(&[5u8; Uid::len()]),
+        route: FriendsRoute {
+            public_keys: vec![public_keys[0].clone(), public_keys[1].clone()],
+        },
+        dest_payment: 150,
+        fees: 0,
+    };
+    node_controls[0]
+        .send(FunderControl::CreateTransaction(create_transaction))
+        .await;
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+
+    // We expect failure, without sending the request to node 1:
+    match transaction_result.result {
+        RequestResult::Failure => {}
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_funder_credit_limits_basic() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_funder_credit_limits_basic(test_executor.clone()));
+    assert!(res.is_output());
+}
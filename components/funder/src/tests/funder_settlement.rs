@@ -0,0 +1,269 @@
+use std::convert::TryFrom;
+
+use common::test_executor::TestExecutor;
+
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    AckClosePayment, AddInvoice, CreatePayment, CreateTransaction, Currency, FriendStatus,
+    FriendsRoute, FunderControl, PaymentStatus, RequestResult, RequestsStatus, SettleFriend,
+};
+use proto::report::messages::{FunderReport, SettlementReport};
+
+use signature::verify::verify_settlement_statement;
+
+use super::utils::{create_node_controls, dummy_relay_address};
+
+/// Test a cooperative settlement between two adjacent nodes
+async fn task_funder_settlement_basic(test_executor: TestExecutor) {
+    let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+    let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+
+    let num_nodes = 2;
+    let mut node_controls = create_node_controls(num_nodes, test_executor.clone()).await;
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    node_controls[0]
+        .add_friend(&public_keys[1], relays1, "node1")
+        .await;
+    node_controls[1]
+        .add_friend(&public_keys[0], relays0, "node0")
+        .await;
+    assert_eq!(node_controls[0].report.friends.len(), 1);
+    assert_eq!(node_controls[1].report.friends.len(), 1);
+
+    node_controls[0]
+        .set_friend_status(&public_keys[1], FriendStatus::Enabled)
+        .await;
+    node_controls[1]
+        .set_friend_status(&public_keys[0], FriendStatus::Enabled)
+        .await;
+
+    // The two nodes should eventually agree to trade `currency1`.
+    node_controls[0]
+        .set_friend_currencies(&public_keys[1], vec![currency1.clone()])
+        .await;
+    node_controls[1]
+        .set_friend_currencies(&public_keys[0], vec![currency1.clone(), currency2.clone()])
+        .await;
+
+    node_controls[0]
+        .wait_until_currency_active(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_currency_active(&public_keys[0], &currency1)
+        .await;
+
+    // Set remote max debt for both sides:
+    node_controls[0]
+        .set_remote_max_debt(&public_keys[1], &currency1, 200)
+        .await;
+    node_controls[1]
+        .set_remote_max_debt(&public_keys[0], &currency1, 100)
+        .await;
+
+    // Open requests:
+    node_controls[0]
+        .set_requests_status(&public_keys[1], &currency1, RequestsStatus::Open)
+        .await;
+    node_controls[1]
+        .set_requests_status(&public_keys[0], &currency1, RequestsStatus::Open)
+        .await;
+
+    // Wait for liveness:
+    node_controls[0]
+        .wait_until_ready(&public_keys[1], &currency1)
+        .await;
+    node_controls[1]
+        .wait_until_ready(&public_keys[0], &currency1)
+        .await;
+
+    // Let node 1 open an invoice:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        currency: currency1.clone(),
+        total_dest_payment: 4,
+    };
+    node_controls[1]
+        .send(FunderControl::AddInvoice(add_invoice))
+        .await;
+
+    // Create payment 0 --> 1
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        invoice_id: InvoiceId::from(&[1u8; InvoiceId::len()]),
+        currency: currency1.clone(),
+        total_dest_payment: 4,
+        dest_public_key: node_controls[1].public_key.clone(),
+    };
+    node_controls[0]
+        .send(FunderControl::CreatePayment(create_payment))
+        .await;
+
+    // Create transaction 0 --> 1:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        request_id: Uid::from(&[5u8; Uid::len()]),
+        route: FriendsRoute {
+            public_keys: vec![public_keys[0].clone(), public_keys[1].clone()],
+        },
+        dest_payment: 4,
+        fees: 1,
+    };
+
+    node_controls[0]
+        .send(FunderControl::CreateTransaction(create_transaction))
+        .await;
+    let transaction_result = node_controls[0]
+        .recv_until_transaction_result()
+        .await
+        .unwrap();
+
+    let commit = match transaction_result.result {
+        RequestResult::Complete(commit) => commit,
+        _ => unreachable!(),
+    };
+
+    // Commit: 0 ==> 1  (Out of band)
+
+    // 1: Apply Commit:
+    node_controls[1]
+        .send(FunderControl::CommitInvoice(commit))
+        .await;
+
+    // Wait until no more progress can be made
+    test_executor.wait().await;
+
+    // 0: Expect a receipt:
+
+    node_controls[0]
+        .send(FunderControl::RequestClosePayment(PaymentId::from(
+            &[2u8; PaymentId::len()],
+        )))
+        .await;
+    let response_close_payment = node_controls[0]
+        .recv_until_response_close_payment()
+        .await
+        .unwrap();
+    let (receipt, ack_uid) = match response_close_payment.status {
+        PaymentStatus::Success(payment_status_success) => (
+            payment_status_success.receipt,
+            payment_status_success.ack_uid,
+        ),
+        _ => unreachable!(),
+    };
+
+    // 0: Acknowledge response close:
+    let ack_close_payment = AckClosePayment {
+        payment_id: PaymentId::from(&[2u8; PaymentId::len()]),
+        ack_uid,
+    };
+    node_controls[0]
+        .send(FunderControl::AckClosePayment(ack_close_payment))
+        .await;
+
+    assert_eq!(
+        receipt.invoice_id,
+        InvoiceId::from(&[1u8; InvoiceId::len()])
+    );
+    assert_eq!(receipt.dest_payment, 4);
+    assert_eq!(receipt.total_dest_payment, 4);
+
+    // Verify expected balances:
+    node_controls[0]
+        .wait_friend_balance(&public_keys[1], &currency1, -5)
+        .await;
+    node_controls[1]
+        .wait_friend_balance(&public_keys[0], &currency1, 5)
+        .await;
+
+    // Wait until no more progress can be made
+    test_executor.wait().await;
+
+    // Node0 starts a cooperative close of the relationship with node1:
+    let settle_friend = SettleFriend {
+        friend_public_key: public_keys[1].clone(),
+    };
+    node_controls[0]
+        .send(FunderControl::SettleFriend(settle_friend))
+        .await;
+
+    // Both sides should eventually hold the same signed statement:
+    for (i, j) in &[(0, 1), (1, 0)] {
+        let remote_public_key = public_keys[*j].clone();
+        let pred = |report: &FunderReport<_>| {
+            let friend = report.friends.get(&remote_public_key).unwrap();
+            match &friend.settlement {
+                SettlementReport::Complete(_) => true,
+                _ => false,
+            }
+        };
+        node_controls[*i].recv_until(pred).await;
+    }
+
+    let statement0 = match &node_controls[0]
+        .report
+        .friends
+        .get(&public_keys[1])
+        .unwrap()
+        .settlement
+    {
+        SettlementReport::Complete(settlement_statement) => settlement_statement.clone(),
+        _ => unreachable!(),
+    };
+    let statement1 = match &node_controls[1]
+        .report
+        .friends
+        .get(&public_keys[0])
+        .unwrap()
+        .settlement
+    {
+        SettlementReport::Complete(settlement_statement) => settlement_statement.clone(),
+        _ => unreachable!(),
+    };
+
+    assert!(verify_settlement_statement(&statement0));
+    assert!(verify_settlement_statement(&statement1));
+
+    assert_eq!(statement0.local_public_key, public_keys[0]);
+    assert_eq!(statement0.remote_public_key, public_keys[1]);
+    assert_eq!(statement0.move_token_counter, statement1.move_token_counter);
+    assert_eq!(statement0.local_signature, statement1.remote_signature);
+    assert_eq!(statement0.remote_signature, statement1.local_signature);
+
+    let balance0 = statement0
+        .balances
+        .iter()
+        .find(|currency_balance| currency_balance.currency == currency1)
+        .unwrap()
+        .balance;
+    let balance1 = statement1
+        .balances
+        .iter()
+        .find(|currency_balance| currency_balance.currency == currency1)
+        .unwrap()
+        .balance;
+    assert_eq!(balance0, -5);
+    assert_eq!(balance1, 5);
+
+    // A tampered statement should not verify:
+    let mut tampered_statement = statement0.clone();
+    for currency_balance in &mut tampered_statement.balances {
+        if currency_balance.currency == currency1 {
+            currency_balance.balance = -4;
+        }
+    }
+    assert!(!verify_settlement_statement(&tampered_statement));
+}
+
+#[test]
+fn test_funder_settlement_basic() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_funder_settlement_basic(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -1,7 +1,10 @@
 mod funder_basic;
+mod funder_credit_limits;
+mod funder_deliver_commit;
 mod funder_error_command;
 mod funder_forward_payment;
 mod funder_inconsistency_basic;
 mod funder_payment_failure;
+mod funder_settlement;
 
 pub mod utils;
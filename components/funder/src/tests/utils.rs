@@ -21,10 +21,10 @@ use proto::report::messages::{
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
-    AddFriend, Currency, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl,
-    Rate, RemoveFriend, RemoveFriendCurrency, RequestsStatus, ResponseClosePayment,
-    SetFriendCurrencyMaxDebt, SetFriendCurrencyRate, SetFriendCurrencyRequestsStatus,
-    SetFriendStatus, TransactionResult,
+    AddFriend, Commit, Currency, FriendStatus, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, Rate, RemoveFriend, RemoveFriendCurrency, RequestsStatus,
+    ResponseClosePayment, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate,
+    SetFriendCurrencyRequestsStatus, SetFriendStatus, TransactionResult,
 };
 
 use database::DatabaseClient;
@@ -43,6 +43,7 @@ use crate::types::{
 const TEST_MAX_NODE_RELAYS: usize = 16;
 const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+const TEST_ANNOUNCE_CREDIT_LIMITS: bool = true;
 
 // This is required to make sure the tests are not stuck.
 //
@@ -217,6 +218,7 @@ pub enum NodeRecv<B: Clone> {
     ReportMutations(FunderReportMutations<B>),
     ResponseClosePayment(ResponseClosePayment),
     TransactionResult(TransactionResult),
+    InvoiceCommitted(Commit),
 }
 
 impl<B> NodeControl<B>
@@ -269,9 +271,12 @@ where
             FunderOutgoingControl::ResponseClosePayment(response_close_payment) => {
                 Some(NodeRecv::ResponseClosePayment(response_close_payment))
             }
-            FunderOutgoingControl::TransactionResult(transaction_result) => {
+            FunderOutgoingControl::TransactionResult(transaction_result, _) => {
                 Some(NodeRecv::TransactionResult(transaction_result))
             }
+            FunderOutgoingControl::InvoiceCommitted(commit) => {
+                Some(NodeRecv::InvoiceCommitted(commit))
+            }
         }
     }
 
@@ -284,6 +289,7 @@ where
                 NodeRecv::ReportMutations(_) => {}
                 NodeRecv::TransactionResult(_) => unreachable!(),
                 NodeRecv::ResponseClosePayment(_) => unreachable!(),
+                NodeRecv::InvoiceCommitted(_) => unreachable!(),
             };
         }
     }
@@ -294,6 +300,7 @@ where
                 NodeRecv::ReportMutations(_) => {}
                 NodeRecv::TransactionResult(transaction_result) => return Some(transaction_result),
                 NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::InvoiceCommitted(_) => {}
             };
         }
     }
@@ -306,6 +313,18 @@ where
                 NodeRecv::ResponseClosePayment(response_close_payment) => {
                     return Some(response_close_payment)
                 }
+                NodeRecv::InvoiceCommitted(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_invoice_committed(&mut self) -> Option<Commit> {
+        loop {
+            match self.recv().await? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::InvoiceCommitted(commit) => return Some(commit),
             };
         }
     }
@@ -593,6 +612,7 @@ where
             TEST_MAX_NODE_RELAYS,
             TEST_MAX_OPERATIONS_IN_BATCH,
             TEST_MAX_PENDING_USER_REQUESTS,
+            TEST_ANNOUNCE_CREDIT_LIMITS,
             None,
         );
 
@@ -459,6 +459,21 @@ where
         }
     }
 
+    pub fn get_move_token_counter(&self) -> u128 {
+        match &self.direction {
+            TcDirection::Incoming(tc_incoming) => {
+                tc_incoming
+                    .move_token_in
+                    .token_info
+                    .counters
+                    .move_token_counter
+            }
+            TcDirection::Outgoing(tc_outgoing) => {
+                tc_outgoing.token_info.counters.move_token_counter
+            }
+        }
+    }
+
     pub fn get_outgoing(&self) -> Option<TcOutBorrow<'_, B>> {
         match self.get_direction() {
             TcDirectionBorrow::In(_) => None,
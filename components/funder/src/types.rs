@@ -6,14 +6,15 @@ use proto::crypto::{HashResult, HashedLock, PublicKey, RandValue, Signature, Uid
 
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
-    CancelSendFundsOp, ChannelerUpdateFriend, Currency, CurrencyOperations, FriendMessage,
-    FunderIncomingControl, FunderOutgoingControl, MoveToken, PendingTransaction,
-    RequestSendFundsOp, ResponseSendFundsOp, TokenInfo, TransactionStage, UnsignedMoveToken,
-    UnsignedResponseSendFundsOp,
+    CancelSendFundsOp, ChannelerUpdateFriend, Currency, CurrencyBalance, CurrencyOperations,
+    FriendMessage, FunderIncomingControl, FunderOutgoingControl, MoveToken, PendingTransaction,
+    RequestSendFundsOp, ResponseSendFundsOp, SettlementSignature, TokenInfo, TransactionStage,
+    UnsignedMoveToken, UnsignedResponseSendFundsOp,
 };
 
 use signature::signature_buff::{
-    create_response_signature_buffer, hash_token_info, move_token_signature_buff, prefix_hash,
+    create_response_signature_buffer, create_settlement_signature_buff, hash_token_info,
+    move_token_signature_buff, prefix_hash,
 };
 
 use identity::IdentityClient;
@@ -42,6 +43,33 @@ where
     }
 }
 
+/// Sign over the final balances of a mutual credit with a friend.
+/// Returns None if the balances can not be represented from the point of view of the side with
+/// the lower public key (A balance of `i128::MIN` can not be negated).
+pub async fn sign_settlement<'a>(
+    local_public_key: &'a PublicKey,
+    remote_public_key: &'a PublicKey,
+    balances: &'a [CurrencyBalance],
+    move_token_counter: u128,
+    identity_client: &'a mut IdentityClient,
+) -> Option<SettlementSignature> {
+    let signature_buff = create_settlement_signature_buff(
+        local_public_key,
+        remote_public_key,
+        balances,
+        move_token_counter,
+    )?;
+    let signature = identity_client
+        .request_signature(signature_buff)
+        .await
+        .unwrap();
+
+    Some(SettlementSignature {
+        move_token_counter,
+        signature,
+    })
+}
+
 pub async fn create_response_send_funds<'a>(
     currency: &Currency,
     pending_transaction: &'a PendingTransaction,
@@ -11,20 +11,28 @@ use common::mutable_state::MutableState;
 use common::never::Never;
 use common::select_streams::select_streams;
 
+use crypto::rand::CryptoRandom;
+
+use proto::consts::MAX_INDEX_MUTATIONS_IN_UPDATE;
 use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::Currency;
 
 use database::DatabaseClient;
 
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientReportMutation,
     IndexClientReportMutations, IndexClientRequest, IndexClientToAppServer, IndexMutation,
-    RequestRoutes, ResponseRoutesResult,
+    RequestRoutes, ResponseRoutesResult, RouteOutcome,
+};
+use proto::index_server::messages::{
+    IndexServerAddress, NamedIndexServerAddress, ResponseTopology,
 };
-use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
 
 use crate::client_session::{ControlSender, SessionHandle};
+use crate::routes_privacy::{filter_multi_routes, RoutesBlinder, RoutesPrivacy};
 use crate::seq_friends::SeqFriendsClient;
 use crate::single_client::SingleClientControl;
+use crate::topology_cache::TopologyCache;
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, Default)]
 pub struct IndexClientConfig<ISA> {
@@ -114,10 +122,12 @@ enum IndexClientEvent<ISA> {
     IndexServerConnected(ControlSender),
     IndexServerClosed,
     ResponseRoutes((Uid, ResponseRoutesResult)),
+    /// A response to a topology request: (session, server_public_key, currency, response)
+    ResponseTopology((u64, PublicKey, Currency, Option<ResponseTopology>)),
     TimerTick,
 }
 
-struct IndexClient<ISA, TAS, ICS, S> {
+struct IndexClient<ISA, TAS, ICS, R, S> {
     event_sender: mpsc::Sender<IndexClientEvent<ISA>>,
     to_app_server: TAS,
     /// A cyclic list of index server addresses.
@@ -133,10 +143,18 @@ struct IndexClient<ISA, TAS, ICS, S> {
     backoff_ticks: usize,
     conn_status: ConnStatus<ISA>,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
+    /// Creates decoy requests for routes, to hide our real requests from the index servers
+    routes_blinder: RoutesBlinder<R>,
+    /// Topology snapshots synchronized from the index server (If enabled).
+    /// Used to find routes locally when no index server is connected.
+    opt_topology_cache: Option<TopologyCache>,
+    /// Report outcomes of routes used for sending credits to the index server
+    report_route_outcomes: bool,
+    rng: R,
     spawner: S,
 }
 
-/// Send our full friends state as mutations to the server.
+/// Send our full friends state (and exchange rates) as mutations to the server.
 /// We do this in a separate task so that we don't block user requests or incoming funder reports.
 async fn send_full_state(
     mut seq_friends_client: SeqFriendsClient,
@@ -147,6 +165,7 @@ async fn send_full_state(
         .await
         .map_err(|_| IndexClientError::SeqFriendsError)?;
 
+    let mut mutations = Vec::new();
     loop {
         let next_update_res = seq_friends_client
             .next_update()
@@ -157,12 +176,29 @@ async fn send_full_state(
             Some(next_update) => next_update,
             None => break,
         };
+        mutations.push(IndexMutation::UpdateFriendCurrency(update_friend_currency));
+
+        if cyclic_countdown == 0 {
+            break;
+        }
+    }
 
-        // TODO: Maybe send mutations in batches in the future:
-        // However, we need to be careful to not send too many mutations in one batch.
-        let mutations = vec![IndexMutation::UpdateFriendCurrency(update_friend_currency)];
+    // Add our exchange rates:
+    let exchange_rates = seq_friends_client
+        .exchange_rates()
+        .await
+        .map_err(|_| IndexClientError::SeqFriendsError)?;
+    mutations.extend(
+        exchange_rates
+            .into_iter()
+            .map(IndexMutation::SetExchangeRate),
+    );
+
+    // Send the mutations in batches. Sending every mutation in a separate update could exceed the
+    // amount of updates the server allows us to send in a burst.
+    for batch in mutations.chunks(MAX_INDEX_MUTATIONS_IN_UPDATE) {
         if control_sender
-            .send(SingleClientControl::SendMutations(mutations))
+            .send(SingleClientControl::SendMutations(batch.to_vec()))
             .await
             .is_err()
         {
@@ -170,15 +206,11 @@ async fn send_full_state(
         }
         // Note that here we can not reset the ticks_to_send_keepalive counter because we are
         // running as a separate task. We might want to change this in the future.
-
-        if cyclic_countdown == 0 {
-            break;
-        }
     }
     Ok(())
 }
 
-impl<ISA, TAS, ICS, S> IndexClient<ISA, TAS, ICS, S>
+impl<ISA, TAS, ICS, R, S> IndexClient<ISA, TAS, ICS, R, S>
 where
     ISA: Debug + Eq + Clone + Send + 'static,
     TAS: Sink<IndexClientToAppServer<ISA>> + Unpin,
@@ -186,6 +218,7 @@ where
         + Clone
         + Send
         + 'static,
+    R: CryptoRandom + Clone,
     S: Spawn + Clone + Send + 'static,
 {
     pub fn new(
@@ -198,6 +231,10 @@ where
         keepalive_ticks: usize,
         backoff_ticks: usize,
         db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
+        routes_privacy: RoutesPrivacy,
+        opt_topology_sync_ticks: Option<usize>,
+        report_route_outcomes: bool,
+        rng: R,
         spawner: S,
     ) -> Self {
         let index_servers = index_client_config
@@ -221,6 +258,10 @@ where
             backoff_ticks,
             conn_status: ConnStatus::Empty(backoff_ticks),
             db_client,
+            routes_blinder: RoutesBlinder::new(routes_privacy, rng.clone()),
+            opt_topology_cache: opt_topology_sync_ticks.map(TopologyCache::new),
+            report_route_outcomes,
+            rng,
             spawner,
         }
     }
@@ -333,6 +374,114 @@ where
             .map_err(|_| IndexClientError::SendToAppServerFailed)
     }
 
+    /// Find routes locally using the topology cache, because no index server is connected.
+    /// Returns a failure if we have no topology snapshot for the requested currency.
+    pub async fn return_local_response_routes(
+        &mut self,
+        request_routes: RequestRoutes,
+    ) -> Result<(), IndexClientError> {
+        let opt_multi_routes = self
+            .opt_topology_cache
+            .as_ref()
+            .and_then(|topology_cache| topology_cache.get_multi_routes(&request_routes));
+
+        let multi_routes = match opt_multi_routes {
+            Some(multi_routes) => multi_routes,
+            None => {
+                return self
+                    .return_response_routes_failure(request_routes.request_id)
+                    .await
+            }
+        };
+
+        let client_response_routes = ClientResponseRoutes {
+            request_id: request_routes.request_id,
+            result: ResponseRoutesResult::Success(multi_routes),
+        };
+        self.to_app_server
+            .send(IndexClientToAppServer::ResponseRoutes(
+                client_response_routes,
+            ))
+            .await
+            .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
+    /// Send report mutations that were not triggered by an app request
+    async fn send_report_mutations(
+        &mut self,
+        mutations: Vec<IndexClientReportMutation<ISA>>,
+    ) -> Result<(), IndexClientError> {
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: None,
+            mutations,
+        };
+        self.to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations,
+            ))
+            .await
+            .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
+    /// Request topology updates for all the synchronized currencies from the connected index
+    /// server. Does nothing if topology synchronization is disabled, or if we are not connected.
+    fn sync_topology(&mut self) -> Result<(), IndexClientError> {
+        let topology_cache = match &mut self.opt_topology_cache {
+            Some(topology_cache) => topology_cache,
+            None => return Ok(()),
+        };
+
+        let server_connected = match &self.conn_status {
+            ConnStatus::Connected(server_connected) => server_connected,
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => return Ok(()),
+        };
+
+        let control_sender = match &server_connected.opt_control_sender {
+            Some(control_sender) => control_sender,
+            None => return Ok(()),
+        };
+
+        let session = topology_cache.session();
+        for request_topology in topology_cache.create_requests(&mut self.rng) {
+            let mut c_control_sender = control_sender.clone();
+            let mut c_event_sender = self.event_sender.clone();
+            let server_public_key = server_connected.index_server.public_key.clone();
+
+            let request_fut = async move {
+                let currency = request_topology.currency.clone();
+                let (response_sender, response_receiver) = oneshot::channel();
+                let single_client_control =
+                    SingleClientControl::RequestTopology((request_topology, response_sender));
+                let opt_response_topology =
+                    if c_control_sender.send(single_client_control).await.is_ok() {
+                        response_receiver.await.ok()
+                    } else {
+                        None
+                    };
+                let _ = c_event_sender
+                    .send(IndexClientEvent::ResponseTopology((
+                        session,
+                        server_public_key,
+                        currency,
+                        opt_response_topology,
+                    )))
+                    .await;
+            };
+
+            self.spawner
+                .spawn(request_fut)
+                .map_err(|_| IndexClientError::SpawnError)?;
+        }
+        Ok(())
+    }
+
+    /// Start synchronizing the topology of a currency (If topology synchronization is enabled)
+    fn add_topology_currency(&mut self, currency: &Currency) {
+        if let Some(topology_cache) = &mut self.opt_topology_cache {
+            topology_cache.add_currency(currency);
+        }
+    }
+
     pub async fn handle_from_app_server_add_index_server(
         &mut self,
         app_request_id: Uid,
@@ -452,39 +601,51 @@ where
                 .await;
         }
 
-        // Check server connection status:
+        self.add_topology_currency(&request_routes.currency);
+
+        // Check server connection status.
+        // If we are not connected to any server, we attempt to find routes locally:
         let mut server_connected = match &mut self.conn_status {
             ConnStatus::Empty(_) | ConnStatus::Connecting(_) => {
-                return self
-                    .return_response_routes_failure(request_routes.request_id)
-                    .await
+                return self.return_local_response_routes(request_routes).await
             }
             ConnStatus::Connected(server_connected) => server_connected,
         };
 
         let mut control_sender = match server_connected.opt_control_sender.take() {
             Some(control_sender) => control_sender,
-            None => {
-                return self
-                    .return_response_routes_failure(request_routes.request_id)
-                    .await
-            }
+            None => return self.return_local_response_routes(request_routes).await,
         };
 
         let c_request_id = request_routes.request_id.clone();
         let (response_sender, response_receiver) = oneshot::channel();
-        let single_client_control =
-            SingleClientControl::RequestRoutes((request_routes, response_sender));
-
-        match control_sender.send(single_client_control).await {
-            Ok(()) => server_connected.opt_control_sender = Some(control_sender),
-            Err(_) => return self.return_response_routes_failure(c_request_id).await,
-        };
+        let mut opt_response_sender = Some(response_sender);
+        let mut decoy_receivers = Vec::new();
+
+        // The real request is sent among decoy requests (If privacy mode is enabled):
+        for blinded_request in self.routes_blinder.blind(&request_routes) {
+            let sender = if blinded_request.request_id == c_request_id {
+                opt_response_sender.take().unwrap()
+            } else {
+                let (decoy_sender, decoy_receiver) = oneshot::channel();
+                decoy_receivers.push(decoy_receiver);
+                decoy_sender
+            };
+            let single_client_control =
+                SingleClientControl::RequestRoutes((blinded_request, sender));
+            if control_sender.send(single_client_control).await.is_err() {
+                return self.return_response_routes_failure(c_request_id).await;
+            }
+        }
+        server_connected.opt_control_sender = Some(control_sender);
 
         let mut c_event_sender = self.event_sender.clone();
         let request_fut = async move {
             let response_routes_result = match response_receiver.await {
-                Ok(routes) => ResponseRoutesResult::Success(routes),
+                Ok(multi_routes) => ResponseRoutesResult::Success(filter_multi_routes(
+                    &request_routes,
+                    multi_routes,
+                )),
                 Err(_) => ResponseRoutesResult::Failure,
             };
             // TODO: Should report error here if failure occurs?
@@ -494,6 +655,8 @@ where
                     response_routes_result,
                 )))
                 .await;
+            // Wait for the responses to the decoy requests, and discard them:
+            let _ = future::join_all(decoy_receivers).await;
         };
 
         self.num_open_requests = self.num_open_requests.saturating_add(1);
@@ -508,6 +671,11 @@ where
     ) -> Result<(), IndexClientError> {
         // Update state:
         for mutation in &mutations {
+            if let IndexMutation::UpdateFriendCurrency(update_friend_currency) = mutation {
+                self.routes_blinder
+                    .add_known_node(&update_friend_currency.public_key);
+                self.add_topology_currency(&update_friend_currency.currency);
+            }
             self.seq_friends_client
                 .mutate(mutation.clone())
                 .await
@@ -551,6 +719,34 @@ where
         Ok(())
     }
 
+    pub async fn handle_from_app_server_report_route_outcome(
+        &mut self,
+        route_outcome: RouteOutcome,
+    ) -> Result<(), IndexClientError> {
+        if !self.report_route_outcomes {
+            return Ok(());
+        }
+
+        // Route outcomes are not queued. If no server is connected, the outcome is discarded:
+        let server_connected = match &mut self.conn_status {
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => return Ok(()),
+            ConnStatus::Connected(server_connected) => server_connected,
+        };
+
+        let mut control_sender = match server_connected.opt_control_sender.take() {
+            Some(control_sender) => control_sender,
+            None => return Ok(()),
+        };
+
+        if let Ok(()) = control_sender
+            .send(SingleClientControl::ReportRouteOutcome(route_outcome))
+            .await
+        {
+            server_connected.opt_control_sender = Some(control_sender);
+        }
+        Ok(())
+    }
+
     pub async fn handle_from_app_server(
         &mut self,
         app_server_to_index_client: AppServerToIndexClient<ISA>,
@@ -578,6 +774,10 @@ where
             AppServerToIndexClient::ApplyMutations(mutations) => {
                 self.handle_from_app_server_apply_mutations(mutations).await
             }
+            AppServerToIndexClient::ReportRouteOutcome(route_outcome) => {
+                self.handle_from_app_server_report_route_outcome(route_outcome)
+                    .await
+            }
         }
     }
 
@@ -621,7 +821,12 @@ where
             .await
             .map_err(|_| IndexClientError::SendToAppServerFailed)?;
 
-        Ok(())
+        // The new server does not know which topology we have, so we begin a new
+        // synchronization session:
+        if let Some(topology_cache) = &mut self.opt_topology_cache {
+            topology_cache.new_session();
+        }
+        self.sync_topology()
     }
 
     pub async fn handle_index_server_closed(&mut self) -> Result<(), IndexClientError> {
@@ -650,6 +855,10 @@ where
     ) -> Result<(), IndexClientError> {
         self.num_open_requests = self.num_open_requests.checked_sub(1).unwrap();
 
+        if let ResponseRoutesResult::Success(multi_routes) = &response_routes_result {
+            self.routes_blinder.add_known_routes(multi_routes);
+        }
+
         let client_response_routes = ClientResponseRoutes {
             request_id,
             result: response_routes_result,
@@ -663,7 +872,70 @@ where
             .map_err(|_| IndexClientError::SendToAppServerFailed)
     }
 
+    pub async fn handle_response_topology(
+        &mut self,
+        session: u64,
+        server_public_key: PublicKey,
+        currency: Currency,
+        opt_response_topology: Option<ResponseTopology>,
+    ) -> Result<(), IndexClientError> {
+        let topology_cache = match &mut self.opt_topology_cache {
+            Some(topology_cache) => topology_cache,
+            None => return Ok(()),
+        };
+
+        let response_topology = match opt_response_topology {
+            Some(response_topology) => response_topology,
+            None => {
+                topology_cache.request_failed(session, &currency);
+                return Ok(());
+            }
+        };
+
+        match topology_cache.apply_response(session, &server_public_key, response_topology) {
+            Ok(topology_snapshot_report) => {
+                self.send_report_mutations(vec![IndexClientReportMutation::SetTopologySnapshot(
+                    topology_snapshot_report,
+                )])
+                .await
+            }
+            Err(e) => {
+                warn!(
+                    "handle_response_topology(): Invalid topology response for currency {}: {:?}",
+                    currency, e
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Advance time for the topology cache. Periodically reports the staleness of the
+    /// topology snapshots, and requests updates from the index server.
+    async fn topology_tick(&mut self) -> Result<(), IndexClientError> {
+        let topology_cache = match &mut self.opt_topology_cache {
+            Some(topology_cache) => topology_cache,
+            None => return Ok(()),
+        };
+
+        if !topology_cache.tick() {
+            return Ok(());
+        }
+
+        let mutations = topology_cache
+            .reports()
+            .into_iter()
+            .map(IndexClientReportMutation::SetTopologySnapshot)
+            .collect::<Vec<_>>();
+        if !mutations.is_empty() {
+            self.send_report_mutations(mutations).await?;
+        }
+
+        self.sync_topology()
+    }
+
     pub async fn handle_timer_tick(&mut self) -> Result<(), IndexClientError> {
+        self.topology_tick().await?;
+
         // Make sure that we are connected to any server:
         let server_connected: &mut ServerConnected<ISA> = match self.conn_status {
             ConnStatus::Empty(ref mut ticks_to_reconnect) => {
@@ -713,17 +985,25 @@ where
         }
 
         if let Ok(()) = control_sender
-            .send(SingleClientControl::SendMutations(mutations))
+            .send(SingleClientControl::SendMutations(mutations.clone()))
             .await
         {
             server_connected.opt_control_sender = Some(control_sender);
         }
 
+        // Our friends' currencies are also learned from the periodic keepalives, which cycle
+        // through all of our friends:
+        for mutation in &mutations {
+            if let IndexMutation::UpdateFriendCurrency(update_friend_currency) = mutation {
+                self.add_topology_currency(&update_friend_currency.currency);
+            }
+        }
+
         Ok(())
     }
 }
 
-pub async fn index_client_loop<ISA, FAS, TAS, ICS, TS, S>(
+pub async fn index_client_loop<ISA, FAS, TAS, ICS, TS, R, S>(
     from_app_server: FAS,
     to_app_server: TAS,
     index_client_config: IndexClientConfig<ISA>,
@@ -734,6 +1014,10 @@ pub async fn index_client_loop<ISA, FAS, TAS, ICS, TS, S>(
     backoff_ticks: usize,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
     timer_stream: TS,
+    routes_privacy: RoutesPrivacy,
+    opt_topology_sync_ticks: Option<usize>,
+    report_route_outcomes: bool,
+    rng: R,
     spawner: S,
 ) -> Result<(), IndexClientError>
 where
@@ -745,6 +1029,7 @@ where
         + Send
         + 'static,
     TS: Stream + Send + Unpin,
+    R: CryptoRandom + Clone,
     S: Spawn + Clone + Send + 'static,
 {
     let (event_sender, event_receiver) = mpsc::channel(0);
@@ -758,6 +1043,10 @@ where
         keepalive_ticks,
         backoff_ticks,
         db_client,
+        routes_privacy,
+        opt_topology_sync_ticks,
+        report_route_outcomes,
+        rng,
         spawner,
     );
 
@@ -796,6 +1085,21 @@ where
                     .handle_response_routes(request_id, response_routes_result)
                     .await?
             }
+            IndexClientEvent::ResponseTopology((
+                session,
+                server_public_key,
+                currency,
+                opt_response_topology,
+            )) => {
+                index_client
+                    .handle_response_topology(
+                        session,
+                        server_public_key,
+                        currency,
+                        opt_response_topology,
+                    )
+                    .await?
+            }
             IndexClientEvent::TimerTick => index_client.handle_timer_tick().await?,
         };
     }
@@ -22,13 +22,16 @@ extern crate common;
 
 mod client_session;
 mod index_client;
+mod routes_privacy;
 mod seq_friends;
 mod seq_map;
 mod single_client;
 mod spawn;
+mod topology_cache;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::index_client::{IndexClientConfig, IndexClientConfigMutation, IndexClientError};
+pub use self::routes_privacy::RoutesPrivacy;
 pub use self::spawn::{spawn_index_client, SpawnIndexClientError};
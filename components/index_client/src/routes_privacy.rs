@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crypto::rand::{CryptoRandom, RandGen};
+
+use proto::crypto::{PublicKey, Uid};
+use proto::index_server::messages::{Edge, MultiRoute, RequestRoutes};
+
+/// Maximum amount of nodes we remember for use as decoy destinations.
+const MAX_KNOWN_NODES: usize = 0x100;
+
+/// Privacy settings for routes requests sent to index servers.
+///
+/// Every request for routes reveals to the index server the source, destination, currency and
+/// capacity of a payment we might be about to make. To blind the server, the real request can
+/// be sent together with decoy requests for other destinations, and the requested capacity can
+/// be rounded. Responses are filtered locally, so that only routes that are valid for the real
+/// request are returned.
+///
+/// Tradeoff: With `num_decoys` decoys, the index server can guess the real destination with
+/// probability of at most `1 / (num_decoys + 1)`, while the index server has to handle
+/// `num_decoys + 1` requests for every real request. Rounding the capacity hides the exact
+/// amount of the payment, but might fail to find routes when the exact capacity would have
+/// succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutesPrivacy {
+    /// Amount of decoy requests sent together with every real request
+    pub num_decoys: usize,
+    /// Round the requested capacity up to the next power of two
+    pub round_capacity: bool,
+}
+
+impl RoutesPrivacy {
+    /// Send the exact request only. The index server learns everything about the request.
+    pub fn disabled() -> Self {
+        RoutesPrivacy {
+            num_decoys: 0,
+            round_capacity: false,
+        }
+    }
+
+    /// Amount of requests the index server has to handle for every real request
+    pub fn requests_per_query(&self) -> usize {
+        self.num_decoys.saturating_add(1)
+    }
+}
+
+/// Round a capacity up to the next power of two.
+/// The capacity is kept if rounding overflows.
+fn round_capacity(capacity: u128) -> u128 {
+    capacity.checked_next_power_of_two().unwrap_or(capacity)
+}
+
+/// The excluded edge of a decoy request.
+/// Same as the excluded edge of the real request, where the real destination is replaced by the
+/// decoy destination. This way the excluded edge does not reveal which request is the real one.
+fn decoy_exclude(
+    opt_exclude: &Option<Edge>,
+    real_destination: &PublicKey,
+    decoy_destination: &PublicKey,
+) -> Option<Edge> {
+    let replace = |public_key: &PublicKey| {
+        if public_key == real_destination {
+            decoy_destination.clone()
+        } else {
+            public_key.clone()
+        }
+    };
+    opt_exclude.as_ref().map(|edge| Edge {
+        from_public_key: replace(&edge.from_public_key),
+        to_public_key: replace(&edge.to_public_key),
+    })
+}
+
+/// Blinds routes requests according to the configured RoutesPrivacy
+pub struct RoutesBlinder<R> {
+    routes_privacy: RoutesPrivacy,
+    /// Nodes we know about, used as decoy destinations
+    known_nodes: VecDeque<PublicKey>,
+    rng: R,
+}
+
+impl<R> RoutesBlinder<R>
+where
+    R: CryptoRandom,
+{
+    pub fn new(routes_privacy: RoutesPrivacy, rng: R) -> Self {
+        RoutesBlinder {
+            routes_privacy,
+            known_nodes: VecDeque::new(),
+            rng,
+        }
+    }
+
+    /// Remember a node, to be used later as a decoy destination
+    pub fn add_known_node(&mut self, public_key: &PublicKey) {
+        if self.known_nodes.contains(public_key) {
+            return;
+        }
+        if self.known_nodes.len() >= MAX_KNOWN_NODES {
+            let _ = self.known_nodes.pop_front();
+        }
+        self.known_nodes.push_back(public_key.clone());
+    }
+
+    /// Remember all the nodes that show up in received routes
+    pub fn add_known_routes(&mut self, multi_routes: &[MultiRoute]) {
+        for multi_route in multi_routes {
+            for route in &multi_route.routes {
+                for public_key in &route.route.public_keys {
+                    self.add_known_node(public_key);
+                }
+            }
+        }
+    }
+
+    /// Create the requests to be sent to the index server instead of `request_routes`.
+    /// The real request keeps its request_id, and shows up in a random position among the decoy
+    /// requests.
+    ///
+    /// Less decoys than configured might be created if not enough nodes are known.
+    pub fn blind(&mut self, request_routes: &RequestRoutes) -> Vec<RequestRoutes> {
+        let capacity = if self.routes_privacy.round_capacity {
+            round_capacity(request_routes.capacity)
+        } else {
+            request_routes.capacity
+        };
+
+        let mut candidates: Vec<&PublicKey> = self
+            .known_nodes
+            .iter()
+            .filter(|public_key| {
+                **public_key != request_routes.source && **public_key != request_routes.destination
+            })
+            .collect();
+        candidates.shuffle(&mut self.rng);
+        let decoy_destinations: Vec<PublicKey> = candidates
+            .into_iter()
+            .take(self.routes_privacy.num_decoys)
+            .cloned()
+            .collect();
+
+        let mut requests = Vec::new();
+        for destination in decoy_destinations {
+            let opt_exclude = decoy_exclude(
+                &request_routes.opt_exclude,
+                &request_routes.destination,
+                &destination,
+            );
+            requests.push(RequestRoutes {
+                request_id: Uid::rand_gen(&mut self.rng),
+                currency: request_routes.currency.clone(),
+                capacity,
+                source: request_routes.source.clone(),
+                destination,
+                opt_exclude,
+            });
+        }
+
+        let mut real_request = request_routes.clone();
+        real_request.capacity = capacity;
+        let real_index = self.rng.gen_range(0, requests.len() + 1);
+        requests.insert(real_index, real_request);
+
+        requests
+    }
+}
+
+/// Keep only multi routes that are valid for `request_routes`:
+/// Every route must go from the source to the destination without using the excluded edge, and
+/// the total capacity of the multi route must be at least the requested capacity.
+pub fn filter_multi_routes(
+    request_routes: &RequestRoutes,
+    multi_routes: Vec<MultiRoute>,
+) -> Vec<MultiRoute> {
+    let is_valid_route = |public_keys: &[PublicKey]| {
+        if public_keys.first() != Some(&request_routes.source)
+            || public_keys.last() != Some(&request_routes.destination)
+        {
+            return false;
+        }
+        if let Some(edge) = &request_routes.opt_exclude {
+            if public_keys
+                .windows(2)
+                .any(|pair| pair[0] == edge.from_public_key && pair[1] == edge.to_public_key)
+            {
+                return false;
+            }
+        }
+        true
+    };
+
+    multi_routes
+        .into_iter()
+        .filter(|multi_route| {
+            let mut total_capacity = 0u128;
+            for route in &multi_route.routes {
+                if !is_valid_route(&route.route.public_keys) {
+                    return false;
+                }
+                total_capacity = total_capacity.saturating_add(route.capacity);
+            }
+            !multi_route.routes.is_empty() && total_capacity >= request_routes.capacity
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::test_utils::DummyRandom;
+
+    use proto::funder::messages::{Currency, FriendsRoute, Rate};
+    use proto::index_server::messages::{Edge, RouteCapacityRate};
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    fn create_request_routes(capacity: u128) -> RequestRoutes {
+        RequestRoutes {
+            request_id: Uid::from(&[3; Uid::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            capacity,
+            source: pk(0),
+            destination: pk(1),
+            opt_exclude: None,
+        }
+    }
+
+    fn create_multi_route(routes: &[(&[u8], u128)]) -> MultiRoute {
+        MultiRoute {
+            routes: routes
+                .iter()
+                .map(|(route, capacity)| RouteCapacityRate {
+                    route: FriendsRoute {
+                        public_keys: route.iter().map(|i| pk(*i)).collect(),
+                    },
+                    capacity: *capacity,
+                    rate: Rate::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_round_capacity() {
+        assert_eq!(round_capacity(0), 1);
+        assert_eq!(round_capacity(1), 1);
+        assert_eq!(round_capacity(5), 8);
+        assert_eq!(round_capacity(8), 8);
+        assert_eq!(round_capacity(u128::max_value()), u128::max_value());
+    }
+
+    #[test]
+    fn test_blind_disabled() {
+        let rng = DummyRandom::new(&[1u8]);
+        let mut routes_blinder = RoutesBlinder::new(RoutesPrivacy::disabled(), rng);
+        for i in 2..10 {
+            routes_blinder.add_known_node(&pk(i));
+        }
+        let request_routes = create_request_routes(100);
+        assert_eq!(routes_blinder.blind(&request_routes), vec![request_routes]);
+    }
+
+    #[test]
+    fn test_blind_decoys() {
+        let routes_privacy = RoutesPrivacy {
+            num_decoys: 3,
+            round_capacity: true,
+        };
+        let rng = DummyRandom::new(&[2u8]);
+        let mut routes_blinder = RoutesBlinder::new(routes_privacy, rng);
+        let mut request_routes = create_request_routes(100);
+        // An excluded edge next to the destination, like the ones used for finding loops:
+        request_routes.opt_exclude = Some(Edge {
+            from_public_key: pk(0),
+            to_public_key: pk(1),
+        });
+
+        // No known nodes, so no decoys can be created:
+        let requests = routes_blinder.blind(&request_routes);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].capacity, 128);
+
+        // Source and destination are never used as decoys:
+        for i in 0..8 {
+            routes_blinder.add_known_node(&pk(i));
+        }
+
+        // Count the positions of the real request, to make sure the server can not tell which
+        // request is the real one:
+        let mut real_positions = [0usize; 4];
+        for _ in 0..400 {
+            let requests = routes_blinder.blind(&request_routes);
+            assert_eq!(requests.len(), routes_privacy.requests_per_query());
+            let mut num_real = 0;
+            for (index, request) in requests.iter().enumerate() {
+                assert_eq!(request.capacity, 128);
+                assert_eq!(request.source, pk(0));
+                assert_eq!(request.currency, request_routes.currency);
+                // The excluded edge of every request has the same shape:
+                assert_eq!(
+                    request.opt_exclude,
+                    Some(Edge {
+                        from_public_key: pk(0),
+                        to_public_key: request.destination.clone(),
+                    })
+                );
+                if request.request_id == request_routes.request_id {
+                    assert_eq!(request.destination, pk(1));
+                    real_positions[index] += 1;
+                    num_real += 1;
+                } else {
+                    assert!(request.destination != pk(0) && request.destination != pk(1));
+                }
+            }
+            assert_eq!(num_real, 1);
+        }
+        for count in &real_positions {
+            assert!(*count > 50);
+        }
+    }
+
+    #[test]
+    fn test_add_known_routes() {
+        let rng = DummyRandom::new(&[3u8]);
+        let mut routes_blinder = RoutesBlinder::new(RoutesPrivacy::disabled(), rng);
+        routes_blinder.add_known_routes(&[create_multi_route(&[(&[0, 4, 5, 1], 10)])]);
+        routes_blinder.add_known_routes(&[create_multi_route(&[(&[0, 5, 1], 10)])]);
+        assert_eq!(routes_blinder.known_nodes.len(), 4);
+
+        for i in 0..(MAX_KNOWN_NODES * 2) {
+            routes_blinder.add_known_node(&PublicKey::from(&[(i % 256) as u8; PublicKey::len()]));
+        }
+        assert!(routes_blinder.known_nodes.len() <= MAX_KNOWN_NODES);
+    }
+
+    #[test]
+    fn test_filter_multi_routes() {
+        let mut request_routes = create_request_routes(100);
+        request_routes.opt_exclude = Some(Edge {
+            from_public_key: pk(2),
+            to_public_key: pk(3),
+        });
+
+        let valid1 = create_multi_route(&[(&[0, 1], 100)]);
+        let valid2 = create_multi_route(&[(&[0, 3, 2, 1], 60), (&[0, 4, 1], 40)]);
+        let multi_routes = vec![
+            valid1.clone(),
+            // Not enough capacity:
+            create_multi_route(&[(&[0, 4, 1], 99)]),
+            // Wrong destination:
+            create_multi_route(&[(&[0, 4, 5], 100)]),
+            // Wrong source:
+            create_multi_route(&[(&[6, 4, 1], 100)]),
+            // Excluded edge:
+            create_multi_route(&[(&[0, 2, 3, 1], 100)]),
+            valid2.clone(),
+            // Empty multi route:
+            create_multi_route(&[]),
+        ];
+
+        assert_eq!(
+            filter_multi_routes(&request_routes, multi_routes),
+            vec![valid1, valid2]
+        );
+    }
+}
@@ -1,9 +1,11 @@
+use std::collections::HashMap;
+
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnError, SpawnExt};
 use futures::{SinkExt, StreamExt};
 
 use proto::crypto::PublicKey;
-use proto::funder::messages::Currency;
+use proto::funder::messages::{Currency, CurrencyPair, ExchangeRate};
 use proto::index_client::messages::{FriendInfo, IndexMutation, UpdateFriendCurrency};
 
 use crate::seq_map::SeqMap;
@@ -14,6 +16,7 @@ pub enum SeqFriendsRequest {
     Mutate(IndexMutation, oneshot::Sender<()>),
     ResetCountdown(oneshot::Sender<()>),
     NextUpdate(oneshot::Sender<Option<(usize, UpdateFriendCurrency)>>),
+    ExchangeRates(oneshot::Sender<Vec<ExchangeRate>>),
 }
 
 #[derive(Debug)]
@@ -27,7 +30,11 @@ pub struct SeqFriendsClient {
     requests_sender: mpsc::Sender<SeqFriendsRequest>,
 }
 
-fn apply_index_mutation(seq_friends: &mut SeqFriends, index_mutation: &IndexMutation) {
+fn apply_index_mutation(
+    seq_friends: &mut SeqFriends,
+    exchange_rates: &mut HashMap<CurrencyPair, ExchangeRate>,
+    index_mutation: &IndexMutation,
+) {
     match index_mutation {
         IndexMutation::UpdateFriendCurrency(update_friend_currency) => {
             let friend_info = FriendInfo {
@@ -48,17 +55,25 @@ fn apply_index_mutation(seq_friends: &mut SeqFriends, index_mutation: &IndexMuta
                 remove_friend_currency.currency.clone(),
             ));
         }
+        IndexMutation::SetExchangeRate(exchange_rate) => {
+            let _ =
+                exchange_rates.insert(exchange_rate.currency_pair.clone(), exchange_rate.clone());
+        }
+        IndexMutation::RemoveExchangeRate(currency_pair) => {
+            let _ = exchange_rates.remove(currency_pair);
+        }
     }
 }
 
 async fn seq_friends_loop(
     mut seq_friends: SeqFriends,
+    mut exchange_rates: HashMap<CurrencyPair, ExchangeRate>,
     mut requests_receiver: mpsc::Receiver<SeqFriendsRequest>,
 ) {
     while let Some(request) = requests_receiver.next().await {
         match request {
             SeqFriendsRequest::Mutate(index_mutation, response_sender) => {
-                apply_index_mutation(&mut seq_friends, &index_mutation);
+                apply_index_mutation(&mut seq_friends, &mut exchange_rates, &index_mutation);
                 let _ = response_sender.send(());
             }
             SeqFriendsRequest::ResetCountdown(response_sender) => {
@@ -83,6 +98,9 @@ async fn seq_friends_loop(
                 );
                 let _ = response_sender.send(update_friend);
             }
+            SeqFriendsRequest::ExchangeRates(response_sender) => {
+                let _ = response_sender.send(exchange_rates.values().cloned().collect());
+            }
         }
     }
 }
@@ -132,17 +150,30 @@ impl SeqFriendsClient {
             .await
             .map_err(|_| SeqFriendsClientError::RecvResponseError)?)
     }
+
+    pub async fn exchange_rates(&mut self) -> Result<Vec<ExchangeRate>, SeqFriendsClientError> {
+        let (sender, receiver) = oneshot::channel();
+        let request = SeqFriendsRequest::ExchangeRates(sender);
+        self.requests_sender
+            .send(request)
+            .await
+            .map_err(|_| SeqFriendsClientError::SendRequestError)?;
+        Ok(receiver
+            .await
+            .map_err(|_| SeqFriendsClientError::RecvResponseError)?)
+    }
 }
 
 pub fn create_seq_friends_service<S>(
     seq_friends: SeqFriends,
+    exchange_rates: HashMap<CurrencyPair, ExchangeRate>,
     spawner: S,
 ) -> Result<SeqFriendsClient, SpawnError>
 where
     S: Spawn,
 {
     let (requests_sender, requests_receiver) = mpsc::channel(0);
-    let loop_fut = seq_friends_loop(seq_friends, requests_receiver);
+    let loop_fut = seq_friends_loop(seq_friends, exchange_rates, requests_receiver);
     spawner.spawn(loop_fut)?;
 
     Ok(SeqFriendsClient::new(requests_sender))
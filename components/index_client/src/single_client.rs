@@ -11,7 +11,7 @@ use proto::crypto::{HashResult, PublicKey, RandValue, Signature, Uid};
 
 use proto::index_server::messages::{
     IndexClientToServer, IndexMutation, IndexServerToClient, MultiRoute, MutationsUpdate,
-    RequestRoutes, ResponseRoutes,
+    RequestRoutes, RequestTopology, ResponseRoutes, ResponseTopology, RouteOutcome,
 };
 
 use signature::signature_buff::create_mutations_update_signature_buff;
@@ -26,6 +26,8 @@ pub type ServerConn = ConnPair<IndexClientToServer, IndexServerToClient>;
 pub enum SingleClientControl {
     RequestRoutes((RequestRoutes, oneshot::Sender<Vec<MultiRoute>>)),
     SendMutations(Vec<IndexMutation>),
+    RequestTopology((RequestTopology, oneshot::Sender<ResponseTopology>)),
+    ReportRouteOutcome(RouteOutcome),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -58,6 +60,8 @@ struct SingleClient<TS, R> {
     server_time_hash: HashResult,
     /// Unanswered requests, waiting for a response from the server
     open_requests: HashMap<Uid, oneshot::Sender<Vec<MultiRoute>>>,
+    /// Unanswered topology requests, waiting for a response from the server
+    open_topology_requests: HashMap<Uid, oneshot::Sender<ResponseTopology>>,
 }
 
 impl<TS, R> SingleClient<TS, R>
@@ -82,6 +86,7 @@ where
             counter: 0,
             server_time_hash,
             open_requests: HashMap::new(),
+            open_topology_requests: HashMap::new(),
         }
     }
 
@@ -114,6 +119,45 @@ where
                     );
                 }
             }
+            IndexServerToClient::ResponseTopology(response_topology) => {
+                let request_id = response_topology.request_id.clone();
+                let request_sender = match self.open_topology_requests.remove(&request_id) {
+                    Some(request_sender) => request_sender,
+                    None => {
+                        warn!(
+                            "Received a topology response for unrecognized request_id: {:?}",
+                            &request_id
+                        );
+                        return Ok(());
+                    }
+                };
+                if request_sender.send(response_topology).is_err() {
+                    warn!(
+                        "Failed to return topology response for request_id: {:?} ",
+                        &request_id
+                    );
+                }
+            }
+            IndexServerToClient::RequestRejected(request_rejected) => {
+                warn!(
+                    "Request {:?} was rejected by the server: {:?}",
+                    &request_rejected.request_id, request_rejected.reason
+                );
+                // Dropping the response sender reports a failure to the requester:
+                let _ = self.open_requests.remove(&request_rejected.request_id);
+                let _ = self
+                    .open_topology_requests
+                    .remove(&request_rejected.request_id);
+            }
+            IndexServerToClient::MutationsRejected(mutations_rejected) => {
+                // The state of our friends is resent sequentially together with later mutations,
+                // and edges of removed friend currencies eventually age out on the server.
+                // Exchange rates are only sent again with our full state, when we reconnect.
+                warn!(
+                    "Mutations update (counter: {}) was rejected by the server: {:?}",
+                    mutations_rejected.counter, mutations_rejected.reason
+                );
+            }
         }
         Ok(())
     }
@@ -169,6 +213,23 @@ where
                     .await
                     .map_err(|_| SingleClientError::SendToServerError)?;
             }
+            SingleClientControl::RequestTopology((request_topology, response_sender)) => {
+                self.open_topology_requests
+                    .insert(request_topology.request_id.clone(), response_sender);
+
+                let to_server_message = IndexClientToServer::RequestTopology(request_topology);
+                self.to_server
+                    .send(to_server_message)
+                    .await
+                    .map_err(|_| SingleClientError::SendToServerError)?;
+            }
+            SingleClientControl::ReportRouteOutcome(route_outcome) => {
+                let to_server_message = IndexClientToServer::ReportRouteOutcome(route_outcome);
+                self.to_server
+                    .send(to_server_message)
+                    .await
+                    .map_err(|_| SingleClientError::SendToServerError)?;
+            }
         }
         Ok(())
     }
@@ -258,7 +319,8 @@ mod tests {
     use futures::{FutureExt, TryFutureExt};
 
     use proto::crypto::PrivateKey;
-    use proto::funder::messages::Currency;
+    use proto::funder::messages::{Currency, FriendsRoute};
+    use proto::index_server::messages::{HopOutcome, RejectReason, RequestRejected};
 
     use signature::verify::verify_mutations_update;
 
@@ -386,6 +448,37 @@ mod tests {
         let multi_routes = response_receiver.await.unwrap();
         assert_eq!(multi_routes, vec![]);
 
+        // Request routes again, this time the server rejects the request:
+        let mut request_routes = request_routes;
+        request_routes.request_id = Uid::from(&[4; Uid::len()]);
+        let (response_sender, response_receiver) = oneshot::channel();
+        control_sender
+            .send(SingleClientControl::RequestRoutes((
+                request_routes.clone(),
+                response_sender,
+            )))
+            .await
+            .unwrap();
+
+        let index_client_to_server = server_receiver.next().await.unwrap();
+        match index_client_to_server {
+            IndexClientToServer::RequestRoutes(sent_request_routes) => {
+                assert_eq!(request_routes, sent_request_routes);
+            }
+            _ => unreachable!(),
+        };
+
+        server_sender
+            .send(IndexServerToClient::RequestRejected(RequestRejected {
+                request_id: Uid::from(&[4; Uid::len()]),
+                reason: RejectReason::RateLimited,
+            }))
+            .await
+            .unwrap();
+
+        // The requester is notified of the failure:
+        assert!(response_receiver.await.is_err());
+
         for iter in 0..3 {
             // Counter should increment every time
             // Send mutations:
@@ -412,6 +505,72 @@ mod tests {
                 _ => unreachable!(),
             };
         }
+
+        // Request topology:
+        let request_topology = RequestTopology {
+            request_id: Uid::from(&[4; Uid::len()]),
+            currency: currency.clone(),
+            is_incremental: false,
+        };
+        let (response_sender, response_receiver) = oneshot::channel();
+        control_sender
+            .send(SingleClientControl::RequestTopology((
+                request_topology.clone(),
+                response_sender,
+            )))
+            .await
+            .unwrap();
+
+        match server_receiver.next().await.unwrap() {
+            IndexClientToServer::RequestTopology(sent_request_topology) => {
+                assert_eq!(request_topology, sent_request_topology);
+            }
+            _ => unreachable!(),
+        };
+
+        let response_topology = ResponseTopology {
+            request_id: Uid::from(&[4; Uid::len()]),
+            currency,
+            is_full: true,
+            updated_edges: vec![],
+            removed_edges: vec![],
+            topology_hash: HashResult::from(&[5; HashResult::len()]),
+            signature: Signature::from(&[6; Signature::len()]),
+        };
+        server_sender
+            .send(IndexServerToClient::ResponseTopology(
+                response_topology.clone(),
+            ))
+            .await
+            .unwrap();
+
+        // Client receives the topology response:
+        assert_eq!(response_receiver.await.unwrap(), response_topology);
+
+        // Report a route outcome:
+        let route_outcome = RouteOutcome {
+            currency: response_topology.currency,
+            route: FriendsRoute {
+                public_keys: vec![
+                    PublicKey::from(&[0xaa; PublicKey::len()]),
+                    PublicKey::from(&[0xbb; PublicKey::len()]),
+                ],
+            },
+            hop_outcomes: vec![HopOutcome::Success],
+        };
+        control_sender
+            .send(SingleClientControl::ReportRouteOutcome(
+                route_outcome.clone(),
+            ))
+            .await
+            .unwrap();
+
+        match server_receiver.next().await.unwrap() {
+            IndexClientToServer::ReportRouteOutcome(sent_route_outcome) => {
+                assert_eq!(route_outcome, sent_route_outcome);
+            }
+            _ => unreachable!(),
+        };
     }
 
     #[test]
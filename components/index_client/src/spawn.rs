@@ -24,6 +24,7 @@ use crate::client_session::IndexClientSession;
 use crate::index_client::{
     index_client_loop, IndexClientConfig, IndexClientConfigMutation, IndexClientError,
 };
+use crate::routes_privacy::RoutesPrivacy;
 use crate::seq_friends::create_seq_friends_service;
 use crate::seq_map::SeqMap;
 use crate::single_client::ServerConn;
@@ -119,6 +120,9 @@ pub async fn spawn_index_client<ISA, C, R, S>(
     max_open_index_client_requests: usize,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    routes_privacy: RoutesPrivacy,
+    opt_topology_sync_ticks: Option<usize>,
+    report_route_outcomes: bool,
     index_connector: C,
     rng: R,
     spawner: S,
@@ -138,8 +142,12 @@ where
         .map_err(|_| SpawnIndexClientError::RequestTimerStreamError)?;
 
     let seq_friends = SeqMap::new(index_client_state.friends);
-    let seq_friends_client = create_seq_friends_service(seq_friends, spawner.clone())
-        .map_err(|_| SpawnIndexClientError::SpawnError)?;
+    let seq_friends_client = create_seq_friends_service(
+        seq_friends,
+        index_client_state.exchange_rates,
+        spawner.clone(),
+    )
+    .map_err(|_| SpawnIndexClientError::SpawnError)?;
 
     let serde_client_connector = SerdeClientConnector::new(index_connector, spawner.clone());
 
@@ -147,7 +155,7 @@ where
         serde_client_connector,
         local_public_key,
         identity_client,
-        rng,
+        rng.clone(),
         spawner.clone(),
     );
 
@@ -162,6 +170,10 @@ where
         backoff_ticks,
         database_client,
         timer_stream,
+        routes_privacy,
+        opt_topology_sync_ticks,
+        report_route_outcomes,
+        rng,
         spawner.clone(),
     );
 
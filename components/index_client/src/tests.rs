@@ -7,19 +7,26 @@ use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
 use common::dummy_connector::{ConnRequest, DummyConnector};
 
+use proto::consts::MAX_INDEX_MUTATIONS_IN_UPDATE;
 use proto::crypto::{PublicKey, Uid};
 
-use proto::funder::messages::{Currency, Rate};
+use crypto::test_utils::DummyRandom;
+
+use proto::funder::messages::{Currency, CurrencyPair, ExchangeRate, FriendsRoute, Rate};
 use proto::index_client::messages::{
-    AppServerToIndexClient, IndexClientReportMutation, IndexClientRequest, IndexClientToAppServer,
-    IndexMutation, RequestRoutes, ResponseRoutesResult, UpdateFriendCurrency,
+    AppServerToIndexClient, HopOutcome, IndexClientReportMutation, IndexClientRequest,
+    IndexClientToAppServer, IndexMutation, RequestRoutes, ResponseRoutesResult, RouteOutcome,
+    UpdateFriendCurrency,
+};
+use proto::index_server::messages::{
+    IndexServerAddress, MultiRoute, NamedIndexServerAddress, RouteCapacityRate,
 };
-use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
 
 use database::{DatabaseClient, DatabaseRequest};
 
 use crate::client_session::SessionHandle;
 use crate::index_client::{index_client_loop, IndexClientConfig, IndexClientConfigMutation};
+use crate::routes_privacy::RoutesPrivacy;
 use crate::seq_friends::{SeqFriendsClient, SeqFriendsRequest};
 use crate::single_client::{SingleClientControl, SingleClientError};
 
@@ -43,6 +50,17 @@ struct IndexClientControl<ISA> {
 
 /// Create a basic IndexClientControl, used for testing
 fn basic_index_client<S>(spawner: S) -> IndexClientControl<u32>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    basic_index_client_with_privacy(spawner, RoutesPrivacy::disabled())
+}
+
+/// Create a basic IndexClientControl with the given routes privacy settings
+fn basic_index_client_with_privacy<S>(
+    spawner: S,
+    routes_privacy: RoutesPrivacy,
+) -> IndexClientControl<u32>
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -84,6 +102,10 @@ where
         backoff_ticks,
         db_client,
         timer_stream,
+        routes_privacy,
+        None,
+        true,
+        DummyRandom::new(&[0x13, 0x37]),
         spawner.clone(),
     )
     .map_err(|e| error!("index_client_loop() error: {:?}", e))
@@ -169,6 +191,14 @@ where
             _ => unreachable!(),
         };
 
+        // The send_full_state() task then asks for our exchange rates (We have none):
+        match self.seq_friends_receiver.next().await.unwrap() {
+            SeqFriendsRequest::ExchangeRates(response_sender) => {
+                response_sender.send(Vec::new()).unwrap();
+            }
+            _ => unreachable!(),
+        };
+
         // IndexClient will send to the server the information about the 0xaa friend:
         match control_receiver.next().await.unwrap() {
             SingleClientControl::SendMutations(_) => {}
@@ -472,6 +502,314 @@ fn test_index_client_loop_request_routes_basic() {
     ));
 }
 
+async fn task_index_client_loop_report_route_outcome<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PublicKey::len()]),
+        address: 0x1337,
+    };
+    let (mut control_receiver, _close_sender) = icc.expect_server_connection(index_server).await;
+
+    let route_outcome = RouteOutcome {
+        currency,
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xee; PublicKey::len()]),
+                PublicKey::from(&[0xff; PublicKey::len()]),
+            ],
+        },
+        hop_outcomes: vec![HopOutcome::Failure],
+    };
+    icc.app_server_sender
+        .send(AppServerToIndexClient::ReportRouteOutcome(
+            route_outcome.clone(),
+        ))
+        .await
+        .unwrap();
+
+    // IndexClient forwards the route outcome to the server:
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::ReportRouteOutcome(route_outcome0) => {
+            assert_eq!(route_outcome0, route_outcome);
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_report_route_outcome() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_report_route_outcome(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_index_client_loop_send_full_state_batches<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    // More friend currencies than the amount of updates an index server allows in a burst
+    // (By default):
+    const NUM_FRIENDS: usize = 100;
+
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+    let mut icc = basic_index_client(spawner.clone());
+
+    // Wait for a connection request:
+    let session_conn_request = icc.session_receiver.next().await.unwrap();
+    let (control_sender, mut control_receiver) = mpsc::channel(0);
+    let (_close_sender, close_receiver) = oneshot::channel();
+    session_conn_request.reply(Some((control_sender, close_receiver)));
+
+    icc.expect_set_connected_server(Some(PublicKey::from(&[0x37; PublicKey::len()])))
+        .await;
+
+    match icc.seq_friends_receiver.next().await.unwrap() {
+        SeqFriendsRequest::ResetCountdown(response_sender) => {
+            response_sender.send(()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    // Feeding the send_full_state() task with the states of all friends:
+    for i in 0..NUM_FRIENDS {
+        match icc.seq_friends_receiver.next().await.unwrap() {
+            SeqFriendsRequest::NextUpdate(response_sender) => {
+                let update_friend = UpdateFriendCurrency {
+                    public_key: PublicKey::from(&[i as u8; PublicKey::len()]),
+                    currency: currency.clone(),
+                    recv_capacity: 50,
+                    rate: Rate { mul: 0, add: 1 },
+                };
+                response_sender
+                    .send(Some((NUM_FRIENDS - 1 - i, update_friend)))
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    let exchange_rate = ExchangeRate {
+        currency_pair: CurrencyPair {
+            src_currency: currency.clone(),
+            dest_currency: Currency::try_from("FDT".to_owned()).unwrap(),
+        },
+        mul: 2,
+        valid_from: 0,
+        valid_until: u64::max_value(),
+    };
+    match icc.seq_friends_receiver.next().await.unwrap() {
+        SeqFriendsRequest::ExchangeRates(response_sender) => {
+            response_sender.send(vec![exchange_rate.clone()]).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    // All the mutations are sent in a few large batches:
+    let mut mutations = Vec::new();
+    let mut num_batches = 0;
+    while mutations.len() < NUM_FRIENDS + 1 {
+        match control_receiver.next().await.unwrap() {
+            SingleClientControl::SendMutations(batch) => {
+                assert!(batch.len() <= MAX_INDEX_MUTATIONS_IN_UPDATE);
+                mutations.extend(batch);
+                num_batches += 1;
+            }
+            _ => unreachable!(),
+        };
+    }
+    assert_eq!(mutations.len(), NUM_FRIENDS + 1);
+    assert_eq!(
+        num_batches,
+        (NUM_FRIENDS + 1 + MAX_INDEX_MUTATIONS_IN_UPDATE - 1) / MAX_INDEX_MUTATIONS_IN_UPDATE
+    );
+    for (i, mutation) in mutations[..NUM_FRIENDS].iter().enumerate() {
+        match mutation {
+            IndexMutation::UpdateFriendCurrency(update_friend) => assert_eq!(
+                update_friend.public_key,
+                PublicKey::from(&[i as u8; PublicKey::len()])
+            ),
+            _ => unreachable!(),
+        }
+    }
+    assert_eq!(
+        mutations[NUM_FRIENDS],
+        IndexMutation::SetExchangeRate(exchange_rate)
+    );
+}
+
+#[test]
+fn test_index_client_loop_send_full_state_batches() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_send_full_state_batches(
+        thread_pool.clone(),
+    ));
+}
+
+fn create_multi_route(route: &[u8], capacity: u128) -> MultiRoute {
+    MultiRoute {
+        routes: vec![RouteCapacityRate {
+            route: FriendsRoute {
+                public_keys: route
+                    .iter()
+                    .map(|i| PublicKey::from(&[*i; PublicKey::len()]))
+                    .collect(),
+            },
+            capacity,
+            rate: Rate::new(),
+        }],
+    }
+}
+
+impl<ISA> IndexClientControl<ISA>
+where
+    ISA: std::cmp::Eq + std::fmt::Debug + Clone,
+{
+    /// Request routes from IndexClient (From AppServer), and wait for the empty report
+    async fn send_request_routes(&mut self, app_request_id: Uid, request_routes: RequestRoutes) {
+        let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+            app_request_id.clone(),
+            IndexClientRequest::RequestRoutes(request_routes),
+        ));
+        self.app_server_sender
+            .send(app_server_to_index_client)
+            .await
+            .unwrap();
+
+        match self.app_server_receiver.next().await.unwrap() {
+            IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+                assert_eq!(ic_report_mutations.opt_app_request_id, Some(app_request_id));
+                assert!(ic_report_mutations.mutations.is_empty());
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    /// Expect routes to be returned to the AppServer
+    async fn expect_response_routes(&mut self, request_id: Uid) -> Vec<MultiRoute> {
+        match self.app_server_receiver.next().await.unwrap() {
+            IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
+                assert_eq!(client_response_routes.request_id, request_id);
+                match client_response_routes.result {
+                    ResponseRoutesResult::Success(multi_routes) => multi_routes,
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+async fn task_index_client_loop_request_routes_privacy<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+    let routes_privacy = RoutesPrivacy {
+        num_decoys: 2,
+        round_capacity: true,
+    };
+    let mut icc = basic_index_client_with_privacy(spawner.clone(), routes_privacy);
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PublicKey::len()]),
+        address: 0x1337,
+    };
+    let (mut control_receiver, _close_sender) = icc.expect_server_connection(index_server).await;
+
+    let mut request_routes = RequestRoutes {
+        request_id: Uid::from(&[3; Uid::len()]),
+        currency: currency.clone(),
+        capacity: 250,
+        source: PublicKey::from(&[0xee; PublicKey::len()]),
+        destination: PublicKey::from(&[0xff; PublicKey::len()]),
+        opt_exclude: None,
+    };
+
+    // We don't know any nodes yet, so no decoys can be sent:
+    icc.send_request_routes(Uid::from(&[50; Uid::len()]), request_routes.clone())
+        .await;
+    match control_receiver.next().await.unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0.request_id, request_routes.request_id);
+            // Capacity was rounded:
+            assert_eq!(request_routes0.capacity, 256);
+            response_sender
+                .send(vec![create_multi_route(&[0xee, 0xaa, 0xbb, 0xff], 256)])
+                .unwrap();
+        }
+        _ => unreachable!(),
+    };
+    let multi_routes = icc
+        .expect_response_routes(request_routes.request_id.clone())
+        .await;
+    assert_eq!(
+        multi_routes,
+        vec![create_multi_route(&[0xee, 0xaa, 0xbb, 0xff], 256)]
+    );
+
+    // Now the nodes 0xaa and 0xbb are known, and can be used as decoys:
+    request_routes.request_id = Uid::from(&[4; Uid::len()]);
+    icc.send_request_routes(Uid::from(&[51; Uid::len()]), request_routes.clone())
+        .await;
+
+    let mut num_real = 0;
+    let mut decoy_destinations = Vec::new();
+    for _ in 0..routes_privacy.requests_per_query() {
+        match control_receiver.next().await.unwrap() {
+            SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+                assert_eq!(request_routes0.capacity, 256);
+                assert_eq!(request_routes0.source, request_routes.source);
+                if request_routes0.request_id == request_routes.request_id {
+                    num_real += 1;
+                    // Return one valid multi route, and one multi route to the wrong destination:
+                    response_sender
+                        .send(vec![
+                            create_multi_route(&[0xee, 0xaa, 0xcc], 256),
+                            create_multi_route(&[0xee, 0xbb, 0xff], 256),
+                        ])
+                        .unwrap();
+                } else {
+                    decoy_destinations.push(request_routes0.destination.clone());
+                    response_sender
+                        .send(vec![create_multi_route(&[0xee, 0xaa], 256)])
+                        .unwrap();
+                }
+            }
+            _ => unreachable!(),
+        };
+    }
+    assert_eq!(num_real, 1);
+    decoy_destinations.sort();
+    assert_eq!(
+        decoy_destinations,
+        vec![
+            PublicKey::from(&[0xaa; PublicKey::len()]),
+            PublicKey::from(&[0xbb; PublicKey::len()])
+        ]
+    );
+
+    // Only valid routes for the real request are returned:
+    let multi_routes = icc
+        .expect_response_routes(request_routes.request_id.clone())
+        .await;
+    assert_eq!(
+        multi_routes,
+        vec![create_multi_route(&[0xee, 0xbb, 0xff], 256)]
+    );
+}
+
+#[test]
+fn test_index_client_loop_request_routes_privacy() {
+    let thread_pool = ThreadPool::new().unwrap();
+    block_on(task_index_client_loop_request_routes_privacy(
+        thread_pool.clone(),
+    ));
+}
+
 async fn task_index_client_loop_connecting_state<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
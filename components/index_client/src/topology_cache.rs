@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+
+use crypto::rand::{CryptoRandom, RandGen};
+
+use proto::crypto::{PublicKey, Uid};
+use proto::funder::messages::Currency;
+use proto::index_client::messages::TopologySnapshotReport;
+use proto::index_server::messages::{
+    MultiRoute, RequestRoutes, RequestTopology, ResponseTopology, TopologyEdge,
+};
+
+use common::int_convert::usize_to_u64;
+
+use signature::signature_buff::topology_hash;
+use signature::verify::verify_response_topology;
+
+use index_server::topology_multi_routes;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TopologyError {
+    /// The response belongs to a previous connection to an index server
+    StaleSession,
+    InvalidSignature,
+    /// An incremental response was received, but we have no topology to apply it to
+    MissingBase,
+    /// The topology we obtained does not match the topology signed by the index server
+    HashMismatch,
+}
+
+#[derive(Debug)]
+struct TopologySnapshot {
+    /// The index server that signed this snapshot
+    server_public_key: PublicKey,
+    edges: HashMap<(PublicKey, PublicKey), TopologyEdge>,
+    ticks_since_sync: u64,
+    /// The session in which the snapshot was last synchronized.
+    /// The index server keeps track of the topology it sent us only during a single session, so
+    /// incremental updates can only be applied during the same session.
+    /// None if the next update must be a full topology.
+    opt_session: Option<u64>,
+}
+
+impl TopologySnapshot {
+    fn report(&self, currency: &Currency) -> TopologySnapshotReport {
+        TopologySnapshotReport {
+            currency: currency.clone(),
+            server_public_key: self.server_public_key.clone(),
+            num_edges: usize_to_u64(self.edges.len()).unwrap(),
+            ticks_since_sync: self.ticks_since_sync,
+        }
+    }
+}
+
+/// Signed topology snapshots received from index servers, kept for every currency we use.
+/// Used to find routes locally when no index server is connected.
+#[derive(Debug)]
+pub struct TopologyCache {
+    /// Currencies we synchronize the topology of
+    currencies: HashSet<Currency>,
+    snapshots: HashMap<Currency, TopologySnapshot>,
+    /// Currencies with a topology request in progress
+    pending: HashSet<Currency>,
+    /// Current session. A new session begins whenever we connect to an index server.
+    session: u64,
+    sync_ticks: usize,
+    /// Ticks left until the next synchronization
+    ticks_to_sync: usize,
+}
+
+impl TopologyCache {
+    /// Create a new cache, synchronizing the topology every `sync_ticks` ticks.
+    pub fn new(sync_ticks: usize) -> Self {
+        TopologyCache {
+            currencies: HashSet::new(),
+            snapshots: HashMap::new(),
+            pending: HashSet::new(),
+            session: 0,
+            sync_ticks,
+            ticks_to_sync: sync_ticks,
+        }
+    }
+
+    /// Start synchronizing the topology of `currency`
+    pub fn add_currency(&mut self, currency: &Currency) {
+        self.currencies.insert(currency.clone());
+    }
+
+    /// Begin a new session. Should be called whenever a connection to an index server is
+    /// established.
+    pub fn new_session(&mut self) {
+        self.session = self.session.wrapping_add(1);
+        self.pending.clear();
+        self.ticks_to_sync = self.sync_ticks;
+    }
+
+    pub fn session(&self) -> u64 {
+        self.session
+    }
+
+    /// Create topology requests for all the currencies that have no request in progress.
+    pub fn create_requests<R>(&mut self, rng: &mut R) -> Vec<RequestTopology>
+    where
+        R: CryptoRandom,
+    {
+        let mut requests = Vec::new();
+        for currency in &self.currencies {
+            if self.pending.contains(currency) {
+                continue;
+            }
+            let is_incremental = self
+                .snapshots
+                .get(currency)
+                .map(|snapshot| snapshot.opt_session == Some(self.session))
+                .unwrap_or(false);
+
+            requests.push(RequestTopology {
+                request_id: Uid::rand_gen(rng),
+                currency: currency.clone(),
+                is_incremental,
+            });
+        }
+        for request in &requests {
+            self.pending.insert(request.currency.clone());
+        }
+        requests
+    }
+
+    /// A topology request failed to complete
+    pub fn request_failed(&mut self, session: u64, currency: &Currency) {
+        if session == self.session {
+            self.pending.remove(currency);
+        }
+    }
+
+    /// Advance time.
+    /// Returns true if it is time to synchronize the topology again.
+    pub fn tick(&mut self) -> bool {
+        for snapshot in self.snapshots.values_mut() {
+            snapshot.ticks_since_sync = snapshot.ticks_since_sync.saturating_add(1);
+        }
+        self.ticks_to_sync = self.ticks_to_sync.saturating_sub(1);
+        if self.ticks_to_sync == 0 {
+            self.ticks_to_sync = self.sync_ticks;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Apply a topology response received from the index server `server_public_key` during
+    /// `session`. On success, returns a report of the updated snapshot.
+    ///
+    /// If the response is invalid, the current snapshot is kept (It was verified previously),
+    /// and the next request for this currency will ask for a full topology.
+    pub fn apply_response(
+        &mut self,
+        session: u64,
+        server_public_key: &PublicKey,
+        response_topology: ResponseTopology,
+    ) -> Result<TopologySnapshotReport, TopologyError> {
+        if session != self.session {
+            return Err(TopologyError::StaleSession);
+        }
+        let currency = response_topology.currency.clone();
+        self.pending.remove(&currency);
+
+        let res = self.create_snapshot(session, server_public_key, response_topology);
+        match res {
+            Ok(snapshot) => {
+                let report = snapshot.report(&currency);
+                self.snapshots.insert(currency, snapshot);
+                Ok(report)
+            }
+            Err(e) => {
+                if let Some(snapshot) = self.snapshots.get_mut(&currency) {
+                    snapshot.opt_session = None;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn create_snapshot(
+        &self,
+        session: u64,
+        server_public_key: &PublicKey,
+        response_topology: ResponseTopology,
+    ) -> Result<TopologySnapshot, TopologyError> {
+        if !verify_response_topology(&response_topology, server_public_key) {
+            return Err(TopologyError::InvalidSignature);
+        }
+
+        let mut edges = if response_topology.is_full {
+            HashMap::new()
+        } else {
+            match self.snapshots.get(&response_topology.currency) {
+                Some(snapshot) if snapshot.opt_session == Some(session) => snapshot.edges.clone(),
+                _ => return Err(TopologyError::MissingBase),
+            }
+        };
+
+        for topology_edge in response_topology.updated_edges {
+            let key = (
+                topology_edge.from_public_key.clone(),
+                topology_edge.to_public_key.clone(),
+            );
+            edges.insert(key, topology_edge);
+        }
+        for edge in response_topology.removed_edges {
+            let _ = edges.remove(&(edge.from_public_key, edge.to_public_key));
+        }
+
+        if topology_hash(edges.values()) != response_topology.topology_hash {
+            return Err(TopologyError::HashMismatch);
+        }
+
+        Ok(TopologySnapshot {
+            server_public_key: server_public_key.clone(),
+            edges,
+            ticks_since_sync: 0,
+            opt_session: Some(session),
+        })
+    }
+
+    /// Reports of all the kept snapshots
+    pub fn reports(&self) -> Vec<TopologySnapshotReport> {
+        self.snapshots
+            .iter()
+            .map(|(currency, snapshot)| snapshot.report(currency))
+            .collect()
+    }
+
+    /// Find routes locally, using the snapshot of the requested currency.
+    /// Returns None if we have no snapshot for this currency.
+    pub fn get_multi_routes(&self, request_routes: &RequestRoutes) -> Option<Vec<MultiRoute>> {
+        let snapshot = self.snapshots.get(&request_routes.currency)?;
+        Some(topology_multi_routes(
+            snapshot.edges.values(),
+            request_routes,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::identity::{Identity, SoftwareEd25519Identity};
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{PrivateKey, Signature};
+    use proto::funder::messages::Rate;
+    use proto::index_server::messages::Edge;
+
+    use signature::signature_buff::create_response_topology_signature_buff;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    fn topology_edge(from: u8, to: u8, recv_capacity: u128) -> TopologyEdge {
+        TopologyEdge {
+            from_public_key: pk(from),
+            to_public_key: pk(to),
+            recv_capacity,
+            rate: Rate::new(),
+        }
+    }
+
+    /// Create a ResponseTopology signed by `identity`
+    fn create_response_topology(
+        identity: &SoftwareEd25519Identity,
+        currency: &Currency,
+        is_full: bool,
+        updated_edges: Vec<TopologyEdge>,
+        removed_edges: Vec<Edge>,
+        full_topology: &[TopologyEdge],
+    ) -> ResponseTopology {
+        let mut response_topology = ResponseTopology {
+            request_id: Uid::from(&[0; Uid::len()]),
+            currency: currency.clone(),
+            is_full,
+            updated_edges,
+            removed_edges,
+            topology_hash: topology_hash(full_topology),
+            signature: Signature::default(),
+        };
+        response_topology.signature =
+            identity.sign(&create_response_topology_signature_buff(&response_topology));
+        response_topology
+    }
+
+    #[test]
+    fn test_topology_cache_sync() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let mut rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = PrivateKey::rand_gen(&mut rng);
+        let identity = SoftwareEd25519Identity::from_private_key(&pkcs8).unwrap();
+        let server_public_key = identity.get_public_key();
+
+        let mut topology_cache = TopologyCache::new(2);
+        // No requests before we know any currency:
+        topology_cache.new_session();
+        assert!(topology_cache.create_requests(&mut rng).is_empty());
+
+        topology_cache.add_currency(&currency);
+        let requests = topology_cache.create_requests(&mut rng);
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].is_incremental);
+        // A request is already in progress:
+        assert!(topology_cache.create_requests(&mut rng).is_empty());
+
+        let edges1 = vec![
+            topology_edge(0, 1, 30),
+            topology_edge(1, 0, 30),
+            topology_edge(1, 2, 30),
+            topology_edge(2, 1, 30),
+        ];
+        let session = topology_cache.session();
+        let response =
+            create_response_topology(&identity, &currency, true, edges1.clone(), vec![], &edges1);
+        let report = topology_cache
+            .apply_response(session, &server_public_key, response)
+            .unwrap();
+        assert_eq!(report.num_edges, 4);
+        assert_eq!(report.ticks_since_sync, 0);
+
+        let request_routes = RequestRoutes {
+            request_id: Uid::from(&[2; Uid::len()]),
+            currency: currency.clone(),
+            capacity: 20,
+            source: pk(0),
+            destination: pk(2),
+            opt_exclude: None,
+        };
+        let multi_routes = topology_cache.get_multi_routes(&request_routes).unwrap();
+        assert!(!multi_routes.is_empty());
+
+        // Staleness grows with time, and a sync is required every 2 ticks:
+        assert!(!topology_cache.tick());
+        assert!(topology_cache.tick());
+        assert_eq!(topology_cache.reports()[0].ticks_since_sync, 2);
+
+        // The next request is incremental:
+        let requests = topology_cache.create_requests(&mut rng);
+        assert!(requests[0].is_incremental);
+
+        // Remove the edges between 1 and 2:
+        let edges2 = vec![topology_edge(0, 1, 30), topology_edge(1, 0, 30)];
+        let removed_edges = vec![
+            Edge {
+                from_public_key: pk(1),
+                to_public_key: pk(2),
+            },
+            Edge {
+                from_public_key: pk(2),
+                to_public_key: pk(1),
+            },
+        ];
+        let response =
+            create_response_topology(&identity, &currency, false, vec![], removed_edges, &edges2);
+        let report = topology_cache
+            .apply_response(session, &server_public_key, response)
+            .unwrap();
+        assert_eq!(report.num_edges, 2);
+        assert_eq!(report.ticks_since_sync, 0);
+        assert!(topology_cache
+            .get_multi_routes(&request_routes)
+            .unwrap()
+            .is_empty());
+
+        // A response with a wrong hash is rejected, and the snapshot is kept:
+        let _ = topology_cache.create_requests(&mut rng);
+        let response =
+            create_response_topology(&identity, &currency, false, vec![], vec![], &edges1);
+        assert_eq!(
+            topology_cache.apply_response(session, &server_public_key, response),
+            Err(TopologyError::HashMismatch)
+        );
+        assert_eq!(topology_cache.reports()[0].num_edges, 2);
+        // The next request asks for a full topology:
+        let requests = topology_cache.create_requests(&mut rng);
+        assert!(!requests[0].is_incremental);
+
+        // A response signed by a different server is rejected:
+        let response =
+            create_response_topology(&identity, &currency, true, edges1.clone(), vec![], &edges1);
+        assert_eq!(
+            topology_cache.apply_response(session, &pk(9), response),
+            Err(TopologyError::InvalidSignature)
+        );
+
+        // Responses from a previous session are rejected:
+        topology_cache.new_session();
+        let response =
+            create_response_topology(&identity, &currency, true, edges1.clone(), vec![], &edges1);
+        assert_eq!(
+            topology_cache.apply_response(session, &server_public_key, response),
+            Err(TopologyError::StaleSession)
+        );
+    }
+}
@@ -0,0 +1,109 @@
+/// Limits on the resources a single client may consume from the index server.
+#[derive(Debug, Clone)]
+pub struct ClientLimits {
+    /// Maximum amount of requests (Routes, topology or route outcome) a client may send in a
+    /// burst
+    pub max_request_burst: usize,
+    /// Amount of requests a client is allowed to send every tick
+    pub requests_per_tick: usize,
+    /// Maximum amount of MutationsUpdate messages a client may send in a burst
+    pub max_mutations_burst: usize,
+    /// Amount of MutationsUpdate messages a client is allowed to send every tick
+    pub mutations_per_tick: usize,
+    /// Maximum amount of mutations in a single MutationsUpdate message
+    pub max_mutations_per_update: usize,
+    /// Maximum amount of route searches (From all clients) waiting to be processed by the graph
+    /// service. Route searches beyond this limit are rejected.
+    pub max_pending_route_searches: usize,
+}
+
+impl ClientLimits {
+    /// No limits at all. Useful for testing.
+    pub fn unlimited() -> Self {
+        ClientLimits {
+            max_request_burst: usize::max_value(),
+            requests_per_tick: usize::max_value(),
+            max_mutations_burst: usize::max_value(),
+            mutations_per_tick: usize::max_value(),
+            max_mutations_per_update: usize::max_value(),
+            max_pending_route_searches: usize::max_value(),
+        }
+    }
+}
+
+/// A token bucket, refilled every tick
+#[derive(Debug)]
+pub struct RateLimiter {
+    tokens: usize,
+    max_tokens: usize,
+    tokens_per_tick: usize,
+}
+
+impl RateLimiter {
+    /// Create a full RateLimiter
+    pub fn new(max_tokens: usize, tokens_per_tick: usize) -> Self {
+        RateLimiter {
+            tokens: max_tokens,
+            max_tokens,
+            tokens_per_tick,
+        }
+    }
+
+    /// Try to spend one token. Returns false if no tokens are left.
+    pub fn try_consume(&mut self) -> bool {
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    pub fn tick(&mut self) {
+        self.tokens = self
+            .tokens
+            .saturating_add(self.tokens_per_tick)
+            .min(self.max_tokens);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_basic() {
+        let mut rate_limiter = RateLimiter::new(3, 2);
+        for _ in 0..3 {
+            assert!(rate_limiter.try_consume());
+        }
+        assert!(!rate_limiter.try_consume());
+
+        rate_limiter.tick();
+        assert!(rate_limiter.try_consume());
+        assert!(rate_limiter.try_consume());
+        assert!(!rate_limiter.try_consume());
+
+        // Tokens never exceed max_tokens:
+        for _ in 0..10 {
+            rate_limiter.tick();
+        }
+        for _ in 0..3 {
+            assert!(rate_limiter.try_consume());
+        }
+        assert!(!rate_limiter.try_consume());
+    }
+
+    #[test]
+    fn test_rate_limiter_unlimited() {
+        let client_limits = ClientLimits::unlimited();
+        let mut rate_limiter = RateLimiter::new(
+            client_limits.max_request_burst,
+            client_limits.requests_per_tick,
+        );
+        for _ in 0..1000 {
+            assert!(rate_limiter.try_consume());
+        }
+        rate_limiter.tick();
+        assert!(rate_limiter.try_consume());
+    }
+}
@@ -31,6 +31,24 @@ impl<C, T> CapacityEdge<C, T> {
     }
 }
 
+/// Size of a capacity graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphStats {
+    /// Amount of nodes showing up in the graph, either as a source or as a destination of an edge
+    pub num_nodes: usize,
+    /// Amount of directed edges
+    pub num_edges: usize,
+}
+
+/// Reported outcome of sending credits along a single edge of a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeOutcome {
+    Success,
+    Failure,
+    /// The reporting node does not know if sending credits along this edge failed
+    Unknown,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CapacityRoute<N, C, T> {
     pub route: Vec<N>,
@@ -86,6 +104,16 @@ pub trait CapacityGraph {
         opt_exclude: Option<(&Self::Node, &Self::Node)>,
     ) -> Vec<CapacityMultiRoute<Self::Node, Self::Capacity, Self::Rate>>;
 
-    /// Simulate advancement of time. Used to remove old edges.
+    /// Report the outcome of sending credits along `route`, one outcome for every edge.
+    /// The report is sent by the first node of the route.
+    /// Edges that failed are less preferable when searching for routes, and edges that fail too
+    /// often (According to multiple reporting nodes) are removed.
+    fn report_route(&mut self, route: &[Self::Node], edge_outcomes: &[EdgeOutcome]);
+
+    /// Simulate advancement of time. Used to remove old edges, and edges of `a` that were not
+    /// matched by an edge in the opposite direction for too long.
     fn tick(&mut self, a: &Self::Node);
+
+    /// Get the size of the graph
+    fn stats(&self) -> GraphStats;
 }
@@ -0,0 +1,217 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::{cmp, hash};
+
+fn dijkstra_loop<'c, I, N, F>(
+    src: &'c N,
+    dst: &'c N,
+    get_neighbors: F,
+) -> Option<HashMap<N, Option<N>>>
+where
+    I: Iterator<Item = (&'c N, u128)>,
+    F: Fn(&N) -> I,
+    N: Clone + cmp::Eq + hash::Hash,
+{
+    let mut backtrack: HashMap<N, Option<N>> = HashMap::new();
+    // Lowest known (cost, hops) for every discovered node:
+    let mut best: HashMap<N, (u128, usize)> = HashMap::new();
+    let mut visited: HashSet<N> = HashSet::new();
+    // Nodes are not ordered, so the queue holds indices into `entries`.
+    // Ties in cost are broken in favour of routes with less hops.
+    let mut entries: Vec<N> = Vec::new();
+    let mut queue: BinaryHeap<Reverse<(u128, usize, usize)>> = BinaryHeap::new();
+
+    backtrack.insert(src.clone(), None);
+    best.insert(src.clone(), (0, 0));
+    entries.push(src.clone());
+    queue.push(Reverse((0, 0, 0)));
+
+    while let Some(Reverse((cost, hops, index))) = queue.pop() {
+        let node = entries[index].clone();
+        if !visited.insert(node.clone()) {
+            // We already found a cheaper way to this node:
+            continue;
+        }
+        if node == *dst {
+            return Some(backtrack);
+        }
+        for (neighbor, edge_cost) in get_neighbors(&node) {
+            if visited.contains(neighbor) {
+                continue;
+            }
+            let new_cost = match cost.checked_add(edge_cost) {
+                Some(new_cost) => new_cost,
+                None => continue,
+            };
+            let new_best = (new_cost, hops + 1);
+            if let Some(cur_best) = best.get(neighbor) {
+                if *cur_best <= new_best {
+                    continue;
+                }
+            }
+            best.insert(neighbor.clone(), new_best);
+            backtrack.insert(neighbor.clone(), Some(node.clone()));
+            entries.push(neighbor.clone());
+            queue.push(Reverse((new_cost, hops + 1, entries.len() - 1)));
+        }
+    }
+    None
+}
+
+fn dijkstra_backtrack<N>(dst: &N, backtrack: &HashMap<N, Option<N>>) -> Option<Vec<N>>
+where
+    N: Clone + cmp::Eq + hash::Hash,
+{
+    let mut route = Vec::new();
+
+    route.push(dst.clone());
+    let mut node = dst;
+
+    while let Some(new_node) = backtrack.get(node)? {
+        route.push(new_node.clone());
+        node = new_node;
+    }
+
+    route.reverse();
+    Some(route)
+}
+
+/// Find the cheapest route from `src` to `dst`.
+/// `get_neighbors` returns the neighbors of a node, together with the cost of moving to each
+/// neighbor. Among routes of equal cost, a route with the least amount of hops is returned.
+/// A route from a node to itself is never returned.
+pub fn dijkstra<'c, I, N, F>(src: &'c N, dst: &'c N, get_neighbors: F) -> Option<Vec<N>>
+where
+    I: Iterator<Item = (&'c N, u128)>,
+    F: Fn(&N) -> I,
+    N: Clone + cmp::Eq + hash::Hash,
+{
+    if src == dst {
+        return None;
+    }
+    let backtrack = dijkstra_loop(src, dst, get_neighbors)?;
+    dijkstra_backtrack(dst, &backtrack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dijkstra_backtrack_basic() {
+        let mut backtrack: HashMap<u32, Option<u32>> = HashMap::new();
+        /*
+         *    3 -- 4
+         *     \-- 5 -- 7
+         *     \-- 6 -- 8 -- 9 -- 10
+         *                   \-- 11
+         *
+         */
+        backtrack.insert(3, None);
+        backtrack.insert(4, Some(3));
+        backtrack.insert(5, Some(3));
+        backtrack.insert(6, Some(3));
+        backtrack.insert(7, Some(5));
+        backtrack.insert(8, Some(6));
+        backtrack.insert(9, Some(8));
+        backtrack.insert(10, Some(9));
+        backtrack.insert(11, Some(9));
+
+        let opt_route = dijkstra_backtrack(&11, &backtrack);
+        assert_eq!(opt_route.unwrap(), vec![3, 6, 8, 9, 11]);
+    }
+
+    #[test]
+    fn test_dijkstra_backtrack_failure() {
+        let mut backtrack: HashMap<u32, Option<u32>> = HashMap::new();
+        backtrack.insert(2, Some(1));
+        backtrack.insert(3, Some(2));
+
+        // Backtracking should fail, because 1 is not a key at the backtrack map:
+        assert!(dijkstra_backtrack(&3, &backtrack).is_none());
+    }
+
+    #[test]
+    fn test_dijkstra_unit_costs() {
+        /*
+         Example graph:
+                            0 --> 1
+                            ^     |
+                            |     |
+               9            |     V
+               ^            3 <-- 2
+               |            |
+               |            V
+               8 <-- 6 <--- 4 --> 5
+                     ^
+                     |
+                     V
+                     7
+        */
+
+        let mut graph = HashMap::new();
+        graph.insert(0u32, vec![1u32]);
+        graph.insert(1, vec![2]);
+        graph.insert(2, vec![3]);
+        graph.insert(3, vec![0, 4]);
+        graph.insert(4, vec![5, 6]);
+        graph.insert(5, vec![]);
+        graph.insert(6, vec![7, 8]);
+        graph.insert(7, vec![6]);
+        graph.insert(8, vec![9]);
+        graph.insert(9, vec![]);
+
+        let get_neighbors = |node: &u32| graph.get(&node).unwrap().iter().map(|n| (n, 1));
+        assert_eq!(dijkstra(&0, &1, get_neighbors), Some(vec![0, 1]));
+        assert_eq!(dijkstra(&1, &0, get_neighbors), Some(vec![1, 2, 3, 0]));
+
+        assert_eq!(
+            dijkstra(&0, &9, get_neighbors),
+            Some(vec![0, 1, 2, 3, 4, 6, 8, 9])
+        );
+
+        assert_eq!(dijkstra(&8, &6, get_neighbors), None);
+        assert_eq!(dijkstra(&9, &8, get_neighbors), None);
+        assert_eq!(dijkstra(&5, &4, get_neighbors), None);
+        assert_eq!(dijkstra(&4, &3, get_neighbors), None);
+
+        assert_eq!(dijkstra(&6, &7, get_neighbors), Some(vec![6, 7]));
+        assert_eq!(dijkstra(&7, &6, get_neighbors), Some(vec![7, 6]));
+
+        assert_eq!(dijkstra(&0, &0, get_neighbors), None);
+    }
+
+    #[test]
+    fn test_dijkstra_cheapest() {
+        /*
+         Example graph (costs in parentheses):
+
+               (1)     (1)
+            0 -----> 1 -----> 3
+            |                 ^
+            |(1)   (5)        |(1)
+            +-----> 2 --------+
+                    |         |
+                 (1)|         |
+                    V   (1)   |
+                    4 --------+
+        */
+
+        let mut graph = HashMap::new();
+        graph.insert(0u32, vec![(1u32, 1u128), (2, 1)]);
+        graph.insert(1, vec![(3, 1)]);
+        graph.insert(2, vec![(3, 5), (4, 1)]);
+        graph.insert(3, vec![]);
+        graph.insert(4, vec![(3, 1)]);
+
+        let get_neighbors = |node: &u32| {
+            graph
+                .get(&node)
+                .unwrap()
+                .iter()
+                .map(|(neighbor, cost)| (neighbor, *cost))
+        };
+        assert_eq!(dijkstra(&0, &3, get_neighbors), Some(vec![0, 1, 3]));
+        assert_eq!(dijkstra(&2, &3, get_neighbors), Some(vec![2, 4, 3]));
+    }
+}
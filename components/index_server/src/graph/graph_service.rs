@@ -1,11 +1,19 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt::Debug;
 use std::hash::Hash;
 
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnError, SpawnExt};
-use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
+use futures::{future, stream, FutureExt, SinkExt, StreamExt, TryFutureExt};
 
-use super::capacity_graph::{CapacityEdge, CapacityGraph, CapacityMultiRoute};
+use common::conn::BoxStream;
+use common::select_streams::select_streams;
+
+use super::capacity_graph::{
+    CapacityEdge, CapacityGraph, CapacityMultiRoute, EdgeOutcome, GraphStats,
+};
+use super::graph_shards::{GraphShards, GraphStoreConfig};
+use super::graph_store::{GraphStore, PersistentGraph, StoredEdge};
 
 pub enum GraphRequest<G, N, C, T> {
     /// Change capacities on a directed edge:
@@ -23,16 +31,23 @@ pub enum GraphRequest<G, N, C, T> {
     RemoveNode(N, oneshot::Sender<()>),
     /// Get some routes from one node to another of at least certain capacity.
     /// If an exclude directed edge is provided, the routes must not contain this directed edge.
+    /// Returns None if too many route searches are already pending.
     GetMultiRoutes(
         G,
         N,
         N,
         C,
         Option<(N, N)>,
-        oneshot::Sender<Vec<CapacityMultiRoute<N, C, T>>>,
+        oneshot::Sender<Option<Vec<CapacityMultiRoute<N, C, T>>>>,
     ), // (from, to, capacity, opt_exclude)
     /// Expire old outgoing edges for the specified node
     Tick(N, oneshot::Sender<()>),
+    /// Get all the edges of a graph
+    ExportEdges(G, oneshot::Sender<Vec<StoredEdge<N, T>>>),
+    /// Report the outcome of sending credits along a route
+    ReportRoute(G, Vec<N>, Vec<EdgeOutcome>, oneshot::Sender<()>), // (route, edge_outcomes)
+    /// Get the size of every graph. Graphs that are not loaded in memory have no statistics.
+    GetStats(oneshot::Sender<Vec<(G, Option<GraphStats>)>>),
 }
 
 #[derive(Debug)]
@@ -44,16 +59,18 @@ pub enum GraphServiceError {
 #[allow(clippy::many_single_char_names)]
 /// Process one GraphRequest, and send the response through the provided sender.
 /// This function might perform a long computation and take a long time to complete.
-fn process_request<G, N, C, T, CG>(
-    capacity_graphs: &mut HashMap<G, CG>,
-    graph_request: GraphRequest<G, N, C, T>,
+fn process_request<G, N, T, CG, ST>(
+    capacity_graphs: &mut GraphShards<G, N, CG, ST>,
+    graph_request: GraphRequest<G, N, u128, T>,
 ) where
-    G: Hash + Eq,
-    CG: CapacityGraph<Node = N, Capacity = C, Rate = T>,
+    G: Hash + Eq + Clone + Debug,
+    N: Hash + Eq + Clone,
+    CG: PersistentGraph<Node = N, Rate = T>,
+    ST: GraphStore<G, N, T>,
 {
     match graph_request {
         GraphRequest::UpdateEdge(g, a, b, capacity_edge, sender) => {
-            let capacity_graph = capacity_graphs.entry(g).or_insert_with(CG::new);
+            let capacity_graph = capacity_graphs.get_mut_or_create(g);
             let _ = sender.send(capacity_graph.update_edge(a, b, capacity_edge));
         }
         GraphRequest::RemoveEdge(g, a, b, sender) => {
@@ -62,11 +79,11 @@ fn process_request<G, N, C, T, CG>(
             }
         }
         GraphRequest::RemoveNode(a, sender) => {
-            capacity_graphs.retain(|_g, capacity_graph| capacity_graph.remove_node(&a));
+            capacity_graphs.remove_node(&a);
             let _ = sender.send(());
         }
         GraphRequest::GetMultiRoutes(g, a, b, capacity, opt_exclude, sender) => {
-            let routes = if let Some(capacity_graph) = capacity_graphs.get_mut(&g) {
+            let routes = if let Some(capacity_graph) = capacity_graphs.get(&g) {
                 match opt_exclude {
                     Some((c, d)) => {
                         capacity_graph.get_multi_routes(&a, &b, capacity, Some((&c, &d)))
@@ -76,44 +93,164 @@ fn process_request<G, N, C, T, CG>(
             } else {
                 vec![]
             };
-            let _ = sender.send(routes);
+            let _ = sender.send(Some(routes));
         }
         GraphRequest::Tick(a, sender) => {
-            for capacity_graph in capacity_graphs.values_mut() {
-                capacity_graph.tick(&a);
+            capacity_graphs.tick(&a);
+            let _ = sender.send(());
+        }
+        GraphRequest::ExportEdges(g, sender) => {
+            let edges = if let Some(capacity_graph) = capacity_graphs.get(&g) {
+                capacity_graph.export_edges()
+            } else {
+                vec![]
+            };
+            let _ = sender.send(edges);
+        }
+        GraphRequest::ReportRoute(g, route, edge_outcomes, sender) => {
+            if let Some(capacity_graph) = capacity_graphs.get_mut(&g) {
+                capacity_graph.report_route(&route, &edge_outcomes);
             }
             let _ = sender.send(());
         }
+        GraphRequest::GetStats(sender) => {
+            let _ = sender.send(capacity_graphs.stats());
+        }
     }
 }
 
-async fn graph_service_loop<G, N, C, T, CG, GS>(
-    mut capacity_graphs: HashMap<G, CG>,
-    mut incoming_requests: mpsc::Receiver<GraphRequest<G, N, C, T>>,
+#[allow(clippy::large_enum_variant)]
+enum GraphServiceEvent<G, N, T, CG, ST> {
+    Request(GraphRequest<G, N, u128, T>),
+    RequestsClosed,
+    /// A request was processed, and the graphs are available again
+    RequestProcessed(GraphShards<G, N, CG, ST>),
+}
+
+struct GraphService<G, N, T, CG, ST, GS> {
+    /// The graphs, if no request is currently being processed
+    opt_capacity_graphs: Option<GraphShards<G, N, CG, ST>>,
+    /// Requests waiting to be processed
+    pending_requests: VecDeque<GraphRequest<G, N, u128, T>>,
+    /// Amount of route searches in `pending_requests`
+    num_pending_route_searches: usize,
+    max_pending_route_searches: usize,
+    processed_sender: mpsc::Sender<GraphShards<G, N, CG, ST>>,
     graph_service_spawner: GS,
-) -> Result<(), GraphServiceError>
+}
+
+impl<G, N, T, CG, ST, GS> GraphService<G, N, T, CG, ST, GS>
 where
-    G: Send + Hash + Eq + 'static,
-    N: Send + 'static,
-    C: Send + 'static,
+    G: Send + Hash + Eq + Clone + Debug + 'static,
+    N: Send + Hash + Eq + Clone + 'static,
     T: Send + 'static,
-    CG: CapacityGraph<Node = N, Capacity = C, Rate = T> + Send + 'static,
+    CG: PersistentGraph<Node = N, Rate = T> + Send + 'static,
+    ST: GraphStore<G, N, T> + Send + 'static,
     GS: Spawn,
 {
-    // We use a separate spawner to be used for long graph computations.
-    // We don't want to block the external shared thread pool.
+    /// Queue a request. Route searches are rejected if there are too many pending route searches,
+    /// because route searches are expensive, and we don't want them to pile up.
+    fn add_request(&mut self, graph_request: GraphRequest<G, N, u128, T>) {
+        let graph_request = match graph_request {
+            GraphRequest::GetMultiRoutes(g, a, b, capacity, opt_exclude, sender) => {
+                if self.num_pending_route_searches >= self.max_pending_route_searches {
+                    warn!("GraphService: Too many pending route searches");
+                    let _ = sender.send(None);
+                    return;
+                }
+                self.num_pending_route_searches += 1;
+                GraphRequest::GetMultiRoutes(g, a, b, capacity, opt_exclude, sender)
+            }
+            graph_request => graph_request,
+        };
+        self.pending_requests.push_back(graph_request);
+    }
 
-    while let Some(graph_request) = incoming_requests.next().await {
+    /// Start processing the next pending request, if the graphs are not busy
+    fn process_next(&mut self) -> Result<(), GraphServiceError> {
+        if self.opt_capacity_graphs.is_none() {
+            return Ok(());
+        }
+        let graph_request = match self.pending_requests.pop_front() {
+            Some(graph_request) => graph_request,
+            None => return Ok(()),
+        };
+        if let GraphRequest::GetMultiRoutes(..) = &graph_request {
+            self.num_pending_route_searches -= 1;
+        }
+
+        let mut capacity_graphs = self.opt_capacity_graphs.take().unwrap();
+        let mut c_processed_sender = self.processed_sender.clone();
         // Run the graph computation over own pool:
-        let process_request_handle = graph_service_spawner
-            .spawn_with_handle(async move {
+        self.graph_service_spawner
+            .spawn(async move {
                 process_request(&mut capacity_graphs, graph_request);
-                capacity_graphs
+                capacity_graphs.request_processed();
+                let _ = c_processed_sender.send(capacity_graphs).await;
             })
-            .map_err(|_| GraphServiceError::LocalSpawnError)?;
+            .map_err(|_| GraphServiceError::LocalSpawnError)
+    }
 
-        // Wait for completion of the computation on the external pool:
-        capacity_graphs = process_request_handle.await;
+    fn is_idle(&self) -> bool {
+        self.opt_capacity_graphs.is_some() && self.pending_requests.is_empty()
+    }
+}
+
+async fn graph_service_loop<G, N, T, CG, ST, GS>(
+    capacity_graphs: GraphShards<G, N, CG, ST>,
+    incoming_requests: mpsc::Receiver<GraphRequest<G, N, u128, T>>,
+    max_pending_route_searches: usize,
+    graph_service_spawner: GS,
+) -> Result<(), GraphServiceError>
+where
+    G: Send + Hash + Eq + Clone + Debug + 'static,
+    N: Send + Hash + Eq + Clone + 'static,
+    T: Send + 'static,
+    CG: PersistentGraph<Node = N, Rate = T> + Send + 'static,
+    ST: GraphStore<G, N, T> + Send + 'static,
+    GS: Spawn,
+{
+    // We use a separate spawner to be used for long graph computations.
+    // We don't want to block the external shared thread pool.
+    // Requests are processed one by one. New requests are queued while a request is processed.
+
+    let (processed_sender, processed_receiver) = mpsc::channel(0);
+    let mut graph_service = GraphService {
+        opt_capacity_graphs: Some(capacity_graphs),
+        pending_requests: VecDeque::new(),
+        num_pending_route_searches: 0,
+        max_pending_route_searches,
+        processed_sender,
+        graph_service_spawner,
+    };
+
+    let incoming_requests = incoming_requests
+        .map(GraphServiceEvent::Request)
+        .chain(stream::once(future::ready(
+            GraphServiceEvent::RequestsClosed,
+        )));
+    let processed_receiver = processed_receiver.map(GraphServiceEvent::RequestProcessed);
+
+    let mut events = select_streams![incoming_requests, processed_receiver];
+    let mut requests_closed = false;
+
+    while let Some(event) = events.next().await {
+        match event {
+            GraphServiceEvent::Request(graph_request) => graph_service.add_request(graph_request),
+            GraphServiceEvent::RequestsClosed => requests_closed = true,
+            GraphServiceEvent::RequestProcessed(capacity_graphs) => {
+                graph_service.opt_capacity_graphs = Some(capacity_graphs)
+            }
+        }
+        graph_service.process_next()?;
+        if requests_closed && graph_service.is_idle() {
+            break;
+        }
+    }
+
+    // All clients were dropped. Save all the pending modifications:
+    if let Some(mut capacity_graphs) = graph_service.opt_capacity_graphs.take() {
+        capacity_graphs.save_all();
     }
     Ok(())
 }
@@ -122,6 +259,8 @@ where
 pub enum GraphClientError {
     SendRequestError,
     ResponseReceiverClosed,
+    /// Too many route searches are pending
+    Busy,
 }
 
 impl From<oneshot::Canceled> for GraphClientError {
@@ -192,6 +331,8 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
     ///
     /// opt_exclude is an optional edge to exclude (The returned route must not go through this
     /// edge). This can be useful for finding non trivial loops.
+    ///
+    /// Returns GraphClientError::Busy if too many route searches are pending.
     pub async fn get_multi_routes(
         &mut self,
         g: G,
@@ -211,7 +352,7 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
                 sender,
             ))
             .await?;
-        Ok(receiver.await?)
+        receiver.await?.ok_or(GraphClientError::Busy)
     }
 
     /// Remove an edge from the graph
@@ -222,31 +363,77 @@ impl<G, N, C, T> GraphClient<G, N, C, T> {
             .await?;
         Ok(receiver.await?)
     }
+
+    /// Get all the edges of the graph `g`.
+    /// Returns an empty list if the graph does not exist.
+    pub async fn export_edges(&mut self, g: G) -> Result<Vec<StoredEdge<N, T>>, GraphClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.requests_sender
+            .send(GraphRequest::ExportEdges(g, sender))
+            .await?;
+        Ok(receiver.await?)
+    }
+
+    /// Report the outcome of sending credits along a route in the graph `g`.
+    pub async fn report_route(
+        &mut self,
+        g: G,
+        route: Vec<N>,
+        edge_outcomes: Vec<EdgeOutcome>,
+    ) -> Result<(), GraphClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.requests_sender
+            .send(GraphRequest::ReportRoute(g, route, edge_outcomes, sender))
+            .await?;
+        Ok(receiver.await?)
+    }
+
+    /// Get the size of every graph.
+    /// Graphs that are not loaded in memory have no statistics.
+    pub async fn get_stats(&mut self) -> Result<Vec<(G, Option<GraphStats>)>, GraphClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.requests_sender
+            .send(GraphRequest::GetStats(sender))
+            .await?;
+        Ok(receiver.await?)
+    }
 }
 
 /// Spawn a graph service, returning a GraphClient on success.
 /// GraphClient can be cloned to allow multiple clients.
-pub fn create_graph_service<G, N, C, T, CG, GS, S>(
+///
+/// If `opt_graph_store_config` is provided, the graphs are persisted to the given store (And
+/// loaded from it on startup), and only a limited amount of graphs is kept in memory.
+///
+/// At most `max_pending_route_searches` route searches may wait to be processed. Additional
+/// route searches are rejected.
+pub fn create_graph_service<G, N, T, CG, ST, GS, S>(
+    opt_graph_store_config: Option<GraphStoreConfig<ST>>,
+    max_pending_route_searches: usize,
     graph_service_spawner: GS,
     spawner: S,
-) -> Result<GraphClient<G, N, C, T>, SpawnError>
+) -> Result<GraphClient<G, N, u128, T>, SpawnError>
 where
-    G: Hash + Eq + Send + 'static,
-    N: Send + 'static,
-    C: Send + 'static,
+    G: Hash + Eq + Clone + Debug + Send + 'static,
+    N: Hash + Eq + Clone + Send + 'static,
     T: Send + 'static,
-    CG: CapacityGraph<Node = N, Capacity = C, Rate = T> + Send + 'static,
+    CG: PersistentGraph<Node = N, Rate = T> + Send + 'static,
+    ST: GraphStore<G, N, T> + Send + 'static,
     GS: Spawn + Send + 'static,
     S: Spawn,
 {
     let (requests_sender, requests_receiver) = mpsc::channel(0);
 
-    let capacity_graphs = HashMap::<G, CG>::new();
+    let capacity_graphs = GraphShards::<G, N, CG, ST>::new(opt_graph_store_config);
 
-    let graph_service_loop_fut =
-        graph_service_loop(capacity_graphs, requests_receiver, graph_service_spawner)
-            .map_err(|e| error!("graph_service_loop() error: {:?}", e))
-            .map(|_| ());
+    let graph_service_loop_fut = graph_service_loop(
+        capacity_graphs,
+        requests_receiver,
+        max_pending_route_searches,
+        graph_service_spawner,
+    )
+    .map_err(|e| error!("graph_service_loop() error: {:?}", e))
+    .map(|_| ());
 
     spawner.spawn(graph_service_loop_fut)?;
     Ok(GraphClient::new(requests_sender))
@@ -257,6 +444,7 @@ mod tests {
     use super::*;
 
     use super::super::capacity_graph::CapacityRoute;
+    use super::super::graph_store::FileGraphStore;
     use super::super::simple_capacity_graph::SimpleCapacityGraph;
     use super::super::test_utils::ConstRate;
 
@@ -273,12 +461,12 @@ mod tests {
         let mut graph_client = create_graph_service::<
             u8,
             u32,
-            u128,
             ConstRate,
             SimpleCapacityGraph<u32, ConstRate>,
+            FileGraphStore,
             _,
             _,
-        >(graph_service_spawner, spawner)
+        >(None, usize::max_value(), graph_service_spawner, spawner)
         .unwrap();
 
         graph_client
@@ -324,6 +512,30 @@ mod tests {
             vec![]
         );
 
+        let mut edges = graph_client.export_edges(currency1).await.unwrap();
+        edges.sort_by_key(|edge| (edge.from, edge.to));
+        assert_eq!(edges.len(), 2);
+        assert_eq!(
+            (edges[0].from, edges[0].to, edges[0].recv_capacity),
+            (2, 5, 5)
+        );
+        assert_eq!(
+            (edges[1].from, edges[1].to, edges[1].recv_capacity),
+            (5, 2, 30)
+        );
+        assert!(graph_client.export_edges(2u8).await.unwrap().is_empty());
+
+        assert_eq!(
+            graph_client.get_stats().await.unwrap(),
+            vec![(
+                currency1,
+                Some(GraphStats {
+                    num_nodes: 2,
+                    num_edges: 2
+                })
+            )]
+        );
+
         graph_client.tick(2).await.unwrap();
 
         assert_eq!(
@@ -340,6 +552,49 @@ mod tests {
 
         block_on(task_create_graph_service_basic(thread_pool.clone()));
     }
+
+    async fn task_create_graph_service_busy<S>(spawner: S)
+    where
+        S: Spawn,
+    {
+        let currency1 = 1u8;
+
+        // No route searches are allowed to wait:
+        let graph_service_spawner = ThreadPool::new().unwrap();
+        let mut graph_client = create_graph_service::<
+            u8,
+            u32,
+            ConstRate,
+            SimpleCapacityGraph<u32, ConstRate>,
+            FileGraphStore,
+            _,
+            _,
+        >(None, 0, graph_service_spawner, spawner)
+        .unwrap();
+
+        graph_client
+            .update_edge(currency1, 2u32, 5u32, CapacityEdge::new(5, ConstRate(1u32)))
+            .await
+            .unwrap();
+
+        match graph_client
+            .get_multi_routes(currency1, 2, 5, 1, None)
+            .await
+        {
+            Err(GraphClientError::Busy) => {}
+            _ => unreachable!(),
+        };
+
+        // Other requests are still served:
+        assert_eq!(graph_client.export_edges(currency1).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_create_graph_service_busy() {
+        let thread_pool = ThreadPool::new().unwrap();
+
+        block_on(task_create_graph_service_busy(thread_pool.clone()));
+    }
 }
 
 // TODO: Add a test for multiple currencies at the same time (Different values for the G type)
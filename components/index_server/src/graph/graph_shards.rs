@@ -0,0 +1,436 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::capacity_graph::{CapacityGraph, GraphStats};
+use super::graph_store::{import_graph, GraphStore, PersistentGraph};
+
+/// Amount of processed requests between two consecutive saves of modified shards.
+const SAVE_INTERVAL_REQUESTS: usize = 0x100;
+
+/// Configuration for persisting capacity graphs
+#[derive(Debug)]
+pub struct GraphStoreConfig<ST> {
+    /// Storage for the graph shards
+    pub graph_store: ST,
+    /// Maximum amount of shards kept in memory at the same time.
+    /// Least recently used shards are saved and evicted from memory when this amount is exceeded.
+    pub max_loaded_shards: usize,
+}
+
+/// Operations applied to a node while its shard was not loaded.
+/// They are applied to the shard when it is loaded again.
+struct ShardBacklog<N> {
+    removed_nodes: HashSet<N>,
+    ticks: HashMap<N, usize>,
+}
+
+impl<N> ShardBacklog<N>
+where
+    N: Hash + Eq,
+{
+    fn new() -> Self {
+        ShardBacklog {
+            removed_nodes: HashSet::new(),
+            ticks: HashMap::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.removed_nodes.is_empty() && self.ticks.is_empty()
+    }
+}
+
+/// A collection of capacity graphs, one for every shard key (For example: currency).
+///
+/// If a graph store is configured, shards are persisted together with the ages of their edges,
+/// and only a limited amount of shards is kept in memory.
+pub struct GraphShards<G, N, CG, ST> {
+    loaded: HashMap<G, CG>,
+    /// Last time (In amount of shard accesses) every loaded shard was used
+    last_used: HashMap<G, u64>,
+    usage_counter: u64,
+    /// Loaded shards that were modified since they were last saved
+    modified: HashSet<G>,
+    /// Shards that exist in the store but are not loaded
+    unloaded: HashMap<G, ShardBacklog<N>>,
+    opt_store_config: Option<GraphStoreConfig<ST>>,
+    requests_since_save: usize,
+}
+
+impl<G, N, CG, ST> GraphShards<G, N, CG, ST>
+where
+    G: Hash + Eq + Clone + Debug,
+    N: Hash + Eq + Clone,
+    CG: PersistentGraph<Node = N>,
+    ST: GraphStore<G, N, CG::Rate>,
+{
+    /// Create a new collection of capacity graphs.
+    /// If a graph store is configured, shards are loaded from the store, up to the maximum
+    /// amount of loaded shards.
+    pub fn new(opt_store_config: Option<GraphStoreConfig<ST>>) -> Self {
+        let mut graph_shards = GraphShards {
+            loaded: HashMap::new(),
+            last_used: HashMap::new(),
+            usage_counter: 0,
+            modified: HashSet::new(),
+            unloaded: HashMap::new(),
+            opt_store_config,
+            requests_since_save: 0,
+        };
+
+        let (shards, max_loaded_shards) = match &graph_shards.opt_store_config {
+            Some(store_config) => match store_config.graph_store.list_shards() {
+                Ok(shards) => (shards, store_config.max_loaded_shards),
+                Err(e) => {
+                    error!("GraphShards: Failed listing stored shards: {:?}", e);
+                    return graph_shards;
+                }
+            },
+            None => return graph_shards,
+        };
+
+        for g in shards {
+            graph_shards.unloaded.insert(g, ShardBacklog::new());
+        }
+
+        let initial_shards: Vec<G> = graph_shards
+            .unloaded
+            .keys()
+            .take(max_loaded_shards)
+            .cloned()
+            .collect();
+        for g in initial_shards {
+            graph_shards.load_shard(&g);
+        }
+
+        graph_shards
+    }
+
+    /// Mark a loaded shard as recently used
+    fn touch(&mut self, g: &G) {
+        self.usage_counter = self.usage_counter.wrapping_add(1);
+        self.last_used.insert(g.clone(), self.usage_counter);
+    }
+
+    /// Write a capacity graph into the store.
+    /// An empty graph is removed from the store.
+    fn write_shard(&mut self, g: &G, capacity_graph: &CG) {
+        let store_config = match &mut self.opt_store_config {
+            Some(store_config) => store_config,
+            None => return,
+        };
+
+        let edges = capacity_graph.export_edges();
+        let res = if edges.is_empty() {
+            store_config.graph_store.remove_shard(g)
+        } else {
+            store_config.graph_store.save_shard(g, &edges)
+        };
+        if let Err(e) = res {
+            error!("GraphShards: Failed saving shard {:?}: {:?}", g, e);
+        }
+    }
+
+    /// Read a capacity graph from the store, and apply all operations that happened while the
+    /// shard was not loaded.
+    fn read_shard(&self, g: &G, backlog: ShardBacklog<N>) -> CG {
+        let edges = match &self.opt_store_config {
+            Some(store_config) => match store_config.graph_store.load_shard(g) {
+                Ok(edges) => edges,
+                Err(e) => {
+                    error!("GraphShards: Failed loading shard {:?}: {:?}", g, e);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        };
+        let mut capacity_graph: CG = import_graph(edges);
+
+        for node in &backlog.removed_nodes {
+            capacity_graph.remove_node(node);
+        }
+        for (node, num_ticks) in &backlog.ticks {
+            for _ in 0..*num_ticks {
+                capacity_graph.tick(node);
+            }
+        }
+        capacity_graph
+    }
+
+    /// Save a loaded shard to the store
+    fn save_shard(&mut self, g: &G) {
+        if let Some(capacity_graph) = self.loaded.remove(g) {
+            self.write_shard(g, &capacity_graph);
+            self.loaded.insert(g.clone(), capacity_graph);
+        }
+        self.modified.remove(g);
+    }
+
+    /// Save all loaded shards that were modified since they were last saved, and apply pending
+    /// operations to shards that are not loaded, one shard at a time.
+    ///
+    /// Pending ticks are frequent, so they are only applied if `flush_ticks` is set. Pending
+    /// node removals are always applied.
+    fn flush(&mut self, flush_ticks: bool) {
+        let modified: Vec<G> = self.modified.iter().cloned().collect();
+        for g in modified {
+            self.save_shard(&g);
+        }
+
+        let pending: Vec<G> = self
+            .unloaded
+            .iter()
+            .filter(|(_, backlog)| {
+                !backlog.removed_nodes.is_empty() || (flush_ticks && !backlog.ticks.is_empty())
+            })
+            .map(|(g, _)| g.clone())
+            .collect();
+        for g in pending {
+            let backlog = self
+                .unloaded
+                .insert(g.clone(), ShardBacklog::new())
+                .unwrap();
+            let capacity_graph = self.read_shard(&g, backlog);
+            self.write_shard(&g, &capacity_graph);
+        }
+
+        self.requests_since_save = 0;
+    }
+
+    /// Save all modifications, except for ticks of shards that are not loaded.
+    pub fn save_modified(&mut self) {
+        self.flush(false);
+    }
+
+    /// Save all modifications. Should be called before shutting down.
+    pub fn save_all(&mut self) {
+        self.flush(true);
+    }
+
+    /// Should be called after every processed request.
+    /// Periodically saves modified shards.
+    pub fn request_processed(&mut self) {
+        self.requests_since_save = self.requests_since_save.saturating_add(1);
+        if self.requests_since_save >= SAVE_INTERVAL_REQUESTS {
+            self.save_modified();
+        }
+    }
+
+    /// Load a shard from the store, applying all operations that happened while the shard was
+    /// not loaded.
+    fn load_shard(&mut self, g: &G) {
+        let backlog = match self.unloaded.remove(g) {
+            Some(backlog) => backlog,
+            None => return,
+        };
+        if !backlog.is_empty() {
+            self.modified.insert(g.clone());
+        }
+        let capacity_graph = self.read_shard(g, backlog);
+
+        self.loaded.insert(g.clone(), capacity_graph);
+        self.touch(g);
+        self.evict(g);
+    }
+
+    /// Save and unload least recently used shards, until the amount of loaded shards is
+    /// within the allowed maximum. The shard `keep` is never evicted.
+    fn evict(&mut self, keep: &G) {
+        let max_loaded_shards = match &self.opt_store_config {
+            Some(store_config) => store_config.max_loaded_shards,
+            None => return,
+        };
+
+        while self.loaded.len() > max_loaded_shards {
+            let opt_lru = self
+                .last_used
+                .iter()
+                .filter(|(g, _)| *g != keep)
+                .min_by_key(|(_, last_used)| **last_used)
+                .map(|(g, _)| g.clone());
+            let lru = match opt_lru {
+                Some(lru) => lru,
+                None => return,
+            };
+            if self.modified.contains(&lru) {
+                self.save_shard(&lru);
+            }
+            self.loaded.remove(&lru);
+            self.last_used.remove(&lru);
+            self.unloaded.insert(lru, ShardBacklog::new());
+        }
+    }
+
+    /// Get a shard, loading it from the store if required.
+    pub fn get(&mut self, g: &G) -> Option<&CG> {
+        if self.unloaded.contains_key(g) {
+            self.load_shard(g);
+        }
+        if !self.loaded.contains_key(g) {
+            return None;
+        }
+        self.touch(g);
+        self.loaded.get(g)
+    }
+
+    /// Get a shard for modification, loading it from the store if required.
+    pub fn get_mut(&mut self, g: &G) -> Option<&mut CG> {
+        if self.unloaded.contains_key(g) {
+            self.load_shard(g);
+        }
+        if !self.loaded.contains_key(g) {
+            return None;
+        }
+        self.touch(g);
+        self.modified.insert(g.clone());
+        self.loaded.get_mut(g)
+    }
+
+    /// Get a shard for modification, creating a new empty shard if it does not exist.
+    pub fn get_mut_or_create(&mut self, g: G) -> &mut CG {
+        if self.get_mut(&g).is_none() {
+            self.loaded.insert(g.clone(), CG::new());
+            self.touch(&g);
+            self.modified.insert(g.clone());
+            self.evict(&g);
+        }
+        self.loaded.get_mut(&g).unwrap()
+    }
+
+    /// Get the size of every shard.
+    /// Shards that are not loaded are not loaded for this purpose, and have no statistics.
+    pub fn stats(&self) -> Vec<(G, Option<GraphStats>)> {
+        let loaded_stats = self
+            .loaded
+            .iter()
+            .map(|(g, capacity_graph)| (g.clone(), Some(capacity_graph.stats())));
+        let unloaded_stats = self.unloaded.keys().map(|g| (g.clone(), None));
+        loaded_stats.chain(unloaded_stats).collect()
+    }
+
+    /// Remove a node and all edges starting from this node, from all shards.
+    /// For shards that are not loaded, the removal is applied when the shard is loaded.
+    pub fn remove_node(&mut self, a: &N) {
+        let mut empty_shards = Vec::new();
+        for (g, capacity_graph) in &mut self.loaded {
+            if capacity_graph.remove_node(a) {
+                empty_shards.push(g.clone());
+            }
+            self.modified.insert(g.clone());
+        }
+        for g in empty_shards {
+            // Saving an empty shard removes it from the store:
+            self.save_shard(&g);
+            self.loaded.remove(&g);
+            self.last_used.remove(&g);
+        }
+
+        for backlog in self.unloaded.values_mut() {
+            backlog.ticks.remove(a);
+            backlog.removed_nodes.insert(a.clone());
+        }
+    }
+
+    /// Expire old outgoing edges of a node, in all shards.
+    /// For shards that are not loaded, the tick is applied when the shard is loaded.
+    pub fn tick(&mut self, a: &N) {
+        for (g, capacity_graph) in &mut self.loaded {
+            capacity_graph.tick(a);
+            self.modified.insert(g.clone());
+        }
+        for backlog in self.unloaded.values_mut() {
+            let num_ticks = backlog.ticks.entry(a.clone()).or_insert(0);
+            *num_ticks = num_ticks.saturating_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use super::super::capacity_graph::CapacityEdge;
+    use super::super::graph_store::FileGraphStore;
+    use super::super::simple_capacity_graph::SimpleCapacityGraph;
+    use super::super::test_utils::ConstRate;
+
+    type TestGraphShards =
+        GraphShards<u8, u32, SimpleCapacityGraph<u32, ConstRate>, FileGraphStore>;
+
+    fn create_graph_shards(graph_store: FileGraphStore) -> TestGraphShards {
+        GraphShards::new(Some(GraphStoreConfig {
+            graph_store,
+            max_loaded_shards: 2,
+        }))
+    }
+
+    fn num_edges(graph_shards: &mut TestGraphShards, g: u8) -> usize {
+        graph_shards
+            .get(&g)
+            .map(|capacity_graph| capacity_graph.export_edges().len())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_graph_shards_evict_and_reload() {
+        let dir = tempdir().unwrap();
+        let graph_store = FileGraphStore::new(dir.path().to_path_buf()).unwrap();
+        let mut graph_shards = create_graph_shards(graph_store.clone());
+
+        for g in 0..4u8 {
+            graph_shards.get_mut_or_create(g).update_edge(
+                2,
+                5,
+                CapacityEdge::new(10, ConstRate(1)),
+            );
+            graph_shards.get_mut_or_create(g).update_edge(
+                5,
+                2,
+                CapacityEdge::new(10, ConstRate(1)),
+            );
+            assert!(graph_shards.loaded.len() <= 2);
+        }
+
+        // Statistics are only available for loaded shards:
+        let stats = graph_shards.stats();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(
+            stats
+                .iter()
+                .filter(|(_g, opt_stats)| opt_stats.is_some())
+                .count(),
+            2
+        );
+
+        // Shards that were evicted are loaded on demand:
+        for g in 0..4u8 {
+            assert_eq!(num_edges(&mut graph_shards, g), 2);
+        }
+
+        // Remove a node while some shards are not loaded:
+        graph_shards.remove_node(&2);
+        for g in 0..4u8 {
+            assert_eq!(num_edges(&mut graph_shards, g), 1);
+        }
+
+        // Reload everything from the store:
+        graph_shards.save_all();
+        let mut graph_shards = create_graph_shards(graph_store);
+        for g in 0..4u8 {
+            assert_eq!(num_edges(&mut graph_shards, g), 1);
+        }
+        assert_eq!(num_edges(&mut graph_shards, 4), 0);
+
+        // Empty shards are removed:
+        graph_shards.remove_node(&5);
+        graph_shards.save_modified();
+        let mut graph_shards =
+            create_graph_shards(FileGraphStore::new(dir.path().to_path_buf()).unwrap());
+        for g in 0..4u8 {
+            assert!(graph_shards.get(&g).is_none());
+        }
+    }
+}
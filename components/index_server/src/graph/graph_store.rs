@@ -0,0 +1,233 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use common::ser_utils::ser_string;
+
+use super::capacity_graph::{CapacityEdge, CapacityGraph};
+
+/// Extension of file names used to store graph shards
+const SHARD_FILE_EXTENSION: &str = "json";
+
+/// A directed edge, together with its age and reliability score.
+/// Used to persist the contents of a capacity graph.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredEdge<N, T> {
+    pub from: N,
+    pub to: N,
+    #[serde(with = "ser_string")]
+    pub recv_capacity: u128,
+    pub rate: T,
+    /// Amount of ticks since the edge was last updated
+    #[serde(with = "ser_string")]
+    pub age: u128,
+    /// Score of reported route failures through this edge
+    #[serde(default)]
+    pub unreliability: u32,
+}
+
+/// A capacity graph whose contents can be exported and imported, including the ages of edges.
+pub trait PersistentGraph: CapacityGraph<Capacity = u128> {
+    /// Export all the edges of the graph
+    fn export_edges(&self) -> Vec<StoredEdge<Self::Node, Self::Rate>>;
+
+    /// Add or update an edge, keeping its age
+    fn import_edge(&mut self, stored_edge: StoredEdge<Self::Node, Self::Rate>);
+}
+
+/// Import a full graph from a list of stored edges
+pub fn import_graph<CG>(stored_edges: Vec<StoredEdge<CG::Node, CG::Rate>>) -> CG
+where
+    CG: PersistentGraph,
+{
+    let mut capacity_graph = CG::new();
+    for stored_edge in stored_edges {
+        capacity_graph.import_edge(stored_edge);
+    }
+    capacity_graph
+}
+
+impl<N, T> StoredEdge<N, T> {
+    pub fn capacity_edge(&self) -> CapacityEdge<u128, T>
+    where
+        T: Clone,
+    {
+        CapacityEdge::new(self.recv_capacity, self.rate.clone())
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphStoreError {
+    ReadDirError(io::Error),
+    ReadError(io::Error),
+    WriteError(atomicwrites::Error<io::Error>),
+    RemoveError(io::Error),
+    SerdeJsonError(serde_json::Error),
+    InvalidShardName,
+}
+
+/// A storage for capacity graphs, partitioned into shards.
+/// Every shard (for example: every currency) is stored and loaded separately.
+pub trait GraphStore<G, N, T> {
+    /// List all the shards that exist in the store
+    fn list_shards(&self) -> Result<Vec<G>, GraphStoreError>;
+
+    /// Load all the edges of a shard.
+    /// Returns an empty list if the shard does not exist.
+    fn load_shard(&self, g: &G) -> Result<Vec<StoredEdge<N, T>>, GraphStoreError>;
+
+    /// Replace the contents of a shard
+    fn save_shard(&mut self, g: &G, edges: &[StoredEdge<N, T>]) -> Result<(), GraphStoreError>;
+
+    /// Remove a shard from the store. Does nothing if the shard does not exist.
+    fn remove_shard(&mut self, g: &G) -> Result<(), GraphStoreError>;
+}
+
+/// A graph store keeping every shard in a separate json file inside a directory.
+/// The name of every file is the hex encoding of the serialized shard key.
+#[derive(Debug, Clone)]
+pub struct FileGraphStore {
+    dir_path: PathBuf,
+}
+
+impl FileGraphStore {
+    /// Create a graph store over a directory.
+    /// The directory is created if it does not exist.
+    pub fn new(dir_path: PathBuf) -> Result<Self, GraphStoreError> {
+        fs::create_dir_all(&dir_path).map_err(GraphStoreError::ReadDirError)?;
+        Ok(FileGraphStore { dir_path })
+    }
+
+    fn shard_path<G>(&self, g: &G) -> Result<PathBuf, GraphStoreError>
+    where
+        G: Serialize,
+    {
+        let shard_name = serde_json::to_string(g).map_err(GraphStoreError::SerdeJsonError)?;
+        let file_name = format!(
+            "{}.{}",
+            hex_encode(shard_name.as_bytes()),
+            SHARD_FILE_EXTENSION
+        );
+        Ok(self.dir_path.join(file_name))
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hex_decode(hex_str: &str) -> Option<Vec<u8>> {
+    if hex_str.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex_str.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex_str.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl<G, N, T> GraphStore<G, N, T> for FileGraphStore
+where
+    G: Serialize + DeserializeOwned,
+    N: Serialize + DeserializeOwned,
+    T: Serialize + DeserializeOwned,
+{
+    fn list_shards(&self) -> Result<Vec<G>, GraphStoreError> {
+        let mut shards = Vec::new();
+        for entry in fs::read_dir(&self.dir_path).map_err(GraphStoreError::ReadDirError)? {
+            let path = entry.map_err(GraphStoreError::ReadDirError)?.path();
+            if path.is_dir()
+                || path.extension().and_then(|ext| ext.to_str()) != Some(SHARD_FILE_EXTENSION)
+            {
+                continue;
+            }
+            let shard_name = path
+                .file_stem()
+                .and_then(|file_stem| file_stem.to_str())
+                .and_then(hex_decode)
+                .ok_or(GraphStoreError::InvalidShardName)?;
+            shards.push(
+                serde_json::from_slice(&shard_name).map_err(GraphStoreError::SerdeJsonError)?,
+            );
+        }
+        Ok(shards)
+    }
+
+    fn load_shard(&self, g: &G) -> Result<Vec<StoredEdge<N, T>>, GraphStoreError> {
+        let shard_path = self.shard_path(g)?;
+        if !shard_path.exists() {
+            return Ok(Vec::new());
+        }
+        let ser_string = fs::read_to_string(&shard_path).map_err(GraphStoreError::ReadError)?;
+        serde_json::from_str(&ser_string).map_err(GraphStoreError::SerdeJsonError)
+    }
+
+    fn save_shard(&mut self, g: &G, edges: &[StoredEdge<N, T>]) -> Result<(), GraphStoreError> {
+        let shard_path = self.shard_path(g)?;
+        let ser_string = serde_json::to_string(edges).map_err(GraphStoreError::SerdeJsonError)?;
+        // Save the shard to file, atomically:
+        let af = atomicwrites::AtomicFile::new(&shard_path, atomicwrites::AllowOverwrite);
+        af.write(|fw| fw.write_all(ser_string.as_bytes()))
+            .map_err(GraphStoreError::WriteError)
+    }
+
+    fn remove_shard(&mut self, g: &G) -> Result<(), GraphStoreError> {
+        let shard_path = self.shard_path(g)?;
+        if !shard_path.exists() {
+            return Ok(());
+        }
+        fs::remove_file(&shard_path).map_err(GraphStoreError::RemoveError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    use super::super::simple_capacity_graph::SimpleCapacityGraph;
+    use super::super::test_utils::ConstRate;
+
+    #[test]
+    fn test_file_graph_store_basic() {
+        let dir = tempdir().unwrap();
+        let mut file_graph_store = FileGraphStore::new(dir.path().join("graph")).unwrap();
+        let graph_store: &mut dyn GraphStore<String, u32, ConstRate> = &mut file_graph_store;
+
+        let mut capacity_graph = SimpleCapacityGraph::<u32, ConstRate>::new();
+        capacity_graph.update_edge(2, 5, CapacityEdge::new(10, ConstRate(1)));
+        capacity_graph.update_edge(5, 2, CapacityEdge::new(20, ConstRate(2)));
+        capacity_graph.tick(&2);
+
+        assert!(graph_store.list_shards().unwrap().is_empty());
+
+        let currency1 = "FST1".to_owned();
+        let currency2 = "FST2".to_owned();
+        let mut edges = capacity_graph.export_edges();
+        graph_store.save_shard(&currency1, &edges).unwrap();
+
+        assert_eq!(graph_store.list_shards().unwrap(), vec![currency1.clone()]);
+
+        let mut loaded_edges = graph_store.load_shard(&currency1).unwrap();
+        edges.sort_by_key(|edge| (edge.from, edge.to));
+        loaded_edges.sort_by_key(|edge| (edge.from, edge.to));
+        assert_eq!(loaded_edges, edges);
+        assert_eq!(loaded_edges[0].age, 1);
+        assert_eq!(loaded_edges[1].age, 0);
+
+        // Ages are kept when the graph is imported:
+        let loaded_graph: SimpleCapacityGraph<u32, ConstRate> = import_graph(loaded_edges);
+        let mut reexported_edges = loaded_graph.export_edges();
+        reexported_edges.sort_by_key(|edge| (edge.from, edge.to));
+        assert_eq!(reexported_edges, edges);
+
+        assert!(graph_store.load_shard(&currency2).unwrap().is_empty());
+
+        graph_store.remove_shard(&currency1).unwrap();
+        assert!(graph_store.list_shards().unwrap().is_empty());
+    }
+}
@@ -1,6 +1,8 @@
-mod bfs;
 pub mod capacity_graph;
+mod dijkstra;
 pub mod graph_service;
+pub mod graph_shards;
+pub mod graph_store;
 pub mod simple_capacity_graph;
 mod utils;
 
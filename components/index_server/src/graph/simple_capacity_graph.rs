@@ -1,33 +1,82 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::{cmp, hash};
 
-use super::bfs::bfs;
 use super::capacity_graph::{
-    CapacityEdge, CapacityGraph, CapacityMultiRoute, CapacityRoute, LinearRate,
+    CapacityEdge, CapacityGraph, CapacityMultiRoute, CapacityRoute, EdgeOutcome, GraphStats,
+    LinearRate,
 };
+use super::dijkstra::dijkstra;
+use super::graph_store::{PersistentGraph, StoredEdge};
 use super::utils::{option_to_vec, OptionIterator};
 
 /// Amount of ticks an edge could live regardless of coupon collector's approximation.
 /// This is useful to allow the first edges build (n*log(n) is very small for small n).
 const BASE_MAX_EDGE_AGE: u128 = 16;
 
+/// Maximum amount of routes in a single multi route.
+const MAX_ROUTES_IN_MULTI_ROUTE: usize = 8;
+
+/// Maximum amount of alternative multi routes returned for a single request.
+const MAX_MULTI_ROUTES: usize = 4;
+
+/// When splitting a payment between multiple routes, the minimum capacity of every route is
+/// `capacity >> split_level`, for every split level up to this value.
+const MAX_SPLIT_LEVEL: u32 = 4;
+
+/// Unreliability added to an edge that failed. If the failed edge is not known, the penalty is
+/// divided between all the edges that might have failed.
+const ROUTE_FAILURE_PENALTY: u32 = 0x100;
+
+/// Maximum unreliability of an edge. Edges that reach this value are removed from the graph,
+/// given that their failures were reported by enough nodes.
+const MAX_UNRELIABILITY: u32 = 0x400;
+
+/// Minimum amount of distinct nodes that reported failures of an edge before it is removed.
+/// A single node can not remove edges of other nodes from the graph.
+const MIN_FAILURE_REPORTERS: usize = 3;
+
+/// Sending `amount` credits through an edge costs an extra
+/// `amount * unreliability / UNRELIABILITY_COST_DIVISOR` during route search.
+/// For the most unreliable edges, this is about 1/16 of the amount.
+const UNRELIABILITY_COST_DIVISOR: u128 = 0x4000;
+
+/// Capacity assigned to previously found routes, for every directed edge.
+type UsedCapacities<N> = HashMap<(N, N), u128>;
+
 #[derive(Debug, Clone)]
-struct Edge<T> {
+struct Edge<N, T> {
     capacity_edge: CapacityEdge<u128, T>,
     age: u128,
+    /// Amount of ticks this edge was not matched by an edge in the opposite direction.
+    /// Unlike `age`, this value is not reset when the edge is updated.
+    one_sided_age: u128,
+    /// A decaying score of reported failures of routes going through this edge.
+    /// Unreliable edges are less preferable during route search.
+    unreliability: u32,
+    /// Nodes that reported failures of this edge since it was last reported successful.
+    /// At most `MIN_FAILURE_REPORTERS` nodes are kept.
+    failure_reporters: HashSet<N>,
 }
 
-impl<T> Edge<T> {
+impl<N, T> Edge<N, T> {
     fn new(capacity_edge: CapacityEdge<u128, T>) -> Self {
+        Edge::with_age(capacity_edge, 0)
+    }
+
+    fn with_age(capacity_edge: CapacityEdge<u128, T>, age: u128) -> Self {
         Edge {
             capacity_edge,
-            age: 0,
+            age,
+            one_sided_age: 0,
+            unreliability: 0,
+            failure_reporters: HashSet::new(),
         }
     }
 }
 
 struct NodeEdges<N, T> {
-    edges: HashMap<N, Edge<T>>,
+    edges: HashMap<N, Edge<N, T>>,
 }
 
 impl<N, T> NodeEdges<N, T>
@@ -55,12 +104,24 @@ impl<N, T> NodeEdges<N, T>
 where
     N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
 {
-    pub fn tick(&mut self) {
+    /// Age all edges, and remove edges that are too old.
+    ///
+    /// An edge is only useful for routing if the remote node advertised a matching edge in the
+    /// opposite direction. Edges in `one_sided` (Not matched by the remote node) are removed if
+    /// they stay unmatched for too long, even if they are updated. This prevents a node from
+    /// keeping fake edges towards arbitrary nodes in the graph.
+    pub fn tick(&mut self, one_sided: &HashSet<N>) {
         let max_edge_age = max_edge_age(self.edges.len());
 
-        self.edges.retain(|_remote_node, edge| {
+        self.edges.retain(|remote_node, edge| {
             edge.age = edge.age.saturating_add(1);
-            edge.age < max_edge_age
+            edge.unreliability -= edge.unreliability >> 3;
+            if one_sided.contains(remote_node) {
+                edge.one_sided_age = edge.one_sided_age.saturating_add(1);
+            } else {
+                edge.one_sided_age = 0;
+            }
+            edge.age < max_edge_age && edge.one_sided_age < max_edge_age
         });
     }
 }
@@ -72,7 +133,7 @@ pub struct SimpleCapacityGraph<N, T> {
 impl<N, T> SimpleCapacityGraph<N, T>
 where
     N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
-    T: LinearRate + Clone,
+    T: LinearRate<K = u128> + Clone,
 {
     pub fn new() -> SimpleCapacityGraph<N, T> {
         Self {
@@ -81,7 +142,7 @@ where
     }
 
     /// Get a directed edge (if exists)
-    fn get_edge(&self, a: &N, b: &N) -> Option<Edge<T>> {
+    fn get_edge(&self, a: &N, b: &N) -> Option<Edge<N, T>> {
         match self.nodes.get(a) {
             None => None,
             Some(a_edges) => match a_edges.edges.get(b) {
@@ -104,11 +165,23 @@ where
         }
     }
 
-    fn neighbors_with_send_capacity(
-        &self,
+    /// Get the send capacity from `a` to a direct neighbor `b` that was not yet assigned to
+    /// previously found routes.
+    fn get_residual_send_capacity(&self, a: &N, b: &N, used: &UsedCapacities<N>) -> u128 {
+        let send_capacity = self.get_send_capacity(a, b);
+        if used.is_empty() {
+            return send_capacity;
+        }
+        let used_capacity = used.get(&(a.clone(), b.clone())).cloned().unwrap_or(0);
+        send_capacity.saturating_sub(used_capacity)
+    }
+
+    fn neighbors_with_send_capacity<'a>(
+        &'a self,
         a: N,
         capacity: u128,
-    ) -> OptionIterator<impl Iterator<Item = &N>> {
+        used: &'a UsedCapacities<N>,
+    ) -> OptionIterator<impl Iterator<Item = &'a N>> {
         let a_edges = match self.nodes.get(&a) {
             Some(a_edges) => a_edges,
             None => return OptionIterator::new(None),
@@ -116,15 +189,38 @@ where
         let iter = a_edges
             .edges
             .keys()
-            .filter(move |b| self.get_send_capacity(&a, b) >= capacity);
+            .filter(move |b| self.get_residual_send_capacity(&a, b, used) >= capacity);
         OptionIterator::new(Some(iter))
     }
 
+    /// Calculate the fees paid for sending `amount` credits from `a` to a direct neighbor `b`,
+    /// as part of a route that ends at `dst`.
+    /// No fees are paid for the last hop.
+    fn get_hop_fee(&self, a: &N, b: &N, dst: &N, amount: u128) -> Option<u128> {
+        if b == dst {
+            return Some(0);
+        }
+        let a_b_edge = self.nodes.get(a)?.edges.get(b)?;
+        a_b_edge.capacity_edge.rate.calc_fee(amount)
+    }
+
+    /// Extra cost of sending `amount` credits from `a` to a direct neighbor `b`, according to the
+    /// reported reliability of the edge.
+    fn get_hop_unreliability_cost(&self, a: &N, b: &N, amount: u128) -> u128 {
+        match self.nodes.get(a).and_then(|a_edges| a_edges.edges.get(b)) {
+            Some(a_b_edge) => {
+                amount.saturating_mul(u128::from(a_b_edge.unreliability))
+                    / UNRELIABILITY_COST_DIVISOR
+            }
+            None => 0,
+        }
+    }
+
     /// Calculate the amount of capacity we can send through a route.
     /// This amount if the minimum of all edge capacities of the route.
-    fn get_route_capacity(&self, route: &[N]) -> Option<u128> {
+    fn get_route_capacity(&self, route: &[N], used: &UsedCapacities<N>) -> Option<u128> {
         (0..route.len().checked_sub(1)?)
-            .map(|i| self.get_send_capacity(&route[i], &route[i + 1]))
+            .map(|i| self.get_residual_send_capacity(&route[i], &route[i + 1], used))
             .min()
     }
 
@@ -140,34 +236,55 @@ where
         Some(total_rate)
     }
 
-    /// Get a route with capacity at least `capacity`.
-    /// Returns the route together with the capacity it is possible to send through the route.
-    ///
-    /// opt_exclude is an optional edge to exclude (The returned route must not go through this
-    /// edge). This can be useful for finding non trivial loops.
-    fn get_multi_route(
+    /// Find the cheapest route from `a` to `b` where every edge has at least `capacity`
+    /// residual send capacity.
+    /// The cost of a route is the total fees paid for sending `amount` credits along the route,
+    /// plus an extra cost for edges that were reported as unreliable.
+    fn get_cheapest_route(
         &self,
         a: &N,
         b: &N,
         capacity: u128,
+        amount: u128,
         opt_exclude: Option<(&N, &N)>,
-    ) -> Option<CapacityMultiRoute<N, u128, T>> {
-        // TODO: Update this implementation:
-        // Currently get_route does not attemp to find the cheapest route (according to rate)
-        // It only finds the shortest route and then calculates the rate.
-
+        used: &UsedCapacities<N>,
+    ) -> Option<Vec<N>> {
         let (opt_e_start, opt_e_end) = match opt_exclude {
             Some((e_start, e_end)) => (Some(e_start), Some(e_end)),
             None => (None, None),
         };
         let get_neighbors = |cur_node: &N| {
             let cur_node_is_e_start = Some(cur_node) == opt_e_start;
-            self.neighbors_with_send_capacity(cur_node.clone(), capacity)
+            let c_cur_node = cur_node.clone();
+            self.neighbors_with_send_capacity(cur_node.clone(), capacity, used)
                 .filter(move |&next_node| !cur_node_is_e_start || Some(next_node) != opt_e_end)
+                .filter_map(move |next_node| {
+                    let fee = self.get_hop_fee(&c_cur_node, next_node, b, amount)?;
+                    let unreliability_cost =
+                        self.get_hop_unreliability_cost(&c_cur_node, next_node, amount);
+                    Some((next_node, fee.saturating_add(unreliability_cost)))
+                })
         };
-        let route = bfs(a, b, get_neighbors)?;
+        dijkstra(a, b, get_neighbors)
+    }
+
+    /// Get the cheapest route with capacity at least `capacity`.
+    /// The cost of a route is the total fees paid for sending `capacity` credits along the route.
+    /// Returns the route together with the capacity it is possible to send through the route.
+    ///
+    /// opt_exclude is an optional edge to exclude (The returned route must not go through this
+    /// edge). This can be useful for finding non trivial loops.
+    fn get_multi_route(
+        &self,
+        a: &N,
+        b: &N,
+        capacity: u128,
+        opt_exclude: Option<(&N, &N)>,
+    ) -> Option<CapacityMultiRoute<N, u128, T>> {
+        let used = UsedCapacities::new();
+        let route = self.get_cheapest_route(a, b, capacity, capacity, opt_exclude, &used)?;
         // We assert that we will always have valid capacity here:
-        let capacity = self.get_route_capacity(&route).unwrap();
+        let capacity = self.get_route_capacity(&route, &used).unwrap();
 
         let rate = self.get_route_rate(&route)?;
 
@@ -181,12 +298,98 @@ where
             routes: vec![graph_route],
         })
     }
+
+    /// Get a multi route whose combined capacity is at least `capacity`, by finding successive
+    /// cheapest routes over the capacity that was not yet assigned to previously found routes.
+    /// Every route in the multi route must be able to carry at least `min_route_capacity`
+    /// credits (Or the remaining amount, if smaller).
+    ///
+    /// The capacities of the returned routes can be used together: If some routes share an edge,
+    /// the capacity of that edge is divided between them.
+    fn get_split_multi_route(
+        &self,
+        a: &N,
+        b: &N,
+        capacity: u128,
+        min_route_capacity: u128,
+        opt_exclude: Option<(&N, &N)>,
+    ) -> Option<CapacityMultiRoute<N, u128, T>> {
+        let mut used = UsedCapacities::new();
+        let mut routes = Vec::new();
+        let mut remaining = capacity;
+
+        while remaining > 0 {
+            if routes.len() >= MAX_ROUTES_IN_MULTI_ROUTE {
+                return None;
+            }
+            let route_min_capacity = cmp::max(cmp::min(min_route_capacity, remaining), 1);
+            let route =
+                self.get_cheapest_route(a, b, route_min_capacity, remaining, opt_exclude, &used)?;
+            let route_capacity = self.get_route_capacity(&route, &used)?;
+            let rate = self.get_route_rate(&route)?;
+
+            // Assign the capacity of the route's edges to this route:
+            for i in 0..route.len().checked_sub(1)? {
+                let used_capacity = used
+                    .entry((route[i].clone(), route[i + 1].clone()))
+                    .or_insert(0);
+                *used_capacity = used_capacity.saturating_add(route_capacity);
+            }
+
+            remaining = remaining.saturating_sub(route_capacity);
+            routes.push(CapacityRoute {
+                route,
+                capacity: route_capacity,
+                rate,
+            });
+        }
+
+        Some(CapacityMultiRoute { routes })
+    }
+}
+
+/// Calculate the total fees paid for sending `amount` credits through a multi route.
+/// Credits are assigned to the routes in order, filling every route up to its capacity.
+fn calc_multi_route_fee<N, T>(
+    multi_route: &CapacityMultiRoute<N, u128, T>,
+    amount: u128,
+) -> Option<u128>
+where
+    T: LinearRate<K = u128>,
+{
+    let mut remaining = amount;
+    let mut total_fee = 0u128;
+    for route in &multi_route.routes {
+        let route_amount = cmp::min(route.capacity, remaining);
+        if route_amount == 0 {
+            break;
+        }
+        total_fee = total_fee.checked_add(route.rate.calc_fee(route_amount)?)?;
+        remaining -= route_amount;
+    }
+    Some(total_fee)
+}
+
+/// Do the two multi routes go through the exact same routes?
+fn same_routes<N, T>(
+    multi_route1: &CapacityMultiRoute<N, u128, T>,
+    multi_route2: &CapacityMultiRoute<N, u128, T>,
+) -> bool
+where
+    N: cmp::Eq,
+{
+    multi_route1.routes.len() == multi_route2.routes.len()
+        && multi_route1
+            .routes
+            .iter()
+            .zip(multi_route2.routes.iter())
+            .all(|(route1, route2)| route1.route == route2.route)
 }
 
 impl<N, T> CapacityGraph for SimpleCapacityGraph<N, T>
 where
     N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
-    T: LinearRate + Clone,
+    T: LinearRate<K = u128> + Clone,
 {
     type Node = N;
     type Capacity = u128;
@@ -204,10 +407,15 @@ where
         capacity_edge: CapacityEdge<u128, T>,
     ) -> Option<CapacityEdge<u128, T>> {
         let a_entry = self.nodes.entry(a).or_insert_with(NodeEdges::new);
-        a_entry
-            .edges
-            .insert(b, Edge::new(capacity_edge))
-            .map(|edge| edge.capacity_edge)
+        let mut edge = Edge::new(capacity_edge);
+        // Updating an edge does not restart its grace period as a one sided edge, and does not
+        // reset its reliability score:
+        if let Some(old_edge) = a_entry.edges.get(&b) {
+            edge.one_sided_age = old_edge.one_sided_age;
+            edge.unreliability = old_edge.unreliability;
+            edge.failure_reporters = old_edge.failure_reporters.clone();
+        }
+        a_entry.edges.insert(b, edge).map(|edge| edge.capacity_edge)
     }
 
     /// Remove an edge from the graph
@@ -244,20 +452,195 @@ where
         capacity: u128,
         opt_exclude: Option<(&N, &N)>,
     ) -> Vec<CapacityMultiRoute<N, u128, T>> {
-        option_to_vec(self.get_multi_route(a, b, capacity, opt_exclude))
+        let mut candidates = option_to_vec(self.get_multi_route(a, b, capacity, opt_exclude));
+
+        // Attempt to split the payment between multiple routes, allowing routes of decreasing
+        // capacity:
+        for split_level in 1..=MAX_SPLIT_LEVEL {
+            let min_route_capacity = cmp::max(capacity >> split_level, 1);
+            let multi_route =
+                match self.get_split_multi_route(a, b, capacity, min_route_capacity, opt_exclude) {
+                    Some(multi_route) => multi_route,
+                    None => continue,
+                };
+            if candidates
+                .iter()
+                .all(|candidate| !same_routes(candidate, &multi_route))
+            {
+                candidates.push(multi_route);
+            }
+            if min_route_capacity == 1 {
+                break;
+            }
+        }
+
+        // Rank by total fees:
+        let mut ranked: Vec<_> = candidates
+            .into_iter()
+            .filter_map(|multi_route| {
+                let fee = calc_multi_route_fee(&multi_route, capacity)?;
+                Some((fee, multi_route))
+            })
+            .collect();
+        ranked.sort_by_key(|(fee, multi_route)| (*fee, multi_route.routes.len()));
+        ranked
+            .into_iter()
+            .take(MAX_MULTI_ROUTES)
+            .map(|(_fee, multi_route)| multi_route)
+            .collect()
     }
 
     fn tick(&mut self, a: &N) {
+        let one_sided: HashSet<N> = match self.nodes.get(a) {
+            Some(a_edges) => a_edges
+                .edges
+                .keys()
+                .filter(|b| {
+                    self.nodes
+                        .get(b)
+                        .map_or(true, |b_edges| !b_edges.edges.contains_key(a))
+                })
+                .cloned()
+                .collect(),
+            None => return,
+        };
         if let Some(node_edges) = self.nodes.get_mut(a) {
-            node_edges.tick();
+            node_edges.tick(&one_sided);
+        }
+    }
+
+    fn report_route(&mut self, route: &[N], edge_outcomes: &[EdgeOutcome]) {
+        let (reporter, first_hop) = match route {
+            [reporter, first_hop, ..] if edge_outcomes.len() == route.len() - 1 => {
+                (reporter, first_hop)
+            }
+            _ => return,
+        };
+        // Only a node that owns the first edge of the route could have used this route:
+        let has_first_edge = self.nodes.get(reporter).map_or(false, |reporter_edges| {
+            reporter_edges.edges.contains_key(first_hop)
+        });
+        if !has_first_edge {
+            return;
+        }
+
+        // The reporter can only know for sure that its own edge (The first edge) has failed.
+        // Failures of other edges can not be verified, and are considered unknown.
+        let edge_outcomes: Vec<_> = edge_outcomes
+            .iter()
+            .enumerate()
+            .map(|(index, edge_outcome)| match (index, edge_outcome) {
+                (0, _) | (_, EdgeOutcome::Success) | (_, EdgeOutcome::Unknown) => *edge_outcome,
+                (_, EdgeOutcome::Failure) => EdgeOutcome::Unknown,
+            })
+            .collect();
+
+        // If we know which edge failed, the following edges were never used.
+        // Otherwise, the penalty is divided between all the edges that might have failed.
+        let num_unknown = edge_outcomes
+            .iter()
+            .filter(|edge_outcome| **edge_outcome == EdgeOutcome::Unknown)
+            .count();
+        let unknown_penalty = if edge_outcomes.contains(&EdgeOutcome::Failure) || num_unknown == 0 {
+            0
+        } else {
+            cmp::max(
+                ROUTE_FAILURE_PENALTY / u32::try_from(num_unknown).unwrap_or(u32::max_value()),
+                1,
+            )
+        };
+
+        for (pair, edge_outcome) in route.windows(2).zip(edge_outcomes) {
+            let (a, b) = (&pair[0], &pair[1]);
+            let edge = match self
+                .nodes
+                .get_mut(a)
+                .and_then(|a_edges| a_edges.edges.get_mut(b))
+            {
+                Some(edge) => edge,
+                None => continue,
+            };
+            let penalty = match edge_outcome {
+                EdgeOutcome::Success => {
+                    edge.unreliability /= 2;
+                    edge.failure_reporters.clear();
+                    continue;
+                }
+                EdgeOutcome::Failure => ROUTE_FAILURE_PENALTY,
+                EdgeOutcome::Unknown if unknown_penalty > 0 => unknown_penalty,
+                EdgeOutcome::Unknown => continue,
+            };
+            edge.unreliability = cmp::min(
+                edge.unreliability.saturating_add(penalty),
+                MAX_UNRELIABILITY,
+            );
+            if edge.failure_reporters.len() < MIN_FAILURE_REPORTERS {
+                edge.failure_reporters.insert(reporter.clone());
+            }
+
+            // Expire edges that fail too often, without waiting for them to get old:
+            if edge.unreliability >= MAX_UNRELIABILITY
+                && edge.failure_reporters.len() >= MIN_FAILURE_REPORTERS
+            {
+                let _ = self.remove_edge(a, b);
+            }
+        }
+    }
+
+    fn stats(&self) -> GraphStats {
+        let mut nodes = HashSet::new();
+        let mut num_edges = 0;
+        for (a, a_edges) in &self.nodes {
+            nodes.insert(a);
+            nodes.extend(a_edges.edges.keys());
+            num_edges += a_edges.edges.len();
+        }
+        GraphStats {
+            num_nodes: nodes.len(),
+            num_edges,
         }
     }
 }
 
+impl<N, T> PersistentGraph for SimpleCapacityGraph<N, T>
+where
+    N: cmp::Eq + hash::Hash + Clone + std::fmt::Debug,
+    T: LinearRate<K = u128> + Clone,
+{
+    fn export_edges(&self) -> Vec<StoredEdge<N, T>> {
+        let mut stored_edges = Vec::new();
+        for (a, a_edges) in &self.nodes {
+            for (b, edge) in &a_edges.edges {
+                stored_edges.push(StoredEdge {
+                    from: a.clone(),
+                    to: b.clone(),
+                    recv_capacity: edge.capacity_edge.recv_capacity,
+                    rate: edge.capacity_edge.rate.clone(),
+                    age: edge.age,
+                    unreliability: edge.unreliability,
+                });
+            }
+        }
+        stored_edges
+    }
+
+    fn import_edge(&mut self, stored_edge: StoredEdge<N, T>) {
+        let mut edge = Edge::with_age(stored_edge.capacity_edge(), stored_edge.age);
+        edge.unreliability = stored_edge.unreliability;
+        let a_entry = self
+            .nodes
+            .entry(stored_edge.from)
+            .or_insert_with(NodeEdges::new);
+        let _ = a_entry.edges.insert(stored_edge.to, edge);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::time::Instant;
+
     use super::super::test_utils::ConstRate;
 
     #[test]
@@ -270,6 +653,29 @@ mod tests {
         assert_eq!(cg.get_send_capacity(&1, &0), 20);
     }
 
+    #[test]
+    fn test_stats() {
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        assert_eq!(
+            cg.stats(),
+            GraphStats {
+                num_nodes: 0,
+                num_edges: 0
+            }
+        );
+
+        cg.update_edge(0, 1, CapacityEdge::new(20, ConstRate(1)));
+        cg.update_edge(1, 0, CapacityEdge::new(5, ConstRate(1)));
+        cg.update_edge(1, 2, CapacityEdge::new(5, ConstRate(1)));
+        assert_eq!(
+            cg.stats(),
+            GraphStats {
+                num_nodes: 3,
+                num_edges: 3
+            }
+        );
+    }
+
     #[test]
     fn test_get_send_capacity_one_sided() {
         let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
@@ -382,6 +788,347 @@ mod tests {
         assert!(cg.get_multi_route(&2, &1, 7, Some((&2, &1))).is_none());
     }
 
+    /// Add edges in both directions between `a` and `b`.
+    /// `rate` is the rate on the edge from `a` to `b`.
+    fn add_channel(
+        cg: &mut SimpleCapacityGraph<u32, ConstRate>,
+        a: u32,
+        b: u32,
+        recv_capacity: u128,
+        rate: u32,
+    ) {
+        cg.update_edge(a, b, CapacityEdge::new(recv_capacity, ConstRate(rate)));
+        cg.update_edge(b, a, CapacityEdge::new(recv_capacity, ConstRate(0)));
+    }
+
+    #[test]
+    fn test_get_multi_route_cheapest() {
+        /*
+         * Example graph:
+         *
+         *    0 --> 1 ------> 3
+         *    |               ^
+         *    V               |
+         *    2 ----> 4 ------+
+         *
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        add_channel(&mut cg, 0, 1, 30, 10);
+        add_channel(&mut cg, 1, 3, 30, 0);
+        add_channel(&mut cg, 0, 2, 30, 1);
+        add_channel(&mut cg, 2, 4, 30, 1);
+        add_channel(&mut cg, 4, 3, 20, 0);
+
+        // The longer route is cheaper:
+        let multi_route = cg.get_multi_route(&0, &3, 20, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 2, 4, 3]);
+        assert_eq!(multi_route.routes[0].capacity, 20);
+        assert_eq!(multi_route.routes[0].rate, ConstRate(2));
+
+        // Only the expensive route has enough capacity:
+        let multi_route = cg.get_multi_route(&0, &3, 25, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1, 3]);
+        assert_eq!(multi_route.routes[0].capacity, 30);
+        assert_eq!(multi_route.routes[0].rate, ConstRate(10));
+
+        // Make the short route cheaper:
+        add_channel(&mut cg, 0, 1, 30, 1);
+        let multi_route = cg.get_multi_route(&0, &3, 20, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1, 3]);
+        assert_eq!(multi_route.routes[0].rate, ConstRate(1));
+
+        // No fees are paid for the last hop:
+        let multi_route = cg.get_multi_route(&0, &1, 20, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1]);
+        assert_eq!(multi_route.routes[0].rate, ConstRate(0));
+    }
+
+    #[test]
+    fn test_report_route() {
+        /*
+         * Example graph:
+         *
+         *    0 --> 1 --> 3
+         *    |           ^
+         *    V           |
+         *    2 ----------+
+         *
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        add_channel(&mut cg, 0, 1, 10_000, 1);
+        add_channel(&mut cg, 1, 3, 10_000, 0);
+        add_channel(&mut cg, 0, 2, 10_000, 2);
+        add_channel(&mut cg, 2, 3, 10_000, 0);
+
+        let multi_route = cg.get_multi_route(&0, &3, 10_000, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1, 3]);
+
+        // After a failure, the more expensive route is preferred:
+        cg.report_route(&[0, 1, 3], &[EdgeOutcome::Success, EdgeOutcome::Unknown]);
+        let multi_route = cg.get_multi_route(&0, &3, 10_000, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 2, 3]);
+
+        // Successes restore the reputation of the route:
+        for _ in 0..8 {
+            cg.report_route(&[0, 1, 3], &[EdgeOutcome::Success, EdgeOutcome::Success]);
+        }
+        let multi_route = cg.get_multi_route(&0, &3, 10_000, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 1, 3]);
+
+        // A single node can not remove an edge, no matter how many failures it reports:
+        for _ in 0..16 {
+            cg.report_route(&[0, 1, 3], &[EdgeOutcome::Success, EdgeOutcome::Unknown]);
+        }
+        assert_eq!(
+            cg.get_edge(&1, &3).unwrap().unreliability,
+            MAX_UNRELIABILITY
+        );
+
+        // Edges that fail too often according to multiple nodes are removed:
+        add_channel(&mut cg, 4, 1, 10_000, 0);
+        add_channel(&mut cg, 5, 1, 10_000, 0);
+        cg.report_route(&[4, 1, 3], &[EdgeOutcome::Success, EdgeOutcome::Unknown]);
+        assert!(cg.get_edge(&1, &3).is_some());
+        cg.report_route(&[5, 1, 3], &[EdgeOutcome::Success, EdgeOutcome::Unknown]);
+        assert!(cg.get_edge(&1, &3).is_none());
+        assert!(cg.get_edge(&0, &1).is_some());
+        assert!(cg.get_edge(&0, &2).is_some());
+
+        let multi_route = cg.get_multi_route(&0, &3, 10_000, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 2, 3]);
+
+        // A failure of the first edge is known, so the following edges are not penalized:
+        cg.report_route(&[0, 2, 3], &[EdgeOutcome::Failure, EdgeOutcome::Unknown]);
+        assert_eq!(
+            cg.get_edge(&0, &2).unwrap().unreliability,
+            ROUTE_FAILURE_PENALTY
+        );
+        assert_eq!(cg.get_edge(&2, &3).unwrap().unreliability, 0);
+
+        // Reports about unknown edges, or with a mismatching amount of outcomes are ignored:
+        cg.report_route(&[0, 6, 3], &[EdgeOutcome::Failure, EdgeOutcome::Unknown]);
+        cg.report_route(&[0, 2], &[EdgeOutcome::Failure, EdgeOutcome::Failure]);
+        cg.report_route(&[0], &[]);
+        cg.report_route(&[], &[]);
+        assert_eq!(
+            cg.get_edge(&0, &2).unwrap().unreliability,
+            ROUTE_FAILURE_PENALTY
+        );
+    }
+
+    #[test]
+    fn test_report_route_forged() {
+        /*
+         * Example graph:
+         *
+         *    0 --> 1 --> 2
+         *
+         *    3 --> 4
+         *
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        add_channel(&mut cg, 0, 1, 10_000, 1);
+        add_channel(&mut cg, 1, 2, 10_000, 1);
+        add_channel(&mut cg, 3, 4, 10_000, 1);
+
+        // Nodes report failures of routes they could not have used, because they don't own the
+        // first edge of the route:
+        for reporter in 3..6 {
+            for _ in 0..16 {
+                cg.report_route(
+                    &[reporter, 1, 2],
+                    &[EdgeOutcome::Failure, EdgeOutcome::Failure],
+                );
+                cg.report_route(&[reporter, 0, 1, 2], &[EdgeOutcome::Unknown; 3]);
+            }
+        }
+        assert_eq!(cg.get_edge(&0, &1).unwrap().unreliability, 0);
+        assert_eq!(cg.get_edge(&1, &2).unwrap().unreliability, 0);
+
+        // Node 3 owns the first edge of the route, but it can not blame a specific edge of other
+        // nodes, and it can not remove edges of other nodes by itself:
+        for _ in 0..16 {
+            cg.report_route(
+                &[3, 4, 1, 2],
+                &[
+                    EdgeOutcome::Success,
+                    EdgeOutcome::Failure,
+                    EdgeOutcome::Failure,
+                ],
+            );
+        }
+        assert_eq!(cg.get_edge(&0, &1).unwrap().unreliability, 0);
+        assert_eq!(
+            cg.get_edge(&1, &2).unwrap().unreliability,
+            MAX_UNRELIABILITY
+        );
+        assert_eq!(cg.get_edge(&3, &4).unwrap().unreliability, 0);
+    }
+
+    #[test]
+    fn test_get_multi_routes_split() {
+        /*
+         * Example graph:
+         *
+         *    0 --> 1 ------> 3
+         *    |               ^
+         *    V               |
+         *    2 --------------+
+         *
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        add_channel(&mut cg, 0, 1, 10, 1);
+        add_channel(&mut cg, 1, 3, 10, 0);
+        add_channel(&mut cg, 0, 2, 10, 2);
+        add_channel(&mut cg, 2, 3, 10, 0);
+
+        // A single route is enough:
+        let multi_routes = cg.get_multi_routes(&0, &3, 10, None);
+        assert_eq!(multi_routes.len(), 1);
+        assert_eq!(multi_routes[0].routes.len(), 1);
+        assert_eq!(multi_routes[0].routes[0].route, vec![0, 1, 3]);
+
+        // The payment must be split, the cheaper route comes first:
+        let multi_routes = cg.get_multi_routes(&0, &3, 15, None);
+        assert_eq!(multi_routes.len(), 1);
+        let routes = &multi_routes[0].routes;
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].route, vec![0, 1, 3]);
+        assert_eq!(routes[0].capacity, 10);
+        assert_eq!(routes[0].rate, ConstRate(1));
+        assert_eq!(routes[1].route, vec![0, 2, 3]);
+        assert_eq!(routes[1].capacity, 10);
+        assert_eq!(routes[1].rate, ConstRate(2));
+
+        // Total capacity is not enough:
+        assert!(cg.get_multi_routes(&0, &3, 21, None).is_empty());
+
+        // Excluding an edge leaves only one route:
+        assert!(cg.get_multi_routes(&0, &3, 15, Some((&0, &2))).is_empty());
+    }
+
+    #[test]
+    fn test_get_multi_routes_shared_edge() {
+        /*
+         * Example graph:
+         *
+         *              +--> 2 --+
+         *              |        V
+         *    0 -----> 1         3
+         *              |        ^
+         *              +--> 4 --+
+         *
+         */
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        add_channel(&mut cg, 0, 1, 20, 1);
+        add_channel(&mut cg, 1, 2, 10, 1);
+        add_channel(&mut cg, 2, 3, 10, 0);
+        add_channel(&mut cg, 1, 4, 10, 3);
+        add_channel(&mut cg, 4, 3, 10, 0);
+
+        // Both routes share the edge 0 --> 1:
+        let multi_routes = cg.get_multi_routes(&0, &3, 20, None);
+        assert_eq!(multi_routes.len(), 1);
+        let routes = &multi_routes[0].routes;
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].route, vec![0, 1, 2, 3]);
+        assert_eq!(routes[0].capacity, 10);
+        assert_eq!(routes[1].route, vec![0, 1, 4, 3]);
+        assert_eq!(routes[1].capacity, 10);
+
+        // The shared edge limits the total capacity:
+        add_channel(&mut cg, 1, 2, 15, 1);
+        add_channel(&mut cg, 2, 3, 15, 0);
+        let multi_routes = cg.get_multi_routes(&0, &3, 20, None);
+        let routes = &multi_routes[0].routes;
+        assert_eq!(routes[0].route, vec![0, 1, 2, 3]);
+        assert_eq!(routes[0].capacity, 15);
+        assert_eq!(routes[1].route, vec![0, 1, 4, 3]);
+        assert_eq!(routes[1].capacity, 5);
+
+        assert!(cg.get_multi_routes(&0, &3, 21, None).is_empty());
+    }
+
+    /// A simple deterministic pseudo random generator (xorshift), used to create synthetic graphs.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn next_below(&mut self, bound: u64) -> u64 {
+            self.next() % bound
+        }
+    }
+
+    /// Create a synthetic graph with `num_nodes` nodes, where every node opens channels with
+    /// `degree` random nodes.
+    fn synthetic_capacity_graph(
+        num_nodes: u32,
+        degree: u32,
+        rng: &mut XorShift,
+    ) -> SimpleCapacityGraph<u32, ConstRate> {
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+        for a in 0..num_nodes {
+            for _ in 0..degree {
+                let b = rng.next_below(u64::from(num_nodes)) as u32;
+                if a == b {
+                    continue;
+                }
+                let capacity = u128::from(rng.next_below(1000));
+                cg.update_edge(
+                    a,
+                    b,
+                    CapacityEdge::new(capacity, ConstRate(rng.next_below(10) as u32)),
+                );
+                cg.update_edge(
+                    b,
+                    a,
+                    CapacityEdge::new(capacity, ConstRate(rng.next_below(10) as u32)),
+                );
+            }
+        }
+        cg
+    }
+
+    /// Benchmark route search (Including split routes) on a synthetic graph of 100k nodes.
+    /// Run with: cargo test --release -p offset-index-server bench_ -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_get_multi_route_100k_nodes() {
+        let num_nodes = 100_000;
+        let num_queries = 100;
+        let mut rng = XorShift(0x1234_5678_9abc_def1);
+
+        let start = Instant::now();
+        let cg = synthetic_capacity_graph(num_nodes, 4, &mut rng);
+        println!("Graph construction: {:?}", start.elapsed());
+
+        for &capacity in &[1u128, 100, 500] {
+            let mut num_found = 0;
+            let start = Instant::now();
+            for _ in 0..num_queries {
+                let a = rng.next_below(u64::from(num_nodes)) as u32;
+                let b = rng.next_below(u64::from(num_nodes)) as u32;
+                if !cg.get_multi_routes(&a, &b, capacity, None).is_empty() {
+                    num_found += 1;
+                }
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "capacity={}: {}/{} routes found, {:?} per query",
+                capacity,
+                num_found,
+                num_queries,
+                elapsed / num_queries
+            );
+        }
+    }
+
     #[test]
     fn test_simple_capacity_graph_tick() {
         let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
@@ -421,4 +1168,41 @@ mod tests {
         assert_eq!(multi_route.routes[0].route, vec![2, 3]);
         assert_eq!(multi_route.routes[0].capacity, 30);
     }
+
+    #[test]
+    fn test_simple_capacity_graph_tick_one_sided() {
+        let mut cg = SimpleCapacityGraph::<u32, ConstRate>::new();
+
+        // 0 <-> 1 is advertised by both sides, 0 -> 2 is only advertised by 0:
+        cg.update_edge(1, 0, CapacityEdge::new(30, ConstRate(1)));
+
+        let max_edge_age = max_edge_age(2);
+        for _ in 0..max_edge_age - 1 {
+            // 0 keeps updating its edges:
+            cg.update_edge(0, 1, CapacityEdge::new(30, ConstRate(1)));
+            cg.update_edge(0, 2, CapacityEdge::new(100, ConstRate(1)));
+            cg.tick(&0);
+            assert!(cg.get_edge(&0, &1).is_some());
+            assert!(cg.get_edge(&0, &2).is_some());
+            // One sided edges are never used for routing:
+            assert!(cg.get_multi_route(&0, &2, 1, None).is_none());
+        }
+
+        // The grace period of 0 -> 2 is over, although it was updated:
+        cg.update_edge(0, 1, CapacityEdge::new(30, ConstRate(1)));
+        cg.update_edge(0, 2, CapacityEdge::new(100, ConstRate(1)));
+        cg.tick(&0);
+        assert!(cg.get_edge(&0, &1).is_some());
+        assert!(cg.get_edge(&0, &2).is_none());
+
+        // Once 2 advertises a matching edge, the edge is kept:
+        cg.update_edge(2, 0, CapacityEdge::new(100, ConstRate(1)));
+        for _ in 0..max_edge_age * 2 {
+            cg.update_edge(0, 1, CapacityEdge::new(30, ConstRate(1)));
+            cg.update_edge(0, 2, CapacityEdge::new(100, ConstRate(1)));
+            cg.tick(&0);
+        }
+        let multi_route = cg.get_multi_route(&0, &2, 100, None).unwrap();
+        assert_eq!(multi_route.routes[0].route, vec![0, 2]);
+    }
 }
@@ -1,19 +1,21 @@
+use serde::{Deserialize, Serialize};
+
 use crate::graph::capacity_graph::LinearRate;
 
 /// A contant rate, used for testing
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConstRate(pub u32);
 
 #[cfg(test)]
 impl LinearRate for ConstRate {
-    type K = u32;
+    type K = u128;
 
     fn zero() -> Self {
         ConstRate(0)
     }
 
     fn calc_fee(&self, _k: Self::K) -> Option<Self::K> {
-        Some(self.0)
+        Some(u128::from(self.0))
     }
 
     fn checked_add(&self, other: &Self) -> Option<Self> {
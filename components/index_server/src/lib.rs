@@ -15,9 +15,15 @@ extern crate log;
 extern crate common;
 
 mod backoff_connector;
+mod client_limits;
 mod graph;
 mod server;
 mod server_loop;
+mod topology;
 mod verifier;
 
+pub use client_limits::ClientLimits;
+pub use graph::graph_shards::GraphStoreConfig;
+pub use graph::graph_store::{FileGraphStore, GraphStoreError};
 pub use server::{index_server, IndexServerError};
+pub use topology::topology_multi_routes;
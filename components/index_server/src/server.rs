@@ -14,10 +14,15 @@ use timer::TimerClient;
 use crypto::identity::compare_public_key;
 use crypto::rand::CryptoRandom;
 
-use crate::server_loop::{server_loop, ClientConn, ServerConn, ServerLoopError};
+use identity::IdentityClient;
+
+use crate::server_loop::{server_loop, AdminConn, ClientConn, ServerConn, ServerLoopError};
 
 use crate::backoff_connector::BackoffConnector;
+use crate::client_limits::ClientLimits;
 use crate::graph::graph_service::create_graph_service;
+use crate::graph::graph_shards::GraphStoreConfig;
+use crate::graph::graph_store::FileGraphStore;
 use crate::graph::simple_capacity_graph::SimpleCapacityGraph;
 use crate::verifier::simple_verifier::SimpleVerifier;
 
@@ -30,23 +35,34 @@ pub enum IndexServerError {
 
 /// Run an index server
 /// Will keep running until an error occurs.
-pub async fn index_server<A, IS, IC, SC, R, GS, S>(
+///
+/// `incoming_admin_connections` should only contain connections from authorized admins.
+/// `client_limits` bounds the resources a single client may consume.
+/// `trusted_servers_updates` delivers the full new set of trusted servers whenever it changes.
+pub async fn index_server<A, TU, IS, IC, IA, SC, R, GS, S>(
     local_public_key: PublicKey,
     trusted_servers: HashMap<PublicKey, A>,
+    trusted_servers_updates: TU,
     incoming_server_connections: IS,
     incoming_client_connections: IC,
+    incoming_admin_connections: IA,
     server_connector: SC,
+    identity_client: IdentityClient,
     mut timer_client: TimerClient,
     ticks_to_live: usize,
     backoff_ticks: usize,
+    client_limits: ClientLimits,
     rng: R,
+    opt_graph_store_config: Option<GraphStoreConfig<FileGraphStore>>,
     graph_service_spawner: GS,
     spawner: S,
 ) -> Result<(), IndexServerError>
 where
-    A: Debug + Send + Sync + Clone + 'static,
+    A: Debug + Send + Sync + Clone + PartialEq + 'static,
+    TU: Stream<Item = HashMap<PublicKey, A>> + Unpin + Send,
     IS: Stream<Item = (PublicKey, ServerConn)> + Unpin + Send,
     IC: Stream<Item = (PublicKey, ClientConn)> + Unpin + Send,
+    IA: Stream<Item = (PublicKey, AdminConn)> + Unpin + Send,
     SC: FutTransform<Input = (PublicKey, A), Output = Option<ServerConn>> + Clone + Send + 'static,
     R: CryptoRandom,
     S: Spawn + Clone + Send,
@@ -54,7 +70,9 @@ where
 {
     let verifier = SimpleVerifier::new(ticks_to_live, rng);
 
-    let graph_client = create_graph_service::<_, _, _, _, SimpleCapacityGraph<_, _>, _, _>(
+    let graph_client = create_graph_service::<_, _, _, SimpleCapacityGraph<_, _>, _, _, _>(
+        opt_graph_store_config,
+        client_limits.max_pending_route_searches,
         graph_service_spawner,
         spawner.clone(),
     )
@@ -70,10 +88,14 @@ where
     server_loop(
         local_public_key,
         trusted_servers,
+        trusted_servers_updates,
         incoming_server_connections,
         incoming_client_connections,
+        incoming_admin_connections,
         backoff_connector,
         graph_client,
+        identity_client,
+        client_limits,
         compare_public_key,
         verifier,
         timer_stream,
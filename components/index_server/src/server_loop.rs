@@ -7,27 +7,38 @@ use futures::task::{Spawn, SpawnExt};
 use futures::{future, select, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 
 use common::conn::{sink_to_sender, BoxStream, ConnPair, FutTransform};
+use common::int_convert::usize_to_u64;
 use common::select_streams::select_streams;
 
+use proto::app_server::messages::now_secs;
 use proto::crypto::{PublicKey, Uid};
 
 use proto::index_server::messages::{
-    ForwardMutationsUpdate, IndexClientToServer, IndexMutation, IndexServerToClient,
-    IndexServerToServer, MultiRoute, MutationsUpdate, ResponseRoutes, RouteCapacityRate,
-    TimeProofLink,
+    CurrencyGraphStatus, ForwardMutationsUpdate, HopOutcome, IndexAdminToServer,
+    IndexClientToServer, IndexMutation, IndexServerStatus, IndexServerToAdmin, IndexServerToClient,
+    IndexServerToServer, MutationsRejected, MutationsUpdate, RejectReason, RequestRejected,
+    ResponseRoutes, ServerConnState, ServerStatus, TimeProofLink,
 };
 
-use proto::funder::messages::{Currency, FriendsRoute, Rate};
+use proto::funder::messages::{Currency, CurrencyPair, ExchangeRate, Rate};
 
+use identity::IdentityClient;
+
+use signature::signature_buff::create_response_topology_signature_buff;
 use signature::verify::verify_mutations_update;
 
-use crate::graph::capacity_graph::{CapacityEdge, LinearRate};
+use crate::client_limits::{ClientLimits, RateLimiter};
+use crate::graph::capacity_graph::{CapacityEdge, EdgeOutcome, LinearRate};
 use crate::graph::graph_service::{GraphClient, GraphClientError};
+use crate::topology::{
+    create_response_topology, to_multi_route, topology_from_stored_edges, TopologyEdges,
+};
 
 use crate::verifier::Verifier;
 
 pub type ServerConn = ConnPair<IndexServerToServer, IndexServerToServer>;
 pub type ClientConn = ConnPair<IndexServerToClient, IndexClientToServer>;
+pub type AdminConn = ConnPair<IndexServerToAdmin, IndexAdminToServer>;
 
 // TODO: Find a more scalable solution to the EVENT_BUFFER issue.
 // It might be true that a deadlock could happen to the event buffer
@@ -35,6 +46,12 @@ pub type ClientConn = ConnPair<IndexServerToClient, IndexClientToServer>;
 const EVENT_BUFFER: usize = 0x100;
 const SERVER_SENDER_BUFFER: usize = 0x20;
 const CLIENT_SENDER_BUFFER: usize = 0x20;
+/// Timer ticks are dropped if the client handler is too busy to receive them.
+const CLIENT_TICK_BUFFER: usize = 0x4;
+
+/// A connected server that did not send us a time hash for this amount of ticks is considered
+/// lagging. Mutations forwarded through a lagging server might fail verification.
+const MAX_SERVER_LAG_TICKS: usize = 0x8;
 
 impl LinearRate for Rate {
     /// Type used to count credits
@@ -63,7 +80,10 @@ pub enum ServerLoopError {
     GraphClientError,
     ClientEventSenderError,
     ClientSenderError,
+    AdminEventSenderError,
+    AdminSenderError,
     RemoteSendError,
+    RequestSignatureError,
 }
 
 /// A connected remote entity
@@ -97,6 +117,13 @@ impl<T> Connected<T> {
     }
 }
 
+#[derive(Debug)]
+struct ConnectedClient {
+    connected: Connected<IndexServerToClient>,
+    /// Notifies the client handler about timer ticks
+    tick_sender: mpsc::Sender<()>,
+}
+
 #[derive(Debug)]
 struct ServerInitiating {
     #[allow(unused)]
@@ -110,9 +137,14 @@ enum RemoteServerState {
     Listening,
 }
 
+/// Identifies a single connection (or connection attempt) to a remote server.
+/// Events that belong to an older connection of the same server are ignored.
+type ServerGeneration = u64;
+
 #[derive(Debug)]
 struct RemoteServer<A> {
     address: A,
+    generation: ServerGeneration,
     state: RemoteServerState,
 }
 
@@ -120,11 +152,21 @@ struct IndexServer<A, S, SC, V, CMP> {
     local_public_key: PublicKey,
     server_connector: SC,
     graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
+    /// Used to sign topology responses sent to clients
+    identity_client: IdentityClient,
     verifier: V,
     compare_public_key: CMP,
     remote_servers: HashMap<PublicKey, RemoteServer<A>>,
-    clients: HashMap<PublicKey, Connected<IndexServerToClient>>,
-    event_sender: mpsc::Sender<IndexServerEvent>,
+    /// Generation to be given to the next remote server connection
+    next_server_generation: ServerGeneration,
+    clients: HashMap<PublicKey, ConnectedClient>,
+    /// Limits on the resources a single client may consume
+    client_limits: ClientLimits,
+    /// Exchange rates announced by nodes. Expired exchange rates are removed.
+    /// Routes are searched separately for every currency, so exchange rates are not used for
+    /// route finding (There are no cross currency routes). They are only reported to admins.
+    exchange_rates: HashMap<PublicKey, HashMap<CurrencyPair, ExchangeRate>>,
+    event_sender: mpsc::Sender<IndexServerEvent<A>>,
     spawner: S,
 }
 
@@ -136,12 +178,15 @@ impl From<GraphClientError> for ServerLoopError {
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
-enum IndexServerEvent {
+enum IndexServerEvent<A> {
     ServerConnection((PublicKey, ServerConn)),
-    FromServer((PublicKey, Option<IndexServerToServer>)),
+    FromServer((PublicKey, ServerGeneration, Option<IndexServerToServer>)),
     ClientConnection((PublicKey, ClientConn)),
     ClientClosed(PublicKey),
     ClientMutationsUpdate(MutationsUpdate),
+    AdminConnection((PublicKey, AdminConn)),
+    AdminRequestStatus((Uid, oneshot::Sender<IndexServerStatus>)),
+    TrustedServersUpdate(HashMap<PublicKey, A>),
     TimerTick,
     TimerClosed,
     ClientListenerClosed,
@@ -164,7 +209,7 @@ fn is_listen_server(local_public_key: &PublicKey, friend_public_key: &PublicKey)
 
 impl<A, S, SC, V, CMP> IndexServer<A, S, SC, V, CMP>
 where
-    A: Clone + Send + std::fmt::Debug + 'static,
+    A: Clone + PartialEq + Send + std::fmt::Debug + 'static,
     S: Spawn + Send,
     SC: FutTransform<Input = (PublicKey, A), Output = Option<ServerConn>> + Clone + Send + 'static,
     V: Verifier<Node = PublicKey, Neighbor = PublicKey, SessionId = Uid>,
@@ -175,19 +220,25 @@ where
         trusted_servers: HashMap<PublicKey, A>,
         server_connector: SC,
         graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
+        identity_client: IdentityClient,
+        client_limits: ClientLimits,
         compare_public_key: CMP,
         verifier: V,
-        event_sender: mpsc::Sender<IndexServerEvent>,
+        event_sender: mpsc::Sender<IndexServerEvent<A>>,
         spawner: S,
     ) -> Result<Self, ServerLoopError> {
         let mut index_server = IndexServer {
             local_public_key,
             server_connector,
             graph_client,
+            identity_client,
             verifier,
             compare_public_key,
             remote_servers: HashMap::new(),
+            next_server_generation: 0,
             clients: HashMap::new(),
+            client_limits,
+            exchange_rates: HashMap::new(),
             event_sender,
             spawner,
         };
@@ -217,14 +268,32 @@ where
             )
     }
 
+    /// Allocate a generation for a new remote server connection
+    fn new_server_generation(&mut self) -> ServerGeneration {
+        let generation = self.next_server_generation;
+        self.next_server_generation = self.next_server_generation.wrapping_add(1);
+        generation
+    }
+
+    /// Is `generation` the generation of the current connection to the server `public_key`?
+    fn is_current_server(&self, public_key: &PublicKey, generation: ServerGeneration) -> bool {
+        self.remote_servers
+            .get(public_key)
+            .map_or(false, |remote_server| {
+                remote_server.generation == generation
+            })
+    }
+
     pub fn spawn_server(
         &mut self,
         public_key: PublicKey,
         address: A,
     ) -> Result<RemoteServer<A>, ServerLoopError> {
+        let generation = self.new_server_generation();
         if (self.compare_public_key)(&self.local_public_key, &public_key) == Ordering::Less {
             return Ok(RemoteServer {
                 address,
+                generation,
                 state: RemoteServerState::Listening,
             });
         }
@@ -252,7 +321,7 @@ where
                 // Failed to connect, report that the server was closed
                 // TODO: Test this functionality:
                 let _ = c_event_sender
-                    .send(IndexServerEvent::FromServer((public_key, None)))
+                    .send(IndexServerEvent::FromServer((public_key, generation, None)))
                     .await;
             }
         };
@@ -263,7 +332,11 @@ where
 
         let state = RemoteServerState::Initiating(ServerInitiating { close_sender });
 
-        Ok(RemoteServer { address, state })
+        Ok(RemoteServer {
+            address,
+            generation,
+            state,
+        })
     }
 
     pub async fn handle_forward_mutations_update(
@@ -359,6 +432,29 @@ where
                         )
                         .await?;
                 }
+                IndexMutation::SetExchangeRate(exchange_rate) => {
+                    // An exchange rate whose validity window is over is of no use:
+                    if exchange_rate.valid_until > now_secs() {
+                        let _ = self
+                            .exchange_rates
+                            .entry(mutations_update.node_public_key.clone())
+                            .or_insert_with(HashMap::new)
+                            .insert(exchange_rate.currency_pair.clone(), exchange_rate.clone());
+                    }
+                }
+                IndexMutation::RemoveExchangeRate(currency_pair) => {
+                    if let Some(node_exchange_rates) = self
+                        .exchange_rates
+                        .get_mut(&mutations_update.node_public_key)
+                    {
+                        let _ = node_exchange_rates.remove(currency_pair);
+                        if node_exchange_rates.is_empty() {
+                            let _ = self
+                                .exchange_rates
+                                .remove(&mutations_update.node_public_key);
+                        }
+                    }
+                }
             }
         }
 
@@ -392,9 +488,60 @@ where
         Ok(())
     }
 
+    /// Replace the set of trusted servers.
+    /// Connections to removed servers are closed (Or connection attempts are cancelled), and new
+    /// servers are connected. A server with a changed address is reconnected.
+    pub fn update_trusted_servers(
+        &mut self,
+        trusted_servers: HashMap<PublicKey, A>,
+    ) -> Result<(), ServerLoopError> {
+        let removed_servers = self
+            .remote_servers
+            .iter()
+            .filter(|(public_key, remote_server)| {
+                trusted_servers.get(public_key) != Some(&remote_server.address)
+            })
+            .map(|(public_key, _)| public_key.clone())
+            .collect::<Vec<_>>();
+
+        for public_key in removed_servers {
+            info!(
+                "{}: Server {:?} was removed from the trusted servers",
+                self.local_public_key[0], public_key
+            );
+            // Dropping the remote server closes the connection:
+            let _ = self.remote_servers.remove(&public_key);
+            // Stop including the server's time hashes in our ticks:
+            let _ = self.verifier.remove_neighbor(&public_key);
+        }
+
+        for (public_key, address) in trusted_servers {
+            if self.remote_servers.contains_key(&public_key) {
+                continue;
+            }
+            info!(
+                "{}: Server {:?} was added to the trusted servers",
+                self.local_public_key[0], public_key
+            );
+            let remote_server = self.spawn_server(public_key.clone(), address)?;
+            self.remote_servers.insert(public_key, remote_server);
+        }
+        Ok(())
+    }
+
     pub async fn handle_timer_tick(&mut self) -> Result<(), ServerLoopError> {
         let (time_hash, removed_nodes) = self.verifier.tick();
 
+        // Lags grow by one every tick, so every lagging server is reported once:
+        for (server_public_key, lag) in self.verifier.status().neighbor_lags {
+            if lag == MAX_SERVER_LAG_TICKS {
+                warn!(
+                    "{}: No time hash from server {:?} for {} ticks. Federation might be partitioned.",
+                    self.local_public_key[0], server_public_key, lag
+                );
+            }
+        }
+
         // Try to send the time tick to all servers. Sending to some of them might fail:
         for (_server_public_key, connected_server) in self.iter_connected_servers() {
             let _ = connected_server.try_send(IndexServerToServer::TimeHash(time_hash.clone()));
@@ -402,29 +549,215 @@ where
 
         // Try to send time tick to all connected clients:
         for connected_client in self.clients.values_mut() {
-            let _ = connected_client.try_send(IndexServerToClient::TimeHash(time_hash.clone()));
+            let _ = connected_client
+                .connected
+                .try_send(IndexServerToClient::TimeHash(time_hash.clone()));
+            // Client handlers use ticks to refill the client's quotas:
+            let _ = connected_client.tick_sender.try_send(());
         }
 
+        // Forget expired exchange rates:
+        let now = now_secs();
+        self.exchange_rates
+            .retain(|_node_public_key, node_exchange_rates| {
+                node_exchange_rates
+                    .retain(|_currency_pair, exchange_rate| exchange_rate.valid_until > now);
+                !node_exchange_rates.is_empty()
+            });
+
         // Update the graph service about removed nodes:
         for node_public_key in removed_nodes {
+            let _ = self.exchange_rates.remove(&node_public_key);
             self.graph_client.remove_node(node_public_key).await?;
         }
 
         Ok(())
     }
+
+    /// Collect information about the state of the index server, to be inspected by an admin
+    pub async fn get_status(
+        &mut self,
+        request_id: Uid,
+    ) -> Result<IndexServerStatus, ServerLoopError> {
+        let verifier_status = self.verifier.status();
+        let neighbor_lags: HashMap<PublicKey, usize> =
+            verifier_status.neighbor_lags.into_iter().collect();
+
+        let servers = self
+            .remote_servers
+            .iter()
+            .map(|(public_key, remote_server)| {
+                let (conn_state, lag) = match remote_server.state {
+                    // The lag of a server is tracked from the moment it connects:
+                    RemoteServerState::Connected(_) => (
+                        ServerConnState::Connected,
+                        neighbor_lags.get(public_key).cloned().unwrap_or(0),
+                    ),
+                    RemoteServerState::Initiating(_) => (ServerConnState::Initiating, 0),
+                    RemoteServerState::Listening => (ServerConnState::Listening, 0),
+                };
+                ServerStatus {
+                    public_key: public_key.clone(),
+                    conn_state,
+                    ticks_since_time_hash: usize_to_u64(lag).unwrap(),
+                    is_lagging: lag >= MAX_SERVER_LAG_TICKS,
+                }
+            })
+            .collect();
+
+        let currency_graphs = self
+            .graph_client
+            .get_stats()
+            .await?
+            .into_iter()
+            .map(|(currency, opt_stats)| match opt_stats {
+                Some(stats) => CurrencyGraphStatus {
+                    currency,
+                    is_loaded: true,
+                    num_nodes: usize_to_u64(stats.num_nodes).unwrap(),
+                    num_edges: usize_to_u64(stats.num_edges).unwrap(),
+                },
+                None => CurrencyGraphStatus {
+                    currency,
+                    is_loaded: false,
+                    num_nodes: 0,
+                    num_edges: 0,
+                },
+            })
+            .collect();
+
+        // Exchange rates might not be valid yet:
+        let now = now_secs();
+        let num_exchange_rates = self
+            .exchange_rates
+            .values()
+            .flat_map(|node_exchange_rates| node_exchange_rates.values())
+            .filter(|exchange_rate| exchange_rate.is_valid_at(now))
+            .count();
+
+        Ok(IndexServerStatus {
+            request_id,
+            servers,
+            num_clients: usize_to_u64(self.clients.len()).unwrap(),
+            num_nodes: usize_to_u64(verifier_status.num_nodes).unwrap(),
+            currency_graphs,
+            num_exchange_rates: usize_to_u64(num_exchange_rates).unwrap(),
+        })
+    }
+}
+
+async fn admin_handler<A>(
+    admin_conn: AdminConn,
+    mut event_sender: mpsc::Sender<IndexServerEvent<A>>,
+) -> Result<(), ServerLoopError> {
+    let (mut sender, mut receiver) = admin_conn.split();
+
+    while let Some(admin_msg) = receiver.next().await {
+        match admin_msg {
+            IndexAdminToServer::RequestStatus(request_id) => {
+                // The status is collected by the main server future:
+                let (response_sender, response_receiver) = oneshot::channel();
+                event_sender
+                    .send(IndexServerEvent::AdminRequestStatus((
+                        request_id,
+                        response_sender,
+                    )))
+                    .await
+                    .map_err(|_| ServerLoopError::AdminEventSenderError)?;
+                let index_server_status = response_receiver
+                    .await
+                    .map_err(|_| ServerLoopError::AdminEventSenderError)?;
+
+                sender
+                    .send(IndexServerToAdmin::ResponseStatus(index_server_status))
+                    .await
+                    .map_err(|_| ServerLoopError::AdminSenderError)?;
+            }
+        }
+    }
+    Ok(())
 }
 
-async fn client_handler(
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum ClientHandlerEvent {
+    FromClient(IndexClientToServer),
+    ClientClosed,
+    TimerTick,
+}
+
+async fn client_handler<A>(
     mut graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
-    _public_key: PublicKey, // TODO: unused?
+    public_key: PublicKey,
     client_conn: ClientConn,
-    mut event_sender: mpsc::Sender<IndexServerEvent>,
+    mut identity_client: IdentityClient,
+    client_limits: ClientLimits,
+    tick_receiver: mpsc::Receiver<()>,
+    mut event_sender: mpsc::Sender<IndexServerEvent<A>>,
 ) -> Result<(), ServerLoopError> {
-    let (mut sender, mut receiver) = client_conn.split();
+    let (mut sender, receiver) = client_conn.split();
+    // The last topology sent to the client, for every currency.
+    // Used to send only the changes in incremental topology responses.
+    let mut sent_topologies: HashMap<Currency, TopologyEdges> = HashMap::new();
+
+    // Quotas of the client, refilled every tick:
+    let mut requests_limiter = RateLimiter::new(
+        client_limits.max_request_burst,
+        client_limits.requests_per_tick,
+    );
+    let mut mutations_limiter = RateLimiter::new(
+        client_limits.max_mutations_burst,
+        client_limits.mutations_per_tick,
+    );
+
+    let receiver = receiver
+        .map(ClientHandlerEvent::FromClient)
+        .chain(stream::once(future::ready(
+            ClientHandlerEvent::ClientClosed,
+        )));
+    let tick_receiver = tick_receiver.map(|_| ClientHandlerEvent::TimerTick);
+    let mut events = select_streams![receiver, tick_receiver];
+
+    while let Some(event) = events.next().await {
+        let client_msg = match event {
+            ClientHandlerEvent::FromClient(client_msg) => client_msg,
+            ClientHandlerEvent::ClientClosed => break,
+            ClientHandlerEvent::TimerTick => {
+                requests_limiter.tick();
+                mutations_limiter.tick();
+                continue;
+            }
+        };
 
-    while let Some(client_msg) = receiver.next().await {
         match client_msg {
             IndexClientToServer::MutationsUpdate(mutations_update) => {
+                let opt_reason = if mutations_update.index_mutations.len()
+                    > client_limits.max_mutations_per_update
+                {
+                    Some(RejectReason::TooManyMutations)
+                } else if !mutations_limiter.try_consume() {
+                    Some(RejectReason::RateLimited)
+                } else {
+                    None
+                };
+
+                if let Some(reason) = opt_reason {
+                    warn!(
+                        "client_handler: Rejected mutations update from {:?}: {:?}",
+                        public_key, reason
+                    );
+                    let message = IndexServerToClient::MutationsRejected(MutationsRejected {
+                        session_id: mutations_update.session_id,
+                        counter: mutations_update.counter,
+                        reason,
+                    });
+                    sender
+                        .send(message)
+                        .await
+                        .map_err(|_| ServerLoopError::ClientSenderError)?;
+                    continue;
+                }
+
                 // Forward to main server future to process:
                 event_sender
                     .send(IndexServerEvent::ClientMutationsUpdate(mutations_update))
@@ -432,11 +765,24 @@ async fn client_handler(
                     .map_err(|_| ServerLoopError::ClientEventSenderError)?;
             }
             IndexClientToServer::RequestRoutes(request_routes) => {
+                if !requests_limiter.try_consume() {
+                    warn!("client_handler: Client {:?} is rate limited", public_key);
+                    let message = IndexServerToClient::RequestRejected(RequestRejected {
+                        request_id: request_routes.request_id,
+                        reason: RejectReason::RateLimited,
+                    });
+                    sender
+                        .send(message)
+                        .await
+                        .map_err(|_| ServerLoopError::ClientSenderError)?;
+                    continue;
+                }
+
                 let opt_exclude_edge = request_routes
                     .opt_exclude
                     .map(|edge| (edge.from_public_key.clone(), edge.to_public_key));
 
-                let graph_multi_routes = graph_client
+                let graph_multi_routes_res = graph_client
                     .get_multi_routes(
                         request_routes.currency.clone(),
                         request_routes.source.clone(),
@@ -444,46 +790,120 @@ async fn client_handler(
                         request_routes.capacity,
                         opt_exclude_edge,
                     )
-                    .await?;
-                let multi_routes = graph_multi_routes
-                    .into_iter()
-                    .map(|graph_multi_route| MultiRoute {
-                        routes: graph_multi_route
-                            .routes
+                    .await;
+
+                let message = match graph_multi_routes_res {
+                    Ok(graph_multi_routes) => {
+                        let multi_routes = graph_multi_routes
                             .into_iter()
-                            .map(|graph_route| RouteCapacityRate {
-                                route: FriendsRoute {
-                                    public_keys: graph_route.route,
-                                },
-                                capacity: graph_route.capacity,
-                                rate: graph_route.rate,
-                            })
-                            .collect(),
-                    })
-                    .collect::<Vec<_>>();
+                            .map(to_multi_route)
+                            .collect::<Vec<_>>();
 
-                let response_routes = ResponseRoutes {
-                    request_id: request_routes.request_id,
-                    multi_routes,
+                        IndexServerToClient::ResponseRoutes(ResponseRoutes {
+                            request_id: request_routes.request_id,
+                            multi_routes,
+                        })
+                    }
+                    Err(GraphClientError::Busy) => {
+                        IndexServerToClient::RequestRejected(RequestRejected {
+                            request_id: request_routes.request_id,
+                            reason: RejectReason::ServerBusy,
+                        })
+                    }
+                    Err(e) => return Err(e.into()),
                 };
-                let message = IndexServerToClient::ResponseRoutes(response_routes);
                 sender
                     .send(message)
                     .await
                     .map_err(|_| ServerLoopError::ClientSenderError)?;
             }
+            IndexClientToServer::RequestTopology(request_topology) => {
+                if !requests_limiter.try_consume() {
+                    warn!("client_handler: Client {:?} is rate limited", public_key);
+                    let message = IndexServerToClient::RequestRejected(RequestRejected {
+                        request_id: request_topology.request_id,
+                        reason: RejectReason::RateLimited,
+                    });
+                    sender
+                        .send(message)
+                        .await
+                        .map_err(|_| ServerLoopError::ClientSenderError)?;
+                    continue;
+                }
+
+                let edges = topology_from_stored_edges(
+                    graph_client
+                        .export_edges(request_topology.currency.clone())
+                        .await?,
+                );
+
+                let sent_edges = sent_topologies
+                    .entry(request_topology.currency.clone())
+                    .or_insert_with(TopologyEdges::new);
+                let mut response_topology = create_response_topology(
+                    request_topology.request_id,
+                    request_topology.currency,
+                    &edges,
+                    sent_edges,
+                    !request_topology.is_incremental,
+                );
+
+                response_topology.signature = identity_client
+                    .request_signature(create_response_topology_signature_buff(&response_topology))
+                    .await
+                    .map_err(|_| ServerLoopError::RequestSignatureError)?;
+
+                let message = IndexServerToClient::ResponseTopology(response_topology);
+                sender
+                    .send(message)
+                    .await
+                    .map_err(|_| ServerLoopError::ClientSenderError)?;
+            }
+            IndexClientToServer::ReportRouteOutcome(route_outcome) => {
+                if !requests_limiter.try_consume() {
+                    warn!("client_handler: Client {:?} is rate limited", public_key);
+                    continue;
+                }
+                // A client may only report about routes it used for sending credits.
+                // This limits the ability of clients to harm the reputation of arbitrary edges.
+                // The graph further checks that the client owns the first edge of the route.
+                if route_outcome.route.public_keys.first() != Some(&public_key) {
+                    warn!("client_handler: Route outcome not starting at the reporting client");
+                    continue;
+                }
+                let edge_outcomes = route_outcome
+                    .hop_outcomes
+                    .iter()
+                    .map(|hop_outcome| match hop_outcome {
+                        HopOutcome::Success => EdgeOutcome::Success,
+                        HopOutcome::Failure => EdgeOutcome::Failure,
+                        HopOutcome::Unknown => EdgeOutcome::Unknown,
+                    })
+                    .collect();
+                graph_client
+                    .report_route(
+                        route_outcome.currency,
+                        route_outcome.route.public_keys,
+                        edge_outcomes,
+                    )
+                    .await?;
+            }
         }
     }
     Ok(())
 }
 
-pub async fn server_loop<A, IS, IC, SC, CMP, V, TS, S>(
+pub async fn server_loop<A, TU, IS, IC, IA, SC, CMP, V, TS, S>(
     local_public_key: PublicKey,
     trusted_servers: HashMap<PublicKey, A>,
+    trusted_servers_updates: TU,
     incoming_server_connections: IS,
     incoming_client_connections: IC,
+    incoming_admin_connections: IA,
     server_connector: SC,
     graph_client: GraphClient<Currency, PublicKey, u128, Rate>,
+    identity_client: IdentityClient,
+    client_limits: ClientLimits,
     compare_public_key: CMP,
     verifier: V,
     timer_stream: TS,
@@ -491,9 +911,11 @@ pub async fn server_loop<A, IS, IC, SC, CMP, V, TS, S>(
     mut opt_debug_event_sender: Option<mpsc::Sender<()>>,
 ) -> Result<(), ServerLoopError>
 where
-    A: Clone + Send + std::fmt::Debug + 'static,
+    A: Clone + PartialEq + Send + std::fmt::Debug + 'static,
+    TU: Stream<Item = HashMap<PublicKey, A>> + Unpin + Send,
     IS: Stream<Item = (PublicKey, ServerConn)> + Unpin + Send,
     IC: Stream<Item = (PublicKey, ClientConn)> + Unpin + Send,
+    IA: Stream<Item = (PublicKey, AdminConn)> + Unpin + Send,
     SC: FutTransform<Input = (PublicKey, A), Output = Option<ServerConn>> + Clone + Send + 'static,
     V: Verifier<Node = PublicKey, Neighbor = PublicKey, SessionId = Uid>,
     CMP: Clone + Fn(&PublicKey, &PublicKey) -> Ordering + Sync,
@@ -512,6 +934,8 @@ where
         trusted_servers,
         server_connector,
         graph_client,
+        identity_client,
+        client_limits,
         compare_public_key,
         verifier,
         event_sender,
@@ -539,6 +963,15 @@ where
             IndexServerEvent::ClientListenerClosed,
         )));
 
+    // Admin connections are optional. The server keeps running if the admin listener is closed:
+    let incoming_admin_connections =
+        incoming_admin_connections.map(IndexServerEvent::AdminConnection);
+
+    // The trusted servers might never be updated. The server keeps running if this stream is
+    // closed:
+    let trusted_servers_updates =
+        trusted_servers_updates.map(IndexServerEvent::TrustedServersUpdate);
+
     let timer_stream = timer_stream
         .map(|_| IndexServerEvent::TimerTick)
         .chain(stream::once(future::ready(IndexServerEvent::TimerClosed)));
@@ -547,6 +980,8 @@ where
         event_receiver,
         incoming_server_connections,
         incoming_client_connections,
+        incoming_admin_connections,
+        trusted_servers_updates,
         timer_stream
     ];
 
@@ -579,12 +1014,19 @@ where
                 let sender = sink_to_sender(sender, SERVER_SENDER_BUFFER, &spawner);
 
                 remote_server.state = RemoteServerState::Connected(Connected::new(sender));
+                let generation = index_server.new_server_generation();
+                remote_server.generation = generation;
+                // The server is lagging until it sends us time hashes:
+                index_server.verifier.add_neighbor(public_key.clone());
 
                 let c_public_key = public_key.clone();
                 let receiver = receiver
-                    .map(move |msg| IndexServerEvent::FromServer((c_public_key.clone(), Some(msg))))
+                    .map(move |msg| {
+                        IndexServerEvent::FromServer((c_public_key.clone(), generation, Some(msg)))
+                    })
                     .chain(stream::once(future::ready(IndexServerEvent::FromServer((
                         public_key.clone(),
+                        generation,
                         None,
                     )))));
 
@@ -605,26 +1047,39 @@ where
                     .remote_servers
                     .insert(public_key, remote_server);
             }
-            IndexServerEvent::FromServer((public_key, Some(index_server_to_server))) => {
-                index_server
-                    .handle_from_server(public_key, index_server_to_server)
-                    .await?
+            IndexServerEvent::FromServer((
+                public_key,
+                generation,
+                Some(index_server_to_server),
+            )) => {
+                // A server that was removed from the trusted servers (Or whose address was
+                // changed) might still send a few messages before its old connection is closed.
+                // Those messages must not be processed. In particular, the HashClock must not
+                // learn about a removed server again.
+                if index_server.is_current_server(&public_key, generation) {
+                    index_server
+                        .handle_from_server(public_key, index_server_to_server)
+                        .await?
+                } else {
+                    warn!(
+                        "Ignoring message from a stale connection of server {:?}",
+                        public_key
+                    );
+                }
             }
-            IndexServerEvent::FromServer((public_key, None)) => {
+            IndexServerEvent::FromServer((public_key, generation, None)) => {
                 // Server connection closed
-                let old_server = match index_server.remote_servers.remove(&public_key) {
-                    None => {
-                        error!(
-                            "A non existent server {:?} was closed. Aborting.",
-                            public_key
-                        );
-                        continue;
-                    }
-                    Some(old_server) => old_server,
-                };
-                let _ = index_server.verifier.remove_neighbor(&public_key);
-                let server = index_server.spawn_server(public_key.clone(), old_server.address)?;
-                index_server.remote_servers.insert(public_key, server);
+                if index_server.is_current_server(&public_key, generation) {
+                    let old_server = index_server.remote_servers.remove(&public_key).unwrap();
+                    let _ = index_server.verifier.remove_neighbor(&public_key);
+                    let server =
+                        index_server.spawn_server(public_key.clone(), old_server.address)?;
+                    index_server.remote_servers.insert(public_key, server);
+                } else {
+                    // This happens when a server is removed from the trusted servers, or when
+                    // its address was changed and a new connection was already spawned:
+                    info!("Stale connection to server {:?} was closed.", public_key);
+                }
             }
             IndexServerEvent::ClientConnection((public_key, client_conn)) => {
                 if index_server.clients.contains_key(&public_key) {
@@ -637,6 +1092,7 @@ where
                 let sender = sink_to_sender(sender, CLIENT_SENDER_BUFFER, &spawner);
                 // TODO: Possibly use channel redirection here:
                 let c_sender = sender.clone();
+                let (tick_sender, tick_receiver) = mpsc::channel(CLIENT_TICK_BUFFER);
 
                 let mut c_event_sender = index_server.event_sender.clone();
                 let c_public_key = public_key.clone();
@@ -644,6 +1100,9 @@ where
                     index_server.graph_client.clone(),
                     public_key.clone(),
                     ClientConn::from_raw(sender, receiver),
+                    index_server.identity_client.clone(),
+                    index_server.client_limits.clone(),
+                    tick_receiver,
                     index_server.event_sender.clone(),
                 )
                 .map_err(|e| error!("client_handler() error: {:?}", e))
@@ -657,9 +1116,13 @@ where
                     .spawner
                     .spawn(client_handler_fut)
                     .map_err(|_| ServerLoopError::SpawnError)?;
-                index_server
-                    .clients
-                    .insert(public_key, Connected::new(c_sender));
+                index_server.clients.insert(
+                    public_key,
+                    ConnectedClient {
+                        connected: Connected::new(c_sender),
+                        tick_sender,
+                    },
+                );
             }
             IndexServerEvent::ClientMutationsUpdate(mutations_update) => {
                 let forward_mutations_update = ForwardMutationsUpdate {
@@ -677,6 +1140,24 @@ where
                     error!("A non existent client {:?} was closed.", public_key);
                 }
             }
+            IndexServerEvent::AdminConnection((public_key, admin_conn)) => {
+                info!("Admin {:?} connected", public_key);
+                let admin_handler_fut =
+                    admin_handler(admin_conn, index_server.event_sender.clone())
+                        .map_err(|e| error!("admin_handler() error: {:?}", e))
+                        .map(|_| ());
+                index_server
+                    .spawner
+                    .spawn(admin_handler_fut)
+                    .map_err(|_| ServerLoopError::SpawnError)?;
+            }
+            IndexServerEvent::AdminRequestStatus((request_id, response_sender)) => {
+                let index_server_status = index_server.get_status(request_id).await?;
+                let _ = response_sender.send(index_server_status);
+            }
+            IndexServerEvent::TrustedServersUpdate(trusted_servers) => {
+                index_server.update_trusted_servers(trusted_servers)?
+            }
             IndexServerEvent::TimerTick => index_server.handle_timer_tick().await?,
             IndexServerEvent::TimerClosed => {
                 warn!("server_loop() timer closed!");
@@ -714,16 +1195,23 @@ mod tests {
     use crypto::rand::RandGen;
     use crypto::test_utils::DummyRandom;
 
-    use proto::crypto::{PrivateKey, PublicKey, RandValue, Signature};
-    use proto::funder::messages::Currency;
-    use proto::index_server::messages::{RemoveFriendCurrency, RequestRoutes};
+    use proto::crypto::{HashResult, PrivateKey, PublicKey, RandValue, Signature};
+    use proto::funder::messages::{Currency, FriendsRoute};
+    use proto::index_server::messages::{
+        HopOutcome, MultiRoute, RemoveFriendCurrency, RequestRoutes, RequestTopology, RouteOutcome,
+        TopologyEdge, UpdateFriendCurrency,
+    };
 
     use common::dummy_connector::{ConnRequest, DummyConnector};
     use identity::{create_identity, IdentityClient};
 
-    use signature::signature_buff::create_mutations_update_signature_buff;
+    use signature::signature_buff::{create_mutations_update_signature_buff, topology_hash};
+    use signature::verify::verify_response_topology;
 
-    use crate::graph::graph_service::GraphRequest;
+    use crate::graph::capacity_graph::GraphStats;
+    use crate::graph::graph_service::{create_graph_service, GraphRequest};
+    use crate::graph::graph_store::{FileGraphStore, StoredEdge};
+    use crate::graph::simple_capacity_graph::SimpleCapacityGraph;
     use crate::verifier::simple_verifier::SimpleVerifier;
 
     /// Size of channel used for channels between servers, or channels between a server and a
@@ -758,6 +1246,7 @@ mod tests {
 
         let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
         let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (_admin_connections_sender, incoming_admin_connections) = mpsc::channel(0);
 
         let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
         let server_connector = DummyConnector::new(conn_request_sender);
@@ -774,13 +1263,20 @@ mod tests {
         let rng = DummyRandom::new(&[0u8]);
         let verifier = SimpleVerifier::new(8, rng);
 
+        let server_identity_client = create_identity_client(spawner.clone(), &[0, 0]);
+        let server_signing_public_key = server_identity_client.request_public_key().await.unwrap();
+
         let server_loop_fut = server_loop(
             local_public_key,
             trusted_servers,
+            stream::empty(),
             incoming_server_connections,
             incoming_client_connections,
+            incoming_admin_connections,
             server_connector,
             graph_client,
+            server_identity_client,
+            ClientLimits::unlimited(),
             compare_public_key,
             verifier,
             timer_stream,
@@ -835,7 +1331,7 @@ mod tests {
                 assert_eq!(dest, PublicKey::from(&[9; PublicKey::len()]));
                 assert_eq!(capacity, 100);
                 assert_eq!(opt_exclude, None);
-                response_sender.send(Vec::new()).unwrap();
+                response_sender.send(Some(Vec::new())).unwrap();
             }
             _ => unreachable!(),
         }
@@ -848,6 +1344,124 @@ mod tests {
             _ => unreachable!(),
         };
 
+        // Client requests the topology, and then an incremental update:
+        let stored_edges = vec![
+            StoredEdge {
+                from: PublicKey::from(&[8; PublicKey::len()]),
+                to: PublicKey::from(&[9; PublicKey::len()]),
+                recv_capacity: 50,
+                rate: Rate { mul: 1, add: 2 },
+                age: 3,
+                unreliability: 0,
+            },
+            StoredEdge {
+                from: PublicKey::from(&[9; PublicKey::len()]),
+                to: PublicKey::from(&[8; PublicKey::len()]),
+                recv_capacity: 40,
+                rate: Rate { mul: 0, add: 1 },
+                age: 2,
+                unreliability: 0,
+            },
+            // A one sided edge, not included in the topology:
+            StoredEdge {
+                from: PublicKey::from(&[8; PublicKey::len()]),
+                to: PublicKey::from(&[10; PublicKey::len()]),
+                recv_capacity: 1000,
+                rate: Rate::new(),
+                age: 0,
+                unreliability: 0,
+            },
+        ];
+        let topology_edges = stored_edges[..2]
+            .iter()
+            .map(|stored_edge| TopologyEdge {
+                from_public_key: stored_edge.from.clone(),
+                to_public_key: stored_edge.to.clone(),
+                recv_capacity: stored_edge.recv_capacity,
+                rate: stored_edge.rate.clone(),
+            })
+            .collect::<Vec<_>>();
+        for &is_incremental in &[false, true] {
+            let request_topology = RequestTopology {
+                request_id: request_id.clone(),
+                currency: currency1.clone(),
+                is_incremental,
+            };
+            client_sender
+                .send(IndexClientToServer::RequestTopology(request_topology))
+                .await
+                .unwrap();
+
+            match graph_requests_receiver.next().await.unwrap() {
+                GraphRequest::ExportEdges(currency, response_sender) => {
+                    assert_eq!(currency, currency1);
+                    response_sender.send(stored_edges.clone()).unwrap();
+                }
+                _ => unreachable!(),
+            }
+
+            match client_receiver.next().await.unwrap() {
+                IndexServerToClient::ResponseTopology(response_topology) => {
+                    assert_eq!(response_topology.request_id, request_id);
+                    assert_eq!(response_topology.is_full, !is_incremental);
+                    if is_incremental {
+                        // Nothing changed since the last response:
+                        assert!(response_topology.updated_edges.is_empty());
+                    } else {
+                        let mut updated_edges = response_topology.updated_edges.clone();
+                        updated_edges.sort_by_key(|edge| edge.from_public_key.clone());
+                        assert_eq!(updated_edges, topology_edges);
+                    }
+                    assert!(response_topology.removed_edges.is_empty());
+                    assert_eq!(
+                        response_topology.topology_hash,
+                        topology_hash(&topology_edges)
+                    );
+                    assert!(verify_response_topology(
+                        &response_topology,
+                        &server_signing_public_key
+                    ));
+                }
+                _ => unreachable!(),
+            };
+        }
+
+        // Client reports route outcomes. Only routes starting at the client are accepted:
+        let other_route = FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[8; PublicKey::len()]),
+                PublicKey::from(&[9; PublicKey::len()]),
+            ],
+        };
+        let client_route = FriendsRoute {
+            public_keys: vec![
+                client_public_key.clone(),
+                PublicKey::from(&[8; PublicKey::len()]),
+                PublicKey::from(&[9; PublicKey::len()]),
+            ],
+        };
+        for route in &[other_route, client_route.clone()] {
+            let route_outcome = RouteOutcome {
+                currency: currency1.clone(),
+                route: route.clone(),
+                hop_outcomes: vec![HopOutcome::Success; route.public_keys.len() - 1],
+            };
+            client_sender
+                .send(IndexClientToServer::ReportRouteOutcome(route_outcome))
+                .await
+                .unwrap();
+        }
+
+        match graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::ReportRoute(currency, route, edge_outcomes, response_sender) => {
+                assert_eq!(currency, currency1);
+                assert_eq!(route, client_route.public_keys);
+                assert_eq!(edge_outcomes, vec![EdgeOutcome::Success; 2]);
+                response_sender.send(()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+
         // Server should periodically send time hashes to the client:
         tick_sender.send(()).await.unwrap();
 
@@ -916,8 +1530,10 @@ mod tests {
     struct TestServer {
         public_key: PublicKey,
         tick_sender: mpsc::Sender<()>,
+        trusted_servers_updates_sender: mpsc::Sender<HashMap<PublicKey, u8>>,
         server_connections_sender: mpsc::Sender<(PublicKey, ServerConn)>,
         client_connections_sender: mpsc::Sender<(PublicKey, ClientConn)>,
+        admin_connections_sender: mpsc::Sender<(PublicKey, AdminConn)>,
         graph_requests_receiver: mpsc::Receiver<GraphRequest<Currency, PublicKey, u128, Rate>>,
         server_conn_request_receiver:
             mpsc::Receiver<ConnRequest<(PublicKey, u8), Option<ServerConn>>>,
@@ -936,8 +1552,10 @@ mod tests {
 
         let local_public_key = server_public_key.clone();
 
+        let (trusted_servers_updates_sender, trusted_servers_updates) = mpsc::channel(0);
         let (server_connections_sender, incoming_server_connections) = mpsc::channel(0);
         let (client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (admin_connections_sender, incoming_admin_connections) = mpsc::channel(0);
 
         let (server_conn_request_sender, server_conn_request_receiver) = mpsc::channel(0);
         let server_connector = DummyConnector::new(server_conn_request_sender);
@@ -956,13 +1574,19 @@ mod tests {
 
         let (debug_event_sender, debug_event_receiver) = mpsc::channel(0);
 
+        let identity_client = create_identity_client(spawner.clone(), &[0x13, index]);
+
         let server_loop_fut = server_loop(
             local_public_key,
             trusted_servers,
+            trusted_servers_updates,
             incoming_server_connections,
             incoming_client_connections,
+            incoming_admin_connections,
             server_connector,
             graph_client,
+            identity_client,
+            ClientLimits::unlimited(),
             compare_public_key,
             verifier,
             timer_stream,
@@ -982,8 +1606,10 @@ mod tests {
         TestServer {
             public_key: server_public_key.clone(),
             tick_sender,
+            trusted_servers_updates_sender,
             server_connections_sender,
             client_connections_sender,
+            admin_connections_sender,
             graph_requests_receiver,
             server_conn_request_receiver,
             debug_event_receiver,
@@ -1138,7 +1764,7 @@ mod tests {
                 assert_eq!(dest, PublicKey::from(&[9; PublicKey::len()]));
                 assert_eq!(capacity, 100);
                 assert_eq!(opt_exclude, None);
-                response_sender.send(Vec::new()).unwrap();
+                response_sender.send(Some(Vec::new())).unwrap();
             }
             _ => unreachable!(),
         }
@@ -1242,6 +1868,110 @@ mod tests {
         process_graph_request!(3);
         process_graph_request!(2);
         process_graph_request!(4);
+
+        // An admin connects to server 3:
+        let admin_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let (mut admin_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut admin_receiver) = mpsc::channel(CHANNEL_SIZE);
+        test_servers[3]
+            .admin_connections_sender
+            .send((
+                admin_public_key,
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        test_servers[3].debug_event_receiver.next().await.unwrap();
+
+        macro_rules! request_status {
+            () => {{
+                let request_id = Uid::from(&[3; Uid::len()]);
+                admin_sender
+                    .send(IndexAdminToServer::RequestStatus(request_id.clone()))
+                    .await
+                    .unwrap();
+
+                match test_servers[3]
+                    .graph_requests_receiver
+                    .next()
+                    .await
+                    .unwrap()
+                {
+                    GraphRequest::GetStats(response_sender) => {
+                        let stats = GraphStats {
+                            num_nodes: 5,
+                            num_edges: 7,
+                        };
+                        response_sender
+                            .send(vec![(currency1.clone(), Some(stats))])
+                            .unwrap();
+                    }
+                    _ => unreachable!(),
+                };
+                test_servers[3].debug_event_receiver.next().await.unwrap();
+
+                match admin_receiver.next().await.unwrap() {
+                    IndexServerToAdmin::ResponseStatus(mut index_server_status) => {
+                        assert_eq!(index_server_status.request_id, request_id);
+                        index_server_status
+                            .servers
+                            .sort_by_key(|server_status| server_status.public_key.clone());
+                        index_server_status
+                    }
+                }
+            }};
+        }
+
+        let index_server_status = request_status!();
+        assert_eq!(index_server_status.num_clients, 0);
+        // Server 3 tracks the client of server 0:
+        assert_eq!(index_server_status.num_nodes, 1);
+        assert_eq!(
+            index_server_status.currency_graphs,
+            vec![CurrencyGraphStatus {
+                currency: currency1.clone(),
+                is_loaded: true,
+                num_nodes: 5,
+                num_edges: 7,
+            }]
+        );
+        let servers = &index_server_status.servers;
+        assert_eq!(servers.len(), 3);
+        for (server_status, &index) in servers.iter().zip([1u8, 2, 4].iter()) {
+            assert_eq!(
+                server_status.public_key,
+                PublicKey::from(&[index; PublicKey::len()])
+            );
+            assert_eq!(server_status.conn_state, ServerConnState::Connected);
+            assert!(!server_status.is_lagging);
+        }
+        assert!(index_server_status.unreachable_servers().is_empty());
+
+        // Only server 3 keeps ticking. Servers 1 and 2 did not send a time hash to server 3 for
+        // one tick longer than server 4:
+        for _iter in 0..MAX_SERVER_LAG_TICKS - 1 {
+            test_servers[3].tick_sender.send(()).await.unwrap();
+            test_servers[3].debug_event_receiver.next().await.unwrap();
+            for &j in &[1usize, 2, 4] {
+                test_servers[j].debug_event_receiver.next().await.unwrap();
+            }
+        }
+
+        let index_server_status = request_status!();
+        let ticks_since_time_hash = index_server_status
+            .servers
+            .iter()
+            .map(|server_status| server_status.ticks_since_time_hash)
+            .collect::<Vec<_>>();
+        let max_lag = usize_to_u64(MAX_SERVER_LAG_TICKS).unwrap();
+        assert_eq!(ticks_since_time_hash, vec![max_lag, max_lag, max_lag - 1]);
+        assert_eq!(
+            index_server_status.unreachable_servers(),
+            vec![
+                PublicKey::from(&[1; PublicKey::len()]),
+                PublicKey::from(&[2; PublicKey::len()])
+            ]
+        );
     }
 
     #[test]
@@ -1253,5 +1983,660 @@ mod tests {
         block_on(task_index_server_loop_multi_server(thread_pool.clone()));
     }
 
+    // ###########################################################
+    // ###########################################################
+
+    struct TestClient {
+        public_key: PublicKey,
+        identity_client: IdentityClient,
+        sender: mpsc::Sender<IndexClientToServer>,
+        receiver: mpsc::Receiver<IndexServerToClient>,
+        counter: u64,
+    }
+
+    impl TestClient {
+        /// Send a MutationsUpdate on behalf of `node_public_key`, signed by this client.
+        async fn send_mutations_as(
+            &mut self,
+            node_public_key: PublicKey,
+            time_hash: HashResult,
+            index_mutations: Vec<IndexMutation>,
+        ) {
+            let mut mutations_update = MutationsUpdate {
+                node_public_key,
+                index_mutations,
+                time_hash,
+                session_id: Uid::from(&[0; Uid::len()]),
+                counter: self.counter,
+                rand_nonce: RandValue::from(&[0; RandValue::len()]),
+                signature: Signature::from(&[0; Signature::len()]),
+            };
+            self.counter += 1;
+
+            mutations_update.signature = self
+                .identity_client
+                .request_signature(create_mutations_update_signature_buff(&mutations_update))
+                .await
+                .unwrap();
+
+            self.sender
+                .send(IndexClientToServer::MutationsUpdate(mutations_update))
+                .await
+                .unwrap();
+        }
+
+        async fn send_mutations(
+            &mut self,
+            time_hash: HashResult,
+            index_mutations: Vec<IndexMutation>,
+        ) {
+            self.send_mutations_as(self.public_key.clone(), time_hash, index_mutations)
+                .await;
+        }
+
+        async fn request_routes(
+            &mut self,
+            currency: &Currency,
+            destination: &PublicKey,
+            capacity: u128,
+        ) -> Vec<MultiRoute> {
+            let request_id = Uid::from(&[self.public_key[0]; Uid::len()]);
+            let request_routes = RequestRoutes {
+                request_id: request_id.clone(),
+                currency: currency.clone(),
+                capacity,
+                source: self.public_key.clone(),
+                destination: destination.clone(),
+                opt_exclude: None,
+            };
+            self.sender
+                .send(IndexClientToServer::RequestRoutes(request_routes))
+                .await
+                .unwrap();
+
+            match self.receiver.next().await.unwrap() {
+                IndexServerToClient::ResponseRoutes(response_routes) => {
+                    assert_eq!(response_routes.request_id, request_id);
+                    response_routes.multi_routes
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn update_friend_currency(
+        public_key: &PublicKey,
+        currency: &Currency,
+        recv_capacity: u128,
+    ) -> IndexMutation {
+        IndexMutation::UpdateFriendCurrency(UpdateFriendCurrency {
+            public_key: public_key.clone(),
+            currency: currency.clone(),
+            recv_capacity,
+            rate: Rate::new(),
+        })
+    }
+
+    async fn task_index_server_loop_adversarial_client<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (_admin_connections_sender, incoming_admin_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        // Use a real graph service, to check which edges are accepted:
+        let graph_client = create_graph_service::<
+            Currency,
+            PublicKey,
+            Rate,
+            SimpleCapacityGraph<PublicKey, Rate>,
+            FileGraphStore,
+            _,
+            _,
+        >(
+            None,
+            usize::max_value(),
+            ThreadPool::new().unwrap(),
+            spawner.clone(),
+        )
+        .unwrap();
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+        let verifier = SimpleVerifier::new(8, DummyRandom::new(&[0u8]));
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            HashMap::<PublicKey, u8>::new(),
+            stream::empty(),
+            incoming_server_connections,
+            incoming_client_connections,
+            incoming_admin_connections,
+            server_connector,
+            graph_client,
+            create_identity_client(spawner.clone(), &[0, 0]),
+            ClientLimits::unlimited(),
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            Some(debug_event_sender),
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        // Honest clients a and b are friends. Client m is an adversary:
+        let mut clients = Vec::new();
+        for &seed in &[1u8, 2, 3] {
+            let identity_client = create_identity_client(spawner.clone(), &[seed, seed]);
+            let public_key = identity_client.request_public_key().await.unwrap();
+
+            let (sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+            let (server_sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+            client_connections_sender
+                .send((
+                    public_key.clone(),
+                    ConnPair::from_raw(server_sender, server_receiver),
+                ))
+                .await
+                .unwrap();
+            debug_event_receiver.next().await.unwrap();
+
+            clients.push(TestClient {
+                public_key,
+                identity_client,
+                sender,
+                receiver,
+                counter: 0,
+            });
+        }
+        let mut m = clients.pop().unwrap();
+        let mut b = clients.pop().unwrap();
+        let mut a = clients.pop().unwrap();
+
+        tick_sender.send(()).await.unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        let mut time_hash = None;
+        for client in &mut [&mut a, &mut b, &mut m] {
+            time_hash = match client.receiver.next().await.unwrap() {
+                IndexServerToClient::TimeHash(time_hash) => Some(time_hash),
+                _ => unreachable!(),
+            };
+        }
+        let time_hash = time_hash.unwrap();
+
+        // a and b advertise the edge between them:
+        a.send_mutations(
+            time_hash.clone(),
+            vec![update_friend_currency(&b.public_key, &currency1, 100)],
+        )
+        .await;
+        debug_event_receiver.next().await.unwrap();
+        b.send_mutations(
+            time_hash.clone(),
+            vec![update_friend_currency(&a.public_key, &currency1, 100)],
+        )
+        .await;
+        debug_event_receiver.next().await.unwrap();
+
+        // m advertises fake edges with a large capacity towards a and b:
+        let fake_mutations = vec![
+            update_friend_currency(&a.public_key, &currency1, 1_000_000),
+            update_friend_currency(&b.public_key, &currency1, 1_000_000),
+        ];
+        m.send_mutations(time_hash.clone(), fake_mutations).await;
+        debug_event_receiver.next().await.unwrap();
+
+        // m attempts to advertise an edge from a to m on behalf of a:
+        m.send_mutations_as(
+            a.public_key.clone(),
+            time_hash.clone(),
+            vec![update_friend_currency(&m.public_key, &currency1, 1_000_000)],
+        )
+        .await;
+        debug_event_receiver.next().await.unwrap();
+
+        // Routes are only found through edges advertised by both sides:
+        let multi_routes = a.request_routes(&currency1, &b.public_key, 50).await;
+        assert!(!multi_routes.is_empty());
+        for multi_route in &multi_routes {
+            for route in &multi_route.routes {
+                assert_eq!(
+                    route.route.public_keys,
+                    vec![a.public_key.clone(), b.public_key.clone()]
+                );
+            }
+        }
+        assert!(a
+            .request_routes(&currency1, &m.public_key, 1)
+            .await
+            .is_empty());
+        assert!(b
+            .request_routes(&currency1, &m.public_key, 1)
+            .await
+            .is_empty());
+        assert!(m
+            .request_routes(&currency1, &a.public_key, 1)
+            .await
+            .is_empty());
+
+        // The fake edges are not included in the topology:
+        let request_topology = RequestTopology {
+            request_id: Uid::from(&[5; Uid::len()]),
+            currency: currency1.clone(),
+            is_incremental: false,
+        };
+        a.sender
+            .send(IndexClientToServer::RequestTopology(request_topology))
+            .await
+            .unwrap();
+        match a.receiver.next().await.unwrap() {
+            IndexServerToClient::ResponseTopology(response_topology) => {
+                assert_eq!(response_topology.updated_edges.len(), 2);
+                for topology_edge in &response_topology.updated_edges {
+                    assert!(topology_edge.from_public_key != m.public_key);
+                    assert!(topology_edge.to_public_key != m.public_key);
+                }
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_index_server_loop_adversarial_client() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_adversarial_client(
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_index_server_loop_client_limits<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let local_public_key = PublicKey::from(&[0; PublicKey::len()]);
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+        let (_admin_connections_sender, incoming_admin_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        // Quotas are never refilled during this test:
+        let (_tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let graph_client = create_graph_service::<
+            Currency,
+            PublicKey,
+            Rate,
+            SimpleCapacityGraph<PublicKey, Rate>,
+            FileGraphStore,
+            _,
+            _,
+        >(
+            None,
+            usize::max_value(),
+            ThreadPool::new().unwrap(),
+            spawner.clone(),
+        )
+        .unwrap();
+
+        let client_limits = ClientLimits {
+            max_request_burst: 2,
+            requests_per_tick: 1,
+            max_mutations_burst: 1,
+            mutations_per_tick: 1,
+            max_mutations_per_update: 2,
+            max_pending_route_searches: usize::max_value(),
+        };
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+        let verifier = SimpleVerifier::new(8, DummyRandom::new(&[0u8]));
+        let (debug_event_sender, mut debug_event_receiver) = mpsc::channel(0);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            HashMap::<PublicKey, u8>::new(),
+            stream::empty(),
+            incoming_server_connections,
+            incoming_client_connections,
+            incoming_admin_connections,
+            server_connector,
+            graph_client,
+            create_identity_client(spawner.clone(), &[0, 0]),
+            client_limits,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            Some(debug_event_sender),
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        let identity_client = create_identity_client(spawner.clone(), &[1, 1]);
+        let public_key = identity_client.request_public_key().await.unwrap();
+        let (sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+        client_connections_sender
+            .send((
+                public_key.clone(),
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        debug_event_receiver.next().await.unwrap();
+
+        let mut client = TestClient {
+            public_key,
+            identity_client,
+            sender,
+            receiver,
+            counter: 0,
+        };
+        let friend_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let time_hash = HashResult::from(&[0; HashResult::len()]);
+
+        // Too many mutations in a single update:
+        let index_mutations = vec![
+            update_friend_currency(&friend_public_key, &currency1, 1),
+            update_friend_currency(&friend_public_key, &currency1, 2),
+            update_friend_currency(&friend_public_key, &currency1, 3),
+        ];
+        client
+            .send_mutations(time_hash.clone(), index_mutations)
+            .await;
+        match client.receiver.next().await.unwrap() {
+            IndexServerToClient::MutationsRejected(mutations_rejected) => {
+                assert_eq!(mutations_rejected.counter, 0);
+                assert_eq!(mutations_rejected.reason, RejectReason::TooManyMutations);
+            }
+            _ => unreachable!(),
+        };
+
+        // A small update is forwarded to the main loop:
+        let index_mutations = vec![update_friend_currency(&friend_public_key, &currency1, 1)];
+        client
+            .send_mutations(time_hash.clone(), index_mutations.clone())
+            .await;
+        debug_event_receiver.next().await.unwrap();
+
+        // The mutations quota is exhausted:
+        client.send_mutations(time_hash, index_mutations).await;
+        match client.receiver.next().await.unwrap() {
+            IndexServerToClient::MutationsRejected(mutations_rejected) => {
+                assert_eq!(mutations_rejected.counter, 2);
+                assert_eq!(mutations_rejected.reason, RejectReason::RateLimited);
+            }
+            _ => unreachable!(),
+        };
+
+        // Requests are allowed until the requests quota is exhausted:
+        assert!(client
+            .request_routes(&currency1, &friend_public_key, 1)
+            .await
+            .is_empty());
+        assert!(client
+            .request_routes(&currency1, &friend_public_key, 1)
+            .await
+            .is_empty());
+
+        let request_topology = RequestTopology {
+            request_id: Uid::from(&[5; Uid::len()]),
+            currency: currency1.clone(),
+            is_incremental: false,
+        };
+        client
+            .sender
+            .send(IndexClientToServer::RequestTopology(request_topology))
+            .await
+            .unwrap();
+        match client.receiver.next().await.unwrap() {
+            IndexServerToClient::RequestRejected(request_rejected) => {
+                assert_eq!(request_rejected.request_id, Uid::from(&[5; Uid::len()]));
+                assert_eq!(request_rejected.reason, RejectReason::RateLimited);
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_index_server_loop_client_limits() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_client_limits(thread_pool.clone()));
+    }
+
+    async fn request_test_server_status(
+        test_server: &mut TestServer,
+        admin_sender: &mut mpsc::Sender<IndexAdminToServer>,
+        admin_receiver: &mut mpsc::Receiver<IndexServerToAdmin>,
+    ) -> IndexServerStatus {
+        let request_id = Uid::from(&[4; Uid::len()]);
+        admin_sender
+            .send(IndexAdminToServer::RequestStatus(request_id.clone()))
+            .await
+            .unwrap();
+
+        match test_server.graph_requests_receiver.next().await.unwrap() {
+            GraphRequest::GetStats(response_sender) => {
+                response_sender.send(Vec::new()).unwrap();
+            }
+            _ => unreachable!(),
+        };
+        test_server.debug_event_receiver.next().await.unwrap();
+
+        match admin_receiver.next().await.unwrap() {
+            IndexServerToAdmin::ResponseStatus(index_server_status) => {
+                assert_eq!(index_server_status.request_id, request_id);
+                index_server_status
+            }
+        }
+    }
+
+    async fn task_index_server_loop_trusted_servers_update<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        // Both servers start without any trusted servers:
+        let mut test_servers = Vec::new();
+        test_servers.push(create_test_server(0, &[], spawner.clone()));
+        test_servers.push(create_test_server(1, &[], spawner.clone()));
+
+        // An admin connects to server 0:
+        let admin_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let (mut admin_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut admin_receiver) = mpsc::channel(CHANNEL_SIZE);
+        test_servers[0]
+            .admin_connections_sender
+            .send((
+                admin_public_key,
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        test_servers[0].debug_event_receiver.next().await.unwrap();
+
+        let index_server_status = request_test_server_status(
+            &mut test_servers[0],
+            &mut admin_sender,
+            &mut admin_receiver,
+        )
+        .await;
+        assert!(index_server_status.servers.is_empty());
+
+        // The servers start trusting each other:
+        for &(index, other_index) in &[(0u8, 1u8), (1, 0)] {
+            let test_server = &mut test_servers[index as usize];
+            let mut trusted_servers = HashMap::new();
+            trusted_servers.insert(
+                PublicKey::from(&[other_index; PublicKey::len()]),
+                other_index,
+            );
+            test_server
+                .trusted_servers_updates_sender
+                .send(trusted_servers)
+                .await
+                .unwrap();
+            test_server.debug_event_receiver.next().await.unwrap();
+        }
+
+        // 1 connects to {0}:
+        handle_connect(&mut test_servers[..], 1).await;
+
+        let index_server_status = request_test_server_status(
+            &mut test_servers[0],
+            &mut admin_sender,
+            &mut admin_receiver,
+        )
+        .await;
+        assert_eq!(index_server_status.servers.len(), 1);
+        let server_status = &index_server_status.servers[0];
+        assert_eq!(server_status.public_key, test_servers[1].public_key);
+        assert_eq!(server_status.conn_state, ServerConnState::Connected);
+
+        // Server 0 stops trusting server 1:
+        test_servers[0]
+            .trusted_servers_updates_sender
+            .send(HashMap::new())
+            .await
+            .unwrap();
+        test_servers[0].debug_event_receiver.next().await.unwrap();
+
+        // Server 1 notices that the connection was closed:
+        test_servers[1].debug_event_receiver.next().await.unwrap();
+        // Server 0 receives the remaining closing event of the removed server:
+        test_servers[0].debug_event_receiver.next().await.unwrap();
+
+        let index_server_status = request_test_server_status(
+            &mut test_servers[0],
+            &mut admin_sender,
+            &mut admin_receiver,
+        )
+        .await;
+        assert!(index_server_status.servers.is_empty());
+
+        // Server 1 still trusts server 0, and attempts to reconnect:
+        let conn_request = test_servers[1]
+            .server_conn_request_receiver
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(
+            conn_request.address,
+            (test_servers[0].public_key.clone(), 0u8)
+        );
+    }
+
+    #[test]
+    fn test_index_server_loop_trusted_servers_update() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_trusted_servers_update(
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_index_server_loop_trusted_server_address_change<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let mut test_servers = Vec::new();
+        test_servers.push(create_test_server(0, &[1], spawner.clone()));
+        test_servers.push(create_test_server(1, &[0], spawner.clone()));
+
+        // 1 connects to {0}:
+        handle_connect(&mut test_servers[..], 1).await;
+
+        // Server 1 learns about a new address of server 0:
+        let new_address = 0x10u8;
+        let mut trusted_servers = HashMap::new();
+        trusted_servers.insert(test_servers[0].public_key.clone(), new_address);
+        test_servers[1]
+            .trusted_servers_updates_sender
+            .send(trusted_servers)
+            .await
+            .unwrap();
+        test_servers[1].debug_event_receiver.next().await.unwrap();
+
+        // Server 0 notices that the connection was closed:
+        test_servers[0].debug_event_receiver.next().await.unwrap();
+        // Server 1 receives the closing event of the old connection.
+        // This must not cancel the connection attempt to the new address:
+        test_servers[1].debug_event_receiver.next().await.unwrap();
+
+        let conn_request = test_servers[1]
+            .server_conn_request_receiver
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(
+            conn_request.address,
+            (test_servers[0].public_key.clone(), new_address)
+        );
+
+        // Server 1 connects to server 0 using the new address:
+        let (a_sender, b_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (b_sender, a_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let server1_public_key = test_servers[1].public_key.clone();
+        test_servers[0]
+            .server_connections_sender
+            .send((server1_public_key, ConnPair::from_raw(b_sender, b_receiver)))
+            .await
+            .unwrap();
+        test_servers[0].debug_event_receiver.next().await.unwrap();
+
+        conn_request.reply(Some(ConnPair::from_raw(a_sender, a_receiver)));
+        test_servers[1].debug_event_receiver.next().await.unwrap();
+
+        // An admin connects to server 1:
+        let admin_public_key = PublicKey::from(&[0xaa; PublicKey::len()]);
+        let (mut admin_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut admin_receiver) = mpsc::channel(CHANNEL_SIZE);
+        test_servers[1]
+            .admin_connections_sender
+            .send((
+                admin_public_key,
+                ConnPair::from_raw(server_sender, server_receiver),
+            ))
+            .await
+            .unwrap();
+        test_servers[1].debug_event_receiver.next().await.unwrap();
+
+        // Server 1 is connected to server 0 through the new connection:
+        let index_server_status = request_test_server_status(
+            &mut test_servers[1],
+            &mut admin_sender,
+            &mut admin_receiver,
+        )
+        .await;
+        assert_eq!(index_server_status.servers.len(), 1);
+        let server_status = &index_server_status.servers[0];
+        assert_eq!(server_status.public_key, test_servers[0].public_key);
+        assert_eq!(server_status.conn_state, ServerConnState::Connected);
+    }
+
+    #[test]
+    fn test_index_server_loop_trusted_server_address_change() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_index_server_loop_trusted_server_address_change(
+            thread_pool.clone(),
+        ));
+    }
+
     // TODO: Add tests.
 }
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+
+use proto::consts::MAX_TOPOLOGY_CHANGES;
+use proto::crypto::{PublicKey, Signature, Uid};
+use proto::funder::messages::{Currency, FriendsRoute, Rate};
+use proto::index_server::messages::{
+    Edge, MultiRoute, RequestRoutes, ResponseTopology, RouteCapacityRate, TopologyEdge,
+};
+
+use signature::signature_buff::topology_hash;
+
+use crate::graph::capacity_graph::{CapacityEdge, CapacityGraph, CapacityMultiRoute};
+use crate::graph::graph_store::StoredEdge;
+use crate::graph::simple_capacity_graph::SimpleCapacityGraph;
+
+/// All the edges of a topology, indexed by (from_public_key, to_public_key)
+pub type TopologyEdges = HashMap<(PublicKey, PublicKey), TopologyEdge>;
+
+/// Convert edges exported from a capacity graph into topology edges.
+/// Only edges that were advertised by both sides are included: An edge from `a` to `b` is only
+/// included if there is also an edge from `b` to `a`. One sided edges can not be used for routing,
+/// and might be spoofed.
+pub fn topology_from_stored_edges(stored_edges: Vec<StoredEdge<PublicKey, Rate>>) -> TopologyEdges {
+    let all_edges: TopologyEdges = stored_edges
+        .into_iter()
+        .map(|stored_edge| {
+            let key = (stored_edge.from.clone(), stored_edge.to.clone());
+            let topology_edge = TopologyEdge {
+                from_public_key: stored_edge.from,
+                to_public_key: stored_edge.to,
+                recv_capacity: stored_edge.recv_capacity,
+                rate: stored_edge.rate,
+            };
+            (key, topology_edge)
+        })
+        .collect();
+
+    all_edges
+        .iter()
+        .filter(|((from_public_key, to_public_key), _)| {
+            all_edges.contains_key(&(to_public_key.clone(), from_public_key.clone()))
+        })
+        .map(|(key, topology_edge)| (key.clone(), topology_edge.clone()))
+        .collect()
+}
+
+/// Create an unsigned ResponseTopology, bringing a client that knows `sent_edges` closer to
+/// `edges`. `sent_edges` is updated to contain the topology the client will know after applying
+/// the response.
+///
+/// If `is_full` is true, `sent_edges` is ignored, and the response contains a full topology.
+/// At most MAX_TOPOLOGY_CHANGES changes are sent. If there are more changes, the client will
+/// receive the rest of the changes in the next responses.
+pub fn create_response_topology(
+    request_id: Uid,
+    currency: Currency,
+    edges: &TopologyEdges,
+    sent_edges: &mut TopologyEdges,
+    is_full: bool,
+) -> ResponseTopology {
+    if is_full {
+        sent_edges.clear();
+    }
+
+    let mut updated_edges = Vec::new();
+    let mut removed_edges = Vec::new();
+    for (key, topology_edge) in edges {
+        if updated_edges.len() >= MAX_TOPOLOGY_CHANGES {
+            break;
+        }
+        if sent_edges.get(key) != Some(topology_edge) {
+            updated_edges.push(topology_edge.clone());
+        }
+    }
+    for (from_public_key, to_public_key) in sent_edges.keys() {
+        if updated_edges.len() + removed_edges.len() >= MAX_TOPOLOGY_CHANGES {
+            break;
+        }
+        if !edges.contains_key(&(from_public_key.clone(), to_public_key.clone())) {
+            removed_edges.push(Edge {
+                from_public_key: from_public_key.clone(),
+                to_public_key: to_public_key.clone(),
+            });
+        }
+    }
+
+    // Calculate the topology the client will have after applying the changes:
+    for topology_edge in &updated_edges {
+        let key = (
+            topology_edge.from_public_key.clone(),
+            topology_edge.to_public_key.clone(),
+        );
+        sent_edges.insert(key, topology_edge.clone());
+    }
+    for edge in &removed_edges {
+        let _ = sent_edges.remove(&(edge.from_public_key.clone(), edge.to_public_key.clone()));
+    }
+
+    ResponseTopology {
+        request_id,
+        currency,
+        is_full,
+        updated_edges,
+        removed_edges,
+        topology_hash: topology_hash(sent_edges.values()),
+        signature: Signature::default(),
+    }
+}
+
+/// Convert a multi route found in a capacity graph into a MultiRoute
+pub fn to_multi_route(graph_multi_route: CapacityMultiRoute<PublicKey, u128, Rate>) -> MultiRoute {
+    MultiRoute {
+        routes: graph_multi_route
+            .routes
+            .into_iter()
+            .map(|graph_route| RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: graph_route.route,
+                },
+                capacity: graph_route.capacity,
+                rate: graph_route.rate,
+            })
+            .collect(),
+    }
+}
+
+/// Find routes locally over a topology, using the same route search the index server uses.
+pub fn topology_multi_routes<'a, I>(
+    topology_edges: I,
+    request_routes: &RequestRoutes,
+) -> Vec<MultiRoute>
+where
+    I: IntoIterator<Item = &'a TopologyEdge>,
+{
+    let mut capacity_graph = SimpleCapacityGraph::<PublicKey, Rate>::new();
+    for topology_edge in topology_edges {
+        capacity_graph.update_edge(
+            topology_edge.from_public_key.clone(),
+            topology_edge.to_public_key.clone(),
+            CapacityEdge::new(topology_edge.recv_capacity, topology_edge.rate.clone()),
+        );
+    }
+
+    let opt_exclude = request_routes
+        .opt_exclude
+        .as_ref()
+        .map(|edge| (&edge.from_public_key, &edge.to_public_key));
+
+    capacity_graph
+        .get_multi_routes(
+            &request_routes.source,
+            &request_routes.destination,
+            request_routes.capacity,
+            opt_exclude,
+        )
+        .into_iter()
+        .map(to_multi_route)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    fn topology_edge(from: u8, to: u8, recv_capacity: u128) -> TopologyEdge {
+        TopologyEdge {
+            from_public_key: pk(from),
+            to_public_key: pk(to),
+            recv_capacity,
+            rate: Rate::new(),
+        }
+    }
+
+    fn create_topology(topology_edges: &[TopologyEdge]) -> TopologyEdges {
+        topology_edges
+            .iter()
+            .map(|topology_edge| {
+                let key = (
+                    topology_edge.from_public_key.clone(),
+                    topology_edge.to_public_key.clone(),
+                );
+                (key, topology_edge.clone())
+            })
+            .collect()
+    }
+
+    fn stored_edge(from: u8, to: u8, recv_capacity: u128) -> StoredEdge<PublicKey, Rate> {
+        StoredEdge {
+            from: pk(from),
+            to: pk(to),
+            recv_capacity,
+            rate: Rate::new(),
+            age: 0,
+            unreliability: 0,
+        }
+    }
+
+    #[test]
+    fn test_topology_from_stored_edges_one_sided() {
+        let stored_edges = vec![
+            stored_edge(0, 1, 10),
+            stored_edge(1, 0, 20),
+            // Only advertised by 0. Might be spoofed:
+            stored_edge(0, 2, 1000),
+            // Only advertised by 3:
+            stored_edge(3, 1, 1000),
+        ];
+        assert_eq!(
+            topology_from_stored_edges(stored_edges),
+            create_topology(&[topology_edge(0, 1, 10), topology_edge(1, 0, 20)])
+        );
+    }
+
+    #[test]
+    fn test_create_response_topology() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let request_id = Uid::from(&[1; Uid::len()]);
+
+        let edges = create_topology(&[topology_edge(0, 1, 10), topology_edge(1, 2, 20)]);
+        let mut sent_edges = TopologyEdges::new();
+
+        let response = create_response_topology(
+            request_id.clone(),
+            currency.clone(),
+            &edges,
+            &mut sent_edges,
+            true,
+        );
+        assert!(response.is_full);
+        assert_eq!(response.updated_edges.len(), 2);
+        assert!(response.removed_edges.is_empty());
+        assert_eq!(sent_edges, edges);
+        assert_eq!(response.topology_hash, topology_hash(edges.values()));
+
+        // Nothing changed:
+        let response = create_response_topology(
+            request_id.clone(),
+            currency.clone(),
+            &edges,
+            &mut sent_edges,
+            false,
+        );
+        assert!(!response.is_full);
+        assert!(response.updated_edges.is_empty());
+        assert!(response.removed_edges.is_empty());
+
+        // One edge changed, one edge removed, one edge added:
+        let edges = create_topology(&[topology_edge(0, 1, 15), topology_edge(2, 3, 30)]);
+        let mut response =
+            create_response_topology(request_id, currency, &edges, &mut sent_edges, false);
+        response
+            .updated_edges
+            .sort_by_key(|edge| edge.from_public_key.clone());
+        assert_eq!(
+            response.updated_edges,
+            vec![topology_edge(0, 1, 15), topology_edge(2, 3, 30)]
+        );
+        assert_eq!(
+            response.removed_edges,
+            vec![Edge {
+                from_public_key: pk(1),
+                to_public_key: pk(2),
+            }]
+        );
+        assert_eq!(sent_edges, edges);
+        assert_eq!(response.topology_hash, topology_hash(edges.values()));
+    }
+
+    #[test]
+    fn test_create_response_topology_max_changes() {
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let request_id = Uid::from(&[1; Uid::len()]);
+
+        let num_edges = MAX_TOPOLOGY_CHANGES + 10;
+        let edges = create_topology(
+            &(0..num_edges)
+                .map(|i| TopologyEdge {
+                    from_public_key: PublicKey::from(&[(i % 256) as u8; PublicKey::len()]),
+                    to_public_key: PublicKey::from(&[(i / 256) as u8; PublicKey::len()]),
+                    recv_capacity: 1,
+                    rate: Rate::new(),
+                })
+                .collect::<Vec<_>>(),
+        );
+        let mut sent_edges = TopologyEdges::new();
+
+        let response = create_response_topology(
+            request_id.clone(),
+            currency.clone(),
+            &edges,
+            &mut sent_edges,
+            true,
+        );
+        assert_eq!(response.updated_edges.len(), MAX_TOPOLOGY_CHANGES);
+        assert_eq!(sent_edges.len(), MAX_TOPOLOGY_CHANGES);
+        // The hash matches the partial topology the client will have:
+        assert_eq!(response.topology_hash, topology_hash(sent_edges.values()));
+
+        // The rest of the edges are sent in the next response:
+        let response =
+            create_response_topology(request_id, currency, &edges, &mut sent_edges, false);
+        assert_eq!(response.updated_edges.len(), 10);
+        assert_eq!(sent_edges, edges);
+    }
+
+    #[test]
+    fn test_topology_multi_routes() {
+        // Sending capacity requires edges in both directions:
+        let mut topology_edges = Vec::new();
+        for &(a, b) in &[(0, 1), (1, 2), (0, 3), (3, 2)] {
+            topology_edges.push(topology_edge(a, b, 30));
+            topology_edges.push(topology_edge(b, a, 30));
+        }
+        let mut request_routes = RequestRoutes {
+            request_id: Uid::from(&[1; Uid::len()]),
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            capacity: 20,
+            source: pk(0),
+            destination: pk(2),
+            opt_exclude: None,
+        };
+
+        let multi_routes = topology_multi_routes(&topology_edges, &request_routes);
+        assert!(!multi_routes.is_empty());
+        for multi_route in &multi_routes {
+            for route in &multi_route.routes {
+                assert_eq!(route.route.public_keys.first(), Some(&pk(0)));
+                assert_eq!(route.route.public_keys.last(), Some(&pk(2)));
+            }
+        }
+
+        // Exclude an edge:
+        request_routes.opt_exclude = Some(Edge {
+            from_public_key: pk(0),
+            to_public_key: pk(1),
+        });
+        let multi_routes = topology_multi_routes(&topology_edges, &request_routes);
+        assert!(!multi_routes.is_empty());
+        for multi_route in &multi_routes {
+            for route in &multi_route.routes {
+                assert_eq!(route.route.public_keys, vec![pk(0), pk(3), pk(2)]);
+            }
+        }
+
+        // Not enough capacity:
+        request_routes.capacity = 100;
+        assert!(topology_multi_routes(&topology_edges, &request_routes).is_empty());
+    }
+}
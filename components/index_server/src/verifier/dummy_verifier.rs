@@ -1,6 +1,6 @@
 use std::marker::PhantomData;
 
-use super::verifier::Verifier;
+use super::verifier::{Verifier, VerifierStatus};
 use proto::crypto::HashResult;
 
 pub struct DummyVerifier<N, B, U> {
@@ -50,6 +50,10 @@ impl<N, B, U> Verifier for DummyVerifier<N, B, U> {
         (HashResult::from(&[0; HashResult::len()]), Vec::new())
     }
 
+    fn add_neighbor(&mut self, _neighbor: B) {
+        // Nothing happens
+    }
+
     fn neighbor_tick(&mut self, _neighbor: B, _tick_hash: HashResult) -> Option<HashResult> {
         // Nothing happens
         None
@@ -59,4 +63,11 @@ impl<N, B, U> Verifier for DummyVerifier<N, B, U> {
         // Nothing happens
         None
     }
+
+    fn status(&self) -> VerifierStatus<B> {
+        VerifierStatus {
+            neighbor_lags: Vec::new(),
+            num_nodes: 0,
+        }
+    }
 }
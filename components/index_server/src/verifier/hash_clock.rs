@@ -9,6 +9,8 @@ const HASH_CLOCK_PREFIX: &[u8] = b"HASH_CLOCK";
 pub struct HashClock<N> {
     /// Last hash we received from each neighbor
     neighbor_hashes: HashMap<N, HashResult>,
+    /// Amount of local ticks since we received the last hash from each neighbor
+    neighbor_lags: HashMap<N, usize>,
     /// Maximum length of last_ticks:
     last_ticks_max_len: usize,
     last_ticks: VecDeque<HashResult>,
@@ -39,6 +41,7 @@ where
 
         HashClock {
             neighbor_hashes: HashMap::new(),
+            neighbor_lags: HashMap::new(),
             last_ticks_max_len,
             last_ticks: VecDeque::new(),
             last_ticks_map: HashMap::new(),
@@ -59,19 +62,38 @@ where
         self.last_ticks_map.insert(tick_hash, expansion);
     }
 
+    /// Should be called when a neighbor connects.
+    /// The neighbor's lag grows from now on, until a hash is received from the neighbor.
+    pub fn add_neighbor(&mut self, neighbor: N) {
+        self.neighbor_lags.insert(neighbor, 0);
+    }
+
     /// Should be called when a new hash is received from a neighbor.
     pub fn neighbor_tick(&mut self, neighbor: N, tick_hash: HashResult) -> Option<HashResult> {
+        self.neighbor_lags.insert(neighbor.clone(), 0);
         self.neighbor_hashes.insert(neighbor, tick_hash)
     }
 
     /// Remove a neighbor from the HashClock.
     pub fn remove_neighbor(&mut self, neighbor: &N) -> Option<HashResult> {
+        self.neighbor_lags.remove(neighbor);
         self.neighbor_hashes.remove(neighbor)
     }
 
+    /// Amount of local ticks since the last hash was received from every neighbor
+    pub fn neighbor_lags(&self) -> impl Iterator<Item = (&N, usize)> {
+        self.neighbor_lags
+            .iter()
+            .map(|(neighbor, lag)| (neighbor, *lag))
+    }
+
     /// Advance the time in the clock by one tick.
     /// The resulting tick_hash should be sent to all the neighbors.
     pub fn tick(&mut self, rand_value: RandValue) -> HashResult {
+        for lag in self.neighbor_lags.values_mut() {
+            *lag = lag.saturating_add(1);
+        }
+
         let mut expansion = Vec::new();
 
         let hashed_rand_value = sha_512_256(&rand_value);
@@ -204,4 +226,29 @@ mod tests {
             .verify_expansion_chain(&origin_tick_hash, &[&expansion1, &expansion2, &expansion3])
             .is_none());
     }
+
+    #[test]
+    fn test_hash_clock_neighbor_lags() {
+        let mut hash_clock = HashClock::new(4);
+        let neighbor_hash = HashResult::from(&[1; HashResult::len()]);
+
+        hash_clock.neighbor_tick(1u8, neighbor_hash.clone());
+        hash_clock.neighbor_tick(2u8, neighbor_hash.clone());
+        // A neighbor that never sends a hash is lagging too:
+        hash_clock.add_neighbor(3u8);
+        for iter in 0..3 {
+            hash_clock.tick(RandValue::from(&[iter; RandValue::len()]));
+        }
+        hash_clock.neighbor_tick(2u8, neighbor_hash);
+
+        let mut neighbor_lags = hash_clock
+            .neighbor_lags()
+            .map(|(neighbor, lag)| (*neighbor, lag))
+            .collect::<Vec<_>>();
+        neighbor_lags.sort();
+        assert_eq!(neighbor_lags, vec![(1u8, 3), (2u8, 0), (3u8, 3)]);
+
+        hash_clock.remove_neighbor(&1u8);
+        assert_eq!(hash_clock.neighbor_lags().count(), 2);
+    }
 }
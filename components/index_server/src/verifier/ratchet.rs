@@ -74,6 +74,11 @@ where
         }
     }
 
+    /// Amount of nodes currently tracked
+    pub fn num_ratchets(&self) -> usize {
+        self.ratchets.len()
+    }
+
     pub fn tick(&mut self) -> Vec<N> {
         let mut removed_nodes = Vec::new();
         for (node, ratchet) in &mut self.ratchets {
@@ -215,6 +220,7 @@ mod tests {
 
         assert!(ratchet_pool.update(&0u128, &0u128, 0));
         assert!(ratchet_pool.update(&1u128, &5u128, 100));
+        assert_eq!(ratchet_pool.num_ratchets(), 2);
 
         for _ in 0..4 {
             assert_eq!(ratchet_pool.tick(), vec![]);
@@ -224,6 +230,7 @@ mod tests {
             assert_eq!(ratchet_pool.tick(), vec![]);
         }
         assert_eq!(ratchet_pool.tick(), vec![0u128]);
+        assert_eq!(ratchet_pool.num_ratchets(), 1);
 
         // We expect that node 0u128 was removed,
         // but node 1u128 was not removed:
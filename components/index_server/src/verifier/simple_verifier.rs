@@ -4,7 +4,7 @@ use proto::crypto::{HashResult, RandValue};
 
 use super::hash_clock::HashClock;
 use super::ratchet::RatchetPool;
-use super::verifier::Verifier;
+use super::verifier::{Verifier, VerifierStatus};
 
 pub struct SimpleVerifier<N, B, U, R> {
     hash_clock: HashClock<B>,
@@ -73,6 +73,10 @@ where
         (self.hash_clock.tick(rand_value), self.ratchet_pool.tick())
     }
 
+    fn add_neighbor(&mut self, neighbor: B) {
+        self.hash_clock.add_neighbor(neighbor)
+    }
+
     fn neighbor_tick(&mut self, neighbor: B, tick_hash: HashResult) -> Option<HashResult> {
         self.hash_clock.neighbor_tick(neighbor, tick_hash)
     }
@@ -80,6 +84,17 @@ where
     fn remove_neighbor(&mut self, neighbor: &B) -> Option<HashResult> {
         self.hash_clock.remove_neighbor(neighbor)
     }
+
+    fn status(&self) -> VerifierStatus<B> {
+        VerifierStatus {
+            neighbor_lags: self
+                .hash_clock
+                .neighbor_lags()
+                .map(|(neighbor, lag)| (neighbor.clone(), lag))
+                .collect(),
+            num_nodes: self.ratchet_pool.num_ratchets(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,5 +1,14 @@
 use proto::crypto::HashResult;
 
+/// Information about the state of a Verifier, used for inspection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifierStatus<B> {
+    /// Amount of local ticks since the last time tick received from every neighbor
+    pub neighbor_lags: Vec<(B, usize)>,
+    /// Amount of nodes whose messages are currently tracked
+    pub num_nodes: usize,
+}
+
 pub trait Verifier {
     type Node;
     type Neighbor;
@@ -22,6 +31,10 @@ pub trait Verifier {
     fn tick(&mut self) -> (HashResult, Vec<Self::Node>);
     // TODO: Can we change to &HashResult? Should we?
 
+    /// Start tracking a neighbor. This method should be invoked when a neighbor connects.
+    /// The neighbor is considered lagging until it sends time ticks.
+    fn add_neighbor(&mut self, neighbor: Self::Neighbor);
+
     /// Process a time tick from a neighbor. This information is used when producing a `tick_hash`.
     fn neighbor_tick(
         &mut self,
@@ -32,4 +45,7 @@ pub trait Verifier {
     /// Remove a neighbor. This method should be invoked when a neighbor disconnects.
     /// If not called, the time proofs (list of hashes) will be larger than needed.
     fn remove_neighbor(&mut self, neighbor: &Self::Neighbor) -> Option<HashResult>;
+
+    /// Get information about the current state of the verifier
+    fn status(&self) -> VerifierStatus<Self::Neighbor>;
 }
@@ -17,9 +17,15 @@ extern crate serde;
 #[macro_use]
 extern crate quickcheck_derive;
 
+#[macro_use]
+extern crate common;
+
 mod node;
+mod rebalancer;
 mod types;
 
 pub use self::node::{node, NodeError};
+pub use self::rebalancer::RebalancerError;
 pub use self::types::{NodeConfig, NodeMutation, NodeState};
-pub use app_server::{ConnPairServer, IncomingAppConnection};
+pub use app_server::{AppPermissionsUpdate, ConnPairServer, IncomingAppConnection};
+pub use index_client::RoutesPrivacy;
@@ -1,6 +1,6 @@
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{select, Future, FutureExt, SinkExt, Stream, StreamExt};
+use futures::{future, select, stream, Future, FutureExt, SinkExt, Stream, StreamExt};
 
 use derive_more::*;
 
@@ -13,7 +13,7 @@ use database::DatabaseClient;
 use identity::IdentityClient;
 use timer::TimerClient;
 
-use app_server::{app_server_loop, AppServerError, IncomingAppConnection};
+use app_server::{app_server_loop, AppPermissionsUpdate, AppServerError, IncomingAppConnection};
 use channeler::{channeler_loop, ChannelerError};
 use funder::types::{
     ChannelerConfig, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
@@ -36,6 +36,7 @@ use proto::index_server::messages::IndexServerAddress;
 use proto::net::messages::NetAddress;
 use proto::report::convert::funder_report_to_index_client_state;
 
+use crate::rebalancer::{create_rebalancer_app_connection, rebalancer_loop, RebalancerError};
 use crate::types::{create_node_report, NodeConfig, NodeMutation, NodeState};
 
 #[derive(Debug, From)]
@@ -47,6 +48,8 @@ pub enum NodeError {
     FunderError(FunderError),
     IndexClientError(IndexClientError),
     AppServerError(AppServerError),
+    RebalancerError(RebalancerError),
+    RequestTimerStreamError,
 }
 
 fn node_spawn_channeler<C, EKT, S>(
@@ -214,6 +217,7 @@ where
         node_config.max_node_relays,
         node_config.max_operations_in_batch,
         node_config.max_pending_user_requests,
+        node_config.announce_credit_limits,
         funder_state,
         funder_db_client,
     );
@@ -322,6 +326,9 @@ where
         node_config.max_open_index_client_requests,
         node_config.keepalive_ticks,
         node_config.backoff_ticks,
+        node_config.routes_privacy,
+        node_config.opt_topology_sync_ticks,
+        node_config.report_route_outcomes,
         index_connector,
         rng,
         spawner.clone(),
@@ -330,8 +337,51 @@ where
     .map_err(|_| NodeError::SpawnError)
 }
 
+async fn node_spawn_rebalancer<R, S>(
+    node_config: &NodeConfig,
+    local_public_key: PublicKey,
+    mut timer_client: TimerClient,
+    rng: R,
+    spawner: S,
+) -> Result<
+    (
+        IncomingAppConnection<NetAddress>,
+        impl Future<Output = Result<(), RebalancerError>>,
+    ),
+    NodeError,
+>
+where
+    R: CryptoRandom + Clone + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let timer_stream = timer_client
+        .request_timer_stream("rebalancer".to_owned())
+        .await
+        .map_err(|_| NodeError::RequestTimerStreamError)?;
+
+    // The rebalancer connects to the app server as an internal app:
+    let (incoming_app_connection, report_receiver) =
+        create_rebalancer_app_connection(local_public_key.clone());
+
+    let rebalancer_fut = rebalancer_loop(
+        local_public_key,
+        report_receiver,
+        timer_stream,
+        node_config.rebalance_backoff_ticks,
+        node_config.rebalance_stage_timeout_ticks,
+        node_config.channel_len,
+        rng,
+    );
+
+    let rebalancer_handle = spawner
+        .spawn_with_handle(rebalancer_fut)
+        .map_err(|_| NodeError::SpawnError)?;
+
+    Ok((incoming_app_connection, rebalancer_handle))
+}
+
 // TODO: Possibly rename this function?
-pub async fn node<C, EKT, IA, R, S>(
+pub async fn node<C, EKT, IA, PU, R, S>(
     node_config: NodeConfig,
     identity_client: IdentityClient,
     timer_client: TimerClient,
@@ -341,6 +391,8 @@ pub async fn node<C, EKT, IA, R, S>(
     // encrypt_keepalive is used for encryption of the relayed communication between two nodes.
     encrypt_keepalive: EKT,
     incoming_apps: IA,
+    // Changes to the permissions of trusted apps (Including revocations):
+    app_permissions_updates: PU,
     rng: R,
     spawner: S,
 ) -> Result<(), NodeError>
@@ -356,6 +408,7 @@ where
         + Send
         + 'static,
     IA: Stream<Item = IncomingAppConnection<NetAddress>> + Unpin + Send + 'static,
+    PU: Stream<Item = AppPermissionsUpdate> + Unpin + Send + 'static,
     R: CryptoRandom + Clone + Send + 'static,
     S: Spawn + Clone + Send + 'static,
 {
@@ -415,13 +468,29 @@ where
     let (index_client_to_app_server_sender, index_client_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
 
+    // Rebalancer (Connected to the AppServer as an internal app)
+    let (rebalancer_app_connection, rebalancer_handle) = node_spawn_rebalancer(
+        &node_config,
+        local_public_key.clone(),
+        timer_client.clone(),
+        rng.clone(),
+        spawner.clone(),
+    )
+    .await?;
+    let incoming_apps = stream::select(
+        incoming_apps,
+        stream::once(future::ready(rebalancer_app_connection)),
+    );
+
     let app_server_fut = app_server_loop(
         funder_to_app_server_receiver,
         app_server_to_funder_sender,
         index_client_to_app_server_receiver,
         app_server_to_index_client_sender,
         incoming_apps,
+        app_permissions_updates,
         initial_node_report.clone(),
+        identity_client.clone(),
         spawner.clone(),
     );
 
@@ -450,6 +519,7 @@ where
         res = funder_handle.fuse() => res?,
         res = app_server_handle.fuse() => res?,
         res = index_client_handle.fuse() => res?,
+        res = rebalancer_handle.fuse() => res?,
     }
     Ok(())
 }
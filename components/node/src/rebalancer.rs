@@ -0,0 +1,785 @@
+use std::collections::HashMap;
+
+use futures::channel::{mpsc, oneshot};
+use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+
+use common::conn::{BoxStream, ConnPair};
+use common::select_streams::select_streams;
+
+use crypto::rand::{CryptoRandom, RandGen};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppRestrictions, AppServerToApp, AppToAppServer, NodeReport,
+    ReportMutations,
+};
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    AckClosePayment, AddInvoice, CreatePayment, CreateTransaction, Currency, FriendsRoute,
+    PaymentStatus, RequestResult, ResponseClosePayment, TransactionResult,
+};
+use proto::index_client::messages::{ClientResponseRoutes, ResponseRoutesResult};
+use proto::index_server::messages::{Edge, MultiRoute, RequestRoutes};
+use proto::net::messages::NetAddress;
+use proto::report::messages::{ChannelStatusReport, FriendLivenessReport, FriendStatusReport};
+
+use app_server::{ConnPairServer, IncomingAppConnection};
+
+#[derive(Debug)]
+pub enum RebalancerError {
+    FirstNodeReportError,
+    AppServerClosed,
+    SendToAppServerError,
+    ReportMutationError,
+}
+
+#[derive(Debug)]
+enum RebalancerEvent {
+    FromAppServer(AppServerToApp<NetAddress>),
+    AppServerClosed,
+    TimerTick,
+}
+
+/// Direction of a rebalance loop, with respect to the rebalanced friend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RebalanceDirection {
+    /// The friend owes us too much. We pay the friend, and get paid back through another friend:
+    /// local -- friend -- ... -- local
+    PayFriend,
+    /// We owe the friend too much. We pay another friend, and get paid back by the friend:
+    /// local -- ... -- friend -- local
+    PaidByFriend,
+}
+
+#[derive(Debug)]
+enum RebalanceStage {
+    /// Waiting for routes from the index client
+    RequestRoutes(Uid),
+    /// Waiting for the self payment transaction to complete
+    Transaction((PaymentId, InvoiceId, Uid)), // (payment_id, invoice_id, request_id)
+    /// Waiting for the self payment to be closed
+    ClosePayment(PaymentId),
+}
+
+#[derive(Debug)]
+struct Rebalance {
+    friend_public_key: PublicKey,
+    currency: Currency,
+    amount: u128,
+    max_fees: u128,
+    direction: RebalanceDirection,
+    stage: RebalanceStage,
+    /// Amount of ticks we have been waiting in the current stage
+    stage_ticks: usize,
+}
+
+struct Rebalancer<TAS, R> {
+    local_public_key: PublicKey,
+    node_report: NodeReport<NetAddress>,
+    /// We handle only one rebalance at a time:
+    opt_rebalance: Option<Rebalance>,
+    /// Amount of ticks left until we may attempt to rebalance a mutual credit again
+    backoffs: HashMap<(PublicKey, Currency), usize>,
+    backoff_ticks: usize,
+    /// Amount of ticks we wait in a single stage before giving up on a rebalance
+    stage_timeout_ticks: usize,
+    to_app_server: TAS,
+    rng: R,
+}
+
+/// Pick the cheapest route that can carry `amount` credits within the fees budget.
+/// Returns the full loop route (Starting and ending with the local node).
+///
+/// The rate of a route returned by the index servers does not cover the friend's hop: The friend
+/// is the first hop of a `PayFriend` loop, and it is no longer the destination of a
+/// `PaidByFriend` loop. The friend's rate is not known to us, so the fees of the self payment
+/// should be the whole fees budget. We are the destination of the loop, so any fees not taken by
+/// the nodes along the route are paid back to us.
+fn choose_loop_route(
+    multi_routes: &[MultiRoute],
+    local_public_key: &PublicKey,
+    direction: &RebalanceDirection,
+    amount: u128,
+    max_fees: u128,
+) -> Option<FriendsRoute> {
+    let mut opt_best: Option<(FriendsRoute, u128)> = None;
+    for route_capacity_rate in multi_routes
+        .iter()
+        .flat_map(|multi_route| &multi_route.routes)
+    {
+        if route_capacity_rate.capacity < amount {
+            continue;
+        }
+        let fees = match route_capacity_rate.rate.calc_fee(amount) {
+            Some(fees) if fees <= max_fees => fees,
+            _ => continue,
+        };
+
+        let mut public_keys = route_capacity_rate.route.public_keys.clone();
+        match direction {
+            RebalanceDirection::PayFriend => public_keys.insert(0, local_public_key.clone()),
+            RebalanceDirection::PaidByFriend => public_keys.push(local_public_key.clone()),
+        }
+        let route = FriendsRoute { public_keys };
+        if !route.is_valid() {
+            continue;
+        }
+
+        let is_better = match &opt_best {
+            Some((_, best_fees)) => fees < *best_fees,
+            None => true,
+        };
+        if is_better {
+            opt_best = Some((route, fees));
+        }
+    }
+    opt_best.map(|(route, _fees)| route)
+}
+
+impl<TAS, R> Rebalancer<TAS, R>
+where
+    TAS: Sink<AppToAppServer<NetAddress>> + Unpin,
+    R: CryptoRandom,
+{
+    fn new(
+        local_public_key: PublicKey,
+        node_report: NodeReport<NetAddress>,
+        backoff_ticks: usize,
+        stage_timeout_ticks: usize,
+        to_app_server: TAS,
+        rng: R,
+    ) -> Self {
+        Rebalancer {
+            local_public_key,
+            node_report,
+            opt_rebalance: None,
+            backoffs: HashMap::new(),
+            backoff_ticks,
+            stage_timeout_ticks,
+            to_app_server,
+            rng,
+        }
+    }
+
+    async fn send_request(
+        &mut self,
+        app_request: AppRequest<NetAddress>,
+    ) -> Result<(), RebalancerError> {
+        let app_to_app_server = AppToAppServer::new(Uid::rand_gen(&mut self.rng), app_request);
+        self.to_app_server
+            .send(app_to_app_server)
+            .await
+            .map_err(|_| RebalancerError::SendToAppServerError)
+    }
+
+    /// Finish the current rebalance (If any), and wait before rebalancing the same
+    /// mutual credit again.
+    fn end_rebalance(&mut self) {
+        if let Some(rebalance) = self.opt_rebalance.take() {
+            self.backoffs.insert(
+                (rebalance.friend_public_key, rebalance.currency),
+                self.backoff_ticks,
+            );
+        }
+    }
+
+    /// Find a mutual credit that should be rebalanced.
+    /// Returns (friend_public_key, currency, amount, max_fees, direction)
+    fn find_unbalanced(&self) -> Option<(PublicKey, Currency, u128, u128, RebalanceDirection)> {
+        for (friend_public_key, friend_report) in &self.node_report.funder_report.friends {
+            if friend_report.status != FriendStatusReport::Enabled
+                || friend_report.liveness != FriendLivenessReport::Online
+            {
+                continue;
+            }
+            let currency_reports = match &friend_report.channel_status {
+                ChannelStatusReport::Consistent(channel_consistent) => {
+                    &channel_consistent.currency_reports
+                }
+                ChannelStatusReport::Inconsistent(_) => continue,
+            };
+            for currency_config in &friend_report.currency_configs {
+                let rebalance_policy = match &currency_config.opt_rebalance_policy {
+                    Some(rebalance_policy) => rebalance_policy,
+                    None => continue,
+                };
+                if !currency_config.is_open
+                    || self.backoffs.contains_key(&(
+                        friend_public_key.clone(),
+                        currency_config.currency.clone(),
+                    ))
+                {
+                    continue;
+                }
+                let balance = match currency_reports
+                    .iter()
+                    .find(|currency_report| currency_report.currency == currency_config.currency)
+                {
+                    Some(currency_report) => currency_report.balance.balance,
+                    None => continue,
+                };
+
+                let (amount, direction) = if balance >= 0 {
+                    (balance as u128, RebalanceDirection::PayFriend)
+                } else {
+                    (
+                        balance.wrapping_neg() as u128,
+                        RebalanceDirection::PaidByFriend,
+                    )
+                };
+                if amount > rebalance_policy.threshold {
+                    return Some((
+                        friend_public_key.clone(),
+                        currency_config.currency.clone(),
+                        amount,
+                        rebalance_policy.max_fees,
+                        direction,
+                    ));
+                }
+            }
+        }
+        None
+    }
+
+    async fn try_start_rebalance(&mut self) -> Result<(), RebalancerError> {
+        if self.opt_rebalance.is_some() {
+            return Ok(());
+        }
+
+        let (friend_public_key, currency, amount, max_fees, direction) =
+            if let Some(unbalanced) = self.find_unbalanced() {
+                unbalanced
+            } else {
+                return Ok(());
+            };
+
+        // Find a loop that does not go through the depleted edge:
+        let (source, destination, opt_exclude) = match direction {
+            RebalanceDirection::PayFriend => (
+                friend_public_key.clone(),
+                self.local_public_key.clone(),
+                Edge {
+                    from_public_key: friend_public_key.clone(),
+                    to_public_key: self.local_public_key.clone(),
+                },
+            ),
+            RebalanceDirection::PaidByFriend => (
+                self.local_public_key.clone(),
+                friend_public_key.clone(),
+                Edge {
+                    from_public_key: self.local_public_key.clone(),
+                    to_public_key: friend_public_key.clone(),
+                },
+            ),
+        };
+
+        let request_id = Uid::rand_gen(&mut self.rng);
+        let request_routes = RequestRoutes {
+            request_id: request_id.clone(),
+            currency: currency.clone(),
+            capacity: amount,
+            source,
+            destination,
+            opt_exclude: Some(opt_exclude),
+        };
+
+        info!(
+            "Rebalancer: Attempting to rebalance {} credits of {} with friend {:?}",
+            amount, currency, friend_public_key
+        );
+
+        self.opt_rebalance = Some(Rebalance {
+            friend_public_key,
+            currency,
+            amount,
+            max_fees,
+            direction,
+            stage: RebalanceStage::RequestRoutes(request_id),
+            stage_ticks: 0,
+        });
+
+        self.send_request(AppRequest::RequestRoutes(request_routes))
+            .await
+    }
+
+    async fn handle_report_mutations(
+        &mut self,
+        report_mutations: ReportMutations<NetAddress>,
+    ) -> Result<(), RebalancerError> {
+        for mutation in &report_mutations.mutations {
+            self.node_report
+                .mutate(mutation)
+                .map_err(|_| RebalancerError::ReportMutationError)?;
+        }
+        self.try_start_rebalance().await
+    }
+
+    async fn handle_response_routes(
+        &mut self,
+        client_response_routes: ClientResponseRoutes,
+    ) -> Result<(), RebalancerError> {
+        let rebalance = match &self.opt_rebalance {
+            Some(rebalance) => rebalance,
+            None => return Ok(()),
+        };
+        match &rebalance.stage {
+            RebalanceStage::RequestRoutes(request_id)
+                if request_id == &client_response_routes.request_id => {}
+            _ => return Ok(()),
+        }
+
+        let opt_loop_route = match &client_response_routes.result {
+            ResponseRoutesResult::Success(multi_routes) => choose_loop_route(
+                multi_routes,
+                &self.local_public_key,
+                &rebalance.direction,
+                rebalance.amount,
+                rebalance.max_fees,
+            ),
+            ResponseRoutesResult::Failure => None,
+        };
+        let currency = rebalance.currency.clone();
+        let amount = rebalance.amount;
+        // The route's rate does not cover the friend's hop (See `choose_loop_route()`):
+        let fees = rebalance.max_fees;
+
+        let route = if let Some(loop_route) = opt_loop_route {
+            loop_route
+        } else {
+            warn!("Rebalancer: No suitable loop route was found");
+            self.end_rebalance();
+            return Ok(());
+        };
+
+        let payment_id = PaymentId::rand_gen(&mut self.rng);
+        let invoice_id = InvoiceId::rand_gen(&mut self.rng);
+        let request_id = Uid::rand_gen(&mut self.rng);
+
+        if let Some(rebalance) = &mut self.opt_rebalance {
+            rebalance.stage = RebalanceStage::Transaction((
+                payment_id.clone(),
+                invoice_id.clone(),
+                request_id.clone(),
+            ));
+            rebalance.stage_ticks = 0;
+        }
+
+        // We are both the seller and the buyer of this payment:
+        let add_invoice = AddInvoice {
+            invoice_id: invoice_id.clone(),
+            currency: currency.clone(),
+            total_dest_payment: amount,
+        };
+        self.send_request(AppRequest::AddInvoice(add_invoice))
+            .await?;
+
+        let create_payment = CreatePayment {
+            payment_id: payment_id.clone(),
+            invoice_id,
+            currency,
+            total_dest_payment: amount,
+            dest_public_key: self.local_public_key.clone(),
+        };
+        self.send_request(AppRequest::CreatePayment(create_payment))
+            .await?;
+
+        let create_transaction = CreateTransaction {
+            payment_id: payment_id.clone(),
+            request_id,
+            route,
+            dest_payment: amount,
+            fees,
+        };
+        self.send_request(AppRequest::CreateTransaction(create_transaction))
+            .await?;
+
+        // Signal that no new transactions will be created:
+        self.send_request(AppRequest::RequestClosePayment(payment_id))
+            .await
+    }
+
+    async fn handle_transaction_result(
+        &mut self,
+        transaction_result: TransactionResult,
+    ) -> Result<(), RebalancerError> {
+        let (payment_id, invoice_id) = match &self.opt_rebalance {
+            Some(Rebalance {
+                stage: RebalanceStage::Transaction((payment_id, invoice_id, request_id)),
+                ..
+            }) if request_id == &transaction_result.request_id => {
+                (payment_id.clone(), invoice_id.clone())
+            }
+            _ => return Ok(()),
+        };
+
+        let app_request = match transaction_result.result {
+            // Commit the invoice on the seller side, to collect the credits:
+            RequestResult::Complete(commit) => AppRequest::CommitInvoice(commit),
+            RequestResult::Success => return Ok(()),
+            RequestResult::Failure => {
+                warn!("Rebalancer: Self payment transaction failed");
+                AppRequest::CancelInvoice(invoice_id)
+            }
+        };
+
+        if let Some(rebalance) = &mut self.opt_rebalance {
+            rebalance.stage = RebalanceStage::ClosePayment(payment_id);
+            rebalance.stage_ticks = 0;
+        }
+        self.send_request(app_request).await
+    }
+
+    async fn handle_response_close_payment(
+        &mut self,
+        response_close_payment: ResponseClosePayment,
+    ) -> Result<(), RebalancerError> {
+        let is_current_rebalance = match &self.opt_rebalance {
+            Some(Rebalance {
+                stage: RebalanceStage::Transaction((payment_id, _, _)),
+                ..
+            })
+            | Some(Rebalance {
+                stage: RebalanceStage::ClosePayment(payment_id),
+                ..
+            }) => payment_id == &response_close_payment.payment_id,
+            _ => false,
+        };
+
+        let opt_ack_uid = match response_close_payment.status {
+            PaymentStatus::PaymentNotFound => None,
+            PaymentStatus::Success(payment_status_success) => {
+                info!("Rebalancer: Rebalance completed successfully");
+                Some(payment_status_success.ack_uid)
+            }
+            PaymentStatus::Canceled(ack_uid) => Some(ack_uid),
+        };
+        // All the payments of the rebalancer are self payments. We also ack the payments of
+        // rebalances we gave up on, so that they are not stuck inside the node:
+        if is_current_rebalance {
+            self.end_rebalance();
+        }
+
+        if let Some(ack_uid) = opt_ack_uid {
+            let ack_close_payment = AckClosePayment {
+                payment_id: response_close_payment.payment_id,
+                ack_uid,
+            };
+            self.send_request(AppRequest::AckClosePayment(ack_close_payment))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_from_app_server(
+        &mut self,
+        app_server_to_app: AppServerToApp<NetAddress>,
+    ) -> Result<(), RebalancerError> {
+        match app_server_to_app {
+            AppServerToApp::TransactionResult(transaction_result) => {
+                self.handle_transaction_result(transaction_result).await
+            }
+            AppServerToApp::ResponseClosePayment(response_close_payment) => {
+                self.handle_response_close_payment(response_close_payment)
+                    .await
+            }
+            AppServerToApp::ReportMutations(report_mutations) => {
+                self.handle_report_mutations(report_mutations).await
+            }
+            AppServerToApp::ResponseRoutes(client_response_routes) => {
+                self.handle_response_routes(client_response_routes).await
+            }
+            // The rebalancer never signs or opens invoices:
+            AppServerToApp::ResponseSignInvoice(_) | AppServerToApp::InvoiceCommitted(_) => Ok(()),
+            // The rebalancer never sends verification requests:
+            AppServerToApp::ResponseVerifyReceipt(_)
+            | AppServerToApp::ResponseVerifyFriendToken(_) => Ok(()),
+        }
+    }
+
+    /// Give up on the current rebalance if we have been waiting for a response for too long.
+    /// A response might never arrive, for example if the index client lost a request.
+    async fn check_stage_timeout(&mut self) -> Result<(), RebalancerError> {
+        let opt_invoice_id = match &mut self.opt_rebalance {
+            Some(rebalance) => {
+                rebalance.stage_ticks = rebalance.stage_ticks.saturating_add(1);
+                if rebalance.stage_ticks < self.stage_timeout_ticks {
+                    return Ok(());
+                }
+                match &rebalance.stage {
+                    RebalanceStage::Transaction((_, invoice_id, _)) => Some(invoice_id.clone()),
+                    RebalanceStage::RequestRoutes(_) | RebalanceStage::ClosePayment(_) => None,
+                }
+            }
+            None => return Ok(()),
+        };
+
+        warn!("Rebalancer: Timed out waiting for a response. Giving up on rebalance");
+        self.end_rebalance();
+
+        // Make sure that a late transaction can not complete the payment:
+        if let Some(invoice_id) = opt_invoice_id {
+            self.send_request(AppRequest::CancelInvoice(invoice_id))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_timer_tick(&mut self) -> Result<(), RebalancerError> {
+        for ticks_left in self.backoffs.values_mut() {
+            *ticks_left = ticks_left.saturating_sub(1);
+        }
+        self.backoffs.retain(|_, ticks_left| *ticks_left > 0);
+        self.check_stage_timeout().await?;
+        self.try_start_rebalance().await
+    }
+}
+
+/// Create an internal app connection for the rebalancer.
+/// The rebalancer can request routes, and pay itself (As a buyer and a seller).
+/// It can not change the node's configuration.
+/// The rebalancer is identified by the public key of the node itself.
+pub fn create_rebalancer_app_connection(
+    local_public_key: PublicKey,
+) -> (
+    IncomingAppConnection<NetAddress>,
+    oneshot::Receiver<(
+        NodeReport<NetAddress>,
+        oneshot::Sender<ConnPairServer<NetAddress>>,
+    )>,
+) {
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: false,
+        restrictions: AppRestrictions::default(),
+    };
+    let (report_sender, report_receiver) = oneshot::channel();
+    let incoming_app_connection = IncomingAppConnection {
+        app_public_key: local_public_key,
+        app_permissions,
+        report_sender,
+    };
+    (incoming_app_connection, report_receiver)
+}
+
+/// Automatically rebalance mutual credits with friends.
+/// When the balance with a friend passes the threshold of the configured rebalance policy, we
+/// find a loop through the index servers that does not go through the depleted edge, and pay
+/// ourselves along that loop.
+pub async fn rebalancer_loop<TS, R>(
+    local_public_key: PublicKey,
+    report_receiver: oneshot::Receiver<(
+        NodeReport<NetAddress>,
+        oneshot::Sender<ConnPairServer<NetAddress>>,
+    )>,
+    timer_stream: TS,
+    backoff_ticks: usize,
+    stage_timeout_ticks: usize,
+    channel_len: usize,
+    rng: R,
+) -> Result<(), RebalancerError>
+where
+    TS: Stream + Send + Unpin,
+    R: CryptoRandom,
+{
+    let (node_report, conn_pair_sender) = report_receiver
+        .await
+        .map_err(|_| RebalancerError::FirstNodeReportError)?;
+
+    // Channel between the rebalancer and the app server:
+    let (rebalancer_sender, server_receiver) = mpsc::channel(channel_len);
+    let (server_sender, rebalancer_receiver) = mpsc::channel(channel_len);
+
+    conn_pair_sender
+        .send(ConnPair::from_raw(server_sender, server_receiver))
+        .map_err(|_| RebalancerError::AppServerClosed)?;
+
+    let mut rebalancer = Rebalancer::new(
+        local_public_key,
+        node_report,
+        backoff_ticks,
+        stage_timeout_ticks,
+        rebalancer_sender,
+        rng,
+    );
+
+    // Check if any rebalancing is required according to the initial report:
+    rebalancer.try_start_rebalance().await?;
+
+    let timer_stream = timer_stream.map(|_| RebalancerEvent::TimerTick);
+
+    let from_app_server = rebalancer_receiver
+        .map(RebalancerEvent::FromAppServer)
+        .chain(stream::once(future::ready(
+            RebalancerEvent::AppServerClosed,
+        )));
+
+    let mut events = select_streams![from_app_server, timer_stream];
+
+    while let Some(event) = events.next().await {
+        match event {
+            RebalancerEvent::FromAppServer(app_server_to_app) => {
+                rebalancer.handle_from_app_server(app_server_to_app).await?
+            }
+            RebalancerEvent::AppServerClosed => return Err(RebalancerError::AppServerClosed),
+            RebalancerEvent::TimerTick => rebalancer.handle_timer_tick().await?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use futures::executor::block_on;
+
+    use crypto::test_utils::DummyRandom;
+
+    use proto::funder::messages::Rate;
+    use proto::index_client::messages::IndexClientReport;
+    use proto::index_server::messages::RouteCapacityRate;
+    use proto::report::messages::FunderReport;
+
+    /// A helper function to create a test public key
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    fn dummy_node_report() -> NodeReport<NetAddress> {
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key: pk(0),
+                relays: Vec::new(),
+                friends: HashMap::new(),
+                exchange_rates: Vec::new(),
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                topology_snapshots: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_rebalance_stage_timeout() {
+        let (to_app_server, mut from_rebalancer) = mpsc::channel(0x10);
+        let mut rebalancer = Rebalancer::new(
+            pk(0),
+            dummy_node_report(),
+            8,
+            2,
+            to_app_server,
+            DummyRandom::new(&[1u8]),
+        );
+
+        // A rebalance that waits for the result of its transaction:
+        let currency = Currency::try_from("FST".to_owned()).unwrap();
+        let invoice_id = InvoiceId::from(&[1; InvoiceId::len()]);
+        rebalancer.opt_rebalance = Some(Rebalance {
+            friend_public_key: pk(1),
+            currency: currency.clone(),
+            amount: 100,
+            max_fees: 10,
+            direction: RebalanceDirection::PayFriend,
+            stage: RebalanceStage::Transaction((
+                PaymentId::from(&[2; PaymentId::len()]),
+                invoice_id.clone(),
+                Uid::from(&[3; Uid::len()]),
+            )),
+            stage_ticks: 0,
+        });
+
+        // Still waiting:
+        block_on(rebalancer.handle_timer_tick()).unwrap();
+        assert!(rebalancer.opt_rebalance.is_some());
+
+        // The transaction result never arrived. We give up on the rebalance:
+        block_on(rebalancer.handle_timer_tick()).unwrap();
+        assert!(rebalancer.opt_rebalance.is_none());
+        assert!(rebalancer.backoffs.contains_key(&(pk(1), currency)));
+
+        // The invoice is canceled, so that the payment can not complete later:
+        let app_to_app_server = from_rebalancer.try_next().unwrap().unwrap();
+        assert_eq!(
+            app_to_app_server.app_request,
+            AppRequest::CancelInvoice(invoice_id)
+        );
+        assert!(from_rebalancer.try_next().is_err());
+    }
+
+    #[test]
+    fn test_choose_loop_route() {
+        let multi_routes = vec![
+            MultiRoute {
+                routes: vec![RouteCapacityRate {
+                    route: FriendsRoute {
+                        public_keys: vec![pk(1), pk(2), pk(0)],
+                    },
+                    capacity: 100,
+                    rate: Rate { mul: 0, add: 5 },
+                }],
+            },
+            MultiRoute {
+                routes: vec![
+                    // Not enough capacity:
+                    RouteCapacityRate {
+                        route: FriendsRoute {
+                            public_keys: vec![pk(1), pk(3), pk(0)],
+                        },
+                        capacity: 10,
+                        rate: Rate { mul: 0, add: 1 },
+                    },
+                    RouteCapacityRate {
+                        route: FriendsRoute {
+                            public_keys: vec![pk(1), pk(4), pk(0)],
+                        },
+                        capacity: 50,
+                        rate: Rate { mul: 0, add: 2 },
+                    },
+                ],
+            },
+        ];
+
+        // Cheapest route with enough capacity:
+        let route = choose_loop_route(
+            &multi_routes,
+            &pk(0),
+            &RebalanceDirection::PayFriend,
+            20,
+            10,
+        )
+        .unwrap();
+        assert_eq!(route.public_keys, vec![pk(0), pk(1), pk(4), pk(0)]);
+
+        // Only the first route has enough capacity:
+        let route = choose_loop_route(
+            &multi_routes,
+            &pk(0),
+            &RebalanceDirection::PayFriend,
+            80,
+            10,
+        )
+        .unwrap();
+        assert_eq!(route.public_keys, vec![pk(0), pk(1), pk(2), pk(0)]);
+
+        // Fees are above budget:
+        assert!(
+            choose_loop_route(&multi_routes, &pk(0), &RebalanceDirection::PayFriend, 80, 4)
+                .is_none()
+        );
+
+        // Appending the local node to these routes would result in an invalid route:
+        assert!(choose_loop_route(
+            &multi_routes,
+            &pk(0),
+            &RebalanceDirection::PaidByFriend,
+            20,
+            10
+        )
+        .is_none());
+    }
+}
@@ -2,7 +2,7 @@ use common::mutable_state::MutableState;
 
 use funder::report::create_initial_report;
 use funder::{FunderMutation, FunderState};
-use index_client::{IndexClientConfig, IndexClientConfigMutation};
+use index_client::{IndexClientConfig, IndexClientConfigMutation, RoutesPrivacy};
 
 use proto::app_server::messages::NodeReport;
 use proto::crypto::PublicKey;
@@ -68,6 +68,8 @@ where
         index_servers: index_client_config.index_servers.clone(),
         // Initially we are not connected to a server:
         opt_connected_server: None,
+        // Topology snapshots are only kept in memory:
+        topology_snapshots: Vec::new(),
     }
 }
 
@@ -107,6 +109,21 @@ pub struct NodeConfig {
     pub max_open_index_client_requests: usize,
     /// Maximum amount of relays a node may use.
     pub max_node_relays: usize,
+    /// The amount of ticks we wait before attempting to rebalance a mutual credit again.
+    pub rebalance_backoff_ticks: usize,
+    /// The amount of ticks we wait for a response during a rebalance before giving up.
+    pub rebalance_stage_timeout_ticks: usize,
+    /// Announce to friends the credit we are willing to extend to them.
+    pub announce_credit_limits: bool,
+    /// Privacy settings for routes requests sent to index servers.
+    pub routes_privacy: RoutesPrivacy,
+    /// Amount of ticks between syncs of route topology snapshots from the index server.
+    /// If None, topology snapshots are not synced, and all routes are requested from the index
+    /// server.
+    pub opt_topology_sync_ticks: Option<usize>,
+    /// Report outcomes of routes used for sending credits to the index server, to help it rank
+    /// routes by reliability. The amounts of credits sent are not reported.
+    pub report_route_outcomes: bool,
     /*
     /// Maximum amount of encryption set ups we allow to occur at the same time
     /// for incoming app connections
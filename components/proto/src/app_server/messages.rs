@@ -1,23 +1,28 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
 
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
 use common::mutable_state::MutableState;
-use common::ser_utils::ser_b64;
+use common::ser_utils::{ser_b64, ser_string, ser_vec_b64};
 
-use crate::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use crate::crypto::{InvoiceId, PaymentId, PublicKey, Signature, Uid};
 
 use crate::funder::messages::{
     AckClosePayment, AddFriend, AddInvoice, Commit, CreatePayment, CreateTransaction, Currency,
-    RemoveFriendCurrency, ResetFriendChannel, ResponseClosePayment, SetFriendCurrencyMaxDebt,
-    SetFriendCurrencyRate, SetFriendName, SetFriendRelays, TransactionResult,
+    CurrencyPair, DeliverCommit, ExchangeRate, Receipt, RemoveFriendCurrency, ResetFriendChannel,
+    ResponseClosePayment, SetFriendCurrencyFeePolicy, SetFriendCurrencyMaxDebt,
+    SetFriendCurrencyRate, SetFriendCurrencyRebalancePolicy, SetFriendName, SetFriendRelays,
+    TransactionResult,
 };
 use crate::index_client::messages::{
     ClientResponseRoutes, IndexClientReport, IndexClientReportMutation,
 };
 use crate::index_server::messages::{NamedIndexServerAddress, RequestRoutes};
 use crate::net::messages::NetAddress;
-use crate::report::messages::{FunderReport, FunderReportMutation};
+use crate::report::messages::{FunderReport, FunderReportMutation, MoveTokenHashedReport};
+use crate::wrapper::Wrapper;
 
 // TODO: Move NamedRelayAddress and RelayAddress to another place in offset-proto?
 
@@ -49,6 +54,113 @@ impl<B> From<NamedRelayAddress<B>> for RelayAddress<B> {
     }
 }
 
+/// An invoice, before it is signed by the seller node.
+#[capnp_conv(crate::app_server_capnp::unsigned_invoice)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq)]
+pub struct UnsignedInvoice<B = NetAddress> {
+    pub invoice_id: InvoiceId,
+    pub currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    pub total_dest_payment: u128,
+    /// Relays of the seller, allowing a buyer to contact the seller directly.
+    pub relays: Vec<RelayAddress<B>>,
+    /// Expiry of the invoice (Seconds since the Unix epoch)
+    pub valid_until: u64,
+    pub description: String,
+}
+
+/// An invoice signed by the seller node.
+/// Can be handed to a buyer (For example, encoded as an invoice URI).
+#[capnp_conv(crate::app_server_capnp::signed_invoice)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedInvoice<B = NetAddress> {
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    pub currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+    pub relays: Vec<RelayAddress<B>>,
+    /// Expiry of the invoice (Seconds since the Unix epoch)
+    pub valid_until: u64,
+    pub description: String,
+    /// The seller node
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub signature: Signature,
+}
+
+impl<B> From<SignedInvoice<B>> for UnsignedInvoice<B> {
+    fn from(from: SignedInvoice<B>) -> Self {
+        UnsignedInvoice {
+            invoice_id: from.invoice_id,
+            currency: from.currency,
+            total_dest_payment: from.total_dest_payment,
+            relays: from.relays,
+            valid_until: from.valid_until,
+            description: from.description,
+        }
+    }
+}
+
+/// Verify a receipt (Received from a buyer) against an invoice.
+#[capnp_conv(crate::app_server_capnp::verify_receipt)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReceipt {
+    pub request_id: Uid,
+    pub receipt: Receipt,
+    /// The seller node, expected to have signed the receipt
+    pub dest_public_key: PublicKey,
+    pub invoice_id: InvoiceId,
+    #[capnp_conv(with = Wrapper<u128>)]
+    pub total_dest_payment: u128,
+}
+
+#[capnp_conv(crate::app_server_capnp::verify_receipt_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyReceiptStatus {
+    Valid,
+    /// The receipt does not match the given invoice id or total payment
+    InvoiceMismatch,
+    /// The receipt was not signed by `dest_public_key`
+    WrongSigner,
+}
+
+#[capnp_conv(crate::app_server_capnp::response_verify_receipt)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseVerifyReceipt {
+    pub request_id: Uid,
+    pub status: VerifyReceiptStatus,
+}
+
+/// Verify the last token received from a friend.
+#[capnp_conv(crate::app_server_capnp::verify_friend_token)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyFriendToken {
+    pub request_id: Uid,
+    pub move_token_hashed_report: MoveTokenHashedReport,
+    /// The friend expected to have signed the token
+    pub friend_public_key: PublicKey,
+}
+
+#[capnp_conv(crate::app_server_capnp::verify_friend_token_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyFriendTokenStatus {
+    Valid,
+    /// The token was signed by someone other than `friend_public_key`
+    WrongSigner,
+    InvalidSignature,
+}
+
+#[capnp_conv(crate::app_server_capnp::response_verify_friend_token)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseVerifyFriendToken {
+    pub request_id: Uid,
+    pub status: VerifyFriendTokenStatus,
+}
+
 #[capnp_conv(crate::report_capnp::node_report)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NodeReport<B = NetAddress> {
@@ -107,6 +219,12 @@ pub enum AppServerToApp<B = NetAddress> {
     // Report(NodeReport<B>),
     ReportMutations(ReportMutations<B>),
     ResponseRoutes(ClientResponseRoutes),
+    /// Invoices:
+    ResponseSignInvoice(SignedInvoice<B>),
+    InvoiceCommitted(Commit),
+    /// Verification:
+    ResponseVerifyReceipt(ResponseVerifyReceipt),
+    ResponseVerifyFriendToken(ResponseVerifyFriendToken),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -163,6 +281,67 @@ pub enum AppRequest<B = NetAddress> {
     /// Manage index servers:
     AddIndexServer(NamedIndexServerAddress<B>),
     RemoveIndexServer(PublicKey),
+    /// Manage exchange rates between currencies:
+    SetExchangeRate(ExchangeRate),
+    RemoveExchangeRate(CurrencyPair),
+    /// Dynamic forwarding fees:
+    SetFriendCurrencyFeePolicy(SetFriendCurrencyFeePolicy),
+    /// Automatic rebalancing:
+    SetFriendCurrencyRebalancePolicy(SetFriendCurrencyRebalancePolicy),
+    /// Cooperative close of a friend relationship:
+    SettleFriend(PublicKey),
+    /// Sign an invoice using the node's identity:
+    SignInvoice(UnsignedInvoice<B>),
+    /// Deliver a commit to the seller along the route of the transaction:
+    DeliverCommit(DeliverCommit),
+    /// Verify a receipt or a friend's token, without changing any state:
+    VerifyReceipt(VerifyReceipt),
+    VerifyFriendToken(VerifyFriendToken),
+}
+
+impl<B> AppRequest<B> {
+    /// The group of configuration operations this request belongs to.
+    /// Returns None if this is not a configuration request.
+    pub fn config_permission(&self) -> Option<ConfigPermission> {
+        match self {
+            AppRequest::AddRelay(_) | AppRequest::RemoveRelay(_) => Some(ConfigPermission::Relays),
+            AppRequest::AddIndexServer(_) | AppRequest::RemoveIndexServer(_) => {
+                Some(ConfigPermission::IndexServers)
+            }
+            AppRequest::AddFriend(_)
+            | AppRequest::SetFriendRelays(_)
+            | AppRequest::SetFriendName(_)
+            | AppRequest::RemoveFriend(_)
+            | AppRequest::EnableFriend(_)
+            | AppRequest::DisableFriend(_)
+            | AppRequest::ResetFriendChannel(_)
+            | AppRequest::SettleFriend(_) => Some(ConfigPermission::Friends),
+            AppRequest::OpenFriendCurrency(_)
+            | AppRequest::CloseFriendCurrency(_)
+            | AppRequest::SetFriendCurrencyMaxDebt(_)
+            | AppRequest::SetFriendCurrencyRate(_)
+            | AppRequest::RemoveFriendCurrency(_)
+            | AppRequest::SetFriendCurrencyFeePolicy(_)
+            | AppRequest::SetFriendCurrencyRebalancePolicy(_) => {
+                Some(ConfigPermission::FriendCurrencies)
+            }
+            AppRequest::SetExchangeRate(_) | AppRequest::RemoveExchangeRate(_) => {
+                Some(ConfigPermission::ExchangeRates)
+            }
+            AppRequest::CreatePayment(_)
+            | AppRequest::CreateTransaction(_)
+            | AppRequest::RequestClosePayment(_)
+            | AppRequest::AckClosePayment(_)
+            | AppRequest::AddInvoice(_)
+            | AppRequest::CancelInvoice(_)
+            | AppRequest::CommitInvoice(_)
+            | AppRequest::RequestRoutes(_)
+            | AppRequest::SignInvoice(_)
+            | AppRequest::DeliverCommit(_)
+            | AppRequest::VerifyReceipt(_)
+            | AppRequest::VerifyFriendToken(_) => None,
+        }
+    }
 }
 #[capnp_conv(crate::app_server_capnp::app_to_app_server)]
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -218,6 +397,154 @@ where
     }
 }
 
+/// Groups of configuration operations, used to restrict what an app with the `config`
+/// permission may change.
+#[capnp_conv(crate::app_server_capnp::config_permission)]
+#[derive(Arbitrary, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigPermission {
+    Relays,
+    IndexServers,
+    /// Adding, removing, enabling and disabling friends, resetting and settling channels
+    Friends,
+    /// Opening, closing and configuring currencies with friends
+    FriendCurrencies,
+    ExchangeRates,
+}
+
+/// A cap on the amount of credits an app may spend during a window of time.
+#[capnp_conv(crate::app_server_capnp::spending_limit)]
+#[derive(Arbitrary, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpendingLimit {
+    pub currency: Currency,
+    /// Maximum amount of credits (Including fees) that may be spent in one window
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub max_amount: u128,
+    /// Length of the window (Seconds)
+    pub window_secs: u64,
+}
+
+#[capnp_conv(crate::app_server_capnp::app_restrictions::opt_valid_until)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptValidUntil {
+    Empty,
+    ValidUntil(u64),
+}
+
+// TODO: Replace with a macro:
+impl From<Option<u64>> for OptValidUntil {
+    fn from(opt: Option<u64>) -> Self {
+        match opt {
+            Some(valid_until) => OptValidUntil::ValidUntil(valid_until),
+            None => OptValidUntil::Empty,
+        }
+    }
+}
+
+impl From<OptValidUntil> for Option<u64> {
+    fn from(opt: OptValidUntil) -> Self {
+        match opt {
+            OptValidUntil::ValidUntil(valid_until) => Some(valid_until),
+            OptValidUntil::Empty => None,
+        }
+    }
+}
+
+#[capnp_conv(crate::app_server_capnp::app_restrictions::allowed_destinations)]
+#[derive(Arbitrary, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AllowedDestinations {
+    Any,
+    #[serde(with = "ser_vec_b64")]
+    Only(Vec<PublicKey>),
+}
+
+impl Default for AllowedDestinations {
+    fn default() -> Self {
+        AllowedDestinations::Any
+    }
+}
+
+#[capnp_conv(crate::app_server_capnp::app_restrictions::allowed_config)]
+#[derive(Arbitrary, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AllowedConfig {
+    All,
+    Only(Vec<ConfigPermission>),
+}
+
+impl Default for AllowedConfig {
+    fn default() -> Self {
+        AllowedConfig::All
+    }
+}
+
+/// Current time, in seconds since the Unix epoch.
+/// Used for checking the expiry of time limited permissions.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        // System time is before the Unix epoch:
+        .unwrap_or(0)
+}
+
+/// Finer grained restrictions over the permissions of an app.
+/// The default value does not restrict anything.
+#[capnp_conv(crate::app_server_capnp::app_restrictions)]
+#[derive(Arbitrary, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AppRestrictions {
+    /// Expiry of the permissions (Seconds since the Unix epoch)
+    #[capnp_conv(with = OptValidUntil)]
+    #[serde(default)]
+    pub opt_valid_until: Option<u64>,
+    /// Caps on the credits the app may spend, per currency
+    #[serde(default)]
+    pub spending_limits: Vec<SpendingLimit>,
+    /// Destinations the app may pay
+    #[serde(default)]
+    pub allowed_destinations: AllowedDestinations,
+    /// Configuration operations the app may perform (Given the `config` permission)
+    #[serde(default)]
+    pub allowed_config: AllowedConfig,
+}
+
+impl AppRestrictions {
+    /// Have the permissions expired? `now` is measured in seconds since the Unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.opt_valid_until {
+            Some(valid_until) => now >= valid_until,
+            None => false,
+        }
+    }
+
+    pub fn is_destination_allowed(&self, dest_public_key: &PublicKey) -> bool {
+        match &self.allowed_destinations {
+            AllowedDestinations::Any => true,
+            AllowedDestinations::Only(public_keys) => public_keys.contains(dest_public_key),
+        }
+    }
+
+    pub fn is_config_allowed(&self, config_permission: &ConfigPermission) -> bool {
+        match &self.allowed_config {
+            AllowedConfig::All => true,
+            AllowedConfig::Only(config_permissions) => {
+                config_permissions.contains(config_permission)
+            }
+        }
+    }
+
+    /// Get the spending limit of a currency, if there is any.
+    pub fn spending_limit(&self, currency: &Currency) -> Option<&SpendingLimit> {
+        self.spending_limits
+            .iter()
+            .find(|spending_limit| &spending_limit.currency == currency)
+    }
+}
+
 #[capnp_conv(crate::app_server_capnp::app_permissions)]
 #[derive(Arbitrary, Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
@@ -230,4 +557,7 @@ pub struct AppPermissions {
     pub seller: bool,
     /// Can configure friends
     pub config: bool,
+    /// Finer grained restrictions over the permissions above
+    #[serde(default)]
+    pub restrictions: AppRestrictions,
 }
@@ -4,6 +4,10 @@ pub const PROTOCOL_VERSION: u32 = 0;
 /// Maximum amount of friend operations sent in one move token message.
 pub const MAX_OPERATIONS_IN_BATCH: usize = 16;
 
+/// Maximum amount of mutations an index client sends to an index server in one mutations update.
+/// Index servers should accept updates of at least this size.
+pub const MAX_INDEX_MUTATIONS_IN_UPDATE: usize = 0x40;
+
 /// Maximum length of route used to pass credit.
 pub const MAX_ROUTE_LEN: usize = 32;
 
@@ -39,3 +43,25 @@ pub const MAX_NET_ADDRESS_LENGTH: usize = 256;
 /// We limit this number because sending many relays in a single move token message
 /// might exceed frame length
 pub const MAX_NODE_RELAYS: usize = 16;
+
+/// Rebalancer: The amount of ticks to wait before attempting to rebalance the same
+/// mutual credit again.
+pub const REBALANCE_BACKOFF_TICKS: usize = 5 * 60 * (1000 / TICK_MS); // 5 minutes
+
+/// Rebalancer: The amount of ticks we wait for a response (Routes or a payment result) before
+/// giving up on a rebalance attempt.
+pub const REBALANCE_STAGE_TIMEOUT_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Should nodes announce to their friends the credit they are willing to extend to them?
+/// Announced credit limits are advisory, and are used by the remote side to avoid sending
+/// requests that are going to be rejected.
+pub const ANNOUNCE_CREDIT_LIMITS: bool = true;
+
+/// Index server: Maximum amount of edge changes sent in a single topology response.
+/// Larger changes are sent over a few consecutive responses, to keep every response below
+/// MAX_FRAME_LENGTH.
+pub const MAX_TOPOLOGY_CHANGES: usize = 0x1000;
+
+/// Index client: Amount of ticks between consecutive topology synchronizations with the index
+/// server, when topology synchronization is enabled.
+pub const TOPOLOGY_SYNC_TICKS: usize = 0x40;
@@ -63,6 +63,24 @@ pub struct IndexServerFile {
     pub address: NetAddress,
 }
 
+/// A helper structure for serialize and deserializing an index server administrator.
+/// An administrator is allowed to inspect the state of an index server.
+#[derive(Arbitrary, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexAdminFile {
+    #[serde(with = "ser_b64")]
+    pub public_key: PublicKey,
+}
+
+/// A helper structure for serialize and deserializing an index server client.
+/// Used for index servers that only serve an allowlist of clients.
+#[derive(Arbitrary, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexClientFile {
+    #[serde(with = "ser_b64")]
+    pub public_key: PublicKey,
+}
+
 /// A helper structure for serialize and deserializing NodeAddress.
 #[derive(Arbitrary, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
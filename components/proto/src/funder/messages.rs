@@ -1,4 +1,4 @@
-use std::cmp::Eq;
+use std::cmp::{min, Eq};
 use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::fmt;
@@ -24,6 +24,7 @@ use crate::consts::{MAX_CURRENCY_LEN, MAX_ROUTE_LEN};
 use crate::net::messages::NetAddress;
 use crate::report::messages::FunderReportMutations;
 
+use common::safe_arithmetic::SafeUnsignedArithmetic;
 use common::ser_utils::{ser_b64, ser_seq_str, ser_string, ser_vec_b64};
 
 use crate::wrapper::Wrapper;
@@ -151,6 +152,16 @@ pub struct Commit {
     pub signature: Signature,
 }
 
+/// A commit that is forwarded along the route of the transaction that completed the payment,
+/// until it reaches the seller.
+#[capnp_conv(crate::common_capnp::deliver_commit)]
+#[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub struct DeliverCommit {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub commit: Commit,
+}
+
 #[capnp_conv(crate::funder_capnp::collect_send_funds_op)]
 #[derive(Arbitrary, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct CollectSendFundsOp {
@@ -439,6 +450,65 @@ pub struct MoveTokenRequest<B = NetAddress> {
 pub enum FriendMessage<B = NetAddress> {
     MoveTokenRequest(MoveTokenRequest<B>),
     InconsistencyError(ResetTerms),
+    SettlementSignature(SettlementSignature),
+    CreditLimits(Vec<CreditLimit>),
+    DeliverCommit(DeliverCommit),
+}
+
+/// Credit the sender is willing to extend to the receiver in a certain currency.
+/// Announced between friends so that each side knows how much it may owe the other.
+/// Announcements are advisory: the remote side still enforces its own limit.
+#[capnp_conv(crate::funder_capnp::credit_limit)]
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CreditLimit {
+    pub currency: Currency,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub max_debt: u128,
+}
+
+/// A signature over the final balances of a settled channel,
+/// sent to the remote friend during a cooperative close.
+#[capnp_conv(crate::funder_capnp::settlement_signature)]
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SettlementSignature {
+    /// The move token counter of the drained channel the signature refers to.
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub move_token_counter: u128,
+    #[serde(with = "ser_b64")]
+    pub signature: Signature,
+}
+
+/// A final statement of the balances between two friends, signed by both sides.
+/// Can be used as a proof of the remaining debt after a relationship was closed.
+#[capnp_conv(crate::funder_capnp::settlement_statement)]
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct SettlementStatement {
+    #[serde(with = "ser_b64")]
+    pub local_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub remote_public_key: PublicKey,
+    /// Final balance for every currency, from the point of view of `local_public_key`.
+    pub balances: Vec<CurrencyBalance>,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub move_token_counter: u128,
+    #[serde(with = "ser_b64")]
+    pub local_signature: Signature,
+    #[serde(with = "ser_b64")]
+    pub remote_signature: Signature,
+    /*
+    # Signature{key=localPublicKey, remotePublicKey}(
+    #   sha512/256("SETTLEMENT") ||
+    #   lowPublicKey ||
+    #   highPublicKey ||
+    #   moveTokenCounter ||
+    #   len(balances) ||
+    #   for each currency (sorted): currency || balance
+    # )
+    # Balances are taken from the point of view of lowPublicKey.
+    */
 }
 
 /// A `Receipt` is received if a `RequestSendFunds` is successful.
@@ -678,6 +748,179 @@ impl Rate {
     }
 }
 
+/// Shape of the curve used by a `FeePolicy`.
+#[capnp_conv(crate::common_capnp::fee_curve)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FeeCurve {
+    /// Rate grows linearly with the friend's debt
+    Linear,
+    /// Rate grows slowly at first, and steeply as the friend's debt approaches the limit
+    Quadratic,
+}
+
+/// Dynamic forwarding fee policy.
+/// The effective rate moves from `min_rate` (The friend owes us nothing) to `max_rate` (The
+/// friend's debt reached `remote_max_debt`), according to `curve`.
+#[capnp_conv(crate::common_capnp::fee_policy)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FeePolicy {
+    pub min_rate: Rate,
+    pub max_rate: Rate,
+    pub curve: FeeCurve,
+}
+
+#[capnp_conv(crate::common_capnp::opt_fee_policy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptFeePolicy {
+    FeePolicy(FeePolicy),
+    Empty,
+}
+
+// TODO: Replace with a macro:
+impl From<Option<FeePolicy>> for OptFeePolicy {
+    fn from(opt: Option<FeePolicy>) -> Self {
+        match opt {
+            Some(fee_policy) => OptFeePolicy::FeePolicy(fee_policy),
+            None => OptFeePolicy::Empty,
+        }
+    }
+}
+
+impl From<OptFeePolicy> for Option<FeePolicy> {
+    fn from(opt: OptFeePolicy) -> Self {
+        match opt {
+            OptFeePolicy::FeePolicy(fee_policy) => Some(fee_policy),
+            OptFeePolicy::Empty => None,
+        }
+    }
+}
+
+/// Automatic rebalancing policy for a friend's currency.
+/// When the balance with the friend passes `threshold` (In either direction), the node attempts
+/// to bring it back to zero by paying itself along a loop, paying at most `max_fees` in fees.
+#[capnp_conv(crate::common_capnp::rebalance_policy)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RebalancePolicy {
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub threshold: u128,
+    #[capnp_conv(with = Wrapper<u128>)]
+    #[serde(with = "ser_string")]
+    pub max_fees: u128,
+}
+
+#[capnp_conv(crate::common_capnp::opt_rebalance_policy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptRebalancePolicy {
+    RebalancePolicy(RebalancePolicy),
+    Empty,
+}
+
+// TODO: Replace with a macro:
+impl From<Option<RebalancePolicy>> for OptRebalancePolicy {
+    fn from(opt: Option<RebalancePolicy>) -> Self {
+        match opt {
+            Some(rebalance_policy) => OptRebalancePolicy::RebalancePolicy(rebalance_policy),
+            None => OptRebalancePolicy::Empty,
+        }
+    }
+}
+
+impl From<OptRebalancePolicy> for Option<RebalancePolicy> {
+    fn from(opt: OptRebalancePolicy) -> Self {
+        match opt {
+            OptRebalancePolicy::RebalancePolicy(rebalance_policy) => Some(rebalance_policy),
+            OptRebalancePolicy::Empty => None,
+        }
+    }
+}
+
+/// Interpolate between `min` and `max`, according to the fraction `num / den`.
+/// Assumes that `num <= den`.
+fn interpolate(min: u32, max: u32, num: &BigUint, den: &BigUint) -> u32 {
+    if max >= min {
+        let diff = (BigUint::from(max - min) * num) / den;
+        min + diff.to_u32().unwrap()
+    } else {
+        let diff = (BigUint::from(min - max) * num) / den;
+        min - diff.to_u32().unwrap()
+    }
+}
+
+impl FeePolicy {
+    /// Calculate the effective rate for a mutual credit with the given balance.
+    /// `balance` and `remote_pending_debt` are taken from our side of the mutual credit.
+    pub fn calc_rate(
+        &self,
+        remote_max_debt: u128,
+        balance: i128,
+        remote_pending_debt: u128,
+    ) -> Rate {
+        if remote_max_debt == 0 {
+            // The friend can not owe us anything, the channel is considered depleted:
+            return self.max_rate.clone();
+        }
+
+        // The amount of debt the friend currently has (or may soon have) against us:
+        let debt = min(
+            remote_pending_debt.saturating_add_signed(balance),
+            remote_max_debt,
+        );
+
+        let (num, den) = match self.curve {
+            FeeCurve::Linear => (BigUint::from(debt), BigUint::from(remote_max_debt)),
+            FeeCurve::Quadratic => (
+                BigUint::from(debt) * BigUint::from(debt),
+                BigUint::from(remote_max_debt) * BigUint::from(remote_max_debt),
+            ),
+        };
+
+        Rate {
+            mul: interpolate(self.min_rate.mul, self.max_rate.mul, &num, &den),
+            add: interpolate(self.min_rate.add, self.max_rate.add, &num, &den),
+        }
+    }
+}
+
+/// A pair of currencies, denoting the direction of an exchange rate.
+#[capnp_conv(crate::common_capnp::currency_pair)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyPair {
+    pub src_currency: Currency,
+    pub dest_currency: Currency,
+}
+
+/// Exchange rate between two currencies, valid during a window of time.
+/// `x` credits of `src_currency` are worth `(x * mul) / 2^32` credits of `dest_currency`.
+#[capnp_conv(crate::common_capnp::exchange_rate)]
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeRate {
+    pub currency_pair: CurrencyPair,
+    pub mul: u64,
+    /// Beginning of validity window (Seconds since the Unix epoch)
+    pub valid_from: u64,
+    /// End of validity window (Seconds since the Unix epoch)
+    pub valid_until: u64,
+}
+
+impl ExchangeRate {
+    /// Convert `amount` credits of `src_currency` to credits of `dest_currency`.
+    /// Returns None if the result does not fit into a u128.
+    pub fn convert(&self, amount: u128) -> Option<u128> {
+        let res = (BigUint::from(amount) * BigUint::from(self.mul)) >> 32;
+        res.to_u128()
+    }
+
+    /// Is this exchange rate valid at time `now`? (Seconds since the Unix epoch)
+    pub fn is_valid_at(&self, now: u64) -> bool {
+        self.valid_from <= now && now < self.valid_until
+    }
+}
+
 #[capnp_conv(crate::app_server_capnp::add_friend)]
 #[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddFriend<B = NetAddress> {
@@ -692,6 +935,11 @@ pub struct RemoveFriend {
     pub friend_public_key: PublicKey,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettleFriend {
+    pub friend_public_key: PublicKey,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SetFriendCurrencyRequestsStatus {
     pub friend_public_key: PublicKey,
@@ -743,6 +991,26 @@ pub struct SetFriendCurrencyRate {
     pub rate: Rate,
 }
 
+#[capnp_conv(crate::app_server_capnp::set_friend_currency_fee_policy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetFriendCurrencyFeePolicy {
+    pub friend_public_key: PublicKey,
+    pub currency: Currency,
+    /// A dynamic fee policy. If empty, the static rate is used.
+    #[capnp_conv(with = OptFeePolicy)]
+    pub opt_fee_policy: Option<FeePolicy>,
+}
+
+#[capnp_conv(crate::app_server_capnp::set_friend_currency_rebalance_policy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetFriendCurrencyRebalancePolicy {
+    pub friend_public_key: PublicKey,
+    pub currency: Currency,
+    /// If empty, automatic rebalancing is disabled.
+    #[capnp_conv(with = OptRebalancePolicy)]
+    pub opt_rebalance_policy: Option<RebalancePolicy>,
+}
+
 #[capnp_conv(crate::app_server_capnp::remove_friend_currency)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RemoveFriendCurrency {
@@ -827,14 +1095,20 @@ pub enum FunderControl<B> {
     SetFriendRelays(SetFriendRelays<B>),
     SetFriendName(SetFriendName),
     SetFriendCurrencyRate(SetFriendCurrencyRate),
+    SetFriendCurrencyFeePolicy(SetFriendCurrencyFeePolicy),
+    SetFriendCurrencyRebalancePolicy(SetFriendCurrencyRebalancePolicy),
     SetFriendCurrencyRequestsStatus(SetFriendCurrencyRequestsStatus),
     RemoveFriendCurrency(RemoveFriendCurrency),
     ResetFriendChannel(ResetFriendChannel),
+    SettleFriend(SettleFriend),
+    SetExchangeRate(ExchangeRate),
+    RemoveExchangeRate(CurrencyPair),
     // Buyer API:
     CreatePayment(CreatePayment),
     CreateTransaction(CreateTransaction),
     RequestClosePayment(PaymentId),
     AckClosePayment(AckClosePayment),
+    DeliverCommit(DeliverCommit),
     // Seller API:
     AddInvoice(AddInvoice),
     CancelInvoice(InvoiceId),
@@ -899,9 +1173,14 @@ pub struct ResponseClosePayment {
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum FunderOutgoingControl<B: Clone> {
-    TransactionResult(TransactionResult),
+    /// Result of a transaction originated by us.
+    /// The second field is true if the transaction failed on its first hop, before reaching the
+    /// first friend along the route.
+    TransactionResult(TransactionResult, bool),
     ResponseClosePayment(ResponseClosePayment),
     ReportMutations(FunderReportMutations<B>),
+    /// An open invoice was committed using a commit delivered along the route.
+    InvoiceCommitted(Commit),
 }
 
 impl Currency {
@@ -999,6 +1278,51 @@ mod tests {
         assert_eq!(is_route_part_valid(&[1, 2, 3, 2, 4]), false); // should have no repetitions in a partial route
     }
 
+    #[test]
+    fn test_fee_policy_calc_rate() {
+        let fee_policy = FeePolicy {
+            min_rate: Rate { mul: 0, add: 10 },
+            max_rate: Rate { mul: 100, add: 30 },
+            curve: FeeCurve::Linear,
+        };
+
+        // Friend owes us nothing:
+        assert_eq!(fee_policy.calc_rate(100, 0, 0), Rate { mul: 0, add: 10 });
+        assert_eq!(fee_policy.calc_rate(100, -50, 20), Rate { mul: 0, add: 10 });
+        // Half depleted:
+        assert_eq!(fee_policy.calc_rate(100, 30, 20), Rate { mul: 50, add: 20 });
+        // Fully depleted:
+        assert_eq!(
+            fee_policy.calc_rate(100, 100, 0),
+            Rate { mul: 100, add: 30 }
+        );
+        assert_eq!(
+            fee_policy.calc_rate(100, 90, 50),
+            Rate { mul: 100, add: 30 }
+        );
+        // No credit given to the friend:
+        assert_eq!(fee_policy.calc_rate(0, 0, 0), Rate { mul: 100, add: 30 });
+
+        let fee_policy = FeePolicy {
+            curve: FeeCurve::Quadratic,
+            ..fee_policy
+        };
+        assert_eq!(fee_policy.calc_rate(100, 0, 0), Rate { mul: 0, add: 10 });
+        assert_eq!(fee_policy.calc_rate(100, 50, 0), Rate { mul: 25, add: 15 });
+        assert_eq!(
+            fee_policy.calc_rate(100, 100, 0),
+            Rate { mul: 100, add: 30 }
+        );
+
+        // Decreasing rates are also allowed:
+        let fee_policy = FeePolicy {
+            min_rate: Rate { mul: 100, add: 30 },
+            max_rate: Rate { mul: 0, add: 10 },
+            curve: FeeCurve::Linear,
+        };
+        assert_eq!(fee_policy.calc_rate(100, 50, 0), Rate { mul: 50, add: 20 });
+    }
+
     use im::hashset::HashSet as ImHashSet;
 
     #[derive(Arbitrary, Clone)]
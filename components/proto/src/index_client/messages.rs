@@ -3,9 +3,10 @@ use std::collections::HashMap;
 use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 
 use crate::crypto::{PublicKey, Uid};
-use crate::funder::messages::{Currency, Rate};
+use crate::funder::messages::{Currency, CurrencyPair, ExchangeRate, Rate};
 pub use crate::index_server::messages::{
-    IndexMutation, RemoveFriendCurrency, RequestRoutes, UpdateFriendCurrency,
+    HopOutcome, IndexMutation, RemoveFriendCurrency, RequestRoutes, RouteOutcome,
+    UpdateFriendCurrency,
 };
 use crate::index_server::messages::{MultiRoute, NamedIndexServerAddress};
 use crate::net::messages::NetAddress;
@@ -21,6 +22,7 @@ pub struct FriendInfo {
 #[derive(Debug, Clone)]
 pub struct IndexClientState {
     pub friends: HashMap<(PublicKey, Currency), FriendInfo>,
+    pub exchange_rates: HashMap<CurrencyPair, ExchangeRate>,
 }
 
 // ---------------------------------------------------
@@ -53,6 +55,18 @@ impl From<OptConnectedServer> for Option<PublicKey> {
     }
 }
 
+/// A topology snapshot kept by the index client
+#[capnp_conv(crate::report_capnp::topology_snapshot_report)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopologySnapshotReport {
+    pub currency: Currency,
+    /// The index server that signed the snapshot
+    pub server_public_key: PublicKey,
+    pub num_edges: u64,
+    /// Amount of ticks passed since the snapshot was last synchronized with the index server.
+    pub ticks_since_sync: u64,
+}
+
 #[capnp_conv(crate::report_capnp::index_client_report)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// ISA stands for Index Server Address
@@ -62,6 +76,8 @@ pub struct IndexClientReport<ISA = NetAddress> {
     /// The server we are currently connected to (None if not connected).
     #[capnp_conv(with = OptConnectedServer)]
     pub opt_connected_server: Option<PublicKey>,
+    /// Topology snapshots used to find routes locally when no index server is connected.
+    pub topology_snapshots: Vec<TopologySnapshotReport>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -104,6 +120,8 @@ pub enum IndexClientReportMutation<ISA = NetAddress> {
     RemoveIndexServer(PublicKey),
     #[capnp_conv(with = SetConnectedServer)]
     SetConnectedServer(Option<PublicKey>),
+    SetTopologySnapshot(TopologySnapshotReport),
+    RemoveTopologySnapshot(Currency),
 }
 
 #[capnp_conv(crate::app_server_capnp::response_routes_result)]
@@ -143,6 +161,9 @@ pub enum IndexClientRequest<ISA> {
 pub enum AppServerToIndexClient<ISA> {
     AppRequest((Uid, IndexClientRequest<ISA>)), // (app_request_id, app_request)
     ApplyMutations(Vec<IndexMutation>),
+    /// Outcome of a route used for sending credits.
+    /// Forwarded to the index server only if route outcomes reporting is enabled.
+    ReportRouteOutcome(RouteOutcome),
 }
 
 // TODO: Move this code somewhere else?
@@ -169,6 +190,16 @@ where
             IndexClientReportMutation::SetConnectedServer(opt_public_key) => {
                 self.opt_connected_server = opt_public_key.clone();
             }
+            IndexClientReportMutation::SetTopologySnapshot(topology_snapshot) => {
+                self.topology_snapshots.retain(|cur_topology_snapshot| {
+                    cur_topology_snapshot.currency != topology_snapshot.currency
+                });
+                self.topology_snapshots.push(topology_snapshot.clone());
+            }
+            IndexClientReportMutation::RemoveTopologySnapshot(currency) => {
+                self.topology_snapshots
+                    .retain(|topology_snapshot| &topology_snapshot.currency != currency);
+            }
         }
     }
 }
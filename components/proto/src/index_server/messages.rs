@@ -5,7 +5,7 @@ use capnp_conv::{capnp_conv, CapnpConvError, ReadCapnp, WriteCapnp};
 use common::ser_utils::{ser_b64, ser_string};
 
 use crate::crypto::{HashResult, PublicKey, RandValue, Signature, Uid};
-use crate::funder::messages::{Currency, FriendsRoute, Rate};
+use crate::funder::messages::{Currency, CurrencyPair, ExchangeRate, FriendsRoute, Rate};
 use crate::net::messages::NetAddress;
 use crate::wrapper::Wrapper;
 
@@ -90,6 +90,76 @@ pub struct ResponseRoutes {
     pub multi_routes: Vec<MultiRoute>,
 }
 
+/// A directed edge in the capacity graph of a currency
+#[capnp_conv(crate::index_capnp::topology_edge)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopologyEdge {
+    pub from_public_key: PublicKey,
+    pub to_public_key: PublicKey,
+    /// Capacity of sending credits from `from_public_key` to `to_public_key`
+    #[capnp_conv(with = Wrapper<u128>)]
+    pub recv_capacity: u128,
+    /// Rate `from_public_key` charges for forwarding credits to `to_public_key`
+    pub rate: Rate,
+}
+
+/// IndexClient -> IndexServer
+#[capnp_conv(crate::index_capnp::request_topology)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestTopology {
+    pub request_id: Uid,
+    pub currency: Currency,
+    /// If true, the server may send only the changes since the last topology it sent for this
+    /// currency on this connection.
+    /// If false, the server sends the full topology.
+    pub is_incremental: bool,
+}
+
+/// IndexServer -> IndexClient
+#[capnp_conv(crate::index_capnp::response_topology)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTopology {
+    pub request_id: Uid,
+    pub currency: Currency,
+    /// If true, `updated_edges` contains the full topology.
+    /// Otherwise, `updated_edges` and `removed_edges` should be applied to the last topology
+    /// received for this currency on this connection.
+    pub is_full: bool,
+    pub updated_edges: Vec<TopologyEdge>,
+    pub removed_edges: Vec<Edge>,
+    /// Hash over all the edges of the topology, after the changes are applied.
+    pub topology_hash: HashResult,
+    /// signature(sha_512_256("TOPOLOGY") ||
+    ///           currency ||
+    ///           topologyHash)
+    pub signature: Signature,
+}
+
+/// Outcome of forwarding credits along a single hop of a route.
+#[capnp_conv(crate::index_capnp::hop_outcome)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HopOutcome {
+    /// Credits were forwarded along this hop
+    Success,
+    /// Credits could not be forwarded along this hop
+    Failure,
+    /// The reporting node does not know if this hop failed
+    Unknown,
+}
+
+/// IndexClient -> IndexServer
+/// Feedback about a route previously used for sending credits.
+#[capnp_conv(crate::index_capnp::route_outcome)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteOutcome {
+    pub currency: Currency,
+    /// A route used for sending credits, starting at the reporting node
+    pub route: FriendsRoute,
+    /// Outcome for every hop (pair of consecutive nodes) along the route.
+    /// The amount of credits sent is not reported.
+    pub hop_outcomes: Vec<HopOutcome>,
+}
+
 // TODO: Possibly think of a better name for this structure?
 #[capnp_conv(crate::index_capnp::update_friend_currency)]
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -127,6 +197,8 @@ pub struct RemoveFriendCurrency {
 pub enum IndexMutation {
     UpdateFriendCurrency(UpdateFriendCurrency),
     RemoveFriendCurrency(RemoveFriendCurrency),
+    SetExchangeRate(ExchangeRate),
+    RemoveExchangeRate(CurrencyPair),
 }
 
 #[capnp_conv(crate::index_capnp::mutations_update)]
@@ -176,11 +248,45 @@ pub struct ForwardMutationsUpdate {
     pub time_proof_chain: Vec<TimeProofLink>,
 }
 
+/// The reason a client message was not handled by the index server
+#[capnp_conv(crate::index_capnp::reject_reason)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The client sent too many messages recently
+    RateLimited,
+    /// A MutationsUpdate contained too many mutations
+    TooManyMutations,
+    /// The server has too many pending route searches
+    ServerBusy,
+}
+
+/// IndexServer -> IndexClient
+/// A RequestRoutes or RequestTopology message was not handled by the server
+#[capnp_conv(crate::index_capnp::request_rejected)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestRejected {
+    pub request_id: Uid,
+    pub reason: RejectReason,
+}
+
+/// IndexServer -> IndexClient
+/// A MutationsUpdate message was not applied by the server
+#[capnp_conv(crate::index_capnp::mutations_rejected)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MutationsRejected {
+    pub session_id: Uid,
+    pub counter: u64,
+    pub reason: RejectReason,
+}
+
 #[capnp_conv(crate::index_capnp::index_server_to_client)]
 #[derive(Debug)]
 pub enum IndexServerToClient {
     TimeHash(HashResult),
     ResponseRoutes(ResponseRoutes),
+    ResponseTopology(ResponseTopology),
+    RequestRejected(RequestRejected),
+    MutationsRejected(MutationsRejected),
 }
 
 #[capnp_conv(crate::index_capnp::index_client_to_server)]
@@ -188,6 +294,8 @@ pub enum IndexServerToClient {
 pub enum IndexClientToServer {
     MutationsUpdate(MutationsUpdate),
     RequestRoutes(RequestRoutes),
+    RequestTopology(RequestTopology),
+    ReportRouteOutcome(RouteOutcome),
 }
 
 #[capnp_conv(crate::index_capnp::index_server_to_server)]
@@ -197,6 +305,85 @@ pub enum IndexServerToServer {
     ForwardMutationsUpdate(ForwardMutationsUpdate),
 }
 
+#[capnp_conv(crate::index_capnp::server_conn_state)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerConnState {
+    Connected,
+    /// We are trying to connect to the server
+    Initiating,
+    /// We are waiting for the server to connect to us
+    Listening,
+}
+
+/// Federation status of a trusted index server, as seen by the local index server
+#[capnp_conv(crate::index_capnp::server_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerStatus {
+    pub public_key: PublicKey,
+    pub conn_state: ServerConnState,
+    /// Amount of local ticks since the last time hash received from the server.
+    /// Only meaningful if the server is connected.
+    pub ticks_since_time_hash: u64,
+    /// The server is connected, but did not send a time hash for too long
+    pub is_lagging: bool,
+}
+
+impl ServerStatus {
+    /// Can we currently exchange mutations with this server?
+    pub fn is_reachable(&self) -> bool {
+        self.conn_state == ServerConnState::Connected && !self.is_lagging
+    }
+}
+
+/// Statistics of the capacity graph of one currency
+#[capnp_conv(crate::index_capnp::currency_graph_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrencyGraphStatus {
+    pub currency: Currency,
+    /// Statistics are only available for graphs loaded in memory
+    pub is_loaded: bool,
+    pub num_nodes: u64,
+    pub num_edges: u64,
+}
+
+/// IndexServer -> IndexAdmin
+#[capnp_conv(crate::index_capnp::index_server_status)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexServerStatus {
+    pub request_id: Uid,
+    pub servers: Vec<ServerStatus>,
+    pub num_clients: u64,
+    /// Amount of nodes whose updates are currently tracked
+    pub num_nodes: u64,
+    pub currency_graphs: Vec<CurrencyGraphStatus>,
+    /// Amount of currently valid exchange rates announced by nodes
+    pub num_exchange_rates: u64,
+}
+
+impl IndexServerStatus {
+    /// Trusted servers we can not currently exchange mutations with.
+    /// If this list is not empty, the federation might be partitioned.
+    pub fn unreachable_servers(&self) -> Vec<PublicKey> {
+        self.servers
+            .iter()
+            .filter(|server_status| !server_status.is_reachable())
+            .map(|server_status| server_status.public_key.clone())
+            .collect()
+    }
+}
+
+#[capnp_conv(crate::index_capnp::index_admin_to_server)]
+#[derive(Debug)]
+pub enum IndexAdminToServer {
+    RequestStatus(Uid),
+}
+
+#[capnp_conv(crate::index_capnp::index_server_to_admin)]
+#[derive(Debug)]
+pub enum IndexServerToAdmin {
+    ResponseStatus(IndexServerStatus),
+}
+
 // ----------------------------------------------
 // ----------------------------------------------
 
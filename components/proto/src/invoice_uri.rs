@@ -0,0 +1,57 @@
+use base64::{self, URL_SAFE_NO_PAD};
+
+use crate::app_server::messages::SignedInvoice;
+use crate::net::messages::NetAddress;
+use crate::proto_ser::{ProtoDeserialize, ProtoSerialize};
+
+/// Prefix of all invoice URIs.
+pub const INVOICE_URI_SCHEME: &str = "offset:";
+
+#[derive(Debug)]
+pub enum InvoiceUriError {
+    InvalidScheme,
+    Base64DecodeError,
+    DeserializeError,
+}
+
+/// Encode a signed invoice as a URI, suitable for links and QR codes.
+/// The payload is the capnp serialization of the invoice, encoded using url safe base64.
+pub fn invoice_to_uri(signed_invoice: &SignedInvoice<NetAddress>) -> String {
+    let payload = base64::encode_config(&signed_invoice.proto_serialize(), URL_SAFE_NO_PAD);
+    format!("{}{}", INVOICE_URI_SCHEME, payload)
+}
+
+/// Decode a signed invoice from a URI.
+/// Note that this function does not verify the invoice's signature.
+pub fn uri_to_invoice(uri: &str) -> Result<SignedInvoice<NetAddress>, InvoiceUriError> {
+    let uri = uri.trim();
+    if !uri.starts_with(INVOICE_URI_SCHEME) {
+        return Err(InvoiceUriError::InvalidScheme);
+    }
+    let payload = base64::decode_config(&uri[INVOICE_URI_SCHEME.len()..], URL_SAFE_NO_PAD)
+        .map_err(|_| InvoiceUriError::Base64DecodeError)?;
+    SignedInvoice::proto_deserialize(&payload).map_err(|_| InvoiceUriError::DeserializeError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    #[test]
+    fn qc_invoice_uri() {
+        fn invoice_uri_round_trip(signed_invoice: SignedInvoice<NetAddress>) -> bool {
+            let uri = invoice_to_uri(&signed_invoice);
+            uri.starts_with(INVOICE_URI_SCHEME) && uri_to_invoice(&uri).unwrap() == signed_invoice
+        }
+        quickcheck(invoice_uri_round_trip as fn(SignedInvoice<NetAddress>) -> bool);
+    }
+
+    #[test]
+    fn test_uri_to_invoice_invalid() {
+        assert!(uri_to_invoice("bitcoin:abcd").is_err());
+        assert!(uri_to_invoice("offset:!!!").is_err());
+        assert!(uri_to_invoice("offset:abcd").is_err());
+    }
+}
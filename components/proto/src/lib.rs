@@ -22,6 +22,7 @@ pub mod file;
 pub mod funder;
 pub mod index_client;
 pub mod index_server;
+pub mod invoice_uri;
 pub mod keepalive;
 pub mod net;
 pub mod proto_ser;
@@ -6,7 +6,7 @@ use common::safe_arithmetic::{SafeSignedArithmetic, SafeUnsignedArithmetic};
 
 use crate::crypto::PublicKey;
 
-use crate::funder::messages::{Currency, Rate};
+use crate::funder::messages::{Currency, CurrencyPair, ExchangeRate, Rate};
 use crate::index_client::messages::{FriendInfo, IndexClientState};
 use crate::index_server::messages::{IndexMutation, RemoveFriendCurrency, UpdateFriendCurrency};
 
@@ -99,7 +99,7 @@ where
                         .currency_configs
                         .iter()
                         .find(|currency_config| currency_config.currency == currency)
-                        .map(|currency_config| currency_config.rate.clone())
+                        .map(|currency_config| currency_config.effective_rate.clone())
                         .unwrap_or_else(Rate::new);
 
                     let opt_friend_info = if is_open {
@@ -119,12 +119,24 @@ where
         .map(|(tuple, opt_friend_info)| (tuple, opt_friend_info.unwrap()))
 }
 
+fn calc_exchange_rates<B>(funder_report: &FunderReport<B>) -> HashMap<CurrencyPair, ExchangeRate>
+where
+    B: Clone,
+{
+    funder_report
+        .exchange_rates
+        .iter()
+        .map(|exchange_rate| (exchange_rate.currency_pair.clone(), exchange_rate.clone()))
+        .collect()
+}
+
 pub fn funder_report_to_index_client_state<B>(funder_report: &FunderReport<B>) -> IndexClientState
 where
     B: Clone,
 {
     IndexClientState {
         friends: calc_friends_info(funder_report).collect(),
+        exchange_rates: calc_exchange_rates(funder_report),
     }
 }
 
@@ -168,6 +180,25 @@ where
             rate: friend_info.rate,
         }));
     }
+
+    let old_exchange_rates = calc_exchange_rates(old_funder_report);
+    let new_exchange_rates = calc_exchange_rates(new_funder_report);
+
+    // Push exchange rates remove mutations:
+    for currency_pair in old_exchange_rates.keys() {
+        if !new_exchange_rates.contains_key(currency_pair) {
+            res_mutations.push(IndexMutation::RemoveExchangeRate(currency_pair.clone()));
+        }
+    }
+
+    // Push exchange rates set mutations:
+    for (currency_pair, exchange_rate) in new_exchange_rates {
+        if old_exchange_rates.get(&currency_pair) == Some(&exchange_rate) {
+            continue;
+        }
+        res_mutations.push(IndexMutation::SetExchangeRate(exchange_rate));
+    }
+
     res_mutations
 }
 
@@ -214,18 +245,27 @@ mod tests {
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 0, add: 0 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency2.clone(),
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 200,
                         is_open: false,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 0, add: 0 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency3.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 1, add: 10 },
+                        opt_rebalance_policy: None,
                     },
                 ],
                 remote_relays: vec![],
@@ -260,6 +300,8 @@ mod tests {
                     ],
                 }),
                 status: FriendStatusReport::Enabled,
+                settlement: SettlementReport::NotStarted,
+                remote_credit_limits: Vec::new(),
             },
         );
 
@@ -272,6 +314,9 @@ mod tests {
                     rate: Rate { mul: 2, add: 2 },
                     remote_max_debt: 200,
                     is_open: true,
+                    opt_fee_policy: None,
+                    effective_rate: Rate { mul: 2, add: 2 },
+                    opt_rebalance_policy: None,
                 }],
                 remote_relays: vec![],
                 opt_last_incoming_move_token: None,
@@ -287,12 +332,15 @@ mod tests {
                     }],
                 }),
                 status: FriendStatusReport::Enabled,
+                settlement: SettlementReport::NotStarted,
+                remote_credit_limits: Vec::new(),
             },
         );
         let funder_report = FunderReport {
             local_public_key: pk1.clone(),
             relays: Vec::new(),
             friends,
+            exchange_rates: Vec::new(),
         };
         let friends_info: HashMap<(PublicKey, Currency), FriendInfo> =
             calc_friends_info(&funder_report).collect();
@@ -335,24 +383,36 @@ mod tests {
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 0, add: 0 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency2.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 1, add: 10 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency3.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: false,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 1, add: 10 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency4.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 40,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 1, add: 10 },
+                        opt_rebalance_policy: None,
                     },
                 ],
                 remote_relays: vec![],
@@ -395,6 +455,8 @@ mod tests {
                     ],
                 }),
                 status: FriendStatusReport::Enabled,
+                settlement: SettlementReport::NotStarted,
+                remote_credit_limits: Vec::new(),
             },
         );
 
@@ -402,6 +464,7 @@ mod tests {
             local_public_key: pk1.clone(),
             relays: Vec::new(),
             friends,
+            exchange_rates: Vec::new(),
         };
 
         let mut friends = HashMap::new();
@@ -415,18 +478,27 @@ mod tests {
                         rate: Rate { mul: 0, add: 0 },
                         remote_max_debt: 300,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 0, add: 0 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency3.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 200,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 1, add: 10 },
+                        opt_rebalance_policy: None,
                     },
                     CurrencyConfigReport {
                         currency: currency4.clone(),
                         rate: Rate { mul: 1, add: 10 },
                         remote_max_debt: 40,
                         is_open: true,
+                        opt_fee_policy: None,
+                        effective_rate: Rate { mul: 1, add: 10 },
+                        opt_rebalance_policy: None,
                     },
                 ],
                 remote_relays: vec![],
@@ -461,12 +533,15 @@ mod tests {
                     ],
                 }),
                 status: FriendStatusReport::Enabled,
+                settlement: SettlementReport::NotStarted,
+                remote_credit_limits: Vec::new(),
             },
         );
         let new_funder_report = FunderReport {
             local_public_key: pk1.clone(),
             relays: Vec::new(),
             friends,
+            exchange_rates: Vec::new(),
         };
 
         let index_mutations = calc_index_mutations(&old_funder_report, &new_funder_report);
@@ -488,7 +563,100 @@ mod tests {
                         assert_eq!(update_friend_currency.rate, Rate::new());
                     }
                 }
+                IndexMutation::SetExchangeRate(_) | IndexMutation::RemoveExchangeRate(_) => {
+                    unreachable!()
+                }
             }
         }
     }
+
+    #[test]
+    fn test_calc_index_mutations_exchange_rates() {
+        let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
+        let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
+        let currency3 = Currency::try_from("FST3".to_owned()).unwrap();
+
+        let pk1 = PublicKey::from(&[1; PublicKey::len()]);
+
+        let pair12 = CurrencyPair {
+            src_currency: currency1.clone(),
+            dest_currency: currency2.clone(),
+        };
+        let pair13 = CurrencyPair {
+            src_currency: currency1.clone(),
+            dest_currency: currency3.clone(),
+        };
+        let pair23 = CurrencyPair {
+            src_currency: currency2.clone(),
+            dest_currency: currency3.clone(),
+        };
+
+        let old_funder_report = FunderReport::<u32> {
+            local_public_key: pk1.clone(),
+            relays: Vec::new(),
+            friends: HashMap::new(),
+            exchange_rates: vec![
+                ExchangeRate {
+                    currency_pair: pair12.clone(),
+                    mul: 1 << 32,
+                    valid_from: 100,
+                    valid_until: 200,
+                },
+                ExchangeRate {
+                    currency_pair: pair13.clone(),
+                    mul: 1 << 31,
+                    valid_from: 100,
+                    valid_until: 200,
+                },
+            ],
+        };
+
+        let new_funder_report = FunderReport::<u32> {
+            local_public_key: pk1.clone(),
+            relays: Vec::new(),
+            friends: HashMap::new(),
+            exchange_rates: vec![
+                // Unchanged:
+                ExchangeRate {
+                    currency_pair: pair12.clone(),
+                    mul: 1 << 32,
+                    valid_from: 100,
+                    valid_until: 200,
+                },
+                // Validity window was changed:
+                ExchangeRate {
+                    currency_pair: pair13.clone(),
+                    mul: 1 << 31,
+                    valid_from: 150,
+                    valid_until: 250,
+                },
+                // New:
+                ExchangeRate {
+                    currency_pair: pair23.clone(),
+                    mul: 3 << 32,
+                    valid_from: 100,
+                    valid_until: 200,
+                },
+            ],
+        };
+
+        let index_mutations = calc_index_mutations(&old_funder_report, &new_funder_report);
+        assert_eq!(index_mutations.len(), 2);
+        for index_mutation in &index_mutations {
+            match index_mutation {
+                IndexMutation::SetExchangeRate(exchange_rate) => {
+                    assert!(
+                        exchange_rate.currency_pair == pair13
+                            || exchange_rate.currency_pair == pair23
+                    );
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // Remove all exchange rates:
+        let index_mutations = calc_index_mutations(&new_funder_report, &old_funder_report);
+        assert_eq!(index_mutations.len(), 2);
+        assert!(index_mutations.contains(&IndexMutation::RemoveExchangeRate(pair23.clone())));
+    }
 }
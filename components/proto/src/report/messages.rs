@@ -14,7 +14,9 @@ use crate::crypto::{HashResult, PublicKey, RandValue, Signature, Uid};
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
 use crate::funder::messages::{
-    Currency, CurrencyBalance, FriendStatus, Rate, RequestsStatus, TokenInfo,
+    CreditLimit, Currency, CurrencyBalance, CurrencyPair, ExchangeRate, FeePolicy, FriendStatus,
+    OptFeePolicy, OptRebalancePolicy, Rate, RebalancePolicy, RequestsStatus, SettlementStatement,
+    TokenInfo,
 };
 use crate::net::messages::NetAddress;
 use crate::wrapper::Wrapper;
@@ -182,6 +184,26 @@ pub struct CurrencyConfigReport {
     pub remote_max_debt: u128,
     /// Can requests be sent through this mutual credit?
     pub is_open: bool,
+    /// Dynamic forwarding fee policy (If configured)
+    #[capnp_conv(with = OptFeePolicy)]
+    pub opt_fee_policy: Option<FeePolicy>,
+    /// Rate currently charged for forwarding transactions.
+    /// Equals `rate` if no fee policy is configured.
+    pub effective_rate: Rate,
+    /// Automatic rebalancing policy (If configured)
+    #[capnp_conv(with = OptRebalancePolicy)]
+    pub opt_rebalance_policy: Option<RebalancePolicy>,
+}
+
+/// Status of a cooperative close of the relationship with a friend.
+#[allow(clippy::large_enum_variant)]
+#[capnp_conv(crate::report_capnp::settlement_report)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SettlementReport {
+    NotStarted,
+    /// Waiting for pending transactions to drain and for both signatures.
+    InProgress,
+    Complete(SettlementStatement),
 }
 
 #[capnp_conv(crate::report_capnp::friend_report)]
@@ -199,6 +221,10 @@ pub struct FriendReport<B = NetAddress> {
     pub liveness: FriendLivenessReport, // is the friend online/offline?
     pub channel_status: ChannelStatusReport,
     pub status: FriendStatusReport,
+    pub settlement: SettlementReport,
+    /// Credit limits announced by the remote friend: The amount of credit it is willing to extend
+    /// to us, per currency.
+    pub remote_credit_limits: Vec<CreditLimit>,
 }
 
 #[capnp_conv(crate::report_capnp::pk_friend_report)]
@@ -253,6 +279,8 @@ pub struct FunderReport<B = NetAddress> {
     pub relays: Vec<NamedRelayAddress<B>>,
     #[capnp_conv(with = PkFriendReportList)]
     pub friends: HashMap<PublicKey, FriendReport<B>>,
+    /// Local exchange rates between currencies
+    pub exchange_rates: Vec<ExchangeRate>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -268,6 +296,8 @@ pub enum FriendReportMutation<B = NetAddress> {
     #[capnp_conv(with = OptLastIncomingMoveToken)]
     SetOptLastIncomingMoveToken(Option<MoveTokenHashedReport>),
     SetLiveness(FriendLivenessReport),
+    SetSettlement(SettlementReport),
+    SetRemoteCreditLimits(Vec<CreditLimit>),
 }
 
 #[capnp_conv(crate::report_capnp::add_friend_report)]
@@ -315,6 +345,8 @@ pub enum FunderReportMutation<B = NetAddress> {
     RemoveFriend(PublicKey),
     #[capnp_conv(with = PkFriendReportMutation<NetAddress>)]
     PkFriendReportMutation((PublicKey, FriendReportMutation<B>)),
+    SetExchangeRate(ExchangeRate),
+    RemoveExchangeRate(CurrencyPair),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -394,6 +426,12 @@ where
             FriendReportMutation::SetLiveness(friend_liveness_report) => {
                 self.liveness = friend_liveness_report.clone();
             }
+            FriendReportMutation::SetSettlement(settlement_report) => {
+                self.settlement = settlement_report.clone();
+            }
+            FriendReportMutation::SetRemoteCreditLimits(remote_credit_limits) => {
+                self.remote_credit_limits = remote_credit_limits.clone();
+            }
         };
         Ok(())
     }
@@ -434,6 +472,8 @@ where
                     liveness: FriendLivenessReport::Offline,
                     channel_status: add_friend_report.channel_status.clone(),
                     status: FriendStatusReport::from(&FriendStatus::Disabled),
+                    settlement: SettlementReport::NotStarted,
+                    remote_credit_limits: Vec::new(),
                 };
                 if self
                     .friends
@@ -465,6 +505,20 @@ where
                     .map_err(|_| unreachable!())?;
                 Ok(())
             }
+            FunderReportMutation::SetExchangeRate(exchange_rate) => {
+                // Remove duplicates:
+                self.exchange_rates.retain(|cur_exchange_rate| {
+                    cur_exchange_rate.currency_pair != exchange_rate.currency_pair
+                });
+                // Insert:
+                self.exchange_rates.push(exchange_rate.clone());
+                Ok(())
+            }
+            FunderReportMutation::RemoveExchangeRate(currency_pair) => {
+                self.exchange_rates
+                    .retain(|cur_exchange_rate| &cur_exchange_rate.currency_pair != currency_pair);
+                Ok(())
+            }
         }
     }
 }
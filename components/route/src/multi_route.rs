@@ -40,7 +40,10 @@ fn fill_amount(amount: u128, sorted_routes: &[(Option<usize>, u128)]) -> Option<
 /// Returns a vector representing how many credits to push through every chosen route (if successful).
 /// For example: (5usize, 100u128) means: push 100 credits through the route that was given in
 /// index 5.
-fn safe_multi_route_amounts(multi_route: &MultiRoute, amount: u128) -> Option<MultiRouteChoice> {
+pub fn safe_multi_route_amounts(
+    multi_route: &MultiRoute,
+    amount: u128,
+) -> Option<MultiRouteChoice> {
     let routes = &multi_route.routes;
     let sorted_routes = {
         let mut sorted_routes: Vec<_> = routes
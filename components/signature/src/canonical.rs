@@ -4,10 +4,12 @@ use byteorder::{BigEndian, WriteBytesExt};
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     BalanceInfo, CancelSendFundsOp, CollectSendFundsOp, CountersInfo, Currency,
-    CurrencyBalanceInfo, CurrencyOperations, FriendTcOp, FriendsRoute, McInfo, OptLocalRelays,
-    Receipt, RequestSendFundsOp, ResponseSendFundsOp, TokenInfo,
+    CurrencyBalanceInfo, CurrencyOperations, CurrencyPair, ExchangeRate, FriendTcOp, FriendsRoute,
+    McInfo, OptLocalRelays, Rate, Receipt, RequestSendFundsOp, ResponseSendFundsOp, TokenInfo,
+};
+use proto::index_server::messages::{
+    IndexMutation, RemoveFriendCurrency, TopologyEdge, UpdateFriendCurrency,
 };
-use proto::index_server::messages::{IndexMutation, RemoveFriendCurrency, UpdateFriendCurrency};
 use proto::net::messages::NetAddress;
 
 use common::int_convert::usize_to_u64;
@@ -308,6 +310,48 @@ impl CanonicalSerialize for RemoveFriendCurrency {
     }
 }
 
+impl CanonicalSerialize for Rate {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_bytes = Vec::new();
+        res_bytes.extend_from_slice(&self.mul.canonical_serialize());
+        res_bytes.extend_from_slice(&self.add.canonical_serialize());
+        res_bytes
+    }
+}
+
+impl CanonicalSerialize for TopologyEdge {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_bytes = Vec::new();
+        res_bytes.extend_from_slice(&self.from_public_key);
+        res_bytes.extend_from_slice(&self.to_public_key);
+        res_bytes
+            .write_u128::<BigEndian>(self.recv_capacity)
+            .unwrap();
+        res_bytes.extend_from_slice(&self.rate.canonical_serialize());
+        res_bytes
+    }
+}
+
+impl CanonicalSerialize for CurrencyPair {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_bytes = Vec::new();
+        res_bytes.extend_from_slice(&self.src_currency.canonical_serialize());
+        res_bytes.extend_from_slice(&self.dest_currency.canonical_serialize());
+        res_bytes
+    }
+}
+
+impl CanonicalSerialize for ExchangeRate {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_bytes = Vec::new();
+        res_bytes.extend_from_slice(&self.currency_pair.canonical_serialize());
+        res_bytes.extend_from_slice(&self.mul.canonical_serialize());
+        res_bytes.extend_from_slice(&self.valid_from.canonical_serialize());
+        res_bytes.extend_from_slice(&self.valid_until.canonical_serialize());
+        res_bytes
+    }
+}
+
 impl CanonicalSerialize for IndexMutation {
     fn canonical_serialize(&self) -> Vec<u8> {
         let mut res_bytes = Vec::new();
@@ -320,6 +364,14 @@ impl CanonicalSerialize for IndexMutation {
                 res_bytes.push(1);
                 res_bytes.extend(remove_friend_currency.canonical_serialize());
             }
+            IndexMutation::SetExchangeRate(exchange_rate) => {
+                res_bytes.push(2);
+                res_bytes.extend(exchange_rate.canonical_serialize());
+            }
+            IndexMutation::RemoveExchangeRate(currency_pair) => {
+                res_bytes.push(3);
+                res_bytes.extend(currency_pair.canonical_serialize());
+            }
         };
         res_bytes
     }
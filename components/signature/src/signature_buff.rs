@@ -2,15 +2,18 @@ use byteorder::{BigEndian, WriteBytesExt};
 
 use crypto::hash::{self, sha_512_256};
 
-use proto::crypto::HashResult;
+use proto::crypto::{HashResult, PublicKey};
+
+use proto::app_server::messages::UnsignedInvoice;
 
 use common::int_convert::usize_to_u64;
 
 use crate::canonical::CanonicalSerialize;
 use proto::funder::messages::{
-    Currency, PendingTransaction, TokenInfo, UnsignedMoveToken, UnsignedResponseSendFundsOp,
+    Currency, CurrencyBalance, PendingTransaction, TokenInfo, UnsignedMoveToken,
+    UnsignedResponseSendFundsOp,
 };
-use proto::index_server::messages::MutationsUpdate;
+use proto::index_server::messages::{MutationsUpdate, ResponseTopology, TopologyEdge};
 use proto::report::messages::MoveTokenHashedReport;
 
 pub const FUNDS_RESPONSE_PREFIX: &[u8] = b"FUND_RESPONSE";
@@ -134,6 +137,38 @@ pub fn create_mutations_update_signature_buff(mutations_update: &MutationsUpdate
     res_bytes
 }
 
+pub const TOPOLOGY_PREFIX: &[u8] = b"TOPOLOGY";
+
+/// Calculate a hash over all the edges of a topology.
+/// The result does not depend on the order of the edges.
+pub fn topology_hash<'a, I>(topology_edges: I) -> HashResult
+where
+    I: IntoIterator<Item = &'a TopologyEdge>,
+{
+    let mut topology_edges: Vec<&TopologyEdge> = topology_edges.into_iter().collect();
+    topology_edges.sort_by(|edge_a, edge_b| {
+        (&edge_a.from_public_key, &edge_a.to_public_key)
+            .cmp(&(&edge_b.from_public_key, &edge_b.to_public_key))
+    });
+
+    let mut hash_buff = Vec::new();
+    hash_buff
+        .write_u64::<BigEndian>(usize_to_u64(topology_edges.len()).unwrap())
+        .unwrap();
+    for topology_edge in topology_edges {
+        hash_buff.extend(topology_edge.canonical_serialize());
+    }
+    hash::sha_512_256(&hash_buff)
+}
+
+pub fn create_response_topology_signature_buff(response_topology: &ResponseTopology) -> Vec<u8> {
+    let mut res_bytes = Vec::new();
+    res_bytes.extend_from_slice(&hash::sha_512_256(TOPOLOGY_PREFIX));
+    res_bytes.extend_from_slice(&response_topology.currency.canonical_serialize());
+    res_bytes.extend_from_slice(&response_topology.topology_hash);
+    res_bytes
+}
+
 pub fn move_token_hashed_report_signature_buff(
     move_token_hashed_report: &MoveTokenHashedReport,
 ) -> Vec<u8> {
@@ -144,3 +179,82 @@ pub fn move_token_hashed_report_signature_buff(
     sig_buffer.extend_from_slice(&move_token_hashed_report.rand_nonce);
     sig_buffer
 }
+
+pub const SETTLEMENT_PREFIX: &[u8] = b"SETTLEMENT";
+
+/// Create the buffer both friends sign over when settling their channel.
+/// The buffer is the same for both sides: public keys are ordered, and balances are taken from
+/// the point of view of the lower public key.
+///
+/// Returns None if a balance can not be negated.
+pub fn create_settlement_signature_buff(
+    local_public_key: &PublicKey,
+    remote_public_key: &PublicKey,
+    balances: &[CurrencyBalance],
+    move_token_counter: u128,
+) -> Option<Vec<u8>> {
+    let is_local_low = local_public_key < remote_public_key;
+    let (low_public_key, high_public_key) = if is_local_low {
+        (local_public_key, remote_public_key)
+    } else {
+        (remote_public_key, local_public_key)
+    };
+
+    let mut low_balances = Vec::new();
+    for currency_balance in balances {
+        let balance = if is_local_low {
+            currency_balance.balance
+        } else {
+            currency_balance.balance.checked_neg()?
+        };
+        low_balances.push((&currency_balance.currency, balance));
+    }
+    low_balances.sort_by(|(currency_a, _), (currency_b, _)| currency_a.cmp(currency_b));
+
+    let mut res_bytes = Vec::new();
+    res_bytes.extend_from_slice(&hash::sha_512_256(SETTLEMENT_PREFIX));
+    res_bytes.extend_from_slice(low_public_key);
+    res_bytes.extend_from_slice(high_public_key);
+    res_bytes
+        .write_u128::<BigEndian>(move_token_counter)
+        .unwrap();
+
+    res_bytes
+        .write_u64::<BigEndian>(usize_to_u64(low_balances.len()).unwrap())
+        .unwrap();
+    for (currency, balance) in low_balances {
+        res_bytes.extend_from_slice(&currency.canonical_serialize());
+        res_bytes.write_i128::<BigEndian>(balance).unwrap();
+    }
+
+    Some(res_bytes)
+}
+
+pub const INVOICE_PREFIX: &[u8] = b"INVOICE";
+
+/// Create the buffer a seller node signs over when issuing an invoice.
+pub fn create_invoice_signature_buff<B, UI>(
+    dest_public_key: &PublicKey,
+    unsigned_invoice: UI,
+) -> Vec<u8>
+where
+    B: CanonicalSerialize,
+    UI: Into<UnsignedInvoice<B>>,
+{
+    let unsigned_invoice: UnsignedInvoice<B> = unsigned_invoice.into();
+
+    let mut res_bytes = Vec::new();
+    res_bytes.extend_from_slice(&hash::sha_512_256(INVOICE_PREFIX));
+    res_bytes.extend_from_slice(dest_public_key);
+    res_bytes.extend_from_slice(&unsigned_invoice.invoice_id);
+    res_bytes.extend_from_slice(&unsigned_invoice.currency.canonical_serialize());
+    res_bytes
+        .write_u128::<BigEndian>(unsigned_invoice.total_dest_payment)
+        .unwrap();
+    res_bytes.extend_from_slice(&unsigned_invoice.relays.canonical_serialize());
+    res_bytes
+        .write_u64::<BigEndian>(unsigned_invoice.valid_until)
+        .unwrap();
+    res_bytes.extend_from_slice(&unsigned_invoice.description.canonical_serialize());
+    res_bytes
+}
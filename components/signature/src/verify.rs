@@ -4,16 +4,19 @@ use crypto::hash;
 use crypto::hash_lock::HashLock;
 use crypto::identity::verify_signature;
 
-use proto::crypto::PublicKey;
+use proto::crypto::{InvoiceId, PublicKey};
 
-use proto::funder::messages::{Commit, MoveToken, Receipt};
-use proto::index_server::messages::MutationsUpdate;
+use proto::app_server::messages::{SignedInvoice, VerifyFriendTokenStatus, VerifyReceiptStatus};
+
+use proto::funder::messages::{Commit, MoveToken, Receipt, SettlementStatement};
+use proto::index_server::messages::{MutationsUpdate, ResponseTopology};
 use proto::report::messages::MoveTokenHashedReport;
 
 use crate::canonical::CanonicalSerialize;
 use crate::signature_buff::{
-    create_mutations_update_signature_buff, move_token_hashed_report_signature_buff,
-    move_token_signature_buff, FUNDS_RESPONSE_PREFIX,
+    create_invoice_signature_buff, create_mutations_update_signature_buff,
+    create_response_topology_signature_buff, create_settlement_signature_buff,
+    move_token_hashed_report_signature_buff, move_token_signature_buff, FUNDS_RESPONSE_PREFIX,
 };
 
 // TODO: Add a local test that makes sure verify_receipt is in sync with verify_commit_signature
@@ -34,6 +37,25 @@ pub fn verify_receipt(receipt: &Receipt, public_key: &PublicKey) -> bool {
     verify_signature(&data, public_key, &receipt.signature)
 }
 
+/// Verify a receipt against the invoice it is supposed to pay.
+/// `dest_public_key` is the seller node, who signed the receipt.
+pub fn verify_receipt_for_invoice(
+    receipt: &Receipt,
+    dest_public_key: &PublicKey,
+    invoice_id: &InvoiceId,
+    total_dest_payment: u128,
+) -> VerifyReceiptStatus {
+    if &receipt.invoice_id != invoice_id || receipt.total_dest_payment != total_dest_payment {
+        return VerifyReceiptStatus::InvoiceMismatch;
+    }
+
+    if !verify_receipt(receipt, dest_public_key) {
+        return VerifyReceiptStatus::WrongSigner;
+    }
+
+    VerifyReceiptStatus::Valid
+}
+
 /// Verify that a given Commit signature is valid
 fn verify_commit_signature(commit: &Commit, local_public_key: &PublicKey) -> bool {
     let mut data = Vec::new();
@@ -84,6 +106,20 @@ pub fn verify_mutations_update(mutations_update: &MutationsUpdate) -> bool {
     )
 }
 
+/// Verify that a ResponseTopology was signed by the index server.
+/// Note that this does not check that `topology_hash` matches the topology.
+pub fn verify_response_topology(
+    response_topology: &ResponseTopology,
+    server_public_key: &PublicKey,
+) -> bool {
+    let signature_buff = create_response_topology_signature_buff(response_topology);
+    verify_signature(
+        &signature_buff,
+        server_public_key,
+        &response_topology.signature,
+    )
+}
+
 // TODO: Is the public_key argument redundant now? (As it should be exactly the same
 // as move_token_hashed_report.local_public_key)
 /// Verify that new_token is a valid signature over the rest of the fields.
@@ -94,3 +130,152 @@ pub fn verify_move_token_hashed_report(
     let sig_buffer = move_token_hashed_report_signature_buff(move_token_hashed_report);
     verify_signature(&sig_buffer, public_key, &move_token_hashed_report.new_token)
 }
+
+/// Verify a token received from the friend `friend_public_key`.
+pub fn verify_friend_token(
+    move_token_hashed_report: &MoveTokenHashedReport,
+    friend_public_key: &PublicKey,
+) -> VerifyFriendTokenStatus {
+    // The token is signed by the sender side, which is the local side of the mutual credit:
+    if &move_token_hashed_report.token_info.mc.local_public_key != friend_public_key {
+        return VerifyFriendTokenStatus::WrongSigner;
+    }
+
+    if !verify_move_token_hashed_report(move_token_hashed_report, friend_public_key) {
+        return VerifyFriendTokenStatus::InvalidSignature;
+    }
+
+    VerifyFriendTokenStatus::Valid
+}
+
+/// Verify that a SettlementStatement was signed by both sides.
+pub fn verify_settlement_statement(settlement_statement: &SettlementStatement) -> bool {
+    let signature_buff = match create_settlement_signature_buff(
+        &settlement_statement.local_public_key,
+        &settlement_statement.remote_public_key,
+        &settlement_statement.balances,
+        settlement_statement.move_token_counter,
+    ) {
+        Some(signature_buff) => signature_buff,
+        None => return false,
+    };
+
+    verify_signature(
+        &signature_buff,
+        &settlement_statement.local_public_key,
+        &settlement_statement.local_signature,
+    ) && verify_signature(
+        &signature_buff,
+        &settlement_statement.remote_public_key,
+        &settlement_statement.remote_signature,
+    )
+}
+
+/// Verify that a SignedInvoice was signed by the seller node (`dest_public_key`).
+/// Note that this does not check the expiry of the invoice.
+pub fn verify_signed_invoice<B>(signed_invoice: &SignedInvoice<B>) -> bool
+where
+    B: CanonicalSerialize + Clone,
+{
+    let signature_buff =
+        create_invoice_signature_buff(&signed_invoice.dest_public_key, signed_invoice.clone());
+    verify_signature(
+        &signature_buff,
+        &signed_invoice.dest_public_key,
+        &signed_invoice.signature,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::identity::{Identity, SoftwareEd25519Identity};
+    use crypto::rand::RandGen;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::crypto::{HashResult, PrivateKey, RandValue, Signature};
+    use proto::funder::messages::{
+        BalanceInfo, CountersInfo, Currency, CurrencyBalanceInfo, McInfo, TokenInfo,
+    };
+
+    fn create_identity(seed: u8) -> SoftwareEd25519Identity {
+        let mut rng = DummyRandom::new(&[seed]);
+        let private_key = PrivateKey::rand_gen(&mut rng);
+        SoftwareEd25519Identity::from_private_key(&private_key).unwrap()
+    }
+
+    /// Create a MoveTokenHashedReport, signed by the local side of the mutual credit
+    fn create_signed_report(
+        local_identity: &SoftwareEd25519Identity,
+        remote_public_key: PublicKey,
+    ) -> MoveTokenHashedReport {
+        let mut move_token_hashed_report = MoveTokenHashedReport {
+            prefix_hash: HashResult::from(&[1; HashResult::len()]),
+            token_info: TokenInfo {
+                mc: McInfo {
+                    local_public_key: local_identity.get_public_key(),
+                    remote_public_key,
+                    balances: vec![CurrencyBalanceInfo {
+                        currency: Currency::try_from("FST".to_owned()).unwrap(),
+                        balance_info: BalanceInfo {
+                            balance: -5,
+                            local_pending_debt: 3,
+                            remote_pending_debt: 2,
+                        },
+                    }],
+                },
+                counters: CountersInfo {
+                    inconsistency_counter: 1,
+                    move_token_counter: 7,
+                },
+            },
+            rand_nonce: RandValue::from(&[2; RandValue::len()]),
+            new_token: Signature::from(&[0; Signature::len()]),
+        };
+        let sig_buffer = move_token_hashed_report_signature_buff(&move_token_hashed_report);
+        move_token_hashed_report.new_token = local_identity.sign(&sig_buffer);
+        move_token_hashed_report
+    }
+
+    #[test]
+    fn test_verify_friend_token() {
+        let friend_identity = create_identity(1);
+        let local_identity = create_identity(2);
+        let friend_public_key = friend_identity.get_public_key();
+
+        // A token signed by the friend:
+        let move_token_hashed_report =
+            create_signed_report(&friend_identity, local_identity.get_public_key());
+        assert_eq!(
+            verify_friend_token(&move_token_hashed_report, &friend_public_key),
+            VerifyFriendTokenStatus::Valid
+        );
+
+        // A valid token that was signed by us, and not by the friend:
+        let local_report = create_signed_report(&local_identity, friend_public_key.clone());
+        assert_eq!(
+            verify_friend_token(&local_report, &friend_public_key),
+            VerifyFriendTokenStatus::WrongSigner
+        );
+
+        // A token that was modified after it was signed:
+        let mut modified_report = move_token_hashed_report.clone();
+        modified_report.token_info.counters.move_token_counter += 1;
+        assert_eq!(
+            verify_friend_token(&modified_report, &friend_public_key),
+            VerifyFriendTokenStatus::InvalidSignature
+        );
+
+        // A token claiming to be from the friend, signed by someone else:
+        let mut forged_report = local_report;
+        forged_report.token_info.mc.local_public_key = friend_public_key.clone();
+        forged_report.token_info.mc.remote_public_key = local_identity.get_public_key();
+        assert_eq!(
+            verify_friend_token(&forged_report, &friend_public_key),
+            VerifyFriendTokenStatus::InvalidSignature
+        );
+    }
+}
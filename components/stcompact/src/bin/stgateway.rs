@@ -0,0 +1,30 @@
+#![deny(trivial_numeric_casts, warnings)]
+#![allow(intra_doc_link_resolution_failure)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::implicit_hasher,
+    clippy::module_inception,
+    clippy::new_without_default
+)]
+
+#[macro_use]
+extern crate log;
+
+use structopt::StructOpt;
+
+use futures::executor::{block_on, ThreadPool};
+
+use stcompact::stgatewaylib::{stgateway, StGatewayCmd, StGatewayError};
+
+fn run() -> Result<(), StGatewayError> {
+    env_logger::init();
+    let st_gateway_cmd = StGatewayCmd::from_args();
+    let thread_pool = ThreadPool::new().map_err(|_| StGatewayError::SpawnError)?;
+    block_on(stgateway(st_gateway_cmd, thread_pool))
+}
+
+fn main() {
+    if let Err(e) = run() {
+        error!("error: {:?}", e);
+    }
+}
@@ -4,7 +4,8 @@ use crate::compact_node::messages::{
     BalanceInfo, ChannelConsistentReport, ChannelInconsistentReport, ChannelStatusReport, Commit,
     CompactReport, ConfigReport, CountersInfo, CurrencyReport, FriendLivenessReport, FriendReport,
     FriendStatusReport, McInfo, MoveTokenHashedReport, OpenInvoice, OpenPayment, OpenPaymentStatus,
-    RequestsStatusReport, ResetTermsReport, TokenInfo,
+    RequestsStatusReport, ResetTermsReport, TokenInfo, VerifyFriendTokenStatus,
+    VerifyReceiptStatus,
 };
 
 use crate::compact_node::persist;
@@ -149,6 +150,70 @@ impl From<app::report::MoveTokenHashedReport> for MoveTokenHashedReport {
     }
 }
 
+impl From<BalanceInfo> for app::report::BalanceInfo {
+    fn from(from: BalanceInfo) -> Self {
+        app::report::BalanceInfo {
+            balance: from.balance,
+            local_pending_debt: from.local_pending_debt,
+            remote_pending_debt: from.remote_pending_debt,
+        }
+    }
+}
+
+impl From<McInfo> for app::report::McInfo {
+    fn from(from: McInfo) -> Self {
+        let mut balances: Vec<_> = from
+            .balances
+            .into_iter()
+            .map(
+                |(currency, balance_info)| app::report::CurrencyBalanceInfo {
+                    currency,
+                    balance_info: balance_info.into(),
+                },
+            )
+            .collect();
+
+        // The order of balances is lost in the compact representation.
+        // Restore the canonical order (sorted by currency), which is the signed order:
+        balances.sort_by(|cbi1, cbi2| cbi1.currency.cmp(&cbi2.currency));
+
+        app::report::McInfo {
+            local_public_key: from.local_public_key,
+            remote_public_key: from.remote_public_key,
+            balances,
+        }
+    }
+}
+
+impl From<CountersInfo> for app::report::CountersInfo {
+    fn from(from: CountersInfo) -> Self {
+        app::report::CountersInfo {
+            inconsistency_counter: from.inconsistency_counter,
+            move_token_counter: from.move_token_counter,
+        }
+    }
+}
+
+impl From<TokenInfo> for app::report::TokenInfo {
+    fn from(from: TokenInfo) -> Self {
+        app::report::TokenInfo {
+            mc: from.mc.into(),
+            counters: from.counters.into(),
+        }
+    }
+}
+
+impl From<MoveTokenHashedReport> for app::report::MoveTokenHashedReport {
+    fn from(from: MoveTokenHashedReport) -> Self {
+        app::report::MoveTokenHashedReport {
+            prefix_hash: from.prefix_hash,
+            token_info: from.token_info.into(),
+            rand_nonce: from.rand_nonce,
+            new_token: from.new_token,
+        }
+    }
+}
+
 impl From<app::report::FriendStatusReport> for FriendStatusReport {
     fn from(friend_status_report: app::report::FriendStatusReport) -> Self {
         match friend_status_report {
@@ -242,6 +307,28 @@ impl From<Commit> for app::common::Commit {
     }
 }
 
+impl From<app::conn::VerifyReceiptStatus> for VerifyReceiptStatus {
+    fn from(from: app::conn::VerifyReceiptStatus) -> Self {
+        match from {
+            app::conn::VerifyReceiptStatus::Valid => VerifyReceiptStatus::Valid,
+            app::conn::VerifyReceiptStatus::InvoiceMismatch => VerifyReceiptStatus::InvoiceMismatch,
+            app::conn::VerifyReceiptStatus::WrongSigner => VerifyReceiptStatus::WrongSigner,
+        }
+    }
+}
+
+impl From<app::conn::VerifyFriendTokenStatus> for VerifyFriendTokenStatus {
+    fn from(from: app::conn::VerifyFriendTokenStatus) -> Self {
+        match from {
+            app::conn::VerifyFriendTokenStatus::Valid => VerifyFriendTokenStatus::Valid,
+            app::conn::VerifyFriendTokenStatus::WrongSigner => VerifyFriendTokenStatus::WrongSigner,
+            app::conn::VerifyFriendTokenStatus::InvalidSignature => {
+                VerifyFriendTokenStatus::InvalidSignature
+            }
+        }
+    }
+}
+
 // ==================[CompactReport]==========================
 //
 
@@ -278,6 +365,10 @@ impl From<persist::OpenPayment> for OpenPayment {
             description: from.description,
             generation: from.generation,
             status: from.status.into(),
+            opt_fee_budget: from.opt_fee_budget,
+            routes: from.routes,
+            opt_retry_policy: from.opt_retry_policy,
+            attempts: from.attempts,
         }
     }
 }
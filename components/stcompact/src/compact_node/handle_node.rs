@@ -1,23 +1,28 @@
 use futures::{Sink, SinkExt};
 
-use app::common::{MultiRoute, PaymentStatus};
+use app::common::{FriendsRoute, MultiRoute, PaymentId, PaymentStatus, PublicKey, Uid};
 use app::conn::{
-    buyer, AppServerToApp, AppToAppServer, ClientResponseRoutes, RequestResult,
+    buyer, now_secs, routes, AppServerToApp, AppToAppServer, ClientResponseRoutes, RequestResult,
     ResponseClosePayment, ResponseRoutesResult,
 };
 
-use route::{choose_multi_route, MultiRouteChoice};
+use app::ser_utils::invoice_to_uri;
+
+use route::{safe_multi_route_amounts, MultiRouteChoice};
 
 use crate::compact_node::convert::create_compact_report;
 use crate::compact_node::messages::{
-    CompactToUser, CompactToUserAck, PaymentCommit, PaymentDone, PaymentDoneStatus, PaymentFees,
-    PaymentFeesResponse,
+    CompactToUser, CompactToUserAck, CreateInvoiceUriStatus, FeeBudget, InvoiceCommitted,
+    PaymentAttempt, PaymentCommit, PaymentDone, PaymentDoneStatus, PaymentFees,
+    PaymentFeesResponse, PaymentRoute, PaymentTransactionStatus, ResponseCreateInvoiceUri,
 };
-use crate::compact_node::persist::{OpenPaymentStatus, OpenPaymentStatusFoundRoute};
+use crate::compact_node::persist::{CompactState, OpenPaymentStatus, OpenPaymentStatusFoundRoute};
 use crate::compact_node::types::{CompactNodeError, CompactServerState};
 use crate::gen::GenUid;
 
-use crate::compact_node::utils::update_send_compact_state;
+use crate::compact_node::utils::{
+    create_payment_attempt, send_found_route, send_payment_attempt, update_send_compact_state,
+};
 
 /// Calculate fees if we send credits through the given MultiRoute with the MultiRouteChoice
 /// strategy
@@ -35,22 +40,353 @@ fn calc_multi_route_fees(
     Some(total_fees)
 }
 
+/// Check if the given fees are within a fee budget
+fn is_within_budget(fee_budget: &FeeBudget, dest_payment: u128, fees: u128) -> bool {
+    if let Some(max_fees) = fee_budget.opt_max_fees {
+        if fees > max_fees {
+            return false;
+        }
+    }
+    if let Some(max_fees_percent) = fee_budget.opt_max_fees_percent {
+        // Check: fees / dest_payment <= max_fees_percent / 100
+        let fees_scaled = if let Some(fees_scaled) = fees.checked_mul(100) {
+            fees_scaled
+        } else {
+            return false;
+        };
+        if fees_scaled > dest_payment.saturating_mul(u128::from(max_fees_percent)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// All the MultiRoutes that can be used for sending `dest_payment` credits, in the original
+/// order. Every MultiRoute is given together with a choice of credits for every route, and the
+/// total fees.
+fn suitable_multi_routes<'a>(
+    multi_routes: &'a [MultiRoute],
+    dest_payment: u128,
+) -> impl Iterator<Item = (&'a MultiRoute, MultiRouteChoice, u128)> + 'a {
+    multi_routes.iter().filter_map(move |multi_route| {
+        let multi_route_choice = safe_multi_route_amounts(multi_route, dest_payment)?;
+        // Make sure that fees can be calculated correctly:
+        let fees = calc_multi_route_fees(multi_route, &multi_route_choice)?;
+        Some((multi_route, multi_route_choice, fees))
+    })
+}
+
+/// Choose a MultiRoute for sending `dest_payment` credits.
+/// If a fee budget is given, we attempt to find a MultiRoute with fees inside the budget.
+/// Returns the chosen MultiRoute, and whether its fees are inside the budget.
 fn obtain_multi_route(
     client_response_routes: &ClientResponseRoutes,
     dest_payment: u128,
-) -> Option<(MultiRoute, MultiRouteChoice, u128)> {
+    opt_fee_budget: Option<&FeeBudget>,
+) -> Option<(MultiRoute, MultiRouteChoice, u128, bool)> {
     let multi_routes = match &client_response_routes.result {
         ResponseRoutesResult::Success(multi_routes) => multi_routes,
         ResponseRoutesResult::Failure => return None,
     };
 
-    let (route_index, multi_route_choice) = choose_multi_route(&multi_routes, dest_payment)?;
-    let multi_route = &multi_routes[route_index];
+    let mut opt_first = None;
+    for (multi_route, multi_route_choice, fees) in suitable_multi_routes(multi_routes, dest_payment)
+    {
+        if let Some(fee_budget) = opt_fee_budget {
+            if is_within_budget(fee_budget, dest_payment, fees) {
+                return Some((multi_route.clone(), multi_route_choice, fees, true));
+            }
+        } else {
+            return Some((multi_route.clone(), multi_route_choice, fees, false));
+        }
+
+        // Fees are over budget. Remember the first suitable MultiRoute, and try the next one:
+        if opt_first.is_none() {
+            opt_first = Some((multi_route.clone(), multi_route_choice, fees, false));
+        }
+    }
+    opt_first
+}
 
-    // Make sure that fees can be calculated correctly:
-    let fees = calc_multi_route_fees(multi_route, &multi_route_choice)?;
+/// Summarize the routes and fees of a chosen MultiRoute
+fn create_payment_routes(
+    multi_route: &MultiRoute,
+    multi_route_choice: &[(usize, u128)],
+) -> Vec<PaymentRoute> {
+    multi_route_choice
+        .iter()
+        .map(|(route_index, dest_payment)| {
+            let route = &multi_route.routes[*route_index];
+            PaymentRoute {
+                route: route.route.clone(),
+                dest_payment: *dest_payment,
+                // Fees were already checked by `calc_multi_route_fees()`:
+                fees: route.rate.calc_fee(*dest_payment).unwrap(),
+            }
+        })
+        .collect()
+}
 
-    Some((multi_route.clone(), multi_route_choice, fees))
+/// Check if the current time is past the given deadline (Seconds since the Unix epoch)
+fn is_deadline_passed(opt_deadline: Option<u64>) -> bool {
+    match opt_deadline {
+        Some(deadline) => now_secs() >= deadline,
+        None => false,
+    }
+}
+
+/// Update the status of a transaction in the attempts of a payment.
+/// Returns the route of the transaction, if found.
+fn update_transaction_status(
+    attempts: &mut [PaymentAttempt],
+    request_id: &Uid,
+    status: PaymentTransactionStatus,
+) -> Option<PaymentRoute> {
+    for attempt in attempts {
+        for transaction in &mut attempt.transactions {
+            if &transaction.request_id == request_id {
+                transaction.status = status;
+                return Some(transaction.route.clone());
+            }
+        }
+    }
+    None
+}
+
+/// Find an edge to exclude when requesting routes for a retry, according to the last failed
+/// transaction of the last attempt.
+/// The first edge of a route is checked by our own node, hence we exclude the edge
+/// after it, if the route has one.
+fn failed_edge(attempts: &[PaymentAttempt]) -> Option<(PublicKey, PublicKey)> {
+    let transaction = attempts
+        .last()?
+        .transactions
+        .iter()
+        .rev()
+        .find(|transaction| transaction.status == PaymentTransactionStatus::Failure)?;
+
+    let public_keys = &transaction.route.route.public_keys;
+    let index = if public_keys.len() >= 3 { 1 } else { 0 };
+    Some((
+        public_keys.get(index)?.clone(),
+        public_keys.get(index + 1)?.clone(),
+    ))
+}
+
+/// Give up on a payment that allows retries.
+/// Such payments are only closed once we stop sending transactions.
+async fn fail_retry_payment<CG, US, AS>(
+    mut compact_state: CompactState,
+    payment_id: PaymentId,
+    server_state: &mut CompactServerState,
+    compact_gen: &mut CG,
+    user_sender: &mut US,
+    app_sender: &mut AS,
+) -> Result<(), CompactNodeError>
+where
+    CG: GenUid,
+    US: Sink<CompactToUserAck> + Unpin,
+    AS: Sink<AppToAppServer> + Unpin,
+{
+    // Set payment as failed:
+    let open_payment = compact_state.open_payments.get_mut(&payment_id).unwrap();
+    let ack_uid = compact_gen.gen_uid();
+    open_payment.status = OpenPaymentStatus::Failure(ack_uid.clone());
+    update_send_compact_state(compact_state, server_state, user_sender).await?;
+
+    // We are not going to send any more transactions:
+    let app_request = buyer::request_close_payment(payment_id.clone());
+    let app_to_app_server = AppToAppServer {
+        app_request_id: compact_gen.gen_uid(),
+        app_request,
+    };
+    app_sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| CompactNodeError::AppSenderError)?;
+
+    // Inform the user about failure.
+    // Send a message about payment done:
+    let payment_done = PaymentDone {
+        payment_id,
+        status: PaymentDoneStatus::Failure(ack_uid),
+    };
+    let compact_to_user = CompactToUser::PaymentDone(payment_done);
+    user_sender
+        .send(CompactToUserAck::CompactToUser(compact_to_user))
+        .await
+        .map_err(|_| CompactNodeError::UserSenderError)
+}
+
+/// Request routes for sending the missing credits of a payment again.
+/// Fails the payment if the retry policy does not allow another retry.
+async fn retry_payment<CG, US, AS>(
+    mut compact_state: CompactState,
+    payment_id: PaymentId,
+    server_state: &mut CompactServerState,
+    compact_gen: &mut CG,
+    user_sender: &mut US,
+    app_sender: &mut AS,
+) -> Result<(), CompactNodeError>
+where
+    CG: GenUid,
+    US: Sink<CompactToUserAck> + Unpin,
+    AS: Sink<AppToAppServer> + Unpin,
+{
+    let open_payment = compact_state.open_payments.get_mut(&payment_id).unwrap();
+    let retry_policy = open_payment.opt_retry_policy.clone().unwrap();
+
+    // The first attempt is not a retry:
+    let num_retries = open_payment.attempts.len().saturating_sub(1);
+    if num_retries >= retry_policy.max_retries as usize
+        || is_deadline_passed(retry_policy.opt_deadline)
+    {
+        return fail_retry_payment(
+            compact_state,
+            payment_id,
+            server_state,
+            compact_gen,
+            user_sender,
+            app_sender,
+        )
+        .await;
+    }
+
+    let currency = open_payment.currency.clone();
+    let dest_public_key = open_payment.dest_public_key.clone();
+    // Routes going through the edge that failed last are not useful for this retry.
+    // Other failed routes are filtered out when the routes arrive:
+    let opt_exclude = failed_edge(&open_payment.attempts);
+    let sending = if let OpenPaymentStatus::Sending(sending) = &mut open_payment.status {
+        sending
+    } else {
+        unreachable!();
+    };
+    let missing_dest_payment = sending.missing_dest_payment;
+    let request_routes_id = compact_gen.gen_uid();
+    sending.opt_request_routes_id = Some(request_routes_id.clone());
+    update_send_compact_state(compact_state, server_state, user_sender).await?;
+
+    let app_request = routes::request_routes(
+        request_routes_id,
+        currency,
+        missing_dest_payment,
+        server_state
+            .node_report()
+            .funder_report
+            .local_public_key
+            .clone(),
+        dest_public_key,
+        opt_exclude,
+    );
+    let app_to_app_server = AppToAppServer {
+        app_request_id: compact_gen.gen_uid(),
+        app_request,
+    };
+    app_sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| CompactNodeError::AppSenderError)
+}
+
+/// Send the missing credits of a payment along the routes we have obtained for a retry.
+async fn send_retry_routes<CG, US, AS>(
+    mut compact_state: CompactState,
+    payment_id: PaymentId,
+    client_response_routes: ClientResponseRoutes,
+    server_state: &mut CompactServerState,
+    compact_gen: &mut CG,
+    user_sender: &mut US,
+    app_sender: &mut AS,
+) -> Result<(), CompactNodeError>
+where
+    CG: GenUid,
+    US: Sink<CompactToUserAck> + Unpin,
+    AS: Sink<AppToAppServer> + Unpin,
+{
+    let open_payment = compact_state.open_payments.get_mut(&payment_id).unwrap();
+
+    // Routes that have already failed are not used again:
+    let failed_routes: Vec<FriendsRoute> = open_payment
+        .attempts
+        .iter()
+        .flat_map(|attempt| attempt.transactions.iter())
+        .filter(|transaction| transaction.status == PaymentTransactionStatus::Failure)
+        .map(|transaction| transaction.route.route.clone())
+        .collect();
+
+    let multi_routes: Vec<MultiRoute> = match client_response_routes.result {
+        ResponseRoutesResult::Success(multi_routes) => multi_routes
+            .into_iter()
+            .map(|mut multi_route| {
+                multi_route
+                    .routes
+                    .retain(|route| !failed_routes.contains(&route.route));
+                multi_route
+            })
+            .collect(),
+        ResponseRoutesResult::Failure => Vec::new(),
+    };
+
+    let dest_payment = open_payment.dest_payment;
+    let opt_fee_budget = open_payment.opt_fee_budget.clone();
+    let sending = if let OpenPaymentStatus::Sending(sending) = &mut open_payment.status {
+        sending
+    } else {
+        unreachable!();
+    };
+
+    // Total fees (Including the fees of the new routes) must be inside the fee budget:
+    let opt_chosen = suitable_multi_routes(&multi_routes, sending.missing_dest_payment).find(
+        |(_multi_route, _multi_route_choice, fees)| match &opt_fee_budget {
+            Some(fee_budget) => match sending.fees.checked_add(*fees) {
+                Some(total_fees) => is_within_budget(fee_budget, dest_payment, total_fees),
+                None => false,
+            },
+            None => true,
+        },
+    );
+
+    let (multi_route, multi_route_choice, fees) = if let Some(chosen) = opt_chosen {
+        chosen
+    } else {
+        // A suitable route was not found:
+        return fail_retry_payment(
+            compact_state,
+            payment_id,
+            server_state,
+            compact_gen,
+            user_sender,
+            app_sender,
+        )
+        .await;
+    };
+
+    let payment_attempt = create_payment_attempt(multi_route, &multi_route_choice, compact_gen);
+    if !server_state.charge_payment_attempt(&open_payment.currency, &payment_attempt) {
+        // Sending again would exceed our spending limit:
+        return fail_retry_payment(
+            compact_state,
+            payment_id,
+            server_state,
+            compact_gen,
+            user_sender,
+            app_sender,
+        )
+        .await;
+    }
+    for transaction in &payment_attempt.transactions {
+        sending
+            .open_transactions
+            .insert(transaction.request_id.clone());
+    }
+    sending.fees = sending.fees.checked_add(fees).unwrap();
+    sending.missing_dest_payment = 0;
+    sending.opt_request_routes_id = None;
+    open_payment.attempts.push(payment_attempt.clone());
+    update_send_compact_state(compact_state, server_state, user_sender).await?;
+
+    send_payment_attempt(&payment_id, &payment_attempt, compact_gen, app_sender).await
 }
 
 async fn ack_close_payment<CG, AS>(
@@ -106,19 +442,18 @@ where
             for (payment_id, open_payment) in &mut compact_state.open_payments {
                 match &mut open_payment.status {
                     OpenPaymentStatus::Sending(sending) => {
-                        let c_sending = sending.clone();
                         if sending
                             .open_transactions
                             .remove(&transaction_result.request_id)
                         {
-                            opt_found = Some((payment_id.clone(), open_payment, c_sending));
+                            opt_found = Some((payment_id.clone(), open_payment));
                         }
                     }
                     _ => continue,
                 }
             }
 
-            let (payment_id, open_payment, sending) = if let Some(found) = opt_found {
+            let (payment_id, open_payment) = if let Some(found) = opt_found {
                 found
             } else {
                 // We couldn't find this request. This could happen if:
@@ -132,15 +467,71 @@ where
                 return Ok(());
             };
 
+            let transaction_status = match &transaction_result.result {
+                RequestResult::Complete(_) | RequestResult::Success => {
+                    PaymentTransactionStatus::Success
+                }
+                RequestResult::Failure => PaymentTransactionStatus::Failure,
+            };
+            server_state.settle_transaction_charge(
+                &transaction_result.request_id,
+                transaction_status == PaymentTransactionStatus::Success,
+            );
+            let opt_payment_route = update_transaction_status(
+                &mut open_payment.attempts,
+                &transaction_result.request_id,
+                transaction_status,
+            );
+
+            let may_retry = open_payment.opt_retry_policy.is_some();
+            let sending = if let OpenPaymentStatus::Sending(sending) = &mut open_payment.status {
+                sending
+            } else {
+                unreachable!();
+            };
+
             match (
                 transaction_result.result,
                 sending.open_transactions.is_empty(),
             ) {
                 (RequestResult::Complete(commit), _) => {
+                    let deliver_commit = open_payment.deliver_commit;
+
                     // Set payment status to Commit:
                     open_payment.status = OpenPaymentStatus::Commit(commit.clone(), sending.fees);
                     update_send_compact_state(compact_state, server_state, user_sender).await?;
 
+                    if may_retry {
+                        // All the credits have arrived.
+                        // Send RequestClosePayment, as we are not going to send any more
+                        // transactions:
+                        let app_request = buyer::request_close_payment(payment_id.clone());
+                        let app_to_app_server = AppToAppServer {
+                            app_request_id: compact_gen.gen_uid(),
+                            app_request,
+                        };
+                        app_sender
+                            .send(app_to_app_server)
+                            .await
+                            .map_err(|_| CompactNodeError::AppSenderError)?;
+                    }
+
+                    if deliver_commit {
+                        // Deliver the commit to the seller along the route of the transaction:
+                        let app_request = buyer::deliver_commit(
+                            transaction_result.request_id.clone(),
+                            commit.clone(),
+                        );
+                        let app_to_app_server = AppToAppServer {
+                            app_request_id: compact_gen.gen_uid(),
+                            app_request,
+                        };
+                        app_sender
+                            .send(app_to_app_server)
+                            .await
+                            .map_err(|_| CompactNodeError::AppSenderError)?;
+                    }
+
                     // Send commit to user:
                     let payment_commit = PaymentCommit {
                         payment_id,
@@ -152,6 +543,55 @@ where
                         .await
                         .map_err(|_| CompactNodeError::UserSenderError)?;
                 }
+                (RequestResult::Failure, is_empty) if may_retry => {
+                    // The credits sent along this route did not arrive, and should be sent again:
+                    let payment_route = opt_payment_route.unwrap();
+                    sending.missing_dest_payment = sending
+                        .missing_dest_payment
+                        .checked_add(payment_route.dest_payment)
+                        .unwrap();
+                    sending.fees = sending.fees.saturating_sub(payment_route.fees);
+
+                    if is_empty {
+                        // No more pending transactions, we can retry:
+                        retry_payment(
+                            compact_state,
+                            payment_id,
+                            server_state,
+                            compact_gen,
+                            user_sender,
+                            app_sender,
+                        )
+                        .await?;
+                    } else {
+                        // Wait for the remaining transactions before retrying:
+                        update_send_compact_state(compact_state, server_state, user_sender).await?;
+                    }
+                }
+                (RequestResult::Success, true) if may_retry => {
+                    if sending.missing_dest_payment > 0 {
+                        retry_payment(
+                            compact_state,
+                            payment_id,
+                            server_state,
+                            compact_gen,
+                            user_sender,
+                            app_sender,
+                        )
+                        .await?;
+                    } else {
+                        // All transactions were successful, but we did not get a commit.
+                        fail_retry_payment(
+                            compact_state,
+                            payment_id,
+                            server_state,
+                            compact_gen,
+                            user_sender,
+                            app_sender,
+                        )
+                        .await?;
+                    }
+                }
                 (RequestResult::Failure, _) | (RequestResult::Success, true) => {
                     // Set payment as failed:
                     let ack_uid = compact_gen.gen_uid();
@@ -173,6 +613,7 @@ where
                 (RequestResult::Success, false) => {
                     // There are still pending transactions. We will have to wait for the next
                     // transactions to complete.
+                    update_send_compact_state(compact_state, server_state, user_sender).await?;
                 }
             };
         }
@@ -319,6 +760,31 @@ where
             }
         }
         AppServerToApp::ResponseRoutes(mut client_response_routes) => {
+            // Check if these routes were requested for retrying a payment:
+            let opt_retry_payment_id = server_state.compact_state().open_payments.iter().find_map(
+                |(payment_id, open_payment)| match &open_payment.status {
+                    OpenPaymentStatus::Sending(sending)
+                        if sending.opt_request_routes_id.as_ref()
+                            == Some(&client_response_routes.request_id) =>
+                    {
+                        Some(payment_id.clone())
+                    }
+                    _ => None,
+                },
+            );
+            if let Some(payment_id) = opt_retry_payment_id {
+                return send_retry_routes(
+                    server_state.compact_state().clone(),
+                    payment_id,
+                    client_response_routes,
+                    server_state,
+                    compact_gen,
+                    user_sender,
+                    app_sender,
+                )
+                .await;
+            }
+
             // Search for the corresponding OpenPayment:
             let mut compact_state = server_state.compact_state().clone();
             let mut opt_invoice_id_open_payment = None;
@@ -344,9 +810,12 @@ where
                     return Ok(());
                 };
 
-            let (multi_route, multi_route_choice, fees) = if let Some(inner) =
-                obtain_multi_route(&client_response_routes, open_payment.dest_payment)
-            {
+            let (multi_route, multi_route_choice, fees, auto_confirm) = if let Some(inner) =
+                obtain_multi_route(
+                    &client_response_routes,
+                    open_payment.dest_payment,
+                    open_payment.opt_fee_budget.as_ref(),
+                ) {
                 inner
             } else {
                 // A suitable route was not found.
@@ -371,6 +840,7 @@ where
 
             // Update compact state (keep the best multiroute):
             let confirm_id = compact_gen.gen_uid();
+            open_payment.routes = create_payment_routes(&multi_route, &multi_route_choice);
             let found_route = OpenPaymentStatusFoundRoute {
                 confirm_id: confirm_id.clone(),
                 multi_route,
@@ -382,7 +852,7 @@ where
 
             // Notify user that a route was found (Send required fees):
             let payment_fees = PaymentFees {
-                payment_id,
+                payment_id: payment_id.clone(),
                 response: PaymentFeesResponse::Fees(fees, confirm_id),
             };
             let compact_to_user = CompactToUser::PaymentFees(payment_fees);
@@ -390,7 +860,277 @@ where
                 .send(CompactToUserAck::CompactToUser(compact_to_user))
                 .await
                 .map_err(|_| CompactNodeError::UserSenderError)?;
+
+            if auto_confirm {
+                // Fees are within the budget, confirm automatically.
+                // `close_request_id` is not tracked, so no ack is sent to the user.
+                send_found_route(
+                    server_state.compact_state().clone(),
+                    payment_id,
+                    compact_gen.gen_uid(),
+                    server_state,
+                    compact_gen,
+                    user_sender,
+                    app_sender,
+                )
+                .await?;
+            }
+        }
+        AppServerToApp::InvoiceCommitted(commit) => {
+            let mut compact_state = server_state.compact_state().clone();
+            let open_invoice = if let Some(open_invoice) =
+                compact_state.open_invoices.remove(&commit.invoice_id)
+            {
+                open_invoice
+            } else {
+                // The invoice was not opened by us
+                return Ok(());
+            };
+            update_send_compact_state(compact_state, server_state, user_sender).await?;
+
+            // Notify the user about the settled invoice:
+            let invoice_committed = InvoiceCommitted {
+                invoice_id: commit.invoice_id,
+                currency: open_invoice.currency,
+                total_dest_payment: open_invoice.total_dest_payment,
+                description: open_invoice.description,
+            };
+            let compact_to_user = CompactToUser::InvoiceCommitted(invoice_committed);
+            user_sender
+                .send(CompactToUserAck::CompactToUser(compact_to_user))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
+        AppServerToApp::ResponseSignInvoice(signed_invoice) => {
+            let request_id = if let Some(request_id) = server_state
+                .pending_invoice_uris
+                .remove(&signed_invoice.invoice_id)
+            {
+                request_id
+            } else {
+                // We don't remember this request
+                warn!(
+                    "ResponseSignInvoice: Unrecognized invoice_id: {:?}",
+                    signed_invoice.invoice_id
+                );
+                return Ok(());
+            };
+
+            let response_create_invoice_uri = ResponseCreateInvoiceUri {
+                request_id,
+                status: CreateInvoiceUriStatus::Success(invoice_to_uri(&signed_invoice)),
+            };
+            let compact_to_user =
+                CompactToUser::ResponseCreateInvoiceUri(response_create_invoice_uri);
+            user_sender
+                .send(CompactToUserAck::CompactToUser(compact_to_user))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
+        // Verification requests are handled locally, and are never sent to the node:
+        AppServerToApp::ResponseVerifyReceipt(_) | AppServerToApp::ResponseVerifyFriendToken(_) => {
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use app::common::{Rate, RouteCapacityRate};
+
+    use crate::compact_node::messages::PaymentTransaction;
+
+    /// A helper function to create a test public key
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PublicKey::len()])
+    }
+
+    /// Create a MultiRoute with a single route, charging a constant fee
+    fn create_multi_route(i: u8, add: u32) -> MultiRoute {
+        MultiRoute {
+            routes: vec![RouteCapacityRate {
+                route: FriendsRoute {
+                    public_keys: vec![pk(0), pk(i), pk(100)],
+                },
+                capacity: 1000u128,
+                rate: Rate { add, mul: 0 },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_is_within_budget() {
+        let fee_budget = FeeBudget {
+            opt_max_fees: Some(10),
+            opt_max_fees_percent: None,
+        };
+        assert!(is_within_budget(&fee_budget, 100, 10));
+        assert!(!is_within_budget(&fee_budget, 100, 11));
+
+        let fee_budget = FeeBudget {
+            opt_max_fees: None,
+            opt_max_fees_percent: Some(5),
+        };
+        assert!(is_within_budget(&fee_budget, 100, 5));
+        assert!(!is_within_budget(&fee_budget, 100, 6));
+        assert!(!is_within_budget(&fee_budget, 100, u128::max_value()));
+
+        let fee_budget = FeeBudget {
+            opt_max_fees: Some(3),
+            opt_max_fees_percent: Some(5),
+        };
+        assert!(is_within_budget(&fee_budget, 100, 3));
+        assert!(!is_within_budget(&fee_budget, 100, 4));
+        assert!(!is_within_budget(&fee_budget, 20, 2));
+
+        let fee_budget = FeeBudget {
+            opt_max_fees: None,
+            opt_max_fees_percent: None,
+        };
+        assert!(is_within_budget(&fee_budget, 100, 1000));
+    }
+
+    #[test]
+    fn test_obtain_multi_route_fee_budget() {
+        let client_response_routes = ClientResponseRoutes {
+            request_id: Uid::from(&[0; Uid::len()]),
+            result: ResponseRoutesResult::Success(vec![
+                create_multi_route(1, 8),
+                create_multi_route(2, 4),
+                create_multi_route(3, 2),
+            ]),
+        };
+
+        // No budget: The first suitable MultiRoute is chosen:
+        let (multi_route, _, fees, auto_confirm) =
+            obtain_multi_route(&client_response_routes, 100, None).unwrap();
+        assert_eq!(multi_route, create_multi_route(1, 8));
+        assert_eq!(fees, 8);
+        assert!(!auto_confirm);
+
+        // The first MultiRoute inside the budget is chosen:
+        let fee_budget = FeeBudget {
+            opt_max_fees: Some(5),
+            opt_max_fees_percent: None,
+        };
+        let (multi_route, multi_route_choice, fees, auto_confirm) =
+            obtain_multi_route(&client_response_routes, 100, Some(&fee_budget)).unwrap();
+        assert_eq!(multi_route, create_multi_route(2, 4));
+        assert_eq!(fees, 4);
+        assert!(auto_confirm);
+
+        let payment_routes = create_payment_routes(&multi_route, &multi_route_choice);
+        assert_eq!(payment_routes.len(), 1);
+        assert_eq!(payment_routes[0].dest_payment, 100);
+        assert_eq!(payment_routes[0].fees, 4);
+
+        // No MultiRoute is inside the budget. We fall back to the first suitable MultiRoute:
+        let fee_budget = FeeBudget {
+            opt_max_fees: Some(1),
+            opt_max_fees_percent: None,
+        };
+        let (multi_route, _, fees, auto_confirm) =
+            obtain_multi_route(&client_response_routes, 100, Some(&fee_budget)).unwrap();
+        assert_eq!(multi_route, create_multi_route(1, 8));
+        assert_eq!(fees, 8);
+        assert!(!auto_confirm);
+
+        // Not enough capacity:
+        assert!(obtain_multi_route(&client_response_routes, 2000, None).is_none());
+    }
+
+    #[test]
+    fn test_is_deadline_passed() {
+        assert!(!is_deadline_passed(None));
+        assert!(is_deadline_passed(Some(0)));
+        assert!(!is_deadline_passed(Some(u64::max_value())));
+    }
+
+    #[test]
+    fn test_update_transaction_status() {
+        let multi_route = create_multi_route(1, 3);
+        let mut uid_counter = 0u8;
+        let mut gen_uid = || {
+            uid_counter += 1;
+            Uid::from(&[uid_counter; Uid::len()])
+        };
+        let transaction = |request_id: Uid| PaymentTransaction {
+            request_id,
+            route: create_payment_routes(&multi_route, &[(0, 10)])
+                .pop()
+                .unwrap(),
+            status: PaymentTransactionStatus::Pending,
+        };
+        let mut attempts = vec![
+            PaymentAttempt {
+                transactions: vec![transaction(gen_uid()), transaction(gen_uid())],
+            },
+            PaymentAttempt {
+                transactions: vec![transaction(gen_uid())],
+            },
+        ];
+
+        let payment_route = update_transaction_status(
+            &mut attempts,
+            &Uid::from(&[3; Uid::len()]),
+            PaymentTransactionStatus::Failure,
+        )
+        .unwrap();
+        assert_eq!(payment_route.dest_payment, 10);
+        assert_eq!(payment_route.fees, 3);
+        assert_eq!(
+            attempts[1].transactions[0].status,
+            PaymentTransactionStatus::Failure
+        );
+        assert_eq!(
+            attempts[0].transactions[1].status,
+            PaymentTransactionStatus::Pending
+        );
+
+        assert!(update_transaction_status(
+            &mut attempts,
+            &Uid::from(&[4; Uid::len()]),
+            PaymentTransactionStatus::Success,
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_failed_edge() {
+        let multi_route = create_multi_route(1, 3);
+        let transaction = |i: u8, status: PaymentTransactionStatus| PaymentTransaction {
+            request_id: Uid::from(&[i; Uid::len()]),
+            route: create_payment_routes(&multi_route, &[(0, 10)])
+                .pop()
+                .unwrap(),
+            status,
+        };
+
+        assert_eq!(failed_edge(&[]), None);
+
+        let mut attempts = vec![PaymentAttempt {
+            transactions: vec![
+                transaction(0, PaymentTransactionStatus::Failure),
+                transaction(1, PaymentTransactionStatus::Success),
+            ],
+        }];
+        assert_eq!(failed_edge(&attempts), Some((pk(1), pk(100))));
+
+        // Only the last attempt is considered:
+        attempts.push(PaymentAttempt {
+            transactions: vec![transaction(2, PaymentTransactionStatus::Pending)],
+        });
+        assert_eq!(failed_edge(&attempts), None);
+
+        // A direct route to the destination:
+        let mut payment_route = create_payment_routes(&multi_route, &[(0, 10)])
+            .pop()
+            .unwrap();
+        payment_route.route.public_keys = vec![pk(0), pk(100)];
+        attempts[1].transactions[0].route = payment_route;
+        attempts[1].transactions[0].status = PaymentTransactionStatus::Failure;
+        assert_eq!(failed_edge(&attempts), Some((pk(0), pk(100))));
+    }
+}
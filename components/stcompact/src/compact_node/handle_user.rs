@@ -1,21 +1,24 @@
 use futures::{Sink, SinkExt};
 
-use app::common::Uid;
-use app::conn::{buyer, config, routes, seller, AppPermissions, AppToAppServer};
-use app::verify::verify_commit;
+use app::common::RelayAddress;
+use app::conn::{buyer, config, now_secs, routes, seller, AppPermissions, AppToAppServer};
+use app::ser_utils::uri_to_invoice;
+use app::verify::{
+    verify_commit, verify_friend_token, verify_receipt_for_invoice, verify_signed_invoice,
+};
 
 // use crate::compact_node::create_compact_report;
 use crate::compact_node::messages::{
-    CompactToUser, CompactToUserAck, PaymentDone, PaymentDoneStatus, PaymentFees,
-    PaymentFeesResponse, ResponseVerifyCommit, UserToCompact, UserToCompactAck, VerifyCommitStatus,
-};
-use crate::compact_node::persist::{
-    OpenInvoice, OpenPayment, OpenPaymentStatus, OpenPaymentStatusSending,
+    CompactToUser, CompactToUserAck, CreateInvoiceUriStatus, ParseInvoiceUriStatus, ParsedInvoice,
+    PaymentDone, PaymentDoneStatus, PaymentFees, PaymentFeesResponse, ResponseCreateInvoiceUri,
+    ResponseParseInvoiceUri, ResponseVerifyCommit, ResponseVerifyFriendToken,
+    ResponseVerifyReceipt, UserToCompact, UserToCompactAck, VerifyCommitStatus,
 };
+use crate::compact_node::persist::{OpenInvoice, OpenPayment, OpenPaymentStatus};
 use crate::compact_node::types::{CompactNodeError, CompactServerState};
 use crate::gen::GenUid;
 
-use crate::compact_node::utils::update_send_compact_state;
+use crate::compact_node::utils::{send_found_route, update_send_compact_state};
 
 // TODO: Should we check permissions here in the future?
 // Permissions are already checked on the node side (offset-app-server). I don't want to have code duplication here for
@@ -335,6 +338,11 @@ where
                 description: init_payment.description,
                 generation: compact_state.generation.advance(),
                 status: OpenPaymentStatus::SearchingRoute(request_routes_id),
+                deliver_commit: init_payment.deliver_commit,
+                opt_fee_budget: init_payment.opt_fee_budget,
+                routes: Vec::new(),
+                opt_retry_policy: init_payment.opt_retry_policy,
+                attempts: Vec::new(),
             };
             compact_state
                 .open_payments
@@ -344,130 +352,47 @@ where
             // server_state.update_compact_state(compact_state).await?;
         }
         UserToCompact::ConfirmPaymentFees(confirm_payment_fees) => {
-            let mut compact_state = server_state.compact_state().clone();
-            let opt_inner = if let Some(open_payment) = compact_state
+            let compact_state = server_state.compact_state().clone();
+            let is_confirmed = if let Some(open_payment) = compact_state
                 .open_payments
                 .get(&confirm_payment_fees.payment_id)
             {
                 match &open_payment.status {
-                    OpenPaymentStatus::SearchingRoute(_) => None,
+                    OpenPaymentStatus::SearchingRoute(_) => false,
                     OpenPaymentStatus::FoundRoute(found_route) => {
-                        if confirm_payment_fees.confirm_id == found_route.confirm_id {
-                            // TODO: cloning here might not be the most efficient approach?
-                            Some((
-                                found_route.multi_route.clone(),
-                                found_route.multi_route_choice.clone(),
-                                found_route.fees,
-                            ))
-                        } else {
-                            // confirm_id doesn't match:
-                            None
-                        }
+                        // Make sure that confirm_id matches:
+                        confirm_payment_fees.confirm_id == found_route.confirm_id
                     }
                     OpenPaymentStatus::Sending(_)
                     | OpenPaymentStatus::Commit(_, _)
                     | OpenPaymentStatus::Success(_, _, _)
-                    | OpenPaymentStatus::Failure(_) => None,
+                    | OpenPaymentStatus::Failure(_) => false,
                 }
             } else {
                 // No such payment in progress.
-                None
+                false
             };
 
-            let (multi_route, multi_route_choice, fees) = if let Some(inner) = opt_inner {
-                inner
-            } else {
+            if !is_confirmed {
                 // Send acknowledgement to user:
                 return user_sender
                     .send(CompactToUserAck::Ack(user_request_id))
                     .await
                     .map_err(|_| CompactNodeError::UserSenderError);
-            };
-
-            // Order:
-            // - Update local database
-            // - Send requests along routes
-
-            // Update compact_state:
-            let open_transactions: Vec<Uid> = multi_route_choice
-                .iter()
-                .map(|_| compact_gen.gen_uid())
-                .collect();
-
-            let sending = OpenPaymentStatusSending {
-                fees,
-                open_transactions: open_transactions.clone().into_iter().collect(),
-            };
-
-            let open_payment = compact_state
-                .open_payments
-                .get_mut(&confirm_payment_fees.payment_id)
-                .unwrap();
-
-            open_payment.status = OpenPaymentStatus::Sending(sending);
-            let c_open_payment = open_payment.clone();
-
-            // server_state.update_compact_state(compact_state).await?;
-            update_send_compact_state(compact_state, server_state, user_sender).await?;
-
-            // Create a new payment:
-            let app_request = buyer::create_payment(
-                confirm_payment_fees.payment_id.clone(),
-                c_open_payment.invoice_id,
-                c_open_payment.currency,
-                c_open_payment.dest_payment,
-                c_open_payment.dest_public_key,
-            );
-
-            let app_to_app_server = AppToAppServer {
-                // This is an `app_request_id` we don't need to track:
-                app_request_id: compact_gen.gen_uid(),
-                app_request,
-            };
-            app_sender
-                .send(app_to_app_server)
-                .await
-                .map_err(|_| CompactNodeError::AppSenderError)?;
-
-            // Initiate requests along all routes in the multi route, where credits
-            // are allocated according to the strategy in `multi_route_choice`:
-            for ((route_index, dest_payment), request_id) in
-                multi_route_choice.iter().cloned().zip(open_transactions)
-            {
-                let route = &multi_route.routes[route_index];
-
-                let app_request = buyer::create_transaction(
-                    confirm_payment_fees.payment_id.clone(),
-                    request_id,
-                    route.route.clone(),
-                    dest_payment,
-                    route.rate.calc_fee(dest_payment).unwrap(),
-                );
-
-                let app_to_app_server = AppToAppServer {
-                    // We don't really care about app_request_id here, as we can wait on `request_id`
-                    // instead.
-                    app_request_id: compact_gen.gen_uid(),
-                    app_request,
-                };
-                app_sender
-                    .send(app_to_app_server)
-                    .await
-                    .map_err(|_| CompactNodeError::AppSenderError)?;
             }
 
-            // Send RequestClosePayment, as we are not going to send any more transactions:
-            let app_request = buyer::request_close_payment(confirm_payment_fees.payment_id.clone());
-            let app_to_app_server = AppToAppServer {
-                // We assign `user_request_id` here. This will provide the user with an ack for this
-                // request.
-                app_request_id: user_request_id,
-                app_request,
-            };
-            app_sender
-                .send(app_to_app_server)
-                .await
-                .map_err(|_| CompactNodeError::AppSenderError)?;
+            // We assign `user_request_id` here. This will provide the user with an ack for this
+            // request.
+            send_found_route(
+                compact_state,
+                confirm_payment_fees.payment_id,
+                user_request_id,
+                server_state,
+                compact_gen,
+                user_sender,
+                app_sender,
+            )
+            .await?;
         }
         UserToCompact::CancelPayment(payment_id) => {
             let mut compact_state = server_state.compact_state().clone();
@@ -486,12 +411,32 @@ where
                 OpenPaymentStatus::SearchingRoute(_)
                 | OpenPaymentStatus::FoundRoute(_)
                 | OpenPaymentStatus::Sending(_) => {
+                    // A payment that allows retries is not closed until we stop sending
+                    // transactions:
+                    let should_close = if let OpenPaymentStatus::Sending(_) = &open_payment.status {
+                        open_payment.opt_retry_policy.is_some()
+                    } else {
+                        false
+                    };
+
                     // Set failure status:
                     let ack_uid = compact_gen.gen_uid();
                     open_payment.status = OpenPaymentStatus::Failure(ack_uid.clone());
                     // server_state.update_compact_state(compact_state).await?;
                     update_send_compact_state(compact_state, server_state, user_sender).await?;
 
+                    if should_close {
+                        let app_request = buyer::request_close_payment(payment_id.clone());
+                        let app_to_app_server = AppToAppServer {
+                            app_request_id: compact_gen.gen_uid(),
+                            app_request,
+                        };
+                        app_sender
+                            .send(app_to_app_server)
+                            .await
+                            .map_err(|_| CompactNodeError::AppSenderError)?;
+                    }
+
                     // Send ack:
                     user_sender
                         .send(CompactToUserAck::Ack(user_request_id))
@@ -781,6 +726,160 @@ where
             // server_state.update_compact_state(compact_state).await?;
             update_send_compact_state(compact_state, server_state, user_sender).await?;
         }
+        // =======================[Invoice URIs]=================================
+        UserToCompact::RequestCreateInvoiceUri(request_create_invoice_uri) => {
+            // The node does not report back about signed invoices, so we ack right away:
+            server_state.pending_user_requests.remove(&user_request_id);
+            user_sender
+                .send(CompactToUserAck::Ack(user_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+
+            let open_invoice = if let Some(open_invoice) = server_state
+                .compact_state()
+                .open_invoices
+                .get(&request_create_invoice_uri.invoice_id)
+            {
+                open_invoice.clone()
+            } else {
+                // Invoice is not open. Respond with failure:
+                warn!(
+                    "RequestCreateInvoiceUri: Invoice {:?} is not open!",
+                    request_create_invoice_uri.invoice_id
+                );
+                let response_create_invoice_uri = ResponseCreateInvoiceUri {
+                    request_id: request_create_invoice_uri.request_id,
+                    status: CreateInvoiceUriStatus::Failure,
+                };
+                let compact_to_user =
+                    CompactToUser::ResponseCreateInvoiceUri(response_create_invoice_uri);
+                return user_sender
+                    .send(CompactToUserAck::CompactToUser(compact_to_user))
+                    .await
+                    .map_err(|_| CompactNodeError::UserSenderError);
+            };
+
+            // Let the buyer know how to contact us directly:
+            let relays = server_state
+                .node_report()
+                .funder_report
+                .relays
+                .iter()
+                .cloned()
+                .map(RelayAddress::from)
+                .collect();
+
+            // Remember the request, so that we can respond when the node sends the signed
+            // invoice:
+            server_state.pending_invoice_uris.insert(
+                request_create_invoice_uri.invoice_id.clone(),
+                request_create_invoice_uri.request_id,
+            );
+
+            let app_request = seller::sign_invoice(
+                request_create_invoice_uri.invoice_id,
+                open_invoice.currency,
+                open_invoice.total_dest_payment,
+                relays,
+                request_create_invoice_uri.valid_until,
+                open_invoice.description,
+            );
+            let app_to_app_server = AppToAppServer {
+                app_request_id: compact_gen.gen_uid(),
+                app_request,
+            };
+            app_sender
+                .send(app_to_app_server)
+                .await
+                .map_err(|_| CompactNodeError::AppSenderError)?;
+        }
+        UserToCompact::RequestParseInvoiceUri(request_parse_invoice_uri) => {
+            // Parsing is done locally, so we ack right away:
+            server_state.pending_user_requests.remove(&user_request_id);
+            user_sender
+                .send(CompactToUserAck::Ack(user_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+
+            let status = match uri_to_invoice(&request_parse_invoice_uri.invoice_uri) {
+                Ok(signed_invoice) if !verify_signed_invoice(&signed_invoice) => {
+                    ParseInvoiceUriStatus::Failure
+                }
+                Ok(signed_invoice) if signed_invoice.valid_until < now_secs() => {
+                    ParseInvoiceUriStatus::Expired
+                }
+                Ok(signed_invoice) => ParseInvoiceUriStatus::Success(ParsedInvoice {
+                    invoice_id: signed_invoice.invoice_id,
+                    currency: signed_invoice.currency,
+                    dest_public_key: signed_invoice.dest_public_key,
+                    dest_payment: signed_invoice.total_dest_payment,
+                    relays: signed_invoice.relays,
+                    valid_until: signed_invoice.valid_until,
+                    description: signed_invoice.description,
+                }),
+                Err(_) => ParseInvoiceUriStatus::Failure,
+            };
+
+            let response_parse_invoice_uri = ResponseParseInvoiceUri {
+                request_id: request_parse_invoice_uri.request_id,
+                status,
+            };
+            let compact_to_user =
+                CompactToUser::ResponseParseInvoiceUri(response_parse_invoice_uri);
+            user_sender
+                .send(CompactToUserAck::CompactToUser(compact_to_user))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
+        UserToCompact::RequestVerifyReceipt(request_verify_receipt) => {
+            // Verification is done locally, so we ack right away:
+            server_state.pending_user_requests.remove(&user_request_id);
+            user_sender
+                .send(CompactToUserAck::Ack(user_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+
+            let status = verify_receipt_for_invoice(
+                &request_verify_receipt.receipt,
+                &request_verify_receipt.dest_public_key,
+                &request_verify_receipt.invoice_id,
+                request_verify_receipt.total_dest_payment,
+            );
+
+            let response_verify_receipt = ResponseVerifyReceipt {
+                request_id: request_verify_receipt.request_id,
+                status: status.into(),
+            };
+            let compact_to_user = CompactToUser::ResponseVerifyReceipt(response_verify_receipt);
+            user_sender
+                .send(CompactToUserAck::CompactToUser(compact_to_user))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
+        UserToCompact::RequestVerifyFriendToken(request_verify_friend_token) => {
+            // Verification is done locally, so we ack right away:
+            server_state.pending_user_requests.remove(&user_request_id);
+            user_sender
+                .send(CompactToUserAck::Ack(user_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+
+            let status = verify_friend_token(
+                &request_verify_friend_token.move_token_hashed_report.into(),
+                &request_verify_friend_token.friend_public_key,
+            );
+
+            let response_verify_friend_token = ResponseVerifyFriendToken {
+                request_id: request_verify_friend_token.request_id,
+                status: status.into(),
+            };
+            let compact_to_user =
+                CompactToUser::ResponseVerifyFriendToken(response_verify_friend_token);
+            user_sender
+                .send(CompactToUserAck::CompactToUser(compact_to_user))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
     }
     Ok(())
 }
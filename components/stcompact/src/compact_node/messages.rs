@@ -3,11 +3,13 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
 use app::common::{
-    Currency, HashResult, HashedLock, InvoiceId, NamedIndexServerAddress, NamedRelayAddress,
-    PaymentId, PlainLock, PublicKey, RandValue, Rate, Receipt, RelayAddress, Signature, Uid,
+    Currency, FriendsRoute, HashResult, HashedLock, InvoiceId, NamedIndexServerAddress,
+    NamedRelayAddress, PaymentId, PlainLock, PublicKey, RandValue, Rate, Receipt, RelayAddress,
+    Signature, Uid,
 };
 use common::ser_utils::{
-    ser_b64, ser_map_b64_any, ser_map_str_any, ser_map_str_str, ser_option_b64, ser_string,
+    ser_b64, ser_map_b64_any, ser_map_str_any, ser_map_str_str, ser_option_b64, ser_option_string,
+    ser_string,
 };
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -92,6 +94,33 @@ pub struct SetFriendName {
     pub name: String,
 }
 
+/// Maximum fees we are willing to pay for a payment without an explicit confirmation.
+/// If both limits are specified, both must be satisfied.
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeBudget {
+    /// Maximum total fees
+    #[serde(default)]
+    #[serde(with = "ser_option_string")]
+    pub opt_max_fees: Option<u128>,
+    /// Maximum total fees, as a percentage of the paid amount
+    #[serde(default)]
+    pub opt_max_fees_percent: Option<u32>,
+}
+
+/// Automatic retries of failed transactions of a payment.
+/// Credits that failed to arrive are sent again along other routes.
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryPolicy {
+    /// Maximum amount of retries
+    pub max_retries: u32,
+    /// Do not retry after this time (Seconds since the Unix epoch)
+    #[serde(default)]
+    #[serde(with = "ser_option_string")]
+    pub opt_deadline: Option<u64>,
+}
+
 #[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InitPayment {
@@ -107,6 +136,17 @@ pub struct InitPayment {
     pub dest_payment: u128,
     /// Short textual invoice description
     pub description: String,
+    /// Deliver the commit to the seller through the network, instead of out of band.
+    #[serde(default)]
+    pub deliver_commit: bool,
+    /// Confirm fees automatically if they are within the given budget.
+    /// If not specified, fees must be confirmed using `ConfirmPaymentFees`.
+    #[serde(default)]
+    pub opt_fee_budget: Option<FeeBudget>,
+    /// Retry failed transactions along other routes.
+    /// Fees of retries are checked against `opt_fee_budget`, if specified.
+    #[serde(default)]
+    pub opt_retry_policy: Option<RetryPolicy>,
 }
 
 #[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -173,6 +213,145 @@ pub enum VerifyCommitStatus {
     Success,
 }
 
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestVerifyReceipt {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub receipt: Receipt,
+    /// The seller node, expected to have signed the receipt
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseVerifyReceipt {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub status: VerifyReceiptStatus,
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum VerifyReceiptStatus {
+    Valid,
+    /// The receipt does not match the given invoice id or total payment
+    InvoiceMismatch,
+    /// The receipt was not signed by `dest_public_key`
+    WrongSigner,
+}
+
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestVerifyFriendToken {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub move_token_hashed_report: MoveTokenHashedReport,
+    /// The friend expected to have signed the token
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseVerifyFriendToken {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub status: VerifyFriendTokenStatus,
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum VerifyFriendTokenStatus {
+    Valid,
+    /// The token was signed by someone other than `friend_public_key`
+    WrongSigner,
+    InvalidSignature,
+}
+
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestCreateInvoiceUri {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    /// An open invoice
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    /// Expiry of the invoice (Seconds since the Unix epoch)
+    #[serde(with = "ser_string")]
+    pub valid_until: u64,
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum CreateInvoiceUriStatus {
+    Failure,
+    /// A signed invoice URI (`offset:...`), can be sent to the buyer (For example, as a QR code)
+    Success(String),
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCreateInvoiceUri {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub status: CreateInvoiceUriStatus,
+}
+
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestParseInvoiceUri {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub invoice_uri: String,
+}
+
+/// Contents of a signed invoice URI.
+/// Contains all the information required for `InitPayment`.
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ParsedInvoice {
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    /// Relays of the seller, allowing direct contact with the seller
+    pub relays: Vec<RelayAddress>,
+    /// Expiry of the invoice (Seconds since the Unix epoch)
+    #[serde(with = "ser_string")]
+    pub valid_until: u64,
+    /// Short textual invoice description
+    pub description: String,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum ParseInvoiceUriStatus {
+    /// Malformed URI, or invalid signature
+    Failure,
+    /// The invoice is no longer valid (`valid_until` has passed)
+    Expired,
+    Success(ParsedInvoice),
+}
+
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseParseInvoiceUri {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub status: ParseInvoiceUriStatus,
+}
+
 #[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PaymentCommit {
@@ -181,6 +360,20 @@ pub struct PaymentCommit {
     pub commit: Commit,
 }
 
+/// An open invoice was paid, and committed by the node using a commit delivered by the buyer.
+#[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InvoiceCommitted {
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+    /// Invoice description
+    pub description: String,
+}
+
 #[derive(Arbitrary, Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ConfirmPaymentFees {
@@ -427,6 +620,44 @@ pub enum OpenPaymentStatus {
     Failure(#[serde(with = "ser_b64")] Uid),        // ack_uid
 }
 
+/// A route chosen for a payment, and the fees paid along it
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentRoute {
+    pub route: FriendsRoute,
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+}
+
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PaymentTransactionStatus {
+    Pending,
+    Success,
+    Failure,
+}
+
+/// A transaction sent as part of a payment
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentTransaction {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub route: PaymentRoute,
+    pub status: PaymentTransactionStatus,
+}
+
+/// An attempt to send credits for a payment.
+/// The first attempt sends the full amount, and every retry sends the amount that failed to
+/// arrive.
+#[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentAttempt {
+    pub transactions: Vec<PaymentTransaction>,
+}
+
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct OpenPayment {
@@ -444,6 +675,14 @@ pub struct OpenPayment {
     pub generation: Generation,
     /// Current status of open payment
     pub status: OpenPaymentStatus,
+    /// Fee budget for automatic confirmation
+    pub opt_fee_budget: Option<FeeBudget>,
+    /// Routes chosen for this payment (Empty if no route was found yet)
+    pub routes: Vec<PaymentRoute>,
+    /// Retry policy for failed transactions
+    pub opt_retry_policy: Option<RetryPolicy>,
+    /// Attempts of sending credits, in chronological order
+    pub attempts: Vec<PaymentAttempt>,
 }
 
 #[derive(Arbitrary, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -484,15 +723,24 @@ pub enum CompactToUser {
     // ------------[Buyer]------------------
     /// Response: Shows required fees, or states that the destination is unreachable:
     PaymentFees(PaymentFees),
-    /// Result: Possibly returns the Commit (Should be delivered out of band)
+    /// Result: Possibly returns the Commit
+    /// (Should be delivered out of band, unless it was delivered through the network)
     PaymentCommit(PaymentCommit),
     /// Done: Possibly returns a Receipt or failure
     PaymentDone(PaymentDone),
+    // ------------[Seller]-----------------
+    /// An invoice was committed automatically (The commit was delivered through the network)
+    InvoiceCommitted(InvoiceCommitted),
     // ------------[Reports]-------------------
     /// Reports about current state:
     Report(CompactReport),
     // -------------[Verify]-------------------
     ResponseVerifyCommit(ResponseVerifyCommit),
+    ResponseVerifyReceipt(ResponseVerifyReceipt),
+    ResponseVerifyFriendToken(ResponseVerifyFriendToken),
+    // -------------[Invoice URIs]-------------
+    ResponseCreateInvoiceUri(ResponseCreateInvoiceUri),
+    ResponseParseInvoiceUri(ResponseParseInvoiceUri),
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -542,8 +790,14 @@ pub enum UserToCompact {
     RequestVerifyCommit(RequestVerifyCommit),
     #[serde(with = "ser_b64")]
     CommitInvoice(InvoiceId),
+    // ---------------[Invoice URIs]------------------------
+    /// Create a signed invoice URI for an open invoice (Seller):
+    RequestCreateInvoiceUri(RequestCreateInvoiceUri),
+    /// Parse and verify a signed invoice URI (Buyer):
+    RequestParseInvoiceUri(RequestParseInvoiceUri),
     // ---------------[Verification]------------------------
-    // TODO: Add API for verification of receipt and last token?
+    RequestVerifyReceipt(RequestVerifyReceipt),
+    RequestVerifyFriendToken(RequestVerifyFriendToken),
 }
 
 #[derive(Arbitrary, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -1,10 +1,80 @@
-use app::conn::AppPermissions;
+use app::conn::{now_secs, AppPermissions, AppRestrictions, ConfigPermission};
 
 use crate::compact_node::messages::UserToCompact;
 
+/// The group of configuration operations a user request belongs to.
+/// Returns None if the request does not change the configuration of the node.
+fn config_permission(user_request: &UserToCompact) -> Option<ConfigPermission> {
+    match user_request {
+        UserToCompact::AddRelay(_) | UserToCompact::RemoveRelay(_) => {
+            Some(ConfigPermission::Relays)
+        }
+        UserToCompact::AddIndexServer(_) | UserToCompact::RemoveIndexServer(_) => {
+            Some(ConfigPermission::IndexServers)
+        }
+        UserToCompact::AddFriend(_)
+        | UserToCompact::SetFriendRelays(_)
+        | UserToCompact::SetFriendName(_)
+        | UserToCompact::RemoveFriend(_)
+        | UserToCompact::EnableFriend(_)
+        | UserToCompact::DisableFriend(_)
+        | UserToCompact::ResetFriendChannel(_) => Some(ConfigPermission::Friends),
+        UserToCompact::OpenFriendCurrency(_)
+        | UserToCompact::CloseFriendCurrency(_)
+        | UserToCompact::SetFriendCurrencyMaxDebt(_)
+        | UserToCompact::SetFriendCurrencyRate(_)
+        | UserToCompact::RemoveFriendCurrency(_) => Some(ConfigPermission::FriendCurrencies),
+        UserToCompact::InitPayment(_)
+        | UserToCompact::ConfirmPaymentFees(_)
+        | UserToCompact::CancelPayment(_)
+        | UserToCompact::AckPaymentDone(_, _)
+        | UserToCompact::AddInvoice(_)
+        | UserToCompact::CancelInvoice(_)
+        | UserToCompact::CommitInvoice(_)
+        | UserToCompact::RequestCreateInvoiceUri(_)
+        | UserToCompact::RequestVerifyCommit(_)
+        | UserToCompact::RequestParseInvoiceUri(_)
+        | UserToCompact::RequestVerifyReceipt(_)
+        | UserToCompact::RequestVerifyFriendToken(_) => None,
+    }
+}
+
+/// Check the finer grained restrictions of an app.
+/// Spending over time (Including fees) is charged when transactions are sent, see
+/// `CompactServerState::charge_payment_attempt()`. Here we only reject a single payment that
+/// exceeds the spending limit even without fees.
+fn check_restrictions(
+    user_request: &UserToCompact,
+    restrictions: &AppRestrictions,
+    now: u64,
+) -> bool {
+    if restrictions.is_expired(now) {
+        return false;
+    }
+
+    if let Some(config_permission) = config_permission(user_request) {
+        if !restrictions.is_config_allowed(&config_permission) {
+            return false;
+        }
+    }
+
+    if let UserToCompact::InitPayment(init_payment) = user_request {
+        if !restrictions.is_destination_allowed(&init_payment.dest_public_key) {
+            return false;
+        }
+        if let Some(spending_limit) = restrictions.spending_limit(&init_payment.currency) {
+            if init_payment.dest_payment > spending_limit.max_amount {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
 /// Check if an app is allowed to send a certain user request
 pub fn check_permission(user_request: &UserToCompact, app_permissions: &AppPermissions) -> bool {
-    match user_request {
+    let allowed = match user_request {
         UserToCompact::AddRelay(_)
         | UserToCompact::RemoveRelay(_)
         | UserToCompact::AddIndexServer(_)
@@ -27,7 +97,13 @@ pub fn check_permission(user_request: &UserToCompact, app_permissions: &AppPermi
         | UserToCompact::AckPaymentDone(_, _) => app_permissions.buyer,
         UserToCompact::AddInvoice(_)
         | UserToCompact::CancelInvoice(_)
-        | UserToCompact::CommitInvoice(_) => app_permissions.seller,
-        UserToCompact::RequestVerifyCommit(_) => true,
-    }
+        | UserToCompact::CommitInvoice(_)
+        | UserToCompact::RequestCreateInvoiceUri(_) => app_permissions.seller,
+        UserToCompact::RequestVerifyCommit(_)
+        | UserToCompact::RequestParseInvoiceUri(_)
+        | UserToCompact::RequestVerifyReceipt(_)
+        | UserToCompact::RequestVerifyFriendToken(_) => true,
+    };
+
+    allowed && check_restrictions(user_request, &app_permissions.restrictions, now_secs())
 }
@@ -4,13 +4,15 @@ use serde::{Deserialize, Serialize};
 
 use common::mutable_state::MutableState;
 use common::never::Never;
-use common::ser_utils::{ser_b64, ser_map_b64_any, ser_string};
+use common::ser_utils::{ser_b64, ser_map_b64_any, ser_option_b64, ser_string};
 
 use app::common::{Commit, Currency, InvoiceId, MultiRoute, PaymentId, PublicKey, Receipt, Uid};
 
 use route::MultiRouteChoice;
 
-use crate::compact_node::messages::Generation;
+use crate::compact_node::messages::{
+    FeeBudget, Generation, PaymentAttempt, PaymentRoute, RetryPolicy,
+};
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -33,6 +35,14 @@ pub struct OpenPaymentStatusSending {
     #[serde(with = "ser_string")]
     pub fees: u128,
     pub open_transactions: HashSet<Uid>,
+    /// Amount of credits that failed to arrive, and should be sent again
+    #[serde(default)]
+    #[serde(with = "ser_string")]
+    pub missing_dest_payment: u128,
+    /// Routes request for retrying the missing amount, if in progress
+    #[serde(default)]
+    #[serde(with = "ser_option_b64")]
+    pub opt_request_routes_id: Option<Uid>,
 }
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -76,6 +86,21 @@ pub struct OpenPayment {
     pub generation: Generation,
     /// Current status of open payment
     pub status: OpenPaymentStatus,
+    /// Deliver the commit to the seller through the network
+    #[serde(default)]
+    pub deliver_commit: bool,
+    /// Fee budget for automatic confirmation
+    #[serde(default)]
+    pub opt_fee_budget: Option<FeeBudget>,
+    /// Routes chosen for this payment, together with their fees
+    #[serde(default)]
+    pub routes: Vec<PaymentRoute>,
+    /// Retry policy for failed transactions
+    #[serde(default)]
+    pub opt_retry_policy: Option<RetryPolicy>,
+    /// Attempts of sending credits, in chronological order
+    #[serde(default)]
+    pub attempts: Vec<PaymentAttempt>,
 }
 
 #[derive(Arbitrary, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
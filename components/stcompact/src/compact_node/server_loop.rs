@@ -45,7 +45,12 @@ where
 
     let mut incoming_events = select_streams![user_receiver, app_receiver];
 
-    let mut server_state = CompactServerState::new(node_report, compact_state, database_client);
+    let mut server_state = CompactServerState::new(
+        node_report,
+        compact_state,
+        database_client,
+        app_permissions.restrictions.clone(),
+    );
 
     while let Some(event) = incoming_events.next().await {
         match event {
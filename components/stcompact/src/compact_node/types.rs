@@ -1,12 +1,13 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use common::conn::ConnPair;
 
-use app::common::Uid;
-use app::conn::AppServerToApp;
+use app::common::{Currency, InvoiceId, Uid};
+use app::conn::{now_secs, AppRestrictions, AppServerToApp};
+use app_server::{SpendingCharge, SpendingTracker};
 use database::DatabaseClient;
 
-use crate::compact_node::messages::{CompactToUserAck, UserToCompactAck};
+use crate::compact_node::messages::{CompactToUserAck, PaymentAttempt, UserToCompactAck};
 use crate::compact_node::persist::CompactState;
 
 pub type ConnPairCompact = ConnPair<CompactToUserAck, UserToCompactAck>;
@@ -35,6 +36,16 @@ pub struct CompactServerState {
     /// Ids of requests that were initiated directly by the user,
     /// and were not acked yet.
     pub pending_user_requests: HashSet<Uid>,
+    /// Invoices the node was asked to sign, mapped to the `request_id` of the user's
+    /// `RequestCreateInvoiceUri`.
+    pub pending_invoice_uris: HashMap<InvoiceId, Uid>,
+    /// Restrictions the node has set for the compact server
+    app_restrictions: AppRestrictions,
+    /// Credits spent by the compact server. All the users of the compact server share the same
+    /// spending limits.
+    spending_tracker: SpendingTracker,
+    /// Charges of transactions that are still in progress, by `request_id`
+    transaction_charges: HashMap<Uid, SpendingCharge>,
 }
 
 impl CompactServerState {
@@ -42,12 +53,17 @@ impl CompactServerState {
         node_report: app::report::NodeReport,
         compact_state: CompactState,
         database_client: DatabaseClient<CompactState>,
+        app_restrictions: AppRestrictions,
     ) -> Self {
         Self {
             node_report,
             compact_state,
             database_client,
             pending_user_requests: HashSet::new(),
+            pending_invoice_uris: HashMap::new(),
+            app_restrictions,
+            spending_tracker: SpendingTracker::new(),
+            transaction_charges: HashMap::new(),
         }
     }
 
@@ -77,4 +93,54 @@ impl CompactServerState {
             .map_err(|_| CompactNodeError::DatabaseMutateError)?;
         Ok(())
     }
+
+    /// Charge the transactions of a payment attempt (Including fees) against the spending limit
+    /// of `currency`, if there is any.
+    /// Returns false if the attempt exceeds the limit. In that case nothing is charged.
+    pub fn charge_payment_attempt(
+        &mut self,
+        currency: &Currency,
+        payment_attempt: &PaymentAttempt,
+    ) -> bool {
+        let spending_limit = match self.app_restrictions.spending_limit(currency) {
+            Some(spending_limit) => spending_limit.clone(),
+            None => return true,
+        };
+        let local_public_key = self.node_report.funder_report.local_public_key.clone();
+        let now = now_secs();
+
+        let mut charges = Vec::new();
+        for transaction in &payment_attempt.transactions {
+            let opt_charge = transaction
+                .route
+                .dest_payment
+                .checked_add(transaction.route.fees)
+                .and_then(|amount| {
+                    self.spending_tracker
+                        .charge(&local_public_key, &spending_limit, amount, now)
+                });
+            match opt_charge {
+                Some(charge) => charges.push((transaction.request_id.clone(), charge)),
+                None => {
+                    // Undo the charges of the previous transactions of this attempt:
+                    for (_request_id, charge) in charges {
+                        self.spending_tracker.refund(charge);
+                    }
+                    return false;
+                }
+            }
+        }
+        self.transaction_charges.extend(charges);
+        true
+    }
+
+    /// Settle the charge of a transaction once its result is known.
+    /// Failed transactions do not count towards the spending limits.
+    pub fn settle_transaction_charge(&mut self, request_id: &Uid, is_success: bool) {
+        if let Some(charge) = self.transaction_charges.remove(request_id) {
+            if !is_success {
+                self.spending_tracker.refund(charge);
+            }
+        }
+    }
 }
@@ -1,9 +1,16 @@
 use futures::{Sink, SinkExt};
 
+use app::common::{MultiRoute, PaymentId, Uid};
+use app::conn::{buyer, AppToAppServer};
+
 use crate::compact_node::create_compact_report;
-use crate::compact_node::messages::{CompactToUser, CompactToUserAck};
-use crate::compact_node::persist::CompactState;
+use crate::compact_node::messages::{
+    CompactToUser, CompactToUserAck, PaymentAttempt, PaymentDone, PaymentDoneStatus, PaymentRoute,
+    PaymentTransaction, PaymentTransactionStatus,
+};
+use crate::compact_node::persist::{CompactState, OpenPaymentStatus, OpenPaymentStatusSending};
 use crate::compact_node::types::{CompactNodeError, CompactServerState};
+use crate::gen::GenUid;
 
 /// Update compact state, and send compact report to user if necessary
 pub async fn update_send_compact_state<US>(
@@ -30,3 +37,192 @@ where
     }
     Ok(())
 }
+
+/// Create a new attempt of sending credits along the routes of a MultiRoute,
+/// where credits are allocated according to the strategy in `multi_route_choice`.
+pub fn create_payment_attempt<CG>(
+    multi_route: &MultiRoute,
+    multi_route_choice: &[(usize, u128)],
+    compact_gen: &mut CG,
+) -> PaymentAttempt
+where
+    CG: GenUid,
+{
+    let transactions = multi_route_choice
+        .iter()
+        .map(|(route_index, dest_payment)| {
+            let route = &multi_route.routes[*route_index];
+            PaymentTransaction {
+                request_id: compact_gen.gen_uid(),
+                route: PaymentRoute {
+                    route: route.route.clone(),
+                    dest_payment: *dest_payment,
+                    // Fees were already checked when the MultiRoute was chosen:
+                    fees: route.rate.calc_fee(*dest_payment).unwrap(),
+                },
+                status: PaymentTransactionStatus::Pending,
+            }
+        })
+        .collect();
+
+    PaymentAttempt { transactions }
+}
+
+/// Initiate requests along all the routes of a payment attempt
+pub async fn send_payment_attempt<CG, AS>(
+    payment_id: &PaymentId,
+    payment_attempt: &PaymentAttempt,
+    compact_gen: &mut CG,
+    app_sender: &mut AS,
+) -> Result<(), CompactNodeError>
+where
+    CG: GenUid,
+    AS: Sink<AppToAppServer> + Unpin,
+{
+    for transaction in &payment_attempt.transactions {
+        let app_request = buyer::create_transaction(
+            payment_id.clone(),
+            transaction.request_id.clone(),
+            transaction.route.route.clone(),
+            transaction.route.dest_payment,
+            transaction.route.fees,
+        );
+
+        let app_to_app_server = AppToAppServer {
+            // We don't really care about app_request_id here, as we can wait on `request_id`
+            // instead.
+            app_request_id: compact_gen.gen_uid(),
+            app_request,
+        };
+        app_sender
+            .send(app_to_app_server)
+            .await
+            .map_err(|_| CompactNodeError::AppSenderError)?;
+    }
+    Ok(())
+}
+
+/// Start sending credits along the route found for an open payment.
+/// The open payment must be in the `FoundRoute` state.
+/// `ack_request_id` is used as the `app_request_id` of the last request we send for this
+/// payment, which allows acking the user.
+pub async fn send_found_route<CG, US, AS>(
+    mut compact_state: CompactState,
+    payment_id: PaymentId,
+    ack_request_id: Uid,
+    server_state: &mut CompactServerState,
+    compact_gen: &mut CG,
+    user_sender: &mut US,
+    app_sender: &mut AS,
+) -> Result<(), CompactNodeError>
+where
+    CG: GenUid,
+    US: Sink<CompactToUserAck> + Unpin,
+    AS: Sink<AppToAppServer> + Unpin,
+{
+    let open_payment = compact_state.open_payments.get_mut(&payment_id).unwrap();
+    let found_route = if let OpenPaymentStatus::FoundRoute(found_route) = &open_payment.status {
+        found_route.clone()
+    } else {
+        unreachable!();
+    };
+
+    // Order:
+    // - Update local database
+    // - Send requests along routes
+
+    // Update compact_state:
+    let payment_attempt = create_payment_attempt(
+        &found_route.multi_route,
+        &found_route.multi_route_choice,
+        compact_gen,
+    );
+
+    if !server_state.charge_payment_attempt(&open_payment.currency, &payment_attempt) {
+        // Sending the payment would exceed our spending limit.
+        // The payment was not created on the node yet, so there is nothing to close.
+        let ack_uid = compact_gen.gen_uid();
+        open_payment.status = OpenPaymentStatus::Failure(ack_uid.clone());
+        update_send_compact_state(compact_state, server_state, user_sender).await?;
+
+        let payment_done = PaymentDone {
+            payment_id,
+            status: PaymentDoneStatus::Failure(ack_uid),
+        };
+        let compact_to_user = CompactToUser::PaymentDone(payment_done);
+        user_sender
+            .send(CompactToUserAck::CompactToUser(compact_to_user))
+            .await
+            .map_err(|_| CompactNodeError::UserSenderError)?;
+
+        // We only ack if the `request_id` arrived from the user itself:
+        if server_state.pending_user_requests.remove(&ack_request_id) {
+            user_sender
+                .send(CompactToUserAck::Ack(ack_request_id))
+                .await
+                .map_err(|_| CompactNodeError::UserSenderError)?;
+        }
+        return Ok(());
+    }
+
+    let sending = OpenPaymentStatusSending {
+        fees: found_route.fees,
+        open_transactions: payment_attempt
+            .transactions
+            .iter()
+            .map(|transaction| transaction.request_id.clone())
+            .collect(),
+        missing_dest_payment: 0,
+        opt_request_routes_id: None,
+    };
+
+    open_payment.status = OpenPaymentStatus::Sending(sending);
+    open_payment.attempts.push(payment_attempt.clone());
+    let c_open_payment = open_payment.clone();
+
+    update_send_compact_state(compact_state, server_state, user_sender).await?;
+
+    // If we might retry, we can not close the payment before all the credits have arrived.
+    // In that case the user is acked through the payment creation.
+    let may_retry = c_open_payment.opt_retry_policy.is_some();
+
+    // Create a new payment:
+    let app_request = buyer::create_payment(
+        payment_id.clone(),
+        c_open_payment.invoice_id,
+        c_open_payment.currency,
+        c_open_payment.dest_payment,
+        c_open_payment.dest_public_key,
+    );
+
+    let app_to_app_server = AppToAppServer {
+        app_request_id: if may_retry {
+            ack_request_id.clone()
+        } else {
+            // This is an `app_request_id` we don't need to track:
+            compact_gen.gen_uid()
+        },
+        app_request,
+    };
+    app_sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| CompactNodeError::AppSenderError)?;
+
+    send_payment_attempt(&payment_id, &payment_attempt, compact_gen, app_sender).await?;
+
+    if may_retry {
+        return Ok(());
+    }
+
+    // Send RequestClosePayment, as we are not going to send any more transactions:
+    let app_request = buyer::request_close_payment(payment_id);
+    let app_to_app_server = AppToAppServer {
+        app_request_id: ack_request_id,
+        app_request,
+    };
+    app_sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| CompactNodeError::AppSenderError)
+}
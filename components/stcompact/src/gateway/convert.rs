@@ -0,0 +1,275 @@
+use app::common::PublicKey;
+use app::conn::{buyer, config, routes, seller, AppRequest};
+use app::report::NodeReport;
+
+use proto::app_server::messages::NodeReportMutation;
+use proto::index_client::messages::IndexClientReportMutation;
+use proto::report::messages::FunderReportMutation;
+
+use crate::gateway::messages::{
+    GatewayRequest, PaymentStatus, PaymentStatusSuccess, ReportMutation, RequestResult,
+    ResponseClosePayment, ResponseRoutes, ResponseRoutesResult, TransactionResult, UpdateFriend,
+};
+
+impl From<GatewayRequest> for AppRequest {
+    fn from(from: GatewayRequest) -> Self {
+        match from {
+            GatewayRequest::AddRelay(named_relay_address) => config::add_relay(named_relay_address),
+            GatewayRequest::RemoveRelay(relay_public_key) => config::remove_relay(relay_public_key),
+            GatewayRequest::AddFriend(add_friend) => config::add_friend(
+                add_friend.friend_public_key,
+                add_friend.relays,
+                add_friend.name,
+            ),
+            GatewayRequest::SetFriendRelays(set_friend_relays) => config::set_friend_relays(
+                set_friend_relays.friend_public_key,
+                set_friend_relays.relays,
+            ),
+            GatewayRequest::SetFriendName(set_friend_name) => {
+                config::set_friend_name(set_friend_name.friend_public_key, set_friend_name.name)
+            }
+            GatewayRequest::RemoveFriend(friend_public_key) => {
+                config::remove_friend(friend_public_key)
+            }
+            GatewayRequest::EnableFriend(friend_public_key) => {
+                config::enable_friend(friend_public_key)
+            }
+            GatewayRequest::DisableFriend(friend_public_key) => {
+                config::disable_friend(friend_public_key)
+            }
+            GatewayRequest::OpenFriendCurrency(open_friend_currency) => {
+                config::open_friend_currency(
+                    open_friend_currency.friend_public_key,
+                    open_friend_currency.currency,
+                )
+            }
+            GatewayRequest::CloseFriendCurrency(close_friend_currency) => {
+                config::close_friend_currency(
+                    close_friend_currency.friend_public_key,
+                    close_friend_currency.currency,
+                )
+            }
+            GatewayRequest::SetFriendCurrencyMaxDebt(set_friend_currency_max_debt) => {
+                config::set_friend_currency_max_debt(
+                    set_friend_currency_max_debt.friend_public_key,
+                    set_friend_currency_max_debt.currency,
+                    set_friend_currency_max_debt.remote_max_debt,
+                )
+            }
+            GatewayRequest::SetFriendCurrencyRate(set_friend_currency_rate) => {
+                config::set_friend_currency_rate(
+                    set_friend_currency_rate.friend_public_key,
+                    set_friend_currency_rate.currency,
+                    set_friend_currency_rate.rate,
+                )
+            }
+            GatewayRequest::RemoveFriendCurrency(remove_friend_currency) => {
+                config::remove_friend_currency(
+                    remove_friend_currency.friend_public_key,
+                    remove_friend_currency.currency,
+                )
+            }
+            GatewayRequest::ResetFriendChannel(reset_friend_channel) => {
+                config::reset_friend_channel(
+                    reset_friend_channel.friend_public_key,
+                    reset_friend_channel.reset_token,
+                )
+            }
+            GatewayRequest::SetFriendCurrencyFeePolicy(set_friend_currency_fee_policy) => {
+                config::set_friend_currency_fee_policy(
+                    set_friend_currency_fee_policy.friend_public_key,
+                    set_friend_currency_fee_policy.currency,
+                    set_friend_currency_fee_policy.opt_fee_policy,
+                )
+            }
+            GatewayRequest::SetFriendCurrencyRebalancePolicy(
+                set_friend_currency_rebalance_policy,
+            ) => config::set_friend_currency_rebalance_policy(
+                set_friend_currency_rebalance_policy.friend_public_key,
+                set_friend_currency_rebalance_policy.currency,
+                set_friend_currency_rebalance_policy.opt_rebalance_policy,
+            ),
+            GatewayRequest::SettleFriend(friend_public_key) => {
+                config::settle_friend(friend_public_key)
+            }
+            GatewayRequest::AddIndexServer(named_index_server_address) => {
+                config::add_index_server(named_index_server_address)
+            }
+            GatewayRequest::RemoveIndexServer(index_public_key) => {
+                config::remove_index_server(index_public_key)
+            }
+            GatewayRequest::SetExchangeRate(exchange_rate) => config::set_exchange_rate(
+                exchange_rate.currency_pair.src_currency,
+                exchange_rate.currency_pair.dest_currency,
+                exchange_rate.mul,
+                exchange_rate.valid_from,
+                exchange_rate.valid_until,
+            ),
+            GatewayRequest::RemoveExchangeRate(currency_pair) => config::remove_exchange_rate(
+                currency_pair.src_currency,
+                currency_pair.dest_currency,
+            ),
+            GatewayRequest::CreatePayment(create_payment) => buyer::create_payment(
+                create_payment.payment_id,
+                create_payment.invoice_id,
+                create_payment.currency,
+                create_payment.total_dest_payment,
+                create_payment.dest_public_key,
+            ),
+            GatewayRequest::CreateTransaction(create_transaction) => buyer::create_transaction(
+                create_transaction.payment_id,
+                create_transaction.request_id,
+                create_transaction.route,
+                create_transaction.dest_payment,
+                create_transaction.fees,
+            ),
+            GatewayRequest::RequestClosePayment(payment_id) => {
+                buyer::request_close_payment(payment_id)
+            }
+            GatewayRequest::AckClosePayment(ack_close_payment) => {
+                buyer::ack_close_payment(ack_close_payment.payment_id, ack_close_payment.ack_uid)
+            }
+            GatewayRequest::DeliverCommit(deliver_commit) => {
+                buyer::deliver_commit(deliver_commit.request_id, deliver_commit.commit.into())
+            }
+            GatewayRequest::AddInvoice(add_invoice) => seller::add_invoice(
+                add_invoice.invoice_id,
+                add_invoice.currency,
+                add_invoice.total_dest_payment,
+            ),
+            GatewayRequest::CancelInvoice(invoice_id) => seller::cancel_invoice(invoice_id),
+            GatewayRequest::CommitInvoice(commit) => seller::commit_invoice(commit.into()),
+            GatewayRequest::SignInvoice(sign_invoice) => seller::sign_invoice(
+                sign_invoice.invoice_id,
+                sign_invoice.currency,
+                sign_invoice.total_dest_payment,
+                sign_invoice.relays,
+                sign_invoice.valid_until,
+                sign_invoice.description,
+            ),
+            GatewayRequest::RequestRoutes(request_routes) => routes::request_routes(
+                request_routes.request_id,
+                request_routes.currency,
+                request_routes.capacity,
+                request_routes.source,
+                request_routes.destination,
+                request_routes
+                    .opt_exclude
+                    .map(|edge| (edge.from_public_key, edge.to_public_key)),
+            ),
+        }
+    }
+}
+
+impl From<app::conn::RequestResult> for RequestResult {
+    fn from(from: app::conn::RequestResult) -> Self {
+        match from {
+            app::conn::RequestResult::Complete(commit) => RequestResult::Complete(commit.into()),
+            app::conn::RequestResult::Success => RequestResult::Success,
+            app::conn::RequestResult::Failure => RequestResult::Failure,
+        }
+    }
+}
+
+impl From<app::conn::TransactionResult> for TransactionResult {
+    fn from(from: app::conn::TransactionResult) -> Self {
+        TransactionResult {
+            request_id: from.request_id,
+            result: from.result.into(),
+        }
+    }
+}
+
+impl From<app::common::PaymentStatus> for PaymentStatus {
+    fn from(from: app::common::PaymentStatus) -> Self {
+        match from {
+            app::common::PaymentStatus::PaymentNotFound => PaymentStatus::PaymentNotFound,
+            app::common::PaymentStatus::Success(success) => {
+                PaymentStatus::Success(PaymentStatusSuccess {
+                    receipt: success.receipt,
+                    ack_uid: success.ack_uid,
+                })
+            }
+            app::common::PaymentStatus::Canceled(ack_uid) => PaymentStatus::Canceled(ack_uid),
+        }
+    }
+}
+
+impl From<app::conn::ResponseClosePayment> for ResponseClosePayment {
+    fn from(from: app::conn::ResponseClosePayment) -> Self {
+        ResponseClosePayment {
+            payment_id: from.payment_id,
+            status: from.status.into(),
+        }
+    }
+}
+
+impl From<app::conn::ClientResponseRoutes> for ResponseRoutes {
+    fn from(from: app::conn::ClientResponseRoutes) -> Self {
+        ResponseRoutes {
+            request_id: from.request_id,
+            result: match from.result {
+                app::conn::ResponseRoutesResult::Success(multi_routes) => {
+                    ResponseRoutesResult::Success(multi_routes)
+                }
+                app::conn::ResponseRoutesResult::Failure => ResponseRoutesResult::Failure,
+            },
+        }
+    }
+}
+
+/// The current state of a friend, or its removal if the friend does not exist anymore
+fn friend_report_mutation(
+    node_report: &NodeReport,
+    friend_public_key: &PublicKey,
+) -> ReportMutation {
+    match node_report.funder_report.friends.get(friend_public_key) {
+        Some(friend_report) => ReportMutation::UpdateFriend(UpdateFriend {
+            friend_public_key: friend_public_key.clone(),
+            friend_report: friend_report.clone().into(),
+        }),
+        None => ReportMutation::RemoveFriend(friend_public_key.clone()),
+    }
+}
+
+/// Translate node report mutations into mutations of the report clients see.
+/// `node_report` is the node report after the mutations were applied.
+/// Mutations of parts that are not included in the clients' report are dropped.
+pub fn create_report_mutations(
+    node_report: &NodeReport,
+    mutations: &[NodeReportMutation],
+) -> Vec<ReportMutation> {
+    mutations
+        .iter()
+        .filter_map(|mutation| match mutation {
+            NodeReportMutation::Funder(funder_mutation) => match funder_mutation {
+                FunderReportMutation::AddRelay(_) | FunderReportMutation::RemoveRelay(_) => Some(
+                    ReportMutation::SetRelays(node_report.funder_report.relays.clone()),
+                ),
+                FunderReportMutation::AddFriend(add_friend_report) => Some(friend_report_mutation(
+                    node_report,
+                    &add_friend_report.friend_public_key,
+                )),
+                FunderReportMutation::RemoveFriend(friend_public_key)
+                | FunderReportMutation::PkFriendReportMutation((friend_public_key, _)) => {
+                    Some(friend_report_mutation(node_report, friend_public_key))
+                }
+                FunderReportMutation::SetExchangeRate(_)
+                | FunderReportMutation::RemoveExchangeRate(_) => None,
+            },
+            NodeReportMutation::IndexClient(index_client_mutation) => match index_client_mutation {
+                IndexClientReportMutation::AddIndexServer(_)
+                | IndexClientReportMutation::RemoveIndexServer(_) => {
+                    Some(ReportMutation::SetIndexServers(
+                        node_report.index_client_report.index_servers.clone(),
+                    ))
+                }
+                IndexClientReportMutation::SetConnectedServer(opt_connected_server) => Some(
+                    ReportMutation::SetConnectedIndexServer(opt_connected_server.clone()),
+                ),
+                IndexClientReportMutation::SetTopologySnapshot(_)
+                | IndexClientReportMutation::RemoveTopologySnapshot(_) => None,
+            },
+        })
+        .collect()
+}
@@ -0,0 +1,431 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use app::common::{
+    Currency, CurrencyPair, ExchangeRate, FeePolicy, FriendsRoute, InvoiceId, MultiRoute,
+    NamedIndexServerAddress, NamedRelayAddress, PaymentId, PublicKey, RebalancePolicy, Receipt,
+    RelayAddress, SignedInvoice, Uid,
+};
+use common::ser_utils::{ser_b64, ser_option_b64, ser_string};
+
+use crate::compact_node::messages::{
+    AddFriend, CloseFriendCurrency, Commit, FriendReport, OpenFriendCurrency, RemoveFriendCurrency,
+    ResetFriendChannel, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate, SetFriendName,
+    SetFriendRelays,
+};
+
+/// The only JSON-RPC version we speak
+pub const JSONRPC_VERSION: &str = "2.0";
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// The client did not authenticate yet, or used an unknown API key
+pub const UNAUTHENTICATED: i64 = -32001;
+/// The API key of the client does not allow this request
+pub const PERMISSION_DENIED: i64 = -32002;
+/// The request could not be delivered to the node
+pub const NODE_ERROR: i64 = -32003;
+
+/// Must be the first request sent by a client
+pub const AUTHENTICATE_METHOD: &str = "authenticate";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    /// A request without an id is a notification, and is not answered
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        RpcError {
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub fn success(id: Value, result: Value) -> Self {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn failure(id: Value, error: RpcError) -> Self {
+        RpcResponse {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: Value,
+}
+
+impl RpcNotification {
+    pub fn new(method: &str, params: Value) -> Self {
+        RpcNotification {
+            jsonrpc: JSONRPC_VERSION.to_owned(),
+            method: method.to_owned(),
+            params,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authenticate {
+    pub api_key: String,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetFriendCurrencyFeePolicy {
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    pub opt_fee_policy: Option<FeePolicy>,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetFriendCurrencyRebalancePolicy {
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    pub opt_rebalance_policy: Option<RebalancePolicy>,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatePayment {
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+    #[serde(with = "ser_b64")]
+    pub dest_public_key: PublicKey,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateTransaction {
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub route: FriendsRoute,
+    #[serde(with = "ser_string")]
+    pub dest_payment: u128,
+    #[serde(with = "ser_string")]
+    pub fees: u128,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AckClosePayment {
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    #[serde(with = "ser_b64")]
+    pub ack_uid: Uid,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeliverCommit {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub commit: Commit,
+}
+
+/// Unlike compact's `AddInvoice`, the node does not keep a description for invoices.
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddInvoice {
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignInvoice {
+    #[serde(with = "ser_b64")]
+    pub invoice_id: InvoiceId,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_string")]
+    pub total_dest_payment: u128,
+    pub relays: Vec<RelayAddress>,
+    /// Expiry of the invoice (Seconds since the Unix epoch)
+    #[serde(with = "ser_string")]
+    pub valid_until: u64,
+    pub description: String,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Edge {
+    #[serde(with = "ser_b64")]
+    pub from_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub to_public_key: PublicKey,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestRoutes {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    #[serde(with = "ser_string")]
+    pub currency: Currency,
+    #[serde(with = "ser_string")]
+    pub capacity: u128,
+    #[serde(with = "ser_b64")]
+    pub source: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub destination: PublicKey,
+    /// Directed edge that must not be used by any of the returned routes
+    pub opt_exclude: Option<Edge>,
+}
+
+/// All the requests a gateway client may send to the node, one for every `AppRequest`.
+/// Serialized as a JSON-RPC method name together with its params.
+#[allow(clippy::large_enum_variant)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "camelCase")]
+pub enum GatewayRequest {
+    // ----------------[Configuration]-----------------------
+    AddRelay(NamedRelayAddress),
+    #[serde(with = "ser_b64")]
+    RemoveRelay(PublicKey),
+    AddFriend(AddFriend),
+    SetFriendRelays(SetFriendRelays),
+    SetFriendName(SetFriendName),
+    #[serde(with = "ser_b64")]
+    RemoveFriend(PublicKey),
+    #[serde(with = "ser_b64")]
+    EnableFriend(PublicKey),
+    #[serde(with = "ser_b64")]
+    DisableFriend(PublicKey),
+    OpenFriendCurrency(OpenFriendCurrency),
+    CloseFriendCurrency(CloseFriendCurrency),
+    SetFriendCurrencyMaxDebt(SetFriendCurrencyMaxDebt),
+    SetFriendCurrencyRate(SetFriendCurrencyRate),
+    RemoveFriendCurrency(RemoveFriendCurrency),
+    ResetFriendChannel(ResetFriendChannel),
+    SetFriendCurrencyFeePolicy(SetFriendCurrencyFeePolicy),
+    SetFriendCurrencyRebalancePolicy(SetFriendCurrencyRebalancePolicy),
+    #[serde(with = "ser_b64")]
+    SettleFriend(PublicKey),
+    AddIndexServer(NamedIndexServerAddress),
+    #[serde(with = "ser_b64")]
+    RemoveIndexServer(PublicKey),
+    SetExchangeRate(ExchangeRate),
+    RemoveExchangeRate(CurrencyPair),
+    // ---------------[Buyer]------------------------------
+    CreatePayment(CreatePayment),
+    CreateTransaction(CreateTransaction),
+    #[serde(with = "ser_b64")]
+    RequestClosePayment(PaymentId),
+    AckClosePayment(AckClosePayment),
+    DeliverCommit(DeliverCommit),
+    // ---------------[Seller]------------------------------
+    AddInvoice(AddInvoice),
+    #[serde(with = "ser_b64")]
+    CancelInvoice(InvoiceId),
+    CommitInvoice(Commit),
+    SignInvoice(SignInvoice),
+    // ---------------[Routes]------------------------------
+    RequestRoutes(RequestRoutes),
+}
+
+/// Names of all the methods of `GatewayRequest`.
+/// Used to tell apart unknown methods from invalid params.
+pub const GATEWAY_METHODS: &[&str] = &[
+    "addRelay",
+    "removeRelay",
+    "addFriend",
+    "setFriendRelays",
+    "setFriendName",
+    "removeFriend",
+    "enableFriend",
+    "disableFriend",
+    "openFriendCurrency",
+    "closeFriendCurrency",
+    "setFriendCurrencyMaxDebt",
+    "setFriendCurrencyRate",
+    "removeFriendCurrency",
+    "resetFriendChannel",
+    "setFriendCurrencyFeePolicy",
+    "setFriendCurrencyRebalancePolicy",
+    "settleFriend",
+    "addIndexServer",
+    "removeIndexServer",
+    "setExchangeRate",
+    "removeExchangeRate",
+    "createPayment",
+    "createTransaction",
+    "requestClosePayment",
+    "ackClosePayment",
+    "deliverCommit",
+    "addInvoice",
+    "cancelInvoice",
+    "commitInvoice",
+    "signInvoice",
+    "requestRoutes",
+];
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RequestResult {
+    Complete(Commit),
+    Success,
+    Failure,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionResult {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub result: RequestResult,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaymentStatusSuccess {
+    pub receipt: Receipt,
+    #[serde(with = "ser_b64")]
+    pub ack_uid: Uid,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PaymentStatus {
+    PaymentNotFound,
+    Success(PaymentStatusSuccess),
+    #[serde(with = "ser_b64")]
+    Canceled(Uid), // ack_uid
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseClosePayment {
+    #[serde(with = "ser_b64")]
+    pub payment_id: PaymentId,
+    pub status: PaymentStatus,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResponseRoutesResult {
+    Success(Vec<MultiRoute>),
+    Failure,
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseRoutes {
+    #[serde(with = "ser_b64")]
+    pub request_id: Uid,
+    pub result: ResponseRoutesResult,
+}
+
+/// The result of a `signInvoice` request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignInvoiceResult {
+    /// The signed invoice, encoded as an `offset:` URI
+    pub invoice_uri: String,
+    pub signed_invoice: SignedInvoice,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateFriend {
+    #[serde(with = "ser_b64")]
+    pub friend_public_key: PublicKey,
+    pub friend_report: FriendReport,
+}
+
+/// A change in the `report` sent to a client.
+/// Clients apply the mutations of a `reportMutations` notification to the last report they got.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReportMutation {
+    SetRelays(Vec<NamedRelayAddress>),
+    SetIndexServers(Vec<NamedIndexServerAddress>),
+    #[serde(with = "ser_option_b64")]
+    SetConnectedIndexServer(Option<PublicKey>),
+    UpdateFriend(UpdateFriend),
+    #[serde(with = "ser_b64")]
+    RemoveFriend(PublicKey),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[quickcheck]
+    fn qc_gateway_request(msg: GatewayRequest) -> bool {
+        let value = serde_json::to_value(&msg).unwrap();
+        let is_known_method = match value["method"].as_str() {
+            Some(method) => GATEWAY_METHODS.contains(&method),
+            None => false,
+        };
+        let msg2: GatewayRequest = serde_json::from_value(value).unwrap();
+        is_known_method && msg2 == msg
+    }
+}
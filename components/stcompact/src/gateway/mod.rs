@@ -0,0 +1,6 @@
+mod convert;
+pub mod messages;
+mod permission;
+mod server;
+
+pub use server::{gateway_loop, ApiKeys, GatewayError};
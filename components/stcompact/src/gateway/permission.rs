@@ -0,0 +1,95 @@
+use app::conn::{now_secs, AppPermissions, AppRequest, AppRestrictions};
+
+/// Check if a client is allowed to send a certain app request.
+/// Mirrors the checks done by the node's app server.
+pub fn check_permission(app_request: &AppRequest, app_permissions: &AppPermissions) -> bool {
+    let allowed = match app_request {
+        AppRequest::RequestRoutes(_) => app_permissions.routes,
+        AppRequest::CreatePayment(_)
+        | AppRequest::CreateTransaction(_)
+        | AppRequest::RequestClosePayment(_)
+        | AppRequest::AckClosePayment(_)
+        | AppRequest::DeliverCommit(_) => app_permissions.buyer,
+        AppRequest::AddInvoice(_)
+        | AppRequest::CancelInvoice(_)
+        | AppRequest::CommitInvoice(_)
+        | AppRequest::SignInvoice(_) => app_permissions.seller,
+        AppRequest::AddRelay(_)
+        | AppRequest::RemoveRelay(_)
+        | AppRequest::AddFriend(_)
+        | AppRequest::SetFriendRelays(_)
+        | AppRequest::SetFriendName(_)
+        | AppRequest::RemoveFriend(_)
+        | AppRequest::EnableFriend(_)
+        | AppRequest::DisableFriend(_)
+        | AppRequest::OpenFriendCurrency(_)
+        | AppRequest::CloseFriendCurrency(_)
+        | AppRequest::SetFriendCurrencyMaxDebt(_)
+        | AppRequest::SetFriendCurrencyRate(_)
+        | AppRequest::RemoveFriendCurrency(_)
+        | AppRequest::ResetFriendChannel(_)
+        | AppRequest::AddIndexServer(_)
+        | AppRequest::RemoveIndexServer(_)
+        | AppRequest::SetExchangeRate(_)
+        | AppRequest::RemoveExchangeRate(_)
+        | AppRequest::SetFriendCurrencyFeePolicy(_)
+        | AppRequest::SetFriendCurrencyRebalancePolicy(_)
+        | AppRequest::SettleFriend(_) => app_permissions.config,
+        // Verification does not change any state:
+        AppRequest::VerifyReceipt(_) | AppRequest::VerifyFriendToken(_) => true,
+    };
+
+    allowed && check_restrictions(app_request, &app_permissions.restrictions, now_secs())
+}
+
+/// Check if a client may see the node report.
+/// The report reveals the node's friends and balances, which a key that may only request routes
+/// has no use for.
+pub fn can_view_report(app_permissions: &AppPermissions) -> bool {
+    app_permissions.buyer || app_permissions.seller || app_permissions.config
+}
+
+/// Check the finer grained restrictions of an API key.
+/// The gateway does not keep track of previous payments, so a spending limit only bounds the
+/// size of a single payment. Spending over time is limited by the restrictions the node sets for
+/// the gateway itself.
+fn check_restrictions(app_request: &AppRequest, restrictions: &AppRestrictions, now: u64) -> bool {
+    if restrictions.is_expired(now) {
+        return false;
+    }
+
+    if let Some(config_permission) = app_request.config_permission() {
+        if !restrictions.is_config_allowed(&config_permission) {
+            return false;
+        }
+    }
+
+    if let AppRequest::CreatePayment(create_payment) = app_request {
+        if !restrictions.is_destination_allowed(&create_payment.dest_public_key) {
+            return false;
+        }
+        if let Some(spending_limit) = restrictions.spending_limit(&create_payment.currency) {
+            if create_payment.total_dest_payment > spending_limit.max_amount {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// The permissions of an API key, limited to the permissions the node granted the gateway.
+/// The restrictions of the API key are kept as is. The restrictions the node set for the gateway
+/// are enforced by the node itself.
+pub fn intersect_permissions(
+    key_permissions: &AppPermissions,
+    node_permissions: &AppPermissions,
+) -> AppPermissions {
+    AppPermissions {
+        routes: key_permissions.routes && node_permissions.routes,
+        buyer: key_permissions.buyer && node_permissions.buyer,
+        seller: key_permissions.seller && node_permissions.seller,
+        config: key_permissions.config && node_permissions.config,
+        restrictions: key_permissions.restrictions.clone(),
+    }
+}
@@ -0,0 +1,1168 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use common::conn::{sink_to_sender, BoxStream, ConnPairString};
+use common::select_streams::select_streams;
+
+use app::common::{InvoiceId, PaymentId, Uid};
+use app::conn::{AppConnTuple, AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use app::report::NodeReport;
+use app::ser_utils::invoice_to_uri;
+
+use crate::compact_node::messages::Commit;
+use crate::compact_node::{create_compact_report, CompactState};
+use crate::gateway::convert::create_report_mutations;
+use crate::gateway::messages::{
+    Authenticate, GatewayRequest, ReportMutation, ResponseClosePayment, ResponseRoutes, RpcError,
+    RpcNotification, RpcRequest, RpcResponse, SignInvoiceResult, TransactionResult,
+    AUTHENTICATE_METHOD, GATEWAY_METHODS, INVALID_PARAMS, INVALID_REQUEST, JSONRPC_VERSION,
+    METHOD_NOT_FOUND, NODE_ERROR, PARSE_ERROR, PERMISSION_DENIED, UNAUTHENTICATED,
+};
+use crate::gateway::permission::{can_view_report, check_permission, intersect_permissions};
+use crate::gen::GenUid;
+use crate::multi_user::is_token_equal;
+
+/// Amount of messages we buffer for a single client.
+/// A client that falls further behind is disconnected.
+const CLIENT_SEND_BUFFER: usize = 0x100;
+/// Amount of requests we buffer for the node.
+const APP_SEND_BUFFER: usize = 0x100;
+/// Amount of timer ticks a connected client has to authenticate.
+/// A client that does not authenticate in time is disconnected.
+const AUTH_TIMEOUT_TICKS: usize = 16;
+
+/// Permissions granted to every API key
+pub type ApiKeys = HashMap<String, AppPermissions>;
+
+#[derive(Debug)]
+pub enum GatewayError {
+    SpawnError,
+    ReportMutationError,
+    NodeClosed,
+    TimerClosed,
+}
+
+type ClientId = u64;
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum GatewayEvent {
+    NewClient(ConnPairString),
+    IncomingClientsClosed,
+    Client((ClientId, Option<String>)),
+    App(AppServerToApp),
+    AppClosed,
+    TimerTick,
+    TimerClosed,
+}
+
+struct Client {
+    sender: mpsc::Sender<String>,
+    /// None until the client authenticates
+    opt_permissions: Option<AppPermissions>,
+    /// Amount of timer ticks left for the client to authenticate
+    auth_ticks_left: usize,
+}
+
+/// Requests of clients that are waiting for a result from the node.
+/// Keyed by the client and the id the client chose for the request.
+/// An id may only be pending for a single client, so that a client can not claim the results of
+/// another client's request.
+struct ClientRequests<K, V> {
+    requests: HashMap<(ClientId, K), V>,
+}
+
+impl<K, V> ClientRequests<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new() -> Self {
+        ClientRequests {
+            requests: HashMap::new(),
+        }
+    }
+
+    /// Is `key` already pending for a client other than `client_id`?
+    fn is_taken(&self, client_id: ClientId, key: &K) -> bool {
+        self.requests
+            .keys()
+            .any(|(owner_id, pending_key)| pending_key == key && *owner_id != client_id)
+    }
+
+    fn insert(&mut self, client_id: ClientId, key: K, value: V) {
+        let _ = self.requests.insert((client_id, key), value);
+    }
+
+    /// Remove a pending request, together with the client that is waiting for it
+    fn remove(&mut self, key: &K) -> Option<(ClientId, V)> {
+        let client_id = self
+            .requests
+            .keys()
+            .find(|(_, pending_key)| pending_key == key)
+            .map(|(owner_id, _)| *owner_id)?;
+        let value = self.requests.remove(&(client_id, key.clone()))?;
+        Some((client_id, value))
+    }
+
+    fn remove_client(&mut self, client_id: ClientId) {
+        self.requests
+            .retain(|(owner_id, _), _| *owner_id != client_id);
+    }
+}
+
+struct Gateway<CG> {
+    api_keys: ApiKeys,
+    /// Permissions the node granted the gateway
+    node_permissions: AppPermissions,
+    node_report: NodeReport,
+    app_sender: mpsc::Sender<AppToAppServer>,
+    clients: HashMap<ClientId, Client>,
+    /// Requests forwarded to the node, waiting for a response
+    pending_requests: HashMap<Uid, (ClientId, Value)>,
+    /// Clients that are waiting for a TransactionResult
+    transaction_requests: ClientRequests<Uid, ()>,
+    /// Clients that are waiting for a ResponseClosePayment
+    close_payment_requests: ClientRequests<PaymentId, ()>,
+    /// Clients that are waiting for ResponseRoutes
+    route_requests: ClientRequests<Uid, ()>,
+    /// Clients that are waiting for a signed invoice.
+    /// The node does not report signing as a mutation, so the request is answered directly.
+    sign_invoice_requests: ClientRequests<InvoiceId, Option<Value>>,
+    uid_gen: CG,
+}
+
+fn to_json_string<T: Serialize>(msg: &T) -> String {
+    serde_json::to_string(msg).expect("Serialization error!")
+}
+
+impl<CG> Gateway<CG>
+where
+    CG: GenUid,
+{
+    fn remove_client(&mut self, client_id: ClientId) {
+        if self.clients.remove(&client_id).is_none() {
+            return;
+        }
+        self.pending_requests
+            .retain(|_, (pending_client_id, _)| *pending_client_id != client_id);
+        self.transaction_requests.remove_client(client_id);
+        self.close_payment_requests.remove_client(client_id);
+        self.route_requests.remove_client(client_id);
+        self.sign_invoice_requests.remove_client(client_id);
+    }
+
+    /// Send a message to a client. A client that can not keep up is disconnected.
+    fn send_client(&mut self, client_id: ClientId, msg: String) {
+        let is_sent = match self.clients.get_mut(&client_id) {
+            Some(client) => client.sender.try_send(msg).is_ok(),
+            None => return,
+        };
+        if !is_sent {
+            warn!("gateway: Client {} is too slow. Disconnecting", client_id);
+            self.remove_client(client_id);
+        }
+    }
+
+    fn send_notification<T: Serialize>(&mut self, client_id: ClientId, method: &str, params: &T) {
+        let params = serde_json::to_value(params).expect("Serialization error!");
+        self.send_client(
+            client_id,
+            to_json_string(&RpcNotification::new(method, params)),
+        );
+    }
+
+    fn create_report_notification(&self) -> String {
+        let compact_report = create_compact_report(CompactState::new(), self.node_report.clone());
+        let params = serde_json::to_value(&compact_report).expect("Serialization error!");
+        to_json_string(&RpcNotification::new("report", params))
+    }
+
+    /// Send report mutations to all the clients that may see the report
+    fn broadcast_report_mutations(&mut self, report_mutations: &[ReportMutation]) {
+        if report_mutations.is_empty() {
+            return;
+        }
+        let client_ids: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                client
+                    .opt_permissions
+                    .as_ref()
+                    .map(can_view_report)
+                    .unwrap_or(false)
+            })
+            .map(|(client_id, _)| *client_id)
+            .collect();
+        if client_ids.is_empty() {
+            return;
+        }
+        let params = serde_json::to_value(report_mutations).expect("Serialization error!");
+        let msg = to_json_string(&RpcNotification::new("reportMutations", params));
+        for client_id in client_ids {
+            self.send_client(client_id, msg.clone());
+        }
+    }
+
+    /// Disconnect clients that did not authenticate in time
+    fn handle_timer_tick(&mut self) {
+        let mut timed_out_clients = Vec::new();
+        for (client_id, client) in &mut self.clients {
+            if client.opt_permissions.is_some() {
+                continue;
+            }
+            client.auth_ticks_left = client.auth_ticks_left.saturating_sub(1);
+            if client.auth_ticks_left == 0 {
+                timed_out_clients.push(*client_id);
+            }
+        }
+        for client_id in timed_out_clients {
+            warn!(
+                "gateway: Client {} did not authenticate in time. Disconnecting",
+                client_id
+            );
+            self.remove_client(client_id);
+        }
+    }
+
+    fn handle_authenticate(
+        &mut self,
+        client_id: ClientId,
+        params: Value,
+    ) -> Result<Value, RpcError> {
+        let authenticate: Authenticate = serde_json::from_value(params)
+            .map_err(|e| RpcError::new(INVALID_PARAMS, &e.to_string()))?;
+
+        let key_permissions = self
+            .api_keys
+            .iter()
+            .find(|(api_key, _)| is_token_equal(api_key, &authenticate.api_key))
+            .map(|(_, key_permissions)| key_permissions)
+            .ok_or_else(|| RpcError::new(UNAUTHENTICATED, "Invalid API key"))?;
+
+        let permissions = intersect_permissions(key_permissions, &self.node_permissions);
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.opt_permissions = Some(permissions.clone());
+        }
+        Ok(serde_json::to_value(&permissions).expect("Serialization error!"))
+    }
+
+    /// Forward a request to the node.
+    /// The client will be answered once the node reports the request as done.
+    fn handle_gateway_request(
+        &mut self,
+        client_id: ClientId,
+        mut opt_id: Option<Value>,
+        method: &str,
+        params: Value,
+    ) -> Result<(), RpcError> {
+        let permissions = self
+            .clients
+            .get(&client_id)
+            .and_then(|client| client.opt_permissions.clone())
+            .ok_or_else(|| RpcError::new(UNAUTHENTICATED, "Not authenticated"))?;
+
+        let gateway_request: GatewayRequest =
+            serde_json::from_value(json!({"method": method, "params": params})).map_err(|e| {
+                if GATEWAY_METHODS.contains(&method) {
+                    RpcError::new(INVALID_PARAMS, &e.to_string())
+                } else {
+                    RpcError::new(METHOD_NOT_FOUND, "Method not found")
+                }
+            })?;
+
+        let app_request = AppRequest::from(gateway_request);
+        if !check_permission(&app_request, &permissions) {
+            return Err(RpcError::new(PERMISSION_DENIED, "Permission denied"));
+        }
+
+        // Another client is already waiting for the results of a request with the same id:
+        let is_taken = match &app_request {
+            AppRequest::CreateTransaction(create_transaction) => self
+                .transaction_requests
+                .is_taken(client_id, &create_transaction.request_id),
+            AppRequest::RequestClosePayment(payment_id) => {
+                self.close_payment_requests.is_taken(client_id, payment_id)
+            }
+            AppRequest::RequestRoutes(request_routes) => self
+                .route_requests
+                .is_taken(client_id, &request_routes.request_id),
+            AppRequest::SignInvoice(unsigned_invoice) => self
+                .sign_invoice_requests
+                .is_taken(client_id, &unsigned_invoice.invoice_id),
+            _ => false,
+        };
+        if is_taken {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "Request id is already in use",
+            ));
+        }
+
+        // Remember which client should get the results of this request:
+        match &app_request {
+            AppRequest::CreateTransaction(create_transaction) => {
+                self.transaction_requests.insert(
+                    client_id,
+                    create_transaction.request_id.clone(),
+                    (),
+                );
+            }
+            AppRequest::RequestClosePayment(payment_id) => {
+                self.close_payment_requests
+                    .insert(client_id, payment_id.clone(), ());
+            }
+            AppRequest::RequestRoutes(request_routes) => {
+                self.route_requests
+                    .insert(client_id, request_routes.request_id.clone(), ());
+            }
+            AppRequest::SignInvoice(unsigned_invoice) => {
+                // Answered with the signed invoice, instead of a report mutation:
+                self.sign_invoice_requests.insert(
+                    client_id,
+                    unsigned_invoice.invoice_id.clone(),
+                    opt_id.take(),
+                );
+            }
+            _ => {}
+        }
+
+        // We never wait for the node here, to avoid a deadlock with a node that is waiting for
+        // us to read its messages:
+        let app_request_id = self.uid_gen.gen_uid();
+        self.app_sender
+            .try_send(AppToAppServer::new(app_request_id.clone(), app_request))
+            .map_err(|_| RpcError::new(NODE_ERROR, "Node is unavailable"))?;
+
+        // Notifications (Requests without an id) are never answered:
+        if let Some(id) = opt_id {
+            self.pending_requests
+                .insert(app_request_id, (client_id, id));
+        }
+        Ok(())
+    }
+
+    fn handle_client_line(&mut self, client_id: ClientId, line: &str) {
+        let rpc_request: RpcRequest = match serde_json::from_str::<Value>(line) {
+            Err(_) => {
+                let response =
+                    RpcResponse::failure(Value::Null, RpcError::new(PARSE_ERROR, "Parse error"));
+                self.send_client(client_id, to_json_string(&response));
+                return;
+            }
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(rpc_request) => rpc_request,
+                Err(_) => {
+                    let response = RpcResponse::failure(
+                        Value::Null,
+                        RpcError::new(INVALID_REQUEST, "Invalid request"),
+                    );
+                    self.send_client(client_id, to_json_string(&response));
+                    return;
+                }
+            },
+        };
+
+        let RpcRequest {
+            jsonrpc,
+            id: opt_id,
+            method,
+            params,
+        } = rpc_request;
+
+        if jsonrpc != JSONRPC_VERSION {
+            let response = RpcResponse::failure(
+                opt_id.unwrap_or(Value::Null),
+                RpcError::new(INVALID_REQUEST, "Unsupported jsonrpc version"),
+            );
+            self.send_client(client_id, to_json_string(&response));
+            return;
+        }
+
+        let res = if method == AUTHENTICATE_METHOD {
+            self.handle_authenticate(client_id, params).map(Some)
+        } else {
+            self.handle_gateway_request(client_id, opt_id.clone(), &method, params)
+                .map(|_| None)
+        };
+
+        let is_report_viewer = method == AUTHENTICATE_METHOD
+            && res.is_ok()
+            && self
+                .clients
+                .get(&client_id)
+                .and_then(|client| client.opt_permissions.as_ref())
+                .map(can_view_report)
+                .unwrap_or(false);
+        let opt_response = match (opt_id, res) {
+            (None, _) | (Some(_), Ok(None)) => None,
+            (Some(id), Ok(Some(result))) => Some(RpcResponse::success(id, result)),
+            (Some(id), Err(rpc_error)) => Some(RpcResponse::failure(id, rpc_error)),
+        };
+        if let Some(response) = opt_response {
+            self.send_client(client_id, to_json_string(&response));
+        }
+
+        // A newly authenticated client gets the current report, if it may see it:
+        if is_report_viewer {
+            let msg = self.create_report_notification();
+            self.send_client(client_id, msg);
+        }
+    }
+
+    fn handle_app_message(&mut self, app_message: AppServerToApp) -> Result<(), GatewayError> {
+        match app_message {
+            AppServerToApp::TransactionResult(transaction_result) => {
+                match self
+                    .transaction_requests
+                    .remove(&transaction_result.request_id)
+                {
+                    Some((client_id, ())) => self.send_notification(
+                        client_id,
+                        "transactionResult",
+                        &TransactionResult::from(transaction_result),
+                    ),
+                    None => warn!("gateway: TransactionResult for unknown request"),
+                }
+            }
+            AppServerToApp::ResponseClosePayment(response_close_payment) => {
+                match self
+                    .close_payment_requests
+                    .remove(&response_close_payment.payment_id)
+                {
+                    Some((client_id, ())) => self.send_notification(
+                        client_id,
+                        "responseClosePayment",
+                        &ResponseClosePayment::from(response_close_payment),
+                    ),
+                    None => warn!("gateway: ResponseClosePayment for unknown payment"),
+                }
+            }
+            AppServerToApp::ResponseRoutes(client_response_routes) => {
+                match self
+                    .route_requests
+                    .remove(&client_response_routes.request_id)
+                {
+                    Some((client_id, ())) => self.send_notification(
+                        client_id,
+                        "responseRoutes",
+                        &ResponseRoutes::from(client_response_routes),
+                    ),
+                    None => warn!("gateway: ResponseRoutes for unknown request"),
+                }
+            }
+            AppServerToApp::ResponseSignInvoice(signed_invoice) => {
+                match self
+                    .sign_invoice_requests
+                    .remove(&signed_invoice.invoice_id)
+                {
+                    Some((client_id, Some(id))) => {
+                        let sign_invoice_result = SignInvoiceResult {
+                            invoice_uri: invoice_to_uri(&signed_invoice),
+                            signed_invoice,
+                        };
+                        let result = serde_json::to_value(&sign_invoice_result)
+                            .expect("Serialization error!");
+                        let response = RpcResponse::success(id, result);
+                        self.send_client(client_id, to_json_string(&response));
+                    }
+                    Some((_client_id, None)) => {}
+                    None => warn!("gateway: ResponseSignInvoice for unknown invoice"),
+                }
+            }
+            AppServerToApp::InvoiceCommitted(commit) => {
+                // Invoices are not tracked by the gateway. Notify all the sellers:
+                let commit = Commit::from(commit);
+                let client_ids: Vec<_> = self
+                    .clients
+                    .iter()
+                    .filter(|(_, client)| {
+                        client
+                            .opt_permissions
+                            .as_ref()
+                            .map(|permissions| permissions.seller)
+                            .unwrap_or(false)
+                    })
+                    .map(|(client_id, _)| *client_id)
+                    .collect();
+                for client_id in client_ids {
+                    self.send_notification(client_id, "invoiceCommitted", &commit);
+                }
+            }
+            // The gateway never sends verification requests:
+            AppServerToApp::ResponseVerifyReceipt(_)
+            | AppServerToApp::ResponseVerifyFriendToken(_) => {}
+            AppServerToApp::ReportMutations(report_mutations) => {
+                let mut node_report = self.node_report.clone();
+                for mutation in &report_mutations.mutations {
+                    node_report
+                        .mutate(mutation)
+                        .map_err(|_| GatewayError::ReportMutationError)?;
+                }
+
+                // Send the report mutations before acknowledging the request:
+                if node_report != self.node_report {
+                    let client_mutations =
+                        create_report_mutations(&node_report, &report_mutations.mutations);
+                    self.node_report = node_report;
+                    self.broadcast_report_mutations(&client_mutations);
+                }
+
+                if let Some(app_request_id) = report_mutations.opt_app_request_id {
+                    // Requests sent by other apps of the node are not ours to answer:
+                    if let Some((client_id, id)) = self.pending_requests.remove(&app_request_id) {
+                        let response = RpcResponse::success(id, Value::Null);
+                        self.send_client(client_id, to_json_string(&response));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serve JSON-RPC 2.0 clients over a single app connection to a node.
+/// Every incoming client connection carries one JSON message per item.
+///
+/// A client must first call `authenticate` with an API key from `api_keys`, within
+/// `AUTH_TIMEOUT_TICKS` ticks of `timer_stream`. It may then send any of the `GatewayRequest`
+/// methods allowed by both its API key and the permissions the node granted the gateway.
+/// A request is answered once the node reports it as done.
+/// Clients whose API key allows seeing the report get a `report` notification when they
+/// authenticate, and `reportMutations` notifications whenever the report changes.
+pub async fn gateway_loop<IC, TS, CG, S>(
+    incoming_clients: IC,
+    app_conn_tuple: AppConnTuple,
+    api_keys: ApiKeys,
+    timer_stream: TS,
+    uid_gen: CG,
+    spawner: S,
+) -> Result<(), GatewayError>
+where
+    IC: Stream<Item = ConnPairString> + Unpin + Send + 'static,
+    TS: Stream + Unpin + Send + 'static,
+    CG: GenUid,
+    S: Spawn + Clone + Send + 'static,
+{
+    let (node_permissions, node_report, conn_pair_app) = app_conn_tuple;
+    let (app_sender, app_receiver) = conn_pair_app.split();
+    let app_sender = sink_to_sender(app_sender, APP_SEND_BUFFER, &spawner);
+
+    let (client_events_sender, client_events_receiver) = mpsc::channel(0);
+
+    let incoming_clients = incoming_clients
+        .map(GatewayEvent::NewClient)
+        .chain(stream::once(future::ready(
+            GatewayEvent::IncomingClientsClosed,
+        )));
+
+    let app_receiver = app_receiver
+        .map(GatewayEvent::App)
+        .chain(stream::once(future::ready(GatewayEvent::AppClosed)));
+
+    let client_events_receiver = client_events_receiver.map(GatewayEvent::Client);
+
+    let timer_stream = timer_stream
+        .map(|_| GatewayEvent::TimerTick)
+        .chain(stream::once(future::ready(GatewayEvent::TimerClosed)));
+
+    let mut incoming_events = select_streams![
+        incoming_clients,
+        app_receiver,
+        client_events_receiver,
+        timer_stream
+    ];
+
+    let mut gateway = Gateway {
+        api_keys,
+        node_permissions,
+        node_report,
+        app_sender,
+        clients: HashMap::new(),
+        pending_requests: HashMap::new(),
+        transaction_requests: ClientRequests::new(),
+        close_payment_requests: ClientRequests::new(),
+        route_requests: ClientRequests::new(),
+        sign_invoice_requests: ClientRequests::new(),
+        uid_gen,
+    };
+    let mut next_client_id: ClientId = 0;
+
+    while let Some(event) = incoming_events.next().await {
+        match event {
+            GatewayEvent::NewClient(conn_pair) => {
+                let client_id = next_client_id;
+                next_client_id = next_client_id.wrapping_add(1);
+
+                let (sender, receiver) = conn_pair.split();
+                let sender = sink_to_sender(sender, CLIENT_SEND_BUFFER, &spawner);
+
+                let mut c_client_events_sender = client_events_sender.clone();
+                let mut receiver = receiver
+                    .map(move |line| Ok((client_id, Some(line))))
+                    .chain(stream::once(future::ready(Ok((client_id, None)))));
+                spawner
+                    .spawn(async move {
+                        let _ = c_client_events_sender.send_all(&mut receiver).await;
+                    })
+                    .map_err(|_| GatewayError::SpawnError)?;
+
+                info!("gateway_loop(): Client {} connected", client_id);
+                gateway.clients.insert(
+                    client_id,
+                    Client {
+                        sender,
+                        opt_permissions: None,
+                        auth_ticks_left: AUTH_TIMEOUT_TICKS,
+                    },
+                );
+            }
+            GatewayEvent::IncomingClientsClosed => {
+                // We keep serving the already connected clients:
+                warn!("gateway_loop(): Incoming clients closed");
+            }
+            GatewayEvent::Client((client_id, Some(line))) => {
+                if gateway.clients.contains_key(&client_id) {
+                    gateway.handle_client_line(client_id, &line);
+                }
+            }
+            GatewayEvent::Client((client_id, None)) => {
+                if gateway.clients.contains_key(&client_id) {
+                    info!("gateway_loop(): Client {} disconnected", client_id);
+                    gateway.remove_client(client_id);
+                }
+            }
+            GatewayEvent::App(app_message) => gateway.handle_app_message(app_message)?,
+            GatewayEvent::AppClosed => return Err(GatewayError::NodeClosed),
+            GatewayEvent::TimerTick => gateway.handle_timer_tick(),
+            GatewayEvent::TimerClosed => return Err(GatewayError::TimerClosed),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use futures::executor::{block_on, ThreadPool};
+
+    use crypto::test_utils::DummyRandom;
+
+    use common::conn::ConnPair;
+
+    use app::common::{Currency, NamedRelayAddress, NetAddress, PublicKey};
+    use app::conn::{AppRestrictions, ClientResponseRoutes, ResponseRoutesResult};
+    use app::report::{FunderReport, IndexClientReport};
+
+    use proto::app_server::messages::{NodeReportMutation, ReportMutations};
+    use proto::report::messages::FunderReportMutation;
+
+    use crate::gateway::messages::RequestRoutes;
+    use crate::gen::GenCryptoRandom;
+
+    fn dummy_node_report() -> NodeReport {
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key: PublicKey::from(&[0xaa; PublicKey::len()]),
+                relays: Vec::new(),
+                friends: HashMap::new(),
+                exchange_rates: Vec::new(),
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                topology_snapshots: Vec::new(),
+            },
+        }
+    }
+
+    fn create_client() -> (ConnPairString, mpsc::Sender<String>, mpsc::Receiver<String>) {
+        let (gateway_sender, client_receiver) = mpsc::channel(0x10);
+        let (client_sender, gateway_receiver) = mpsc::channel(0x10);
+        (
+            ConnPairString::from_raw(gateway_sender, gateway_receiver),
+            client_sender,
+            client_receiver,
+        )
+    }
+
+    fn request_line(id: u64, method: &str, params: Value) -> String {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}).to_string()
+    }
+
+    fn gateway_request_line(id: u64, gateway_request: &GatewayRequest) -> String {
+        let mut value = serde_json::to_value(gateway_request).unwrap();
+        value["jsonrpc"] = json!("2.0");
+        value["id"] = json!(id);
+        value.to_string()
+    }
+
+    async fn recv_json(receiver: &mut mpsc::Receiver<String>) -> Value {
+        serde_json::from_str(&receiver.next().await.unwrap()).unwrap()
+    }
+
+    struct TestGateway {
+        clients_sender: mpsc::Sender<ConnPairString>,
+        node_sender: mpsc::Sender<AppServerToApp>,
+        node_receiver: mpsc::Receiver<AppToAppServer>,
+        tick_sender: mpsc::Sender<()>,
+    }
+
+    /// Spawn a gateway with two API keys: "config_key" and "routes_key"
+    fn create_test_gateway<S>(spawner: S) -> TestGateway
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        // Communication with the node:
+        let (gateway_app_sender, node_receiver) = mpsc::channel(0x10);
+        let (node_sender, gateway_app_receiver) = mpsc::channel(0x10);
+        let node_permissions = AppPermissions {
+            routes: true,
+            buyer: true,
+            seller: true,
+            config: true,
+            restrictions: AppRestrictions::default(),
+        };
+        let app_conn_tuple = (
+            node_permissions,
+            dummy_node_report(),
+            ConnPair::from_raw(gateway_app_sender, gateway_app_receiver),
+        );
+
+        let mut api_keys = ApiKeys::new();
+        api_keys.insert(
+            "config_key".to_owned(),
+            AppPermissions {
+                routes: false,
+                buyer: false,
+                seller: false,
+                config: true,
+                restrictions: AppRestrictions::default(),
+            },
+        );
+        api_keys.insert(
+            "routes_key".to_owned(),
+            AppPermissions {
+                routes: true,
+                buyer: false,
+                seller: false,
+                config: false,
+                restrictions: AppRestrictions::default(),
+            },
+        );
+
+        let (clients_sender, incoming_clients) = mpsc::channel(0);
+        let (tick_sender, timer_stream) = mpsc::channel::<()>(0);
+        let uid_gen = GenCryptoRandom(DummyRandom::new(&[1u8]));
+        let loop_fut = gateway_loop(
+            incoming_clients,
+            app_conn_tuple,
+            api_keys,
+            timer_stream,
+            uid_gen,
+            spawner.clone(),
+        );
+        spawner
+            .spawn(async move {
+                let _ = loop_fut.await;
+            })
+            .unwrap();
+
+        TestGateway {
+            clients_sender,
+            node_sender,
+            node_receiver,
+            tick_sender,
+        }
+    }
+
+    fn create_request_routes(request_id: Uid) -> GatewayRequest {
+        GatewayRequest::RequestRoutes(RequestRoutes {
+            request_id,
+            currency: Currency::try_from("FST".to_owned()).unwrap(),
+            capacity: 100,
+            source: PublicKey::from(&[0xaa; PublicKey::len()]),
+            destination: PublicKey::from(&[0xcc; PublicKey::len()]),
+            opt_exclude: None,
+        })
+    }
+
+    async fn task_gateway_loop_basic<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let TestGateway {
+            mut clients_sender,
+            mut node_sender,
+            mut node_receiver,
+            tick_sender: _tick_sender,
+        } = create_test_gateway(spawner);
+
+        // Client a connects:
+        let (conn_pair, mut a_sender, mut a_receiver) = create_client();
+        clients_sender.send(conn_pair).await.unwrap();
+
+        // Invalid json:
+        a_sender.send("{".to_owned()).await.unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["error"]["code"], json!(PARSE_ERROR));
+
+        let named_relay_address = NamedRelayAddress {
+            public_key: PublicKey::from(&[0xbb; PublicKey::len()]),
+            address: NetAddress::try_from("relay_address".to_owned()).unwrap(),
+            name: "relay".to_owned(),
+        };
+        let add_relay = GatewayRequest::AddRelay(named_relay_address.clone());
+
+        // Requests are not allowed before authentication:
+        a_sender
+            .send(gateway_request_line(0, &add_relay))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(0));
+        assert_eq!(response["error"]["code"], json!(UNAUTHENTICATED));
+
+        // Unknown API key:
+        a_sender
+            .send(request_line(
+                1,
+                AUTHENTICATE_METHOD,
+                json!({"apiKey": "bad_key"}),
+            ))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["error"]["code"], json!(UNAUTHENTICATED));
+
+        // Authenticate:
+        a_sender
+            .send(request_line(
+                2,
+                AUTHENTICATE_METHOD,
+                json!({"apiKey": "config_key"}),
+            ))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(2));
+        assert_eq!(response["result"]["config"], json!(true));
+        assert_eq!(response["result"]["routes"], json!(false));
+        let notification = recv_json(&mut a_receiver).await;
+        assert_eq!(notification["method"], json!("report"));
+
+        // Unknown method:
+        a_sender
+            .send(request_line(3, "nonExistentMethod", Value::Null))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["error"]["code"], json!(METHOD_NOT_FOUND));
+
+        // Invalid params:
+        a_sender
+            .send(request_line(4, "addRelay", json!({"name": 5})))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["error"]["code"], json!(INVALID_PARAMS));
+
+        // Client a may not request routes:
+        let request_routes = create_request_routes(Uid::from(&[0x11; Uid::len()]));
+        a_sender
+            .send(gateway_request_line(5, &request_routes))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["error"]["code"], json!(PERMISSION_DENIED));
+
+        // Client a adds a relay:
+        a_sender
+            .send(gateway_request_line(6, &add_relay))
+            .await
+            .unwrap();
+        let app_to_app_server = node_receiver.next().await.unwrap();
+        assert_eq!(
+            app_to_app_server.app_request,
+            AppRequest::AddRelay(named_relay_address.clone())
+        );
+
+        // The node reports the change:
+        node_sender
+            .send(AppServerToApp::ReportMutations(ReportMutations {
+                opt_app_request_id: Some(app_to_app_server.app_request_id),
+                mutations: vec![NodeReportMutation::Funder(FunderReportMutation::AddRelay(
+                    named_relay_address,
+                ))],
+            }))
+            .await
+            .unwrap();
+
+        // Client a gets the report mutations, and then the response:
+        let notification = recv_json(&mut a_receiver).await;
+        assert_eq!(notification["method"], json!("reportMutations"));
+        assert_eq!(
+            notification["params"][0]["setRelays"][0]["name"],
+            json!("relay")
+        );
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(6));
+        assert_eq!(response["result"], Value::Null);
+
+        // Client b connects and requests routes:
+        let (conn_pair, mut b_sender, mut b_receiver) = create_client();
+        clients_sender.send(conn_pair).await.unwrap();
+        b_sender
+            .send(request_line(
+                0,
+                AUTHENTICATE_METHOD,
+                json!({"apiKey": "routes_key"}),
+            ))
+            .await
+            .unwrap();
+        // A routes only key does not get the report:
+        let response = recv_json(&mut b_receiver).await;
+        assert_eq!(response["result"]["routes"], json!(true));
+
+        b_sender
+            .send(gateway_request_line(1, &request_routes))
+            .await
+            .unwrap();
+        let app_to_app_server = node_receiver.next().await.unwrap();
+        match app_to_app_server.app_request {
+            AppRequest::RequestRoutes(_) => {}
+            _ => unreachable!(),
+        };
+
+        node_sender
+            .send(AppServerToApp::ReportMutations(ReportMutations {
+                opt_app_request_id: Some(app_to_app_server.app_request_id),
+                mutations: Vec::new(),
+            }))
+            .await
+            .unwrap();
+        let response = recv_json(&mut b_receiver).await;
+        assert_eq!(response["id"], json!(1));
+
+        // Only client b gets the routes:
+        node_sender
+            .send(AppServerToApp::ResponseRoutes(ClientResponseRoutes {
+                request_id: Uid::from(&[0x11; Uid::len()]),
+                result: ResponseRoutesResult::Failure,
+            }))
+            .await
+            .unwrap();
+        let notification = recv_json(&mut b_receiver).await;
+        assert_eq!(notification["method"], json!("responseRoutes"));
+        assert_eq!(notification["params"]["result"], json!("failure"));
+
+        // Client a is still served, and did not get the routes:
+        a_sender
+            .send(request_line(7, "nonExistentMethod", Value::Null))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(7));
+    }
+
+    #[test]
+    fn test_gateway_loop_basic() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_gateway_loop_basic(thread_pool.clone()));
+    }
+
+    async fn task_gateway_loop_request_id_taken<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let TestGateway {
+            mut clients_sender,
+            mut node_sender,
+            mut node_receiver,
+            tick_sender: _tick_sender,
+        } = create_test_gateway(spawner);
+
+        // Clients a and b connect using the same API key:
+        let (conn_pair, mut a_sender, mut a_receiver) = create_client();
+        clients_sender.send(conn_pair).await.unwrap();
+        let (conn_pair, mut b_sender, mut b_receiver) = create_client();
+        clients_sender.send(conn_pair).await.unwrap();
+        for (sender, receiver) in &mut [
+            (&mut a_sender, &mut a_receiver),
+            (&mut b_sender, &mut b_receiver),
+        ] {
+            sender
+                .send(request_line(
+                    0,
+                    AUTHENTICATE_METHOD,
+                    json!({"apiKey": "routes_key"}),
+                ))
+                .await
+                .unwrap();
+            let response = recv_json(receiver).await;
+            assert_eq!(response["result"]["routes"], json!(true));
+        }
+
+        // Client a requests routes:
+        let request_id = Uid::from(&[0x11; Uid::len()]);
+        let request_routes = create_request_routes(request_id.clone());
+        a_sender
+            .send(gateway_request_line(1, &request_routes))
+            .await
+            .unwrap();
+        let app_to_app_server = node_receiver.next().await.unwrap();
+        node_sender
+            .send(AppServerToApp::ReportMutations(ReportMutations {
+                opt_app_request_id: Some(app_to_app_server.app_request_id),
+                mutations: Vec::new(),
+            }))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(1));
+
+        // Client b attempts to use the same request id, to get the routes of client a:
+        b_sender
+            .send(gateway_request_line(1, &request_routes))
+            .await
+            .unwrap();
+        let response = recv_json(&mut b_receiver).await;
+        assert_eq!(response["id"], json!(1));
+        assert_eq!(response["error"]["code"], json!(INVALID_PARAMS));
+
+        // Only client a gets the routes:
+        node_sender
+            .send(AppServerToApp::ResponseRoutes(ClientResponseRoutes {
+                request_id,
+                result: ResponseRoutesResult::Failure,
+            }))
+            .await
+            .unwrap();
+        let notification = recv_json(&mut a_receiver).await;
+        assert_eq!(notification["method"], json!("responseRoutes"));
+
+        // The request id is free again:
+        b_sender
+            .send(gateway_request_line(2, &request_routes))
+            .await
+            .unwrap();
+        let app_to_app_server = node_receiver.next().await.unwrap();
+        match app_to_app_server.app_request {
+            AppRequest::RequestRoutes(_) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_gateway_loop_request_id_taken() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_gateway_loop_request_id_taken(thread_pool.clone()));
+    }
+
+    async fn task_gateway_loop_auth_timeout<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let TestGateway {
+            mut clients_sender,
+            node_sender: _node_sender,
+            node_receiver: _node_receiver,
+            mut tick_sender,
+        } = create_test_gateway(spawner);
+
+        // Client a authenticates:
+        let (conn_pair, mut a_sender, mut a_receiver) = create_client();
+        clients_sender.send(conn_pair).await.unwrap();
+        a_sender
+            .send(request_line(
+                0,
+                AUTHENTICATE_METHOD,
+                json!({"apiKey": "config_key"}),
+            ))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(0));
+        let notification = recv_json(&mut a_receiver).await;
+        assert_eq!(notification["method"], json!("report"));
+
+        // Client b connects, but never authenticates:
+        let (conn_pair, mut b_sender, mut b_receiver) = create_client();
+        clients_sender.send(conn_pair).await.unwrap();
+        b_sender
+            .send(request_line(0, "nonExistentMethod", Value::Null))
+            .await
+            .unwrap();
+        let response = recv_json(&mut b_receiver).await;
+        assert_eq!(response["error"]["code"], json!(UNAUTHENTICATED));
+
+        for _ in 0..AUTH_TIMEOUT_TICKS {
+            tick_sender.send(()).await.unwrap();
+        }
+
+        // Client b is disconnected:
+        assert!(b_receiver.next().await.is_none());
+
+        // Client a is still served:
+        a_sender
+            .send(request_line(1, "nonExistentMethod", Value::Null))
+            .await
+            .unwrap();
+        let response = recv_json(&mut a_receiver).await;
+        assert_eq!(response["id"], json!(1));
+    }
+
+    #[test]
+    fn test_gateway_loop_auth_timeout() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_gateway_loop_auth_timeout(thread_pool.clone()));
+    }
+
+    #[test]
+    fn test_check_permission() {
+        let config_only = AppPermissions {
+            routes: false,
+            buyer: false,
+            seller: false,
+            config: true,
+            restrictions: AppRestrictions::default(),
+        };
+        let public_key = PublicKey::from(&[0xbb; PublicKey::len()]);
+        assert!(check_permission(
+            &AppRequest::SettleFriend(public_key.clone()),
+            &config_only
+        ));
+        assert!(!check_permission(
+            &AppRequest::RequestClosePayment(PaymentId::from(&[0x22; PaymentId::len()])),
+            &config_only
+        ));
+
+        let node_permissions = AppPermissions {
+            routes: true,
+            buyer: true,
+            seller: false,
+            config: false,
+            restrictions: AppRestrictions::default(),
+        };
+        assert_eq!(
+            intersect_permissions(&config_only, &node_permissions),
+            AppPermissions {
+                routes: false,
+                buyer: false,
+                seller: false,
+                config: false,
+                restrictions: AppRestrictions::default(),
+            }
+        );
+    }
+}
@@ -25,13 +25,16 @@ extern crate quickcheck_derive;
 
 pub mod compact_node;
 
+pub mod gateway;
 mod gen;
 pub mod messages;
+pub mod multi_user;
 pub mod server_loop;
 pub mod store;
 
 mod serialize;
 pub mod stcompactlib;
+pub mod stgatewaylib;
 
 // TODO: Possibly remove later?
 pub use gen::GenCryptoRandom;
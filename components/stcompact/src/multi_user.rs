@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
+
+use common::conn::{sink_to_sender, BoxStream, ConnPairString};
+use common::select_streams::select_streams;
+
+use app::common::Uid;
+
+use crate::compact_node::messages::CompactToUser;
+use crate::messages::{
+    NodeId, NodeMode, NodeOpened, NodesStatus, ServerToUser, ServerToUserAck, UserToServerAck,
+};
+use crate::server_loop::ConnPairCompactServer;
+
+/// Amount of messages we buffer for a single user.
+/// A user that falls further behind is disconnected.
+const USER_SEND_BUFFER: usize = 0x100;
+/// Amount of user requests we buffer for the compact server.
+const SERVER_SEND_BUFFER: usize = 0x100;
+
+#[derive(Debug)]
+pub enum MultiUserError {
+    SpawnError,
+}
+
+type UserId = u64;
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+enum MultiUserEvent {
+    NewUser(ConnPairCompactServer),
+    IncomingUsersClosed,
+    User((UserId, Option<UserToServerAck>)),
+    Server(ServerToUserAck),
+    ServerClosed,
+}
+
+/// The latest state reported by the compact server.
+/// Sent to newly connected users, so that they can catch up.
+#[derive(Debug, Default)]
+struct ServerSnapshot {
+    opt_nodes_status: Option<NodesStatus>,
+    open_nodes: HashMap<NodeId, NodeOpened>,
+}
+
+impl ServerSnapshot {
+    fn update(&mut self, server_to_user: &ServerToUser) {
+        match server_to_user {
+            ServerToUser::NodeOpened(node_opened) => {
+                self.open_nodes
+                    .insert(node_opened.node_id.clone(), node_opened.clone());
+            }
+            ServerToUser::NodesStatus(nodes_status) => {
+                // Forget nodes that are not open anymore:
+                self.open_nodes.retain(|node_id, _| {
+                    nodes_status
+                        .values()
+                        .any(|node_status| node_status.mode == NodeMode::Open(node_id.clone()))
+                });
+                self.opt_nodes_status = Some(nodes_status.clone());
+            }
+            ServerToUser::Node(node_id, CompactToUser::Report(compact_report)) => {
+                if let Some(node_opened) = self.open_nodes.get_mut(node_id) {
+                    node_opened.compact_report = compact_report.clone();
+                }
+            }
+            ServerToUser::Node(_, _) => {}
+        }
+    }
+
+    /// Messages that bring a new user up to date
+    fn to_messages(&self) -> Vec<ServerToUser> {
+        let mut messages = Vec::new();
+        if let Some(nodes_status) = &self.opt_nodes_status {
+            messages.push(ServerToUser::NodesStatus(nodes_status.clone()));
+        }
+        for node_opened in self.open_nodes.values() {
+            messages.push(ServerToUser::NodeOpened(node_opened.clone()));
+        }
+        messages
+    }
+}
+
+/// Compare tokens without leaking the position of the first mismatch through timing
+pub(crate) fn is_token_equal(token_a: &str, token_b: &str) -> bool {
+    token_a.len() == token_b.len()
+        && token_a
+            .bytes()
+            .zip(token_b.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Authenticate a user connection. The first line sent by the user must be the token.
+/// Returns the rest of the connection if the authentication was successful.
+pub async fn auth_user_conn_pair(conn_pair: ConnPairString, token: &str) -> Option<ConnPairString> {
+    let (sender, mut receiver) = conn_pair.split();
+    let line = receiver.next().await?;
+    if !is_token_equal(line.trim(), token) {
+        return None;
+    }
+    Some(ConnPairString::from_box(sender, receiver))
+}
+
+async fn multi_user_loop<IU, S>(
+    incoming_users: IU,
+    server_receiver: mpsc::Receiver<ServerToUserAck>,
+    mut server_sender: mpsc::Sender<UserToServerAck>,
+    spawner: S,
+) -> Result<(), MultiUserError>
+where
+    IU: Stream<Item = ConnPairCompactServer> + Unpin + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let (user_events_sender, user_events_receiver) = mpsc::channel(0);
+
+    let incoming_users = incoming_users
+        .map(MultiUserEvent::NewUser)
+        .chain(stream::once(future::ready(
+            MultiUserEvent::IncomingUsersClosed,
+        )));
+
+    let server_receiver = server_receiver
+        .map(MultiUserEvent::Server)
+        .chain(stream::once(future::ready(MultiUserEvent::ServerClosed)));
+
+    let user_events_receiver = user_events_receiver.map(MultiUserEvent::User);
+
+    let mut incoming_events =
+        select_streams![incoming_users, server_receiver, user_events_receiver];
+
+    let mut users: HashMap<UserId, mpsc::Sender<ServerToUserAck>> = HashMap::new();
+    let mut next_user_id: UserId = 0;
+    // Users choose their request ids independently of each other, so the requests are sent to
+    // the server with request ids of our own.
+    // Requests that were not acked yet: server request_id -> (user_id, user request_id)
+    let mut pending_requests: HashMap<Uid, (UserId, Uid)> = HashMap::new();
+    let mut next_request_id: u128 = 0;
+    let mut server_snapshot = ServerSnapshot::default();
+
+    while let Some(event) = incoming_events.next().await {
+        match event {
+            MultiUserEvent::NewUser(conn_pair) => {
+                let user_id = next_user_id;
+                next_user_id = next_user_id.wrapping_add(1);
+
+                let (sender, receiver) = conn_pair.split();
+                let mut user_sender = sink_to_sender(sender, USER_SEND_BUFFER, &spawner);
+
+                // Bring the new user up to date:
+                let is_updated = server_snapshot
+                    .to_messages()
+                    .into_iter()
+                    .all(|server_to_user| {
+                        user_sender
+                            .try_send(ServerToUserAck::ServerToUser(server_to_user))
+                            .is_ok()
+                    });
+                if !is_updated {
+                    warn!(
+                        "multi_user_loop(): Failed sending state to new user {}",
+                        user_id
+                    );
+                    continue;
+                }
+
+                let mut c_user_events_sender = user_events_sender.clone();
+                let mut receiver = receiver
+                    .map(move |user_to_server_ack| Ok((user_id, Some(user_to_server_ack))))
+                    .chain(stream::once(future::ready(Ok((user_id, None)))));
+                spawner
+                    .spawn(async move {
+                        let _ = c_user_events_sender.send_all(&mut receiver).await;
+                    })
+                    .map_err(|_| MultiUserError::SpawnError)?;
+
+                info!("multi_user_loop(): User {} connected", user_id);
+                users.insert(user_id, user_sender);
+            }
+            MultiUserEvent::IncomingUsersClosed => {
+                // We keep serving the already connected users:
+                warn!("multi_user_loop(): Incoming users closed");
+            }
+            MultiUserEvent::User((user_id, Some(user_to_server_ack))) => {
+                if !users.contains_key(&user_id) {
+                    // User was already disconnected:
+                    continue;
+                }
+                let user_request_id = user_to_server_ack.request_id.clone();
+                let is_pending =
+                    pending_requests
+                        .values()
+                        .any(|(pending_user_id, pending_request_id)| {
+                            *pending_user_id == user_id && pending_request_id == &user_request_id
+                        });
+                if is_pending {
+                    warn!(
+                        "multi_user_loop(): User {} sent a request with a pending request_id",
+                        user_id
+                    );
+                    continue;
+                }
+                let request_id = Uid::from(&next_request_id.to_be_bytes());
+                next_request_id = next_request_id.wrapping_add(1);
+                let user_to_server_ack = UserToServerAck {
+                    request_id: request_id.clone(),
+                    inner: user_to_server_ack.inner,
+                };
+                // We never wait for the server here, to avoid a deadlock with a server that is
+                // waiting for us to read its messages:
+                if server_sender.try_send(user_to_server_ack).is_err() {
+                    warn!(
+                        "multi_user_loop(): Server is overloaded. Disconnecting user {}",
+                        user_id
+                    );
+                    users.remove(&user_id);
+                    continue;
+                }
+                pending_requests.insert(request_id, (user_id, user_request_id));
+            }
+            MultiUserEvent::User((user_id, None)) => {
+                if users.remove(&user_id).is_some() {
+                    info!("multi_user_loop(): User {} disconnected", user_id);
+                }
+            }
+            MultiUserEvent::Server(ServerToUserAck::Ack(request_id)) => {
+                // Acks are only sent to the user that sent the request:
+                let (user_id, user_request_id) = match pending_requests.remove(&request_id) {
+                    Some(pending) => pending,
+                    None => {
+                        warn!("multi_user_loop(): Ack for unknown request_id");
+                        continue;
+                    }
+                };
+                if let Some(user_sender) = users.get_mut(&user_id) {
+                    if user_sender
+                        .try_send(ServerToUserAck::Ack(user_request_id))
+                        .is_err()
+                    {
+                        warn!(
+                            "multi_user_loop(): User {} is too slow. Disconnecting",
+                            user_id
+                        );
+                        users.remove(&user_id);
+                    }
+                }
+            }
+            MultiUserEvent::Server(ServerToUserAck::ServerToUser(server_to_user)) => {
+                server_snapshot.update(&server_to_user);
+                // Broadcast to all users:
+                users.retain(|user_id, user_sender| {
+                    let res = user_sender
+                        .try_send(ServerToUserAck::ServerToUser(server_to_user.clone()))
+                        .is_ok();
+                    if !res {
+                        warn!(
+                            "multi_user_loop(): User {} is too slow. Disconnecting",
+                            user_id
+                        );
+                    }
+                    res
+                });
+            }
+            MultiUserEvent::ServerClosed => {
+                info!("multi_user_loop(): Server closed");
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Serve multiple users using a single compact server.
+/// `ServerToUser` messages are broadcast to all connected users, while acks are only sent to the
+/// user that sent the corresponding request. A newly connected user first receives the current
+/// nodes status, and a `NodeOpened` message for every open node.
+///
+/// Returns a connection pair that should be handed to the compact server.
+pub fn multi_user_conn_pair<IU, S>(
+    incoming_users: IU,
+    spawner: S,
+) -> Result<ConnPairCompactServer, MultiUserError>
+where
+    IU: Stream<Item = ConnPairCompactServer> + Unpin + Send + 'static,
+    S: Spawn + Clone + Send + 'static,
+{
+    let (server_sender, receiver) = mpsc::channel(1);
+    let (sender, server_receiver) = mpsc::channel(SERVER_SEND_BUFFER);
+
+    let loop_fut = multi_user_loop(incoming_users, receiver, sender, spawner.clone())
+        .map_err(|e| error!("multi_user_loop() error: {:?}", e))
+        .map(|_| ());
+
+    spawner
+        .spawn(loop_fut)
+        .map_err(|_| MultiUserError::SpawnError)?;
+
+    Ok(ConnPairCompactServer::from_raw(
+        server_sender,
+        server_receiver,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::{block_on, ThreadPool};
+
+    use crate::messages::{NodeName, UserToServer};
+
+    fn create_user() -> (
+        ConnPairCompactServer,
+        mpsc::Sender<UserToServerAck>,
+        mpsc::Receiver<ServerToUserAck>,
+    ) {
+        let (server_sender, user_receiver) = mpsc::channel(1);
+        let (user_sender, server_receiver) = mpsc::channel(1);
+        (
+            ConnPairCompactServer::from_raw(server_sender, server_receiver),
+            user_sender,
+            user_receiver,
+        )
+    }
+
+    fn create_request(index: u8) -> UserToServerAck {
+        UserToServerAck {
+            request_id: Uid::from(&[index; Uid::len()]),
+            inner: UserToServer::RemoveNode(NodeName::new("node".to_owned())),
+        }
+    }
+
+    async fn task_multi_user_basic<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut users_sender, incoming_users) = mpsc::channel(0);
+        let (mut server_sender, mut server_receiver) =
+            multi_user_conn_pair(incoming_users, spawner.clone())
+                .unwrap()
+                .split();
+
+        // The server sends the initial nodes status:
+        server_sender
+            .send(ServerToUserAck::ServerToUser(ServerToUser::NodesStatus(
+                HashMap::new(),
+            )))
+            .await
+            .unwrap();
+
+        // User a connects:
+        let (conn_pair, mut a_sender, mut a_receiver) = create_user();
+        users_sender.send(conn_pair).await.unwrap();
+        assert_eq!(
+            a_receiver.next().await.unwrap(),
+            ServerToUserAck::ServerToUser(ServerToUser::NodesStatus(HashMap::new()))
+        );
+
+        // User b connects, and gets the current nodes status:
+        let (conn_pair, mut b_sender, mut b_receiver) = create_user();
+        users_sender.send(conn_pair).await.unwrap();
+        assert_eq!(
+            b_receiver.next().await.unwrap(),
+            ServerToUserAck::ServerToUser(ServerToUser::NodesStatus(HashMap::new()))
+        );
+
+        // User a sends a request:
+        a_sender.send(create_request(0)).await.unwrap();
+        let request = server_receiver.next().await.unwrap();
+        assert_eq!(request.inner, create_request(0).inner);
+
+        // The server sends an update and an ack:
+        server_sender
+            .send(ServerToUserAck::ServerToUser(ServerToUser::NodesStatus(
+                HashMap::new(),
+            )))
+            .await
+            .unwrap();
+        server_sender
+            .send(ServerToUserAck::Ack(request.request_id))
+            .await
+            .unwrap();
+
+        // Both users get the update, only user a gets the ack:
+        assert_eq!(
+            a_receiver.next().await.unwrap(),
+            ServerToUserAck::ServerToUser(ServerToUser::NodesStatus(HashMap::new()))
+        );
+        assert_eq!(
+            a_receiver.next().await.unwrap(),
+            ServerToUserAck::Ack(Uid::from(&[0; Uid::len()]))
+        );
+        assert_eq!(
+            b_receiver.next().await.unwrap(),
+            ServerToUserAck::ServerToUser(ServerToUser::NodesStatus(HashMap::new()))
+        );
+
+        // User b sends a request:
+        b_sender.send(create_request(1)).await.unwrap();
+        let request = server_receiver.next().await.unwrap();
+        assert_eq!(request.inner, create_request(1).inner);
+        server_sender
+            .send(ServerToUserAck::Ack(request.request_id))
+            .await
+            .unwrap();
+        assert_eq!(
+            b_receiver.next().await.unwrap(),
+            ServerToUserAck::Ack(Uid::from(&[1; Uid::len()]))
+        );
+
+        // Both users send a request with the same request_id.
+        // The server sees two different requests, and every user gets its own ack:
+        a_sender.send(create_request(3)).await.unwrap();
+        let request_a = server_receiver.next().await.unwrap();
+        b_sender.send(create_request(3)).await.unwrap();
+        let request_b = server_receiver.next().await.unwrap();
+        assert_ne!(request_a.request_id, request_b.request_id);
+
+        server_sender
+            .send(ServerToUserAck::Ack(request_b.request_id))
+            .await
+            .unwrap();
+        assert_eq!(
+            b_receiver.next().await.unwrap(),
+            ServerToUserAck::Ack(Uid::from(&[3; Uid::len()]))
+        );
+        server_sender
+            .send(ServerToUserAck::Ack(request_a.request_id))
+            .await
+            .unwrap();
+        assert_eq!(
+            a_receiver.next().await.unwrap(),
+            ServerToUserAck::Ack(Uid::from(&[3; Uid::len()]))
+        );
+
+        // User a disconnects. User b keeps working:
+        drop(a_sender);
+        drop(a_receiver);
+
+        b_sender.send(create_request(2)).await.unwrap();
+        let request = server_receiver.next().await.unwrap();
+        assert_eq!(request.inner, create_request(2).inner);
+        server_sender
+            .send(ServerToUserAck::Ack(request.request_id))
+            .await
+            .unwrap();
+        assert_eq!(
+            b_receiver.next().await.unwrap(),
+            ServerToUserAck::Ack(Uid::from(&[2; Uid::len()]))
+        );
+
+        // Closing the server disconnects all users:
+        drop(server_sender);
+        assert!(b_receiver.next().await.is_none());
+    }
+
+    #[test]
+    fn test_multi_user_basic() {
+        let thread_pool = ThreadPool::new().unwrap();
+        block_on(task_multi_user_basic(thread_pool.clone()));
+    }
+
+    async fn task_auth_user_conn_pair() {
+        let token = "secret-token";
+
+        let (server_sender, _user_receiver) = mpsc::channel::<String>(1);
+        let (mut user_sender, server_receiver) = mpsc::channel::<String>(1);
+        let conn_pair = ConnPairString::from_raw(server_sender, server_receiver);
+        user_sender.send("wrong-token\n".to_owned()).await.unwrap();
+        assert!(auth_user_conn_pair(conn_pair, token).await.is_none());
+
+        let (server_sender, _user_receiver) = mpsc::channel::<String>(1);
+        let (mut user_sender, server_receiver) = mpsc::channel::<String>(2);
+        let conn_pair = ConnPairString::from_raw(server_sender, server_receiver);
+        user_sender.send("secret-token\n".to_owned()).await.unwrap();
+        user_sender.send("first-line".to_owned()).await.unwrap();
+        let (_sender, mut receiver) = auth_user_conn_pair(conn_pair, token).await.unwrap().split();
+        assert_eq!(receiver.next().await.unwrap(), "first-line");
+    }
+
+    #[test]
+    fn test_auth_user_conn_pair() {
+        block_on(task_auth_user_conn_pair());
+    }
+}
@@ -20,10 +20,13 @@ use app::common::{NetAddress, Uid};
 use app::conn::ConnPairApp;
 use app_client::app_connect_to_node;
 
-use proto::consts::{KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY};
+use proto::consts::{
+    ANNOUNCE_CREDIT_LIMITS, KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH,
+    REBALANCE_BACKOFF_TICKS, REBALANCE_STAGE_TIMEOUT_TICKS, TICKS_TO_REKEY,
+};
 
-use node::{node, ConnPairServer, IncomingAppConnection, NodeConfig};
-use proto::app_server::messages::{AppPermissions, NodeReport};
+use node::{node, ConnPairServer, IncomingAppConnection, NodeConfig, RoutesPrivacy};
+use proto::app_server::messages::{AppPermissions, AppRestrictions, NodeReport};
 
 use crate::messages::{
     CreateNode, CreateNodeLocal, CreateNodeRemote, NodeId, NodeMode, NodeName, NodeOpened,
@@ -523,6 +526,21 @@ const NODE_CONFIG: NodeConfig = NodeConfig {
     max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
     /// Maximum amount of relays a node may use.
     max_node_relays: MAX_NODE_RELAYS,
+    /// The amount of ticks we wait before attempting to rebalance a mutual credit again.
+    rebalance_backoff_ticks: REBALANCE_BACKOFF_TICKS,
+    /// The amount of ticks we wait for a response during a rebalance before giving up.
+    rebalance_stage_timeout_ticks: REBALANCE_STAGE_TIMEOUT_TICKS,
+    /// Announce to friends the credit we are willing to extend to them.
+    announce_credit_limits: ANNOUNCE_CREDIT_LIMITS,
+    /// Privacy settings for routes requests sent to index servers.
+    routes_privacy: RoutesPrivacy {
+        num_decoys: 0,
+        round_capacity: false,
+    },
+    /// Amount of ticks between syncs of route topology snapshots from the index server.
+    opt_topology_sync_ticks: None,
+    /// Report outcomes of routes used for sending credits to the index server.
+    report_route_outcomes: false,
 };
 
 async fn open_node_local<ST, R, C, S>(
@@ -543,10 +561,13 @@ where
         buyer: true,
         seller: true,
         config: true,
+        restrictions: AppRestrictions::default(),
     };
     let (report_sender, report_receiver) =
         oneshot::channel::<(NodeReport, oneshot::Sender<ConnPairServer<NetAddress>>)>();
+    // The compact server connects to the node as an app, using the identity of the node:
     let incoming_app_connection = IncomingAppConnection {
+        app_public_key: local.node_state.funder_state.local_public_key.clone(),
         app_permissions: app_permissions.clone(),
         report_sender,
     };
@@ -577,6 +598,8 @@ where
         secure_connector,
         encrypt_keepalive,
         incoming_apps,
+        // The permissions of the compact server never change:
+        stream::empty(),
         server_state.rng.clone(),
         server_state.spawner.clone(),
     )
@@ -1,4 +1,6 @@
-use std::path::PathBuf;
+use std::fs;
+use std::net::{Shutdown, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use derive_more::From;
@@ -7,27 +9,39 @@ use futures::task::{Spawn, SpawnExt};
 
 use futures::channel::mpsc;
 use futures::AsyncWriteExt;
-use futures::{FutureExt, SinkExt, StreamExt};
+use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
 use structopt::StructOpt;
 
+use async_std::io::prelude::{BufReadExt, ReadExt};
+use async_std::io::BufReader;
+use async_std::net::{TcpListener, TcpStream};
+
 use common::conn::ConnPairString;
 use common::int_convert::usize_to_u64;
 
 use crypto::rand::system_random;
 
-use timer::create_timer;
+use timer::utils::{future_timeout, sleep_ticks};
+use timer::{create_timer, TimerClient};
 
 use net::TcpConnector;
 
 use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
 
+use crate::multi_user::{auth_user_conn_pair, multi_user_conn_pair, MultiUserError};
 use crate::serialize::{serialize_conn_pair, SerializeConnError};
-use crate::server_loop::{compact_server_loop, ServerError};
+use crate::server_loop::{compact_server_loop, ConnPairCompactServer, ServerError};
 use crate::store::open_file_store;
 
 /// Amount of ticks to wait for the next attempt to reconnect to a remote node
 const TICKS_TO_CONNECT: usize = 8;
+/// Amount of ticks a connected user has to send its authentication token
+const AUTH_TIMEOUT_TICKS: usize = 16;
+/// Amount of ticks to wait before accepting again after a failed accept
+const ACCEPT_BACKOFF_TICKS: usize = 2;
+/// Maximum length (in bytes) of a single line received from a user
+const MAX_LINE_LENGTH: usize = 2 * MAX_FRAME_LENGTH;
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, From)]
@@ -36,7 +50,12 @@ pub enum StCompactError {
     OpenFileStoreError,
     ServerError(ServerError),
     SerializeConnError(SerializeConnError),
+    MultiUserError(MultiUserError),
     SpawnError,
+    MissingTokenFile,
+    LoadTokenError,
+    ListenError,
+    NonLoopbackListenAddress,
 }
 
 /// stcompact: Offset Compact
@@ -51,6 +70,14 @@ pub struct StCompactCmd {
     /// If directory is nonexistent, a new store will be created.
     #[structopt(parse(from_os_str), short = "s", long = "store")]
     pub store_path: PathBuf,
+    /// Listening address for users. Must be a loopback address, as communication is not encrypted.
+    /// If not specified, a single user is served through stdin/stdout.
+    #[structopt(short = "l", long = "listen")]
+    pub opt_listen: Option<SocketAddr>,
+    /// Path of a file containing the token users authenticate with.
+    /// Required when listening. A connected user must send the token as its first line.
+    #[structopt(parse(from_os_str), short = "t", long = "tokenfile")]
+    pub opt_token_file: Option<PathBuf>,
 }
 
 fn create_stdio_conn_pair<S>(spawner: &S) -> Result<ConnPairString, StCompactError>
@@ -99,6 +126,138 @@ where
     Ok(ConnPairString::from_raw(server_sender, server_receiver))
 }
 
+/// Convert a tcp stream into a communication of lines
+fn create_tcp_conn_pair<S>(
+    tcp_stream: TcpStream,
+    spawner: &S,
+) -> Result<ConnPairString, StCompactError>
+where
+    S: Spawn,
+{
+    let mut writer = tcp_stream.clone();
+    let mut reader = BufReader::new(tcp_stream);
+
+    let (server_sender, mut receiver) = mpsc::channel::<String>(1);
+    let (mut sender, server_receiver) = mpsc::channel::<String>(1);
+
+    let send_fut = async move {
+        while let Some(line) = receiver.next().await {
+            writer.write_all(line.as_bytes()).await.ok()?;
+            writer.write_all(b"\n").await.ok()?;
+        }
+        // The server closed the connection. This will also stop the receiving side:
+        writer.shutdown(Shutdown::Both).ok()?;
+        Some(())
+    };
+    spawner
+        .spawn(send_fut.map(|_: Option<()>| ()))
+        .map_err(|_| StCompactError::SpawnError)?;
+
+    let recv_fut = async move {
+        loop {
+            // Read one more byte than allowed, to detect lines that are too long:
+            let mut line = Vec::new();
+            let mut limited_reader = (&mut reader).take(usize_to_u64(MAX_LINE_LENGTH + 1).unwrap());
+            if limited_reader.read_until(b'\n', &mut line).await.ok()? == 0 {
+                // Connection was closed:
+                return Some(());
+            }
+            // Note: Lines are forwarded without the trailing newline:
+            if line.last() == Some(&b'\n') {
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+            }
+            if line.len() > MAX_LINE_LENGTH {
+                warn!("create_tcp_conn_pair(): Received line is too long. Closing connection.");
+                return None;
+            }
+            sender.send(String::from_utf8(line).ok()?).await.ok()?;
+        }
+    };
+    spawner
+        .spawn(recv_fut.map(|_: Option<()>| ()))
+        .map_err(|_| StCompactError::SpawnError)?;
+
+    Ok(ConnPairString::from_raw(server_sender, server_receiver))
+}
+
+/// Load the users authentication token from a file
+fn load_token(token_file: &Path) -> Result<String, StCompactError> {
+    let token = fs::read_to_string(token_file)
+        .map_err(|_| StCompactError::LoadTokenError)?
+        .trim()
+        .to_owned();
+    if token.is_empty() {
+        return Err(StCompactError::LoadTokenError);
+    }
+    Ok(token)
+}
+
+/// Accept user connections, and forward the connections of authenticated users
+async fn accept_users<S>(
+    tcp_listener: TcpListener,
+    token: String,
+    users_sender: mpsc::Sender<ConnPairCompactServer>,
+    timer_client: TimerClient,
+    spawner: S,
+) -> Result<(), StCompactError>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    loop {
+        let (tcp_stream, address) = match tcp_listener.accept().await {
+            Ok(tcp_stream_address) => tcp_stream_address,
+            Err(e) => {
+                // Accept may keep failing (For example, when we run out of file descriptors).
+                // We wait a bit to avoid busy looping:
+                warn!("accept_users(): Failed accepting a connection: {:?}", e);
+                if sleep_ticks(ACCEPT_BACKOFF_TICKS, timer_client.clone())
+                    .await
+                    .is_err()
+                {
+                    return Err(StCompactError::CreateTimerError);
+                }
+                continue;
+            }
+        };
+        let conn_pair = create_tcp_conn_pair(tcp_stream, &spawner)?;
+
+        let c_token = token.clone();
+        let c_spawner = spawner.clone();
+        let mut c_users_sender = users_sender.clone();
+        let mut c_timer_client = timer_client.clone();
+        let auth_fut = async move {
+            let timer_stream = match c_timer_client
+                .request_timer_stream("accept_users".to_owned())
+                .await
+            {
+                Ok(timer_stream) => timer_stream,
+                Err(_) => return,
+            };
+            let auth_fut = Box::pin(auth_user_conn_pair(conn_pair, &c_token));
+            let conn_pair = match future_timeout(auth_fut, timer_stream, AUTH_TIMEOUT_TICKS).await {
+                Some(Some(conn_pair)) => conn_pair,
+                Some(None) => {
+                    warn!("accept_users(): Authentication failed for {:?}", address);
+                    return;
+                }
+                None => {
+                    warn!("accept_users(): Authentication timed out for {:?}", address);
+                    return;
+                }
+            };
+            if let Ok(conn_pair) = serialize_conn_pair(conn_pair, &c_spawner) {
+                let _ = c_users_sender.send(conn_pair).await;
+            }
+        };
+        spawner
+            .spawn(auth_fut)
+            .map_err(|_| StCompactError::SpawnError)?;
+    }
+}
+
 pub async fn stcompact<S, FS>(
     st_compact_cmd: StCompactCmd,
     spawner: S,
@@ -108,7 +267,11 @@ where
     S: Spawn + Clone + Send + Sync + 'static,
     FS: Spawn + Clone + Send + Sync + 'static,
 {
-    let StCompactCmd { store_path } = st_compact_cmd;
+    let StCompactCmd {
+        store_path,
+        opt_listen,
+        opt_token_file,
+    } = st_compact_cmd;
 
     // Get a timer client:
     let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
@@ -125,11 +288,45 @@ where
         .await
         .map_err(|_| StCompactError::OpenFileStoreError)?;
 
-    // Get line (string) communication with stdio:
-    let stdio_conn_pair = create_stdio_conn_pair(&spawner)?;
+    let conn_pair = match opt_listen {
+        None => {
+            // Get line (string) communication with stdio:
+            let stdio_conn_pair = create_stdio_conn_pair(&spawner)?;
+
+            // Serialize communication:
+            serialize_conn_pair(stdio_conn_pair, &spawner)?
+        }
+        Some(listen_address) => {
+            let token_file = opt_token_file.ok_or(StCompactError::MissingTokenFile)?;
+            let token = load_token(&token_file)?;
+
+            // Users communication is not encrypted, so we only allow local users:
+            if !listen_address.ip().is_loopback() {
+                return Err(StCompactError::NonLoopbackListenAddress);
+            }
+
+            let tcp_listener = TcpListener::bind(listen_address)
+                .await
+                .map_err(|_| StCompactError::ListenError)?;
 
-    // Serialize communication:
-    let conn_pair = serialize_conn_pair(stdio_conn_pair, &spawner)?;
+            let (users_sender, incoming_users) = mpsc::channel(0);
+            let accept_fut = accept_users(
+                tcp_listener,
+                token,
+                users_sender,
+                timer_client.clone(),
+                spawner.clone(),
+            )
+            .map_err(|e| error!("accept_users() error: {:?}", e))
+            .map(|_| ());
+            spawner
+                .spawn(accept_fut)
+                .map_err(|_| StCompactError::SpawnError)?;
+
+            // Serve all users through a single connection to the compact server:
+            multi_user_conn_pair(incoming_users, spawner.clone())?
+        }
+    };
 
     Ok(compact_server_loop(
         conn_pair,
@@ -0,0 +1,244 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use derive_more::From;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
+
+use structopt::StructOpt;
+
+use async_std::net::{TcpListener, TcpStream};
+use async_tungstenite::tungstenite::Message;
+
+use common::conn::ConnPairString;
+use common::int_convert::usize_to_u64;
+
+use crypto::rand::system_random;
+
+use timer::utils::future_timeout;
+use timer::{create_timer, TimerClient};
+
+use proto::consts::TICK_MS;
+
+use app::conn::{connect, identity_from_file};
+use app::file::NodeAddressFile;
+use app::ser_utils::{deserialize_from_string, StringSerdeError};
+
+use crate::gateway::{gateway_loop, ApiKeys, GatewayError};
+use crate::gen::GenCryptoRandom;
+
+/// Amount of ticks a client has to complete the WebSocket handshake
+const HANDSHAKE_TIMEOUT_TICKS: usize = 16;
+
+#[derive(Debug, From)]
+pub enum StGatewayError {
+    IdFileDoesNotExist,
+    NodeTicketFileDoesNotExist,
+    SpawnIdentityServiceError,
+    ConnectionError,
+    CreateTimerError,
+    RequestTimerStreamError,
+    LoadApiKeysError,
+    ListenError,
+    SpawnError,
+    GatewayError(GatewayError),
+    IoError(std::io::Error),
+    StringSerdeError(StringSerdeError),
+}
+
+/// stgateway: Offset Gateway
+/// Exposes the node's app protocol as JSON-RPC 2.0 over WebSocket
+#[derive(Debug, StructOpt)]
+#[structopt(name = "stgateway")]
+pub struct StGatewayCmd {
+    /// StGateway app identity file path
+    #[structopt(parse(from_os_str), short = "I", long = "idfile")]
+    pub idfile: PathBuf,
+    /// Node ticket file path
+    #[structopt(parse(from_os_str), short = "T", long = "ticket")]
+    pub node_ticket: PathBuf,
+    /// Listening address for WebSocket clients
+    #[structopt(short = "l", long = "listen")]
+    pub listen: SocketAddr,
+    /// Path of a JSON file mapping every API key to its permissions
+    #[structopt(parse(from_os_str), short = "k", long = "keys")]
+    pub api_keys_file: PathBuf,
+}
+
+/// Load API keys and their permissions from a file
+fn load_api_keys(api_keys_file: &Path) -> Result<ApiKeys, StGatewayError> {
+    let data = fs::read_to_string(api_keys_file).map_err(|_| StGatewayError::LoadApiKeysError)?;
+    serde_json::from_str(&data).map_err(|_| StGatewayError::LoadApiKeysError)
+}
+
+/// Perform a WebSocket handshake, and convert the connection into a communication of
+/// text messages
+async fn create_ws_conn_pair<S>(
+    tcp_stream: TcpStream,
+    spawner: &S,
+) -> Result<ConnPairString, StGatewayError>
+where
+    S: Spawn,
+{
+    let ws_stream = async_tungstenite::accept_async(tcp_stream)
+        .await
+        .map_err(|_| StGatewayError::ConnectionError)?;
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+    let (server_sender, mut receiver) = mpsc::channel::<String>(1);
+    let (mut sender, server_receiver) = mpsc::channel::<String>(1);
+
+    let send_fut = async move {
+        while let Some(text) = receiver.next().await {
+            ws_sender.send(Message::Text(text)).await.ok()?;
+        }
+        // The server closed the connection:
+        ws_sender.close().await.ok()?;
+        Some(())
+    };
+    spawner
+        .spawn(send_fut.map(|_: Option<()>| ()))
+        .map_err(|_| StGatewayError::SpawnError)?;
+
+    let recv_fut = async move {
+        while let Some(message) = ws_receiver.next().await {
+            match message.ok()? {
+                Message::Text(text) => sender.send(text).await.ok()?,
+                Message::Close(_) => break,
+                // Pings are answered by the WebSocket implementation:
+                _ => {}
+            }
+        }
+        Some(())
+    };
+    spawner
+        .spawn(recv_fut.map(|_: Option<()>| ()))
+        .map_err(|_| StGatewayError::SpawnError)?;
+
+    Ok(ConnPairString::from_raw(server_sender, server_receiver))
+}
+
+/// Accept WebSocket clients, and forward their connections
+async fn accept_clients<S>(
+    tcp_listener: TcpListener,
+    clients_sender: mpsc::Sender<ConnPairString>,
+    timer_client: TimerClient,
+    spawner: S,
+) -> Result<(), StGatewayError>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    loop {
+        let (tcp_stream, address) = match tcp_listener.accept().await {
+            Ok(tcp_stream_address) => tcp_stream_address,
+            Err(e) => {
+                warn!("accept_clients(): Failed accepting a connection: {:?}", e);
+                continue;
+            }
+        };
+
+        let c_spawner = spawner.clone();
+        let mut c_clients_sender = clients_sender.clone();
+        let mut c_timer_client = timer_client.clone();
+        let handshake_fut = async move {
+            let timer_stream = match c_timer_client
+                .request_timer_stream("accept_clients".to_owned())
+                .await
+            {
+                Ok(timer_stream) => timer_stream,
+                Err(_) => return,
+            };
+            let ws_fut = Box::pin(create_ws_conn_pair(tcp_stream, &c_spawner));
+            match future_timeout(ws_fut, timer_stream, HANDSHAKE_TIMEOUT_TICKS).await {
+                Some(Ok(conn_pair)) => {
+                    let _ = c_clients_sender.send(conn_pair).await;
+                }
+                Some(Err(e)) => warn!(
+                    "accept_clients(): Handshake with {:?} failed: {:?}",
+                    address, e
+                ),
+                None => warn!("accept_clients(): Handshake with {:?} timed out", address),
+            }
+        };
+        spawner
+            .spawn(handshake_fut)
+            .map_err(|_| StGatewayError::SpawnError)?;
+    }
+}
+
+pub async fn stgateway<S>(st_gateway_cmd: StGatewayCmd, spawner: S) -> Result<(), StGatewayError>
+where
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let StGatewayCmd {
+        idfile,
+        node_ticket,
+        listen,
+        api_keys_file,
+    } = st_gateway_cmd;
+
+    // Get application's identity:
+    if !idfile.exists() {
+        return Err(StGatewayError::IdFileDoesNotExist);
+    }
+
+    // Get node's connection information (node-ticket):
+    if !node_ticket.exists() {
+        return Err(StGatewayError::NodeTicketFileDoesNotExist);
+    }
+
+    let node_address_file: NodeAddressFile =
+        deserialize_from_string(&fs::read_to_string(&node_ticket)?)?;
+
+    let api_keys = load_api_keys(&api_keys_file)?;
+
+    // Get a timer client:
+    let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
+    let mut timer_client =
+        create_timer(dur, spawner.clone()).map_err(|_| StGatewayError::CreateTimerError)?;
+    // Used to disconnect clients that do not authenticate in time:
+    let timer_stream = timer_client
+        .request_timer_stream("stgateway".to_owned())
+        .await
+        .map_err(|_| StGatewayError::RequestTimerStreamError)?;
+
+    // Spawn identity service:
+    let app_identity_client = identity_from_file(&idfile, spawner.clone())
+        .map_err(|_| StGatewayError::SpawnIdentityServiceError)?;
+
+    // Connect to node:
+    let app_conn_tuple = connect(
+        node_address_file.public_key,
+        node_address_file.address,
+        app_identity_client,
+        spawner.clone(),
+    )
+    .await
+    .map_err(|_| StGatewayError::ConnectionError)?;
+
+    let tcp_listener = TcpListener::bind(listen)
+        .await
+        .map_err(|_| StGatewayError::ListenError)?;
+
+    let (clients_sender, incoming_clients) = mpsc::channel(0);
+    let accept_fut = accept_clients(tcp_listener, clients_sender, timer_client, spawner.clone())
+        .map_err(|e| error!("accept_clients() error: {:?}", e))
+        .map(|_| ());
+    spawner
+        .spawn(accept_fut)
+        .map_err(|_| StGatewayError::SpawnError)?;
+
+    Ok(gateway_loop(
+        incoming_clients,
+        app_conn_tuple,
+        api_keys,
+        timer_stream,
+        GenCryptoRandom(system_random()),
+        spawner,
+    )
+    .await?)
+}
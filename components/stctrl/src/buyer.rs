@@ -2,6 +2,7 @@ use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use derive_more::From;
 
@@ -11,14 +12,19 @@ use futures::stream::StreamExt;
 use structopt::StructOpt;
 
 use app::common::{
-    Currency, InvoiceId, MultiRoute, PaymentId, PaymentStatus, PaymentStatusSuccess, PublicKey, Uid,
+    Commit, Currency, InvoiceId, MultiRoute, PaymentId, PaymentStatus, PaymentStatusSuccess,
+    PublicKey, Uid,
 };
 use app::conn::{
     self, AppServerToApp, AppToAppServer, ConnPairApp, RequestResult, ResponseRoutesResult,
 };
 use app::gen::{gen_payment_id, gen_uid};
 use app::report::NodeReport;
-use app::ser_utils::{deserialize_from_string, serialize_to_string, StringSerdeError};
+use app::ser_utils::{
+    deserialize_from_string, serialize_to_string, uri_to_invoice, StringSerdeError,
+    INVOICE_URI_SCHEME,
+};
+use app::verify::verify_signed_invoice;
 
 use crate::file::{CommitFile, InvoiceFile, PaymentFile, ReceiptFile};
 
@@ -27,15 +33,21 @@ use route::choose_multi_route;
 /// Pay an invoice
 #[derive(Clone, Debug, StructOpt)]
 pub struct PayInvoiceCmd {
-    /// Path to invoice file to pay
+    /// Path to invoice file to pay (An invoice file, or a file containing an invoice URI)
     #[structopt(parse(from_os_str), short = "i", long = "invoice")]
-    pub invoice_path: PathBuf,
+    pub opt_invoice_path: Option<PathBuf>,
+    /// Invoice URI to pay (Can be used instead of an invoice file)
+    #[structopt(short = "u", long = "uri")]
+    pub opt_invoice_uri: Option<String>,
     /// Output payment file (Used to track the payment)
     #[structopt(parse(from_os_str), short = "p", long = "payment")]
     pub payment_path: PathBuf,
     /// Output commit file
     #[structopt(parse(from_os_str), short = "c", long = "commit")]
     pub commit_path: PathBuf,
+    /// Deliver the commit to the seller through the network (The commit file is still created)
+    #[structopt(short = "d", long = "deliver")]
+    pub deliver: bool,
 }
 
 /// Check payment status (And obtain receipt if successful)
@@ -52,7 +64,7 @@ pub struct PaymentStatusCmd {
 /// Funds sending related commands
 #[derive(Clone, Debug, StructOpt)]
 pub enum BuyerCmd {
-    /// Pay an invoice (Using an invoice file)
+    /// Pay an invoice (Using an invoice file or an invoice URI)
     #[structopt(name = "pay-invoice")]
     PayInvoice(PayInvoiceCmd),
     #[structopt(name = "payment-status")]
@@ -75,9 +87,15 @@ pub enum BuyerError {
     StoreReceiptError,
     ReceiptAckError,
     LoadInvoiceError,
+    InvoiceSourceError,
+    InvalidInvoiceUri,
+    InvalidInvoiceSignature,
+    InvoiceExpired,
+    SystemTimeError,
     WriteError,
     CreatePaymentFailed,
     CreateTransactionFailed,
+    DeliverCommitError,
     StoreCommitError,
     RequestClosePaymentError,
     AckClosePaymentError,
@@ -273,6 +291,82 @@ async fn ack_close_payment(
     Err(BuyerError::AckClosePaymentError)
 }
 
+/// Send a commit to the seller, along the route of the transaction that completed the payment.
+async fn deliver_commit(
+    conn_pair: &mut ConnPairApp,
+    request_id: Uid,
+    commit: Commit,
+) -> Result<(), BuyerError> {
+    let app_request = conn::buyer::deliver_commit(request_id, commit);
+    let app_request_id = gen_uid();
+    let app_to_app_server = AppToAppServer {
+        app_request_id: app_request_id.clone(),
+        app_request,
+    };
+
+    conn_pair
+        .sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| BuyerError::DeliverCommitError)?;
+
+    while let Some(app_server_to_app) = conn_pair.receiver.next().await {
+        if let AppServerToApp::ReportMutations(report_mutations) = app_server_to_app {
+            if let Some(cur_app_request_id) = report_mutations.opt_app_request_id {
+                if cur_app_request_id == app_request_id {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Err(BuyerError::DeliverCommitError)
+}
+
+/// Load an invoice from an invoice URI.
+/// Makes sure that the invoice was signed by the seller, and that it did not expire.
+fn load_invoice_uri(invoice_uri: &str) -> Result<InvoiceFile, BuyerError> {
+    let signed_invoice = uri_to_invoice(invoice_uri).map_err(|_| BuyerError::InvalidInvoiceUri)?;
+    if !verify_signed_invoice(&signed_invoice) {
+        return Err(BuyerError::InvalidInvoiceSignature);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| BuyerError::SystemTimeError)?
+        .as_secs();
+    if signed_invoice.valid_until < now {
+        return Err(BuyerError::InvoiceExpired);
+    }
+
+    Ok(InvoiceFile {
+        invoice_id: signed_invoice.invoice_id,
+        currency: signed_invoice.currency,
+        dest_public_key: signed_invoice.dest_public_key,
+        dest_payment: signed_invoice.total_dest_payment,
+    })
+}
+
+/// Load an invoice from either an invoice file (Possibly containing an invoice URI), or an
+/// invoice URI.
+fn load_invoice(
+    opt_invoice_path: Option<PathBuf>,
+    opt_invoice_uri: Option<String>,
+) -> Result<InvoiceFile, BuyerError> {
+    match (opt_invoice_path, opt_invoice_uri) {
+        (Some(invoice_path), None) => {
+            let data = fs::read_to_string(&invoice_path)?;
+            if data.trim().starts_with(INVOICE_URI_SCHEME) {
+                load_invoice_uri(&data)
+            } else {
+                Ok(deserialize_from_string(&data)?)
+            }
+        }
+        (None, Some(invoice_uri)) => load_invoice_uri(&invoice_uri),
+        _ => Err(BuyerError::InvoiceSourceError),
+    }
+}
+
 /// Pay an invoice
 async fn buyer_pay_invoice(
     pay_invoice_cmd: PayInvoiceCmd,
@@ -281,9 +375,11 @@ async fn buyer_pay_invoice(
     writer: &mut impl io::Write,
 ) -> Result<(), BuyerError> {
     let PayInvoiceCmd {
-        invoice_path,
+        opt_invoice_path,
+        opt_invoice_uri,
         payment_path,
         commit_path,
+        deliver,
     } = pay_invoice_cmd;
 
     // Make sure that we will be able to write the Payment file
@@ -298,7 +394,7 @@ async fn buyer_pay_invoice(
         return Err(BuyerError::CommitFileAlreadyExists);
     }
 
-    let invoice_file: InvoiceFile = deserialize_from_string(&fs::read_to_string(&invoice_path)?)?;
+    let invoice_file = load_invoice(opt_invoice_path, opt_invoice_uri)?;
 
     let multi_routes = request_routes(
         &mut conn_pair,
@@ -392,7 +488,7 @@ async fn buyer_pay_invoice(
 
             match transaction_result.result {
                 RequestResult::Complete(commit) => {
-                    opt_commit = Some(commit);
+                    opt_commit = Some((transaction_result.request_id, commit));
                     break;
                 }
                 RequestResult::Success => {}
@@ -402,14 +498,19 @@ async fn buyer_pay_invoice(
     }
 
     // We expect that some transaction returned with "Complete" signal:
-    let commit = if let Some(commit) = opt_commit {
-        commit
+    let (request_id, commit) = if let Some(request_id_commit) = opt_commit {
+        request_id_commit
     } else {
         return Err(BuyerError::PaymentIncomplete);
     };
 
     writeln!(writer, "Payment successful!").map_err(|_| BuyerError::WriteError)?;
 
+    if deliver {
+        deliver_commit(&mut conn_pair, request_id, commit.clone()).await?;
+        writeln!(writer, "Commit was sent to the seller.").map_err(|_| BuyerError::WriteError)?;
+    }
+
     let commit_file = CommitFile::from(commit);
 
     // Store Commit to file:
@@ -9,7 +9,10 @@ use structopt::StructOpt;
 
 use derive_more::From;
 
-use app::common::{Currency, NamedIndexServerAddress, NamedRelayAddress, Rate, RelayAddress};
+use app::common::{
+    Currency, FeeCurve, FeePolicy, NamedIndexServerAddress, NamedRelayAddress, Rate,
+    RebalancePolicy, RelayAddress,
+};
 use app::conn::{self, AppRequest, AppServerToApp, AppToAppServer, ConnPairApp};
 use app::gen::gen_uid;
 use app::report::{ChannelStatusReport, NodeReport};
@@ -158,6 +161,75 @@ pub struct SetFriendCurrencyRateCmd {
     pub add: u32,
 }
 
+/// Set a dynamic fee policy for a friend's currency.
+/// The rate moves from the min rate to the max rate as the friend's debt grows.
+#[derive(Clone, Debug, StructOpt)]
+pub struct SetFriendCurrencyFeePolicyCmd {
+    /// Friend name
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+    /// Currency to set fee policy
+    #[structopt(long = "currency", short = "c")]
+    pub currency_name: String,
+    /// Multiplier when the friend owes us nothing
+    #[structopt(long = "min-mul")]
+    pub min_mul: u32,
+    /// Adder when the friend owes us nothing
+    #[structopt(long = "min-add")]
+    pub min_add: u32,
+    /// Multiplier when the friend's debt reaches the max debt
+    #[structopt(long = "max-mul")]
+    pub max_mul: u32,
+    /// Adder when the friend's debt reaches the max debt
+    #[structopt(long = "max-add")]
+    pub max_add: u32,
+    /// Use a quadratic curve (Default is linear)
+    #[structopt(long = "quadratic", short = "q")]
+    pub quadratic: bool,
+}
+
+/// Remove a dynamic fee policy for a friend's currency.
+/// The static rate will be used instead.
+#[derive(Clone, Debug, StructOpt)]
+pub struct ClearFriendCurrencyFeePolicyCmd {
+    /// Friend name
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+    /// Currency to clear fee policy
+    #[structopt(long = "currency", short = "c")]
+    pub currency_name: String,
+}
+
+/// Enable automatic rebalancing for a friend's currency.
+/// When the balance with the friend passes the threshold, the node will pay itself along a loop
+/// to bring the balance back to zero.
+#[derive(Clone, Debug, StructOpt)]
+pub struct SetFriendCurrencyRebalanceCmd {
+    /// Friend name
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+    /// Currency to rebalance
+    #[structopt(long = "currency", short = "c")]
+    pub currency_name: String,
+    /// Absolute balance above which rebalancing is attempted
+    #[structopt(long = "threshold", short = "t")]
+    pub threshold: u128,
+    /// Maximum amount of fees to pay for a single rebalance
+    #[structopt(long = "fees", short = "f")]
+    pub max_fees: u128,
+}
+
+/// Disable automatic rebalancing for a friend's currency.
+#[derive(Clone, Debug, StructOpt)]
+pub struct ClearFriendCurrencyRebalanceCmd {
+    /// Friend name
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+    /// Currency to stop rebalancing
+    #[structopt(long = "currency", short = "c")]
+    pub currency_name: String,
+}
+
 /// Remove a currency from the set of currencies we are willing to trade
 /// with a remote friend.
 /// This operation will succeed only if we do not already have an active channel trading this
@@ -182,6 +254,47 @@ pub struct ResetFriendCmd {
     pub friend_name: String,
 }
 
+/// Cooperatively close the relationship with a friend.
+/// Once pending transactions are drained, both sides sign a final settlement statement.
+#[derive(Clone, Debug, StructOpt)]
+pub struct SettleFriendCmd {
+    /// Friend name to settle with
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+}
+
+/// Set a local exchange rate between two currencies.
+/// `x` credits of source currency are worth `(x * mul) / 2^32` credits of destination currency.
+#[derive(Clone, Debug, StructOpt)]
+pub struct SetExchangeRateCmd {
+    /// Source currency
+    #[structopt(long = "src", short = "s")]
+    pub src_currency_name: String,
+    /// Destination currency
+    #[structopt(long = "dest", short = "d")]
+    pub dest_currency_name: String,
+    /// Multiplier (dest = (src * mul) / 2^32)
+    #[structopt(long = "mul", short = "m")]
+    pub mul: u64,
+    /// Beginning of validity window (Seconds since the Unix epoch)
+    #[structopt(long = "from", short = "f")]
+    pub valid_from: u64,
+    /// End of validity window (Seconds since the Unix epoch)
+    #[structopt(long = "until", short = "u")]
+    pub valid_until: u64,
+}
+
+/// Remove a local exchange rate between two currencies.
+#[derive(Clone, Debug, StructOpt)]
+pub struct RemoveExchangeRateCmd {
+    /// Source currency
+    #[structopt(long = "src", short = "s")]
+    pub src_currency_name: String,
+    /// Destination currency
+    #[structopt(long = "dest", short = "d")]
+    pub dest_currency_name: String,
+}
+
 #[derive(Clone, Debug, StructOpt)]
 pub enum ConfigCmd {
     /// Add a relay server
@@ -224,12 +337,33 @@ pub enum ConfigCmd {
     /// friends?
     #[structopt(name = "set-currency-rate")]
     SetFriendCurrencyRate(SetFriendCurrencyRateCmd),
+    /// Set friend's fee policy: Recompute the rate according to the balance with this friend
+    #[structopt(name = "set-currency-fee-policy")]
+    SetFriendCurrencyFeePolicy(SetFriendCurrencyFeePolicyCmd),
+    /// Clear friend's fee policy: Go back to using a static rate
+    #[structopt(name = "clear-currency-fee-policy")]
+    ClearFriendCurrencyFeePolicy(ClearFriendCurrencyFeePolicyCmd),
+    /// Enable automatic rebalancing of the balance with a friend
+    #[structopt(name = "set-currency-rebalance")]
+    SetFriendCurrencyRebalance(SetFriendCurrencyRebalanceCmd),
+    /// Disable automatic rebalancing of the balance with a friend
+    #[structopt(name = "clear-currency-rebalance")]
+    ClearFriendCurrencyRebalance(ClearFriendCurrencyRebalanceCmd),
     /// Remove a currency from the set of currencies we are willing to trade with a friend.
     #[structopt(name = "remove-currency")]
     RemoveFriendCurrency(RemoveFriendCurrencyCmd),
     /// Reset mutual credit with a friend according to friend's terms
     #[structopt(name = "reset-friend")]
     ResetFriend(ResetFriendCmd),
+    /// Cooperatively close the relationship with a friend
+    #[structopt(name = "settle-friend")]
+    SettleFriend(SettleFriendCmd),
+    /// Set an exchange rate between two currencies
+    #[structopt(name = "set-exchange-rate")]
+    SetExchangeRate(SetExchangeRateCmd),
+    /// Remove an exchange rate between two currencies
+    #[structopt(name = "remove-exchange-rate")]
+    RemoveExchangeRate(RemoveExchangeRateCmd),
 }
 
 #[derive(Debug, From)]
@@ -257,6 +391,8 @@ pub enum ConfigError {
     IoError(std::io::Error),
     StringSerdeError(StringSerdeError),
     InvalidCurrencyName,
+    InvalidValidityWindow,
+    ExchangeRateNotFound,
 }
 
 async fn config_request(
@@ -469,6 +605,19 @@ async fn config_remove_friend(
     config_request(&mut conn_pair, app_request).await
 }
 
+async fn config_settle_friend(
+    settle_friend_cmd: SettleFriendCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    let friend_public_key = friend_public_key_by_name(&node_report, &settle_friend_cmd.friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    let app_request = conn::config::settle_friend(friend_public_key);
+    config_request(&mut conn_pair, app_request).await
+}
+
 async fn config_enable_friend(
     enable_friend_cmd: EnableFriendCmd,
     mut conn_pair: ConnPairApp,
@@ -578,6 +727,125 @@ async fn config_set_friend_currency_rate(
     config_request(&mut conn_pair, app_request).await
 }
 
+async fn config_set_friend_currency_fee_policy(
+    set_friend_currency_fee_policy_cmd: SetFriendCurrencyFeePolicyCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    let SetFriendCurrencyFeePolicyCmd {
+        friend_name,
+        currency_name,
+        min_mul,
+        min_add,
+        max_mul,
+        max_add,
+        quadratic,
+    } = set_friend_currency_fee_policy_cmd;
+
+    let friend_public_key = friend_public_key_by_name(&node_report, &friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    let currency =
+        Currency::try_from(currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+
+    let fee_policy = FeePolicy {
+        min_rate: Rate {
+            mul: min_mul,
+            add: min_add,
+        },
+        max_rate: Rate {
+            mul: max_mul,
+            add: max_add,
+        },
+        curve: if quadratic {
+            FeeCurve::Quadratic
+        } else {
+            FeeCurve::Linear
+        },
+    };
+
+    let app_request =
+        conn::config::set_friend_currency_fee_policy(friend_public_key, currency, Some(fee_policy));
+    config_request(&mut conn_pair, app_request).await
+}
+
+async fn config_clear_friend_currency_fee_policy(
+    clear_friend_currency_fee_policy_cmd: ClearFriendCurrencyFeePolicyCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    let ClearFriendCurrencyFeePolicyCmd {
+        friend_name,
+        currency_name,
+    } = clear_friend_currency_fee_policy_cmd;
+
+    let friend_public_key = friend_public_key_by_name(&node_report, &friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    let currency =
+        Currency::try_from(currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+
+    let app_request =
+        conn::config::set_friend_currency_fee_policy(friend_public_key, currency, None);
+    config_request(&mut conn_pair, app_request).await
+}
+
+async fn config_set_friend_currency_rebalance(
+    set_friend_currency_rebalance_cmd: SetFriendCurrencyRebalanceCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    let SetFriendCurrencyRebalanceCmd {
+        friend_name,
+        currency_name,
+        threshold,
+        max_fees,
+    } = set_friend_currency_rebalance_cmd;
+
+    let friend_public_key = friend_public_key_by_name(&node_report, &friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    let currency =
+        Currency::try_from(currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+
+    let rebalance_policy = RebalancePolicy {
+        threshold,
+        max_fees,
+    };
+
+    let app_request = conn::config::set_friend_currency_rebalance_policy(
+        friend_public_key,
+        currency,
+        Some(rebalance_policy),
+    );
+    config_request(&mut conn_pair, app_request).await
+}
+
+async fn config_clear_friend_currency_rebalance(
+    clear_friend_currency_rebalance_cmd: ClearFriendCurrencyRebalanceCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    let ClearFriendCurrencyRebalanceCmd {
+        friend_name,
+        currency_name,
+    } = clear_friend_currency_rebalance_cmd;
+
+    let friend_public_key = friend_public_key_by_name(&node_report, &friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    let currency =
+        Currency::try_from(currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+
+    let app_request =
+        conn::config::set_friend_currency_rebalance_policy(friend_public_key, currency, None);
+    config_request(&mut conn_pair, app_request).await
+}
+
 async fn config_remove_friend_currency(
     remove_friend_currency_cmd: RemoveFriendCurrencyCmd,
     mut conn_pair: ConnPairApp,
@@ -632,6 +900,64 @@ async fn config_reset_friend(
     config_request(&mut conn_pair, app_request).await
 }
 
+async fn config_set_exchange_rate(
+    set_exchange_rate_cmd: SetExchangeRateCmd,
+    mut conn_pair: ConnPairApp,
+) -> Result<(), ConfigError> {
+    let SetExchangeRateCmd {
+        src_currency_name,
+        dest_currency_name,
+        mul,
+        valid_from,
+        valid_until,
+    } = set_exchange_rate_cmd;
+
+    let src_currency =
+        Currency::try_from(src_currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+    let dest_currency =
+        Currency::try_from(dest_currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+
+    if valid_from >= valid_until {
+        return Err(ConfigError::InvalidValidityWindow);
+    }
+
+    let app_request =
+        conn::config::set_exchange_rate(src_currency, dest_currency, mul, valid_from, valid_until);
+    config_request(&mut conn_pair, app_request).await
+}
+
+async fn config_remove_exchange_rate(
+    remove_exchange_rate_cmd: RemoveExchangeRateCmd,
+    mut conn_pair: ConnPairApp,
+    node_report: &NodeReport,
+) -> Result<(), ConfigError> {
+    let RemoveExchangeRateCmd {
+        src_currency_name,
+        dest_currency_name,
+    } = remove_exchange_rate_cmd;
+
+    let src_currency =
+        Currency::try_from(src_currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+    let dest_currency =
+        Currency::try_from(dest_currency_name).map_err(|_| ConfigError::InvalidCurrencyName)?;
+
+    // Make sure that this exchange rate exists:
+    if !node_report
+        .funder_report
+        .exchange_rates
+        .iter()
+        .any(|exchange_rate| {
+            exchange_rate.currency_pair.src_currency == src_currency
+                && exchange_rate.currency_pair.dest_currency == dest_currency
+        })
+    {
+        return Err(ConfigError::ExchangeRateNotFound);
+    }
+
+    let app_request = conn::config::remove_exchange_rate(src_currency, dest_currency);
+    config_request(&mut conn_pair, app_request).await
+}
+
 pub async fn config(
     config_cmd: ConfigCmd,
     node_report: &NodeReport,
@@ -683,6 +1009,38 @@ pub async fn config(
             config_set_friend_currency_rate(set_friend_currency_rate_cmd, conn_pair, node_report)
                 .await?
         }
+        ConfigCmd::SetFriendCurrencyFeePolicy(set_friend_currency_fee_policy_cmd) => {
+            config_set_friend_currency_fee_policy(
+                set_friend_currency_fee_policy_cmd,
+                conn_pair,
+                node_report,
+            )
+            .await?
+        }
+        ConfigCmd::ClearFriendCurrencyFeePolicy(clear_friend_currency_fee_policy_cmd) => {
+            config_clear_friend_currency_fee_policy(
+                clear_friend_currency_fee_policy_cmd,
+                conn_pair,
+                node_report,
+            )
+            .await?
+        }
+        ConfigCmd::SetFriendCurrencyRebalance(set_friend_currency_rebalance_cmd) => {
+            config_set_friend_currency_rebalance(
+                set_friend_currency_rebalance_cmd,
+                conn_pair,
+                node_report,
+            )
+            .await?
+        }
+        ConfigCmd::ClearFriendCurrencyRebalance(clear_friend_currency_rebalance_cmd) => {
+            config_clear_friend_currency_rebalance(
+                clear_friend_currency_rebalance_cmd,
+                conn_pair,
+                node_report,
+            )
+            .await?
+        }
         ConfigCmd::RemoveFriendCurrency(remove_friend_currency_cmd) => {
             config_remove_friend_currency(remove_friend_currency_cmd, conn_pair, node_report)
                 .await?
@@ -690,6 +1048,15 @@ pub async fn config(
         ConfigCmd::ResetFriend(reset_friend_cmd) => {
             config_reset_friend(reset_friend_cmd, conn_pair, node_report).await?
         }
+        ConfigCmd::SettleFriend(settle_friend_cmd) => {
+            config_settle_friend(settle_friend_cmd, conn_pair, node_report).await?
+        }
+        ConfigCmd::SetExchangeRate(set_exchange_rate_cmd) => {
+            config_set_exchange_rate(set_exchange_rate_cmd, conn_pair).await?
+        }
+        ConfigCmd::RemoveExchangeRate(remove_exchange_rate_cmd) => {
+            config_remove_exchange_rate(remove_exchange_rate_cmd, conn_pair, node_report).await?
+        }
     }
 
     Ok(())
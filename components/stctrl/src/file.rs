@@ -2,9 +2,9 @@ use app::ser_utils::{ser_b64, ser_string};
 
 use app::common::{
     Commit, Currency, HashResult, HashedLock, InvoiceId, PaymentId, PlainLock, PublicKey,
-    RandValue, Receipt, Signature,
+    RandValue, Receipt, SettlementStatement, Signature,
 };
-use app::report::{MoveTokenHashedReport, TokenInfo};
+use app::report::{CurrencyBalance, MoveTokenHashedReport, TokenInfo};
 
 use mutual_from::mutual_from;
 
@@ -85,6 +85,23 @@ pub struct TokenFile {
     pub token_info: TokenInfo,
 }
 
+/// A helper structure for serialize and deserializing SettlementStatement.
+#[mutual_from(SettlementStatement)]
+#[derive(Arbitrary, Clone, Serialize, Deserialize, Debug)]
+pub struct SettlementFile {
+    #[serde(with = "ser_b64")]
+    pub local_public_key: PublicKey,
+    #[serde(with = "ser_b64")]
+    pub remote_public_key: PublicKey,
+    pub balances: Vec<CurrencyBalance>,
+    #[serde(with = "ser_string")]
+    pub move_token_counter: u128,
+    #[serde(with = "ser_b64")]
+    pub local_signature: Signature,
+    #[serde(with = "ser_b64")]
+    pub remote_signature: Signature,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -153,4 +170,22 @@ mod test {
 
         let _ = serialize_to_string(&token_file).unwrap();
     }
+
+    /// Check if we can serialize SettlementFile without crasing
+    #[test]
+    fn test_serialize_settlement_file() {
+        let settlement_file = SettlementFile {
+            local_public_key: PublicKey::from(&[1; PublicKey::len()]),
+            remote_public_key: PublicKey::from(&[2; PublicKey::len()]),
+            balances: vec![CurrencyBalance {
+                currency: "FST".parse().unwrap(),
+                balance: -5i128,
+            }],
+            move_token_counter: 4u128,
+            local_signature: Signature::from(&[3; Signature::len()]),
+            remote_signature: Signature::from(&[4; Signature::len()]),
+        };
+
+        let _ = serialize_to_string(&settlement_file).unwrap();
+    }
 }
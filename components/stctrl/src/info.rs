@@ -10,13 +10,14 @@ use derive_more::From;
 use app::common::RelayAddress;
 use app::report::{
     ChannelStatusReport, CurrencyReport, FriendReport, FriendStatusReport, NodeReport,
+    SettlementReport,
 };
 use app::ser_utils::public_key_to_string;
 
 use app::file::{FriendAddressFile, RelayAddressFile};
 use app::ser_utils::{serialize_to_string, StringSerdeError};
 
-use crate::file::TokenFile;
+use crate::file::{SettlementFile, TokenFile};
 
 use crate::utils::friend_public_key_by_name;
 
@@ -49,6 +50,17 @@ pub struct FriendLastTokenCmd {
     pub token_path: PathBuf,
 }
 
+/// Export the final settlement statement with a friend
+#[derive(Clone, Debug, StructOpt)]
+pub struct FriendSettlementCmd {
+    /// Friend's name
+    #[structopt(short = "n", long = "name")]
+    pub friend_name: String,
+    /// Path for output settlement file
+    #[structopt(short = "s", long = "settlement")]
+    pub settlement_path: PathBuf,
+}
+
 /// Display balance summary
 #[derive(Clone, Debug, StructOpt)]
 pub struct BalanceCmd {}
@@ -78,6 +90,9 @@ pub enum InfoCmd {
     /// Export friend's last token
     #[structopt(name = "friend-last-token")]
     FriendLastToken(FriendLastTokenCmd),
+    /// Export the final settlement statement with a friend
+    #[structopt(name = "friend-settlement")]
+    FriendSettlement(FriendSettlementCmd),
     // /// Show current balance
     // #[structopt(name = "balance")]
     // Balance(BalanceCmd),
@@ -95,6 +110,7 @@ pub enum InfoError {
     FriendNameNotFound,
     MissingLastIncomingMoveToken,
     StoreLastIncomingMoveTokenError,
+    SettlementNotComplete,
     WriteError,
     TokenInvalid,
     LoadTokenError,
@@ -185,6 +201,26 @@ pub async fn info_index(
     } else {
         writeln!(writer, "No configured index servers.").map_err(|_| InfoError::WriteError)?;
     }
+
+    let topology_snapshots = &node_report.index_client_report.topology_snapshots;
+    if !topology_snapshots.is_empty() {
+        let mut table = Table::new();
+        table.set_titles(row![
+            "currency",
+            "index server",
+            "edges",
+            "ticks since sync"
+        ]);
+        for topology_snapshot in topology_snapshots {
+            table.add_row(row![
+                topology_snapshot.currency,
+                public_key_to_string(&topology_snapshot.server_public_key),
+                topology_snapshot.num_edges,
+                topology_snapshot.ticks_since_sync
+            ]);
+        }
+        table.print(writer).map_err(|_| InfoError::WriteError)?;
+    }
     Ok(())
 }
 
@@ -263,6 +299,17 @@ fn friend_channel_status(friend_report: &FriendReport) -> String {
             }
         }
     }
+    match &friend_report.settlement {
+        SettlementReport::NotStarted => {}
+        SettlementReport::InProgress => res += "Settlement: In progress\n",
+        SettlementReport::Complete(_) => res += "Settlement: Complete\n",
+    }
+    if !friend_report.remote_credit_limits.is_empty() {
+        res += "Announced credit limits:\n";
+        for credit_limit in &friend_report.remote_credit_limits {
+            res += &format!("- {}: {}\n", credit_limit.currency, credit_limit.max_debt);
+        }
+    }
     res
 }
 
@@ -344,6 +391,43 @@ pub async fn info_friend_last_token(
     Ok(())
 }
 
+/// Export the final settlement statement with a friend.
+/// The statement is signed by both sides, and can be verified using `stverify`.
+pub async fn info_friend_settlement(
+    friend_settlement_cmd: FriendSettlementCmd,
+    node_report: &NodeReport,
+) -> Result<(), InfoError> {
+    let FriendSettlementCmd {
+        friend_name,
+        settlement_path,
+    } = friend_settlement_cmd;
+
+    if settlement_path.exists() {
+        return Err(InfoError::OutputFileAlreadyExists);
+    }
+
+    let friend_public_key = friend_public_key_by_name(node_report, &friend_name)
+        .ok_or(InfoError::FriendNameNotFound)?;
+
+    let friend_report = node_report
+        .funder_report
+        .friends
+        .get(&friend_public_key)
+        .unwrap();
+    let settlement_file: SettlementFile = match &friend_report.settlement {
+        SettlementReport::Complete(settlement_statement) => settlement_statement,
+        SettlementReport::NotStarted | SettlementReport::InProgress => {
+            return Err(InfoError::SettlementNotComplete)
+        }
+    }
+    .clone()
+    .into();
+
+    let mut file = File::create(settlement_path)?;
+    file.write_all(&serialize_to_string(&settlement_file)?.as_bytes())?;
+    Ok(())
+}
+
 /*
 /// Get an approximate value for mutual balance with a friend.
 /// In case of an inconsistency we take the local reset terms to represent the balance.
@@ -418,6 +502,9 @@ pub async fn info(
         InfoCmd::FriendLastToken(friend_last_token_cmd) => {
             info_friend_last_token(friend_last_token_cmd, node_report).await?
         }
+        InfoCmd::FriendSettlement(friend_settlement_cmd) => {
+            info_friend_settlement(friend_settlement_cmd, node_report).await?
+        }
         // InfoCmd::Balance(_balance_cmd) => info_balance(node_report, writer).await?,
         InfoCmd::ExportTicket(export_ticket_cmd) => {
             info_export_ticket(export_ticket_cmd, node_report).await?
@@ -2,17 +2,20 @@ use std::convert::TryFrom;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use futures::sink::SinkExt;
 use futures::stream::StreamExt;
 
 use derive_more::From;
 
-use app::common::{Commit, Currency, PublicKey};
+use app::common::{Commit, Currency, InvoiceId, PublicKey, RelayAddress, SignedInvoice};
 use app::conn::{self, AppRequest, AppServerToApp, AppToAppServer, ConnPairApp};
 use app::gen::{gen_invoice_id, gen_uid};
 use app::report::NodeReport;
-use app::ser_utils::{deserialize_from_string, serialize_to_string, StringSerdeError};
+use app::ser_utils::{
+    deserialize_from_string, invoice_to_uri, serialize_to_string, StringSerdeError,
+};
 use app::verify::verify_commit;
 
 use crate::file::{CommitFile, InvoiceFile};
@@ -31,6 +34,16 @@ pub struct CreateInvoiceCmd {
     /// Path of output invoice file
     #[structopt(parse(from_os_str), short = "i", long = "invoice")]
     pub invoice_path: PathBuf,
+    /// Path of output invoice URI file (A signed invoice that can be shared, for example as a QR
+    /// code)
+    #[structopt(parse(from_os_str), short = "u", long = "uri")]
+    pub opt_uri_path: Option<PathBuf>,
+    /// Short textual description of the invoice (Included in the invoice URI)
+    #[structopt(short = "d", long = "description", default_value = "")]
+    pub description: String,
+    /// Amount of seconds until the invoice URI expires
+    #[structopt(short = "e", long = "expiry", default_value = "86400")]
+    pub expiry: u64,
 }
 
 /// Cancel invoice
@@ -72,7 +85,10 @@ pub enum SellerError {
     NoSellerPermissions,
     ParsePublicKeyError,
     InvoiceFileAlreadyExists,
+    UriFileAlreadyExists,
     StoreInvoiceError,
+    SignInvoiceError,
+    SystemTimeError,
     AddInvoiceError,
     LoadInvoiceError,
     CancelInvoiceError,
@@ -116,15 +132,46 @@ async fn seller_request(
     Err(SellerError::SellerRequestError)
 }
 
+/// Ask the node to sign an invoice, and wait for the signed invoice
+async fn seller_sign_invoice(
+    conn_pair: &mut ConnPairApp,
+    invoice_id: InvoiceId,
+    app_request: AppRequest,
+) -> Result<SignedInvoice, SellerError> {
+    let app_to_app_server = AppToAppServer {
+        app_request_id: gen_uid(),
+        app_request,
+    };
+    conn_pair
+        .sender
+        .send(app_to_app_server)
+        .await
+        .map_err(|_| SellerError::SellerRequestError)?;
+
+    while let Some(app_server_to_app) = conn_pair.receiver.next().await {
+        if let AppServerToApp::ResponseSignInvoice(signed_invoice) = app_server_to_app {
+            if signed_invoice.invoice_id == invoice_id {
+                return Ok(signed_invoice);
+            }
+        }
+    }
+
+    Err(SellerError::SignInvoiceError)
+}
+
 async fn seller_create_invoice(
     create_invoice_cmd: CreateInvoiceCmd,
     local_public_key: PublicKey,
+    relays: Vec<RelayAddress>,
     mut conn_pair: ConnPairApp,
 ) -> Result<(), SellerError> {
     let CreateInvoiceCmd {
         currency_name,
         amount,
         invoice_path,
+        opt_uri_path,
+        description,
+        expiry,
     } = create_invoice_cmd;
 
     let currency =
@@ -135,6 +182,12 @@ async fn seller_create_invoice(
         return Err(SellerError::InvoiceFileAlreadyExists);
     }
 
+    if let Some(uri_path) = &opt_uri_path {
+        if uri_path.exists() {
+            return Err(SellerError::UriFileAlreadyExists);
+        }
+    }
+
     let invoice_id = gen_invoice_id();
 
     let dest_public_key = local_public_key;
@@ -148,13 +201,34 @@ async fn seller_create_invoice(
 
     seller_request(
         &mut conn_pair,
-        conn::seller::add_invoice(invoice_id.clone(), currency, amount),
+        conn::seller::add_invoice(invoice_id.clone(), currency.clone(), amount),
     )
     .await
     .map_err(|_| SellerError::AddInvoiceError)?;
 
     let mut file = File::create(invoice_path)?;
     file.write_all(&serialize_to_string(&invoice_file)?.as_bytes())?;
+
+    if let Some(uri_path) = opt_uri_path {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| SellerError::SystemTimeError)?
+            .as_secs();
+        let valid_until = now.saturating_add(expiry);
+
+        let app_request = conn::seller::sign_invoice(
+            invoice_id.clone(),
+            currency,
+            amount,
+            relays,
+            valid_until,
+            description,
+        );
+        let signed_invoice = seller_sign_invoice(&mut conn_pair, invoice_id, app_request).await?;
+
+        let mut file = File::create(uri_path)?;
+        file.write_all(invoice_to_uri(&signed_invoice).as_bytes())?;
+    }
     Ok(())
 }
 
@@ -214,6 +288,14 @@ pub async fn seller(
 ) -> Result<(), SellerError> {
     // Get our local public key:
     let local_public_key = node_report.funder_report.local_public_key.clone();
+    // Relays buyers may use to contact us directly:
+    let relays = node_report
+        .funder_report
+        .relays
+        .iter()
+        .cloned()
+        .map(RelayAddress::from)
+        .collect();
 
     /*
     // TODO; Check permissions at caller site.
@@ -225,7 +307,7 @@ pub async fn seller(
 
     match seller_cmd {
         SellerCmd::CreateInvoice(create_invoice_cmd) => {
-            seller_create_invoice(create_invoice_cmd, local_public_key, conn_pair).await?
+            seller_create_invoice(create_invoice_cmd, local_public_key, relays, conn_pair).await?
         }
         SellerCmd::CancelInvoice(cancel_invoice_cmd) => {
             seller_cancel_invoice(cancel_invoice_cmd, conn_pair).await?
@@ -6,12 +6,12 @@ use derive_more::From;
 
 use structopt::StructOpt;
 
-use crate::file::{InvoiceFile, ReceiptFile, TokenFile};
+use crate::file::{InvoiceFile, ReceiptFile, SettlementFile, TokenFile};
 
-use app::common::Receipt;
+use app::common::{Receipt, SettlementStatement};
 use app::report::MoveTokenHashedReport;
 use app::ser_utils::{deserialize_from_string, public_key_to_string, StringSerdeError};
-use app::verify::{verify_move_token_hashed_report, verify_receipt};
+use app::verify::{verify_move_token_hashed_report, verify_receipt, verify_settlement_statement};
 
 #[derive(Debug, From)]
 pub enum StVerifyError {
@@ -23,6 +23,7 @@ pub enum StVerifyError {
     InvoiceIdMismatch,
     DestPaymentMismatch,
     InvalidReceipt,
+    SettlementInvalid,
     IoError(std::io::Error),
     StringSerdeError(StringSerdeError),
 }
@@ -47,6 +48,16 @@ pub struct VerifyReceiptCmd {
     pub receipt_path: PathBuf,
 }
 
+/// Verify a settlement statement.
+/// A settlement statement is signed by both friends when their relationship is closed, and
+/// contains the final balances between them.
+#[derive(Clone, Debug, StructOpt)]
+pub struct VerifySettlementCmd {
+    /// Path of settlement file
+    #[structopt(parse(from_os_str), short = "s", long = "settlement")]
+    pub settlement_path: PathBuf,
+}
+
 /// stctrl: offSeT ConTRoL
 /// An application used to interface with the Offset node
 /// Allows to view node's state information, configure node's state and send funds to remote nodes.
@@ -59,6 +70,9 @@ pub enum StVerifyCmd {
     /// Verify a receipt against an invoice
     #[structopt(name = "verify-receipt")]
     VerifyReceipt(VerifyReceiptCmd),
+    /// Verify a settlement statement signed by two friends
+    #[structopt(name = "verify-settlement")]
+    VerifySettlement(VerifySettlementCmd),
 }
 
 /// Verify a given friend token
@@ -160,6 +174,56 @@ fn stverify_verify_receipt(
     }
 }
 
+/// Verify a given settlement statement
+/// If the given statement is valid, output the final balances
+fn stverify_verify_settlement(
+    verify_settlement_cmd: VerifySettlementCmd,
+    writer: &mut impl io::Write,
+) -> Result<(), StVerifyError> {
+    let settlement_file: SettlementFile =
+        deserialize_from_string(&fs::read_to_string(&verify_settlement_cmd.settlement_path)?)?;
+
+    let settlement_statement = SettlementStatement::from(settlement_file);
+
+    if !verify_settlement_statement(&settlement_statement) {
+        return Err(StVerifyError::SettlementInvalid);
+    }
+
+    writeln!(writer, "Settlement is valid!").map_err(|_| StVerifyError::WriteError)?;
+    writeln!(writer).map_err(|_| StVerifyError::WriteError)?;
+    writeln!(
+        writer,
+        "local_public_key: {}",
+        public_key_to_string(&settlement_statement.local_public_key)
+    )
+    .map_err(|_| StVerifyError::WriteError)?;
+    writeln!(
+        writer,
+        "remote_public_key: {}",
+        public_key_to_string(&settlement_statement.remote_public_key)
+    )
+    .map_err(|_| StVerifyError::WriteError)?;
+    writeln!(
+        writer,
+        "move_token_counter: {}",
+        settlement_statement.move_token_counter
+    )
+    .map_err(|_| StVerifyError::WriteError)?;
+
+    writeln!(writer, "balances:\n").map_err(|_| StVerifyError::WriteError)?;
+
+    for currency_balance in settlement_statement.balances {
+        writeln!(
+            writer,
+            "- {}: balance={}",
+            currency_balance.currency, currency_balance.balance
+        )
+        .map_err(|_| StVerifyError::WriteError)?;
+    }
+
+    Ok(())
+}
+
 pub fn stverify(
     st_verify_cmd: StVerifyCmd,
     writer: &mut impl io::Write,
@@ -171,5 +235,8 @@ pub fn stverify(
         StVerifyCmd::VerifyReceipt(verify_receipt_cmd) => {
             stverify_verify_receipt(verify_receipt_cmd, writer)
         }
+        StVerifyCmd::VerifySettlement(verify_settlement_cmd) => {
+            stverify_verify_settlement(verify_settlement_cmd, writer)
+        }
     }
 }
@@ -33,6 +33,8 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
         lclient: stctrl_setup.index0_client_addr.parse().unwrap(),
         lserver: stctrl_setup.index0_server_addr.parse().unwrap(),
         trusted: stctrl_setup.temp_dir_path.join("index0").join("trusted"),
+        opt_graph_dir: Some(stctrl_setup.temp_dir_path.join("index0").join("graph")),
+        max_loaded_graphs: 2,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -49,6 +51,8 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
         lclient: stctrl_setup.index1_client_addr.parse().unwrap(),
         lserver: stctrl_setup.index1_server_addr.parse().unwrap(),
         trusted: stctrl_setup.temp_dir_path.join("index1").join("trusted"),
+        opt_graph_dir: None,
+        max_loaded_graphs: 2,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -86,10 +90,14 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
 
     // Spawn node0:
     let st_node_cmd = StNodeCmd {
-        idfile: stctrl_setup.temp_dir_path.join("node0").join("node0.ident"),
+        idfile: vec![stctrl_setup.temp_dir_path.join("node0").join("node0.ident")],
         laddr: stctrl_setup.node0_addr.clone().parse().unwrap(),
-        database: stctrl_setup.temp_dir_path.join("node0").join("node0.db"),
-        trusted: stctrl_setup.temp_dir_path.join("node0").join("trusted"),
+        database: vec![stctrl_setup.temp_dir_path.join("node0").join("node0.db")],
+        trusted: vec![stctrl_setup.temp_dir_path.join("node0").join("trusted")],
+        decoys: 2,
+        roundcap: false,
+        sync_topology: true,
+        report_routes: true,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -99,10 +107,14 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
 
     // Spawn node1:
     let st_node_cmd = StNodeCmd {
-        idfile: stctrl_setup.temp_dir_path.join("node1").join("node1.ident"),
+        idfile: vec![stctrl_setup.temp_dir_path.join("node1").join("node1.ident")],
         laddr: stctrl_setup.node1_addr.clone().parse().unwrap(),
-        database: stctrl_setup.temp_dir_path.join("node1").join("node1.db"),
-        trusted: stctrl_setup.temp_dir_path.join("node1").join("trusted"),
+        database: vec![stctrl_setup.temp_dir_path.join("node1").join("node1.db")],
+        trusted: vec![stctrl_setup.temp_dir_path.join("node1").join("trusted")],
+        decoys: 0,
+        roundcap: false,
+        sync_topology: false,
+        report_routes: false,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -495,6 +507,9 @@ fn create_cancel_invoice(stctrl_setup: &StCtrlSetup) {
             .temp_dir_path
             .join("node0")
             .join("temp_invoice.invoice"),
+        opt_uri_path: None,
+        description: "".to_owned(),
+        expiry: 86400,
     };
     let seller_cmd = SellerCmd::CreateInvoice(create_invoice_cmd);
     let subcommand = StCtrlSubcommand::Seller(seller_cmd);
@@ -552,6 +567,9 @@ fn pay_invoice(stctrl_setup: &StCtrlSetup) {
             .temp_dir_path
             .join("node0")
             .join("test1.invoice"),
+        opt_uri_path: None,
+        description: "".to_owned(),
+        expiry: 86400,
     };
     let seller_cmd = SellerCmd::CreateInvoice(create_invoice_cmd);
     let subcommand = StCtrlSubcommand::Seller(seller_cmd);
@@ -570,10 +588,13 @@ fn pay_invoice(stctrl_setup: &StCtrlSetup) {
     // -----------------------
     loop {
         let pay_invoice_cmd = PayInvoiceCmd {
-            invoice_path: stctrl_setup
-                .temp_dir_path
-                .join("node0")
-                .join("test1.invoice"),
+            opt_invoice_path: Some(
+                stctrl_setup
+                    .temp_dir_path
+                    .join("node0")
+                    .join("test1.invoice"),
+            ),
+            opt_invoice_uri: None,
             payment_path: stctrl_setup
                 .temp_dir_path
                 .join("node1")
@@ -582,6 +603,7 @@ fn pay_invoice(stctrl_setup: &StCtrlSetup) {
                 .temp_dir_path
                 .join("node1")
                 .join("test1.commit"),
+            deliver: false,
         };
         let buyer_cmd = BuyerCmd::PayInvoice(pay_invoice_cmd);
         let subcommand = StCtrlSubcommand::Buyer(buyer_cmd);
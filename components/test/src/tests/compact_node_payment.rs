@@ -9,23 +9,25 @@ use tempfile::tempdir;
 use common::conn::ConnPair;
 use common::test_executor::TestExecutor;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use proto::crypto::{InvoiceId, PaymentId, PublicKey};
 use proto::funder::messages::{Currency, Rate, Receipt};
 
-use timer::create_timer_incoming;
+use timer::{create_timer_incoming, TimerClient};
 
 use app::gen::gen_uid;
 
 use stcompact::compact_node::messages::{
-    AddFriend, AddInvoice, CompactToUser, CompactToUserAck, ConfirmPaymentFees,
-    FriendLivenessReport, InitPayment, OpenFriendCurrency, OpenPaymentStatus, PaymentDoneStatus,
-    PaymentFeesResponse, RequestVerifyCommit, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate,
-    UserToCompact, UserToCompactAck, VerifyCommitStatus,
+    AddFriend, AddInvoice, CloseFriendCurrency, CompactToUser, CompactToUserAck,
+    ConfirmPaymentFees, CreateInvoiceUriStatus, FeeBudget, FriendLivenessReport, InitPayment,
+    OpenFriendCurrency, OpenPaymentStatus, ParseInvoiceUriStatus, PaymentDoneStatus,
+    PaymentFeesResponse, RequestCreateInvoiceUri, RequestParseInvoiceUri, RequestVerifyCommit,
+    RequestVerifyReceipt, RetryPolicy, SetFriendCurrencyMaxDebt, SetFriendCurrencyRate,
+    UserToCompact, UserToCompactAck, VerifyCommitStatus, VerifyReceiptStatus,
 };
 
 use crate::compact_node_wrapper::send_request;
-use crate::sim_network::create_sim_network;
+use crate::sim_network::{create_sim_network, SimNetworkClient};
 use crate::utils::{
     advance_time, create_compact_node, create_index_server, create_node, create_relay,
     named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
@@ -37,6 +39,9 @@ const TIMER_CHANNEL_LEN: usize = 0;
 
 /// Perform a basic payment between a buyer and a seller.
 /// Node0 sends credits to Node1
+/// If `deliver_commit` is set, the commit is delivered to Node1 through the network.
+/// If `opt_fee_budget` is set, fees are expected to be confirmed automatically.
+/// If `opt_retry_policy` is set, the payment is only closed after all the credits have arrived.
 async fn make_test_payment(
     mut conn_pair0: &mut ConnPair<UserToCompactAck, CompactToUserAck>,
     compact_report_client0: &mut CompactReportClient,
@@ -46,6 +51,9 @@ async fn make_test_payment(
     seller_public_key: PublicKey,
     currency: Currency,
     total_dest_payment: u128,
+    deliver_commit: bool,
+    opt_fee_budget: Option<FeeBudget>,
+    opt_retry_policy: Option<RetryPolicy>,
     mut tick_sender: mpsc::Sender<()>,
     test_executor: TestExecutor,
 ) -> Option<(Receipt, u128)> {
@@ -63,21 +71,92 @@ async fn make_test_payment(
         .await
         .unwrap();
 
+    // Node1: Create an invoice URI:
+    let create_request_id = gen_uid();
+    let request_create_invoice_uri = RequestCreateInvoiceUri {
+        request_id: create_request_id.clone(),
+        invoice_id: invoice_id.clone(),
+        valid_until: u64::max_value(),
+    };
+    send_request(
+        &mut conn_pair1,
+        UserToCompact::RequestCreateInvoiceUri(request_create_invoice_uri),
+    )
+    .await
+    .unwrap();
+
+    let invoice_uri = loop {
+        let compact_to_user_ack = conn_pair1.receiver.next().await.unwrap();
+        let response_create_invoice_uri = if let CompactToUserAck::CompactToUser(
+            CompactToUser::ResponseCreateInvoiceUri(response_create_invoice_uri),
+        ) = compact_to_user_ack
+        {
+            response_create_invoice_uri
+        } else {
+            continue;
+        };
+        assert_eq!(response_create_invoice_uri.request_id, create_request_id);
+        match response_create_invoice_uri.status {
+            CreateInvoiceUriStatus::Success(invoice_uri) => break invoice_uri,
+            CreateInvoiceUriStatus::Failure => unreachable!(),
+        }
+    };
+
+    // ... Node1 now passes the invoice URI to Node0 out of band ...
+
+    // Node0: Parse the invoice URI:
+    let parse_request_id = gen_uid();
+    let request_parse_invoice_uri = RequestParseInvoiceUri {
+        request_id: parse_request_id.clone(),
+        invoice_uri,
+    };
+    send_request(
+        &mut conn_pair0,
+        UserToCompact::RequestParseInvoiceUri(request_parse_invoice_uri),
+    )
+    .await
+    .unwrap();
+
+    let parsed_invoice = loop {
+        let compact_to_user_ack = conn_pair0.receiver.next().await.unwrap();
+        let response_parse_invoice_uri = if let CompactToUserAck::CompactToUser(
+            CompactToUser::ResponseParseInvoiceUri(response_parse_invoice_uri),
+        ) = compact_to_user_ack
+        {
+            response_parse_invoice_uri
+        } else {
+            continue;
+        };
+        assert_eq!(response_parse_invoice_uri.request_id, parse_request_id);
+        match response_parse_invoice_uri.status {
+            ParseInvoiceUriStatus::Success(parsed_invoice) => break parsed_invoice,
+            ParseInvoiceUriStatus::Failure | ParseInvoiceUriStatus::Expired => unreachable!(),
+        }
+    };
+    assert_eq!(parsed_invoice.invoice_id, invoice_id);
+    assert_eq!(parsed_invoice.currency, currency);
+    assert_eq!(parsed_invoice.dest_public_key, seller_public_key);
+    assert_eq!(parsed_invoice.dest_payment, total_dest_payment);
+    assert_eq!(parsed_invoice.description, "Example payment");
+
     // Node0: Init payment
     let init_payment = InitPayment {
         payment_id: payment_id.clone(),
-        invoice_id: invoice_id.clone(),
-        currency: currency.clone(),
-        dest_public_key: seller_public_key.clone(),
-        dest_payment: total_dest_payment,
-        description: "Example payment".to_owned(),
+        invoice_id: parsed_invoice.invoice_id,
+        currency: parsed_invoice.currency,
+        dest_public_key: parsed_invoice.dest_public_key,
+        dest_payment: parsed_invoice.dest_payment,
+        description: parsed_invoice.description,
+        deliver_commit,
+        opt_fee_budget: opt_fee_budget.clone(),
+        opt_retry_policy,
     };
     send_request(&mut conn_pair0, UserToCompact::InitPayment(init_payment))
         .await
         .unwrap();
 
     // Node0: Wait for payment fees:
-    let (_fees, confirm_id) = loop {
+    let (fees, confirm_id) = loop {
         let compact_to_user_ack = conn_pair0.receiver.next().await.unwrap();
         let payment_fees =
             if let CompactToUserAck::CompactToUser(CompactToUser::PaymentFees(payment_fees)) =
@@ -94,30 +173,36 @@ async fn make_test_payment(
         };
     };
 
-    // Make sure that `compact_report` contains the information about the found route:
-    let compact_report = compact_report_client0.request_report().await;
-    match compact_report
-        .open_payments
-        .get(&payment_id)
-        .unwrap()
-        .status
-    {
-        OpenPaymentStatus::FoundRoute(_, _) => {}
-        _ => unreachable!(),
+    if opt_fee_budget.is_none() {
+        // Make sure that `compact_report` contains the information about the found route:
+        let compact_report = compact_report_client0.request_report().await;
+        let open_payment = compact_report.open_payments.get(&payment_id).unwrap();
+        match open_payment.status {
+            OpenPaymentStatus::FoundRoute(_, _) => {}
+            _ => unreachable!(),
+        }
+        assert_eq!(
+            open_payment
+                .routes
+                .iter()
+                .map(|payment_route| payment_route.fees)
+                .sum::<u128>(),
+            fees
+        );
+
+        // Node0: Confirm payment fees:
+        let confirm_payment_fees = ConfirmPaymentFees {
+            payment_id: payment_id.clone(),
+            confirm_id,
+        };
+        send_request(
+            &mut conn_pair0,
+            UserToCompact::ConfirmPaymentFees(confirm_payment_fees),
+        )
+        .await
+        .unwrap();
     }
 
-    // Node0: Confirm payment fees:
-    let confirm_payment_fees = ConfirmPaymentFees {
-        payment_id: payment_id.clone(),
-        confirm_id,
-    };
-    send_request(
-        &mut conn_pair0,
-        UserToCompact::ConfirmPaymentFees(confirm_payment_fees),
-    )
-    .await
-    .unwrap();
-
     // Node0: Wait for commit to be created:
     let commit = loop {
         let compact_to_user_ack = conn_pair0.receiver.next().await.unwrap();
@@ -149,46 +234,64 @@ async fn make_test_payment(
         break payment_commit.commit;
     };
 
-    // ... Node0 now passes the commit to Node1 out of band ...
+    if deliver_commit {
+        // Node1: Wait for the delivered commit to be applied:
+        loop {
+            let compact_to_user_ack = conn_pair1.receiver.next().await.unwrap();
+            let invoice_committed = if let CompactToUserAck::CompactToUser(
+                CompactToUser::InvoiceCommitted(invoice_committed),
+            ) = compact_to_user_ack
+            {
+                invoice_committed
+            } else {
+                continue;
+            };
+            assert_eq!(invoice_committed.invoice_id, commit.invoice_id);
+            assert_eq!(invoice_committed.total_dest_payment, total_dest_payment);
+            break;
+        }
+    } else {
+        // ... Node0 now passes the commit to Node1 out of band ...
+
+        // Node1: Verify the commit:
+        let verify_request_id = gen_uid();
+        let request_verify_commit = RequestVerifyCommit {
+            request_id: verify_request_id.clone(),
+            // seller_public_key: seller_public_key.clone(),
+            commit: commit.clone(),
+        };
+        send_request(
+            &mut conn_pair1,
+            UserToCompact::RequestVerifyCommit(request_verify_commit),
+        )
+        .await
+        .unwrap();
 
-    // Node1: Verify the commit:
-    let verify_request_id = gen_uid();
-    let request_verify_commit = RequestVerifyCommit {
-        request_id: verify_request_id.clone(),
-        // seller_public_key: seller_public_key.clone(),
-        commit: commit.clone(),
-    };
-    send_request(
-        &mut conn_pair1,
-        UserToCompact::RequestVerifyCommit(request_verify_commit),
-    )
-    .await
-    .unwrap();
+        // Node1: Wait for verification of commit:
+        loop {
+            let compact_to_user_ack = conn_pair1.receiver.next().await.unwrap();
+            let response_verify_commit = if let CompactToUserAck::CompactToUser(
+                CompactToUser::ResponseVerifyCommit(response_verify_commit),
+            ) = compact_to_user_ack
+            {
+                response_verify_commit
+            } else {
+                continue;
+            };
+            assert_eq!(response_verify_commit.request_id, verify_request_id);
+            assert_eq!(response_verify_commit.status, VerifyCommitStatus::Success);
+            break;
+        }
 
-    // Node1: Wait for verification of commit:
-    loop {
-        let compact_to_user_ack = conn_pair1.receiver.next().await.unwrap();
-        let response_verify_commit = if let CompactToUserAck::CompactToUser(
-            CompactToUser::ResponseVerifyCommit(response_verify_commit),
-        ) = compact_to_user_ack
-        {
-            response_verify_commit
-        } else {
-            continue;
-        };
-        assert_eq!(response_verify_commit.request_id, verify_request_id);
-        assert_eq!(response_verify_commit.status, VerifyCommitStatus::Success);
-        break;
+        // Node1: Apply the commit:
+        send_request(
+            &mut conn_pair1,
+            UserToCompact::CommitInvoice(commit.invoice_id.clone()),
+        )
+        .await
+        .unwrap();
     }
 
-    // Node1: Apply the commit:
-    send_request(
-        &mut conn_pair1,
-        UserToCompact::CommitInvoice(commit.invoice_id.clone()),
-    )
-    .await
-    .unwrap();
-
     // Wait some time:
     advance_time(5, &mut tick_sender, &test_executor).await;
 
@@ -220,9 +323,292 @@ async fn make_test_payment(
     .await
     .unwrap();
 
+    if let Some((receipt, _fees)) = &opt_receipt_fees {
+        // ... Node0 now passes the receipt to Node1 out of band ...
+
+        // Node1: Verify the receipt:
+        let verify_request_id = gen_uid();
+        let request_verify_receipt = RequestVerifyReceipt {
+            request_id: verify_request_id.clone(),
+            receipt: receipt.clone(),
+            dest_public_key: seller_public_key,
+            invoice_id,
+            total_dest_payment,
+        };
+        send_request(
+            &mut conn_pair1,
+            UserToCompact::RequestVerifyReceipt(request_verify_receipt),
+        )
+        .await
+        .unwrap();
+
+        loop {
+            let compact_to_user_ack = conn_pair1.receiver.next().await.unwrap();
+            let response_verify_receipt = if let CompactToUserAck::CompactToUser(
+                CompactToUser::ResponseVerifyReceipt(response_verify_receipt),
+            ) = compact_to_user_ack
+            {
+                response_verify_receipt
+            } else {
+                continue;
+            };
+            assert_eq!(response_verify_receipt.request_id, verify_request_id);
+            assert_eq!(response_verify_receipt.status, VerifyReceiptStatus::Valid);
+            break;
+        }
+    }
+
     opt_receipt_fees
 }
 
+/// Wait until a compact node sees the node `friend_index` as an online friend
+async fn wait_friend_online(
+    compact_report_client: &mut CompactReportClient,
+    friend_index: u8,
+    tick_sender: &mut mpsc::Sender<()>,
+    test_executor: &TestExecutor,
+) {
+    loop {
+        let compact_report = compact_report_client.request_report().await;
+        if let Some(friend_report) = compact_report.friends.get(&node_public_key(friend_index)) {
+            if friend_report.liveness == FriendLivenessReport::Online {
+                break;
+            }
+        }
+        advance_time(5, tick_sender, test_executor).await;
+    }
+}
+
+/// Create four nodes connected in a diamond shape, sharing a single index server:
+///
+/// ```text
+///     1
+///   /   \
+///  0     3
+///   \   /
+///     2
+/// ```
+///
+/// Node2 charges a fee for forwarding credits from Node0, so routes through Node1 are preferred.
+/// Node1 then disconnects from the index server and closes `currency` with Node3.
+/// The index server still knows about the route through Node1, but Node1 will cancel every
+/// request forwarded to Node3.
+async fn create_diamond_network(
+    currency: &Currency,
+    sim_db: SimDb,
+    sim_net_client: SimNetworkClient,
+    timer_client: TimerClient,
+    tick_sender: &mut mpsc::Sender<()>,
+    test_executor: &TestExecutor,
+) -> Vec<(
+    ConnPair<UserToCompactAck, CompactToUserAck>,
+    CompactReportClient,
+)> {
+    let edges = [(0u8, 1u8), (0, 2), (1, 3), (2, 3)];
+
+    let mut nodes = Vec::new();
+    for index in 0..4u8 {
+        sim_db.init_node_db(index).unwrap();
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            index,
+            AppPermissions {
+                routes: true,
+                buyer: true,
+                seller: true,
+                config: true,
+                restrictions: AppRestrictions::default(),
+            },
+        );
+        create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone(),
+        )
+        .await
+        .forget();
+
+        let (compact_node, compact_report) = create_compact_node(
+            index,
+            sim_db.clone(),
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone(),
+        )
+        .await
+        .unwrap();
+
+        // Handle reports:
+        let (sender, receiver) = compact_node.split();
+        let (receiver, compact_report_client) =
+            compact_report_service(compact_report, receiver, test_executor);
+        nodes.push((ConnPair::from_raw(sender, receiver), compact_report_client));
+
+        create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone(),
+        )
+        .await;
+    }
+
+    create_index_server(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![],
+        test_executor.clone(),
+    )
+    .await;
+
+    // Configure relays and index servers:
+    for (index, (compact_node, _)) in nodes.iter_mut().enumerate() {
+        send_request(
+            compact_node,
+            UserToCompact::AddRelay(named_relay_address(u8::try_from(index).unwrap())),
+        )
+        .await
+        .unwrap();
+
+        send_request(
+            compact_node,
+            UserToCompact::AddIndexServer(named_index_server_address(0)),
+        )
+        .await
+        .unwrap();
+    }
+
+    // Wait some time:
+    advance_time(40, tick_sender, test_executor).await;
+
+    // Add and enable friends on both sides of every edge:
+    for &(a, b) in edges.iter() {
+        for &(local, remote) in [(a, b), (b, a)].iter() {
+            let compact_node = &mut nodes[usize::from(local)].0;
+            let add_friend = AddFriend {
+                friend_public_key: node_public_key(remote),
+                relays: vec![relay_address(remote)],
+                name: format!("node{}", remote),
+            };
+            send_request(compact_node, UserToCompact::AddFriend(add_friend))
+                .await
+                .unwrap();
+
+            send_request(
+                compact_node,
+                UserToCompact::EnableFriend(node_public_key(remote)),
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    advance_time(10, tick_sender, test_executor).await;
+
+    for &(a, b) in edges.iter() {
+        for &(local, remote) in [(a, b), (b, a)].iter() {
+            wait_friend_online(
+                &mut nodes[usize::from(local)].1,
+                remote,
+                tick_sender,
+                test_executor,
+            )
+            .await;
+        }
+    }
+
+    // Set active currencies.
+    // Node2 charges Node0 for forwarding credits. The index server learns about this rate from
+    // the rate Node0 sets for Node2:
+    for &(a, b) in edges.iter() {
+        for &(local, remote) in [(a, b), (b, a)].iter() {
+            let rate = if (a, b) == (0, 2) {
+                Rate { mul: 0, add: 1 }
+            } else {
+                Rate::new()
+            };
+            let set_friend_currency_rate = SetFriendCurrencyRate {
+                friend_public_key: node_public_key(remote),
+                currency: currency.clone(),
+                rate,
+            };
+            send_request(
+                &mut nodes[usize::from(local)].0,
+                UserToCompact::SetFriendCurrencyRate(set_friend_currency_rate),
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    // Wait some time, to let the nodes negotiate currencies:
+    advance_time(10, tick_sender, test_executor).await;
+
+    for &(a, b) in edges.iter() {
+        for &(local, remote) in [(a, b), (b, a)].iter() {
+            let open_friend_currency = OpenFriendCurrency {
+                friend_public_key: node_public_key(remote),
+                currency: currency.clone(),
+            };
+            send_request(
+                &mut nodes[usize::from(local)].0,
+                UserToCompact::OpenFriendCurrency(open_friend_currency),
+            )
+            .await
+            .unwrap();
+
+            let set_friend_currency_max_debt = SetFriendCurrencyMaxDebt {
+                friend_public_key: node_public_key(remote),
+                currency: currency.clone(),
+                remote_max_debt: 100,
+            };
+            send_request(
+                &mut nodes[usize::from(local)].0,
+                UserToCompact::SetFriendCurrencyMaxDebt(set_friend_currency_max_debt),
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    // Wait some time, to let the index server learn about the new capacities:
+    advance_time(10, tick_sender, test_executor).await;
+
+    // Node1: Disconnect from the index server, so that the index server is not updated about
+    // the closed currency:
+    let compact_node1 = &mut nodes[1].0;
+    send_request(
+        compact_node1,
+        UserToCompact::RemoveIndexServer(named_index_server_address(0).public_key),
+    )
+    .await
+    .unwrap();
+
+    advance_time(1, tick_sender, test_executor).await;
+
+    // Node1: Stop forwarding requests to Node3:
+    let close_friend_currency = CloseFriendCurrency {
+        friend_public_key: node_public_key(3),
+        currency: currency.clone(),
+    };
+    send_request(
+        compact_node1,
+        UserToCompact::CloseFriendCurrency(close_friend_currency),
+    )
+    .await
+    .unwrap();
+
+    advance_time(2, tick_sender, test_executor).await;
+
+    nodes
+}
+
 async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
     let currency1 = Currency::try_from("FST1".to_owned()).unwrap();
     let currency2 = Currency::try_from("FST2".to_owned()).unwrap();
@@ -253,6 +639,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
 
@@ -289,6 +676,7 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
     create_node(
@@ -569,6 +957,9 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(1),
         currency1.clone(),
         10u128, // total_dest_payment
+        false,  // deliver_commit
+        None,   // opt_fee_budget
+        None,   // opt_retry_policy
         tick_sender.clone(),
         test_executor.clone(),
     )
@@ -589,6 +980,9 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(1),
         currency2.clone(),
         11u128, // total_dest_payment
+        true,   // deliver_commit
+        None,   // opt_fee_budget
+        None,   // opt_retry_policy
         tick_sender.clone(),
         test_executor.clone(),
     )
@@ -609,6 +1003,12 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(0),
         currency1.clone(),
         5u128, // total_dest_payment
+        false, // deliver_commit
+        Some(FeeBudget {
+            opt_max_fees: Some(0),
+            opt_max_fees_percent: None,
+        }), // opt_fee_budget
+        None,  // opt_retry_policy
         tick_sender.clone(),
         test_executor.clone(),
     )
@@ -630,6 +1030,12 @@ async fn task_compact_node_two_nodes_payment(mut test_executor: TestExecutor) {
         node_public_key(0),
         currency1.clone(),
         6u128, // total_dest_payment
+        false, // deliver_commit
+        None,  // opt_fee_budget
+        Some(RetryPolicy {
+            max_retries: 2,
+            opt_deadline: None,
+        }), // opt_retry_policy
         tick_sender.clone(),
         test_executor.clone(),
     )
@@ -645,3 +1051,134 @@ fn test_compact_node_two_nodes_payment() {
     let res = test_executor.run(task_compact_node_two_nodes_payment(test_executor.clone()));
     assert!(res.is_output());
 }
+
+async fn task_compact_node_payment_alternative_route(mut test_executor: TestExecutor) {
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let mut nodes = create_diamond_network(
+        &currency,
+        sim_db,
+        sim_net_client,
+        timer_client,
+        &mut tick_sender,
+        &test_executor,
+    )
+    .await;
+    let (mut compact_node3, mut compact_report_client3) = nodes.pop().unwrap();
+    let (mut compact_node0, mut compact_report_client0) = nodes.remove(0);
+
+    // Node0: Send 20 credits to Node3.
+    // The route through Node1 fails, and the credits are sent again through Node2:
+    let (_receipt, fees) = make_test_payment(
+        &mut compact_node0,
+        &mut compact_report_client0,
+        &mut compact_node3,
+        &mut compact_report_client3,
+        node_public_key(0),
+        node_public_key(3),
+        currency.clone(),
+        20u128, // total_dest_payment
+        false,  // deliver_commit
+        None,   // opt_fee_budget
+        Some(RetryPolicy {
+            max_retries: 2,
+            opt_deadline: None,
+        }), // opt_retry_policy
+        tick_sender.clone(),
+        test_executor.clone(),
+    )
+    .await
+    .unwrap();
+
+    // Only the route through Node2 charges fees:
+    assert_eq!(fees, 1);
+}
+
+#[test]
+fn test_compact_node_payment_alternative_route() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_compact_node_payment_alternative_route(
+        test_executor.clone(),
+    ));
+    assert!(res.is_output());
+}
+
+async fn task_compact_node_payment_retries_exhausted(mut test_executor: TestExecutor) {
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let mut nodes = create_diamond_network(
+        &currency,
+        sim_db,
+        sim_net_client,
+        timer_client,
+        &mut tick_sender,
+        &test_executor,
+    )
+    .await;
+    let (mut compact_node3, mut compact_report_client3) = nodes.pop().unwrap();
+    let (mut compact_node0, mut compact_report_client0) = nodes.remove(0);
+
+    // Node0: Attempt to send 20 credits to Node3.
+    // The route through Node1 fails. The payment is not allowed to be retried, so it fails
+    // although the route through Node2 is still available:
+    let opt_receipt_fees = make_test_payment(
+        &mut compact_node0,
+        &mut compact_report_client0,
+        &mut compact_node3,
+        &mut compact_report_client3,
+        node_public_key(0),
+        node_public_key(3),
+        currency.clone(),
+        20u128, // total_dest_payment
+        false,  // deliver_commit
+        None,   // opt_fee_budget
+        Some(RetryPolicy {
+            max_retries: 0,
+            opt_deadline: None,
+        }), // opt_retry_policy
+        tick_sender.clone(),
+        test_executor.clone(),
+    )
+    .await;
+
+    assert!(opt_receipt_fees.is_none());
+}
+
+#[test]
+fn test_compact_node_payment_retries_exhausted() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_compact_node_payment_retries_exhausted(
+        test_executor.clone(),
+    ));
+    assert!(res.is_output());
+}
@@ -9,7 +9,7 @@ use tempfile::tempdir;
 use common::conn::ConnPair;
 use common::test_executor::TestExecutor;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use proto::crypto::{InvoiceId, PaymentId, PublicKey};
 use proto::funder::messages::{Currency, Rate, Receipt};
 
@@ -92,6 +92,9 @@ async fn make_test_payment(
         dest_public_key: seller_public_key.clone(),
         dest_payment: total_dest_payment,
         description: "Example payment".to_owned(),
+        deliver_commit: false,
+        opt_fee_budget: None,
+        opt_retry_policy: None,
     };
     node_request(
         &mut compact0,
@@ -305,6 +308,7 @@ async fn task_compact_server_remote_node(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
     let node1_handle = create_node(
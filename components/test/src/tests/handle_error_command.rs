@@ -6,7 +6,7 @@ use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 
 use timer::create_timer_incoming;
 
@@ -55,6 +55,7 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
 
@@ -101,6 +102,7 @@ async fn task_handle_error_command(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
     create_node(
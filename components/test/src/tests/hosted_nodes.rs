@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
+
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    create_app_with_address, create_hosted_nodes, listen_node_address, node_public_key, SimDb,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_hosted_nodes(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (_tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create initial databases for node 0 and node 1:
+    sim_db.init_node_db(0).unwrap();
+    sim_db.init_node_db(1).unwrap();
+
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        restrictions: AppRestrictions::default(),
+    };
+
+    // App0 is trusted by node0, App1 is trusted by node1:
+    let mut nodes_trusted_apps = HashMap::new();
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(0, app_permissions.clone());
+    nodes_trusted_apps.insert(0, trusted_apps);
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(1, app_permissions.clone());
+    nodes_trusted_apps.insert(1, trusted_apps);
+
+    // Both nodes listen on the address of node0:
+    create_hosted_nodes(
+        0,
+        nodes_trusted_apps,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone(),
+    )
+    .await
+    .forget();
+
+    // Every app reaches the node it targets:
+    for index in 0..2 {
+        let (_permissions, node_report, _conn_pair) = create_app_with_address(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            listen_node_address(0),
+            test_executor.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            node_report.funder_report.local_public_key,
+            node_public_key(index)
+        );
+    }
+
+    // App0 is not trusted by node1:
+    let opt_untrusted_app = create_app_with_address(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        listen_node_address(0),
+        test_executor.clone(),
+    )
+    .await;
+    assert!(opt_untrusted_app.is_none());
+}
+
+#[test]
+fn test_hosted_nodes() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_hosted_nodes(test_executor.clone()));
+    assert!(res.is_output());
+}
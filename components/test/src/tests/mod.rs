@@ -1,7 +1,9 @@
 mod compact_node_payment;
 mod compact_server_remote_node;
 mod handle_error_command;
+mod hosted_nodes;
 mod nodes_chain;
+mod rebalance;
 mod relay_migration;
 mod resolve_inconsistency;
 mod serialize;
@@ -7,7 +7,7 @@ use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use proto::funder::messages::{Currency, PaymentStatus, PaymentStatusSuccess, Rate};
 
 use timer::create_timer_incoming;
@@ -68,6 +68,7 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
                 buyer: true,
                 seller: true,
                 config: true,
+                restrictions: AppRestrictions::default(),
             },
         );
 
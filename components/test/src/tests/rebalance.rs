@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
+use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
+use proto::funder::messages::{
+    Currency, FriendsRoute, PaymentStatus, PaymentStatusSuccess, Rate, RebalancePolicy,
+};
+use proto::report::messages::ChannelStatusReport;
+
+use timer::create_timer_incoming;
+
+use app::conn::{self, ConnPairApp, RequestResult};
+
+use crate::app_wrapper::{
+    ack_close_payment, create_transaction, request_close_payment, send_request,
+};
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_index_server, create_node, create_relay,
+    named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+};
+
+use crate::node_report_service::{node_report_service, NodeReportClient};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+struct AppControl {
+    conn_pair: ConnPairApp,
+    report_client: NodeReportClient,
+}
+
+/// Get the balance of a node with one of its friends
+async fn get_balance(
+    report_client: &mut NodeReportClient,
+    friend_public_key: &PublicKey,
+    currency: &Currency,
+) -> i128 {
+    let node_report = report_client.request_report().await;
+    let friend_report = node_report
+        .funder_report
+        .friends
+        .get(friend_public_key)
+        .unwrap();
+    let channel_consistent = match &friend_report.channel_status {
+        ChannelStatusReport::Consistent(channel_consistent) => channel_consistent,
+        ChannelStatusReport::Inconsistent(_) => unreachable!(),
+    };
+    channel_consistent
+        .currency_reports
+        .iter()
+        .find(|currency_report| &currency_report.currency == currency)
+        .unwrap()
+        .balance
+        .balance
+}
+
+async fn task_rebalance(mut test_executor: TestExecutor) {
+    let currency = Currency::try_from("FST".to_owned()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    let mut apps = Vec::new();
+
+    // Create 3 nodes with apps:
+    for i in 0..3 {
+        sim_db.init_node_db(i).unwrap();
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                buyer: true,
+                seller: true,
+                config: true,
+                restrictions: AppRestrictions::default(),
+            },
+        );
+
+        create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone(),
+        )
+        .await
+        .forget();
+
+        let (_permissions, node_report, conn_pair) = create_app(
+            i,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            i,
+            test_executor.clone(),
+        )
+        .await
+        .unwrap();
+
+        // Create report service (Allowing to query reports):
+        let (sender, receiver) = conn_pair.split();
+        let (receiver, report_client) = node_report_service(node_report, receiver, &test_executor);
+        let conn_pair = ConnPairApp::from_raw(sender, receiver);
+
+        apps.push(AppControl {
+            conn_pair,
+            report_client,
+        });
+    }
+
+    // Create a relay and an index server:
+    create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone(),
+    )
+    .await;
+
+    create_index_server(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![],
+        test_executor.clone(),
+    )
+    .await;
+
+    for app in &mut apps {
+        send_request(
+            &mut app.conn_pair,
+            conn::config::add_relay(named_relay_address(0)),
+        )
+        .await
+        .unwrap();
+
+        send_request(
+            &mut app.conn_pair,
+            conn::config::add_index_server(named_index_server_address(0)),
+        )
+        .await
+        .unwrap();
+    }
+
+    /*
+              0
+             / \
+            1 - 2
+    */
+
+    // Enable friends and open currencies:
+    for (i, j) in &[(0u8, 1u8), (0, 2), (1, 2)] {
+        for (&a, &b) in &[(i, j), (j, i)] {
+            send_request(
+                &mut apps[a as usize].conn_pair,
+                conn::config::add_friend(
+                    node_public_key(b),
+                    vec![relay_address(0)],
+                    format!("node{}", b),
+                ),
+            )
+            .await
+            .unwrap();
+
+            send_request(
+                &mut apps[a as usize].conn_pair,
+                conn::config::enable_friend(node_public_key(b)),
+            )
+            .await
+            .unwrap();
+
+            send_request(
+                &mut apps[a as usize].conn_pair,
+                conn::config::set_friend_currency_rate(
+                    node_public_key(b),
+                    currency.clone(),
+                    Rate::new(),
+                ),
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    // Wait until active currencies are negotiated:
+    advance_time(40, &mut tick_sender, &test_executor).await;
+
+    for (i, j) in &[(0u8, 1u8), (0, 2), (1, 2)] {
+        for (&a, &b) in &[(i, j), (j, i)] {
+            send_request(
+                &mut apps[a as usize].conn_pair,
+                conn::config::set_friend_currency_max_debt(
+                    node_public_key(b),
+                    currency.clone(),
+                    100,
+                ),
+            )
+            .await
+            .unwrap();
+
+            send_request(
+                &mut apps[a as usize].conn_pair,
+                conn::config::open_friend_currency(node_public_key(b), currency.clone()),
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    // Node1 charges a fee for forwarding requests that arrive from Node0.
+    // Node1 is the friend's hop of the rebalance loop of Node0, and its fee is not a part of the
+    // rate of routes returned by the index server:
+    send_request(
+        &mut apps[1].conn_pair,
+        conn::config::set_friend_currency_rate(
+            node_public_key(0),
+            currency.clone(),
+            Rate { mul: 0, add: 1 },
+        ),
+    )
+    .await
+    .unwrap();
+
+    // Wait some time:
+    advance_time(40, &mut tick_sender, &test_executor).await;
+
+    // Node1 pays Node2 through Node0: 1 -> 0 -> 2
+    // ===========================================
+    // Node1 will owe Node0 50 credits, and Node0 will owe Node2 50 credits.
+
+    let payment_id = PaymentId::from(&[0u8; PaymentId::len()]);
+    let invoice_id = InvoiceId::from(&[1u8; InvoiceId::len()]);
+    let request_id = Uid::from(&[2u8; Uid::len()]);
+    let total_dest_payment = 50u128;
+
+    // Node2: Create an invoice:
+    send_request(
+        &mut apps[2].conn_pair,
+        conn::seller::add_invoice(invoice_id.clone(), currency.clone(), total_dest_payment),
+    )
+    .await
+    .unwrap();
+
+    // Node1: Open a payment to pay the invoice issued by Node2:
+    send_request(
+        &mut apps[1].conn_pair,
+        conn::buyer::create_payment(
+            payment_id.clone(),
+            invoice_id.clone(),
+            currency.clone(),
+            total_dest_payment,
+            node_public_key(2),
+        ),
+    )
+    .await
+    .unwrap();
+
+    // Node0 does not charge fees for forwarding requests from Node1:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(1), node_public_key(0), node_public_key(2)],
+    };
+    let request_result = create_transaction(
+        &mut apps[1].conn_pair,
+        payment_id.clone(),
+        request_id.clone(),
+        route,
+        total_dest_payment,
+        0,
+    )
+    .await
+    .unwrap();
+
+    let commit = if let RequestResult::Complete(commit) = request_result {
+        commit
+    } else {
+        unreachable!();
+    };
+
+    // Node2: Apply the Commit
+    send_request(&mut apps[2].conn_pair, conn::seller::commit_invoice(commit))
+        .await
+        .unwrap();
+
+    // Node1: Close payment (No more transactions will be sent through this payment)
+    let _ = request_close_payment(&mut apps[1].conn_pair, payment_id.clone())
+        .await
+        .unwrap();
+
+    // Wait some time:
+    advance_time(5, &mut tick_sender, &test_executor).await;
+
+    // Node1: Check the payment's result:
+    let payment_status = request_close_payment(&mut apps[1].conn_pair, payment_id.clone())
+        .await
+        .unwrap();
+
+    match &payment_status {
+        PaymentStatus::Success(PaymentStatusSuccess { receipt, ack_uid }) => {
+            assert_eq!(receipt.total_dest_payment, total_dest_payment);
+            ack_close_payment(&mut apps[1].conn_pair, payment_id.clone(), ack_uid.clone())
+                .await
+                .unwrap();
+        }
+        _ => unreachable!(),
+    }
+
+    // Wait until the index server learns about the new balances:
+    advance_time(40, &mut tick_sender, &test_executor).await;
+
+    // Node0 is now out of balance with both friends:
+    assert_eq!(
+        get_balance(&mut apps[0].report_client, &node_public_key(1), &currency).await,
+        50
+    );
+    assert_eq!(
+        get_balance(&mut apps[0].report_client, &node_public_key(2), &currency).await,
+        -50
+    );
+
+    // Node0: Rebalance the mutual credit with Node1
+    // =============================================
+    // Node0 should pay itself along the loop 0 -> 1 -> 2 -> 0
+
+    send_request(
+        &mut apps[0].conn_pair,
+        conn::config::set_friend_currency_rebalance_policy(
+            node_public_key(1),
+            currency.clone(),
+            Some(RebalancePolicy {
+                threshold: 10,
+                max_fees: 4,
+            }),
+        ),
+    )
+    .await
+    .unwrap();
+
+    // Wait for the rebalance to complete:
+    advance_time(40, &mut tick_sender, &test_executor).await;
+
+    // Node0 offered the whole fees budget (4 credits). Node1 took 1 credit, and the remaining 3
+    // credits were paid back to Node0 through Node2:
+    assert_eq!(
+        get_balance(&mut apps[0].report_client, &node_public_key(1), &currency).await,
+        -4
+    );
+    assert_eq!(
+        get_balance(&mut apps[0].report_client, &node_public_key(2), &currency).await,
+        3
+    );
+}
+
+#[test]
+fn test_rebalance() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_rebalance(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -6,7 +6,7 @@ use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use timer::create_timer_incoming;
 
 use app::conn::{self, ConnPairApp};
@@ -88,6 +88,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
 
@@ -122,6 +123,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
     let node1_handle = create_node(
@@ -314,6 +316,7 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
     let _node1_handle = create_node(
@@ -7,7 +7,7 @@ use tempfile::tempdir;
 
 use common::test_executor::TestExecutor;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppRestrictions};
 use proto::crypto::{InvoiceId, PaymentId, PublicKey, Uid};
 use proto::funder::messages::{Currency, FriendsRoute, PaymentStatus, PaymentStatusSuccess, Rate};
 use proto::report::messages::ChannelStatusReport;
@@ -160,6 +160,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
 
@@ -195,6 +196,7 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
             buyer: true,
             seller: true,
             config: true,
+            restrictions: AppRestrictions::default(),
         },
     );
     create_node(
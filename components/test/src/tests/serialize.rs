@@ -3,8 +3,8 @@ use std::convert::TryFrom;
 
 use funder::FunderState;
 use proto::file::{
-    FriendAddressFile, FriendFile, IdentityFile, IndexServerFile, NodeAddressFile,
-    RelayAddressFile, TrustedAppFile,
+    FriendAddressFile, FriendFile, IdentityFile, IndexAdminFile, IndexClientFile, IndexServerFile,
+    NodeAddressFile, RelayAddressFile, TrustedAppFile,
 };
 use proto::funder::messages::Currency;
 use proto::net::messages::NetAddress;
@@ -40,6 +40,8 @@ ser_de_test!(qc_ser_de_friend_address_file, FriendAddressFile);
 ser_de_test!(qc_ser_de_friend_file, FriendFile);
 ser_de_test!(qc_ser_de_identity_file, IdentityFile);
 ser_de_test!(qc_ser_de_index_server_file, IndexServerFile);
+ser_de_test!(qc_ser_de_index_admin_file, IndexAdminFile);
+ser_de_test!(qc_ser_de_index_client_file, IndexClientFile);
 ser_de_test!(qc_ser_de_node_address_file, NodeAddressFile);
 ser_de_test!(qc_ser_de_relay_address_file, RelayAddressFile);
 ser_de_test!(qc_ser_de_trusted_app_file, TrustedAppFile);
@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 
 use futures::channel::mpsc;
 use futures::future::RemoteHandle;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, FutureExt, SinkExt, TryFutureExt};
+use futures::{future, stream, FutureExt, SinkExt, TryFutureExt};
 
 use crypto::identity::{Identity, SoftwareEd25519Identity};
 
@@ -13,12 +13,15 @@ use crypto::test_utils::DummyRandom;
 
 use common::test_executor::TestExecutor;
 
-use common::conn::{BoxFuture, ConnPair};
+use common::conn::{BoxFuture, ConnPair, ConnPairVec};
 
 use proto::crypto::{PrivateKey, PublicKey};
 
 use proto::app_server::messages::{AppPermissions, NamedRelayAddress, RelayAddress};
-use proto::consts::{KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY};
+use proto::consts::{
+    ANNOUNCE_CREDIT_LIMITS, KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH,
+    REBALANCE_BACKOFF_TICKS, REBALANCE_STAGE_TIMEOUT_TICKS, TICKS_TO_REKEY,
+};
 use proto::index_server::messages::NamedIndexServerAddress;
 use proto::net::messages::NetAddress;
 
@@ -28,13 +31,15 @@ use app::conn::AppConnTuple;
 use app_client::app_connect_to_node;
 use connection::create_secure_connector;
 
-use node::{NodeConfig, NodeState};
+use node::{NodeConfig, NodeState, RoutesPrivacy};
+
+use index_server::ClientLimits;
 
 use database::file_db::FileDb;
 use database::{database_loop, AtomicDb, DatabaseClient};
 
 use bin::stindex::net_index_server;
-use bin::stnode::{net_node, TrustedApps};
+use bin::stnode::{demux_incoming_apps, net_node, TrustedApps};
 use bin::strelay::net_relay_server;
 
 use stcompact::compact_node::messages::{CompactReport, CompactToUserAck, UserToCompactAck};
@@ -133,6 +138,18 @@ fn default_node_config() -> NodeConfig {
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        /// The amount of ticks we wait before attempting to rebalance a mutual credit again.
+        rebalance_backoff_ticks: REBALANCE_BACKOFF_TICKS,
+        /// The amount of ticks we wait for a response during a rebalance before giving up.
+        rebalance_stage_timeout_ticks: REBALANCE_STAGE_TIMEOUT_TICKS,
+        /// Announce to friends the credit we are willing to extend to them.
+        announce_credit_limits: ANNOUNCE_CREDIT_LIMITS,
+        /// Privacy settings for routes requests sent to index servers.
+        routes_privacy: RoutesPrivacy::disabled(),
+        /// Amount of ticks between syncs of route topology snapshots from the index server.
+        opt_topology_sync_ticks: None,
+        /// Report outcomes of routes used for sending credits to the index server.
+        report_route_outcomes: true,
         /*
         /// Maximum amount of incoming app connections we set up at the same time
         max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
@@ -250,6 +267,30 @@ pub async fn create_app<S>(
     node_index: u8,
     spawner: S,
 ) -> Option<AppConnTuple>
+where
+    S: Spawn + Clone + Sync + Send + 'static,
+{
+    create_app_with_address(
+        app_index,
+        sim_network_client,
+        timer_client,
+        node_index,
+        listen_node_address(node_index),
+        spawner,
+    )
+    .await
+}
+
+/// Connect an app to node `node_index`, listening on `node_address`.
+/// Used for nodes that share a listening address with other nodes.
+pub async fn create_app_with_address<S>(
+    app_index: u8,
+    sim_network_client: SimNetworkClient,
+    timer_client: TimerClient,
+    node_index: u8,
+    node_address: NetAddress,
+    spawner: S,
+) -> Option<AppConnTuple>
 where
     S: Spawn + Clone + Sync + Send + 'static,
 {
@@ -270,7 +311,7 @@ where
     app_connect_to_node(
         secure_connector,
         node_public_key,
-        listen_node_address(node_index),
+        node_address,
         spawner.clone(),
     )
     .await
@@ -404,64 +445,115 @@ pub async fn create_node<S>(
     index: u8,
     sim_db: SimDb,
     timer_client: TimerClient,
-    mut sim_network_client: SimNetworkClient,
+    sim_network_client: SimNetworkClient,
     trusted_apps: HashMap<u8, AppPermissions>,
     spawner: S,
 ) -> RemoteHandle<()>
 where
     S: Spawn + Send + Sync + Clone + 'static,
 {
-    let identity = get_node_identity(index);
-    let identity_client = create_identity_client(identity, spawner.clone());
-    let listen_address = listen_node_address(index);
-    let incoming_app_raw_conns = sim_network_client.listen(listen_address).await.unwrap();
-
-    // Translate application index to application public key:
-    let trusted_apps_map = trusted_apps
-        .into_iter()
-        .map(|(index, app_permissions)| (get_app_identity(index).get_public_key(), app_permissions))
-        .collect::<HashMap<_, _>>();
-    let dummy_trusted_apps = DummyTrustedApps {
-        trusted_apps: trusted_apps_map,
-    };
-    // let get_trusted_apps = move || Some(trusted_apps.clone());
-
-    let rng = DummyRandom::new(&[0xff, 0x13, 0x37, index]);
-
-    let atomic_db = sim_db.load_node_db(index).unwrap();
+    let mut nodes_trusted_apps = HashMap::new();
+    nodes_trusted_apps.insert(index, trusted_apps);
+    create_hosted_nodes(
+        index,
+        nodes_trusted_apps,
+        sim_db,
+        timer_client,
+        sim_network_client,
+        spawner,
+    )
+    .await
+}
 
-    // Get initial node_state:
-    let node_state = atomic_db.get_state().clone();
+/// Create a few nodes that share one listening address (The address of node `listen_index`),
+/// like nodes hosted together by one stnode process.
+/// `nodes_trusted_apps` maps the index of every node to its trusted apps.
+pub async fn create_hosted_nodes<S>(
+    listen_index: u8,
+    nodes_trusted_apps: HashMap<u8, HashMap<u8, AppPermissions>>,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    mut sim_network_client: SimNetworkClient,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    let listen_address = listen_node_address(listen_index);
+    let incoming_app_raw_conns = sim_network_client.listen(listen_address).await.unwrap();
 
-    // Spawn database service:
-    let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
-    let loop_fut = database_loop(atomic_db, incoming_db_requests, spawner.clone())
-        .map_err(|e| error!("database_loop() error: {:?}", e))
+    let mut nodes = HashMap::new();
+    let mut net_node_futs = Vec::new();
+    for (index, trusted_apps) in nodes_trusted_apps {
+        let identity = get_node_identity(index);
+        let identity_client = create_identity_client(identity, spawner.clone());
+
+        // Incoming app connections that target this node:
+        let (incoming_apps_sender, node_incoming_app_raw_conns) = mpsc::channel(CHANNEL_LEN);
+        nodes.insert(node_public_key(index), incoming_apps_sender);
+
+        // Translate application index to application public key:
+        let trusted_apps_map = trusted_apps
+            .into_iter()
+            .map(|(index, app_permissions)| {
+                (get_app_identity(index).get_public_key(), app_permissions)
+            })
+            .collect::<HashMap<_, _>>();
+        let dummy_trusted_apps = DummyTrustedApps {
+            trusted_apps: trusted_apps_map,
+        };
+
+        let rng = DummyRandom::new(&[0xff, 0x13, 0x37, index]);
+
+        let atomic_db = sim_db.load_node_db(index).unwrap();
+
+        // Get initial node_state:
+        let node_state = atomic_db.get_state().clone();
+
+        // Spawn database service:
+        let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
+        let loop_fut = database_loop(atomic_db, incoming_db_requests, spawner.clone())
+            .map_err(|e| error!("database_loop() error: {:?}", e))
+            .map(|_| ());
+
+        spawner.spawn(loop_fut).unwrap();
+
+        // Obtain a client to the database service:
+        let database_client = DatabaseClient::new(db_request_sender);
+
+        // Note: we use the same spawner for testing purposes.
+        // Simulating the passage of time becomes more difficult if our code uses a few different executors.
+        let net_node_fut = net_node(
+            node_incoming_app_raw_conns,
+            sim_network_client.clone(),
+            timer_client.clone(),
+            identity_client,
+            rng,
+            default_node_config(),
+            dummy_trusted_apps,
+            stream::empty(),
+            node_state,
+            database_client,
+            spawner.clone(),
+        )
+        .map_err(|e| error!("net_node() error: {:?}", e))
         .map(|_| ());
+        net_node_futs.push(net_node_fut);
+    }
 
-    spawner.spawn(loop_fut).unwrap();
-
-    // Obtain a client to the database service:
-    let database_client = DatabaseClient::new(db_request_sender);
-
-    // Note: we use the same spawner for testing purposes.
-    // Simulating the passage of time becomes more difficult if our code uses a few different executors.
-    let net_node_fut = net_node(
+    // Apps reach the nodes through the same demultiplexer used by stnode:
+    let demux_fut = demux_incoming_apps(
         incoming_app_raw_conns,
-        sim_network_client,
-        timer_client,
-        identity_client,
-        rng,
-        default_node_config(),
-        dummy_trusted_apps,
-        node_state,
-        database_client,
+        nodes,
+        timer_client.clone(),
         spawner.clone(),
     )
-    .map_err(|e| error!("net_node() error: {:?}", e))
+    .map_err(|e| error!("demux_incoming_apps() error: {:?}", e))
     .map(|_| ());
 
-    spawner.spawn_with_handle(net_node_fut).unwrap()
+    spawner
+        .spawn_with_handle(future::join(future::join_all(net_node_futs), demux_fut).map(|_| ()))
+        .unwrap()
 }
 
 pub async fn create_index_server<S>(
@@ -504,13 +596,22 @@ pub async fn create_index_server<S>(
     let net_index_server_fut = net_index_server(
         incoming_client_raw_conns,
         incoming_server_raw_conns,
+        // Index servers in tests have no admins:
+        stream::empty::<ConnPairVec>(),
+        HashSet::new(),
+        // All clients are allowed:
+        None,
         sim_network_client,
         identity_client,
         timer_client,
         rng,
         trusted_servers,
+        // Trusted servers never change during tests:
+        stream::empty(),
         MAX_CONCURRENT_ENCRYPT,
         BACKOFF_TICKS,
+        ClientLimits::unlimited(),
+        None,
         spawner.clone(),
         spawner.clone(),
     )